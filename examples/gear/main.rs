@@ -1,13 +1,13 @@
-use std::{sync::Arc, time::Instant};
+use std::sync::Arc;
 
 use cgmath::{perspective, Deg, InnerSpace, Matrix4, Point3, Rad, Vector3};
 use gears::{
-    load_obj, Buffer, ContextGPUPick, ContextValidation, EventLoopTarget, Frame, FrameLoop,
-    FrameLoopTarget, FramePerfReport, ImmediateFrameInfo, InputState, KeyboardInput, Pipeline,
-    RenderRecordInfo, Renderer, RendererRecord, SyncMode, UpdateRecordInfo, VertexBuffer,
-    VirtualKeyCode, WindowEvent,
+    load_obj, ContextGPUPick, ContextValidation, EventLoopTarget, Frame, FrameCtx, FrameInfo,
+    FrameLoop, FrameLoopTarget, FramePerfReport, InputState, KeyboardInput, Pipeline,
+    RenderRecordBeginInfo, Renderer, RendererRecord, SyncMode, VertexBuffer, VirtualKeyCode,
+    WindowEvent,
 };
-use parking_lot::{Mutex, RwLock};
+use parking_lot::RwLock;
 
 mod shader {
     gears_pipeline::pipeline! {
@@ -22,38 +22,36 @@ const MAX_VBO_LEN: usize = 50_000;
 
 struct App {
     frame: Frame,
-    renderer: Renderer,
+    renderer: Arc<Renderer>,
     input: Arc<RwLock<InputState>>,
 
     vb: VertexBuffer<shader::VertexData>,
     shader: Pipeline,
 
-    delta_time: Mutex<Instant>,
-    distance: Mutex<f32>,
-    position: Mutex<Vector3<f32>>,
+    distance: f32,
+    position: Vector3<f32>,
 }
 
 impl App {
-    fn init(frame: Frame, renderer: Renderer, input: Arc<RwLock<InputState>>) -> Arc<RwLock<Self>> {
+    fn init(frame: Frame, renderer: Renderer, input: Arc<RwLock<InputState>>) -> Self {
         let vb = VertexBuffer::new(&renderer, MAX_VBO_LEN).unwrap();
         let shader = shader::build(&renderer);
 
         let mut app = Self {
             frame,
-            renderer,
+            renderer: Arc::new(renderer),
             input,
 
             vb,
             shader,
 
-            delta_time: Mutex::new(Instant::now()),
-            distance: Mutex::new(2.5),
-            position: Mutex::new(Vector3::new(0.0, 0.0, 0.0)),
+            distance: 2.5,
+            position: Vector3::new(0.0, 0.0, 0.0),
         };
 
         app.reload_mesh();
 
-        Arc::new(RwLock::new(app))
+        app
     }
 
     fn reload_mesh(&mut self) {
@@ -71,19 +69,14 @@ impl App {
 }
 
 impl RendererRecord for App {
-    fn immediate(&self, imfi: &ImmediateFrameInfo) {
-        let dt_s = {
-            let mut delta_time = self.delta_time.lock();
-            let dt_s = delta_time.elapsed().as_secs_f32();
-            *delta_time = Instant::now();
-            dt_s
-        };
+    fn frame(&mut self, frame: &mut FrameCtx) {
+        let dt_s = frame.time().delta.as_secs_f32();
         let aspect = self.frame.aspect();
 
         let mut distance_delta = 0.0;
         let mut velocity = Vector3::new(0.0, 0.0, 0.0);
         {
-            let input = self.input.read();
+            let input = self.input.read().snapshot();
             if input.key_held(VirtualKeyCode::E) {
                 distance_delta += 1.0;
             }
@@ -106,22 +99,15 @@ impl RendererRecord for App {
                 velocity.z += 2.0;
             }
         }
-        let distance = {
-            let mut distance = self.distance.lock();
-            *distance += distance_delta * 3.0 * dt_s;
-            *distance
-        };
-        let position = {
-            let mut position = self.position.lock();
-
-            *position += velocity * 3.0 * dt_s;
-            position.y = position
-                .y
-                .min(std::f32::consts::PI / 2.0 - 0.0001)
-                .max(-std::f32::consts::PI / 2.0 + 0.0001);
+        self.distance += distance_delta * 3.0 * dt_s;
+        self.position += velocity * 3.0 * dt_s;
+        self.position.y = self
+            .position
+            .y
+            .min(std::f32::consts::PI / 2.0 - 0.0001)
+            .max(-std::f32::consts::PI / 2.0 + 0.0001);
 
-            *position
-        };
+        let (distance, position) = (self.distance, self.position);
 
         let eye = Point3::new(
             position.x.sin() * position.y.cos(),
@@ -137,17 +123,15 @@ impl RendererRecord for App {
             light_dir: Vector3::new(0.2, 2.0, 0.5).normalize(),
         };
 
-        self.shader.write_ubo(imfi, &ubo).unwrap();
-    }
+        self.shader.write_ubo(frame.immediate(), &ubo).unwrap();
 
-    fn update(&self, uri: &UpdateRecordInfo) -> bool {
-        unsafe { self.shader.update(uri) || self.vb.update(uri) }
-    }
+        let mut upload = frame.upload();
+        upload.pipeline(&self.shader);
+        upload.buffer(&self.vb);
 
-    fn record(&self, rri: &RenderRecordInfo) {
-        unsafe {
-            self.shader.bind(rri);
-            self.vb.draw(rri);
+        if let Some(record) = frame.record(RenderRecordBeginInfo::default()) {
+            let mut draw = record.begin_pass();
+            draw.bind(&self.shader).draw(&self.vb);
         }
     }
 }
@@ -171,8 +155,9 @@ impl EventLoopTarget for App {
 }
 
 impl FrameLoopTarget for App {
-    fn frame(&self) -> FramePerfReport {
-        self.renderer.frame(self)
+    fn frame(&mut self, info: &FrameInfo) -> FramePerfReport {
+        let renderer = self.renderer.clone();
+        renderer.frame(info, self)
     }
 }
 
@@ -198,9 +183,8 @@ fn main() {
 
     FrameLoop::new()
         .with_event_loop(event_loop)
-        .with_event_target(input)
-        .with_event_target(app.clone())
-        .with_frame_target(app)
+        .with_input(input)
+        .with_target(app)
         .build()
         .run();
 }
@@ -21,10 +21,10 @@ use gears::{
         buffer::{IndexBuffer, VertexBuffer},
         pipeline::Pipeline,
     },
-    Buffer, ContextGPUPick, ContextValidation, CursorController, ElementState, EventLoopTarget,
-    Frame, FrameLoop, FrameLoopTarget, FramePerfReport, HideMode, ImmediateFrameInfo,
-    PipelineBuilder, RenderRecordBeginInfo, RenderRecordInfo, Renderer, RendererRecord, SyncMode,
-    UpdateLoop, UpdateLoopTarget, UpdateRate, UpdateRecordInfo, VirtualKeyCode, WindowEvent,
+    ContextGPUPick, ContextValidation, CursorController, ElementState, EventLoopTarget, Frame,
+    FrameCtx, FrameInfo, FrameLoop, FrameLoopTarget, FramePerfReport, HideMode, PipelineBuilder,
+    RenderRecordBeginInfo, Renderer, RendererRecord, SyncMode, UpdateLoop, UpdateLoopTarget,
+    UpdateRate, VirtualKeyCode, WindowEvent,
 };
 use marching_cubes::generate_marching_cubes;
 use parking_lot::RwLock;
@@ -74,7 +74,7 @@ impl MeshMode {
 
 struct App {
     frame: Frame,
-    renderer: Renderer,
+    renderer: Arc<Renderer>,
 
     vb: VertexBuffer<shader::VertexData>,
     ib: IndexBuffer<u32>,
@@ -139,8 +139,8 @@ impl App {
         let fill_shader = shader::build(&renderer);
         let line_shader = PipelineBuilder::new(&renderer)
             .with_ubo::<shader::UBO>()
-            .with_graphics_modules(debug_shader::VERT_SPIRV_REF, shader::FRAG_SPIRV_REF)
-            .with_geometry_module(debug_shader::GEOM_SPIRV_REF)
+            .with_graphics_modules(debug_shader::VERT_SPIRV_WORDS, shader::FRAG_SPIRV_WORDS)
+            .with_geometry_module(debug_shader::GEOM_SPIRV_WORDS)
             .with_input::<shader::VertexData>()
             .build(false)
             .unwrap();
@@ -149,7 +149,7 @@ impl App {
 
         Arc::new(RwLock::new(Self {
             frame,
-            renderer,
+            renderer: Arc::new(renderer),
 
             vb,
             ib,
@@ -201,7 +201,7 @@ impl App {
 }
 
 impl RendererRecord for App {
-    fn immediate(&self, imfi: &ImmediateFrameInfo) {
+    fn frame(&mut self, frame: &mut FrameCtx) {
         let dt_s = self.delta_time.elapsed().as_secs_f32() / self.updaterate.as_secs_f32();
         let aspect = self.frame.aspect();
 
@@ -221,41 +221,36 @@ impl RendererRecord for App {
                 * Matrix4::from_scale(1.0),
         };
 
-        self.shaders.0.write_ubo(imfi, &ubo).unwrap();
-        self.shaders.1.write_ubo(imfi, &ubo).unwrap();
-    }
+        self.shaders.0.write_ubo(frame.immediate(), &ubo).unwrap();
+        self.shaders.1.write_ubo(frame.immediate(), &ubo).unwrap();
 
-    fn update(&self, uri: &UpdateRecordInfo) -> bool {
-        unsafe {
-            self.shaders.0.update(uri)
-                || self.shaders.1.update(uri)
-                || self.ib.update(uri)
-                || self.vb.update(uri)
-        }
-    }
+        let mut upload = frame.upload();
+        upload.pipeline(&self.shaders.0);
+        upload.pipeline(&self.shaders.1);
+        upload.buffer(&self.ib);
+        upload.buffer(&self.vb);
 
-    fn begin_info(&self) -> RenderRecordBeginInfo {
-        RenderRecordBeginInfo {
+        let begin_info = RenderRecordBeginInfo {
             clear_color: Vector4::new(1.0, 1.0, 1.0, 1.0),
             debug_calls: true,
-        }
-    }
-
-    fn record(&self, rri: &RenderRecordInfo) {
-        unsafe {
-            if self.debug {
-                self.shaders.0.bind(rri);
+        };
+        if let Some(record) = frame.record(begin_info) {
+            let shader = if self.debug {
+                &self.shaders.0
             } else {
-                self.shaders.1.bind(rri);
-            }
-            self.ib.draw(rri, &self.vb);
+                &self.shaders.1
+            };
+
+            let mut draw = record.begin_pass();
+            draw.bind(shader).draw_indexed(&self.ib, &self.vb);
         }
     }
 }
 
 impl FrameLoopTarget for App {
-    fn frame(&self) -> FramePerfReport {
-        self.renderer.frame(self)
+    fn frame(&mut self, info: &FrameInfo) -> FramePerfReport {
+        let renderer = self.renderer.clone();
+        renderer.frame(info, self)
     }
 }
 
@@ -0,0 +1,233 @@
+use std::{
+    collections::HashSet,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    format::{AssetError, MESH_EXTENSION, TEXTURE_EXTENSION},
+    import::{extension, import_mesh, import_texture},
+};
+
+/// What a source file is converted to, from its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AssetKind {
+    Mesh,
+    Texture,
+}
+
+/// Converts source assets into cache files and writes a module with a
+/// `MeshAsset` or `TextureAsset` constant for each of them.
+///
+/// In a build script the cache and the module go to `OUT_DIR` and cargo
+/// reruns it when a source changes. Sources older than their cache file
+/// are not converted again.
+pub struct AssetBuilder {
+    dirs: Vec<PathBuf>,
+    assets: Vec<(String, PathBuf)>,
+    out_dir: Option<PathBuf>,
+    module: String,
+}
+
+struct Source {
+    name: String,
+    path: PathBuf,
+    kind: AssetKind,
+}
+
+impl AssetKind {
+    /// `None` for files that are not assets.
+    pub fn of<P: AsRef<Path>>(path: P) -> Option<Self> {
+        match extension(path.as_ref()).as_str() {
+            "obj" | "gltf" | "glb" => Some(AssetKind::Mesh),
+            "png" | "jpg" | "jpeg" | "bmp" | "tga" => Some(AssetKind::Texture),
+            _ => None,
+        }
+    }
+
+    fn cache_extension(&self) -> &'static str {
+        match self {
+            AssetKind::Mesh => MESH_EXTENSION,
+            AssetKind::Texture => TEXTURE_EXTENSION,
+        }
+    }
+}
+
+impl AssetBuilder {
+    pub fn new() -> Self {
+        Self {
+            dirs: Vec::new(),
+            assets: Vec::new(),
+            out_dir: None,
+            module: "assets.rs".to_owned(),
+        }
+    }
+
+    /// Every asset in `dir` and its subdirectories, named after their path
+    /// without the extension. `res/ui/button.png` of `with_dir("res")` is `UI_BUTTON`.
+    pub fn with_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.dirs.push(dir.into());
+        self
+    }
+
+    /// One asset, `name` is turned into the constant name.
+    pub fn with_asset<S: Into<String>, P: Into<PathBuf>>(mut self, name: S, path: P) -> Self {
+        self.assets.push((name.into(), path.into()));
+        self
+    }
+
+    /// Where the cache files and the module go, `OUT_DIR` by default.
+    pub fn with_out_dir<P: Into<PathBuf>>(mut self, out_dir: P) -> Self {
+        self.out_dir = Some(out_dir.into());
+        self
+    }
+
+    /// File name of the generated module, `assets.rs` by default.
+    pub fn with_module_name<S: Into<String>>(mut self, module: S) -> Self {
+        self.module = module.into();
+        self
+    }
+
+    /// Converts the assets and returns the path of the module.
+    pub fn build(self) -> Result<PathBuf, AssetError> {
+        // only build scripts run with OUT_DIR set
+        let build_script = env::var_os("OUT_DIR");
+        let out_dir = match (self.out_dir.clone(), build_script.as_ref()) {
+            (Some(out_dir), _) => out_dir,
+            (None, Some(out_dir)) => PathBuf::from(out_dir),
+            (None, None) => {
+                return Err(AssetError::Import(
+                    "No output directory, OUT_DIR is only set for build scripts".to_owned(),
+                ))
+            }
+        };
+        fs::create_dir_all(&out_dir)?;
+
+        if build_script.is_some() {
+            for dir in self.dirs.iter() {
+                println!("cargo:rerun-if-changed={}", dir.display());
+            }
+            for (_, path) in self.assets.iter() {
+                println!("cargo:rerun-if-changed={}", path.display());
+            }
+        }
+
+        let sources = self.sources()?;
+        let mut module = String::from("// Generated by gears-assets, do not edit.\n");
+        for source in sources.iter() {
+            let file_name = format!(
+                "{}.{}",
+                source.name.to_ascii_lowercase(),
+                source.kind.cache_extension()
+            );
+            let cache = out_dir.join(file_name);
+            if !up_to_date(&source.path, &cache) {
+                let bytes = match source.kind {
+                    AssetKind::Mesh => import_mesh(&source.path)?.to_bytes(),
+                    AssetKind::Texture => import_texture(&source.path)?.to_bytes(),
+                };
+                fs::write(&cache, bytes)?;
+            }
+
+            let ty = match source.kind {
+                AssetKind::Mesh => "MeshAsset",
+                AssetKind::Texture => "TextureAsset",
+            };
+            module += &format!(
+                "\n/// `{}`\npub const {}: gears_assets::{2} = gears_assets::{2}::new(include_bytes!({3:?}));\n",
+                source.path.display(),
+                source.name,
+                ty,
+                fs::canonicalize(&cache)?.display().to_string()
+            );
+        }
+
+        let module_path = out_dir.join(&self.module);
+        fs::write(&module_path, module)?;
+        Ok(module_path)
+    }
+
+    // sorted by name so the module does not change with the directory order
+    fn sources(&self) -> Result<Vec<Source>, AssetError> {
+        let mut sources = Vec::new();
+        for dir in self.dirs.iter() {
+            collect(dir, dir, &mut sources)?;
+        }
+        for (name, path) in self.assets.iter() {
+            let kind = AssetKind::of(path).ok_or_else(|| {
+                AssetError::Import(format!("'{}' is not an asset", path.display()))
+            })?;
+            sources.push(Source {
+                name: const_name(name),
+                path: path.clone(),
+                kind,
+            });
+        }
+        sources.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut names = HashSet::new();
+        for source in sources.iter() {
+            if !names.insert(source.name.as_str()) {
+                return Err(AssetError::DuplicateName(source.name.clone()));
+            }
+        }
+        Ok(sources)
+    }
+}
+
+impl Default for AssetBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn collect(root: &Path, dir: &Path, sources: &mut Vec<Source>) -> Result<(), AssetError> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(root, &path, sources)?;
+            continue;
+        }
+
+        if let Some(kind) = AssetKind::of(&path) {
+            let name = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .with_extension("")
+                .to_string_lossy()
+                .into_owned();
+            sources.push(Source {
+                name: const_name(&name),
+                path,
+                kind,
+            });
+        }
+    }
+    Ok(())
+}
+
+// `ui/button-large` is `UI_BUTTON_LARGE`
+fn const_name(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => name,
+        _ => format!("_{}", name),
+    }
+}
+
+fn up_to_date(source: &Path, cache: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified());
+    match (modified(source), modified(cache)) {
+        (Ok(source), Ok(cache)) => cache >= source,
+        _ => false,
+    }
+}
@@ -0,0 +1,354 @@
+use std::{collections::HashMap, convert::TryInto, fmt, io};
+
+/// File extension of cached meshes.
+pub const MESH_EXTENSION: &str = "gmesh";
+/// File extension of cached textures.
+pub const TEXTURE_EXTENSION: &str = "gtex";
+
+const MESH_MAGIC: &[u8; 4] = b"GMSH";
+const TEXTURE_MAGIC: &[u8; 4] = b"GTEX";
+// bumped on every layout change, old caches are rejected instead of misread
+const VERSION: u32 = 1;
+// magic, version and two counts
+const HEADER_SIZE: usize = 16;
+const VERTEX_SIZE: usize = 8 * 4;
+
+/// The vertex every cached mesh has, convert it to the vertex type of the pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[repr(C)]
+pub struct CachedVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    /// From the top left like Vulkan, OBJ texture coordinates are flipped.
+    pub uv: [f32; 2],
+}
+
+/// An indexed triangle list.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MeshData {
+    pub vertices: Vec<CachedVertex>,
+    pub indices: Vec<u32>,
+}
+
+/// Tightly packed 8 bit RGBA rows, top row first. The color channels are sRGB.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TextureData {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// A cached mesh embedded in the binary, the generated asset module has one per mesh.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshAsset {
+    bytes: &'static [u8],
+}
+
+/// A cached texture embedded in the binary, the generated asset module has one per texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextureAsset {
+    bytes: &'static [u8],
+}
+
+#[derive(Debug)]
+pub enum AssetError {
+    Io(io::Error),
+    /// The source asset could not be parsed.
+    Import(String),
+    /// Not a cache file or one written by another version.
+    InvalidCache(&'static str),
+    /// Two assets would get the same constant name.
+    DuplicateName(String),
+}
+
+impl MeshData {
+    /// Indexes a triangle list, identical vertices are merged.
+    pub fn from_triangles(triangles: &[CachedVertex]) -> Self {
+        let mut mesh = Self::default();
+        let mut seen = HashMap::new();
+        for vertex in triangles {
+            let index = *seen.entry(vertex_key(vertex)).or_insert_with(|| {
+                mesh.vertices.push(*vertex);
+                mesh.vertices.len() as u32 - 1
+            });
+            mesh.indices.push(index);
+        }
+        mesh
+    }
+
+    /// The cache file contents, `from_bytes` reverses it.
+    ///
+    /// ```
+    /// # use gears_assets::{CachedVertex, MeshData};
+    /// let vertex = CachedVertex::default();
+    /// let mesh = MeshData::from_triangles(&[vertex, vertex, vertex]);
+    /// assert_eq!(mesh.vertices.len(), 1);
+    /// assert_eq!(MeshData::from_bytes(&mesh.to_bytes()).unwrap(), mesh);
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(
+            HEADER_SIZE + self.vertices.len() * VERTEX_SIZE + self.indices.len() * 4,
+        );
+        header(
+            &mut bytes,
+            MESH_MAGIC,
+            self.vertices.len() as u32,
+            self.indices.len() as u32,
+        );
+        for vertex in self.vertices.iter() {
+            let floats = vertex
+                .position
+                .iter()
+                .chain(vertex.normal.iter())
+                .chain(vertex.uv.iter());
+            for float in floats {
+                bytes.extend_from_slice(&float.to_le_bytes());
+            }
+        }
+        for index in self.indices.iter() {
+            bytes.extend_from_slice(&index.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, AssetError> {
+        let (vertex_count, index_count) = read_header(bytes, MESH_MAGIC)?;
+        let (vertex_count, index_count) = (vertex_count as usize, index_count as usize);
+        let vertex_bytes = &bytes[HEADER_SIZE..];
+        if vertex_bytes.len() != vertex_count * VERTEX_SIZE + index_count * 4 {
+            return Err(AssetError::InvalidCache(
+                "mesh size does not match its header",
+            ));
+        }
+
+        let float = |offset: usize| f32::from_le_bytes(word(vertex_bytes, offset));
+        let vertices = (0..vertex_count)
+            .map(|vertex| {
+                let at = |component: usize| float(vertex * VERTEX_SIZE + component * 4);
+                CachedVertex {
+                    position: [at(0), at(1), at(2)],
+                    normal: [at(3), at(4), at(5)],
+                    uv: [at(6), at(7)],
+                }
+            })
+            .collect();
+
+        let index_bytes = &vertex_bytes[vertex_count * VERTEX_SIZE..];
+        let indices = (0..index_count)
+            .map(|index| u32::from_le_bytes(word(index_bytes, index * 4)))
+            .collect::<Vec<_>>();
+        if indices.iter().any(|&index| index as usize >= vertex_count) {
+            return Err(AssetError::InvalidCache("mesh index out of range"));
+        }
+
+        Ok(Self { vertices, indices })
+    }
+}
+
+impl TextureData {
+    /// The cache file contents, `from_bytes` reverses it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.pixels.len());
+        header(&mut bytes, TEXTURE_MAGIC, self.width, self.height);
+        bytes.extend_from_slice(&self.pixels);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, AssetError> {
+        let (width, height) = read_header(bytes, TEXTURE_MAGIC)?;
+        let pixels = &bytes[HEADER_SIZE..];
+        if pixels.len() != width as usize * height as usize * 4 {
+            return Err(AssetError::InvalidCache(
+                "texture size does not match its header",
+            ));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            pixels: pixels.to_vec(),
+        })
+    }
+}
+
+impl MeshAsset {
+    pub const fn new(bytes: &'static [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn bytes(&self) -> &'static [u8] {
+        self.bytes
+    }
+
+    /// Only fails if the cache was written by another version of this crate.
+    pub fn load(&self) -> Result<MeshData, AssetError> {
+        MeshData::from_bytes(self.bytes)
+    }
+}
+
+impl TextureAsset {
+    pub const fn new(bytes: &'static [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn bytes(&self) -> &'static [u8] {
+        self.bytes
+    }
+
+    /// Only fails if the cache was written by another version of this crate.
+    pub fn load(&self) -> Result<TextureData, AssetError> {
+        TextureData::from_bytes(self.bytes)
+    }
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetError::Io(err) => write!(f, "Asset io failed: {}", err),
+            AssetError::Import(err) => write!(f, "Asset import failed: {}", err),
+            AssetError::InvalidCache(err) => write!(f, "Invalid asset cache: {}", err),
+            AssetError::DuplicateName(name) => {
+                write!(f, "Two assets would be named '{}'", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AssetError {}
+
+impl From<io::Error> for AssetError {
+    fn from(err: io::Error) -> Self {
+        AssetError::Io(err)
+    }
+}
+
+// bit exact, so -0.0 and 0.0 or NaNs are never merged
+fn vertex_key(vertex: &CachedVertex) -> [u32; 8] {
+    let floats = vertex
+        .position
+        .iter()
+        .chain(vertex.normal.iter())
+        .chain(vertex.uv.iter());
+    let mut key = [0; 8];
+    for (key, float) in key.iter_mut().zip(floats) {
+        *key = float.to_bits();
+    }
+    key
+}
+
+fn header(bytes: &mut Vec<u8>, magic: &[u8; 4], a: u32, b: u32) {
+    bytes.extend_from_slice(magic);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&a.to_le_bytes());
+    bytes.extend_from_slice(&b.to_le_bytes());
+}
+
+// the two counts after the magic and version
+fn read_header(bytes: &[u8], magic: &[u8; 4]) -> Result<(u32, u32), AssetError> {
+    if bytes.len() < HEADER_SIZE || &bytes[0..4] != magic {
+        return Err(AssetError::InvalidCache("wrong magic"));
+    }
+    if u32::from_le_bytes(word(bytes, 4)) != VERSION {
+        return Err(AssetError::InvalidCache("unsupported version"));
+    }
+    Ok((
+        u32::from_le_bytes(word(bytes, 8)),
+        u32::from_le_bytes(word(bytes, 12)),
+    ))
+}
+
+fn word(bytes: &[u8], offset: usize) -> [u8; 4] {
+    bytes[offset..offset + 4].try_into().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texture() -> TextureData {
+        TextureData {
+            width: 2,
+            height: 3,
+            pixels: (0..24).collect(),
+        }
+    }
+
+    fn invalid<T: fmt::Debug>(result: Result<T, AssetError>) -> &'static str {
+        match result {
+            Err(AssetError::InvalidCache(reason)) => reason,
+            other => panic!("expected an invalid cache, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn texture_round_trip() {
+        let texture = texture();
+        let bytes = texture.to_bytes();
+        assert_eq!(bytes.len(), HEADER_SIZE + 24);
+        assert_eq!(TextureData::from_bytes(&bytes).unwrap(), texture);
+
+        let empty = TextureData::default();
+        assert_eq!(TextureData::from_bytes(&empty.to_bytes()).unwrap(), empty);
+    }
+
+    #[test]
+    fn texture_wrong_magic() {
+        let mut bytes = texture().to_bytes();
+        assert_eq!(invalid(MeshData::from_bytes(&bytes)), "wrong magic");
+
+        bytes[0] = b'X';
+        assert_eq!(invalid(TextureData::from_bytes(&bytes)), "wrong magic");
+        assert_eq!(invalid(TextureData::from_bytes(&[])), "wrong magic");
+    }
+
+    #[test]
+    fn texture_wrong_version() {
+        let mut bytes = texture().to_bytes();
+        bytes[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(
+            invalid(TextureData::from_bytes(&bytes)),
+            "unsupported version"
+        );
+    }
+
+    #[test]
+    fn texture_wrong_size() {
+        let bytes = texture().to_bytes();
+        assert_eq!(
+            invalid(TextureData::from_bytes(&bytes[..bytes.len() - 1])),
+            "texture size does not match its header"
+        );
+        // only the header has no pixels, less is not a cache at all
+        assert!(TextureData::from_bytes(&bytes[..HEADER_SIZE]).is_err());
+        assert_eq!(
+            invalid(TextureData::from_bytes(&bytes[..HEADER_SIZE - 1])),
+            "wrong magic"
+        );
+
+        let mut longer = bytes;
+        longer.push(0);
+        assert!(TextureData::from_bytes(&longer).is_err());
+    }
+
+    #[test]
+    fn mesh_index_out_of_range() {
+        let mesh = MeshData {
+            vertices: vec![CachedVertex::default()],
+            indices: vec![0, 1, 0],
+        };
+        assert_eq!(
+            invalid(MeshData::from_bytes(&mesh.to_bytes())),
+            "mesh index out of range"
+        );
+    }
+
+    #[test]
+    fn mesh_truncated() {
+        let vertex = CachedVertex::default();
+        let bytes = MeshData::from_triangles(&[vertex, vertex, vertex]).to_bytes();
+        assert_eq!(
+            invalid(MeshData::from_bytes(&bytes[..bytes.len() - 4])),
+            "mesh size does not match its header"
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use std::{fs, path::Path};
+use wavefront_obj::obj::{self, Primitive};
+
+use crate::format::{AssetError, CachedVertex, MeshData, TextureData};
+
+/// Every object of an `.obj` or every mesh of a `.gltf` or `.glb` as one mesh.
+///
+/// Only triangles are imported. Missing normals become face normals,
+/// missing texture coordinates zero. glTF node transforms are not applied.
+pub fn import_mesh<P: AsRef<Path>>(path: P) -> Result<MeshData, AssetError> {
+    let path = path.as_ref();
+    match extension(path).as_str() {
+        "obj" => import_obj(&fs::read_to_string(path)?),
+        "gltf" | "glb" => import_gltf(path),
+        other => Err(AssetError::Import(format!(
+            "'{}' is not a mesh format",
+            other
+        ))),
+    }
+}
+
+/// Any image the `image` crate decodes, converted to RGBA.
+pub fn import_texture<P: AsRef<Path>>(path: P) -> Result<TextureData, AssetError> {
+    let image = image::open(path)
+        .map_err(|err| AssetError::Import(err.to_string()))?
+        .into_rgba8();
+    let (width, height) = image.dimensions();
+
+    Ok(TextureData {
+        width,
+        height,
+        pixels: image.into_raw(),
+    })
+}
+
+pub(crate) fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+fn import_obj(source: &str) -> Result<MeshData, AssetError> {
+    let objset = obj::parse(source).map_err(|err| AssetError::Import(format!("{:?}", err)))?;
+
+    let mut triangles = Vec::new();
+    for object in objset.objects.iter() {
+        let vertex = |(vertex_id, uv_id, normal_id): obj::VTNIndex, face_normal: [f32; 3]| {
+            let position = object.vertices[vertex_id];
+            CachedVertex {
+                position: [position.x as f32, position.y as f32, position.z as f32],
+                normal: match normal_id {
+                    Some(normal_id) => {
+                        let normal = object.normals[normal_id];
+                        [normal.x as f32, normal.y as f32, normal.z as f32]
+                    }
+                    None => face_normal,
+                },
+                // OBJ v points up
+                uv: match uv_id {
+                    Some(uv_id) => {
+                        let uv = object.tex_vertices[uv_id];
+                        [uv.u as f32, 1.0 - uv.v as f32]
+                    }
+                    None => [0.0, 0.0],
+                },
+            }
+        };
+
+        for shape in object.geometry.iter().flat_map(|g| g.shapes.iter()) {
+            match shape.primitive {
+                Primitive::Triangle(a, b, c) => {
+                    let corner = |(vertex_id, _, _): obj::VTNIndex| {
+                        let vertex = object.vertices[vertex_id];
+                        [vertex.x as f32, vertex.y as f32, vertex.z as f32]
+                    };
+                    let normal = face_normal(corner(a), corner(b), corner(c));
+                    triangles.push(vertex(a, normal));
+                    triangles.push(vertex(b, normal));
+                    triangles.push(vertex(c, normal));
+                }
+                _ => return Err(AssetError::Import("Only triangles".to_owned())),
+            }
+        }
+    }
+
+    Ok(MeshData::from_triangles(&triangles))
+}
+
+fn import_gltf(path: &Path) -> Result<MeshData, AssetError> {
+    let (document, buffers, _) =
+        gltf::import(path).map_err(|err| AssetError::Import(err.to_string()))?;
+
+    let mut mesh = MeshData::default();
+    for primitive in document.meshes().flat_map(|mesh| mesh.primitives()) {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            return Err(AssetError::Import("Only triangles".to_owned()));
+        }
+
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()].0[..]));
+        let positions = reader
+            .read_positions()
+            .ok_or_else(|| AssetError::Import("Primitive without positions".to_owned()))?
+            .collect::<Vec<_>>();
+        let indices = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        let normals = match reader.read_normals() {
+            Some(normals) => normals.collect::<Vec<_>>(),
+            None => vertex_normals(&positions, &indices),
+        };
+        let uvs = match reader.read_tex_coords(0) {
+            Some(uvs) => uvs.into_f32().collect::<Vec<_>>(),
+            None => vec![[0.0, 0.0]; positions.len()],
+        };
+        if normals.len() != positions.len() || uvs.len() != positions.len() {
+            return Err(AssetError::Import(format!(
+                "Primitive with {} positions, {} normals and {} texture coordinates",
+                positions.len(),
+                normals.len(),
+                uvs.len()
+            )));
+        }
+
+        let base = mesh.vertices.len() as u32;
+        mesh.vertices
+            .extend(positions.iter().zip(normals.iter().zip(uvs.iter())).map(
+                |(position, (normal, uv))| CachedVertex {
+                    position: *position,
+                    normal: *normal,
+                    uv: *uv,
+                },
+            ));
+        mesh.indices
+            .extend(indices.iter().map(|index| base + index));
+    }
+
+    Ok(mesh)
+}
+
+fn face_normal(a: [f32; 3], b: [f32; 3], c: [f32; 3]) -> [f32; 3] {
+    normalize(cross(a, b, c))
+}
+
+// twice the area long
+fn cross(a: [f32; 3], b: [f32; 3], c: [f32; 3]) -> [f32; 3] {
+    let ab = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+    let ac = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+    [
+        ab[1] * ac[2] - ab[2] * ac[1],
+        ab[2] * ac[0] - ab[0] * ac[2],
+        ab[0] * ac[1] - ab[1] * ac[0],
+    ]
+}
+
+// degenerate normals point up
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if length > 0.0 {
+        [v[0] / length, v[1] / length, v[2] / length]
+    } else {
+        [0.0, 1.0, 0.0]
+    }
+}
+
+// area weighted face normals summed per vertex
+fn vertex_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    let mut normals = vec![[0.0; 3]; positions.len()];
+    for triangle in indices.chunks_exact(3) {
+        let normal = cross(
+            positions[triangle[0] as usize],
+            positions[triangle[1] as usize],
+            positions[triangle[2] as usize],
+        );
+        for &index in triangle {
+            for axis in 0..3 {
+                normals[index as usize][axis] += normal[axis];
+            }
+        }
+    }
+
+    normals.into_iter().map(normalize).collect()
+}
@@ -0,0 +1,47 @@
+//! Converts OBJ and glTF meshes and PNG or JPEG textures into the gears
+//! binary asset cache at build time.
+//!
+//! A build script converts a directory and generates a module with a typed
+//! constant per asset, the cache files are embedded with `include_bytes!`:
+//!
+//! ```ignore
+//! // build.rs
+//! fn main() {
+//!     gears_assets::AssetBuilder::new()
+//!         .with_dir("res")
+//!         .build()
+//!         .unwrap();
+//! }
+//!
+//! // main.rs
+//! mod assets {
+//!     include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+//! }
+//!
+//! let gear = assets::GEAR.load()?;
+//! let vertices = gear.vertices.iter().map(|v| VertexData::from(*v)).collect::<Vec<_>>();
+//! let vb = VertexBuffer::new_with_data(&renderer, &vertices)?;
+//! let ib = IndexBuffer::new_with_data(&renderer, &gear.indices)?;
+//! ```
+//!
+//! Reading the cache needs no dependencies, shipping builds can use the
+//! crate without default features. The `import` feature adds the text and
+//! image format decoders, `AssetBuilder` and the `gears-assets` CLI for
+//! converting assets by hand.
+
+pub use format::{
+    AssetError, CachedVertex, MeshAsset, MeshData, TextureAsset, TextureData, MESH_EXTENSION,
+    TEXTURE_EXTENSION,
+};
+
+#[cfg(feature = "import")]
+pub use builder::{AssetBuilder, AssetKind};
+#[cfg(feature = "import")]
+pub use import::{import_mesh, import_texture};
+
+mod format;
+
+#[cfg(feature = "import")]
+mod builder;
+#[cfg(feature = "import")]
+mod import;
@@ -0,0 +1,53 @@
+use std::{env, path::PathBuf, process};
+
+use gears_assets::{AssetBuilder, AssetKind};
+
+const USAGE: &str = "usage: gears-assets <file or dir>... -o <out dir> [--module <file name>]";
+
+fn main() {
+    let mut builder = AssetBuilder::new();
+    let mut out_dir = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--out" => out_dir = args.next().map(PathBuf::from),
+            "--module" => match args.next() {
+                Some(module) => builder = builder.with_module_name(module),
+                None => exit(USAGE),
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => {
+                let path = PathBuf::from(arg);
+                builder = if path.is_dir() {
+                    builder.with_dir(path)
+                } else if AssetKind::of(&path).is_some() {
+                    let name = path
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    builder.with_asset(name, path)
+                } else {
+                    exit(&format!(
+                        "'{}' is not an asset or directory",
+                        path.display()
+                    ))
+                };
+            }
+        }
+    }
+
+    let out_dir = out_dir.unwrap_or_else(|| exit(USAGE));
+    match builder.with_out_dir(out_dir).build() {
+        Ok(module) => println!("{}", module.display()),
+        Err(err) => exit(&err.to_string()),
+    }
+}
+
+fn exit(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
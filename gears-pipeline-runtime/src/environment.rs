@@ -0,0 +1,26 @@
+//! Specialization constants of `#include "gears://environment.glsl"`.
+//!
+//! `pipeline!` generates the include from `CONSTANTS` and `gears` specializes
+//! the shaders with values in the same order, so both share one list.
+
+/// Type, name and default value of each constant, booleans are 0 or 1.
+pub const CONSTANTS: [(&str, &str, u32); 6] = [
+    ("int", "GEARS_MSAA_SAMPLES", 1),
+    ("int", "GEARS_MAX_LIGHTS", DEFAULT_MAX_LIGHTS),
+    ("int", "GEARS_FRAMES_IN_FLIGHT", 3),
+    ("bool", "GEARS_DESCRIPTOR_INDEXING", 0),
+    ("bool", "GEARS_MESH_SHADER", 0),
+    ("bool", "GEARS_RAY_TRACING", 0),
+];
+
+/// `constant_id` of the first constant, the others follow in order.
+///
+/// `FIRST_CONSTANT_ID..END_CONSTANT_ID` is reserved, `pipeline!` rejects
+/// shaders declaring own constants in it.
+pub const FIRST_CONSTANT_ID: u32 = 1000;
+
+/// One past the last reserved `constant_id`.
+pub const END_CONSTANT_ID: u32 = FIRST_CONSTANT_ID + CONSTANTS.len() as u32;
+
+/// `GEARS_MAX_LIGHTS` unless the renderer sets another one.
+pub const DEFAULT_MAX_LIGHTS: u32 = 256;
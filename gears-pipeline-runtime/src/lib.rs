@@ -0,0 +1,21 @@
+//! Types and helpers the code generated by `gears_pipeline::pipeline!` depends on.
+//!
+//! `no_std` without default features. `alloc` adds `inflate_spirv`, `std` adds
+//! `Lazy` for the `compress` option and `cgmath` the vector conversions of the
+//! packed vertex types. Pipelines with the `no_std` option only need this crate.
+
+#![no_std]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub use once_cell::sync::Lazy;
+pub use packed::{Half, PackedNormal};
+#[cfg(feature = "alloc")]
+pub use spirv::inflate_spirv;
+pub use spirv::spirv_bytes;
+
+pub mod environment;
+mod packed;
+mod spirv;
@@ -0,0 +1,234 @@
+#[cfg(feature = "cgmath")]
+use cgmath::{Vector2, Vector3, Vector4};
+
+/// IEEE 754 half precision float, the Rust side of ```f16vec2``` and ```f16vec4``` vertex fields.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct Half(pub u16);
+
+/// Normal packed as A2B10G10R10 signed normalized, the Rust side of ```packed_normal``` vertex fields.
+///
+/// xyz get 10 bits each and w the top 2 bits, read as a ```vec4``` in the shader.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct PackedNormal(pub u32);
+
+impl Half {
+    pub const ZERO: Self = Self(0);
+    pub const ONE: Self = Self(0x3c00);
+
+    /// Rounds to the nearest half, too large values become infinity.
+    pub fn from_f32(value: f32) -> Self {
+        let bits = value.to_bits();
+        let sign = ((bits >> 16) & 0x8000) as u16;
+        let exp = ((bits >> 23) & 0xff) as i32;
+        let man = bits & 0x007f_ffff;
+
+        if exp == 0xff {
+            // keeps NaNs NaN
+            let nan = if man != 0 { 0x0200 } else { 0 };
+            return Self(sign | 0x7c00 | nan);
+        }
+
+        let exp = exp - 127 + 15;
+        if exp >= 0x1f {
+            return Self(sign | 0x7c00);
+        }
+
+        // subnormals get the implicit leading bit explicitly
+        let (man, shift) = if exp <= 0 {
+            if exp < -10 {
+                return Self(sign);
+            }
+            (man | 0x0080_0000, (14 - exp) as u32)
+        } else {
+            (man, 13)
+        };
+        let exp = exp.max(0) as u32;
+        let half = (exp << 10) | (man >> shift);
+
+        // round to nearest even, a carry into the exponent is still correct
+        let rem = man & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round = (rem > halfway || (rem == halfway && half & 1 == 1)) as u32;
+
+        Self(sign | (half + round) as u16)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        let sign = ((self.0 & 0x8000) as u32) << 16;
+        let exp = ((self.0 >> 10) & 0x1f) as u32;
+        let man = (self.0 & 0x03ff) as u32;
+
+        let bits = match exp {
+            0 => {
+                // zero or subnormal
+                let value = man as f32 / (1 << 24) as f32;
+                return if sign != 0 { -value } else { value };
+            }
+            0x1f => sign | 0x7f80_0000 | (man << 13),
+            _ => sign | ((exp + 127 - 15) << 23) | (man << 13),
+        };
+
+        f32::from_bits(bits)
+    }
+
+    #[cfg(feature = "cgmath")]
+    pub fn vec2(value: Vector2<f32>) -> [Self; 2] {
+        [Self::from_f32(value.x), Self::from_f32(value.y)]
+    }
+
+    #[cfg(feature = "cgmath")]
+    pub fn vec4(value: Vector4<f32>) -> [Self; 4] {
+        [
+            Self::from_f32(value.x),
+            Self::from_f32(value.y),
+            Self::from_f32(value.z),
+            Self::from_f32(value.w),
+        ]
+    }
+}
+
+impl PackedNormal {
+    /// Components are clamped to -1..=1, w is rounded to -1, 0 or 1.
+    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+        Self(snorm(x, 10) | snorm(y, 10) << 10 | snorm(z, 10) << 20 | snorm(w, 2) << 30)
+    }
+
+    /// xyzw
+    pub fn components(self) -> [f32; 4] {
+        [
+            unsnorm(self.0, 0, 10),
+            unsnorm(self.0, 10, 10),
+            unsnorm(self.0, 20, 10),
+            unsnorm(self.0, 30, 2),
+        ]
+    }
+
+    #[cfg(feature = "cgmath")]
+    pub fn unpack(self) -> Vector4<f32> {
+        self.components().into()
+    }
+}
+
+impl From<f32> for Half {
+    fn from(value: f32) -> Self {
+        Self::from_f32(value)
+    }
+}
+
+impl From<Half> for f32 {
+    fn from(value: Half) -> Self {
+        value.to_f32()
+    }
+}
+
+#[cfg(feature = "cgmath")]
+impl From<Vector3<f32>> for PackedNormal {
+    fn from(value: Vector3<f32>) -> Self {
+        Self::new(value.x, value.y, value.z, 0.0)
+    }
+}
+
+#[cfg(feature = "cgmath")]
+impl From<Vector4<f32>> for PackedNormal {
+    fn from(value: Vector4<f32>) -> Self {
+        Self::new(value.x, value.y, value.z, value.w)
+    }
+}
+
+// two's complement snorm with `bits` bits
+fn snorm(value: f32, bits: u32) -> u32 {
+    let max = ((1 << (bits - 1)) - 1) as f32;
+    let value = value.clamp(-1.0, 1.0) * max;
+    // rounds half away from zero like `f32::round`, which needs std
+    let value = if value < 0.0 {
+        value - 0.5
+    } else {
+        value + 0.5
+    } as i32;
+    value as u32 & ((1 << bits) - 1)
+}
+
+fn unsnorm(packed: u32, shift: u32, bits: u32) -> f32 {
+    let max = ((1 << (bits - 1)) - 1) as f32;
+    // sign extends the field
+    let value = ((packed << (32 - shift - bits)) as i32) >> (32 - bits);
+    (value as f32 / max).max(-1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_exact_values() {
+        assert_eq!(Half::ZERO, Half::from_f32(0.0));
+        assert_eq!(Half::ONE, Half::from_f32(1.0));
+        assert_eq!(Half(0x8000), Half::from_f32(-0.0));
+        assert_eq!(Half(0xc000), Half::from_f32(-2.0));
+        assert_eq!(Half(0x7bff), Half::from_f32(65504.0));
+        assert_eq!(Half(0x3555), Half::from_f32(1.0 / 3.0));
+
+        for &value in &[0.0, 1.0, -2.0, 0.5, 65504.0, -0.000_061_035_156] {
+            assert_eq!(value, Half::from_f32(value).to_f32());
+        }
+    }
+
+    #[test]
+    fn half_rounds_to_nearest_even() {
+        // halfway between 1.0 and the next half rounds down to the even 1.0
+        assert_eq!(Half::ONE, Half::from_f32(1.0 + 1.0 / 2048.0));
+        // halfway between 0x3c01 and 0x3c02 rounds up to the even 0x3c02
+        assert_eq!(Half(0x3c02), Half::from_f32(1.0 + 3.0 / 2048.0));
+        // a carry out of the mantissa bumps the exponent
+        assert_eq!(Half(0x4000), Half::from_f32(2.0 - 1.0 / 4096.0));
+    }
+
+    #[test]
+    fn half_subnormals_and_specials() {
+        let smallest = 1.0 / (1 << 24) as f32;
+        assert_eq!(Half(0x0001), Half::from_f32(smallest));
+        assert_eq!(smallest, Half(0x0001).to_f32());
+        assert_eq!(Half(0x0200), Half::from_f32(smallest * 512.0));
+        assert_eq!(Half::ZERO, Half::from_f32(smallest / 4.0));
+
+        assert_eq!(Half(0x7c00), Half::from_f32(f32::INFINITY));
+        assert_eq!(Half(0xfc00), Half::from_f32(-70000.0));
+        assert!(Half::from_f32(f32::NAN).to_f32().is_nan());
+        assert_eq!(f32::NEG_INFINITY, Half(0xfc00).to_f32());
+    }
+
+    #[test]
+    fn packed_normal_layout() {
+        assert_eq!(PackedNormal(0), PackedNormal::new(0.0, 0.0, 0.0, 0.0));
+        assert_eq!(PackedNormal(0x1ff), PackedNormal::new(1.0, 0.0, 0.0, 0.0));
+        assert_eq!(
+            PackedNormal(0x201 << 10),
+            PackedNormal::new(0.0, -1.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            PackedNormal(0x1ff << 20),
+            PackedNormal::new(0.0, 0.0, 1.0, 0.0)
+        );
+        assert_eq!(PackedNormal(1 << 30), PackedNormal::new(0.0, 0.0, 0.0, 1.0));
+        assert_eq!(
+            PackedNormal(3 << 30),
+            PackedNormal::new(0.0, 0.0, 0.0, -1.0)
+        );
+    }
+
+    #[test]
+    fn packed_normal_round_trip() {
+        let normal = PackedNormal::new(0.5, -0.25, 2.0, -1.0);
+        let [x, y, z, w] = normal.components();
+
+        assert!((x - 0.5).abs() < 1.0 / 511.0);
+        assert!((y + 0.25).abs() < 1.0 / 511.0);
+        assert_eq!(1.0, z);
+        assert_eq!(-1.0, w);
+
+        // the most negative field value is clamped back to -1
+        assert_eq!(-1.0, PackedNormal(0x200).components()[0]);
+    }
+}
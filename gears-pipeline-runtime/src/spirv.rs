@@ -0,0 +1,48 @@
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::{mem, slice};
+
+/// Decompresses SPIR-V embedded by `pipeline!` with the `compress` option.
+///
+/// The words are stored little endian, so the macro host and the target may differ.
+#[cfg(feature = "alloc")]
+pub fn inflate_spirv(compressed: &[u8]) -> Vec<u32> {
+    let bytes = miniz_oxide::inflate::decompress_to_vec(compressed)
+        .expect("Embedded SPIR-V is not valid deflate data");
+
+    bytes
+        .chunks_exact(mem::size_of::<u32>())
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect()
+}
+
+/// SPIR-V words as bytes, for APIs taking `&[u8]`.
+pub fn spirv_bytes(words: &[u32]) -> &[u8] {
+    // u8 has no alignment requirement
+    unsafe { slice::from_raw_parts(words.as_ptr() as *const u8, mem::size_of_val(words)) }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inflate_little_endian() {
+        let words = [0x0723_0203u32, 0x0001_0000, 0xdead_beef];
+        let bytes = words
+            .iter()
+            .flat_map(|word| word.to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let compressed = miniz_oxide::deflate::compress_to_vec(&bytes, 10);
+
+        assert_eq!(&words[..], &inflate_spirv(&compressed)[..]);
+    }
+
+    #[test]
+    fn bytes_of_words() {
+        let words = [0x0723_0203u32, 1];
+
+        assert_eq!(8, spirv_bytes(&words).len());
+        assert_eq!(&words[0].to_ne_bytes(), &spirv_bytes(&words)[..4]);
+    }
+}
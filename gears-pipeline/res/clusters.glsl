@@ -0,0 +1,86 @@
+// clustered point lights of a LightClusters pass, for forward shading with
+// #include "gears://clusters.glsl"
+//
+// the three storage buffers of LightClusters::bind_to are at set 1 from
+// CLUSTER_BINDING, 0 unless defined before the include
+
+#ifndef GEARS_CLUSTERS
+#define GEARS_CLUSTERS
+
+#ifndef CLUSTER_BINDING
+#define CLUSTER_BINDING 0
+#endif
+
+struct ClusterLight {
+	// world space position in xyz and radius in w
+	vec4 position;
+	// linear color in rgb and intensity in w
+	vec4 color;
+};
+
+layout(std430, set = 1, binding = CLUSTER_BINDING) readonly buffer ClusterLights {
+	ClusterLight cluster_lights[];
+};
+
+layout(std430, set = 1, binding = CLUSTER_BINDING + 1) readonly buffer Clusters {
+	// screen width and height, near and far
+	vec4 cluster_params;
+	// x, y and z cluster counts and the max lights per cluster
+	uvec4 cluster_grid;
+	uint cluster_counts[];
+};
+
+layout(std430, set = 1, binding = CLUSTER_BINDING + 2) readonly buffer ClusterIndices {
+	uint cluster_indices[];
+};
+
+// the cluster of a fragment, `view_depth` is the positive view space distance
+// along the view direction
+uint cluster_index(vec2 frag_coord, float view_depth) {
+	uvec3 grid = cluster_grid.xyz;
+	float near = cluster_params.z;
+	float far = cluster_params.w;
+
+	uvec2 tile = min(uvec2(frag_coord / cluster_params.xy * vec2(grid.xy)), grid.xy - 1);
+	float slice = log(max(view_depth, near) / near) / log(far / near) * float(grid.z);
+	uint z = min(uint(slice), grid.z - 1);
+
+	return tile.x + tile.y * grid.x + z * grid.x * grid.y;
+}
+
+// diffuse and blinn-phong specular light of the point lights reaching a
+// fragment, the same model as the deferred composition
+vec3 clustered_point_lights(
+	vec2 frag_coord,
+	float view_depth,
+	vec3 position,
+	vec3 normal,
+	vec3 view,
+	vec3 albedo
+) {
+	uint cluster = cluster_index(frag_coord, view_depth);
+	uint first = cluster * cluster_grid.w;
+
+	vec3 color = vec3(0.0);
+	for (uint i = 0; i < cluster_counts[cluster]; i++) {
+		ClusterLight light = cluster_lights[cluster_indices[first + i]];
+
+		vec3 to_light = light.position.xyz - position;
+		float dist = length(to_light);
+		if (dist > light.position.w) {
+			continue;
+		}
+		to_light /= dist;
+
+		float attenuation = 1.0 - dist / light.position.w;
+		attenuation *= attenuation;
+
+		float diffuse = max(dot(normal, to_light), 0.0);
+		float specular = pow(max(dot(normal, normalize(to_light + view)), 0.0), 32.0);
+
+		color += (albedo * diffuse + specular) * light.color.rgb * light.color.w * attenuation;
+	}
+	return color;
+}
+
+#endif
@@ -1,8 +1,26 @@
-use std::{fs::File, io::Read, path::Path};
+use gears_pipeline_runtime::environment;
+use std::{cell::RefCell, collections::BTreeSet, fs::File, io::Read, iter, path::Path};
 
 use proc_macro2::Punct;
+use regex::Regex;
+#[cfg(feature = "validate")]
+use spirv_tools::val::Validator;
 use syn::{parse::ParseStream, Error, LitStr, Token};
 
+use crate::scanner;
+
+/// Virtual include containing the uniform declarations of every module in the pipeline.
+pub const BINDINGS_INCLUDE: &str = "gears://bindings.glsl";
+
+/// Virtual include describing the target environment.
+pub const ENVIRONMENT_INCLUDE: &str = "gears://environment.glsl";
+
+/// Built-in include reading the lights of `gears::LightClusters`.
+pub const CLUSTERS_INCLUDE: &str = "gears://clusters.glsl";
+
+// also `gears::CLUSTERS_GLSL`
+const CLUSTERS_GLSL: &str = include_str!("../res/clusters.glsl");
+
 // struct/enum
 
 pub struct DefinesInput {
@@ -76,27 +94,172 @@ pub fn compile_shader_module(
     name: &str,
     entry: &str,
     include_path: Option<&Path>,
+    bindings: &str,
     defines: &DefinesInput,
     default_defines: bool,
     debug: bool,
-) -> Result<shaderc::CompilationArtifact, String> {
-    let compiler = unsafe {
+) -> Result<(shaderc::CompilationArtifact, Vec<String>), String> {
+    let compiler = static_compiler();
+    let included = RefCell::new(Vec::new());
+    let options = compile_options(
+        kind,
+        include_path,
+        bindings,
+        environment_include(true),
+        defines,
+        default_defines,
+        &included,
+    );
+
+    let result = if debug {
+        compiler
+            .preprocess(source, name, entry, Some(&options))
+            .map_or_else(|err| Err(format!("{}", err)), |res| Err(res.as_text()))
+    } else {
+        compiler
+            .compile_into_spirv(source, kind, name, entry, Some(&options))
+            .or_else(|err| Err(format!("{}", err)))
+    };
+
+    let spirv = result.or_else(|err| Err(with_source_lines(err, source)))?;
+    check_constant_ids(source, &included.borrow())?;
+    #[cfg(feature = "validate")]
+    validate(kind, spirv.as_binary()).or_else(|err| Err(with_source_lines(err, source)))?;
+    let warnings = check_defines(source, &included.borrow(), defines);
+
+    Ok((spirv, warnings))
+}
+
+/// Resolves includes and defines without compiling, gears-pipeline defines are not added.
+pub fn preprocess_shader(
+    source: &str,
+    name: &str,
+    include_path: Option<&Path>,
+    bindings: &str,
+    defines: &DefinesInput,
+) -> Result<(String, Vec<String>), String> {
+    // the kind is only used for the gears-pipeline defines
+    let included = RefCell::new(Vec::new());
+    let options = compile_options(
+        shaderc::ShaderKind::InferFromSource,
+        include_path,
+        bindings,
+        environment_include(false),
+        defines,
+        false,
+        &included,
+    );
+
+    let preprocessed = static_compiler()
+        .preprocess(source, name, "main", Some(&options))
+        .map(|res| res.as_text())
+        .or_else(|err| Err(with_source_lines(format!("{}", err), source)))?;
+    let warnings = check_defines(source, &included.borrow(), defines);
+
+    Ok((preprocessed, warnings))
+}
+
+// fn
+
+// specialization constants for Vulkan, `glsl!` output is not specialized so
+// the defaults are plain constants there
+fn environment_include(vulkan: bool) -> String {
+    let mut include = String::from("#ifndef GEARS_ENVIRONMENT\n#define GEARS_ENVIRONMENT\n");
+    include += if vulkan {
+        "#define GEARS_BACKEND_VULKAN\n"
+    } else {
+        "#define GEARS_BACKEND_GLSL\n"
+    };
+    for (id, &(ty, name, default)) in
+        (environment::FIRST_CONSTANT_ID..).zip(&environment::CONSTANTS)
+    {
+        let default = match ty {
+            "bool" => (default != 0).to_string(),
+            _ => default.to_string(),
+        };
+        if vulkan {
+            include += &format!("layout(constant_id = {}) ", id);
+        }
+        include += &format!("const {} {} = {};\n", ty, name, default);
+    }
+    include + "#endif\n"
+}
+
+// the ids of the environment constants are reserved even without the include
+fn check_constant_ids(source: &str, included: &[String]) -> Result<(), String> {
+    let id_matcher = Regex::new(r#"\bconstant_id\s*=\s*(\d+)"#).unwrap();
+    let reserved = environment::FIRST_CONSTANT_ID..environment::END_CONSTANT_ID;
+
+    let sources = iter::once(scanner::strip_comments(source))
+        .chain(included.iter().map(|s| scanner::strip_comments(s)));
+    for source in sources {
+        for captures in id_matcher.captures_iter(&source) {
+            match captures[1].parse::<u32>() {
+                Ok(id) if reserved.contains(&id) => {
+                    return Err(format!(
+                        "constant_id {} is reserved for {}, use one outside of {}..{}",
+                        id, ENVIRONMENT_INCLUDE, reserved.start, reserved.end
+                    ))
+                }
+                _ => (),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn static_compiler() -> &'static mut shaderc::Compiler {
+    unsafe {
         if STATIC_COMPILER.is_none() {
             STATIC_COMPILER = Some(
                 shaderc::Compiler::new()
                     .unwrap_or_else(|| panic!("Could not create a shaderc Compiler")),
             );
-            STATIC_COMPILER.as_mut().unwrap()
-        } else {
-            STATIC_COMPILER.as_mut().unwrap()
         }
-    };
+        STATIC_COMPILER.as_mut().unwrap()
+    }
+}
 
+fn compile_options<'a>(
+    kind: shaderc::ShaderKind,
+    include_path: Option<&'a Path>,
+    bindings: &'a str,
+    environment: String,
+    defines: &DefinesInput,
+    default_defines: bool,
+    included: &'a RefCell<Vec<String>>,
+) -> shaderc::CompileOptions<'a> {
     let mut options = shaderc::CompileOptions::new()
         .unwrap_or_else(|| panic!("Could not create a shaderc CompileOptions"));
     options.set_optimization_level(shaderc::OptimizationLevel::Zero);
+    // GL_EXT_ray_tracing needs SPIR-V 1.4
+    if ray_tracing(kind) {
+        options.set_target_env(
+            shaderc::TargetEnv::Vulkan,
+            shaderc::EnvVersion::Vulkan1_2 as u32,
+        );
+    }
     options.set_include_callback(
-        |name: &str, _include_type: shaderc::IncludeType, _source: &str, _depth: usize| {
+        move |name: &str, _include_type: shaderc::IncludeType, _source: &str, _depth: usize| {
+            if name == BINDINGS_INCLUDE {
+                return Ok(shaderc::ResolvedInclude {
+                    content: bindings.into(),
+                    resolved_name: name.into(),
+                });
+            }
+            if name == ENVIRONMENT_INCLUDE {
+                return Ok(shaderc::ResolvedInclude {
+                    content: environment.clone(),
+                    resolved_name: name.into(),
+                });
+            }
+            if name == CLUSTERS_INCLUDE {
+                return Ok(shaderc::ResolvedInclude {
+                    content: CLUSTERS_GLSL.into(),
+                    resolved_name: name.into(),
+                });
+            }
+
             let full_path = include_path.ok_or("No include path")?.join(name);
             let mut file = File::open(&full_path).or(Err(format!(
                 "Could not open file '{}'",
@@ -108,6 +271,7 @@ pub fn compile_shader_module(
                 "Could not read from file '{}'",
                 full_path.to_str().ok_or("Path unwrap failed")?
             )))?;
+            included.borrow_mut().push(content.clone());
 
             Ok(shaderc::ResolvedInclude {
                 content,
@@ -136,6 +300,18 @@ pub fn compile_shader_module(
                 Some("layout(binding = _location) _data;"),
             );
         }
+        // inputs are per primitive vertex arrays, outputs go to the fragment shader
+        (shaderc::ShaderKind::Geometry, true) => {
+            options.add_macro_definition("GEARS_GEOMETRY", None);
+            options.add_macro_definition(
+                "GEARS_IN(_location, _data)",
+                Some("layout(location = _location) in _data[];"),
+            );
+            options.add_macro_definition(
+                "GEARS_OUT(_location, _data)",
+                Some("layout(location = _location) out _data;"),
+            );
+        }
         (shaderc::ShaderKind::Fragment, true) => {
             options.add_macro_definition("GEARS_FRAGMENT", None);
             options.add_macro_definition(
@@ -154,29 +330,187 @@ pub fn compile_shader_module(
         options.add_macro_definition(define, val.as_ref().map_or(None, |s| Some(s.as_str())));
     }
 
-    let result = if debug {
-        compiler
-            .preprocess(source, name, entry, Some(&options))
-            .map_or_else(|err| Err(format!("{}", err)), |res| Err(res.as_text()))
+    options
+}
+
+fn ray_tracing(kind: shaderc::ShaderKind) -> bool {
+    match kind {
+        shaderc::ShaderKind::RayGeneration
+        | shaderc::ShaderKind::Miss
+        | shaderc::ShaderKind::ClosestHit => true,
+        _ => false,
+    }
+}
+
+// shaderc occasionally emits SPIR-V that drivers only reject at pipeline creation
+#[cfg(feature = "validate")]
+fn validate(kind: shaderc::ShaderKind, spirv: &[u32]) -> Result<(), String> {
+    let target_env = if ray_tracing(kind) {
+        spirv_tools::TargetEnv::Vulkan_1_2
     } else {
-        compiler
-            .compile_into_spirv(source, kind, name, entry, Some(&options))
-            .or_else(|err| Err(format!("{}", err)))
+        spirv_tools::TargetEnv::Vulkan_1_0
     };
 
-    result.or_else(|err| {
-        let source_with_lines: String = source
-            .lines()
-            .enumerate()
-            .map(|(i, line)| format!("{:-4}: {}\n", i + 1, line))
-            .collect();
+    spirv_tools::val::create(Some(target_env))
+        .validate(spirv, None)
+        .or_else(|err| Err(format!("SPIR-V validation failed: {}", err)))
+}
+
+// unused `define:` entries and #ifdef names that nothing defines, likely typos
+fn check_defines(source: &str, included: &[String], defines: &DefinesInput) -> Vec<String> {
+    let ident_matcher = Regex::new(r#"[A-Za-z_]\w*"#).unwrap();
+    let checked_matcher =
+        Regex::new(r#"(?m)^\s*#\s*(ifdef|ifndef)\s+(?P<a>\w+)|\bdefined\s*\(?\s*(?P<b>\w+)"#)
+            .unwrap();
+    let defined_matcher = Regex::new(r#"(?m)^\s*#\s*define\s+(\w+)"#).unwrap();
+
+    let sources = iter::once(source.to_string())
+        .chain(included.iter().map(|s| scanner::strip_comments(s)))
+        .collect::<Vec<_>>();
+
+    let provided = defines
+        .defines
+        .iter()
+        .map(|(define, _)| define.split('(').next().unwrap_or("").trim())
+        .collect::<Vec<_>>();
+    let idents = sources
+        .iter()
+        .flat_map(|s| ident_matcher.find_iter(s).map(|m| m.as_str()))
+        .collect::<BTreeSet<_>>();
+    let defined = sources
+        .iter()
+        .flat_map(|s| defined_matcher.captures_iter(s).map(|c| c[1].to_string()))
+        .collect::<BTreeSet<_>>();
+    let checked = sources
+        .iter()
+        .flat_map(|s| {
+            checked_matcher.captures_iter(s).filter_map(|c| {
+                c.name("a")
+                    .or_else(|| c.name("b"))
+                    .map(|m| m.as_str().to_string())
+            })
+        })
+        .collect::<BTreeSet<_>>();
+
+    let unused = provided
+        .iter()
+        .filter(|define| !idents.contains(*define))
+        .map(|define| format!("Unused define '{}'", define));
+
+    // optional switches like `#ifdef DEBUGGING` are left undefined on purpose,
+    // only names one or two edits away from a known define look like typos
+    let known = provided
+        .iter()
+        .copied()
+        .chain(defined.iter().map(|name| name.as_str()))
+        .collect::<BTreeSet<_>>();
+    let undefined = checked
+        .iter()
+        .filter(|name| {
+            // gears-pipeline defines are stage specific, GL_ and __ are built in
+            !known.contains(name.as_str())
+                && !name.starts_with("GEARS_")
+                && !name.starts_with("GL_")
+                && !name.starts_with("__")
+        })
+        .filter_map(|name| {
+            known
+                .iter()
+                .find(|known| edit_distance(name, known) <= 2)
+                .map(|known| {
+                    format!(
+                        "'{}' is checked with #ifdef or defined() but never defined, did you mean '{}'?",
+                        name, known
+                    )
+                })
+        });
 
-        Err(format!(
-            "Error:\n{}\nSource:\n{}",
-            err,
-            source_with_lines.trim_end()
-        ))
-    })
+    unused.chain(undefined).collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substitution = diagonal + if a == *b { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn with_source_lines(err: String, source: &str) -> String {
+    let source_with_lines: String = source
+        .lines()
+        .enumerate()
+        .map(|(i, line)| format!("{:-4}: {}\n", i + 1, line))
+        .collect();
+
+    format!("Error:\n{}\nSource:\n{}", err, source_with_lines.trim_end())
 }
 
 static mut STATIC_COMPILER: Option<shaderc::Compiler> = None;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defines(names: &[&str]) -> DefinesInput {
+        DefinesInput {
+            defines: names.iter().map(|name| (name.to_string(), None)).collect(),
+        }
+    }
+
+    #[test]
+    fn optional_switch_is_not_reported() {
+        let source = "#ifdef DEBUGGING\n#endif\nvoid main() {}";
+
+        assert!(check_defines(source, &[], &defines(&[])).is_empty());
+    }
+
+    #[test]
+    fn typo_is_reported() {
+        let source = "#ifdef FRAGMNT\n#endif\nvoid main() {}";
+        let warnings = check_defines(source, &[], &defines(&["FRAGMENT"]));
+
+        assert_eq!(2, warnings.len());
+        assert!(warnings[0].contains("Unused define 'FRAGMENT'"));
+        assert!(warnings[1].contains("did you mean 'FRAGMENT'"));
+    }
+
+    #[test]
+    fn distance() {
+        assert_eq!(0, edit_distance("FRAGMENT", "FRAGMENT"));
+        assert_eq!(1, edit_distance("FRAGMNT", "FRAGMENT"));
+        assert_eq!(2, edit_distance("VERTX", "VERTEX_"));
+        assert_eq!(6, edit_distance("", "VERTEX"));
+    }
+
+    #[test]
+    fn environment_defaults() {
+        let vulkan = environment_include(true);
+        assert!(vulkan.contains("layout(constant_id = 1001) const int GEARS_MAX_LIGHTS = 256;"));
+        assert!(vulkan.contains("layout(constant_id = 1005) const bool GEARS_RAY_TRACING = false;"));
+
+        let glsl = environment_include(false);
+        assert!(glsl.contains("\nconst int GEARS_MAX_LIGHTS = 256;"));
+        assert!(!glsl.contains("constant_id"));
+    }
+
+    #[test]
+    fn reserved_constant_ids() {
+        let own =
+            "layout(constant_id = 0) const int A = 1;\nlayout(constant_id = 1006) const int B = 1;";
+        assert!(check_constant_ids(own, &[]).is_ok());
+        // commented out declarations do not count
+        assert!(check_constant_ids("// layout(constant_id = 1000)", &[]).is_ok());
+
+        let reserved = "layout(constant_id=1003) const bool C = false;".to_string();
+        assert!(check_constant_ids(&reserved, &[]).is_err());
+        assert!(check_constant_ids(own, &[reserved]).is_err());
+    }
+}
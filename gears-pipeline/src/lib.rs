@@ -1,12 +1,15 @@
 use proc_macro::TokenStream;
 
+use module::InputModule;
 use pipeline::{Pipeline, PipelineInput};
+use proc_macro2::Span;
 use quote::ToTokens;
-use syn::parse_macro_input;
+use syn::{parse_macro_input, LitStr};
 
 mod compiler;
 mod module;
 mod pipeline;
+mod scanner;
 mod ubo;
 
 /// # gears-pipeline main macro
@@ -17,6 +20,29 @@ mod ubo;
 /// It defines the shader module type.
 /// - ```vertex: { /* module options */ }``` (with aliases ```vs``` and ```v```)
 /// - ```fragment: { /* module options */ }``` (with aliases ```fs``` and ```f```)
+/// - ```geometry: { /* module options */ }``` (with aliases ```gs```, ```geom``` and ```g```)
+/// - ```task: { /* module options */ }``` (with alias ```t```)
+/// - ```mesh: { /* module options */ }``` (with alias ```m```)
+/// - ```compute: { /* module options */ }``` (with aliases ```comp``` and ```c```)
+/// - ```raygen: { /* module options */ }``` (with alias ```rgen```)
+/// - ```miss: { /* module options */ }``` (with alias ```rmiss```)
+/// - ```closesthit: { /* module options */ }``` (with alias ```rchit```)
+///
+/// ```mesh``` replaces ```vertex``` (and ```geometry```), ```task``` requires ```mesh```.
+/// ```compute``` cannot be combined with other modules or ```builders```,
+/// use ```PipelineBuilder::with_compute_module(COMP_SPIRV_WORDS)```.
+/// Ray tracing modules cannot be combined with other modules or ```builders```
+/// either and require ```raygen```, use ```RayTracingPipelineBuilder``` with
+/// ```RGEN_SPIRV_WORDS```, ```RMISS_SPIRV_WORDS``` and ```RCHIT_SPIRV_WORDS```.
+///
+/// Every module also gets ```*_SPIRV_WORDS```, the same SPIR-V as a ```&[u32]```,
+/// which is what ```PipelineBuilder``` takes.
+/// The ```u8``` arrays have no alignment guarantee, so APIs taking words (like
+/// ```vk::ShaderModuleCreateInfo::code```) should use these instead of casting.
+///
+/// With the default ```validate``` feature, the SPIR-V of every module is
+/// checked with spirv-val from SPIRV-Tools and anything it rejects is a
+/// compile error, instead of a pipeline creation failure at runtime.
 /// ### module options
 /// #### ```source: "..."```
 /// Has aliases: ```src``` and ```s```
@@ -29,7 +55,9 @@ mod ubo;
 /// Only one ```source``` or ```path``` can be given.
 /// #### ```include: "..."```
 /// Has aliases: ```inc``` and ```i```
-/// Path to be used with #include.
+/// Path to be used with #include, relative to the crate like ```path```.
+/// A path that is not a directory in the crate is used as given, relative
+/// to the working directory of the compiler as before.
 /// Overwrites ```include``` if already given.
 /// #### ```define: ["NAME1" = "VALUE", "NAME2"]```
 /// Has aliases: ```def``` and ```d```
@@ -40,9 +68,56 @@ mod ubo;
 /// #### ```entry: "..."```
 /// Has aliases: ```ep``` and ```e```
 /// Specifies the entry point name.
+/// #### ```custom: ["NAME" = "TEMPLATE"]```
+/// Adds handlers for ```#[gears_custom(NAME)]``` structs, see below.
+/// #### ```deny_defines```
+/// Turns the define warnings into errors.
+/// Defines given with ```define``` but never referenced are warned about,
+/// as are names checked with ```#ifdef```, ```#ifndef``` or ```defined()```
+/// that nothing defines but are within two edits of a name that is, to catch
+/// typos like ```"FRAGMNT"```. Optional switches that are simply left
+/// undefined are not reported.
 /// #### ```debug```
 /// Dumps glsl as a compile error
 ///
+/// ### pipeline options
+/// Given at the top level next to the modules, each at most once.
+/// #### ```builders``` and ```no_builders```
+/// Builders are not generated by default, ```no_builders``` states it explicitly.
+/// Generates ```build(&renderer)```, ```build_with_debug(&renderer)```
+/// and ```build_with(&renderer, gears::PipelineConfig { .. })```.
+/// The latter overrides the frame count.
+/// Pipelines with a vertex input also get
+/// ```build_with_remap(&renderer, config, &gears::VertexRemap::new().with_binding(1, 1))```,
+/// which reads the listed attribute locations from other vertex buffers
+/// instead of the one interleaved buffer.
+/// #### ```visibility: pub(crate)```
+/// Has alias: ```vis```
+/// Visibility of every generated item, ```pub``` by default.
+/// #### ```module: name```
+/// Wraps the generated items in ```mod name```, which gets the ```visibility```,
+/// instead of requiring a hand written module around the macro.
+/// #### ```ubo_name: "CameraUbo"```
+/// Rust name of the uniform struct, for pipelines with exactly one uniform.
+/// #### ```rename: ["GlslName" = "RustName"]```
+/// Rust names of any ```#[gears_bindgen]``` structs, the GLSL keeps the original names.
+/// #### ```struct_prefix: "..."``` and ```struct_suffix: "..."```
+/// Added to the Rust names of structs not named by ```ubo_name``` or ```rename```.
+/// #### ```const_prefix: "SKY_"```
+/// Prefixes the SPIR-V constants, ```SKY_VERT_SPIRV``` and so on, for several
+/// pipelines in one module.
+/// #### ```compress```
+/// Embeds the SPIR-V deflate compressed, for projects with many shaders.
+/// ```VERT_SPIRV``` and the others become a ```gears_traits::Lazy<Vec<u32>>```
+/// that is inflated on first use, ```*_SPIRV_REF``` and ```*_SPIRV_WORDS``` are
+/// not generated. Pass them to ```PipelineBuilder``` with ```&VERT_SPIRV[..]```.
+/// #### ```no_std```
+/// For renderers that can not depend on gears, the generated code then only needs
+/// the ```no_std``` ```gears-pipeline-runtime``` crate. Vectors and matrices are
+/// ```[f32; N]``` and column major ```[[f32; N]; N]``` instead of cgmath types and the
+/// ```UBO``` and ```Vertex``` impls are not generated. Cannot be combined with
+/// ```builders``` or ```compress```.
+///
 /// ## gears-pipeline defines
 ///
 /// ### for vertex shaders:
@@ -51,7 +126,15 @@ mod ubo;
 ///  - ```#define GEARS_INOUT(_location, _data) layout(location = _location) out _data;```
 ///  - ```#define GEARS_OUT(_location, _data) _data;```
 ///
-/// ### for vertex shaders:
+/// ### for geometry shaders:
+///  - ```#define GEARS_GEOMETRY```
+///  - ```#define GEARS_IN(_location, _data) layout(location = _location) in _data[];```
+///  - ```#define GEARS_OUT(_location, _data) layout(location = _location) out _data;```
+///
+/// The inputs are arrays with one element per vertex of the input primitive,
+/// the vertex shader writes them with ```GEARS_INOUT```.
+///
+/// ### for fragment shaders:
 ///  - ```#define GEARS_FRAGMENT```
 ///  - ```#define GEARS_IN(_location, _data) _data;```
 ///  - ```#define GEARS_INOUT(_location, _data) layout(location = _location) in _data;```
@@ -66,13 +149,76 @@ mod ubo;
 /// This expands a struct or uniform in the glsl source and generates rust bindings for it.
 /// Arguments for it can be given after 'gears_bindgen' in parentheses.
 /// Possible arguments:
-///  - shader input: ```in```
-///  - shader output: ```out```
-///  - uniforms: ```unifom(binding = 0)``` (the binding can be any integer)
+///  - shader input: ```in``` or ```in(location = 0)```
+///  - shader output: ```out``` or ```out(location = 0)```
+///  - uniforms: ```uniform``` or ```uniform(binding = 0)``` (the binding can be any integer)
+///
+/// Fields of ```in``` and ```out``` structs get consecutive locations starting from the
+/// struct's location, so a fragment ```out``` struct with three ```vec4``` fields writes
+/// to the first three color attachments of a ```RenderTarget```.
+///
+/// Vertex ```in``` structs can store fields in smaller formats that the shader still
+/// reads as floats: ```f16vec2``` and ```f16vec4``` are ```vec2``` and ```vec4``` in the
+/// shader and ```[gears_traits::Half; N]``` in Rust, ```packed_normal``` is a ```vec4```
+/// from an A2B10G10R10 signed normalized ```gears_traits::PackedNormal```.
+/// Convert with ```Half::vec2```, ```Half::vec4``` and ```PackedNormal::from```.
+///
+/// A uniform is visible to the module it is declared in. ```uniform(stages = [vertex, fragment])```
+/// makes it one descriptor binding visible to every listed stage instead, the other
+/// modules use it through the bindings header below rather than declaring it again.
+/// The stages are module names like ```vs``` or ```frag``` and must be in the pipeline.
+/// ```binding``` and ```stages``` can be combined: ```uniform(binding = 1, stages = [vs, fs])```.
+///
+/// ```uniform(count = 4)``` declares an array of four blocks and ```uniform(count = unbounded)```
+/// a runtime sized one, which needs ```#extension GL_EXT_nonuniform_qualifier : require```.
+/// Their ```UBO``` impl sets ```COUNT``` and the ```PARTIALLY_BOUND``` binding flag, plus
+/// ```VARIABLE_DESCRIPTOR_COUNT``` if unbounded, which ```PipelineBuilder::with_ubo_blocks```
+/// and ```UBO::layout_binding``` pass on to the descriptor set layout.
+///
+/// Every ```uniform``` struct ```Name``` also gets ```type NameArray = gears_traits::AlignedArray<Name>```
+/// for per object UBOs, see ```PipelineBuilder::with_ubo_array``` and ```Pipeline::write_ubo_slice```.
 ///
 /// ```#[gears_gen]```
 /// This is the same as ```#[gears_bindgen]``` but will not generate the rust bindings.
 ///
+/// ```#[gears_custom(name)]``` or ```#[gears_custom(name, args)]```
+/// Replaces the struct with the ```custom``` template registered as ```name```
+/// in the module options. In the template ```$name``` is the struct name,
+/// ```$field``` the instance name, ```$fields``` the field declarations and
+/// ```$args``` everything after the first comma. Templates are expanded before
+/// the other attributes, so they can produce ```#[gears_bindgen]``` structs.
+/// For ex. ```"material" = "#[gears_bindgen(uniform)] struct $name { $fields } $field;"```.
+///
+/// ### generated bindings header
+/// ```#include "gears://bindings.glsl"``` includes the uniform blocks expanded from
+/// ```#[gears_bindgen]``` and ```#[gears_gen]``` in any module of the same pipeline,
+/// so a fragment shader can use a uniform declared in the vertex shader without
+/// repeating it. Blocks are guarded with ```GEARS_BINDGEN_<StructName>```, declaring
+/// a block inline and including the header is fine in either order.
+/// ```in``` and ```out``` structs are stage specific and not part of the header.
+///
+/// ### environment header
+/// ```#include "gears://environment.glsl"``` declares specialization constants
+/// describing the target, which every ```gears::PipelineBuilder``` fills in at
+/// pipeline build time, see ```gears::ShaderEnvironment```:
+///  - ```int GEARS_MSAA_SAMPLES```, the sample count of the render target
+///  - ```int GEARS_MAX_LIGHTS```, from ```RendererBuilder::with_max_lights```
+///  - ```int GEARS_FRAMES_IN_FLIGHT```
+///  - ```bool GEARS_DESCRIPTOR_INDEXING```, ```bool GEARS_MESH_SHADER``` and
+///    ```bool GEARS_RAY_TRACING```, the supported device features
+///
+/// They can size arrays and branches on them are removed by the driver, but
+/// they are not macros and do not work with ```#if```. The header also defines
+/// ```GEARS_BACKEND_VULKAN```, or ```GEARS_BACKEND_GLSL``` in ```glsl!``` output
+/// where the constants are plain and keep their defaults.
+///
+/// The constants take the ```constant_id```s 1000 to 1005, shaders declaring
+/// own constants with these ids are rejected, with or without the header.
+///
+/// ### built-in includes
+/// ```#include "gears://clusters.glsl"``` reads the lights of a
+/// ```gears::LightClusters``` pass, see its docs.
+///
 /// ### example
 /// ```
 /// mod pl {
@@ -91,9 +237,121 @@ mod ubo;
 /// // check SPIRV generation
 /// assert_eq!(1248, pl::VERT_SPIRV.len(), "Vert spirv not what expected");
 /// assert_eq!(252, pl::FRAG_SPIRV.len(), "Frag spirv not what expected");
+/// assert_eq!(pl::VERT_SPIRV.len(), pl::VERT_SPIRV_WORDS.len() * 4);
+/// assert_eq!(0x0723_0203, pl::VERT_SPIRV_WORDS[0], "SPIR-V magic number");
 ///
 /// // check UBO struct generation
 /// pl::UBO { time: 0f32 };
+/// let mut ubos = pl::UBOArray::new(256);
+/// ubos.push(pl::UBO { time: 1f32 });
+/// assert_eq!(256, ubos.offset(1));
+///
+/// mod named {
+///     gears_pipeline::pipeline! {
+///         ubo_name: "TimeUbo"
+///         struct_suffix: "Data"
+///         const_prefix: "TEST_"
+///         vs: {
+///             path: "tests/test.glsl"
+///             def: [ "FRAGMENT", "VALUE" = "2" ]
+///         }
+///     }
+/// }
+///
+/// // check renamed items
+/// named::TimeUbo { time: 0f32 };
+/// assert_eq!(pl::VERT_SPIRV.len(), named::TEST_VERT_SPIRV.len());
+///
+/// mod bare {
+///     gears_pipeline::pipeline! {
+///         no_std
+///         vs: {
+///             path: "tests/test.glsl"
+///             def: [ "FRAGMENT", "VALUE" = "2" ]
+///         }
+///     }
+/// }
+///
+/// // check plain array fields
+/// assert_eq!(0f32, bare::UBO::default().time);
+/// bare::VertexData { pos: [0f32; 2], col: [0f32; 3] };
+///
+/// mod compressed {
+///     gears_pipeline::pipeline! {
+///         compress
+///         vs: {
+///             path: "tests/test.glsl"
+///             def: [ "FRAGMENT", "VALUE" = "2" ]
+///         }
+///     }
+/// }
+///
+/// // check that it inflates to SPIR-V
+/// assert_eq!(0x0723_0203, compressed::VERT_SPIRV[0], "SPIR-V magic number");
+///
+/// mod sprites {
+///     gears_pipeline::pipeline! {
+///         vs: {
+///             source: "#version 440
+///                 GEARS_INOUT(0, vec2 size)
+///                 void main() {
+///                     size = vec2(0.1);
+///                     gl_Position = vec4(0.0, 0.0, 0.0, 1.0);
+///                 }"
+///         }
+///         gs: {
+///             source: "#version 440
+///                 layout(points) in;
+///                 layout(triangle_strip, max_vertices = 4) out;
+///                 GEARS_IN(0, vec2 size)
+///                 GEARS_OUT(0, vec2 uv)
+///                 void main() {
+///                     for (int i = 0; i < 4; i++) {
+///                         uv = vec2(i & 1, i >> 1);
+///                         vec2 offset = (uv * 2.0 - 1.0) * size[0];
+///                         gl_Position = gl_in[0].gl_Position + vec4(offset, 0.0, 0.0);
+///                         EmitVertex();
+///                     }
+///                     EndPrimitive();
+///                 }"
+///         }
+///         fs: {
+///             source: "#version 440
+///                 GEARS_INOUT(0, vec2 uv)
+///                 GEARS_OUT(0, vec4 color)
+///                 void main() {
+///                     color = vec4(uv, 0.0, 1.0);
+///                 }"
+///         }
+///     }
+/// }
+///
+/// // check the geometry module, points expanded into quads
+/// assert_eq!(0x0723_0203, sprites::GEOM_SPIRV_WORDS[0], "SPIR-V magic number");
+/// assert_eq!(sprites::GEOM_SPIRV.len(), sprites::GEOM_SPIRV_WORDS.len() * 4);
+///
+/// mod arrays {
+///     gears_pipeline::pipeline! {
+///         vs: {
+///             source: "#version 450
+///                 #extension GL_EXT_nonuniform_qualifier : require
+///                 #[gears_bindgen(uniform(count = 4))] struct Light { vec4 color; } lights;
+///                 #[gears_bindgen(uniform(count = unbounded))] struct Material { vec4 color; } materials;
+///                 void main() {
+///                     gl_Position = lights[1].color + materials[nonuniformEXT(gl_InstanceIndex)].color;
+///                 }"
+///         }
+///     }
+/// }
+///
+/// // check the descriptor counts and binding flags of block arrays
+/// use gears_traits::{vk, UBO};
+/// let lights = arrays::Light::layout_binding(0, 64);
+/// assert_eq!(4, lights.descriptor_count);
+/// assert_eq!(vk::DescriptorBindingFlags::PARTIALLY_BOUND, arrays::Light::BINDING_FLAGS);
+/// let materials = arrays::Material::layout_binding(1, 64);
+/// assert_eq!(64, materials.descriptor_count);
+/// assert!(arrays::Material::BINDING_FLAGS.contains(vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT));
 /// ```
 #[proc_macro]
 pub fn pipeline(input: TokenStream) -> TokenStream {
@@ -102,3 +360,46 @@ pub fn pipeline(input: TokenStream) -> TokenStream {
         Ok(pipeline) => pipeline.to_token_stream().into(),
     }
 }
+
+/// # gears-pipeline GLSL macro
+///
+/// Runs the ```#[gears_bindgen]``` and ```#[gears_gen]``` expansion and the GLSL
+/// preprocessor without compiling to SPIR-V, and expands to the resulting source
+/// as a ```&'static str```. For OpenGL or WebGL backends that need GLSL text.
+///
+/// Takes the same module options as a ```pipeline!``` module, ```entry```,
+/// ```no-autodefine``` and ```debug``` have no effect. The gears-pipeline defines
+/// are stage specific and never added, define them manually if needed.
+/// Rust bindings are not generated.
+///
+/// ### example
+/// ```
+/// const SOURCE: &str = gears_pipeline::glsl! {
+///     path: "tests/test.glsl"
+///     def: [ "FRAGMENT", "VALUE" = "2" ]
+/// };
+///
+/// assert!(SOURCE.contains("uniform UBO"));
+/// assert!(!SOURCE.contains("gears_bindgen"));
+/// ```
+#[proc_macro]
+pub fn glsl(input: TokenStream) -> TokenStream {
+    match parse_macro_input!(input as InputModule).expand() {
+        Err(err) => err.to_compile_error().into(),
+        Ok(module) => module.to_token_stream().into(),
+    }
+}
+
+/// ```pipeline!``` expanding to its generated code as a ```&'static str```
+/// instead, for the snapshot tests. Not part of the public API.
+#[doc(hidden)]
+#[proc_macro]
+pub fn __internal_expand_for_tests(input: TokenStream) -> TokenStream {
+    let expanded = match Pipeline::new(parse_macro_input!(input as PipelineInput)) {
+        Err(err) => return err.to_compile_error().into(),
+        Ok(pipeline) => pipeline.to_token_stream().to_string(),
+    };
+    LitStr::new(&expanded, Span::call_site())
+        .to_token_stream()
+        .into()
+}
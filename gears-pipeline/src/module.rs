@@ -1,23 +1,32 @@
 use crate::{
     compiler::{self, DefinesInput},
+    scanner::{self, Attribute},
     ubo::{BindgenFieldType, BindgenStruct, StructRegistry},
 };
 
+use miniz_oxide::deflate::compress_to_vec;
 use proc_macro::TokenStream;
 use proc_macro2::{Group, Ident, Span};
-use quote::{format_ident, quote, ToTokens};
+use quote::{format_ident, quote, quote_spanned, ToTokens};
 use regex::{Captures, Regex};
 use shaderc::CompilationArtifact;
-use std::{collections::HashMap, env, fs::File, io::Read, path::Path};
-use syn::{parse::ParseStream, Error, LitStr, Token};
+use std::{collections::BTreeMap, env, fs::File, io::Read, path::Path};
+use syn::{parse::ParseStream, Error, LitStr, Token, Visibility};
 
 // struct/enum
 
-#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+// declaration order is the stage order, modules are processed and emitted in it
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 pub enum ModuleType {
     Vertex,
-    Fragment,
     Geometry,
+    Task,
+    Mesh,
+    Fragment,
+    Compute,
+    RayGen,
+    Miss,
+    ClosestHit,
 }
 
 pub struct InputModule {
@@ -27,27 +36,86 @@ pub struct InputModule {
     defines: DefinesInput,
     default_defines: bool,
     entry: Option<String>,
+    hooks: CustomHooks,
+    deny_defines: bool,
     debug: bool,
     span: Span,
 }
 
-pub type InputModules = HashMap<ModuleType, InputModule>;
-pub type CompiledModules = HashMap<ModuleType, CompiledModule>;
+/// ```custom: ["name" = "template"]``` templates for ```#[gears_custom(name)]``` structs.
+pub struct CustomHooks {
+    hooks: Vec<(String, String)>,
+}
+
+pub struct PreprocessedModule {
+    source: String,
+    input: InputModule,
+}
+
+/// Uniform declarations of every module, resolved by ```#include "gears://bindings.glsl"```.
+pub struct BindingsHeader {
+    names: Vec<String>,
+    source: String,
+}
+
+// ordered for reproducible output
+pub type InputModules = BTreeMap<ModuleType, InputModule>;
+pub type CompiledModules = BTreeMap<ModuleType, CompiledModule>;
+
+/// How the SPIR-V of every module is emitted, from the pipeline options.
+#[derive(Clone)]
+pub struct ModuleOutput {
+    pub compress: bool,
+    pub visibility: Visibility,
+    pub const_prefix: String,
+}
 
 pub struct CompiledModule {
     spirv: CompilationArtifact,
     module_type: ModuleType,
+    output: ModuleOutput,
+    source_file: Option<String>,
+    warnings: Vec<String>,
+    span: Span,
+}
+
+pub struct ExpandedModule {
+    source: String,
     source_file: Option<String>,
+    warnings: Vec<String>,
+    span: Span,
 }
 
 // impl
 
 impl ModuleType {
+    /// A module name of `pipeline!` or a `stages` entry of a uniform, like `vs` or `frag`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "v" | "vs" | "vertex" | "vert" => ModuleType::Vertex,
+            "f" | "fs" | "fragment" | "frag" => ModuleType::Fragment,
+            "g" | "gs" | "geometry" | "geom" => ModuleType::Geometry,
+            "t" | "task" => ModuleType::Task,
+            "m" | "mesh" => ModuleType::Mesh,
+            "c" | "comp" | "compute" => ModuleType::Compute,
+            "rgen" | "raygen" => ModuleType::RayGen,
+            "rmiss" | "miss" => ModuleType::Miss,
+            "rchit" | "closesthit" => ModuleType::ClosestHit,
+            _ => return None,
+        })
+    }
+
     pub fn name(&self) -> &'static str {
         match self {
             ModuleType::Fragment => "FRAG",
             ModuleType::Vertex => "VERT",
             ModuleType::Geometry => "GEOM",
+            ModuleType::Task => "TASK",
+            ModuleType::Mesh => "MESH",
+            ModuleType::Compute => "COMP",
+            ModuleType::RayGen => "RGEN",
+            ModuleType::Miss => "RMISS",
+            ModuleType::ClosestHit => "RCHIT",
         }
     }
 
@@ -56,50 +124,199 @@ impl ModuleType {
             ModuleType::Fragment => shaderc::ShaderKind::Fragment,
             ModuleType::Vertex => shaderc::ShaderKind::Vertex,
             ModuleType::Geometry => shaderc::ShaderKind::Geometry,
+            ModuleType::Task => shaderc::ShaderKind::Task,
+            ModuleType::Mesh => shaderc::ShaderKind::Mesh,
+            ModuleType::Compute => shaderc::ShaderKind::Compute,
+            ModuleType::RayGen => shaderc::ShaderKind::RayGeneration,
+            ModuleType::Miss => shaderc::ShaderKind::Miss,
+            ModuleType::ClosestHit => shaderc::ShaderKind::ClosestHit,
+        }
+    }
+
+    pub fn ray_tracing(&self) -> bool {
+        match self {
+            ModuleType::RayGen | ModuleType::Miss | ModuleType::ClosestHit => true,
+            _ => false,
         }
     }
 }
 
 impl InputModule {
-    pub fn compile(
+    pub fn preprocess(
         self,
         module_type: ModuleType,
         struct_reg: &mut StructRegistry,
         bindgen_structs: &mut Vec<BindgenStruct>,
-    ) -> Result<CompiledModule, Error> {
-        let span = self.span;
-
-        let (source, mut new_bindgen_structs) =
-            preprocess_glsl(self.source.as_str(), module_type.clone(), struct_reg);
+        header: &mut BindingsHeader,
+    ) -> Result<PreprocessedModule, Error> {
+        let (source, mut new_bindgen_structs) = preprocess_glsl(
+            self.source.as_str(),
+            module_type.clone(),
+            &self.hooks,
+            struct_reg,
+            header,
+        )
+        .or_else(|err| Err(Error::new(self.span, err)))?;
 
         bindgen_structs.append(&mut new_bindgen_structs);
 
-        let spirv = compiler::compile_shader_module(
-            module_type.kind(),
+        Ok(PreprocessedModule {
+            source,
+            input: self,
+        })
+    }
+
+    /// Attribute expansion and the GLSL preprocessor only, for ```glsl!```.
+    pub fn expand(self) -> Result<ExpandedModule, Error> {
+        let mut header = BindingsHeader::new();
+
+        // rust bindings are not generated, so the module type does not matter
+        let (source, _) = preprocess_glsl(
+            self.source.as_str(),
+            ModuleType::Vertex,
+            &self.hooks,
+            &mut StructRegistry::new(),
+            &mut header,
+        )
+        .or_else(|err| Err(Error::new(self.span, err)))?;
+
+        let (source, warnings) = compiler::preprocess_shader(
             source.as_ref(),
-            module_type.name(),
-            self.entry.as_ref().map_or("main", |e| e.as_str()),
+            "GLSL",
             self.include_path
                 .as_ref()
                 .map_or(None, |s| Some(Path::new(s))),
+            header.source.as_str(),
             &self.defines,
-            self.default_defines,
-            self.debug,
         )
-        .or_else(|err| Err(Error::new(span, err)))?;
+        .or_else(|err| Err(Error::new(self.span, err)))?;
+        deny_defines(self.deny_defines, &warnings, self.span)?;
+
+        Ok(ExpandedModule {
+            source,
+            source_file: self.source_file,
+            warnings,
+            span: self.span,
+        })
+    }
+}
+
+impl PreprocessedModule {
+    pub fn span(&self) -> Span {
+        self.input.span
+    }
 
-        let source_file = self.source_file;
+    pub fn compile(
+        self,
+        module_type: ModuleType,
+        header: &BindingsHeader,
+        output: ModuleOutput,
+    ) -> Result<CompiledModule, Error> {
+        let input = self.input;
+
+        let (spirv, warnings) = compiler::compile_shader_module(
+            module_type.kind(),
+            self.source.as_ref(),
+            module_type.name(),
+            input.entry.as_ref().map_or("main", |e| e.as_str()),
+            input
+                .include_path
+                .as_ref()
+                .map_or(None, |s| Some(Path::new(s))),
+            header.source.as_str(),
+            &input.defines,
+            input.default_defines,
+            input.debug,
+        )
+        .or_else(|err| Err(Error::new(input.span, err)))?;
+        deny_defines(input.deny_defines, &warnings, input.span)?;
+
+        let source_file = input.source_file;
 
         Ok(CompiledModule {
             spirv,
             module_type,
+            output,
             source_file,
+            warnings,
+            span: input.span,
         })
     }
 }
 
+impl BindingsHeader {
+    pub fn new() -> Self {
+        Self {
+            names: Vec::new(),
+            source: String::new(),
+        }
+    }
+
+    // the first declaration of each struct name wins
+    fn push(&mut self, name: &str, glsl: &str) {
+        if self.names.iter().any(|n| n == name) {
+            return;
+        }
+
+        self.names.push(name.into());
+        self.source += guarded(name, glsl).as_str();
+    }
+}
+
+impl CustomHooks {
+    pub fn new() -> Self {
+        Self { hooks: Vec::new() }
+    }
+
+    // `$name`, `$field`, `$fields` and `$args` are replaced in the template
+    fn expand(&self, attribute: &Attribute) -> Result<String, String> {
+        let error = |msg: String| format!("line {}: {}", attribute.line, msg);
+
+        let mut args = attribute.args.unwrap_or("").splitn(2, ',');
+        let hook = args.next().unwrap_or("").trim();
+        let args = args.next().unwrap_or("").trim();
+
+        let template = self
+            .hooks
+            .iter()
+            .find(|(name, _)| name == hook)
+            .map(|(_, template)| template)
+            .ok_or_else(|| error(format!("Unknown custom attribute 'gears_custom({})'", hook)))?;
+        let struct_name = attribute
+            .struct_name
+            .ok_or_else(|| error(format!("'gears_custom({})' requires a struct name", hook)))?;
+
+        Ok(template
+            .replace("$name", struct_name)
+            .replace("$fields", attribute.fields.trim())
+            .replace("$field", attribute.field)
+            .replace("$args", args))
+    }
+}
+
 // trait impl
 
+impl syn::parse::Parse for CustomHooks {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut hooks = Vec::new();
+
+        while !input.is_empty() {
+            let name: LitStr = input.parse()?;
+            input.parse::<Token![=]>()?;
+            let template: LitStr = input.parse()?;
+            hooks.push((name.value(), template.value()));
+
+            if input.is_empty() {
+                break;
+            }
+
+            input.parse::<Token![,]>()?;
+        }
+
+        Ok(Self { hooks })
+    }
+}
+
 impl syn::parse::Parse for InputModule {
     fn parse(input: ParseStream) -> syn::Result<Self> {
         let mut end_span = input.span();
@@ -109,6 +326,8 @@ impl syn::parse::Parse for InputModule {
         let mut defines = DefinesInput::new();
         let mut default_defines = true;
         let mut entry = None;
+        let mut hooks = CustomHooks::new();
+        let mut deny_defines = false;
         let mut debug = false;
 
         while !input.is_empty() {
@@ -130,11 +349,10 @@ impl syn::parse::Parse for InputModule {
                     end_span = path.span();
                     let (s, f) = read_shader_source(path.value(), path.span())?;
                     source = Some(s);
-                    source_file = Some(f);
 
                     if include_path.is_none() {
-                        let source_path_string = path.value();
-                        let source_path = Path::new(source_path_string.as_str());
+                        // next to the resolved file, rustc does not run in the crate directory
+                        let source_path = Path::new(f.as_str());
                         include_path = Some(
                             source_path
                                 .parent()
@@ -144,6 +362,7 @@ impl syn::parse::Parse for InputModule {
                                 .into(),
                         );
                     }
+                    source_file = Some(f);
                 }
                 "s" | "src" | "source" => {
                     input.parse::<Token![:]>()?;
@@ -162,9 +381,15 @@ impl syn::parse::Parse for InputModule {
                 "i" | "inc" | "include" => {
                     input.parse::<Token![:]>()?;
 
+                    // relative to the crate like 'path', or as given if that is not a directory
                     let path: LitStr = input.parse()?;
                     end_span = path.span();
-                    include_path = Some(path.value());
+                    let crate_path = Path::new(&manifest_dir(path.span())?).join(path.value());
+                    include_path = Some(if crate_path.is_dir() {
+                        crate_path.to_string_lossy().into()
+                    } else {
+                        path.value()
+                    });
                 }
                 "d" | "def" | "define" => {
                     input.parse::<Token![:]>()?;
@@ -185,6 +410,20 @@ impl syn::parse::Parse for InputModule {
                     end_span = ep.span();
                     entry = Some(ep.value());
                 }
+                "custom" => {
+                    input.parse::<Token![:]>()?;
+
+                    let group: Group = input.parse()?;
+                    end_span = group.span();
+
+                    let group_tokens: TokenStream = group.stream().into();
+                    hooks
+                        .hooks
+                        .append(&mut syn::parse::<CustomHooks>(group_tokens)?.hooks);
+                }
+                "deny_defines" => {
+                    deny_defines = true;
+                }
                 "debug" => {
                     debug = true;
                 }
@@ -211,6 +450,8 @@ impl syn::parse::Parse for InputModule {
             default_defines,
 
             entry,
+            hooks,
+            deny_defines,
             debug,
             span: end_span,
         })
@@ -219,10 +460,14 @@ impl syn::parse::Parse for InputModule {
 
 impl ToTokens for CompiledModule {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
-        let field_name = format_ident!("{}_SPIRV", self.module_type.name());
-        let field_ref_name = format_ident!("{}_SPIRV_REF", self.module_type.name());
+        let prefix = &self.output.const_prefix;
+        let name = self.module_type.name();
+        let field_name = format_ident!("{}{}_SPIRV", prefix, name);
+        let field_ref_name = format_ident!("{}{}_SPIRV_REF", prefix, name);
+        let field_words_name = format_ident!("{}{}_SPIRV_WORDS", prefix, name);
         let spirv = self.spirv.as_binary_u8();
         let len = spirv.len();
+        let words = self.spirv.as_binary();
 
         if let Some(source_file) = self.source_file.as_ref() {
             let field = quote! {
@@ -233,68 +478,156 @@ impl ToTokens for CompiledModule {
             field.to_tokens(tokens);
         }
 
-        let field = quote! {
-            // spirv:
-            pub const #field_name: [u8; #len] = [ #( #spirv ),* ];
-            pub const #field_ref_name: &[u8] = &#field_name;
+        warnings(&self.warnings, self.span).to_tokens(tokens);
+
+        let visibility = &self.output.visibility;
+        let field = if self.output.compress {
+            // little endian regardless of the host, inflate_spirv reads it back the same way
+            let le_bytes = words
+                .iter()
+                .flat_map(|word| word.to_le_bytes().to_vec())
+                .collect::<Vec<_>>();
+            let compressed = compress_to_vec(&le_bytes, 10);
+            quote! {
+                // spirv, deflate compressed:
+                #visibility static #field_name: gears_traits::Lazy<Vec<u32>> = gears_traits::Lazy::new(|| {
+                    gears_traits::inflate_spirv(&[ #( #compressed ),* ])
+                });
+            }
+        } else {
+            quote! {
+                // spirv:
+                #visibility const #field_name: [u8; #len] = [ #( #spirv ),* ];
+                #visibility const #field_ref_name: &[u8] = &#field_name;
+                // u8 arrays are not aligned for u32 reads, these can be passed on as is
+                #visibility const #field_words_name: &[u32] = &[ #( #words ),* ];
+            }
         };
 
         field.to_tokens(tokens);
     }
 }
 
+impl ToTokens for ExpandedModule {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        let source = &self.source;
+        let recompile = self.source_file.as_ref().map(|source_file| {
+            quote! {
+                // recompile on write hack:
+                const _: &str = include_str!(#source_file);
+            }
+        });
+
+        let warnings = warnings(&self.warnings, self.span);
+
+        let expr = quote! {
+            {
+                #recompile
+                #warnings
+                #source
+            }
+        };
+
+        expr.to_tokens(tokens);
+    }
+}
+
 // pub fn
 
 // fn
 
+fn deny_defines(deny: bool, warnings: &[String], span: Span) -> Result<(), Error> {
+    if deny && !warnings.is_empty() {
+        Err(Error::new(span, warnings.join("\n")))
+    } else {
+        Ok(())
+    }
+}
+
+// deprecation lints are the only way to emit warnings from a proc macro on stable
+fn warnings(warnings: &[String], span: Span) -> proc_macro2::TokenStream {
+    let warnings = warnings.iter();
+    quote_spanned! {span=>
+        #(
+            const _: () = {
+                #[deprecated(note = #warnings)]
+                struct GearsPipelineWarning;
+                let _ = GearsPipelineWarning;
+            };
+        )*
+    }
+}
+
+// a uniform block can be both declared inline and included from the bindings header
+fn guarded(struct_name: &str, glsl: &str) -> String {
+    format!(
+        "#ifndef GEARS_BINDGEN_{0}\n#define GEARS_BINDGEN_{0}\n{1}\n#endif\n",
+        struct_name, glsl
+    )
+}
+
 fn preprocess_glsl<'a>(
     source: &'a str,
     module: ModuleType,
+    hooks: &CustomHooks,
     struct_reg: &mut StructRegistry,
-) -> (String, Vec<BindgenStruct>) {
+    header: &mut BindingsHeader,
+) -> Result<(String, Vec<BindgenStruct>), String> {
     struct_reg.next_module();
 
-    let comment_matcher = Regex::new(r#"(//.*)|(/\*(.|(\r?\n))*?\*/)"#).unwrap();
-
-    let attrib_matcher =
-        Regex::new(r#"#\[gears_(bind)?(gen)\(.+\)\]((\r?\n)?.+)\{([^}]+)*(\r?\n)?\}.+;"#).unwrap();
-
     let mut bindgen_structs = Vec::new();
     let mut ident_renameres = Vec::new();
 
-    let mut output = comment_matcher.replace_all(source, " ").to_string();
-
-    output = attrib_matcher
-        .replace_all(&output[..], |caps: &Captures| {
-            let cap = &caps[0];
-            match syn::parse_str::<BindgenStruct>(cap) {
-                Ok(mut s) => {
-                    s.meta.in_module = module;
-                    s.generate(struct_reg);
-                    let glsl = format!("\n{}", s.to_glsl());
-
-                    // uniforms do not have to be renamed
-                    match &s.meta.bind_type {
-                        BindgenFieldType::Uniform(_) => (),
-                        BindgenFieldType::In(_) | BindgenFieldType::Out(_) => {
-                            ident_renameres.push(
-                                Regex::new(format!("\\b{}\\.\\b", s.field_name).as_str()).unwrap(),
-                            );
-                        }
-                    };
-
-                    // bind only gears_bindgen not gears_gen for ex.
-                    if s.meta.bind {
-                        bindgen_structs.push(s);
+    let mut output = scanner::strip_comments(source);
+
+    // custom hooks go first, their templates can use the built in attributes
+    output = scanner::replace(&output, |attribute| match attribute.name {
+        "gears_custom" => hooks.expand(attribute).map(Some),
+        _ => Ok(None),
+    })?;
+
+    output = scanner::replace(&output, |attribute| {
+        match attribute.name {
+            "gears_bindgen" | "gears_gen" => (),
+            name => {
+                return Err(format!(
+                    "line {}: Unknown attribute '{}'",
+                    attribute.line, name
+                ))
+            }
+        }
+
+        match syn::parse_str::<BindgenStruct>(attribute.text) {
+            Ok(mut s) => {
+                s.meta.in_module = module;
+                s.generate(struct_reg);
+                let mut glsl = format!("\n{}", s.to_glsl());
+
+                // uniforms do not have to be renamed
+                match &s.meta.bind_type {
+                    BindgenFieldType::Uniform(_) => {
+                        header.push(&s.struct_name, &s.to_glsl());
+                        glsl = format!("\n{}", guarded(&s.struct_name, &s.to_glsl()));
                     }
-                    glsl
-                }
-                Err(e) => {
-                    panic!("attrib failed: {:?}, {}", e.to_string(), cap);
+                    BindgenFieldType::In(_) | BindgenFieldType::Out(_) => {
+                        ident_renameres.push(
+                            Regex::new(format!("\\b{}\\.\\b", s.field_name).as_str()).unwrap(),
+                        );
+                    }
+                };
+
+                // bind only gears_bindgen not gears_gen for ex.
+                if s.meta.bind {
+                    bindgen_structs.push(s);
                 }
+                Ok(Some(glsl))
             }
-        })
-        .to_string();
+            Err(e) => Err(format!(
+                "line {}: Invalid '{}' struct: {}",
+                attribute.line, attribute.name, e
+            )),
+        }
+    })?;
 
     for ident_renamer in ident_renameres {
         output = ident_renamer
@@ -306,12 +639,19 @@ fn preprocess_glsl<'a>(
             .to_string();
     }
 
-    (output, bindgen_structs)
+    Ok((output, bindgen_structs))
 }
 
 // 0: source, 1: path
+fn manifest_dir(span: Span) -> syn::Result<String> {
+    env::var("CARGO_MANIFEST_DIR").or(Err(Error::new(
+        span,
+        "CARGO_MANIFEST_DIR is not set, build with cargo",
+    )))
+}
+
 fn read_shader_source(path: String, span: Span) -> syn::Result<(String, String)> {
-    let root = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let root = manifest_dir(span)?;
 
     let error_msg = format!("File not found: '{}' does not exist in '{}'", path, root);
     let full_path = Path::new(&root).join(path);
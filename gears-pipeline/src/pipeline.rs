@@ -1,9 +1,15 @@
-use proc_macro2::{Group, Ident, TokenStream};
+use proc_macro2::{Group, Ident, Span, TokenStream};
 use quote::{format_ident, quote, ToTokens};
-use syn::{parse::ParseStream, parse_macro_input::ParseMacroInput, Error, Token};
+use std::collections::HashMap;
+use syn::{
+    parse::ParseStream, parse_macro_input::ParseMacroInput, parse_quote, Error, LitStr, Token,
+    Visibility,
+};
 
 use crate::{
-    module::{CompiledModules, InputModule, InputModules, ModuleType},
+    module::{
+        BindingsHeader, CompiledModules, InputModule, InputModules, ModuleOutput, ModuleType,
+    },
     ubo::{BindgenFieldType, BindgenStruct, StructRegistry},
 };
 
@@ -12,65 +18,267 @@ use crate::{
 pub struct PipelineInput {
     // name: String,
     modules: InputModules,
-    builders: bool,
+    options: PipelineOptions,
 }
 
 pub struct Pipeline {
     // name: String,
     modules: CompiledModules,
     bindgen_structs: Vec<BindgenStruct>,
+    options: PipelineOptions,
+}
+
+// everything at the top level that is not a shader module
+struct PipelineOptions {
     builders: bool,
+    compress: bool,
+    no_std: bool,
+    visibility: Visibility,
+    module: Option<Ident>,
+
+    renames: Vec<(LitStr, LitStr)>,
+    ubo_name: Option<LitStr>,
+    struct_prefix: String,
+    struct_suffix: String,
+    const_prefix: String,
+
+    // where each option was given, for errors
+    spans: HashMap<&'static str, Span>,
 }
 
+// `["GlslName" = "RustName"]`
+struct Renames(Vec<(LitStr, LitStr)>);
+
 // impl
 
 impl Pipeline {
     pub fn new(input: PipelineInput) -> syn::Result<Self> {
         let mut struct_reg = StructRegistry::new();
         let mut bindgen_structs = Vec::new();
+        let mut header = BindingsHeader::new();
+
+        // all modules are preprocessed first for a complete bindings header
         let modules = input
             .modules
             .into_iter()
-            .map(|(module_type, input)| {
+            .map(|(module_type, module)| {
+                let preprocessed = module.preprocess(
+                    module_type.clone(),
+                    &mut struct_reg,
+                    &mut bindgen_structs,
+                    &mut header,
+                )?;
+                Ok((module_type, preprocessed))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // a uniform can only be visible to the stages of this pipeline
+        for bindgen_struct in bindgen_structs.iter() {
+            let missing = bindgen_struct
+                .meta
+                .stages
+                .iter()
+                .flatten()
+                .find(|stage| modules.iter().all(|(module_type, _)| module_type != *stage));
+            if let Some(missing) = missing {
+                let span = modules
+                    .iter()
+                    .find(|(module_type, _)| *module_type == bindgen_struct.meta.in_module)
+                    .map_or_else(Span::call_site, |(_, preprocessed)| preprocessed.span());
+                return Err(Error::new(
+                    span,
+                    format!(
+                        "Uniform '{}' is visible to the {:?} stage, which is not in this pipeline",
+                        bindgen_struct.struct_name, missing
+                    ),
+                ));
+            }
+        }
+
+        let options = input.options;
+        options.rename(&mut bindgen_structs)?;
+        let output = ModuleOutput {
+            compress: options.compress,
+            visibility: options.item_visibility(),
+            const_prefix: options.const_prefix.clone(),
+        };
+        let modules = modules
+            .into_iter()
+            .map(|(module_type, preprocessed)| {
                 Ok((
                     module_type.clone(),
-                    input.compile(module_type.clone(), &mut struct_reg, &mut bindgen_structs)?,
+                    preprocessed.compile(module_type.clone(), &header, output.clone())?,
                 ))
             })
             .collect::<Result<CompiledModules, Error>>()?;
-        let builders = input.builders;
+        for bindgen_struct in bindgen_structs.iter_mut() {
+            bindgen_struct.visibility = options.item_visibility();
+            bindgen_struct.no_std = options.no_std;
+        }
 
         Ok(Pipeline {
             modules,
             bindgen_structs,
-            builders,
+            options,
         })
     }
 }
 
+impl PipelineOptions {
+    fn new() -> Self {
+        Self {
+            builders: false,
+            compress: false,
+            no_std: false,
+            visibility: parse_quote!(pub),
+            module: None,
+
+            renames: Vec::new(),
+            ubo_name: None,
+            struct_prefix: String::new(),
+            struct_suffix: String::new(),
+            const_prefix: String::new(),
+
+            spans: HashMap::new(),
+        }
+    }
+
+    /// Returns false if `option` is not an option, but possibly a shader module.
+    fn parse_option(&mut self, option: &Ident, input: ParseStream) -> syn::Result<bool> {
+        let option_string = option.to_string();
+        let name = match option_string.as_str() {
+            "builders" | "no_builders" => "builders",
+            "compress" => "compress",
+            "no_std" => "no_std",
+            "visibility" | "vis" => "visibility",
+            "module" => "module",
+            "rename" => "rename",
+            "ubo_name" => "ubo_name",
+            "struct_prefix" => "struct_prefix",
+            "struct_suffix" => "struct_suffix",
+            "const_prefix" => "const_prefix",
+            _ => return Ok(false),
+        };
+
+        if self.spans.insert(name, option.span()).is_some() {
+            // builders is only set by the first of the two
+            let message = if name == "builders" && self.builders != (option_string == "builders") {
+                "'builders' and 'no_builders' are mutually exclusive".to_owned()
+            } else {
+                format!("'{}' option already specified", name)
+            };
+            return Err(Error::new(option.span(), message));
+        }
+
+        match option_string.as_str() {
+            "builders" => self.builders = true,
+            "no_builders" => self.builders = false,
+            "compress" => self.compress = true,
+            "no_std" => self.no_std = true,
+            _ => {
+                input.parse::<Token![:]>()?;
+                match name {
+                    "visibility" => self.visibility = input.parse()?,
+                    "module" => self.module = Some(input.parse()?),
+                    "rename" => {
+                        let group: Group = input.parse()?;
+                        self.renames = syn::parse2::<Renames>(group.stream())?.0;
+                    }
+                    "ubo_name" => self.ubo_name = Some(input.parse()?),
+                    _ => {
+                        let affix: LitStr = input.parse()?;
+                        match name {
+                            "struct_prefix" => self.struct_prefix = affix.value(),
+                            "struct_suffix" => self.struct_suffix = affix.value(),
+                            _ => self.const_prefix = affix.value(),
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn span(&self, name: &str) -> Span {
+        self.spans
+            .get(name)
+            .copied()
+            .unwrap_or_else(Span::call_site)
+    }
+
+    // sets the Rust names of the generated structs
+    fn rename(&self, bindgen_structs: &mut [BindgenStruct]) -> syn::Result<()> {
+        for bindgen_struct in bindgen_structs.iter_mut() {
+            bindgen_struct.rust_name = format!(
+                "{}{}{}",
+                self.struct_prefix, bindgen_struct.struct_name, self.struct_suffix
+            );
+        }
+
+        for (glsl_name, rust_name) in self.renames.iter() {
+            let bindgen_struct = bindgen_structs
+                .iter_mut()
+                .find(|s| s.struct_name == glsl_name.value())
+                .ok_or_else(|| {
+                    Error::new(
+                        glsl_name.span(),
+                        format!("No generated struct named '{}'", glsl_name.value()),
+                    )
+                })?;
+            bindgen_struct.rust_name = rust_ident(rust_name)?.to_string();
+        }
+
+        if let Some(ubo_name) = self.ubo_name.as_ref() {
+            let mut ubos = bindgen_structs
+                .iter_mut()
+                .filter(|s| match s.meta.bind_type {
+                    BindgenFieldType::Uniform(_) => true,
+                    _ => false,
+                });
+            match (ubos.next(), ubos.next()) {
+                (Some(ubo), None) => ubo.rust_name = rust_ident(ubo_name)?.to_string(),
+                _ => {
+                    return Err(Error::new(
+                        ubo_name.span(),
+                        "'ubo_name' requires exactly one uniform struct, use 'rename'",
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // items in a generated module are always pub, the module gets the visibility
+    fn item_visibility(&self) -> Visibility {
+        match self.module {
+            Some(_) => parse_quote!(pub),
+            None => self.visibility.clone(),
+        }
+    }
+}
+
 // trait impl
 
 impl ParseMacroInput for PipelineInput {
     fn parse(input: ParseStream) -> syn::Result<Self> {
         let mut modules = InputModules::new();
-        let mut builders = false;
+        let mut options = PipelineOptions::new();
 
         while !input.is_empty() {
             let shader: Ident = input.parse()?;
+            if options.parse_option(&shader, input)? {
+                continue;
+            }
             let shader_type_string = shader.to_string();
 
-            let module_type = match shader_type_string.as_str() {
-                "v" | "vertex" | "vert" => ModuleType::Vertex,
-                "f" | "fragment" | "frag" => ModuleType::Fragment,
-                "g" | "geometry" | "geom" => ModuleType::Geometry,
-                "builders" => {
-                    builders = true;
-                    continue;
-                }
-                _ => {
+            let module_type = match ModuleType::from_name(shader_type_string.as_str()) {
+                Some(module_type) => module_type,
+                None => {
                     return Err(Error::new(
                         shader.span(),
-                        format!("Unknown shader type: {}", shader_type_string),
+                        format!("Unknown shader type or option: {}", shader_type_string),
                     ));
                 }
             };
@@ -86,12 +294,95 @@ impl ParseMacroInput for PipelineInput {
             }
         }
 
-        Ok(PipelineInput { modules, builders })
+        if options.no_std {
+            // both need std, the builders the whole gears crate
+            if options.builders {
+                return Err(Error::new(
+                    options.span("builders"),
+                    "Builders are not generated for no_std pipelines",
+                ));
+            }
+            if options.compress {
+                return Err(Error::new(
+                    options.span("compress"),
+                    "Compressed SPIR-V is inflated with std, remove 'no_std' or 'compress'",
+                ));
+            }
+        }
+
+        if modules.keys().any(ModuleType::ray_tracing) {
+            if modules.keys().any(|module_type| !module_type.ray_tracing()) {
+                return Err(Error::new(
+                    input.span(),
+                    "Ray tracing shaders cannot be combined with other shaders",
+                ));
+            }
+            if !modules.contains_key(&ModuleType::RayGen) {
+                return Err(Error::new(
+                    input.span(),
+                    "Ray tracing shaders require a ray generation shader",
+                ));
+            }
+            if options.builders {
+                return Err(Error::new(
+                    options.span("builders"),
+                    "Builders are not generated for ray tracing shaders",
+                ));
+            }
+        } else if modules.contains_key(&ModuleType::Compute) {
+            if modules.len() != 1 {
+                return Err(Error::new(
+                    input.span(),
+                    "Compute shaders cannot be combined with other shaders",
+                ));
+            }
+            if options.builders {
+                return Err(Error::new(
+                    options.span("builders"),
+                    "Builders are not generated for compute shaders",
+                ));
+            }
+        } else if modules.contains_key(&ModuleType::Mesh) {
+            if modules.contains_key(&ModuleType::Vertex)
+                || modules.contains_key(&ModuleType::Geometry)
+            {
+                return Err(Error::new(
+                    input.span(),
+                    "Mesh shaders cannot be combined with vertex or geometry shaders",
+                ));
+            }
+        } else if modules.contains_key(&ModuleType::Task) {
+            return Err(Error::new(
+                input.span(),
+                "Task shaders require a mesh shader",
+            ));
+        }
+
+        Ok(PipelineInput { modules, options })
     }
 }
 
 impl ToTokens for Pipeline {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        match self.options.module.as_ref() {
+            Some(module) => {
+                let visibility = &self.options.visibility;
+                let mut items = TokenStream::new();
+                self.items_to_tokens(&mut items);
+                quote! {
+                    #visibility mod #module {
+                        #items
+                    }
+                }
+                .to_tokens(tokens);
+            }
+            None => self.items_to_tokens(tokens),
+        }
+    }
+}
+
+impl Pipeline {
+    fn items_to_tokens(&self, tokens: &mut TokenStream) {
         for (_, module) in self.modules.iter() {
             module.to_tokens(tokens);
         }
@@ -100,12 +391,13 @@ impl ToTokens for Pipeline {
             bindgen_struct.to_tokens(tokens);
         }
 
-        if self.builders {
+        if self.options.builders {
+            let visibility = self.options.item_visibility();
             let ubos: Vec<Ident> = self
                 .bindgen_structs
                 .iter()
                 .filter_map(|s| match s.meta.bind_type {
-                    BindgenFieldType::Uniform(_) => Some(format_ident!("{}", s.struct_name)),
+                    BindgenFieldType::Uniform(_) => Some(format_ident!("{}", s.rust_name)),
                     _ => None,
                 })
                 .collect();
@@ -114,46 +406,138 @@ impl ToTokens for Pipeline {
                 .bindgen_structs
                 .iter()
                 .filter_map(|s| match s.meta.bind_type {
-                    BindgenFieldType::In(_) => Some(format_ident!("{}", s.struct_name)),
+                    BindgenFieldType::In(_) => Some(format_ident!("{}", s.rust_name)),
                     _ => None,
                 })
                 .collect();
 
+            // compressed modules only have the lazily inflated words
+            let compress = self.options.compress;
+            let prefix = &self.options.const_prefix;
+            let spirv = |module_type: ModuleType| {
+                if compress {
+                    let words = format_ident!("{}{}_SPIRV", prefix, module_type.name());
+                    quote! { &#words[..] }
+                } else {
+                    let words = format_ident!("{}{}_SPIRV_WORDS", prefix, module_type.name());
+                    quote! { #words }
+                }
+            };
+
             let modules: Vec<TokenStream> = self
                 .modules
                 .iter()
                 .filter_map(|(t, _)| match t {
-                    ModuleType::Geometry => Some(
-                        quote! {
-                            .with_geometry_module(GEOM_SPIRV_REF)
-                        }
-                        .into(),
-                    ),
+                    ModuleType::Geometry => {
+                        let geom = spirv(ModuleType::Geometry);
+                        Some(quote! {
+                            .with_geometry_module(#geom)
+                        })
+                    }
+                    ModuleType::Task => {
+                        let task = spirv(ModuleType::Task);
+                        Some(quote! {
+                            .with_task_module(#task)
+                        })
+                    }
                     _ => None,
                 })
                 .collect();
 
+            let frag = spirv(ModuleType::Fragment);
+            let main_modules = if self.modules.contains_key(&ModuleType::Mesh) {
+                let mesh = spirv(ModuleType::Mesh);
+                quote! {
+                    .with_mesh_modules(#mesh, #frag)
+                }
+            } else {
+                let vert = spirv(ModuleType::Vertex);
+                quote! {
+                    .with_graphics_modules(#vert, #frag)
+                }
+            };
+
+            // only pipelines with a vertex input can remap it
+            let remap_builder = if inputs.is_empty() {
+                quote! {}
+            } else {
+                quote! {
+                    #visibility fn build_with_remap(
+                        renderer: &gears::Renderer,
+                        config: gears::PipelineConfig,
+                        remap: &gears::VertexRemap,
+                    ) -> gears::Pipeline {
+                        gears::PipelineBuilder::new_with_config(renderer, &config)
+                            #( .with_ubo::<#ubos>() )*
+                            #main_modules
+                            #( #modules )*
+                            #( .with_input_remap::<#inputs>(remap) )*
+                            .build(config.debug)
+                            .unwrap()
+                    }
+                }
+            };
+
             let builders = quote! {
-                fn _build(renderer: &gears::Renderer, debug: bool) -> gears::Pipeline {
-                    gears::PipelineBuilder::new(renderer)
+                #visibility fn build_with(
+                    renderer: &gears::Renderer,
+                    config: gears::PipelineConfig,
+                ) -> gears::Pipeline {
+                    gears::PipelineBuilder::new_with_config(renderer, &config)
                         #( .with_ubo::<#ubos>() )*
-                        .with_graphics_modules(VERT_SPIRV_REF, FRAG_SPIRV_REF)
+                        #main_modules
                         #( #modules )*
                         #( .with_input::<#inputs>() )*
-                        .build(debug)
+                        .build(config.debug)
                         .unwrap()
                 }
 
-                pub fn build(renderer: &gears::Renderer) -> gears::Pipeline {
-                    _build(renderer, false)
+                #visibility fn build(renderer: &gears::Renderer) -> gears::Pipeline {
+                    build_with(renderer, gears::PipelineConfig::default())
                 }
 
-                pub fn build_with_debug(renderer: &gears::Renderer) -> gears::Pipeline {
-                    _build(renderer, true)
+                #visibility fn build_with_debug(renderer: &gears::Renderer) -> gears::Pipeline {
+                    build_with(
+                        renderer,
+                        gears::PipelineConfig {
+                            debug: true,
+                            ..gears::PipelineConfig::default()
+                        },
+                    )
                 }
+
+                #remap_builder
             };
 
             builders.to_tokens(tokens);
         }
     }
 }
+
+impl syn::parse::Parse for Renames {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut renames = Vec::new();
+
+        while !input.is_empty() {
+            let glsl_name: LitStr = input.parse()?;
+            input.parse::<Token![=]>()?;
+            let rust_name: LitStr = input.parse()?;
+            renames.push((glsl_name, rust_name));
+
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+
+        Ok(Renames(renames))
+    }
+}
+
+fn rust_ident(name: &LitStr) -> syn::Result<Ident> {
+    syn::parse_str::<Ident>(&name.value()).map_err(|_| {
+        Error::new(
+            name.span(),
+            format!("'{}' is not a valid Rust name", name.value()),
+        )
+    })
+}
@@ -0,0 +1,424 @@
+use std::ops::Range;
+
+// struct/enum
+
+/// ```#[gears_*(args)] struct Name { fields } field;``` block in GLSL source.
+pub struct Attribute<'a> {
+    pub text: &'a str,
+    pub range: Range<usize>,
+    pub line: usize,
+
+    pub name: &'a str,
+    pub args: Option<&'a str>,
+    pub struct_name: Option<&'a str>,
+    pub fields: &'a str,
+    pub field: &'a str,
+}
+
+struct Cursor<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+// state of one #if level, `condition` is only known for `#if 0` and `#if 1`
+struct Branch {
+    condition: Option<bool>,
+    in_else: bool,
+}
+
+// impl
+
+impl<'a> Cursor<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.source.as_bytes().get(self.pos).cloned()
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\r') | Some(b'\n') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_string(&mut self) {
+        self.pos += 1;
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == b'"' || c == b'\n' {
+                break;
+            }
+        }
+    }
+
+    fn ident(&mut self) -> Option<&'a str> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_alphanumeric() || c == b'_') {
+                break;
+            }
+            self.pos += 1;
+        }
+
+        if start == self.pos {
+            None
+        } else {
+            Some(&self.source[start..self.pos])
+        }
+    }
+
+    // contents up to the matching `close`, the opening one is already eaten
+    fn matching(&mut self, open: u8, close: u8) -> Option<&'a str> {
+        let start = self.pos;
+        let mut depth = 1;
+        while let Some(c) = self.peek() {
+            match c {
+                b'"' => {
+                    self.skip_string();
+                    continue;
+                }
+                c if c == open => depth += 1,
+                c if c == close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.pos += 1;
+                        return Some(&self.source[start..self.pos - 1]);
+                    }
+                }
+                _ => (),
+            }
+            self.pos += 1;
+        }
+
+        None
+    }
+
+    fn rest_of_line(&mut self) -> &'a str {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c == b'\n' {
+                break;
+            }
+            self.pos += 1;
+        }
+
+        &self.source[start..self.pos]
+    }
+
+    fn line(&self, pos: usize) -> usize {
+        self.source[..pos].matches('\n').count() + 1
+    }
+}
+
+impl Branch {
+    fn disabled(&self) -> bool {
+        match self.condition {
+            Some(condition) => condition == self.in_else,
+            None => false,
+        }
+    }
+}
+
+// pub fn
+
+/// Replaces comments with whitespace, keeping newlines so lines stay the same.
+pub fn strip_comments(source: &str) -> String {
+    let mut output = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    let mut in_string = false;
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            in_string = c != '"' && c != '\n';
+            output.push(c);
+            continue;
+        }
+
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                output.push(c);
+            }
+            ('/', Some('/')) => {
+                while let Some(&c) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    chars.next();
+                }
+                output.push(' ');
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                output.push(' ');
+
+                let mut prev = ' ';
+                for c in &mut chars {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    if c == '\n' {
+                        output.push('\n');
+                    }
+                    prev = c;
+                }
+            }
+            _ => output.push(c),
+        }
+    }
+
+    output
+}
+
+/// Finds all ```#[gears_*]``` attribute blocks outside of ```#if 0``` blocks.
+///
+/// `source` must not contain comments, see `strip_comments`.
+pub fn scan(source: &str) -> Result<Vec<Attribute<'_>>, String> {
+    let mut cursor = Cursor { source, pos: 0 };
+    let mut branches: Vec<Branch> = Vec::new();
+    let mut attributes = Vec::new();
+    let mut line_start = true;
+
+    while let Some(c) = cursor.peek() {
+        match c {
+            b'\n' => {
+                line_start = true;
+                cursor.pos += 1;
+            }
+            b' ' | b'\t' | b'\r' => {
+                cursor.pos += 1;
+            }
+            b'"' => {
+                line_start = false;
+                cursor.skip_string();
+            }
+            b'#' if source[cursor.pos..].starts_with("#[gears_") => {
+                line_start = false;
+                let attribute = attribute(&mut cursor)?;
+                if !branches.iter().any(Branch::disabled) {
+                    attributes.push(attribute);
+                }
+            }
+            b'#' if line_start => {
+                cursor.pos += 1;
+                directive(cursor.rest_of_line(), &mut branches);
+            }
+            _ => {
+                line_start = false;
+                cursor.pos += 1;
+            }
+        }
+    }
+
+    Ok(attributes)
+}
+
+/// Replaces every attribute `f` returns `Some` for.
+///
+/// Replacements are padded with newlines to the line count of the
+/// original, so later errors point at the right line.
+pub fn replace<F>(source: &str, mut f: F) -> Result<String, String>
+where
+    F: FnMut(&Attribute) -> Result<Option<String>, String>,
+{
+    let mut output = String::with_capacity(source.len());
+    let mut last = 0;
+
+    for attribute in scan(source)? {
+        let replacement = match f(&attribute)? {
+            Some(replacement) => replacement,
+            None => continue,
+        };
+
+        output += &source[last..attribute.range.start];
+        output += replacement.as_str();
+        let newlines = attribute.text.matches('\n').count();
+        for _ in replacement.matches('\n').count()..newlines {
+            output.push('\n');
+        }
+        last = attribute.range.end;
+    }
+
+    output += &source[last..];
+    Ok(output)
+}
+
+// fn
+
+fn attribute<'a>(cursor: &mut Cursor<'a>) -> Result<Attribute<'a>, String> {
+    let start = cursor.pos;
+    let line = cursor.line(start);
+    let error = |msg: &str| format!("line {}: {}", line, msg);
+
+    cursor.pos += 2;
+    let name = cursor
+        .ident()
+        .ok_or_else(|| error("expected attribute name"))?;
+
+    cursor.skip_whitespace();
+    let args = if cursor.eat(b'(') {
+        Some(
+            cursor
+                .matching(b'(', b')')
+                .ok_or_else(|| error("unclosed '(' in attribute"))?,
+        )
+    } else {
+        None
+    };
+
+    cursor.skip_whitespace();
+    if !cursor.eat(b']') {
+        return Err(error("expected ']' after attribute"));
+    }
+
+    cursor.skip_whitespace();
+    if cursor.ident() != Some("struct") {
+        return Err(error("expected 'struct' after attribute"));
+    }
+
+    cursor.skip_whitespace();
+    let struct_name = cursor.ident();
+
+    cursor.skip_whitespace();
+    if !cursor.eat(b'{') {
+        return Err(error("expected '{' after struct name"));
+    }
+    let fields = cursor
+        .matching(b'{', b'}')
+        .ok_or_else(|| error("unclosed '{' in struct"))?;
+
+    cursor.skip_whitespace();
+    let field = cursor
+        .ident()
+        .ok_or_else(|| error("expected a field name after struct"))?;
+
+    cursor.skip_whitespace();
+    if !cursor.eat(b';') {
+        return Err(error("expected ';' after field name"));
+    }
+
+    Ok(Attribute {
+        text: &cursor.source[start..cursor.pos],
+        range: start..cursor.pos,
+        line,
+
+        name,
+        args,
+        struct_name,
+        fields,
+        field,
+    })
+}
+
+fn directive(line: &str, branches: &mut Vec<Branch>) {
+    let mut words = line.split_whitespace();
+    match words.next() {
+        Some("if") => {
+            let condition = match words.collect::<String>().as_str() {
+                "0" => Some(false),
+                "1" => Some(true),
+                _ => None,
+            };
+            branches.push(Branch {
+                condition,
+                in_else: false,
+            });
+        }
+        Some("ifdef") | Some("ifndef") => branches.push(Branch {
+            condition: None,
+            in_else: false,
+        }),
+        Some("elif") => {
+            if let Some(branch) = branches.last_mut() {
+                // taken if nothing before it was
+                if branch.condition == Some(true) {
+                    branch.in_else = true;
+                } else {
+                    branch.condition = None;
+                }
+            }
+        }
+        Some("else") => {
+            if let Some(branch) = branches.last_mut() {
+                branch.in_else = true;
+            }
+        }
+        Some("endif") => {
+            branches.pop();
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_braces() {
+        let source =
+            "#[gears_bindgen] struct A { struct B { float x; } b; vec2 y; } a;\nvoid main() {}";
+        let attributes = scan(source).unwrap();
+
+        assert_eq!(1, attributes.len());
+        assert_eq!(Some("A"), attributes[0].struct_name);
+        assert_eq!(" struct B { float x; } b; vec2 y; ", attributes[0].fields);
+        assert_eq!("a", attributes[0].field);
+    }
+
+    #[test]
+    fn brace_in_string() {
+        let source = "#include \"}.glsl\"\n#[gears_custom(name, \")}\")] struct A { float x; } a;";
+        let attributes = scan(source).unwrap();
+
+        assert_eq!(1, attributes.len());
+        assert_eq!(Some("name, \")}\""), attributes[0].args);
+        assert_eq!(" float x; ", attributes[0].fields);
+        assert_eq!(2, attributes[0].line);
+    }
+
+    #[test]
+    fn if_0_blocks() {
+        let source = "#if 0
+            #[gears_bindgen] struct A { float x; } a;
+            #ifdef X
+            #endif
+            #[gears_bindgen] struct B { float x; } b;
+            #else
+            #[gears_bindgen] struct C { float x; } c;
+            #endif
+            #if 1
+            #elif X
+            #[gears_bindgen] struct D { float x; } d;
+            #endif";
+        let attributes = scan(source).unwrap();
+
+        let names = attributes
+            .iter()
+            .map(|attribute| attribute.struct_name.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["C"], names);
+    }
+
+    #[test]
+    fn multi_line_comments() {
+        let source = "/* #[gears_bindgen] struct A { float x; } a;
+            }
+            */
+            #[gears_bindgen] struct B { float x; } b; // }";
+        let stripped = strip_comments(source);
+        let attributes = scan(&stripped).unwrap();
+
+        assert_eq!(source.lines().count(), stripped.lines().count());
+        assert_eq!(1, attributes.len());
+        assert_eq!(Some("B"), attributes[0].struct_name);
+        assert_eq!(4, attributes[0].line);
+    }
+}
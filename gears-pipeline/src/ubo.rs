@@ -1,11 +1,15 @@
-use std::{
-    collections::{hash_map::DefaultHasher, HashMap},
-    hash::{Hash, Hasher},
-};
+use std::collections::HashMap;
 
 use proc_macro2::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream};
-use quote::{ToTokens, TokenStreamExt};
-use syn::{ext::IdentExt, parse::ParseStream, Error, Token};
+use quote::{quote, ToTokens, TokenStreamExt};
+use syn::{
+    bracketed,
+    ext::IdentExt,
+    parse::{ParseStream, Parser},
+    parse_quote,
+    punctuated::Punctuated,
+    Error, Token, Visibility,
+};
 
 use crate::module::ModuleType;
 
@@ -36,6 +40,11 @@ pub enum StructFieldType {
     Mat2(),
     Mat3(),
     Mat4(),
+
+    // vertex input only, converted to floats by the vertex fetch
+    Half2(),
+    Half4(),
+    PackedNormal(),
 }
 
 pub struct StructField {
@@ -55,6 +64,11 @@ pub struct StructFields {
 pub struct BindgenStruct {
     pub field_name: String,
     pub struct_name: String,
+    // generated Rust struct, the GLSL keeps `struct_name`
+    pub rust_name: String,
+    pub visibility: Visibility,
+    // plain arrays and no trait impls, see the `no_std` option
+    pub no_std: bool,
 
     pub fields: StructFields,
 
@@ -65,6 +79,10 @@ pub struct BindgenFields {
     pub bind: bool,
     pub bind_type: BindgenFieldType,
     pub in_module: ModuleType,
+    // uniforms only, `in_module` if not given
+    pub stages: Option<Vec<ModuleType>>,
+    // uniforms only, an array of blocks if given
+    pub count: Option<DescriptorCount>,
 }
 
 #[derive(Debug)]
@@ -74,6 +92,31 @@ pub enum BindgenFieldType {
     Out(Option<Location>),
 }
 
+// `uniform(binding = 0, stages = [vertex, fragment], count = 4)`
+struct BindgenArgs {
+    bind_type: BindgenFieldType,
+    stages: Option<Vec<ModuleType>>,
+    count: Option<DescriptorCount>,
+}
+
+#[derive(Debug)]
+enum ExplicitArg {
+    Location(u32),
+    Binding(u32),
+    Stages(Vec<ModuleType>),
+    Count(DescriptorCount),
+}
+
+// `count = N` or `count = unbounded`, the latter with a variable descriptor count
+#[derive(Debug, Clone, Copy)]
+pub enum DescriptorCount {
+    Fixed(u32),
+    Unbounded,
+}
+
+// an explicit arg and the span of its key
+struct SpannedArg(Span, ExplicitArg);
+
 #[derive(Debug, Clone, Copy)]
 pub struct Binding(u32);
 
@@ -135,6 +178,10 @@ impl StructFieldType {
             Self::Mat2() => std::mem::size_of::<f32>() * 2 * 2,
             Self::Mat3() => std::mem::size_of::<f32>() * 3 * 3,
             Self::Mat4() => std::mem::size_of::<f32>() * 4 * 4,
+
+            Self::Half2() => std::mem::size_of::<u16>() * 2,
+            Self::Half4() => std::mem::size_of::<u16>() * 4,
+            Self::PackedNormal() => std::mem::size_of::<u32>(),
         }
     }
 
@@ -153,6 +200,10 @@ impl StructFieldType {
                 Self::Mat2() => "R32G32_SFLOAT",
                 Self::Mat3() => "R32G32B32_SFLOAT",
                 Self::Mat4() => "R32G32B32A32_SFLOAT",
+
+                Self::Half2() => "R16G16_SFLOAT",
+                Self::Half4() => "R16G16B16A16_SFLOAT",
+                Self::PackedNormal() => "A2B10G10R10_SNORM_PACK32",
             },
             Span::call_site(),
         )
@@ -192,6 +243,56 @@ impl StructFieldType {
             Self::Mat2() => "mat2",
             Self::Mat3() => "mat3",
             Self::Mat4() => "mat4",
+
+            Self::Half2() => "vec2",
+            Self::Half4() | Self::PackedNormal() => "vec4",
+        }
+    }
+
+    /// Array replacing the cgmath type of vectors and matrices, for ```no_std``` pipelines.
+    pub fn array_type(&self) -> Option<TokenStream> {
+        let (columns, rows) = self.dimensions()?;
+        Some(if columns == 1 {
+            quote! { [f32; #rows] }
+        } else {
+            quote! { [[f32; #rows]; #columns] }
+        })
+    }
+
+    /// Zero vector or identity matrix of ```array_type```.
+    pub fn array_default(&self) -> Option<TokenStream> {
+        let (columns, rows) = self.dimensions()?;
+        Some(if columns == 1 {
+            quote! { [0f32; #rows] }
+        } else {
+            let columns = (0..columns).map(|column| {
+                let values = (0..rows).map(|row| if row == column { 1f32 } else { 0f32 });
+                quote! { [#(#values),*] }
+            });
+            quote! { [#(#columns),*] }
+        })
+    }
+
+    // columns and rows of vectors and matrices
+    fn dimensions(&self) -> Option<(usize, usize)> {
+        match self {
+            Self::Float2() => Some((1, 2)),
+            Self::Float3() => Some((1, 3)),
+            Self::Float4() => Some((1, 4)),
+
+            Self::Mat2() => Some((2, 2)),
+            Self::Mat3() => Some((3, 3)),
+            Self::Mat4() => Some((4, 4)),
+
+            _ => None,
+        }
+    }
+
+    /// Only valid in ```in``` structs.
+    pub fn packed(&self) -> bool {
+        match self {
+            Self::Half2() | Self::Half4() | Self::PackedNormal() => true,
+            _ => false,
         }
     }
 }
@@ -234,9 +335,7 @@ impl syn::parse::Parse for StructFields {
 }
 impl syn::parse::Parse for BindgenStruct {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        let mut hasher = DefaultHasher::new();
-        input.to_string().hash(&mut hasher);
-        let hash = hasher.finish();
+        let hash = stable_hash(&input.to_string());
 
         input.parse::<Token![#]>()?;
         let group: Group = input.parse()?;
@@ -263,9 +362,30 @@ impl syn::parse::Parse for BindgenStruct {
 
             input.parse::<Token![;]>()?;
 
+            let is_in = match meta.bind_type {
+                BindgenFieldType::In(_) => true,
+                _ => false,
+            };
+            if let Some(field) = fields
+                .fields
+                .iter()
+                .find(|field| !is_in && field.field_type.packed())
+            {
+                return Err(Error::new(
+                    span,
+                    format!(
+                        "field '{}' of '{}': packed types are only valid in 'in' structs",
+                        field.field_name, struct_name
+                    ),
+                ));
+            }
+
             Ok(BindgenStruct {
+                rust_name: struct_name.clone(),
                 struct_name,
                 field_name,
+                visibility: parse_quote!(pub),
+                no_std: false,
                 fields,
                 meta,
             })
@@ -277,7 +397,7 @@ impl syn::parse::Parse for BindgenFields {
     fn parse(input: ParseStream) -> syn::Result<Self> {
         let ident = input.parse::<Ident>()?.to_string();
         let group: Group = input.parse()?;
-        let bind_type = syn::parse::<BindgenFieldType>(group.stream().into())?;
+        let args = syn::parse::<BindgenArgs>(group.stream().into())?;
 
         Ok(Self {
             bind: match ident.as_str() {
@@ -285,20 +405,150 @@ impl syn::parse::Parse for BindgenFields {
                 "gears_gen" => false,
                 _ => panic!("Unknown BindgenFields: {}", ident),
             },
-            bind_type,
+            bind_type: args.bind_type,
             in_module: ModuleType::Vertex,
+            stages: args.stages,
+            count: args.count,
         })
     }
 }
 
-impl syn::parse::Parse for BindgenFieldType {
+impl syn::parse::Parse for BindgenArgs {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        let ident = input.call(Ident::parse_any)?.to_string();
-        Ok(match ident.as_str() {
-            "in" => Self::In(None),
-            "out" => Self::Out(None),
-            "uniform" => Self::Uniform(None),
+        let ident = input.call(Ident::parse_any)?;
+        if ident != "in" && ident != "out" && ident != "uniform" {
+            return Err(Error::new(
+                ident.span(),
+                format!("expected 'in', 'out' or 'uniform', found '{}'", ident),
+            ));
+        }
+        let ident = ident.to_string();
+
+        // optional explicit (location = N), (binding = N), (stages = [..]) and (count = N)
+        let explicit = if input.is_empty() {
+            Punctuated::new()
+        } else {
+            let group: Group = input.parse()?;
+            Punctuated::<SpannedArg, Token![,]>::parse_terminated.parse2(group.stream())?
+        };
+
+        let mut index = None;
+        let mut stages = None;
+        let mut count = None;
+        for SpannedArg(span, explicit) in explicit {
+            match (ident.as_str(), explicit) {
+                ("in", ExplicitArg::Location(l)) | ("out", ExplicitArg::Location(l))
+                    if index.is_none() =>
+                {
+                    index = Some(ExplicitArg::Location(l))
+                }
+                ("uniform", ExplicitArg::Binding(b)) if index.is_none() => {
+                    index = Some(ExplicitArg::Binding(b))
+                }
+                ("uniform", ExplicitArg::Stages(s)) if stages.is_none() => stages = Some(s),
+                ("uniform", ExplicitArg::Count(c)) if count.is_none() => count = Some(c),
+                (_, explicit) => {
+                    return Err(Error::new(
+                        span,
+                        format!("{:?} is not valid for '{}' or repeated", explicit, ident),
+                    ))
+                }
+            }
+        }
+
+        let bind_type = match (ident.as_str(), index) {
+            ("in", None) => BindgenFieldType::In(None),
+            ("out", None) => BindgenFieldType::Out(None),
+            ("uniform", None) => BindgenFieldType::Uniform(None),
+            ("in", Some(ExplicitArg::Location(l))) => BindgenFieldType::In(Some(Location(l))),
+            ("out", Some(ExplicitArg::Location(l))) => BindgenFieldType::Out(Some(Location(l))),
+            ("uniform", Some(ExplicitArg::Binding(b))) => {
+                BindgenFieldType::Uniform(Some(Binding(b)))
+            }
             _ => panic!("Unknown BindgenFieldType: {}", ident),
+        };
+
+        Ok(Self {
+            bind_type,
+            stages,
+            count,
+        })
+    }
+}
+
+impl syn::parse::Parse for SpannedArg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        Ok(Self(input.span(), input.parse()?))
+    }
+}
+
+impl syn::parse::Parse for ExplicitArg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let ident = input.parse::<Ident>()?;
+        input.parse::<Token![=]>()?;
+
+        if ident == "stages" {
+            let list;
+            bracketed!(list in input);
+            let names = Punctuated::<Ident, Token![,]>::parse_terminated(&list)?;
+
+            let mut stages = names
+                .iter()
+                .map(|name| {
+                    ModuleType::from_name(name.to_string().as_str()).ok_or_else(|| {
+                        Error::new(name.span(), format!("Unknown shader stage '{}'", name))
+                    })
+                })
+                .collect::<syn::Result<Vec<_>>>()?;
+            if stages.is_empty() {
+                return Err(Error::new(
+                    ident.span(),
+                    "'stages' needs at least one stage",
+                ));
+            }
+            stages.sort();
+            stages.dedup();
+            return Ok(Self::Stages(stages));
+        }
+
+        if ident == "count" {
+            if input.peek(syn::Ident) {
+                let unbounded = input.parse::<Ident>()?;
+                if unbounded != "unbounded" {
+                    return Err(Error::new(
+                        unbounded.span(),
+                        format!("expected a count or 'unbounded', found '{}'", unbounded),
+                    ));
+                }
+                return Ok(Self::Count(DescriptorCount::Unbounded));
+            }
+
+            let count = input.parse::<syn::LitInt>()?;
+            return match count.base10_parse::<u32>()? {
+                0 => Err(Error::new(
+                    count.span(),
+                    "'count' needs at least one descriptor",
+                )),
+                count => Ok(Self::Count(DescriptorCount::Fixed(count))),
+            };
+        }
+
+        // the key is checked first, the value may not be an integer either
+        if ident != "location" && ident != "binding" {
+            return Err(Error::new(
+                ident.span(),
+                format!(
+                    "expected 'location', 'binding', 'stages' or 'count', found '{}'",
+                    ident
+                ),
+            ));
+        }
+        let index = input.parse::<syn::LitInt>()?.base10_parse::<u32>()?;
+
+        Ok(if ident == "location" {
+            Self::Location(index)
+        } else {
+            Self::Binding(index)
         })
     }
 }
@@ -321,6 +571,10 @@ impl syn::parse::Parse for StructFieldType {
             "mat3" => StructFieldType::Mat3(),
             "mat4" => StructFieldType::Mat4(),
 
+            "f16vec2" => StructFieldType::Half2(),
+            "f16vec4" => StructFieldType::Half4(),
+            "packed_normal" => StructFieldType::PackedNormal(),
+
             _ => panic!("Currently unsupported field type: {}", field_type),
         })
     }
@@ -331,6 +585,11 @@ impl syn::parse::Parse for StructFieldType {
 impl BindgenStruct {
     pub fn generate(&mut self, reg: &mut StructRegistry) {
         match (&mut self.meta.bind_type, reg.map.get(&self.struct_name)) {
+            // explicit
+            (BindgenFieldType::Uniform(Some(_)), _)
+            | (BindgenFieldType::In(Some(_)), _)
+            | (BindgenFieldType::Out(Some(_)), _) => (),
+
             (BindgenFieldType::Uniform(i), Some(BindingLocation::Binding(new_i))) => {
                 *i = Some(*new_i);
             }
@@ -375,12 +634,19 @@ impl BindgenStruct {
                     );
                 }
 
+                let array = match self.meta.count {
+                    None => String::new(),
+                    Some(DescriptorCount::Fixed(count)) => format!("[{}]", count),
+                    Some(DescriptorCount::Unbounded) => "[]".to_owned(),
+                };
+
                 format!(
-                    "layout(binding = {}) uniform {} {{{}}} {};",
+                    "layout(binding = {}) uniform {} {{{}}} {}{};",
                     i.as_ref().expect("BindgenStruct bindings not generated").0,
                     self.struct_name,
                     fields,
-                    self.field_name
+                    self.field_name,
+                    array
                 )
             }
             BindgenFieldType::In(l) | BindgenFieldType::Out(l) => {
@@ -415,7 +681,7 @@ impl BindgenStruct {
         namespacer("gears_traits", tokens);
         tokens.append(Ident::new("Vertex", Span::call_site()));
         tokens.append(Ident::new("for", Span::call_site()));
-        tokens.append(Ident::new(self.struct_name.as_str(), Span::call_site()));
+        tokens.append(Ident::new(self.rust_name.as_str(), Span::call_site()));
 
         let empty_tokens = TokenStream::new();
 
@@ -565,7 +831,7 @@ impl BindgenStruct {
         namespacer("gears_traits", tokens);
         tokens.append(Ident::new("UBO", Span::call_site()));
         tokens.append(Ident::new("for", Span::call_site()));
-        tokens.append(Ident::new(self.struct_name.as_str(), Span::call_site()));
+        tokens.append(Ident::new(self.rust_name.as_str(), Span::call_site()));
 
         let impl_tokens = {
             let mut impl_tokens = TokenStream::new();
@@ -579,29 +845,62 @@ impl BindgenStruct {
 
             impl_tokens.append(Punct::new('=', Spacing::Alone));
 
-            namespacer("gears_traits", &mut impl_tokens);
-            namespacer("vk", &mut impl_tokens);
-            namespacer("ShaderStageFlags", &mut impl_tokens);
-            impl_tokens.append(Ident::new(
-                match self.meta.in_module {
-                    ModuleType::Vertex => "VERTEX",
-                    ModuleType::Fragment => "FRAGMENT",
-                    ModuleType::Geometry => "GEOMETRY",
-                },
-                Span::call_site(),
-            ));
+            // one descriptor binding visible to every listed stage
+            let stages = match self.meta.stages.as_ref() {
+                Some(stages) => stages.clone(),
+                None => vec![self.meta.in_module],
+            };
+            let flags = stages.iter().map(|stage| {
+                let flag = Ident::new(stage_flag(*stage), Span::call_site());
+                quote! { gears_traits::vk::ShaderStageFlags::#flag }
+            });
+            impl_tokens.extend(if stages.len() == 1 {
+                quote! { #(#flags)* }
+            } else {
+                quote! {
+                    gears_traits::vk::ShaderStageFlags::from_raw(#(#flags.as_raw())|*)
+                }
+            });
 
             impl_tokens.append(Punct::new(';', Spacing::Alone));
 
+            // arrays of blocks may leave elements unbound, see `UBO::layout_binding`
+            impl_tokens.extend(match self.meta.count {
+                None => quote! {},
+                Some(DescriptorCount::Fixed(count)) => quote! {
+                    const COUNT: Option<u32> = Some(#count);
+                    const BINDING_FLAGS: gears_traits::vk::DescriptorBindingFlags =
+                        gears_traits::vk::DescriptorBindingFlags::PARTIALLY_BOUND;
+                },
+                Some(DescriptorCount::Unbounded) => quote! {
+                    const COUNT: Option<u32> = None;
+                    const BINDING_FLAGS: gears_traits::vk::DescriptorBindingFlags =
+                        gears_traits::vk::DescriptorBindingFlags::from_raw(
+                            gears_traits::vk::DescriptorBindingFlags::PARTIALLY_BOUND.as_raw()
+                                | gears_traits::vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT
+                                    .as_raw(),
+                        );
+                },
+            });
+
             impl_tokens
         };
         tokens.append(Group::new(Delimiter::Brace, impl_tokens));
 
-        // impl Default
+        // per object array, see `Pipeline::write_ubo_slice`
+        let visibility = &self.visibility;
+        let name = Ident::new(self.rust_name.as_str(), Span::call_site());
+        let array_name = Ident::new(&format!("{}Array", self.rust_name), Span::call_site());
+        tokens.extend(quote! {
+            #visibility type #array_name = gears_traits::AlignedArray<#name>;
+        });
+    }
+
+    fn default_to_tokens(&self, tokens: &mut TokenStream) {
         tokens.append(Ident::new("impl", Span::call_site()));
         tokens.append(Ident::new("Default", Span::call_site()));
         tokens.append(Ident::new("for", Span::call_site()));
-        tokens.append(Ident::new(self.struct_name.as_str(), Span::call_site()));
+        tokens.append(Ident::new(self.rust_name.as_str(), Span::call_site()));
 
         let impl_tokens = {
             let empty_tokens = TokenStream::new();
@@ -622,6 +921,13 @@ impl BindgenStruct {
                         self_tokens
                             .append(Ident::new(field.field_name.as_str(), Span::call_site()));
                         self_tokens.append(Punct::new(':', Spacing::Alone));
+                        if let Some(array_default) =
+                            field.field_type.array_default().filter(|_| self.no_std)
+                        {
+                            self_tokens.extend(array_default);
+                            self_tokens.append(Punct::new(',', Spacing::Alone));
+                            continue;
+                        }
                         match &field.field_type {
                             StructFieldType::Bool() => {
                                 self_tokens.append(Ident::new("false", Span::call_site()))
@@ -698,6 +1004,15 @@ impl BindgenStruct {
                                 self_tokens
                                     .append(Group::new(Delimiter::Parenthesis, value_tokens));
                             }
+
+                            StructFieldType::Half2()
+                            | StructFieldType::Half4()
+                            | StructFieldType::PackedNormal() => {
+                                namespacer("Default", &mut self_tokens);
+                                self_tokens.append(Ident::new("default", Span::call_site()));
+                                self_tokens
+                                    .append(Group::new(Delimiter::Parenthesis, TokenStream::new()));
+                            }
                         };
                         self_tokens.append(Punct::new(',', Spacing::Alone));
                     }
@@ -733,38 +1048,84 @@ impl ToTokens for BindgenStruct {
         attrib_tokens.append(Group::new(Delimiter::Parenthesis, derive_tokens));
         tokens.append(Group::new(Delimiter::Bracket, attrib_tokens));
 
-        tokens.append(Ident::new("pub", Span::call_site()));
+        self.visibility.to_tokens(tokens);
         tokens.append(Ident::new("struct", Span::call_site()));
 
-        tokens.append(Ident::new(self.struct_name.as_str(), Span::call_site()));
+        tokens.append(Ident::new(self.rust_name.as_str(), Span::call_site()));
 
         let mut struct_tokens = TokenStream::new();
         for field in self.fields.fields.iter() {
-            field.to_tokens(&mut struct_tokens);
+            field.to_tokens(self.no_std, &mut struct_tokens);
         }
         tokens.append(Group::new(Delimiter::Brace, struct_tokens));
 
         // impls
 
         match &self.meta.bind_type {
-            BindgenFieldType::Uniform(_) => self.uniform_to_tokens(tokens),
-            BindgenFieldType::In(_) | BindgenFieldType::Out(_) => self.in_out_to_tokens(tokens),
+            BindgenFieldType::Uniform(_) => {
+                if !self.no_std {
+                    self.uniform_to_tokens(tokens);
+                }
+                self.default_to_tokens(tokens);
+            }
+            BindgenFieldType::In(_) | BindgenFieldType::Out(_) => {
+                if !self.no_std {
+                    self.in_out_to_tokens(tokens);
+                }
+            }
         }
     }
 }
 
+fn stage_flag(stage: ModuleType) -> &'static str {
+    match stage {
+        ModuleType::Vertex => "VERTEX",
+        ModuleType::Fragment => "FRAGMENT",
+        ModuleType::Geometry => "GEOMETRY",
+        ModuleType::Task => "TASK_NV",
+        ModuleType::Mesh => "MESH_NV",
+        ModuleType::Compute => "COMPUTE",
+        ModuleType::RayGen => "RAYGEN_KHR",
+        ModuleType::Miss => "MISS_KHR",
+        ModuleType::ClosestHit => "CLOSEST_HIT_KHR",
+    }
+}
+
+// FNV-1a, unlike `DefaultHasher` it is the same on every toolchain
+fn stable_hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// where the packed vertex types are, gears_traits re-exports the runtime
+fn runtime_crate(no_std: bool) -> &'static str {
+    if no_std {
+        "gears_pipeline_runtime"
+    } else {
+        "gears_traits"
+    }
+}
+
 fn namespacer(namespace: &'static str, tokens: &mut TokenStream) {
     tokens.append(Ident::new(namespace, Span::call_site()));
     tokens.append(Punct::new(':', Spacing::Joint));
     tokens.append(Punct::new(':', Spacing::Joint));
 }
 
-impl ToTokens for StructField {
-    fn to_tokens(&self, tokens: &mut TokenStream) {
+impl StructField {
+    fn to_tokens(&self, no_std: bool, tokens: &mut TokenStream) {
         tokens.append(Ident::new("pub", Span::call_site()));
         tokens.append(Ident::new(self.field_name.as_str(), Span::call_site()));
         tokens.append(Punct::new(':', Spacing::Alone));
 
+        if let Some(array_type) = self.field_type.array_type().filter(|_| no_std) {
+            tokens.extend(array_type);
+            tokens.append(Punct::new(',', Spacing::Alone));
+            return;
+        }
+        let runtime = runtime_crate(no_std);
+
         let append_cgmath = |tokens: &mut TokenStream| {
             tokens.append(Ident::new("cgmath", Span::call_site()));
             tokens.append(Punct::new(':', Spacing::Joint));
@@ -812,6 +1173,22 @@ impl ToTokens for StructField {
                 tokens.append(Ident::new("Matrix4", Span::call_site()));
                 append_f32(tokens);
             }
+
+            StructFieldType::Half2() | StructFieldType::Half4() => {
+                let mut array_tokens = TokenStream::new();
+                namespacer(runtime, &mut array_tokens);
+                array_tokens.append(Ident::new("Half", Span::call_site()));
+                array_tokens.append(Punct::new(';', Spacing::Alone));
+                array_tokens.append(Literal::usize_unsuffixed(match self.field_type {
+                    StructFieldType::Half2() => 2,
+                    _ => 4,
+                }));
+                tokens.append(Group::new(Delimiter::Bracket, array_tokens));
+            }
+            StructFieldType::PackedNormal() => {
+                namespacer(runtime, tokens);
+                tokens.append(Ident::new("PackedNormal", Span::call_site()));
+            }
         };
 
         tokens.append(Punct::new(',', Spacing::Alone));
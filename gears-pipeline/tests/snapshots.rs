@@ -0,0 +1,95 @@
+// snapshots of the GLSL and the Rust bindings the macros generate, in `tests/snapshots/`
+//
+// After an intended change, or for a new snapshot,
+// `UPDATE_SNAPSHOTS=1 cargo test -p gears-pipeline --test snapshots` writes them.
+
+use quote::ToTokens;
+use std::{env, fs, path::Path};
+
+fn assert_snapshot(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(name);
+
+    if env::var_os("UPDATE_SNAPSHOTS").map_or(false, |update| update == "1") {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "Snapshot '{}' is missing, run with UPDATE_SNAPSHOTS=1 to write it",
+            name
+        )
+    });
+    assert!(
+        expected == actual,
+        "Snapshot '{}' changed, rerun with UPDATE_SNAPSHOTS=1 to update it\n--- expected\n{}\n--- actual\n{}",
+        name,
+        expected,
+        actual
+    );
+}
+
+// the generated structs, aliases and impls, one per line, without the SPIR-V
+fn bindings(expanded: &str) -> String {
+    let file = syn::parse_file(expanded).expect("Expansion is not valid Rust");
+    file.items
+        .iter()
+        .filter(|item| match item {
+            syn::Item::Struct(_) | syn::Item::Type(_) | syn::Item::Impl(_) => true,
+            _ => false,
+        })
+        .map(|item| item.to_token_stream().to_string() + "\n")
+        .collect()
+}
+
+#[test]
+fn glsl() {
+    const SOURCE: &str = gears_pipeline::glsl! {
+        path: "tests/test.glsl"
+        def: [ "FRAGMENT", "VALUE" = "2" ]
+    };
+
+    assert_snapshot("test.glsl.snap", SOURCE);
+}
+
+#[test]
+fn bindings_default() {
+    const EXPANDED: &str = gears_pipeline::__internal_expand_for_tests! {
+        vert: {
+            path: "tests/test.glsl"
+            def: [ "FRAGMENT", "VALUE" = "2" ]
+        }
+    };
+
+    assert_snapshot("bindings.snap", &bindings(EXPANDED));
+}
+
+#[test]
+fn bindings_renamed() {
+    const EXPANDED: &str = gears_pipeline::__internal_expand_for_tests! {
+        ubo_name: "TimeUbo"
+        struct_suffix: "Data"
+        vert: {
+            path: "tests/test.glsl"
+            def: [ "FRAGMENT", "VALUE" = "2" ]
+        }
+    };
+
+    assert_snapshot("bindings_renamed.snap", &bindings(EXPANDED));
+}
+
+#[test]
+fn bindings_no_std() {
+    const EXPANDED: &str = gears_pipeline::__internal_expand_for_tests! {
+        no_std
+        vert: {
+            path: "tests/test.glsl"
+            def: [ "FRAGMENT", "VALUE" = "2" ]
+        }
+    };
+
+    assert_snapshot("bindings_no_std.snap", &bindings(EXPANDED));
+}
@@ -0,0 +1,9 @@
+// compile errors of `pipeline!`, their messages and spans, in `tests/ui/`
+//
+// `TRYBUILD=overwrite cargo test -p gears-pipeline --test ui` rewrites the
+// expected `.stderr` files after an intended change.
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
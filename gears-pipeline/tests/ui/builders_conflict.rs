@@ -0,0 +1,9 @@
+gears_pipeline::pipeline! {
+    builders
+    no_builders
+    vert: {
+        source: "#version 450\nvoid main() {}"
+    }
+}
+
+fn main() {}
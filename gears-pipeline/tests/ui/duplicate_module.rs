@@ -0,0 +1,10 @@
+gears_pipeline::pipeline! {
+    vert: {
+        source: "#version 450\nvoid main() {}"
+    }
+    vert: {
+        source: "#version 450\nvoid main() {}"
+    }
+}
+
+fn main() {}
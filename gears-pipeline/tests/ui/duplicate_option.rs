@@ -0,0 +1,9 @@
+gears_pipeline::pipeline! {
+    compress
+    compress
+    vert: {
+        source: "#version 450\nvoid main() {}"
+    }
+}
+
+fn main() {}
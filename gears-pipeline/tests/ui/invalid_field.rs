@@ -0,0 +1,7 @@
+gears_pipeline::pipeline! {
+    vert: {
+        sauce: "#version 450\nvoid main() {}"
+    }
+}
+
+fn main() {}
@@ -0,0 +1,7 @@
+gears_pipeline::pipeline! {
+    vs: {
+        source: "#version 450\n#[gears_bindgen(uniform(set = [0]))] struct UBO { float time; } ubo;\nvoid main() {}"
+    }
+}
+
+fn main() {}
@@ -0,0 +1,7 @@
+gears_pipeline::pipeline! {
+    shaders: {
+        source: "#version 450\nvoid main() {}"
+    }
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+gears_pipeline::pipeline! {
+    rename: ["Camera" = "CameraUbo"]
+    vert: {
+        source: "#version 450\nvoid main() {}"
+    }
+}
+
+fn main() {}
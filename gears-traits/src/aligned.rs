@@ -0,0 +1,149 @@
+use std::{marker::PhantomData, mem, ptr};
+
+/// `U` instances packed `stride` bytes apart, for per object UBOs read with dynamic offsets.
+///
+/// The stride is the size of `U` rounded up to the device's
+/// `minUniformBufferOffsetAlignment`, get one with the right alignment from
+/// `Pipeline::ubo_array` and upload it with `Pipeline::write_ubo_slice`.
+#[derive(Debug, Clone)]
+pub struct AlignedArray<U> {
+    bytes: Vec<u8>,
+    stride: usize,
+
+    _p: PhantomData<U>,
+}
+
+impl<U: Copy> AlignedArray<U> {
+    /// `alignment` has to be a power of two.
+    pub fn new(alignment: usize) -> Self {
+        let alignment = alignment.max(1);
+        assert!(
+            alignment.is_power_of_two(),
+            "UBO alignment {} is not a power of two",
+            alignment
+        );
+
+        // zero sized UBOs still get distinct offsets
+        let stride = ((mem::size_of::<U>() + alignment - 1) & !(alignment - 1)).max(alignment);
+
+        Self {
+            bytes: Vec::new(),
+            stride,
+
+            _p: PhantomData::default(),
+        }
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len() / self.stride
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Dynamic offset of element `index` in bytes.
+    pub fn offset(&self, index: usize) -> usize {
+        index * self.stride
+    }
+
+    pub fn push(&mut self, value: U) {
+        let index = self.len();
+        self.bytes.resize(self.bytes.len() + self.stride, 0);
+        self.set(index, value);
+    }
+
+    /// Panics if `index` is out of range.
+    pub fn set(&mut self, index: usize, value: U) {
+        assert!(index < self.len(), "UBO array index {} out of range", index);
+        let offset = self.offset(index);
+        // the bytes are only aligned to 1
+        unsafe { ptr::write_unaligned(self.bytes[offset..].as_mut_ptr() as *mut U, value) }
+    }
+
+    /// Panics if `index` is out of range.
+    pub fn get(&self, index: usize) -> U {
+        assert!(index < self.len(), "UBO array index {} out of range", index);
+        let offset = self.offset(index);
+        unsafe { ptr::read_unaligned(self.bytes[offset..].as_ptr() as *const U) }
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+    }
+
+    /// Every element with its padding, as uploaded.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    #[repr(C)]
+    struct Object {
+        position: [f32; 3],
+        id: u32,
+    }
+
+    #[test]
+    fn stride_rounds_up_to_alignment() {
+        assert_eq!(256, AlignedArray::<Object>::new(256).stride());
+        assert_eq!(16, AlignedArray::<Object>::new(16).stride());
+        assert_eq!(16, AlignedArray::<Object>::new(1).stride());
+        assert_eq!(32, AlignedArray::<[Object; 2]>::new(0).stride());
+        assert_eq!(64, AlignedArray::<()>::new(64).stride());
+    }
+
+    #[test]
+    #[should_panic]
+    fn alignment_has_to_be_a_power_of_two() {
+        AlignedArray::<Object>::new(48);
+    }
+
+    #[test]
+    fn layout() {
+        let mut array = AlignedArray::new(64);
+        let a = Object {
+            position: [1.0, 2.0, 3.0],
+            id: 7,
+        };
+        let b = Object {
+            position: [-1.0, 0.5, 0.0],
+            id: 9,
+        };
+
+        assert!(array.is_empty());
+        array.push(a);
+        array.push(b);
+
+        assert_eq!(2, array.len());
+        assert_eq!(128, array.as_bytes().len());
+        assert_eq!(64, array.offset(1));
+        assert_eq!(a, array.get(0));
+        assert_eq!(b, array.get(1));
+
+        // padding stays zeroed
+        assert!(array.as_bytes()[16..64].iter().all(|&byte| byte == 0));
+        assert_eq!(&9u32.to_ne_bytes(), &array.as_bytes()[76..80]);
+
+        array.set(0, b);
+        assert_eq!(b, array.get(0));
+
+        array.clear();
+        assert!(array.is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn get_out_of_range() {
+        AlignedArray::<Object>::new(16).get(0);
+    }
+}
@@ -1,8 +1,31 @@
+pub use aligned::AlignedArray;
 pub use ash::vk;
 pub use cgmath::{Matrix2, Matrix3, Matrix4, Vector2, Vector3, Vector4};
+pub use gears_pipeline_runtime::{
+    environment, inflate_spirv, spirv_bytes, Half, Lazy, PackedNormal,
+};
+
+mod aligned;
 
 pub trait UBO {
     const STAGE: vk::ShaderStageFlags;
+    /// Blocks in an array of `uniform(count = N)`, `None` for `count = unbounded`.
+    const COUNT: Option<u32> = Some(1);
+    const BINDING_FLAGS: vk::DescriptorBindingFlags = vk::DescriptorBindingFlags::empty();
+
+    /// The descriptor set layout binding of this block at `binding`.
+    ///
+    /// `max_count` is the descriptor count of an unbounded array, the
+    /// descriptor set is allocated with its actual variable count. Pass
+    /// `BINDING_FLAGS` to `vk::DescriptorSetLayoutBindingFlagsCreateInfo`.
+    fn layout_binding(binding: u32, max_count: u32) -> vk::DescriptorSetLayoutBinding {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_count(Self::COUNT.unwrap_or(max_count))
+            .stage_flags(Self::STAGE)
+            .build()
+    }
 }
 
 pub trait Vertex /* <const N: usize> */ {
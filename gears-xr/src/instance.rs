@@ -0,0 +1,180 @@
+use ash::{
+    version::InstanceV1_0,
+    vk::{self, Handle},
+};
+use gears::{
+    context::{ContextError, ContextRequirements},
+    logging,
+    renderer::buffer::BufferError,
+};
+use log::{debug, warn};
+use openxr as xr;
+use std::{ffi::CString, fmt};
+
+pub(crate) const VIEW_TYPE: xr::ViewConfigurationType = xr::ViewConfigurationType::PRIMARY_STEREO;
+
+#[derive(Debug)]
+pub enum XrError {
+    /// The OpenXR loader library could not be loaded.
+    Loader(String),
+    /// The runtime lacks something gears needs, for ex. Vulkan support.
+    Unsupported(&'static str),
+    Runtime(xr::sys::Result),
+    Vulkan(vk::Result),
+    Buffer(BufferError),
+    Context(ContextError),
+}
+
+/// The OpenXR runtime and its head mounted display.
+///
+/// Created before the gears `Context`: the runtime decides the GPU and the
+/// Vulkan extensions, see `requirements`.
+pub struct XrInstance {
+    pub(crate) instance: xr::Instance,
+    pub(crate) system: xr::SystemId,
+    pub(crate) blend_mode: xr::EnvironmentBlendMode,
+}
+
+impl XrInstance {
+    pub fn new(application_name: &str) -> Result<Self, XrError> {
+        let entry = xr::Entry::load().map_err(|err| XrError::Loader(format!("{:?}", err)))?;
+
+        // the runtime creates the Vulkan instance itself with vulkan_enable2,
+        // gears creates its own
+        if !entry.enumerate_extensions()?.khr_vulkan_enable {
+            return Err(XrError::Unsupported("XR_KHR_vulkan_enable"));
+        }
+        let mut extensions = xr::ExtensionSet::default();
+        extensions.khr_vulkan_enable = true;
+
+        let instance = entry.create_instance(
+            &xr::ApplicationInfo {
+                application_name,
+                application_version: 0,
+                engine_name: "gears",
+                engine_version: 0,
+            },
+            &extensions,
+            &[],
+        )?;
+        let properties = instance.properties()?;
+        debug!(
+            target: logging::XR,
+            "OpenXR runtime: {} {}",
+            properties.runtime_name, properties.runtime_version
+        );
+
+        let system = instance.system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)?;
+        let blend_mode = *instance
+            .enumerate_environment_blend_modes(system, VIEW_TYPE)?
+            .first()
+            .ok_or(XrError::Unsupported("environment blend mode"))?;
+
+        Ok(Self {
+            instance,
+            system,
+            blend_mode,
+        })
+    }
+
+    /// Instance and device extensions and the GPU of the headset, for
+    /// `Frame::context_with_requirements`.
+    ///
+    /// The context creation fails with `ContextError::NoSuitableGPUs` if
+    /// the Vulkan version of the headset's GPU is outside the versions the
+    /// runtime supports.
+    pub fn requirements(&self) -> Result<ContextRequirements, XrError> {
+        // has to be queried before a session is created
+        let versions = self
+            .instance
+            .graphics_requirements::<xr::Vulkan>(self.system)?;
+        let min_version = versions.min_api_version_supported;
+        let max_version = versions.max_api_version_supported;
+        debug!(
+            target: logging::XR,
+            "OpenXR Vulkan versions: {} to {}",
+            min_version,
+            max_version
+        );
+
+        // space separated lists
+        let names = |list: String| {
+            list.split_whitespace()
+                .filter_map(|name| CString::new(name).ok())
+                .collect()
+        };
+
+        let instance = self.instance.clone();
+        let system = self.system;
+        Ok(ContextRequirements {
+            instance_extensions: names(self.instance.vulkan_legacy_instance_extensions(system)?),
+            device_extensions: names(self.instance.vulkan_legacy_device_extensions(system)?),
+            pick: Some(Box::new(move |vk_instance: &ash::Instance| {
+                let pdevice = instance
+                    .vulkan_graphics_device(system, vk_instance.handle().as_raw() as usize as _)
+                    .ok()?;
+                let pdevice = vk::PhysicalDevice::from_raw(pdevice as usize as u64);
+
+                // newer minor versions are compatible, newer major versions are not
+                let api_version =
+                    unsafe { vk_instance.get_physical_device_properties(pdevice) }.api_version;
+                let version = xr::Version::new(
+                    vk::version_major(api_version) as u16,
+                    vk::version_minor(api_version) as u16,
+                    vk::version_patch(api_version),
+                );
+                if version < min_version || version.major() > max_version.major() {
+                    warn!(
+                        target: logging::XR,
+                        "Vulkan {} of the headset's GPU is outside the supported {} to {}",
+                        version,
+                        min_version,
+                        max_version
+                    );
+                    return None;
+                }
+
+                Some(pdevice)
+            })),
+        })
+    }
+}
+
+impl fmt::Display for XrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XrError::Loader(err) => write!(f, "OpenXR loader not found: {}", err),
+            XrError::Unsupported(what) => write!(f, "OpenXR runtime does not support {}", what),
+            XrError::Runtime(err) => write!(f, "OpenXR call failed: {}", err),
+            XrError::Vulkan(err) => write!(f, "Vulkan call failed: {}", err),
+            XrError::Buffer(err) => write!(f, "Render target creation failed: {:?}", err),
+            XrError::Context(err) => write!(f, "Context creation failed: {:?}", err),
+        }
+    }
+}
+
+impl std::error::Error for XrError {}
+
+impl From<xr::sys::Result> for XrError {
+    fn from(err: xr::sys::Result) -> Self {
+        XrError::Runtime(err)
+    }
+}
+
+impl From<vk::Result> for XrError {
+    fn from(err: vk::Result) -> Self {
+        XrError::Vulkan(err)
+    }
+}
+
+impl From<BufferError> for XrError {
+    fn from(err: BufferError) -> Self {
+        XrError::Buffer(err)
+    }
+}
+
+impl From<ContextError> for XrError {
+    fn from(err: ContextError) -> Self {
+        XrError::Context(err)
+    }
+}
@@ -0,0 +1,38 @@
+//! Renders gears `RenderTarget`s to an OpenXR headset.
+//!
+//! The runtime picks the GPU and adds Vulkan extensions, so the
+//! `XrInstance` is created before the gears `Context`:
+//!
+//! ```ignore
+//! let xr = XrInstance::new("app")?;
+//! let context = frame.context_with_requirements(
+//!     ContextGPUPick::Automatic,
+//!     ContextValidation::WithValidation,
+//!     xr.requirements()?,
+//! )?;
+//! let renderer = Renderer::new().build(context)?;
+//! let mut session = XrSession::new(&xr, &renderer, vk::SampleCountFlags::TYPE_4)?;
+//!
+//! while session.poll_events()? {
+//!     let xr_frame = match session.begin_frame()? {
+//!         Some(xr_frame) => xr_frame,
+//!         None => continue,
+//!     };
+//!     for (eye, view) in xr_frame.views.iter().enumerate() {
+//!         eyes[eye].update(view.projection * view.view);
+//!     }
+//!     // records each eye into session.target(eye)
+//!     renderer.frame(&frame_info, &mut app);
+//!     session.end_frame(xr_frame)?;
+//! }
+//! ```
+//!
+//! The OpenXR loader is loaded at runtime, applications without a headset
+//! get `XrError::Loader` from `XrInstance::new`.
+
+pub use instance::{XrError, XrInstance};
+pub use openxr;
+pub use session::{projection_matrix, view_matrix, XrFrame, XrSession, XrView};
+
+mod instance;
+mod session;
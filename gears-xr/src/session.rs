@@ -0,0 +1,542 @@
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk::{self, Handle},
+};
+use cgmath::{Matrix4, Quaternion, Vector3, Vector4};
+use gears::{
+    logging,
+    renderer::target::{RenderTarget, Resolve},
+    renderer::Renderer,
+};
+use log::debug;
+use openxr as xr;
+
+use crate::instance::{XrError, XrInstance, VIEW_TYPE};
+
+// preferred swapchain formats, the runtime order otherwise
+const FORMATS: [vk::Format; 2] = [vk::Format::R8G8B8A8_SRGB, vk::Format::B8G8R8A8_SRGB];
+
+/// Head pose and projection of one eye at the predicted display time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct XrView {
+    /// World to eye, the world is the stage space of the session.
+    pub view: Matrix4<f32>,
+    /// See `projection_matrix`.
+    pub projection: Matrix4<f32>,
+    pub pose: xr::Posef,
+    pub fov: xr::Fovf,
+}
+
+/// A frame from `XrSession::begin_frame`, it has to be passed to `end_frame`.
+pub struct XrFrame {
+    display_time: xr::Time,
+    /// One per eye, empty if the runtime does not show this frame, for ex.
+    /// while the headset is taken off.
+    pub views: Vec<XrView>,
+}
+
+/// Renders a `RenderTarget` per eye to the headset.
+///
+/// A frame goes through `poll_events`, `begin_frame` for the eye matrices,
+/// `Renderer::frame` recording each eye into `target(eye)` like any other
+/// `RenderTarget` and `end_frame`, which copies the targets to the
+/// runtime's swapchain images and submits them as a projection layer. All
+/// of them run on the thread calling `Renderer::frame`, they share its
+/// queue.
+pub struct XrSession {
+    device: ash::Device,
+    queue: vk::Queue,
+
+    instance: xr::Instance,
+    blend_mode: xr::EnvironmentBlendMode,
+    session: xr::Session<xr::Vulkan>,
+    frame_waiter: xr::FrameWaiter,
+    frame_stream: xr::FrameStream<xr::Vulkan>,
+    stage: xr::Space,
+    events: xr::EventDataBuffer,
+    running: bool,
+
+    // the targets keep the device alive
+    eyes: Vec<Eye>,
+    format: vk::Format,
+    near: f32,
+    far: f32,
+
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+}
+
+struct Eye {
+    swapchain: xr::Swapchain<xr::Vulkan>,
+    images: Vec<vk::Image>,
+    target: RenderTarget,
+    extent: vk::Extent2D,
+}
+
+impl XrView {
+    pub fn new(view: &xr::View, near: f32, far: f32) -> Self {
+        Self {
+            view: view_matrix(&view.pose),
+            projection: projection_matrix(&view.fov, near, far),
+            pose: view.pose,
+            fov: view.fov,
+        }
+    }
+}
+
+impl XrFrame {
+    pub fn should_render(&self) -> bool {
+        !self.views.is_empty()
+    }
+}
+
+impl XrSession {
+    /// `renderer` has to come from a `Context` with `XrInstance::requirements`.
+    ///
+    /// The eye targets have the size the runtime recommends and one color
+    /// attachment in the swapchain format, `samples` works like in
+    /// `RenderTarget::new_multisampled`.
+    pub fn new(
+        xr_instance: &XrInstance,
+        renderer: &Renderer,
+        samples: vk::SampleCountFlags,
+    ) -> Result<Self, XrError> {
+        let device = renderer.device().clone();
+        let (queue, queue_family) = renderer.graphics_queue();
+
+        let (session, frame_waiter, frame_stream) = unsafe {
+            xr_instance.instance.create_session::<xr::Vulkan>(
+                xr_instance.system,
+                &xr::vulkan::SessionCreateInfo {
+                    instance: renderer.instance().handle().as_raw() as usize as _,
+                    physical_device: renderer.physical_device().as_raw() as usize as _,
+                    device: device.handle().as_raw() as usize as _,
+                    queue_family_index: queue_family,
+                    queue_index: 0,
+                },
+            )
+        }?;
+
+        // room scale if the runtime has it
+        let space_type = if session
+            .enumerate_reference_spaces()?
+            .contains(&xr::ReferenceSpaceType::STAGE)
+        {
+            xr::ReferenceSpaceType::STAGE
+        } else {
+            xr::ReferenceSpaceType::LOCAL
+        };
+        let stage = session.create_reference_space(space_type, xr::Posef::IDENTITY)?;
+
+        let formats = session
+            .enumerate_swapchain_formats()?
+            .into_iter()
+            .map(|format| vk::Format::from_raw(format as i32))
+            .collect::<Vec<_>>();
+        let format = FORMATS
+            .iter()
+            .copied()
+            .find(|format| formats.contains(format))
+            .or_else(|| formats.first().copied())
+            .ok_or(XrError::Unsupported("swapchain formats"))?;
+
+        let eyes = xr_instance
+            .instance
+            .enumerate_view_configuration_views(xr_instance.system, VIEW_TYPE)?
+            .iter()
+            .map(|view| {
+                let extent = vk::Extent2D {
+                    width: view.recommended_image_rect_width,
+                    height: view.recommended_image_rect_height,
+                };
+                let swapchain = session.create_swapchain(&xr::SwapchainCreateInfo {
+                    create_flags: xr::SwapchainCreateFlags::EMPTY,
+                    usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT
+                        | xr::SwapchainUsageFlags::TRANSFER_DST,
+                    format: format.as_raw() as _,
+                    sample_count: 1,
+                    width: extent.width,
+                    height: extent.height,
+                    face_count: 1,
+                    array_size: 1,
+                    mip_count: 1,
+                })?;
+                let images = swapchain
+                    .enumerate_images()?
+                    .into_iter()
+                    .map(vk::Image::from_raw)
+                    .collect();
+                let target = RenderTarget::new_multisampled(
+                    renderer,
+                    extent.width,
+                    extent.height,
+                    &[format],
+                    samples,
+                    Resolve::EndOfPass,
+                )?;
+
+                Ok(Eye {
+                    swapchain,
+                    images,
+                    target,
+                    extent,
+                })
+            })
+            .collect::<Result<Vec<_>, XrError>>()?;
+
+        let command_pool_info = vk::CommandPoolCreateInfo::builder()
+            .queue_family_index(queue_family)
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+        let command_pool = unsafe { device.create_command_pool(&command_pool_info, None) }?;
+        let command_buffer_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        let command_buffer = unsafe { device.allocate_command_buffers(&command_buffer_info) }?[0];
+        let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+        let fence = unsafe { device.create_fence(&fence_info, None) }?;
+
+        debug!(
+            target: logging::XR,
+            "XrSession created: {} eyes, {:?}, {:?} space",
+            eyes.len(),
+            format,
+            space_type
+        );
+
+        Ok(Self {
+            device,
+            queue,
+
+            instance: xr_instance.instance.clone(),
+            blend_mode: xr_instance.blend_mode,
+            session,
+            frame_waiter,
+            frame_stream,
+            stage,
+            events: xr::EventDataBuffer::new(),
+            running: false,
+
+            eyes,
+            format,
+            near: 0.05,
+            far: 100.0,
+
+            command_pool,
+            command_buffer,
+            fence,
+        })
+    }
+
+    /// Starts and stops the session as the runtime asks, `false` once the application should exit.
+    pub fn poll_events(&mut self) -> Result<bool, XrError> {
+        while let Some(event) = self.instance.poll_event(&mut self.events)? {
+            match event {
+                xr::Event::SessionStateChanged(event) => {
+                    debug!(target: logging::XR, "XrSession state: {:?}", event.state());
+                    match event.state() {
+                        xr::SessionState::READY => {
+                            self.session.begin(VIEW_TYPE)?;
+                            self.running = true;
+                        }
+                        xr::SessionState::STOPPING => {
+                            self.session.end()?;
+                            self.running = false;
+                        }
+                        xr::SessionState::EXITING | xr::SessionState::LOSS_PENDING => {
+                            return Ok(false)
+                        }
+                        _ => {}
+                    }
+                }
+                xr::Event::InstanceLossPending(_) => return Ok(false),
+                _ => {}
+            }
+        }
+        Ok(true)
+    }
+
+    /// Asks the runtime to stop the session, `poll_events` returns `false` once it did.
+    pub fn request_exit(&self) -> Result<(), XrError> {
+        self.session.request_exit()?;
+        Ok(())
+    }
+
+    /// Waits for the runtime to be ready for the next frame, `None` while the session is not running.
+    pub fn begin_frame(&mut self) -> Result<Option<XrFrame>, XrError> {
+        if !self.running {
+            return Ok(None);
+        }
+
+        let state = self.frame_waiter.wait()?;
+        self.frame_stream.begin()?;
+
+        let views = if state.should_render {
+            let (_, views) =
+                self.session
+                    .locate_views(VIEW_TYPE, state.predicted_display_time, &self.stage)?;
+            views
+                .iter()
+                .map(|view| XrView::new(view, self.near, self.far))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Ok(Some(XrFrame {
+            display_time: state.predicted_display_time,
+            views,
+        }))
+    }
+
+    /// Copies the eye targets to the headset, after `Renderer::frame` recorded them.
+    pub fn end_frame(&mut self, frame: XrFrame) -> Result<(), XrError> {
+        if !frame.should_render() {
+            self.frame_stream
+                .end(frame.display_time, self.blend_mode, &[])?;
+            return Ok(());
+        }
+
+        let mut indices = Vec::with_capacity(self.eyes.len());
+        for eye in self.eyes.iter_mut() {
+            indices.push(eye.swapchain.acquire_image()? as usize);
+            eye.swapchain.wait_image(xr::Duration::INFINITE)?;
+        }
+        unsafe { self.copy(&indices) }?;
+        for eye in self.eyes.iter_mut() {
+            eye.swapchain.release_image()?;
+        }
+
+        let views = self
+            .eyes
+            .iter()
+            .zip(frame.views.iter())
+            .map(|(eye, view)| {
+                let rect = xr::Rect2Di {
+                    offset: xr::Offset2Di { x: 0, y: 0 },
+                    extent: xr::Extent2Di {
+                        width: eye.extent.width as i32,
+                        height: eye.extent.height as i32,
+                    },
+                };
+                xr::CompositionLayerProjectionView::new()
+                    .pose(view.pose)
+                    .fov(view.fov)
+                    .sub_image(
+                        xr::SwapchainSubImage::new()
+                            .swapchain(&eye.swapchain)
+                            .image_array_index(0)
+                            .image_rect(rect),
+                    )
+            })
+            .collect::<Vec<_>>();
+        let layer = xr::CompositionLayerProjection::new()
+            .space(&self.stage)
+            .views(&views);
+        self.frame_stream
+            .end(frame.display_time, self.blend_mode, &[&layer])?;
+
+        Ok(())
+    }
+
+    /// Near and far planes of `XrView::projection`, 0.05 and 100 by default.
+    pub fn set_depth_range(&mut self, near: f32, far: f32) {
+        self.near = near;
+        self.far = far;
+    }
+
+    /// The target of `XrFrame::views[eye]`.
+    pub fn target(&self, eye: usize) -> &RenderTarget {
+        &self.eyes[eye].target
+    }
+
+    pub fn eye_count(&self) -> usize {
+        self.eyes.len()
+    }
+
+    /// Color format of the eye targets.
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    // the targets are left in SHADER_READ_ONLY_OPTIMAL, the runtime expects
+    // released images in COLOR_ATTACHMENT_OPTIMAL
+    unsafe fn copy(&self, indices: &[usize]) -> Result<(), XrError> {
+        self.device.wait_for_fences(&[self.fence], true, !0)?;
+        self.device.reset_fences(&[self.fence])?;
+
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        self.device
+            .begin_command_buffer(self.command_buffer, &begin_info)?;
+
+        let range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(1)
+            .layer_count(1)
+            .build();
+        let layers = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1)
+            .build();
+        let barrier = |image: vk::Image,
+                       old_layout: vk::ImageLayout,
+                       new_layout: vk::ImageLayout,
+                       src_access: vk::AccessFlags,
+                       dst_access: vk::AccessFlags| {
+            vk::ImageMemoryBarrier::builder()
+                .image(image)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .subresource_range(range)
+                .build()
+        };
+
+        for (eye, &index) in self.eyes.iter().zip(indices) {
+            let src = eye.target.color_image(0).image();
+            let dst = eye.images[index];
+
+            self.device.cmd_pipeline_barrier(
+                self.command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    barrier(
+                        src,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                        vk::AccessFlags::TRANSFER_READ,
+                    ),
+                    barrier(
+                        dst,
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::TRANSFER_WRITE,
+                    ),
+                ],
+            );
+
+            let region = vk::ImageCopy::builder()
+                .src_subresource(layers)
+                .dst_subresource(layers)
+                .extent(vk::Extent3D {
+                    width: eye.extent.width,
+                    height: eye.extent.height,
+                    depth: 1,
+                })
+                .build();
+            self.device.cmd_copy_image(
+                self.command_buffer,
+                src,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                dst,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+
+            self.device.cmd_pipeline_barrier(
+                self.command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    barrier(
+                        src,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_READ,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                    barrier(
+                        dst,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                    ),
+                ],
+            );
+        }
+
+        self.device.end_command_buffer(self.command_buffer)?;
+
+        let command_buffers = [self.command_buffer];
+        let submit_info = vk::SubmitInfo::builder().command_buffers(&command_buffers);
+        self.device
+            .queue_submit(self.queue, &[submit_info.build()], self.fence)?;
+
+        Ok(())
+    }
+}
+
+impl Drop for XrSession {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.device.wait_for_fences(&[self.fence], true, !0);
+            self.device.destroy_fence(self.fence, None);
+            self.device.destroy_command_pool(self.command_pool, None);
+        }
+    }
+}
+
+/// World to eye matrix of an eye at `pose`.
+pub fn view_matrix(pose: &xr::Posef) -> Matrix4<f32> {
+    let (o, p) = (pose.orientation, pose.position);
+    let rotation = Quaternion::new(o.w, o.x, o.y, o.z);
+    Matrix4::from(rotation.conjugate()) * Matrix4::from_translation(-Vector3::new(p.x, p.y, p.z))
+}
+
+/// Off center perspective projection of `fov` into Vulkan clip space, with
+/// y pointing down and depth from 0 at `near` to 1 at `far`.
+///
+/// ```
+/// # use cgmath::Vector4;
+/// # use gears_xr::{openxr::Fovf, projection_matrix};
+/// let quarter = std::f32::consts::FRAC_PI_4;
+/// let fov = Fovf {
+///     angle_left: -quarter,
+///     angle_right: quarter,
+///     angle_up: quarter,
+///     angle_down: -quarter,
+/// };
+/// let projection = projection_matrix(&fov, 0.1, 100.0);
+///
+/// let up = projection * Vector4::new(0.0, 1.0, -1.0, 1.0);
+/// assert!((up.y / up.w + 1.0).abs() < 1e-5);
+/// let near = projection * Vector4::new(0.0, 0.0, -0.1, 1.0);
+/// assert!((near.z / near.w).abs() < 1e-5);
+/// ```
+pub fn projection_matrix(fov: &xr::Fovf, near: f32, far: f32) -> Matrix4<f32> {
+    let left = fov.angle_left.tan();
+    let right = fov.angle_right.tan();
+    // negative, y points down
+    let height = fov.angle_down.tan() - fov.angle_up.tan();
+    let width = right - left;
+    let up_down = fov.angle_up.tan() + fov.angle_down.tan();
+
+    Matrix4::from_cols(
+        Vector4::new(2.0 / width, 0.0, 0.0, 0.0),
+        Vector4::new(0.0, 2.0 / height, 0.0, 0.0),
+        Vector4::new(
+            (right + left) / width,
+            up_down / height,
+            -far / (far - near),
+            -1.0,
+        ),
+        Vector4::new(0.0, 0.0, -(far * near) / (far - near), 0.0),
+    )
+}
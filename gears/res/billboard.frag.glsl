@@ -0,0 +1,17 @@
+#version 450
+
+layout(location = 0) in vec2 uv;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 out_color;
+
+layout(set = 1, binding = 0) uniform sampler2D atlas;
+
+void main() {
+	vec4 texel = texture(atlas, uv) * color;
+	// alpha tested, billboards are drawn unsorted
+	if (texel.a < 0.5) {
+		discard;
+	}
+	out_color = vec4(texel.rgb, 1.0);
+}
@@ -0,0 +1,21 @@
+#version 450
+
+#[gears_bindgen(in)]
+struct BillboardVertex {
+	vec3 position;
+	vec2 uv;
+	vec4 color;
+} vert_in;
+
+layout(location = 0) out vec2 uv;
+layout(location = 1) out vec4 color;
+
+layout(push_constant) uniform Push {
+	mat4 view_projection;
+} push;
+
+void main() {
+	uv = vert_in.uv;
+	color = vert_in.color;
+	gl_Position = push.view_projection * vec4(vert_in.position, 1.0);
+}
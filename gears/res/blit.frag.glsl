@@ -0,0 +1,10 @@
+#version 450
+
+layout(location = 0) in vec2 uv;
+layout(location = 0) out vec4 out_color;
+
+layout(set = 1, binding = 0) uniform sampler2D source;
+
+void main() {
+	out_color = texture(source, uv);
+}
@@ -0,0 +1,27 @@
+#version 450
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(set = 1, binding = 0) uniform sampler2D src;
+layout(rgba16f, set = 1, binding = 1) writeonly uniform image2D dst;
+
+layout(push_constant) uniform Blur {
+	// one texel step along the blur axis, in uv units
+	vec2 direction;
+};
+
+const float weights[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+void main() {
+	ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+	ivec2 size = imageSize(dst);
+	if (pixel.x >= size.x || pixel.y >= size.y) return;
+
+	vec2 uv = (vec2(pixel) + 0.5) / vec2(size);
+	vec4 color = texture(src, uv) * weights[0];
+	for (int i = 1; i < 5; i++) {
+		color += texture(src, uv + direction * i) * weights[i];
+		color += texture(src, uv - direction * i) * weights[i];
+	}
+
+	imageStore(dst, pixel, color);
+}
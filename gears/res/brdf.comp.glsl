@@ -0,0 +1,50 @@
+#version 450
+#include "ibl.glsl"
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+// r = scale and g = bias of f0, by n dot v along x and roughness along y
+layout(rgba16f, set = 1, binding = 0) writeonly uniform image2D dst;
+
+layout(push_constant) uniform Brdf {
+	uint sample_count;
+};
+
+float geometry_schlick_ggx(float n_dot_x, float roughness) {
+	// k for image based lighting
+	float k = roughness * roughness / 2.0;
+	return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+void main() {
+	ivec2 texel = ivec2(gl_GlobalInvocationID.xy);
+	ivec2 size = imageSize(dst);
+	if (texel.x >= size.x || texel.y >= size.y) return;
+
+	vec2 uv = (vec2(texel) + 0.5) / vec2(size);
+	float n_dot_v = uv.x;
+	float roughness = uv.y;
+
+	vec3 v = vec3(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+	vec3 n = vec3(0.0, 0.0, 1.0);
+
+	float scale = 0.0;
+	float bias = 0.0;
+	for (uint i = 0u; i < sample_count; i++) {
+		vec3 h = ibl_importance_sample_ggx(ibl_hammersley(i, sample_count), n, roughness);
+		vec3 l = normalize(2.0 * dot(v, h) * h - v);
+
+		float n_dot_l = max(l.z, 0.0);
+		float n_dot_h = max(h.z, 0.0);
+		float v_dot_h = max(dot(v, h), 0.0);
+		if (n_dot_l > 0.0) {
+			float g = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+			float g_vis = g * v_dot_h / (n_dot_h * n_dot_v);
+			float fc = pow(1.0 - v_dot_h, 5.0);
+			scale += (1.0 - fc) * g_vis;
+			bias += fc * g_vis;
+		}
+	}
+
+	imageStore(dst, texel, vec4(scale, bias, 0.0, 1.0) / vec4(vec3(float(sample_count)), 1.0));
+}
@@ -0,0 +1,81 @@
+#version 450
+layout(local_size_x = 64) in;
+
+struct PointLight {
+	vec4 position;
+	vec4 color;
+};
+
+layout(std430, set = 1, binding = 0) readonly buffer Lights {
+	PointLight lights[];
+};
+
+layout(std430, set = 1, binding = 1) writeonly buffer Clusters {
+	vec4 cluster_params;
+	uvec4 cluster_grid;
+	uint cluster_counts[];
+};
+
+layout(std430, set = 1, binding = 2) writeonly buffer ClusterIndices {
+	uint cluster_indices[];
+};
+
+layout(push_constant) uniform Cluster {
+	mat4 view;
+	// projection[0][0], projection[1][1], near and far
+	vec4 projection;
+	// x, y and z cluster counts and the max lights per cluster
+	uvec4 grid;
+	vec2 screen;
+	uint light_count;
+} push;
+
+float slice_depth(uint slice) {
+	float near = push.projection.z;
+	float far = push.projection.w;
+	return near * pow(far / near, float(slice) / float(push.grid.z));
+}
+
+void main() {
+	uint i = gl_GlobalInvocationID.x;
+	uvec3 grid = push.grid.xyz;
+	uint max_lights = push.grid.w;
+
+	// read by the lighting include
+	if (i == 0) {
+		cluster_params = vec4(push.screen, push.projection.zw);
+		cluster_grid = push.grid;
+	}
+	if (i >= grid.x * grid.y * grid.z) {
+		return;
+	}
+
+	uvec3 cluster = uvec3(i % grid.x, (i / grid.x) % grid.y, i / (grid.x * grid.y));
+
+	// view space bounds of the froxel, the view looks along -z
+	vec2 ndc_min = vec2(cluster.xy) / vec2(grid.xy) * 2.0 - 1.0;
+	vec2 ndc_max = vec2(cluster.xy + 1) / vec2(grid.xy) * 2.0 - 1.0;
+	vec2 scale = push.projection.xy;
+	float near = slice_depth(cluster.z);
+	float far = slice_depth(cluster.z + 1);
+
+	vec2 a = ndc_min / scale;
+	vec2 b = ndc_max / scale;
+	vec2 lo = min(min(a * near, b * near), min(a * far, b * far));
+	vec2 hi = max(max(a * near, b * near), max(a * far, b * far));
+	vec3 box_min = vec3(lo, -far);
+	vec3 box_max = vec3(hi, -near);
+
+	uint count = 0;
+	for (uint l = 0; l < push.light_count && count < max_lights; l++) {
+		vec4 light = lights[l].position;
+		vec3 center = (push.view * vec4(light.xyz, 1.0)).xyz;
+		vec3 offset = center - clamp(center, box_min, box_max);
+		if (dot(offset, offset) <= light.w * light.w) {
+			cluster_indices[i * max_lights + count] = l;
+			count++;
+		}
+	}
+
+	cluster_counts[i] = count;
+}
@@ -0,0 +1,53 @@
+#version 450
+layout(local_size_x = 64) in;
+
+struct CullObject {
+	vec4 sphere;
+	uint index_count;
+	uint first_index;
+	int vertex_offset;
+	uint first_instance;
+};
+
+struct DrawCommand {
+	uint index_count;
+	uint instance_count;
+	uint first_index;
+	int vertex_offset;
+	uint first_instance;
+};
+
+layout(std430, set = 1, binding = 0) readonly buffer Objects {
+	CullObject objects[];
+};
+
+layout(std430, set = 1, binding = 1) writeonly buffer Draws {
+	DrawCommand draws[];
+};
+
+layout(push_constant) uniform Frustum {
+	vec4 planes[6];
+	uint object_count;
+} frustum;
+
+void main() {
+	uint i = gl_GlobalInvocationID.x;
+	if (i >= frustum.object_count) {
+		return;
+	}
+
+	CullObject object = objects[i];
+
+	bool visible = true;
+	for (int p = 0; p < 6; p++) {
+		vec4 plane = frustum.planes[p];
+		visible = visible && dot(plane.xyz, object.sphere.xyz) + plane.w > -object.sphere.w;
+	}
+
+	draws[i] = DrawCommand(
+		object.index_count,
+		visible ? 1 : 0,
+		object.first_index,
+		object.vertex_offset,
+		object.first_instance);
+}
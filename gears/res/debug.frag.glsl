@@ -0,0 +1,17 @@
+#version 450
+
+layout(location = 0) out vec4 out_color;
+
+// exaggerates depth slopes, depth is not linear so this is only a rough shape
+const float SLOPE = 4096.0;
+
+void main() {
+#if NORMALS
+	// flat per triangle, the user shader outputs are not known here
+	vec3 normal = normalize(vec3(-dFdx(gl_FragCoord.z) * SLOPE, -dFdy(gl_FragCoord.z) * SLOPE, 1.0));
+	out_color = vec4(normal * 0.5 + 0.5, 1.0);
+#else
+	// blended additively, saturates after about ten layers
+	out_color = vec4(0.1, 0.04, 0.02, 1.0);
+#endif
+}
@@ -0,0 +1,69 @@
+#version 450
+
+struct Decal {
+	mat4 transform;
+	vec4 atlas;
+	vec4 color;
+	float normal_threshold;
+};
+
+layout(location = 0) in vec2 uv;
+layout(location = 0) out vec4 out_color;
+
+layout(set = 1, binding = 0) uniform sampler2D depth;
+layout(set = 1, binding = 1) uniform sampler2D atlas;
+layout(std430, set = 1, binding = 2) readonly buffer Decals {
+	Decal decals[];
+};
+
+layout(push_constant) uniform Push {
+	mat4 inverse_view_projection;
+	vec4 camera;
+	uint decal_count;
+} push;
+
+void main() {
+	float z = texture(depth, uv).r;
+	vec4 world = push.inverse_view_projection * vec4(uv * 2.0 - 1.0, z, 1.0);
+	vec3 position = world.xyz / world.w;
+
+	// derivatives before any discard, facing the camera
+	vec3 normal = normalize(cross(dFdx(position), dFdy(position)));
+	if (dot(normal, push.camera.xyz - position) < 0.0) {
+		normal = -normal;
+	}
+
+	// nothing was drawn
+	if (z >= 1.0) {
+		discard;
+	}
+
+	vec4 color = vec4(0.0);
+	for (uint i = 0; i < push.decal_count; i++) {
+		Decal decal = decals[i];
+
+		vec3 local = (decal.transform * vec4(position, 1.0)).xyz;
+		if (any(greaterThan(abs(local), vec3(1.0)))) {
+			continue;
+		}
+
+		// the gradient of the decal space z is the projection axis in world space
+		vec3 axis = normalize(vec3(decal.transform[0][2], decal.transform[1][2], decal.transform[2][2]));
+		if (dot(normal, axis) < decal.normal_threshold) {
+			continue;
+		}
+
+		vec2 decal_uv = decal.atlas.xy + (local.xy * 0.5 + 0.5) * decal.atlas.zw;
+		// no implicit derivatives in non uniform control flow
+		vec4 texel = textureLod(atlas, decal_uv, 0.0) * decal.color;
+
+		// premultiplied, later decals over earlier ones
+		color.rgb = texel.rgb * texel.a + color.rgb * (1.0 - texel.a);
+		color.a = texel.a + color.a * (1.0 - texel.a);
+	}
+
+	if (color.a == 0.0) {
+		discard;
+	}
+	out_color = vec4(color.rgb / color.a, color.a);
+}
@@ -0,0 +1,55 @@
+#version 450
+
+struct PointLight {
+	vec4 position;
+	vec4 color;
+};
+
+layout(location = 0) in vec2 uv;
+layout(location = 0) out vec4 out_color;
+
+layout(set = 1, binding = 0) uniform sampler2D g_albedo;
+layout(set = 1, binding = 1) uniform sampler2D g_normal;
+layout(set = 1, binding = 2) uniform sampler2D g_position;
+layout(std430, set = 1, binding = 3) readonly buffer Lights {
+	PointLight lights[];
+};
+
+layout(push_constant) uniform Push {
+	vec4 camera;
+	vec4 ambient;
+	uint light_count;
+} push;
+
+void main() {
+	vec4 albedo = texture(g_albedo, uv);
+	if (albedo.a == 0.0) {
+		discard;
+	}
+
+	vec3 normal = normalize(texture(g_normal, uv).xyz);
+	vec3 position = texture(g_position, uv).xyz;
+	vec3 view = normalize(push.camera.xyz - position);
+
+	vec3 color = albedo.rgb * push.ambient.rgb;
+	for (uint i = 0; i < push.light_count; i++) {
+		PointLight light = lights[i];
+
+		vec3 to_light = light.position.xyz - position;
+		float dist = length(to_light);
+		if (dist > light.position.w) {
+			continue;
+		}
+		to_light /= dist;
+
+		float attenuation = 1.0 - dist / light.position.w;
+		attenuation *= attenuation;
+
+		float diffuse = max(dot(normal, to_light), 0.0);
+		float specular = pow(max(dot(normal, normalize(to_light + view)), 0.0), 32.0);
+
+		color += (albedo.rgb * diffuse + specular) * light.color.rgb * light.color.w * attenuation;
+	}
+
+	out_color = vec4(color, 1.0);
+}
@@ -0,0 +1,30 @@
+#version 450
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(set = 1, binding = 0) uniform sampler2D src;
+layout(rgba16f, set = 1, binding = 1) writeonly uniform image2D dst;
+
+layout(push_constant) uniform Downsample {
+	// luminance below this is dropped, 0 keeps everything
+	float threshold;
+};
+
+void main() {
+	ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+	ivec2 size = imageSize(dst);
+	if (pixel.x >= size.x || pixel.y >= size.y) return;
+
+	// 4 bilinear taps cover the 4x4 source texels under this pixel
+	vec2 texel = 1.0 / vec2(size);
+	vec2 uv = (vec2(pixel) + 0.5) * texel;
+	vec4 color = 0.25 * (
+		texture(src, uv + texel * vec2(-0.25, -0.25)) +
+		texture(src, uv + texel * vec2( 0.25, -0.25)) +
+		texture(src, uv + texel * vec2(-0.25,  0.25)) +
+		texture(src, uv + texel * vec2( 0.25,  0.25)));
+
+	float luminance = dot(color.rgb, vec3(0.2126, 0.7152, 0.0722));
+	color.rgb *= max(luminance - threshold, 0.0) / max(luminance, 0.0001);
+
+	imageStore(dst, pixel, color);
+}
@@ -0,0 +1,72 @@
+// distance and height fog of a gears::atmosphere::Fog, with #include "fog.glsl"
+//
+// the fogs of the engine UBO from EngineUniforms::bind_to are at set
+// ENGINE_SET and ENGINE_BINDING, 1 and 0 unless defined before the include,
+// pipeline_fog returns the FOG_SLOT of them, 0 by default
+
+#ifndef GEARS_FOG
+#define GEARS_FOG
+
+// must match gears::atmosphere::FOG_SLOTS
+#define GEARS_FOG_SLOTS 8
+
+#ifndef ENGINE_SET
+#define ENGINE_SET 1
+#endif
+
+#ifndef ENGINE_BINDING
+#define ENGINE_BINDING 0
+#endif
+
+#ifndef FOG_SLOT
+#define FOG_SLOT 0
+#endif
+
+struct FogParams {
+	// rgb and the max opacity in a
+	vec4 color;
+	// world space camera position in xyz
+	vec4 camera;
+	// linear start, linear end, density and the mode in w
+	vec4 distance;
+	// base height and height falloff
+	vec4 height;
+};
+
+layout(set = ENGINE_SET, binding = ENGINE_BINDING) uniform Engine {
+	FogParams engine_fogs[GEARS_FOG_SLOTS];
+};
+
+FogParams pipeline_fog() {
+	return engine_fogs[FOG_SLOT];
+}
+
+// 0 is clear and 1 fully fogged, for the world space `position`
+float fog_amount(FogParams params, vec3 position) {
+	vec3 ray = position - params.camera.xyz;
+	float dist = length(ray);
+	float density = params.distance.z;
+
+	float amount = 0.0;
+	int mode = int(params.distance.w);
+	if (mode == 1) {
+		amount = (dist - params.distance.x) / max(params.distance.y - params.distance.x, 0.0001);
+	} else if (mode == 2) {
+		amount = 1.0 - exp(-density * dist);
+	} else if (mode == 3) {
+		// density falling off exponentially above the base height, integrated along the ray
+		float falloff = params.height.y;
+		float camera_height = params.camera.y - params.height.x;
+		float rise = falloff * ray.y;
+		float along = abs(rise) > 0.0001 ? (1.0 - exp(-rise)) / rise : 1.0;
+		amount = 1.0 - exp(-density * exp(-falloff * camera_height) * dist * along);
+	}
+
+	return clamp(amount, 0.0, 1.0) * params.color.a;
+}
+
+vec3 apply_fog(FogParams params, vec3 color, vec3 position) {
+	return mix(color, params.color.rgb, fog_amount(params, position));
+}
+
+#endif
@@ -0,0 +1,87 @@
+// image based lighting helpers, shared by the probe baking shaders and
+// usable from lighting shaders with #include "ibl.glsl"
+
+#ifndef GEARS_IBL
+#define GEARS_IBL
+
+const float IBL_PI = 3.14159265359;
+
+// direction through texel `uv` (0..1) of cube `face` in the
+// +X, -X, +Y, -Y, +Z, -Z layer order
+vec3 ibl_cube_direction(uint face, vec2 uv) {
+	vec2 p = uv * 2.0 - 1.0;
+	vec3 dir;
+	switch (face) {
+	case 0u: dir = vec3(1.0, -p.y, -p.x); break;
+	case 1u: dir = vec3(-1.0, -p.y, p.x); break;
+	case 2u: dir = vec3(p.x, 1.0, p.y); break;
+	case 3u: dir = vec3(p.x, -1.0, -p.y); break;
+	case 4u: dir = vec3(p.x, -p.y, 1.0); break;
+	default: dir = vec3(-p.x, -p.y, -1.0); break;
+	}
+	return normalize(dir);
+}
+
+// equirectangular panorama coordinates of `dir`, +Y at the top
+vec2 ibl_equirect_uv(vec3 dir) {
+	float u = atan(dir.z, dir.x) / (2.0 * IBL_PI) + 0.5;
+	float v = 0.5 - asin(clamp(dir.y, -1.0, 1.0)) / IBL_PI;
+	return vec2(u, v);
+}
+
+vec2 ibl_hammersley(uint i, uint count) {
+	uint bits = i;
+	bits = (bits << 16u) | (bits >> 16u);
+	bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+	bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+	bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+	bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+	return vec2(float(i) / float(count), float(bits) * 2.3283064365386963e-10);
+}
+
+// GGX distributed half vector around `n`
+vec3 ibl_importance_sample_ggx(vec2 xi, vec3 n, float roughness) {
+	float a = roughness * roughness;
+	float phi = 2.0 * IBL_PI * xi.x;
+	float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+	float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+	vec3 h = vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+	vec3 up = abs(n.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+	vec3 tangent = normalize(cross(up, n));
+	vec3 bitangent = cross(n, tangent);
+	return normalize(tangent * h.x + bitangent * h.y + n * h.z);
+}
+
+vec3 ibl_fresnel_roughness(float n_dot_v, vec3 f0, float roughness) {
+	return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(1.0 - n_dot_v, 5.0);
+}
+
+// diffuse and specular ambient light of a metallic roughness material
+// from a baked EnvironmentProbe and BrdfLut, `max_lod` is the specular
+// mip count - 1
+vec3 ibl_ambient(
+	vec3 n,
+	vec3 v,
+	vec3 albedo,
+	float metallic,
+	float roughness,
+	samplerCube irradiance,
+	samplerCube specular,
+	sampler2D brdf_lut,
+	float max_lod
+) {
+	float n_dot_v = max(dot(n, v), 0.0);
+	vec3 f0 = mix(vec3(0.04), albedo, metallic);
+	vec3 f = ibl_fresnel_roughness(n_dot_v, f0, roughness);
+
+	vec3 diffuse = texture(irradiance, n).rgb * albedo * (1.0 - f) * (1.0 - metallic);
+
+	vec3 r = reflect(-v, n);
+	vec3 prefiltered = textureLod(specular, r, roughness * max_lod).rgb;
+	vec2 brdf = texture(brdf_lut, vec2(n_dot_v, roughness)).rg;
+
+	return diffuse + prefiltered * (f0 * brdf.x + brdf.y);
+}
+
+#endif
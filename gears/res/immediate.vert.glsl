@@ -0,0 +1,20 @@
+#version 450
+
+#[gears_bindgen(in)]
+struct ImmediateVertex {
+	vec3 position;
+	vec4 color;
+} vert_in;
+
+layout(location = 0) out vec4 color;
+
+layout(push_constant) uniform Push {
+	mat4 view_projection;
+} push;
+
+void main() {
+	color = vert_in.color;
+	gl_Position = push.view_projection * vec4(vert_in.position, 1.0);
+	// used by point lists only
+	gl_PointSize = 1.0;
+}
@@ -0,0 +1,37 @@
+#version 450
+#include "ibl.glsl"
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(set = 1, binding = 0) uniform sampler2D env;
+layout(rgba16f, set = 1, binding = 1) writeonly uniform image2DArray dst;
+
+layout(push_constant) uniform Irradiance {
+	// angle between hemisphere samples in radians
+	float sample_delta;
+};
+
+void main() {
+	ivec3 texel = ivec3(gl_GlobalInvocationID);
+	ivec2 size = imageSize(dst).xy;
+	if (texel.x >= size.x || texel.y >= size.y) return;
+
+	vec3 n = ibl_cube_direction(uint(texel.z), (vec2(texel.xy) + 0.5) / vec2(size));
+	vec3 up = abs(n.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(0.0, 0.0, 1.0);
+	vec3 right = normalize(cross(up, n));
+	up = cross(n, right);
+
+	// cosine weighted hemisphere integral
+	vec3 sum = vec3(0.0);
+	float count = 0.0;
+	for (float phi = 0.0; phi < 2.0 * IBL_PI; phi += sample_delta) {
+		for (float theta = 0.0; theta < 0.5 * IBL_PI; theta += sample_delta) {
+			vec3 local = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+			vec3 dir = local.x * right + local.y * up + local.z * n;
+			sum += textureLod(env, ibl_equirect_uv(dir), 0.0).rgb * cos(theta) * sin(theta);
+			count += 1.0;
+		}
+	}
+
+	imageStore(dst, texel, vec4(IBL_PI * sum / max(count, 1.0), 1.0));
+}
@@ -0,0 +1,27 @@
+#version 450
+
+layout(location = 0) in vec4 color;
+layout(location = 1) in vec2 local;
+layout(location = 2) flat in float line_length;
+layout(location = 3) flat in float half_width;
+layout(location = 4) flat in float round_cap;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+	// distance outside of the line in pixels, negative inside
+	float outside;
+	if (round_cap > 0.5) {
+		// capsule, overlapping round caps join path segments
+		vec2 nearest = vec2(clamp(local.x, 0.0, line_length), 0.0);
+		outside = length(local - nearest) - half_width;
+	} else {
+		outside = max(abs(local.y) - half_width, max(-local.x, local.x - line_length));
+	}
+
+	float coverage = clamp(0.5 - outside, 0.0, 1.0);
+	if (coverage == 0.0) {
+		discard;
+	}
+	out_color = vec4(color.rgb, color.a * coverage);
+}
@@ -0,0 +1,67 @@
+#version 450
+
+#[gears_bindgen(in)]
+struct LineVertex {
+	vec3 start;
+	vec3 end;
+	vec4 color;
+	// x: 0 at the start and 1 at the end, y: -1 or 1 across the line
+	vec2 corner;
+	float width;
+	float round_cap;
+} vert_in;
+
+layout(location = 0) out vec4 color;
+// pixels along and across the line, from its start
+layout(location = 1) out vec2 local;
+layout(location = 2) flat out float line_length;
+layout(location = 3) flat out float half_width;
+layout(location = 4) flat out float round_cap;
+
+layout(push_constant) uniform Push {
+	mat4 view_projection;
+	vec2 viewport;
+} push;
+
+// anti-aliasing feather in pixels
+const float FEATHER = 1.0;
+const float NEAR_W = 0.0001;
+
+void main() {
+	vec4 a = push.view_projection * vec4(vert_in.start, 1.0);
+	vec4 b = push.view_projection * vec4(vert_in.end, 1.0);
+
+	// clip to the near plane, so the screen space direction is defined
+	if (a.w < NEAR_W && b.w < NEAR_W) {
+		gl_Position = vec4(0.0, 0.0, -1.0, 1.0);
+		return;
+	}
+	if (a.w < NEAR_W) {
+		a = mix(a, b, (NEAR_W - a.w) / (b.w - a.w));
+	} else if (b.w < NEAR_W) {
+		b = mix(b, a, (NEAR_W - b.w) / (a.w - b.w));
+	}
+
+	vec2 screen_a = (a.xy / a.w * 0.5 + 0.5) * push.viewport;
+	vec2 screen_b = (b.xy / b.w * 0.5 + 0.5) * push.viewport;
+	vec2 delta = screen_b - screen_a;
+	float len = length(delta);
+	vec2 dir = len > 0.0 ? delta / len : vec2(1.0, 0.0);
+	vec2 normal = vec2(-dir.y, dir.x);
+
+	// room for the caps and the feather on every side
+	float extent = vert_in.width * 0.5 + FEATHER;
+	float along = vert_in.corner.x * len + (vert_in.corner.x * 2.0 - 1.0) * extent;
+	float across = vert_in.corner.y * extent;
+
+	vec4 clip = vert_in.corner.x < 0.5 ? a : b;
+	vec2 offset = dir * (along - vert_in.corner.x * len) + normal * across;
+	clip.xy += offset / push.viewport * 2.0 * clip.w;
+	gl_Position = clip;
+
+	color = vert_in.color;
+	local = vec2(along, across);
+	line_length = len;
+	half_width = vert_in.width * 0.5;
+	round_cap = vert_in.round_cap;
+}
@@ -0,0 +1,36 @@
+#version 450
+#include "ibl.glsl"
+
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(set = 1, binding = 0) uniform sampler2D env;
+layout(rgba16f, set = 1, binding = 1) writeonly uniform image2DArray dst;
+
+layout(push_constant) uniform Prefilter {
+	float roughness;
+	uint sample_count;
+};
+
+void main() {
+	ivec3 texel = ivec3(gl_GlobalInvocationID);
+	ivec2 size = imageSize(dst).xy;
+	if (texel.x >= size.x || texel.y >= size.y) return;
+
+	// view and reflection along the normal, the usual split sum approximation
+	vec3 n = ibl_cube_direction(uint(texel.z), (vec2(texel.xy) + 0.5) / vec2(size));
+	vec3 v = n;
+
+	vec3 sum = vec3(0.0);
+	float weight = 0.0;
+	for (uint i = 0u; i < sample_count; i++) {
+		vec3 h = ibl_importance_sample_ggx(ibl_hammersley(i, sample_count), n, roughness);
+		vec3 l = normalize(2.0 * dot(v, h) * h - v);
+		float n_dot_l = dot(n, l);
+		if (n_dot_l > 0.0) {
+			sum += textureLod(env, ibl_equirect_uv(l), 0.0).rgb * n_dot_l;
+			weight += n_dot_l;
+		}
+	}
+
+	imageStore(dst, texel, vec4(sum / max(weight, 0.0001), 1.0));
+}
@@ -0,0 +1,42 @@
+#version 450
+
+layout(location = 0) in vec2 uv;
+layout(location = 0) out vec4 out_color;
+
+layout(set = 1, binding = 0) uniform sampler2D scene;
+#if LUT
+layout(set = 1, binding = 1) uniform sampler3D lut;
+#endif
+
+layout(push_constant) uniform Push {
+	// the rendered part of the target with dynamic resolution
+	vec2 scale;
+	float lut_strength;
+	// the target decodes sRGB when sampled, LUTs are authored for encoded colors
+	uint srgb;
+} push;
+
+vec3 to_srgb(vec3 color) {
+	return mix(color * 12.92, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, color));
+}
+
+vec3 to_linear(vec3 color) {
+	return mix(color / 12.92, pow((color + 0.055) / 1.055, vec3(2.4)), step(0.04045, color));
+}
+
+void main() {
+	// keeps linear filtering from bleeding in texels outside the rendered part
+	vec2 half_texel = 0.5 / vec2(textureSize(scene, 0));
+	vec4 color = texture(scene, min(uv * push.scale, push.scale - half_texel));
+
+#if LUT
+	vec3 encoded = clamp(push.srgb != 0 ? to_srgb(color.rgb) : color.rgb, 0.0, 1.0);
+	// texel centers of the first and last entries
+	vec3 size = vec3(textureSize(lut, 0));
+	vec3 graded = texture(lut, encoded * (size - 1.0) / size + 0.5 / size).rgb;
+	graded = push.srgb != 0 ? to_linear(graded) : graded;
+	color.rgb = mix(color.rgb, graded, push.lut_strength);
+#endif
+
+	out_color = color;
+}
@@ -0,0 +1,100 @@
+#version 450
+
+layout(location = 0) in vec2 uv;
+layout(location = 0) out vec4 out_color;
+
+layout(push_constant) uniform Sky {
+	mat4 inverse_view_projection;
+	// direction towards the sun in xyz and its intensity in w
+	vec4 sun;
+	// rayleigh scale, mie scale, mie anisotropy and exposure
+	vec4 params;
+} push;
+
+const float PI = 3.14159265359;
+const float GROUND = 6360e3;
+const float ATMOSPHERE = 6420e3;
+const vec3 RAYLEIGH = vec3(5.8e-6, 13.5e-6, 33.1e-6);
+const float MIE = 21e-6;
+const float RAYLEIGH_HEIGHT = 7994.0;
+const float MIE_HEIGHT = 1200.0;
+const int VIEW_SAMPLES = 16;
+const int LIGHT_SAMPLES = 8;
+
+// distances to the entry and exit of a sphere around the planet center
+vec2 ray_sphere(vec3 origin, vec3 dir, float radius) {
+	float b = dot(origin, dir);
+	float c = dot(origin, origin) - radius * radius;
+	float d = b * b - c;
+	if (d < 0.0) {
+		return vec2(1.0, -1.0);
+	}
+	d = sqrt(d);
+	return vec2(-b - d, -b + d);
+}
+
+void main() {
+	vec4 near = push.inverse_view_projection * vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+	vec4 far = push.inverse_view_projection * vec4(uv * 2.0 - 1.0, 1.0, 1.0);
+	vec3 dir = normalize(far.xyz / far.w - near.xyz / near.w);
+	vec3 sun = normalize(push.sun.xyz);
+
+	vec3 rayleigh = RAYLEIGH * push.params.x;
+	float mie = MIE * push.params.y;
+	float g = push.params.z;
+
+	// single scattering seen from just above the ground
+	vec3 origin = vec3(0.0, GROUND + 1.0, 0.0);
+	float length_view = ray_sphere(origin, dir, ATMOSPHERE).y;
+	vec2 ground = ray_sphere(origin, dir, GROUND);
+	if (ground.x > 0.0) {
+		length_view = min(length_view, ground.x);
+	}
+
+	float step_view = length_view / float(VIEW_SAMPLES);
+	vec2 depth_view = vec2(0.0);
+	vec3 sum_rayleigh = vec3(0.0);
+	vec3 sum_mie = vec3(0.0);
+	for (int i = 0; i < VIEW_SAMPLES; i++) {
+		vec3 point = origin + dir * (float(i) + 0.5) * step_view;
+		float height = length(point) - GROUND;
+		vec2 density = exp(-height / vec2(RAYLEIGH_HEIGHT, MIE_HEIGHT)) * step_view;
+		depth_view += density;
+
+		float length_light = ray_sphere(point, sun, ATMOSPHERE).y;
+		float step_light = length_light / float(LIGHT_SAMPLES);
+		vec2 depth_light = vec2(0.0);
+		bool shadowed = false;
+		for (int j = 0; j < LIGHT_SAMPLES; j++) {
+			vec3 light_point = point + sun * (float(j) + 0.5) * step_light;
+			float light_height = length(light_point) - GROUND;
+			if (light_height < 0.0) {
+				shadowed = true;
+				break;
+			}
+			depth_light += exp(-light_height / vec2(RAYLEIGH_HEIGHT, MIE_HEIGHT)) * step_light;
+		}
+		if (shadowed) {
+			continue;
+		}
+
+		vec2 depth = depth_view + depth_light;
+		vec3 attenuation = exp(-(rayleigh * depth.x + 1.1 * mie * depth.y));
+		sum_rayleigh += density.x * attenuation;
+		sum_mie += density.y * attenuation;
+	}
+
+	float mu = dot(dir, sun);
+	float phase_rayleigh = 3.0 / (16.0 * PI) * (1.0 + mu * mu);
+	float phase_mie = 3.0 / (8.0 * PI) * ((1.0 - g * g) * (1.0 + mu * mu))
+		/ ((2.0 + g * g) * pow(1.0 + g * g - 2.0 * g * mu, 1.5));
+
+	vec3 color = (sum_rayleigh * rayleigh * phase_rayleigh + sum_mie * mie * phase_mie) * push.sun.w;
+
+	// the sun disk, unless the ground is in front of it
+	if (ground.x <= 0.0 && mu > 0.9998) {
+		color += push.sun.w * exp(-(rayleigh * depth_view.x + 1.1 * mie * depth_view.y));
+	}
+
+	out_color = vec4(vec3(1.0) - exp(-color * push.params.w), 1.0);
+}
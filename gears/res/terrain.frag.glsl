@@ -0,0 +1,33 @@
+#version 450
+
+layout(location = 0) in vec3 normal;
+layout(location = 1) in vec2 uv;
+
+layout(location = 0) out vec4 out_color;
+
+layout(set = 1, binding = 0) uniform sampler2D splat;
+layout(set = 1, binding = 1) uniform sampler2D layer_0;
+layout(set = 1, binding = 2) uniform sampler2D layer_1;
+layout(set = 1, binding = 3) uniform sampler2D layer_2;
+layout(set = 1, binding = 4) uniform sampler2D layer_3;
+
+layout(push_constant) uniform Push {
+	mat4 view_projection;
+	vec4 light;
+	float layer_scale;
+} push;
+
+void main() {
+	// rgba weights of the four layers
+	vec4 weights = texture(splat, uv);
+	weights /= max(dot(weights, vec4(1.0)), 0.0001);
+
+	vec2 layer_uv = uv * push.layer_scale;
+	vec3 albedo = texture(layer_0, layer_uv).rgb * weights.r
+		+ texture(layer_1, layer_uv).rgb * weights.g
+		+ texture(layer_2, layer_uv).rgb * weights.b
+		+ texture(layer_3, layer_uv).rgb * weights.a;
+
+	float diffuse = max(dot(normalize(normal), push.light.xyz), 0.0);
+	out_color = vec4(albedo * (diffuse + push.light.w), 1.0);
+}
@@ -0,0 +1,23 @@
+#version 450
+
+#[gears_bindgen(in)]
+struct TerrainVertex {
+	vec3 position;
+	vec3 normal;
+	vec2 uv;
+} vert_in;
+
+layout(location = 0) out vec3 normal;
+layout(location = 1) out vec2 uv;
+
+layout(push_constant) uniform Push {
+	mat4 view_projection;
+	vec4 light;
+	float layer_scale;
+} push;
+
+void main() {
+	normal = vert_in.normal;
+	uv = vert_in.uv;
+	gl_Position = push.view_projection * vec4(vert_in.position, 1.0);
+}
@@ -0,0 +1,38 @@
+#version 450
+
+layout(location = 0) in vec2 uv;
+layout(location = 1) in vec4 color;
+layout(location = 2) in vec4 border_color;
+layout(location = 3) in vec2 local;
+layout(location = 4) flat in vec2 half_size;
+layout(location = 5) flat in float radius;
+layout(location = 6) flat in float border;
+layout(location = 7) flat in float textured;
+
+layout(location = 0) out vec4 out_color;
+
+layout(set = 1, binding = 0) uniform sampler2D atlas;
+
+// distance outside of a rounded box in pixels, negative inside
+float rounded_box(vec2 p, vec2 half_extent, float r) {
+	vec2 q = abs(p) - half_extent + r;
+	return length(max(q, 0.0)) + min(max(q.x, q.y), 0.0) - r;
+}
+
+void main() {
+	float outside = rounded_box(local, half_size, radius);
+	float coverage = clamp(0.5 - outside, 0.0, 1.0);
+	if (coverage == 0.0) {
+		discard;
+	}
+
+	vec4 fill = color;
+	if (textured > 0.5) {
+		fill *= texture(atlas, uv);
+	}
+	// the border is the band within `border` pixels of the edge
+	float inside_border = clamp(0.5 - (outside + border), 0.0, 1.0);
+	vec4 shaded = border > 0.0 ? mix(border_color, fill, inside_border) : fill;
+
+	out_color = vec4(shaded.rgb, shaded.a * coverage);
+}
@@ -0,0 +1,43 @@
+#version 450
+
+#[gears_bindgen(in)]
+struct UiVertex {
+	// pixels from the top left
+	vec2 position;
+	vec2 uv;
+	vec4 color;
+	vec4 border_color;
+	// pixels from the center of the shape
+	vec2 local;
+	vec2 half_size;
+	float radius;
+	float border;
+	float textured;
+} vert_in;
+
+layout(location = 0) out vec2 uv;
+layout(location = 1) out vec4 color;
+layout(location = 2) out vec4 border_color;
+layout(location = 3) out vec2 local;
+layout(location = 4) flat out vec2 half_size;
+layout(location = 5) flat out float radius;
+layout(location = 6) flat out float border;
+layout(location = 7) flat out float textured;
+
+layout(push_constant) uniform Push {
+	vec2 viewport;
+} push;
+
+void main() {
+	uv = vert_in.uv;
+	color = vert_in.color;
+	border_color = vert_in.border_color;
+	local = vert_in.local;
+	half_size = vert_in.half_size;
+	radius = vert_in.radius;
+	border = vert_in.border;
+	textured = vert_in.textured;
+
+	// clip space y points down like the pixels, the near plane draws over the scene
+	gl_Position = vec4(vert_in.position / push.viewport * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -0,0 +1,31 @@
+#version 450
+layout(local_size_x = 8, local_size_y = 8) in;
+
+layout(set = 1, binding = 0) uniform sampler2D src;
+layout(rgba16f, set = 1, binding = 1) uniform image2D dst;
+
+layout(push_constant) uniform Upsample {
+	float intensity;
+};
+
+void main() {
+	ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+	ivec2 size = imageSize(dst);
+	if (pixel.x >= size.x || pixel.y >= size.y) return;
+
+	// 3x3 tent filter over the smaller level
+	vec2 texel = 1.0 / vec2(textureSize(src, 0));
+	vec2 uv = (vec2(pixel) + 0.5) / vec2(size);
+	vec4 color =
+		texture(src, uv + texel * vec2(-1.0, -1.0)) * 1.0 +
+		texture(src, uv + texel * vec2( 0.0, -1.0)) * 2.0 +
+		texture(src, uv + texel * vec2( 1.0, -1.0)) * 1.0 +
+		texture(src, uv + texel * vec2(-1.0,  0.0)) * 2.0 +
+		texture(src, uv)                            * 4.0 +
+		texture(src, uv + texel * vec2( 1.0,  0.0)) * 2.0 +
+		texture(src, uv + texel * vec2(-1.0,  1.0)) * 1.0 +
+		texture(src, uv + texel * vec2( 0.0,  1.0)) * 2.0 +
+		texture(src, uv + texel * vec2( 1.0,  1.0)) * 1.0;
+
+	imageStore(dst, pixel, imageLoad(dst, pixel) + color * (intensity / 16.0));
+}
@@ -0,0 +1,439 @@
+use cgmath::{InnerSpace, Point3, Quaternion, Vector2, Vector3, Vector4};
+use std::{f32::consts::PI, time::Duration};
+
+/// How a value moves from one keyframe to the next, `t` goes from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Easing {
+    Linear,
+    /// Holds the value until the next keyframe.
+    Step,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    SineInOut,
+    /// Overshoots the end a little and settles back.
+    BackOut,
+    /// Springs past the end and oscillates into it.
+    ElasticOut,
+    BounceOut,
+}
+
+/// What a `Curve` does past its last keyframe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Wrap {
+    /// Holds the first or last value.
+    Clamp,
+    /// Starts over.
+    Loop,
+    /// Plays backwards, then forwards again.
+    PingPong,
+}
+
+/// Values a `Curve` can animate.
+pub trait Lerp: Clone {
+    /// `self` at `t` = 0, `other` at `t` = 1, easings can go past both.
+    fn lerp(&self, other: &Self, t: f32) -> Self;
+}
+
+/// A value at a time in seconds, eased towards the next keyframe.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Keyframe<T> {
+    pub time: f32,
+    pub value: T,
+    pub easing: Easing,
+}
+
+/// Keyframes sampled at any time, for camera moves and simple object animation.
+///
+/// ```ignore
+/// let bob = Curve::new()
+///     .with_key(0.0, 0.0, Easing::SineInOut)
+///     .with_key(1.0, 0.5, Easing::SineInOut)
+///     .with_wrap(Wrap::PingPong);
+/// let height = bob.sample(info.elapsed.as_secs_f32());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Curve<T> {
+    // sorted by time
+    keys: Vec<Keyframe<T>>,
+    wrap: Wrap,
+}
+
+/// Plays a `Curve` in steps of the fixed update loop.
+///
+/// ```ignore
+/// let mut fade = Tween::new(0.0, 1.0, 0.25, Easing::QuadOut);
+///
+/// // in UpdateLoopTarget::update
+/// menu.opacity = fade.update(delta_time);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tween<T> {
+    curve: Curve<T>,
+    time: f32,
+    speed: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TweenId(u64);
+
+/// Tweens that write their value to a property every update, for UI
+/// transitions and one off animations that should not need a field each.
+///
+/// ```ignore
+/// let position = self.door.position.clone();
+/// self.tweens.add(
+///     Tween::new(closed, open, 0.5, Easing::CubicInOut),
+///     move |value| *position.lock() = value,
+/// );
+///
+/// // in UpdateLoopTarget::update
+/// self.tweens.update(delta_time);
+/// ```
+///
+/// Finished tweens are removed after writing their last value.
+#[derive(Default)]
+pub struct Tweens {
+    next_id: u64,
+    tweens: Vec<(TweenId, TweenFn)>,
+}
+
+// writes the next value, false once finished
+type TweenFn = Box<dyn FnMut(&Duration) -> bool + Send>;
+
+impl Easing {
+    /// Eased `t`, which is clamped to 0..1.
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.max(0.0).min(1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::Step => {
+                if t < 1.0 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+            Easing::QuadIn => t * t,
+            Easing::QuadOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::QuadInOut => {
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(2) / 2.0
+                }
+            }
+            Easing::CubicIn => t * t * t,
+            Easing::CubicOut => 1.0 - (1.0 - t).powi(3),
+            Easing::CubicInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+            Easing::SineInOut => -((PI * t).cos() - 1.0) / 2.0,
+            Easing::BackOut => {
+                const C1: f32 = 1.70158;
+                const C3: f32 = C1 + 1.0;
+                1.0 + C3 * (t - 1.0).powi(3) + C1 * (t - 1.0).powi(2)
+            }
+            Easing::ElasticOut => {
+                if t == 0.0 || t == 1.0 {
+                    t
+                } else {
+                    2.0_f32.powf(-10.0 * t) * ((t * 10.0 - 0.75) * (2.0 * PI / 3.0)).sin() + 1.0
+                }
+            }
+            Easing::BounceOut => {
+                const N1: f32 = 7.5625;
+                const D1: f32 = 2.75;
+                if t < 1.0 / D1 {
+                    N1 * t * t
+                } else if t < 2.0 / D1 {
+                    let t = t - 1.5 / D1;
+                    N1 * t * t + 0.75
+                } else if t < 2.5 / D1 {
+                    let t = t - 2.25 / D1;
+                    N1 * t * t + 0.9375
+                } else {
+                    let t = t - 2.625 / D1;
+                    N1 * t * t + 0.984375
+                }
+            }
+        }
+    }
+}
+
+impl Default for Easing {
+    fn default() -> Self {
+        Easing::Linear
+    }
+}
+
+impl Default for Wrap {
+    fn default() -> Self {
+        Wrap::Clamp
+    }
+}
+
+impl Lerp for f32 {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for Vector2<f32> {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        *self + (*other - *self) * t
+    }
+}
+
+impl Lerp for Vector3<f32> {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        *self + (*other - *self) * t
+    }
+}
+
+impl Lerp for Vector4<f32> {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        *self + (*other - *self) * t
+    }
+}
+
+impl Lerp for Point3<f32> {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        *self + (*other - *self) * t
+    }
+}
+
+impl Lerp for Quaternion<f32> {
+    /// Normalized lerp along the shorter arc.
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        let other = if self.dot(*other) < 0.0 {
+            -*other
+        } else {
+            *other
+        };
+        (*self + (other - *self) * t).normalize()
+    }
+}
+
+impl<T> Curve<T> {
+    pub fn new() -> Self {
+        Self {
+            keys: Vec::new(),
+            wrap: Wrap::default(),
+        }
+    }
+
+    /// `easing` is used from this keyframe to the next.
+    pub fn with_key(mut self, time: f32, value: T, easing: Easing) -> Self {
+        self.insert(time, value, easing);
+        self
+    }
+
+    pub fn with_wrap(mut self, wrap: Wrap) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    /// Replaces a keyframe at the same time.
+    pub fn insert(&mut self, time: f32, value: T, easing: Easing) {
+        let key = Keyframe {
+            time,
+            value,
+            easing,
+        };
+        match self.keys.iter().position(|key| key.time >= time) {
+            Some(i) if self.keys[i].time == time => self.keys[i] = key,
+            Some(i) => self.keys.insert(i, key),
+            None => self.keys.push(key),
+        }
+    }
+
+    pub fn keys(&self) -> &[Keyframe<T>] {
+        &self.keys
+    }
+
+    pub fn wrap(&self) -> Wrap {
+        self.wrap
+    }
+
+    /// Time of the last keyframe in seconds.
+    pub fn duration(&self) -> f32 {
+        self.keys.last().map_or(0.0, |key| key.time)
+    }
+}
+
+impl<T: Lerp> Curve<T> {
+    /// The value at `time` seconds, `None` without keyframes.
+    pub fn sample(&self, time: f32) -> Option<T> {
+        let first = self.keys.first()?;
+        let time = self.wrap_time(time);
+
+        let next = match self.keys.iter().position(|key| key.time > time) {
+            Some(0) => return Some(first.value.clone()),
+            Some(next) => next,
+            None => return self.keys.last().map(|key| key.value.clone()),
+        };
+
+        let (from, to) = (&self.keys[next - 1], &self.keys[next]);
+        let t = (time - from.time) / (to.time - from.time);
+        Some(from.value.lerp(&to.value, from.easing.apply(t)))
+    }
+
+    fn wrap_time(&self, time: f32) -> f32 {
+        let start = self.keys.first().map_or(0.0, |key| key.time);
+        let length = self.duration() - start;
+        if length <= 0.0 {
+            return time;
+        }
+        match self.wrap {
+            Wrap::Clamp => time,
+            Wrap::Loop => start + (time - start).rem_euclid(length),
+            Wrap::PingPong => {
+                let t = (time - start).rem_euclid(2.0 * length);
+                start + if t > length { 2.0 * length - t } else { t }
+            }
+        }
+    }
+}
+
+impl<T> Default for Curve<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Lerp> Tween<T> {
+    /// From `from` to `to` in `seconds`.
+    pub fn new(from: T, to: T, seconds: f32, easing: Easing) -> Self {
+        Self::from_curve(Curve::new().with_key(0.0, from, easing).with_key(
+            seconds.max(0.0),
+            to,
+            Easing::Linear,
+        ))
+    }
+
+    /// Plays `curve` from its start, looping ones never finish.
+    pub fn from_curve(curve: Curve<T>) -> Self {
+        let time = curve.keys.first().map_or(0.0, |key| key.time);
+        Self {
+            curve,
+            time,
+            speed: 1.0,
+        }
+    }
+
+    /// Advances by `delta_time` times the speed and returns the new value.
+    ///
+    /// Takes the fixed update loop's `delta_time` directly.
+    pub fn update(&mut self, delta_time: &Duration) -> T {
+        self.time += delta_time.as_secs_f32() * self.speed;
+        if self.curve.wrap == Wrap::Clamp {
+            let start = self.curve.keys.first().map_or(0.0, |key| key.time);
+            self.time = self.time.max(start).min(self.curve.duration());
+        }
+        self.value()
+    }
+
+    pub fn value(&self) -> T {
+        self.curve.sample(self.time).expect("tweens have keyframes")
+    }
+
+    /// True once a clamped curve reached its end in the direction it plays.
+    pub fn finished(&self) -> bool {
+        if self.curve.wrap != Wrap::Clamp {
+            return false;
+        }
+        if self.speed < 0.0 {
+            self.curve
+                .keys
+                .first()
+                .map_or(true, |key| self.time <= key.time)
+        } else {
+            self.time >= self.curve.duration()
+        }
+    }
+
+    /// 1.0 by default, negative plays backwards, for ex. to close what opened.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Seconds since the start of the curve.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn seek(&mut self, time: f32) {
+        self.time = time;
+    }
+
+    pub fn curve(&self) -> &Curve<T> {
+        &self.curve
+    }
+}
+
+impl Tweens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Plays `tween`, `apply` gets its value every `update`.
+    pub fn add<T, F>(&mut self, mut tween: Tween<T>, mut apply: F) -> TweenId
+    where
+        T: Lerp + Send + 'static,
+        F: FnMut(T) + Send + 'static,
+    {
+        let id = TweenId(self.next_id);
+        self.next_id += 1;
+
+        self.tweens.push((
+            id,
+            Box::new(move |delta_time| {
+                apply(tween.update(delta_time));
+                !tween.finished()
+            }),
+        ));
+        id
+    }
+
+    /// Stops a tween where it is, does nothing if it already finished.
+    pub fn cancel(&mut self, id: TweenId) {
+        self.tweens.retain(|(tween, _)| *tween != id);
+    }
+
+    /// Advances every tween and writes their values, call once per fixed update.
+    pub fn update(&mut self, delta_time: &Duration) {
+        let mut i = 0;
+        while i < self.tweens.len() {
+            if (self.tweens[i].1)(delta_time) {
+                i += 1;
+            } else {
+                drop(self.tweens.remove(i));
+            }
+        }
+    }
+
+    /// Number of tweens still playing.
+    pub fn len(&self) -> usize {
+        self.tweens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tweens.is_empty()
+    }
+}
@@ -0,0 +1,538 @@
+use parking_lot::Mutex;
+use std::{collections::HashMap, marker::PhantomData, mem, ops::Deref, sync::Arc};
+
+use crate::{
+    loops::task::TaskPool,
+    renderer::{
+        buffer::{
+            index::{IndexBuffer, UInt},
+            texture::{Texture2D, Texture3D},
+            vertex::VertexBuffer,
+            Buffer, BufferError,
+        },
+        RenderRecordInfo, Renderer, UpdateRecordInfo,
+    },
+};
+
+/// GPU resources `Assets` can hold.
+pub trait Asset: Send + Sync + 'static {
+    /// Memory counted against the `Assets` budget, in bytes.
+    fn memory_size(&self) -> u64;
+
+    /// Records pending uploads, returns true if anything was recorded.
+    unsafe fn update(&self, _uri: &UpdateRecordInfo) -> bool {
+        false
+    }
+}
+
+/// Indexed geometry, the usual `Assets` entry next to textures.
+pub struct Mesh<V, I: UInt = u32> {
+    pub vertices: VertexBuffer<V>,
+    pub indices: IndexBuffer<I>,
+}
+
+/// A strong reference to an asset, it is not evicted while one exists.
+pub struct Handle<T> {
+    id: u64,
+    asset: Arc<T>,
+}
+
+/// A reference that does not keep the asset loaded, `Assets::upgrade` it to use it.
+pub struct WeakHandle<T> {
+    id: u64,
+    _p: PhantomData<fn() -> T>,
+}
+
+/// Reference counted registry of GPU assets with least recently used eviction.
+///
+/// ```ignore
+/// let textures = Assets::<Texture2D>::new(&renderer, 256 * 1024 * 1024);
+/// let grass = textures.get_or_insert_with("grass.png", || load(&renderer, "grass.png"))?;
+/// ```
+///
+/// Named assets stay cached after their last `Handle` is dropped and are
+/// only evicted, least recently used first, once the cache goes over the
+/// budget. Unnamed assets are evicted as soon as they are unreferenced.
+/// Assets in use can go over the budget, they are never evicted.
+///
+/// `maintain` has to run once per frame, evicted assets are dropped after
+/// the frames in flight are done with them. Recordings drawing an asset
+/// have to keep a `Handle` to it until they are rerecorded.
+pub struct Assets<T> {
+    frames_in_flight: usize,
+    state: Mutex<AssetState<T>>,
+}
+
+struct AssetState<T> {
+    entries: HashMap<u64, Entry<T>>,
+    names: HashMap<String, u64>,
+    next_id: u64,
+
+    frame: u64,
+    budget: u64,
+    used: u64,
+
+    retired: Vec<(usize, Arc<T>)>,
+}
+
+struct Entry<T> {
+    asset: Arc<T>,
+    name: Option<String>,
+    size: u64,
+    // frame of the last use
+    last_used: u64,
+}
+
+impl Asset for Texture2D {
+    fn memory_size(&self) -> u64 {
+        // the image and its staging copy
+        2 * self.width() as u64 * self.height() as u64 * 4
+    }
+
+    unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        Texture2D::update(self, uri)
+    }
+}
+
+impl Asset for Texture3D {
+    fn memory_size(&self) -> u64 {
+        2 * self.width() as u64 * self.height() as u64 * self.depth() as u64 * 4
+    }
+
+    unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        Texture3D::update(self, uri)
+    }
+}
+
+impl<V: Send + Sync + 'static, I: UInt + Send + Sync + 'static> Asset for Mesh<V, I> {
+    fn memory_size(&self) -> u64 {
+        // device buffers and their staging copies
+        2 * (self.vertices.capacity() * mem::size_of::<V>()
+            + self.indices.capacity() * mem::size_of::<I>()) as u64
+    }
+
+    unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        // both have to run
+        let vertices = self.vertices.update(uri);
+        self.indices.update(uri) || vertices
+    }
+}
+
+impl<V, I: UInt> Mesh<V, I> {
+    pub fn new(renderer: &Renderer, vertices: &[V], indices: &[I]) -> Result<Self, BufferError> {
+        Ok(Self {
+            vertices: VertexBuffer::new_with_data(renderer, vertices)?,
+            indices: IndexBuffer::new_with_data(renderer, indices)?,
+        })
+    }
+
+    pub unsafe fn draw(&self, rri: &RenderRecordInfo) {
+        self.indices.draw(rri, &self.vertices);
+    }
+}
+
+impl<T> Handle<T> {
+    /// Unique in its `Assets`.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn downgrade(&self) -> WeakHandle<T> {
+        WeakHandle {
+            id: self.id,
+            _p: PhantomData::default(),
+        }
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id,
+            asset: self.asset.clone(),
+        }
+    }
+}
+
+impl<T> Deref for Handle<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.asset
+    }
+}
+
+impl<T> WeakHandle<T> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl<T> Clone for WeakHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id,
+            _p: PhantomData::default(),
+        }
+    }
+}
+
+impl<T: Asset> Assets<T> {
+    /// `budget` in bytes, see `Asset::memory_size`.
+    pub fn new(renderer: &Renderer, budget: u64) -> Self {
+        Self::new_with_frames(renderer.frames_in_flight(), budget)
+    }
+
+    fn new_with_frames(frames_in_flight: usize, budget: u64) -> Self {
+        Self {
+            frames_in_flight,
+            state: Mutex::new(AssetState {
+                entries: HashMap::new(),
+                names: HashMap::new(),
+                next_id: 0,
+
+                frame: 0,
+                budget,
+                used: 0,
+
+                retired: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn insert(&self, asset: T) -> Handle<T> {
+        self.insert_entry(None, asset)
+    }
+
+    /// Cached under `name` after its last `Handle` is dropped, replaces an
+    /// earlier asset with the same name for later lookups.
+    pub fn insert_named<S: Into<String>>(&self, name: S, asset: T) -> Handle<T> {
+        self.insert_entry(Some(name.into()), asset)
+    }
+
+    /// The asset named `name` if it is still loaded.
+    pub fn get(&self, name: &str) -> Option<Handle<T>> {
+        let mut state = self.state.lock();
+        let id = *state.names.get(name)?;
+        state.handle(id)
+    }
+
+    /// `get`, or `insert_named` what `load` returns.
+    pub fn get_or_insert_with<E, F: FnOnce() -> Result<T, E>>(
+        &self,
+        name: &str,
+        load: F,
+    ) -> Result<Handle<T>, E> {
+        match self.get(name) {
+            Some(handle) => Ok(handle),
+            None => Ok(self.insert_named(name, load()?)),
+        }
+    }
+
+    /// `get_or_insert_with` of every name, in the order of `names`.
+    ///
+    /// The missing assets are decoded on the threads of `pool` in parallel,
+    /// `create` then makes the assets from the decoded data on this thread,
+    /// which usually has the `Renderer` the uploads need.
+    pub fn get_or_insert_all<D, E, F, C>(
+        &self,
+        pool: &TaskPool,
+        names: &[&str],
+        decode: F,
+        mut create: C,
+    ) -> Vec<Result<Handle<T>, E>>
+    where
+        D: Send,
+        E: Send,
+        F: Fn(&str) -> Result<D, E> + Send + Sync,
+        C: FnMut(D) -> Result<T, E>,
+    {
+        let loaded = names
+            .iter()
+            .map(|name| (*name, self.get(name)))
+            .collect::<Vec<_>>();
+        let decoded = pool.par_map(&loaded, |(name, handle)| {
+            handle.clone().ok_or_else(|| decode(name))
+        });
+
+        names
+            .iter()
+            .zip(decoded)
+            .map(|(name, decoded)| match decoded {
+                Ok(handle) => Ok(handle),
+                Err(data) => Ok(self.insert_named(*name, create(data?)?)),
+            })
+            .collect()
+    }
+
+    /// `None` if the asset was evicted.
+    pub fn upgrade(&self, weak: &WeakHandle<T>) -> Option<Handle<T>> {
+        self.state.lock().handle(weak.id)
+    }
+
+    /// Records the uploads of every loaded asset, returns true if anything was recorded.
+    pub unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        let state = self.state.lock();
+        let mut updates = false;
+        for entry in state.entries.values() {
+            updates |= entry.asset.update(uri);
+        }
+        updates
+    }
+
+    /// Evicts unreferenced assets and drops the ones no frame in flight uses anymore.
+    ///
+    /// Call once per frame.
+    pub fn maintain(&self) {
+        let mut state = self.state.lock();
+        let state = &mut *state;
+        state.frame += 1;
+
+        for (frames, _) in state.retired.iter_mut() {
+            *frames -= 1;
+        }
+        state.retired.retain(|(frames, _)| *frames > 0);
+
+        let frame = state.frame;
+        let mut unreferenced = Vec::new();
+        for (id, entry) in state.entries.iter_mut() {
+            if Arc::strong_count(&entry.asset) > 1 {
+                entry.last_used = frame;
+            } else if entry.name.is_none() {
+                // nothing can look it up again
+                unreferenced.push((0, *id));
+            } else {
+                unreferenced.push((entry.last_used + 1, *id));
+            }
+        }
+
+        // least recently used first, unnamed ones before everything else
+        unreferenced.sort_unstable();
+        for (last_used, id) in unreferenced {
+            if last_used != 0 && state.used <= state.budget {
+                break;
+            }
+            state.evict(id, self.frames_in_flight);
+        }
+    }
+
+    /// In bytes.
+    pub fn set_budget(&self, budget: u64) {
+        self.state.lock().budget = budget;
+    }
+
+    pub fn budget(&self) -> u64 {
+        self.state.lock().budget
+    }
+
+    /// Memory of the loaded assets in bytes, including referenced ones over the budget.
+    pub fn used(&self) -> u64 {
+        self.state.lock().used
+    }
+
+    /// Number of loaded assets.
+    pub fn len(&self) -> usize {
+        self.state.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().entries.is_empty()
+    }
+
+    fn insert_entry(&self, name: Option<String>, asset: T) -> Handle<T> {
+        let mut state = self.state.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+
+        let size = asset.memory_size();
+        let asset = Arc::new(asset);
+        if let Some(name) = name.as_ref() {
+            if let Some(old) = state.names.insert(name.clone(), id) {
+                // the old asset stays loaded while referenced, but is not found by name anymore
+                if let Some(entry) = state.entries.get_mut(&old) {
+                    entry.name = None;
+                }
+            }
+        }
+
+        let last_used = state.frame;
+        state.used += size;
+        state.entries.insert(
+            id,
+            Entry {
+                asset: asset.clone(),
+                name,
+                size,
+                last_used,
+            },
+        );
+
+        Handle { id, asset }
+    }
+}
+
+impl<T> AssetState<T> {
+    fn handle(&mut self, id: u64) -> Option<Handle<T>> {
+        let frame = self.frame;
+        let entry = self.entries.get_mut(&id)?;
+        entry.last_used = frame;
+        Some(Handle {
+            id,
+            asset: entry.asset.clone(),
+        })
+    }
+
+    fn evict(&mut self, id: u64, frames_in_flight: usize) {
+        if let Some(entry) = self.entries.remove(&id) {
+            if let Some(name) = entry.name {
+                self.names.remove(&name);
+            }
+            self.used -= entry.size;
+            self.retired.push((frames_in_flight, entry.asset));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Dummy {
+        size: u64,
+        drops: Arc<AtomicUsize>,
+    }
+
+    impl Asset for Dummy {
+        fn memory_size(&self) -> u64 {
+            self.size
+        }
+    }
+
+    impl Drop for Dummy {
+        fn drop(&mut self) {
+            self.drops.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn dummy(size: u64) -> Dummy {
+        Dummy {
+            size,
+            drops: Arc::default(),
+        }
+    }
+
+    #[test]
+    fn insert_all_decodes_missing() {
+        let assets = Assets::new_with_frames(1, 100);
+        let a = assets.insert_named("a", dummy(10));
+        let pool = TaskPool::with_threads(2);
+
+        let decodes = AtomicUsize::new(0);
+        let results = assets.get_or_insert_all(
+            &pool,
+            &["a", "b", "bad", "c"],
+            |name| {
+                decodes.fetch_add(1, Ordering::SeqCst);
+                match name {
+                    "bad" => Err(name.to_string()),
+                    _ => Ok(name.len() as u64 * 20),
+                }
+            },
+            |size| Ok(dummy(size)),
+        );
+
+        assert_eq!(decodes.load(Ordering::SeqCst), 3);
+        assert_eq!(results[0].as_ref().unwrap().id(), a.id());
+        assert_eq!(results[1].as_ref().unwrap().size, 20);
+        assert_eq!(results[2].as_ref().err().unwrap(), "bad");
+        assert_eq!(results[3].as_ref().unwrap().size, 20);
+        assert!(loaded(&assets, "b"));
+        assert!(!loaded(&assets, "bad"));
+    }
+
+    // without touching `last_used` like `get` does
+    fn loaded(assets: &Assets<Dummy>, name: &str) -> bool {
+        assets.state.lock().names.contains_key(name)
+    }
+
+    #[test]
+    fn least_recently_used_first() {
+        let assets = Assets::new_with_frames(1, 100);
+        for name in ["a", "b", "c"].iter() {
+            assets.insert_named(*name, dummy(10));
+        }
+
+        assets.maintain();
+        assets.get("a").unwrap();
+        assets.maintain();
+        assets.get("c").unwrap();
+
+        assets.set_budget(25);
+        assets.maintain();
+        assert!(loaded(&assets, "a"));
+        assert!(!loaded(&assets, "b"));
+        assert!(loaded(&assets, "c"));
+        assert_eq!(assets.used(), 20);
+
+        assets.set_budget(15);
+        assets.maintain();
+        assert!(!loaded(&assets, "a"));
+        assert!(loaded(&assets, "c"));
+        assert_eq!(assets.used(), 10);
+    }
+
+    #[test]
+    fn referenced_are_kept_over_budget() {
+        let assets = Assets::new_with_frames(1, 0);
+        let handle = assets.insert_named("a", dummy(10));
+        assets.maintain();
+        assert!(loaded(&assets, "a"));
+        assert_eq!(assets.used(), 10);
+
+        drop(handle);
+        assets.maintain();
+        assert!(!loaded(&assets, "a"));
+        assert_eq!(assets.used(), 0);
+    }
+
+    #[test]
+    fn unnamed_evicted_when_unreferenced() {
+        let assets = Assets::new_with_frames(1, 100);
+        let handle = assets.insert(dummy(10));
+        let weak = handle.downgrade();
+        assets.maintain();
+        assert!(assets.upgrade(&weak).is_some());
+
+        drop(handle);
+        assets.maintain();
+        assert!(assets.upgrade(&weak).is_none());
+        assert!(assets.is_empty());
+    }
+
+    #[test]
+    fn replaced_name_is_evicted_first() {
+        let assets = Assets::new_with_frames(1, 100);
+        assets.insert_named("a", dummy(10));
+        assets.insert_named("a", dummy(10));
+        assets.maintain();
+        assert_eq!(assets.len(), 1);
+        assert!(loaded(&assets, "a"));
+    }
+
+    #[test]
+    fn evicted_dropped_after_frames_in_flight() {
+        let assets = Assets::new_with_frames(2, 0);
+        let asset = dummy(10);
+        let drops = asset.drops.clone();
+        assets.insert(asset);
+
+        assets.maintain();
+        assert!(assets.is_empty());
+        assert_eq!(drops.load(Ordering::SeqCst), 0);
+        assets.maintain();
+        assert_eq!(drops.load(Ordering::SeqCst), 0);
+        assets.maintain();
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+    }
+}
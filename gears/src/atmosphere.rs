@@ -0,0 +1,259 @@
+use ash::vk;
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4};
+
+use crate::{
+    fullscreen::{Pass, PassBuilder},
+    renderer::{
+        buffer::{storage::StorageBuffer, Buffer, BufferError, WriteType},
+        pipeline::GraphicsPipelineBuilder,
+        target::RenderTarget,
+        RenderRecordInfo, Renderer, UpdateRecordInfo,
+    },
+};
+
+mod shader {
+    gears_pipeline::pipeline! {
+        frag: {
+            path: "res/sky.frag.glsl"
+        }
+    }
+}
+
+/// Source of `apply_fog` and `fog_amount`, for ```#include "fog.glsl"``` in own shaders.
+pub const FOG_GLSL: &str = include_str!("../res/fog.glsl");
+
+/// Fog slots of `EngineUniforms`, must match `GEARS_FOG_SLOTS` in `FOG_GLSL`.
+pub const FOG_SLOTS: usize = 8;
+
+/// How fog thickens with distance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FogMode {
+    None,
+    /// Clear before `start` and fully fogged after `end`, in world units.
+    Linear {
+        start: f32,
+        end: f32,
+    },
+    /// `1 - e^(-density * distance)`.
+    Exponential {
+        density: f32,
+    },
+    /// Exponential fog whose density falls off with `falloff` per world unit
+    /// above `base`, thick in valleys and thin on mountains.
+    Height {
+        density: f32,
+        base: f32,
+        falloff: f32,
+    },
+}
+
+/// Fog parameters of one pipeline, one of the `FOG_SLOTS` of the shared
+/// `EngineUniforms`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fog {
+    color: Vector4<f32>,
+    camera: Vector4<f32>,
+    distance: Vector4<f32>,
+    height: Vector4<f32>,
+}
+
+// must match the push constant block in sky.frag.glsl
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SkyPush {
+    inverse_view_projection: Matrix4<f32>,
+    sun: Vector4<f32>,
+    params: Vector4<f32>,
+}
+
+/// Physically based sky from single Rayleigh and Mie scattering of the sun
+/// light in an earth like atmosphere.
+///
+/// `draw` fills every pixel still at the far plane, so it belongs after the
+/// opaque draws of the `DrawScope`. Like push constants in general, the
+/// camera and sun are recorded and need a rerecord to change.
+pub struct Sky {
+    pass: Pass,
+    push: SkyPush,
+}
+
+/// The engine UBO, one device buffer every pipeline binds instead of
+/// keeping its own copy of the per frame engine state.
+///
+/// It holds `FOG_SLOTS` fogs, pipelines pick theirs with `FOG_SLOT` and
+/// bind the buffer with `bind_to`, at set 1 (2 with a texture registry)
+/// and `ENGINE_BINDING` in the shader:
+///
+/// ```glsl
+/// #define FOG_SLOT 1
+/// #include "fog.glsl"
+///
+/// out_color.rgb = apply_fog(pipeline_fog(), out_color.rgb, world_position);
+/// ```
+///
+/// Writes are uploaded by `update` in the `UploadScope`, so the fog changes
+/// without rerecording the pipelines that read it.
+pub struct EngineUniforms {
+    fogs: [Fog; FOG_SLOTS],
+    buffer: StorageBuffer<Fog>,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self::new(FogMode::None, Vector3::new(0.5, 0.6, 0.7))
+    }
+}
+
+impl Fog {
+    pub fn new(mode: FogMode, color: Vector3<f32>) -> Self {
+        let (distance, height) = match mode {
+            FogMode::None => (
+                Vector4::new(0.0, 0.0, 0.0, 0.0),
+                Vector4::new(0.0, 0.0, 0.0, 0.0),
+            ),
+            FogMode::Linear { start, end } => (
+                Vector4::new(start, end, 0.0, 1.0),
+                Vector4::new(0.0, 0.0, 0.0, 0.0),
+            ),
+            FogMode::Exponential { density } => (
+                Vector4::new(0.0, 0.0, density, 2.0),
+                Vector4::new(0.0, 0.0, 0.0, 0.0),
+            ),
+            FogMode::Height {
+                density,
+                base,
+                falloff,
+            } => (
+                Vector4::new(0.0, 0.0, density, 3.0),
+                Vector4::new(base, falloff, 0.0, 0.0),
+            ),
+        };
+
+        Self {
+            color: color.extend(1.0),
+            camera: Vector4::new(0.0, 0.0, 0.0, 1.0),
+            distance,
+            height,
+        }
+    }
+
+    /// Caps the fog so distant geometry stays visible, 1 by default.
+    pub fn with_max_opacity(mut self, opacity: f32) -> Self {
+        self.color.w = opacity.max(0.0).min(1.0);
+        self
+    }
+
+    /// World space camera position the distances are measured from.
+    pub fn set_camera(&mut self, camera: Vector3<f32>) {
+        self.camera = camera.extend(1.0);
+    }
+
+    pub fn set_color(&mut self, color: Vector3<f32>) {
+        self.color = color.extend(self.color.w);
+    }
+
+    pub fn color(&self) -> Vector3<f32> {
+        self.color.truncate()
+    }
+}
+
+impl EngineUniforms {
+    pub fn new(renderer: &Renderer) -> Result<Self, BufferError> {
+        let fogs = [Fog::default(); FOG_SLOTS];
+        let buffer =
+            StorageBuffer::new_with_data(renderer, &fogs, vk::BufferUsageFlags::UNIFORM_BUFFER)?;
+        Ok(Self { fogs, buffer })
+    }
+
+    /// Sets the fog of `slot`, panics if `slot` is not below `FOG_SLOTS`.
+    pub fn set_fog(&mut self, slot: usize, fog: Fog) -> Result<WriteType, BufferError> {
+        self.fogs[slot] = fog;
+        // the stage buffer copies its whole written length
+        self.buffer.write(0, &self.fogs)
+    }
+
+    pub fn fog(&self, slot: usize) -> &Fog {
+        &self.fogs[slot]
+    }
+
+    /// World space camera position the fog distances of every slot are
+    /// measured from.
+    pub fn set_camera(&mut self, camera: Vector3<f32>) -> Result<WriteType, BufferError> {
+        for fog in self.fogs.iter_mut() {
+            fog.set_camera(camera);
+        }
+        self.buffer.write(0, &self.fogs)
+    }
+
+    /// Binds the engine UBO as the next uniform buffer of `builder`.
+    pub fn bind_to<'a>(&self, builder: GraphicsPipelineBuilder<'a>) -> GraphicsPipelineBuilder<'a> {
+        builder.with_uniform_buffer(&self.buffer)
+    }
+}
+
+impl Buffer for EngineUniforms {
+    unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        self.buffer.update(uri)
+    }
+
+    fn get(&self) -> vk::Buffer {
+        self.buffer.get()
+    }
+}
+
+impl Sky {
+    pub fn new(renderer: &Renderer) -> Result<Self, BufferError> {
+        Self::build(Pass::new(renderer, shader::FRAG_SPIRV_WORDS))
+    }
+
+    /// Draws the sky to `target` instead of the swapchain.
+    pub fn new_with_render_target(
+        renderer: &Renderer,
+        target: &RenderTarget,
+    ) -> Result<Self, BufferError> {
+        Self::build(Pass::new(renderer, shader::FRAG_SPIRV_WORDS).with_render_target(target))
+    }
+
+    fn build(pass: PassBuilder) -> Result<Self, BufferError> {
+        Ok(Self {
+            pass: pass.with_push_constants::<SkyPush>().build()?,
+            push: SkyPush {
+                inverse_view_projection: Matrix4::identity(),
+                sun: Vector3::new(0.0, 0.5, -1.0).normalize().extend(20.0),
+                params: Vector4::new(1.0, 1.0, 0.76, 1.0),
+            },
+        })
+    }
+
+    /// The camera the sky is seen from, only its rotation matters.
+    ///
+    /// Skipped if `view_projection` is not invertible.
+    pub fn set_view_projection(&mut self, view_projection: &Matrix4<f32>) {
+        if let Some(inverse) = view_projection.invert() {
+            self.push.inverse_view_projection = inverse;
+        }
+    }
+
+    /// `direction` points towards the sun, below the horizon gives a dusk sky.
+    pub fn set_sun(&mut self, direction: Vector3<f32>, intensity: f32) {
+        self.push.sun = direction.normalize().extend(intensity);
+    }
+
+    /// Scales of the Rayleigh (blue sky) and Mie (haze around the sun)
+    /// scattering, 1 is a clear day.
+    pub fn set_scattering(&mut self, rayleigh: f32, mie: f32) {
+        self.push.params.x = rayleigh;
+        self.push.params.y = mie;
+    }
+
+    /// Tone mapping exposure, 1 by default.
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.push.params.w = exposure;
+    }
+
+    pub unsafe fn draw(&self, rri: &RenderRecordInfo) {
+        self.pass.draw_with(rri, &self.push);
+    }
+}
@@ -0,0 +1,314 @@
+use ash::vk;
+use cgmath::{
+    ortho, EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Vector2, Vector3, Vector4,
+    Zero,
+};
+use std::f32::consts::PI;
+
+use crate::renderer::{
+    buffer::{streaming::StreamingVertexBuffer, BufferError},
+    pipeline::{Pipeline, PipelineBuilder},
+    target::RenderTarget,
+    ImmediateFrameInfo, RenderRecordInfo, Renderer,
+};
+
+mod shader {
+    gears_pipeline::pipeline! {
+        vert: {
+            path: "res/billboard.vert.glsl"
+        }
+        frag: {
+            path: "res/billboard.frag.glsl"
+        }
+    }
+}
+
+/// Which way a `Billboard` turns to face the camera.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Facing {
+    /// Parallel to the screen, for particles and far away objects.
+    Spherical,
+    /// Only turns around the axis, the top of the quad points along it.
+    /// For trees and grass with the world up axis.
+    Axis(Vector3<f32>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Billboard {
+    /// World space center.
+    pub position: Vector3<f32>,
+    /// World space width and height.
+    pub size: Vector2<f32>,
+    /// Atlas uv offset in xy and size in zw, negative sizes mirror it.
+    pub atlas: Vector4<f32>,
+    /// Linear color multiplied with the atlas.
+    pub color: Vector4<f32>,
+    pub facing: Facing,
+}
+
+/// Camera facing textured quads, rebuilt every frame like `Immediate`.
+///
+/// `push` billboards while updating and `draw` them once per frame in a
+/// `DrawScope`, which has to `request_rerecord` every frame. The atlas is
+/// alpha tested at 0.5, so they need no sorting.
+pub struct Billboards {
+    vertices: StreamingVertexBuffer<shader::BillboardVertex>,
+    pipeline: Pipeline,
+
+    pending: Vec<Billboard>,
+}
+
+/// Bakes a mesh seen from `angles` directions around the y axis into
+/// a row of atlas cells, to draw it as an axis locked `Billboard` far away.
+///
+/// Cell `i` is seen from the angle `i * 2π / angles` from the z axis towards
+/// the x axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Impostor {
+    angles: u32,
+    center: Vector3<f32>,
+    radius: f32,
+}
+
+impl Billboard {
+    /// An axis locked billboard over the whole atlas, white.
+    pub fn new(position: Vector3<f32>, size: Vector2<f32>) -> Self {
+        Self {
+            position,
+            size,
+            atlas: Vector4::new(0.0, 0.0, 1.0, 1.0),
+            color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            facing: Facing::Axis(Vector3::unit_y()),
+        }
+    }
+
+    pub fn with_atlas(mut self, atlas: Vector4<f32>) -> Self {
+        self.atlas = atlas;
+        self
+    }
+
+    pub fn with_color(mut self, color: Vector4<f32>) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_facing(mut self, facing: Facing) -> Self {
+        self.facing = facing;
+        self
+    }
+}
+
+impl Billboards {
+    /// `capacity` is the billboard count shared by all frames in flight.
+    ///
+    /// `atlas_view` has to outlive the billboards and be in `SHADER_READ_ONLY_OPTIMAL` when drawn.
+    pub fn new(
+        renderer: &Renderer,
+        capacity: usize,
+        atlas_view: vk::ImageView,
+        atlas_sampler: vk::Sampler,
+    ) -> Result<Self, BufferError> {
+        let pipeline = PipelineBuilder::new(renderer);
+        Self::new_with_pipeline(renderer, pipeline, capacity, atlas_view, atlas_sampler)
+    }
+
+    /// `new` drawing to `target` instead of the swapchain.
+    pub fn new_with_target(
+        renderer: &Renderer,
+        target: &RenderTarget,
+        capacity: usize,
+        atlas_view: vk::ImageView,
+        atlas_sampler: vk::Sampler,
+    ) -> Result<Self, BufferError> {
+        let pipeline = PipelineBuilder::new(renderer).with_render_target(target);
+        Self::new_with_pipeline(renderer, pipeline, capacity, atlas_view, atlas_sampler)
+    }
+
+    fn new_with_pipeline(
+        renderer: &Renderer,
+        pipeline: PipelineBuilder,
+        capacity: usize,
+        atlas_view: vk::ImageView,
+        atlas_sampler: vk::Sampler,
+    ) -> Result<Self, BufferError> {
+        // debug, quads are seen from both sides
+        let pipeline = pipeline
+            .with_graphics_modules(shader::VERT_SPIRV_WORDS, shader::FRAG_SPIRV_WORDS)
+            .with_input::<shader::BillboardVertex>()
+            .with_push_constants::<Matrix4<f32>>(vk::ShaderStageFlags::VERTEX)
+            .with_sampled_image(atlas_view, atlas_sampler)
+            .build(true)?;
+
+        Ok(Self {
+            vertices: StreamingVertexBuffer::new(renderer, capacity * 6)?,
+            pipeline,
+
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn push(&mut self, billboard: Billboard) {
+        self.pending.push(billboard);
+    }
+
+    /// Builds quads facing the camera of `view` and draws them, then clears the billboards.
+    ///
+    /// `TriedToOverflow` if the frames in flight used up the capacity, the billboards are dropped.
+    pub unsafe fn draw(
+        &mut self,
+        rri: &RenderRecordInfo,
+        view: &Matrix4<f32>,
+        projection: &Matrix4<f32>,
+    ) -> Result<(), BufferError> {
+        self.vertices.begin_frame(&ImmediateFrameInfo {
+            image_index: rri.image_index(),
+        });
+
+        // the rows of the view matrix are the camera axes in world space
+        let right = Vector3::new(view.x.x, view.y.x, view.z.x);
+        // clip space y points down the screen
+        let down = Vector3::new(view.x.y, view.y.y, view.z.y);
+        let camera = view
+            .invert()
+            .map_or(Vector3::zero(), |inverse| inverse.w.truncate());
+
+        let mut vertices = Vec::with_capacity(self.pending.len() * 6);
+        for billboard in self.pending.drain(..) {
+            let (right, up) = match billboard.facing {
+                Facing::Spherical => (right, -down),
+                Facing::Axis(axis) => {
+                    // the right of someone looking at it, independent of the view's handedness
+                    let axis = axis.normalize();
+                    let right = axis.cross(camera - billboard.position);
+                    if right.magnitude2() < f32::EPSILON {
+                        // looking along the axis
+                        continue;
+                    }
+                    (right.normalize(), axis)
+                }
+            };
+            quad(&mut vertices, &billboard, right, up);
+        }
+        if vertices.is_empty() {
+            return Ok(());
+        }
+
+        let slice = self.vertices.alloc_frame(&vertices)?;
+        self.pipeline.bind(rri);
+        self.pipeline.push_constants(rri, &(projection * view));
+        slice.bind(rri, 0);
+        self.pipeline.draw_vertices(rri, slice.len() as u32);
+        Ok(())
+    }
+}
+
+impl Impostor {
+    /// `center` and `radius` bound the mesh, anything outside is cut off.
+    pub fn new(angles: u32, center: Vector3<f32>, radius: f32) -> Self {
+        Self {
+            angles: angles.max(1),
+            center,
+            radius,
+        }
+    }
+
+    pub fn angles(&self) -> u32 {
+        self.angles
+    }
+
+    /// Orthographic view projection of cell `index`, for drawing the mesh by hand.
+    pub fn view_projection(&self, index: u32) -> Matrix4<f32> {
+        let angle = index as f32 * 2.0 * PI / self.angles as f32;
+        let direction = Vector3::new(angle.sin(), 0.0, angle.cos());
+        let eye = Point3::from_vec(self.center + direction * self.radius * 2.0);
+
+        // a y down up vector keeps the cells upright in the y down clip space
+        let view = Matrix4::look_at_rh(eye, Point3::from_vec(self.center), -Vector3::unit_y());
+        // maps the bounding sphere to depth 0..1, the near plane is behind the eye
+        let r = self.radius;
+        ortho(-r, r, -r, r, -r, 3.0 * r) * view
+    }
+
+    /// Records the mesh drawn by `draw` into the cells of `target`, which
+    /// has to be `angles` cells wide and one cell high.
+    ///
+    /// `draw` is called once per cell with its view projection, pipelines
+    /// drawing the mesh have to be built `with_render_target(target)`.
+    /// Belongs in a `RecordScope`, the cells stay valid until the target is drawn to again.
+    pub unsafe fn bake<F: FnMut(&Matrix4<f32>)>(
+        &self,
+        rri: &RenderRecordInfo,
+        target: &RenderTarget,
+        mut draw: F,
+    ) {
+        let cell_width = target.width() / self.angles;
+        target.begin(rri, Vector4::zero());
+        for index in 0..self.angles {
+            rri.set_viewport(vk::Rect2D {
+                offset: vk::Offset2D {
+                    x: (index * cell_width) as i32,
+                    y: 0,
+                },
+                extent: vk::Extent2D {
+                    width: cell_width,
+                    height: target.height(),
+                },
+            });
+            draw(&self.view_projection(index));
+        }
+        target.end(rri);
+    }
+
+    /// The cell seen from closest to the direction of `camera`.
+    pub fn cell(&self, position: Vector3<f32>, camera: Vector3<f32>) -> u32 {
+        let to_camera = camera - position;
+        let angle = to_camera.x.atan2(to_camera.z).rem_euclid(2.0 * PI);
+        (angle / (2.0 * PI) * self.angles as f32).round() as u32 % self.angles
+    }
+
+    /// Atlas region of `cell`, mirrored as the y down up vector mirrors the cells.
+    pub fn atlas(&self, cell: u32) -> Vector4<f32> {
+        let width = 1.0 / self.angles as f32;
+        Vector4::new((cell + 1) as f32 * width, 0.0, -width, 1.0)
+    }
+
+    /// An axis locked billboard standing in for the mesh moved to `position`.
+    pub fn billboard(&self, position: Vector3<f32>, camera: Vector3<f32>) -> Billboard {
+        let cell = self.cell(position, camera);
+        let diameter = self.radius * 2.0;
+        Billboard::new(position + self.center, Vector2::new(diameter, diameter))
+            .with_atlas(self.atlas(cell))
+    }
+}
+
+fn quad(
+    vertices: &mut Vec<shader::BillboardVertex>,
+    billboard: &Billboard,
+    right: Vector3<f32>,
+    up: Vector3<f32>,
+) {
+    let right = right * billboard.size.x * 0.5;
+    let up = up * billboard.size.y * 0.5;
+    let atlas = billboard.atlas;
+
+    // uv y grows down the texture, towards -up
+    let corner = |x: f32, y: f32| shader::BillboardVertex {
+        position: billboard.position + right * x + up * y,
+        uv: Vector2::new(
+            atlas.x + (x * 0.5 + 0.5) * atlas.z,
+            atlas.y + (0.5 - y * 0.5) * atlas.w,
+        ),
+        color: billboard.color,
+    };
+
+    let (bottom_left, bottom_right) = (corner(-1.0, -1.0), corner(1.0, -1.0));
+    let (top_left, top_right) = (corner(-1.0, 1.0), corner(1.0, 1.0));
+    vertices.extend_from_slice(&[
+        bottom_left,
+        top_left,
+        bottom_right,
+        bottom_right,
+        top_left,
+        top_right,
+    ]);
+}
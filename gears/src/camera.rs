@@ -0,0 +1,158 @@
+use cgmath::{EuclideanSpace, Matrix4, Point3, SquareMatrix, Vector2, Vector3};
+
+/// World position of the pixel `screen_pos` with the depth buffer value `depth`.
+///
+/// `screen_pos` is in pixels from the top left of a `size` sized viewport,
+/// use the pixel center for exact results. `None` if `view_projection` can
+/// not be inverted. See `Renderer::pick_position` for reading the depth.
+pub fn unproject(
+    screen_pos: Vector2<f32>,
+    depth: f32,
+    size: Vector2<f32>,
+    view_projection: &Matrix4<f32>,
+) -> Option<Point3<f32>> {
+    let inverse = view_projection.invert()?;
+    Some(unproject_inverse(screen_pos, depth, size, &inverse))
+}
+
+/// `unproject` with an already inverted view projection, for many positions per frame.
+pub fn unproject_inverse(
+    screen_pos: Vector2<f32>,
+    depth: f32,
+    size: Vector2<f32>,
+    inverse_view_projection: &Matrix4<f32>,
+) -> Point3<f32> {
+    let ndc = Vector3::new(
+        screen_pos.x / size.x * 2.0 - 1.0,
+        screen_pos.y / size.y * 2.0 - 1.0,
+        depth,
+    );
+    Point3::from_homogeneous(inverse_view_projection * ndc.extend(1.0))
+}
+
+/// Screen position in pixels and depth of `world`, the inverse of `unproject`.
+///
+/// The depth is outside of 0..1 for positions behind the camera or past the far plane.
+pub fn project(
+    world: Point3<f32>,
+    size: Vector2<f32>,
+    view_projection: &Matrix4<f32>,
+) -> (Vector2<f32>, f32) {
+    let ndc = Point3::from_homogeneous(view_projection * world.to_homogeneous()).to_vec();
+    let screen_pos = Vector2::new((ndc.x + 1.0) * 0.5 * size.x, (ndc.y + 1.0) * 0.5 * size.y);
+    (screen_pos, ndc.z)
+}
+
+/// Sub-pixel camera offset of frame `frame_index` in pixels, both axes in -0.5..0.5.
+///
+/// Halton (2, 3) points repeating every `period` frames, 8 or 16 are usual
+/// for TAA. Use `FrameInfo::frame_index` and apply it with `jitter_projection`.
+pub fn jitter(frame_index: u64, period: u32) -> Vector2<f32> {
+    // the sequence starts at 1, 0 would be (0, 0) in both bases
+    let index = (frame_index % period.max(1) as u64) as u32 + 1;
+    Vector2::new(halton(index, 2) - 0.5, halton(index, 3) - 0.5)
+}
+
+/// `projection` moved by `jitter` pixels of a `size` sized viewport.
+///
+/// Draw with the jittered projection but compute velocities from the
+/// unjittered ones, so the motion vectors do not include the jitter.
+pub fn jitter_projection(
+    projection: &Matrix4<f32>,
+    jitter: Vector2<f32>,
+    size: Vector2<f32>,
+) -> Matrix4<f32> {
+    // ndc spans 2 units across the viewport
+    let offset = Vector3::new(jitter.x * 2.0 / size.x, jitter.y * 2.0 / size.y, 0.0);
+    Matrix4::from_translation(offset) * projection
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{perspective, Deg, InnerSpace};
+
+    fn view_projection() -> Matrix4<f32> {
+        let view = Matrix4::look_at_rh(
+            Point3::new(1.0, 2.0, 5.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::unit_y(),
+        );
+        perspective(Deg(60.0), 16.0 / 9.0, 0.1, 100.0) * view
+    }
+
+    #[test]
+    fn project_unproject_round_trip() {
+        let size = Vector2::new(1280.0, 720.0);
+        let view_projection = view_projection();
+
+        for &world in &[
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.5, -0.25, 1.0),
+            Point3::new(-2.0, 1.0, -3.0),
+        ] {
+            let (screen_pos, depth) = project(world, size, &view_projection);
+            assert!(depth > 0.0 && depth < 1.0);
+
+            let unprojected = unproject(screen_pos, depth, size, &view_projection).unwrap();
+            assert!((unprojected - world).magnitude() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn screen_corners() {
+        let size = Vector2::new(200.0, 100.0);
+        let identity = Matrix4::identity();
+
+        let top_left = unproject(Vector2::new(0.0, 0.0), 0.5, size, &identity).unwrap();
+        let center = unproject(Vector2::new(100.0, 50.0), 0.5, size, &identity).unwrap();
+
+        assert_eq!(Point3::new(-1.0, -1.0, 0.5), top_left);
+        assert_eq!(Point3::new(0.0, 0.0, 0.5), center);
+        assert_eq!(
+            (Vector2::new(200.0, 100.0), 0.25),
+            project(Point3::new(1.0, 1.0, 0.25), size, &identity)
+        );
+    }
+
+    #[test]
+    fn singular_view_projection() {
+        let size = Vector2::new(1.0, 1.0);
+
+        assert_eq!(None, unproject(size, 0.5, size, &Matrix4::from_scale(0.0)));
+    }
+
+    #[test]
+    fn jitter_sequence() {
+        assert_eq!(Vector2::new(0.0, 1.0 / 3.0 - 0.5), jitter(0, 8));
+        assert_eq!(Vector2::new(-0.25, 2.0 / 3.0 - 0.5), jitter(1, 8));
+        assert_eq!(jitter(0, 8), jitter(8, 8));
+        assert_eq!(jitter(0, 0), jitter(5, 0));
+
+        for frame_index in 0..16 {
+            let offset = jitter(frame_index, 16);
+            assert!(offset.x.abs() < 0.5 && offset.y.abs() < 0.5);
+        }
+    }
+
+    #[test]
+    fn jitter_moves_by_pixels() {
+        let size = Vector2::new(100.0, 50.0);
+        let projection = Matrix4::identity();
+        let jittered = jitter_projection(&projection, Vector2::new(0.5, -0.5), size);
+
+        let (screen_pos, _) = project(Point3::new(0.0, 0.0, 0.5), size, &jittered);
+        assert_eq!(Vector2::new(50.5, 24.5), screen_pos);
+    }
+}
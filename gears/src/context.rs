@@ -1,4 +1,8 @@
-use super::{debug::Debugger, renderer::queue::QueueFamilies, MapErrorLog};
+use super::{
+    debug::Debugger,
+    renderer::{device::RenderDevice, queue::QueueFamilies},
+    MapErrorLog, SyncMode,
+};
 
 use ash::{
     extensions::{ext, khr},
@@ -9,7 +13,7 @@ use colored::Colorize;
 use log::{debug, error, warn};
 use std::{
     env,
-    ffi::CStr,
+    ffi::{CStr, CString},
     io::{self, Write},
 };
 use winit::window::Window;
@@ -34,6 +38,54 @@ pub enum ContextValidation {
     WithValidation,
 }
 
+/// Picks a GPU of the instance, see `ContextRequirements::pick`.
+pub type GPUPick = Box<dyn Fn(&ash::Instance) -> Option<vk::PhysicalDevice>>;
+
+/// Vulkan requirements of a library sharing the device, for ex. an OpenXR runtime.
+#[derive(Default)]
+pub struct ContextRequirements {
+    pub instance_extensions: Vec<CString>,
+    pub device_extensions: Vec<CString>,
+    /// Picks the GPU instead of `ContextGPUPick`, `None` or an unsuitable
+    /// GPU fails with `ContextError::NoSuitableGPUs`.
+    pub pick: Option<GPUPick>,
+}
+
+/// Device capabilities that textures, samplers and UBO arrays are clamped to.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Limits {
+    /// `1.0` if anisotropic filtering is not supported.
+    pub max_anisotropy: f32,
+    pub max_texture_size: u32,
+    /// Formats that can be sampled with linear filtering.
+    pub sampled_formats: Vec<vk::Format>,
+    /// Dynamic uniform buffer offsets are multiples of this.
+    pub min_uniform_buffer_offset_alignment: u64,
+    /// Nanoseconds per timestamp query tick.
+    pub timestamp_period: f32,
+}
+
+/// What the picked GPU and surface support, for graying out options in settings menus.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Capabilities {
+    pub device_name: String,
+    /// `RendererSettings::msaa` sample counts, ascending, always starting with 1.
+    pub msaa: Vec<u32>,
+    pub max_texture_size: u32,
+    /// `1.0` if anisotropic filtering is not supported.
+    pub max_anisotropy: f32,
+    /// Sync modes the surface presents with, the others fall back to `Fifo`.
+    pub sync_modes: Vec<SyncMode>,
+    /// The surface offers an HDR10 or extended linear sRGB color space.
+    pub hdr: bool,
+    /// The graphics queue runs compute shaders.
+    pub compute: bool,
+    pub tessellation: bool,
+    pub geometry_shader: bool,
+    pub mesh_shader: bool,
+    pub ray_tracing: bool,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum ContextError {
     MissingVulkan,
@@ -63,6 +115,11 @@ pub struct Context {
 
     pub instance: ash::Instance,
     pub instance_layers: Vec<&'static CStr>,
+    pub api_version: u32,
+    /// `ContextRequirements::device_extensions`.
+    pub device_extensions: Vec<CString>,
+
+    pub limits: Limits,
 
     pub entry: Entry,
 }
@@ -85,6 +142,17 @@ impl Context {
         size: (u32, u32),
         pick: ContextGPUPick,
         valid: ContextValidation,
+    ) -> Result<Self, ContextError> {
+        Self::new_with_requirements(window, size, pick, valid, ContextRequirements::default())
+    }
+
+    /// `new` with extensions and a GPU some other library needs.
+    pub fn new_with_requirements(
+        window: &Window,
+        size: (u32, u32),
+        pick: ContextGPUPick,
+        valid: ContextValidation,
+        requirements: ContextRequirements,
     ) -> Result<Self, ContextError> {
         let entry = unsafe { ash::Entry::new() }
             .map_err_log("Ash entry creation failed", ContextError::MissingVulkan)?;
@@ -152,6 +220,11 @@ impl Context {
                 ContextError::UnsupportedPlatform,
             )?,
         );
+        for ext in requirements.instance_extensions.iter() {
+            if !requested_extensions.contains(&ext.as_c_str()) {
+                requested_extensions.push(ext.as_c_str());
+            }
+        }
         let requested_extensions_raw: Vec<*const i8> = requested_extensions
             .iter()
             .map(|raw_name| raw_name.as_ptr())
@@ -261,6 +334,11 @@ impl Context {
 
             if suitable_pdevices.len() == 0 {
                 None
+            } else if let Some(pick) = requirements.pick.as_ref() {
+                let picked = pick(&instance);
+                suitable_pdevices
+                    .into_iter()
+                    .find(|(pdevice, _, _)| Some(*pdevice) == picked)
             } else if pick == ContextGPUPick::Manual
                 || env::var("GEARS_GPU").map_or(false, |value| value.to_lowercase() == "pick")
             {
@@ -315,11 +393,16 @@ impl Context {
             all_pdevices_to_string(&pdevice_names, false)
         );
 
+        let limits = pdevice_limits(&instance, pdevice);
+        debug!("GPU limits: {:?}", limits);
+
         Ok(Self {
             entry,
 
             instance,
             instance_layers: requested_layers,
+            api_version: application_info.api_version,
+            device_extensions: requirements.device_extensions,
 
             surface,
             surface_loader,
@@ -331,9 +414,139 @@ impl Context {
             pdevice,
             queue_families,
 
+            limits,
+
             debugger,
         })
     }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Queried from the picked GPU and the surface, call before handing the
+    /// context to `RendererBuilder::build` and keep it around.
+    pub fn capabilities(&self) -> Capabilities {
+        let properties = unsafe { self.instance.get_physical_device_properties(self.pdevice) };
+        let features = unsafe { self.instance.get_physical_device_features(self.pdevice) };
+
+        let sample_counts = properties.limits.framebuffer_color_sample_counts
+            & properties.limits.framebuffer_depth_sample_counts;
+        let msaa = (0..7)
+            .map(|bit| 1 << bit)
+            .filter(|&count| {
+                count == 1 || sample_counts.contains(vk::SampleCountFlags::from_raw(count))
+            })
+            .collect();
+
+        let present_modes = unsafe {
+            self.surface_loader
+                .get_physical_device_surface_present_modes(self.pdevice, self.surface)
+        }
+        .unwrap_or_default();
+        let sync_modes = [
+            (SyncMode::Immediate, vk::PresentModeKHR::IMMEDIATE),
+            (SyncMode::Fifo, vk::PresentModeKHR::FIFO),
+            (SyncMode::Mailbox, vk::PresentModeKHR::MAILBOX),
+        ]
+        .iter()
+        // fifo is always supported
+        .filter(|(sync, mode)| *sync == SyncMode::Fifo || present_modes.contains(mode))
+        .map(|(sync, _)| *sync)
+        .collect();
+
+        let hdr = unsafe {
+            self.surface_loader
+                .get_physical_device_surface_formats(self.pdevice, self.surface)
+        }
+        .unwrap_or_default()
+        .iter()
+        .any(|format| {
+            format.color_space == vk::ColorSpaceKHR::HDR10_ST2084_EXT
+                || format.color_space == vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT
+        });
+
+        let compute = self.queue_families.graphics.map_or(false, |family| {
+            unsafe {
+                self.instance
+                    .get_physical_device_queue_family_properties(self.pdevice)
+            }
+            .get(family)
+            .map_or(false, |properties| {
+                properties.queue_flags.contains(vk::QueueFlags::COMPUTE)
+            })
+        });
+
+        let (device_name, _) = pdevice_name_and_type(&self.instance, self.pdevice);
+        Capabilities {
+            device_name,
+            msaa,
+            max_texture_size: self.limits.max_texture_size,
+            max_anisotropy: self.limits.max_anisotropy,
+            sync_modes,
+            hdr,
+            compute,
+            tessellation: features.tessellation_shader == vk::TRUE,
+            geometry_shader: features.geometry_shader == vk::TRUE,
+            mesh_shader: unsafe {
+                RenderDevice::mesh_shader(&self.instance, self.pdevice, self.api_version)
+            },
+            ray_tracing: unsafe {
+                RenderDevice::ray_tracing(&self.instance, self.pdevice, self.api_version)
+            },
+        }
+    }
+}
+
+impl Limits {
+    pub fn format_sampled(&self, format: vk::Format) -> bool {
+        self.sampled_formats.contains(&format)
+    }
+}
+
+const SAMPLED_FORMAT_CANDIDATES: [vk::Format; 12] = [
+    vk::Format::R8_UNORM,
+    vk::Format::R8_SRGB,
+    vk::Format::R8G8_UNORM,
+    vk::Format::R8G8_SRGB,
+    vk::Format::R8G8B8_UNORM,
+    vk::Format::R8G8B8_SRGB,
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::B8G8R8A8_UNORM,
+    vk::Format::B8G8R8A8_SRGB,
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::Format::R32G32B32A32_SFLOAT,
+];
+
+fn pdevice_limits(instance: &ash::Instance, pdevice: vk::PhysicalDevice) -> Limits {
+    let properties = unsafe { instance.get_physical_device_properties(pdevice) };
+    let features = unsafe { instance.get_physical_device_features(pdevice) };
+
+    let sampled_formats = SAMPLED_FORMAT_CANDIDATES
+        .iter()
+        .cloned()
+        .filter(|&format| {
+            unsafe { instance.get_physical_device_format_properties(pdevice, format) }
+                .optimal_tiling_features
+                .contains(
+                    vk::FormatFeatureFlags::SAMPLED_IMAGE
+                        | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+                )
+        })
+        .collect();
+
+    Limits {
+        max_anisotropy: if features.sampler_anisotropy == vk::TRUE {
+            properties.limits.max_sampler_anisotropy
+        } else {
+            1.0
+        },
+        max_texture_size: properties.limits.max_image_dimension2_d,
+        sampled_formats,
+        min_uniform_buffer_offset_alignment: properties.limits.min_uniform_buffer_offset_alignment,
+        timestamp_period: properties.limits.timestamp_period,
+    }
 }
 
 fn pdevice_name_and_type(
@@ -1,7 +1,13 @@
 use ash::{extensions::ext, vk};
 use log::{log, Level};
+use parking_lot::{const_mutex, Mutex};
 use std::{borrow::Cow, ffi::CStr};
 
+const RECENT_MESSAGES: usize = 32;
+
+// warnings and errors for `Diagnostics`, oldest first
+static RECENT: Mutex<Vec<String>> = const_mutex(Vec::new());
+
 unsafe extern "system" fn vulkan_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     message_type: vk::DebugUtilsMessageTypeFlagsEXT,
@@ -39,6 +45,17 @@ unsafe extern "system" fn vulkan_debug_callback(
         message
     );
 
+    if level <= Level::Warn {
+        let mut recent = RECENT.lock();
+        if recent.len() >= RECENT_MESSAGES {
+            recent.remove(0);
+        }
+        recent.push(format!(
+            "{:?}: {} ({}) {}",
+            level, message_id_name, message_id_number, message
+        ));
+    }
+
     #[cfg(feature = "validation_panic")]
     if level == Level::Error {
         panic!("Validation error");
@@ -47,6 +64,11 @@ unsafe extern "system" fn vulkan_debug_callback(
     vk::FALSE
 }
 
+/// The latest validation warnings and errors, oldest first.
+pub fn recent_messages() -> Vec<String> {
+    RECENT.lock().clone()
+}
+
 pub struct Debugger {
     debug_utils: ext::DebugUtils,
     debug_messenger: vk::DebugUtilsMessengerEXT,
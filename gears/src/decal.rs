@@ -0,0 +1,168 @@
+use ash::vk;
+use cgmath::{Matrix4, SquareMatrix, Vector3, Vector4};
+
+use crate::renderer::{
+    buffer::{storage::StorageBuffer, Buffer, BufferError, WriteType},
+    pipeline::{Pipeline, PipelineBuilder},
+    target::RenderTarget,
+    RenderRecordInfo, Renderer, UpdateRecordInfo,
+};
+
+mod shader {
+    gears_pipeline::pipeline! {
+        vert: {
+            path: "res/fullscreen.vert.glsl"
+        }
+        frag: {
+            path: "res/decal.frag.glsl"
+        }
+    }
+}
+
+/// A box projected decal, for bullet holes, blob shadows and the like.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decal {
+    /// World to decal space. The decal box is -1..1 on every axis and
+    /// projects along z, the top left of the atlas region is at (-1, -1).
+    pub transform: Matrix4<f32>,
+    /// Atlas uv offset in xy and size in zw.
+    pub atlas: Vector4<f32>,
+    /// Linear color multiplied with the atlas, blended with its alpha.
+    pub color: Vector4<f32>,
+    /// Minimum cosine between the surface normal and the decal z axis,
+    /// -1.0 projects onto every surface in the box.
+    pub normal_threshold: f32,
+
+    // std430 array stride
+    _pad: [f32; 3],
+}
+
+// must match the push constant block in decal.frag.glsl
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct DecalPush {
+    inverse_view_projection: Matrix4<f32>,
+    camera: Vector4<f32>,
+    decal_count: u32,
+}
+
+impl Decal {
+    /// `model` places the -1..1 decal box in the world, `None` if it is not
+    /// invertible, like a box scaled to zero on one axis.
+    ///
+    /// White, and clipped on surfaces facing away from the decal z axis.
+    pub fn new(model: &Matrix4<f32>, atlas: Vector4<f32>) -> Option<Self> {
+        Some(Self {
+            transform: model.invert()?,
+            atlas,
+            color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            normal_threshold: 0.0,
+
+            _pad: [0.0; 3],
+        })
+    }
+
+    pub fn with_color(mut self, color: Vector4<f32>) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_normal_threshold(mut self, normal_threshold: f32) -> Self {
+        self.normal_threshold = normal_threshold;
+        self
+    }
+}
+
+/// Projected decals over an already drawn scene.
+///
+/// The decals are clipped against the depth buffer of the scene `RenderTarget`
+/// and drawn in one full screen pass, blended over the swapchain contents.
+/// A frame then looks like:
+/// - `UploadScope`: `decals.update(uri)`
+/// - `RecordScope`: the scene to its target
+/// - `DrawScope`: the scene color to the swapchain, then `decals.draw(rri)`
+///
+/// Surface normals are reconstructed from the depth, so decals can bleed
+/// a pixel over depth discontinuities.
+pub struct DecalRenderer {
+    decals: StorageBuffer<Decal>,
+    pipeline: Pipeline,
+
+    // `None` skips the draw
+    inverse_view_projection: Option<Matrix4<f32>>,
+    camera: Vector3<f32>,
+}
+
+impl DecalRenderer {
+    /// `scene` has to be single sampled and outlive the renderer, `atlas_view`
+    /// has to be in `SHADER_READ_ONLY_OPTIMAL` when drawn.
+    pub fn new(
+        renderer: &Renderer,
+        scene: &RenderTarget,
+        atlas_view: vk::ImageView,
+        atlas_sampler: vk::Sampler,
+        max_decals: usize,
+    ) -> Result<Self, BufferError> {
+        if scene.samples() != vk::SampleCountFlags::TYPE_1 {
+            return Err(BufferError::UnsupportedFeature("multisampled decal depth"));
+        }
+
+        let decals = StorageBuffer::new(renderer, max_decals, vk::BufferUsageFlags::empty())?;
+        let pipeline = PipelineBuilder::new(renderer)
+            .without_debug_views()
+            .with_graphics_modules(shader::VERT_SPIRV_WORDS, shader::FRAG_SPIRV_WORDS)
+            .with_push_constants::<DecalPush>(vk::ShaderStageFlags::FRAGMENT)
+            .with_sampled_image(scene.depth_view(), scene.sampler())
+            .with_sampled_image(atlas_view, atlas_sampler)
+            .with_storage_buffer(&decals)
+            .with_transparency()
+            .build(false)?;
+
+        Ok(Self {
+            decals,
+            pipeline,
+
+            inverse_view_projection: Some(Matrix4::identity()),
+            camera: Vector3::new(0.0, 0.0, 0.0),
+        })
+    }
+
+    /// The decal count is the highest written index + 1.
+    pub fn write_decals(
+        &mut self,
+        offset: usize,
+        decals: &[Decal],
+    ) -> Result<WriteType, BufferError> {
+        self.decals.write(offset, decals)
+    }
+
+    /// The view projection and world space camera position the scene was drawn with.
+    ///
+    /// Like push constants in general, this is recorded and needs a rerecord to change.
+    /// Nothing is drawn if `view_projection` can not be inverted.
+    pub fn set_camera(&mut self, view_projection: &Matrix4<f32>, camera: Vector3<f32>) {
+        self.inverse_view_projection = view_projection.invert();
+        self.camera = camera;
+    }
+
+    pub unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        self.decals.update(uri)
+    }
+
+    pub unsafe fn draw(&self, rri: &RenderRecordInfo) {
+        let inverse_view_projection = match self.inverse_view_projection {
+            Some(inverse_view_projection) => inverse_view_projection,
+            None => return,
+        };
+        let push = DecalPush {
+            inverse_view_projection,
+            camera: self.camera.extend(1.0),
+            decal_count: self.decals.len() as u32,
+        };
+
+        self.pipeline.bind(rri);
+        self.pipeline.push_constants(rri, &push);
+        self.pipeline.draw_vertices(rri, 3);
+    }
+}
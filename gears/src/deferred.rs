@@ -0,0 +1,152 @@
+use ash::vk;
+use cgmath::{Vector3, Vector4};
+
+use crate::renderer::{
+    buffer::{storage::StorageBuffer, Buffer, BufferError, WriteType},
+    pipeline::{Pipeline, PipelineBuilder},
+    target::RenderTarget,
+    RenderRecordInfo, Renderer, UpdateRecordInfo,
+};
+
+mod shader {
+    gears_pipeline::pipeline! {
+        vert: {
+            path: "res/fullscreen.vert.glsl"
+        }
+        frag: {
+            path: "res/deferred.frag.glsl"
+        }
+    }
+}
+
+/// G-buffer attachments, in fragment output location order.
+///
+/// Geometry pass fragment shaders write world space values:
+///
+/// ```glsl
+/// #[gears_bindgen(out)]
+/// struct GBuffer {
+///     vec4 albedo;   // rgb, a = 0 is treated as empty
+///     vec4 normal;   // xyz
+///     vec4 position; // xyz
+/// } gbuffer;
+/// ```
+pub const GBUFFER_FORMATS: [vk::Format; 3] = [
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::Format::R16G16B16A16_SFLOAT,
+];
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointLight {
+    /// World space position in xyz and radius in w.
+    pub position: Vector4<f32>,
+    /// Linear color in rgb and intensity in w.
+    pub color: Vector4<f32>,
+}
+
+// must match the push constant block in deferred.frag.glsl
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct LightPush {
+    camera: Vector4<f32>,
+    ambient: Vector4<f32>,
+    light_count: u32,
+}
+
+/// Deferred shading preset.
+///
+/// Materials are ordinary pipelines built with
+/// `PipelineBuilder::new(renderer).with_render_target(deferred.gbuffer())`
+/// that write the `GBUFFER_FORMATS` layout. A frame then looks like:
+/// - `UploadScope`: `deferred.update(uri)`
+/// - `RecordScope`: `deferred.begin_geometry(rri)`, material draws, `deferred.end_geometry(rri)`
+/// - `DrawScope`: `deferred.compose(rri)`, which lights the G-buffer in one full
+///   screen pass, followed by any forward or transparent draws
+///
+/// The G-buffer has the swapchain size at creation, recreate it after resizes.
+pub struct DeferredRenderer {
+    gbuffer: RenderTarget,
+    lights: StorageBuffer<PointLight>,
+    light_pipeline: Pipeline,
+
+    camera: Vector3<f32>,
+    ambient: Vector3<f32>,
+}
+
+impl DeferredRenderer {
+    pub fn new(renderer: &Renderer, max_lights: usize) -> Result<Self, BufferError> {
+        let (width, height) = renderer.extent();
+        let gbuffer = RenderTarget::new(renderer, width, height, &GBUFFER_FORMATS)?;
+        let lights = StorageBuffer::new(renderer, max_lights, vk::BufferUsageFlags::empty())?;
+
+        let mut light_pipeline = PipelineBuilder::new(renderer)
+            .without_debug_views()
+            .with_graphics_modules(shader::VERT_SPIRV_WORDS, shader::FRAG_SPIRV_WORDS)
+            .with_push_constants::<LightPush>(vk::ShaderStageFlags::FRAGMENT);
+        for i in 0..GBUFFER_FORMATS.len() {
+            light_pipeline =
+                light_pipeline.with_sampled_image(gbuffer.color_view(i), gbuffer.sampler());
+        }
+        let light_pipeline = light_pipeline.with_storage_buffer(&lights).build(false)?;
+
+        Ok(Self {
+            gbuffer,
+            lights,
+            light_pipeline,
+
+            camera: Vector3::new(0.0, 0.0, 0.0),
+            ambient: Vector3::new(0.1, 0.1, 0.1),
+        })
+    }
+
+    pub fn gbuffer(&self) -> &RenderTarget {
+        &self.gbuffer
+    }
+
+    /// The light count is the highest written index + 1.
+    pub fn write_lights(
+        &mut self,
+        offset: usize,
+        lights: &[PointLight],
+    ) -> Result<WriteType, BufferError> {
+        self.lights.write(offset, lights)
+    }
+
+    /// World space camera position for specular highlights.
+    ///
+    /// Like push constants in general, this is recorded and needs a rerecord to change.
+    pub fn set_camera(&mut self, camera: Vector3<f32>) {
+        self.camera = camera;
+    }
+
+    pub fn set_ambient(&mut self, ambient: Vector3<f32>) {
+        self.ambient = ambient;
+    }
+
+    pub unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        self.lights.update(uri)
+    }
+
+    pub unsafe fn begin_geometry(&self, rri: &RenderRecordInfo) {
+        self.gbuffer.begin(rri, Vector4::new(0.0, 0.0, 0.0, 0.0));
+    }
+
+    pub unsafe fn end_geometry(&self, rri: &RenderRecordInfo) {
+        self.gbuffer.end(rri);
+    }
+
+    /// Lighting and composition to the swapchain.
+    pub unsafe fn compose(&self, rri: &RenderRecordInfo) {
+        let push = LightPush {
+            camera: self.camera.extend(1.0),
+            ambient: self.ambient.extend(1.0),
+            light_count: self.lights.len() as u32,
+        };
+
+        self.light_pipeline.bind(rri);
+        self.light_pipeline.push_constants(rri, &push);
+        self.light_pipeline.draw_vertices(rri, 3);
+    }
+}
@@ -5,12 +5,14 @@ use winit::{
 };
 
 use crate::{
-    context::{Context, ContextError, ContextGPUPick, ContextValidation},
+    context::{Context, ContextError, ContextGPUPick, ContextRequirements, ContextValidation},
+    io::clipboard::Clipboard,
     ExpectLog,
 };
 
 pub struct Frame {
     window: Window,
+    clipboard: Clipboard,
 }
 
 pub struct FrameBuilder<'a> {
@@ -47,6 +49,16 @@ impl Frame {
         Context::new(&self.window, self.size(), pick, valid)
     }
 
+    /// See `Context::new_with_requirements`.
+    pub fn context_with_requirements(
+        &self,
+        pick: ContextGPUPick,
+        valid: ContextValidation,
+        requirements: ContextRequirements,
+    ) -> Result<Context, ContextError> {
+        Context::new_with_requirements(&self.window, self.size(), pick, valid, requirements)
+    }
+
     pub fn size(&self) -> (u32, u32) {
         lsize_to_tuple(
             self.window
@@ -72,6 +84,11 @@ impl Frame {
     pub fn window_mut(&mut self) -> &mut Window {
         &mut self.window
     }
+
+    /// Text copy and paste, for text fields and debug consoles.
+    pub fn clipboard(&mut self) -> &mut Clipboard {
+        &mut self.clipboard
+    }
 }
 
 impl<'a> FrameBuilder<'a> {
@@ -108,7 +125,11 @@ impl<'a> FrameBuilder<'a> {
             .build(&event_loop)
             .expect_log("Window creation failed");
 
-        (Frame { window }, event_loop)
+        let frame = Frame {
+            window,
+            clipboard: Clipboard::new(),
+        };
+        (frame, event_loop)
     }
 }
 
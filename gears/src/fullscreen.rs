@@ -0,0 +1,103 @@
+use ash::vk;
+
+use crate::renderer::{
+    buffer::BufferError,
+    pipeline::{Pipeline, PipelineBuilder},
+    target::RenderTarget,
+    RenderRecordInfo, Renderer,
+};
+
+mod shader {
+    gears_pipeline::pipeline! {
+        vert: {
+            path: "res/fullscreen.vert.glsl"
+        }
+    }
+}
+
+/// The built-in vertex shader of `Pass`, for fullscreen pipelines built by hand.
+pub const VERT_SPIRV: &[u32] = shader::VERT_SPIRV_WORDS;
+
+/// Single fullscreen triangle, for post effects that only need a fragment shader.
+///
+/// The fragment shader gets the uv from the built-in vertex shader and the
+/// inputs in `with_input` call order:
+///
+/// ```glsl
+/// layout(location = 0) in vec2 uv;
+/// layout(set = 1, binding = 0) uniform sampler2D source;
+/// ```
+///
+/// `draw` belongs in `DrawScope` or between `RenderTarget::begin` and `end`
+/// of the target given to `PassBuilder::with_render_target`.
+pub struct Pass {
+    pipeline: Pipeline,
+}
+
+pub struct PassBuilder<'a> {
+    base: PipelineBuilder,
+    frag_spirv: &'a [u32],
+    inputs: Vec<(vk::ImageView, vk::Sampler)>,
+}
+
+impl Pass {
+    /// `frag_spirv` is usually `FRAG_SPIRV_WORDS` of a `pipeline!` with only an `fs` module.
+    pub fn new<'a>(renderer: &Renderer, frag_spirv: &'a [u32]) -> PassBuilder<'a> {
+        PassBuilder {
+            base: PipelineBuilder::new(renderer).without_debug_views(),
+            frag_spirv,
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn pipeline(&self) -> &Pipeline {
+        &self.pipeline
+    }
+
+    pub unsafe fn draw(&self, rri: &RenderRecordInfo) {
+        self.pipeline.bind(rri);
+        self.pipeline.draw_vertices(rri, 3);
+    }
+
+    /// `draw` with the push constant block of `PassBuilder::with_push_constants`.
+    pub unsafe fn draw_with<P: 'static + Copy>(&self, rri: &RenderRecordInfo, push: &P) {
+        self.pipeline.bind(rri);
+        self.pipeline.push_constants(rri, push);
+        self.pipeline.draw_vertices(rri, 3);
+    }
+}
+
+impl<'a> PassBuilder<'a> {
+    /// Samples `view` with `sampler`, for ex. a `RenderTarget` color view and its sampler.
+    ///
+    /// `view` has to outlive the pass and be in `SHADER_READ_ONLY_OPTIMAL` when drawn.
+    pub fn with_input(mut self, view: vk::ImageView, sampler: vk::Sampler) -> Self {
+        self.inputs.push((view, sampler));
+        self
+    }
+
+    /// Draw to `target` instead of the swapchain, for chaining passes.
+    pub fn with_render_target(mut self, target: &RenderTarget) -> Self {
+        self.base = self.base.with_render_target(target);
+        self
+    }
+
+    /// Fragment stage push constant block of type `P`, see `Pass::draw_with`.
+    pub fn with_push_constants<P: 'static + Copy>(mut self) -> Self {
+        self.base = self
+            .base
+            .with_push_constants::<P>(vk::ShaderStageFlags::FRAGMENT);
+        self
+    }
+
+    pub fn build(self) -> Result<Pass, BufferError> {
+        let mut builder = self.base.with_graphics_modules(VERT_SPIRV, self.frag_spirv);
+        for (view, sampler) in self.inputs {
+            builder = builder.with_sampled_image(view, sampler);
+        }
+
+        Ok(Pass {
+            pipeline: builder.build(false)?,
+        })
+    }
+}
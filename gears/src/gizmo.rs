@@ -0,0 +1,393 @@
+use cgmath::{
+    EuclideanSpace, InnerSpace, Matrix4, Point3, Quaternion, Rad, Rotation3, SquareMatrix, Vector2,
+    Vector3, Vector4,
+};
+use std::{f32::consts::PI, mem};
+
+use crate::{
+    camera::{project, unproject_inverse},
+    line::Lines,
+};
+
+// screen space distance in pixels the cursor grabs a handle from
+const GRAB_DISTANCE: f32 = 8.0;
+const CIRCLE_SEGMENTS: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+/// The world axis a handle moves along or rotates around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GizmoAxis {
+    X,
+    Y,
+    Z,
+}
+
+/// Change since the last `Gizmo::update`, to apply to the manipulated transform.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GizmoDelta {
+    /// World space offset.
+    Translate(Vector3<f32>),
+    /// Rotation around the pivot, multiply it in from the left.
+    Rotate(Quaternion<f32>),
+    /// Scale factor per axis.
+    Scale(Vector3<f32>),
+}
+
+/// World axis aligned translate, rotate and scale handles for editor style tools.
+///
+/// ```ignore
+/// if let Some(GizmoDelta::Translate(delta)) =
+///     gizmo.update(cursor, button_held, &view_projection, viewport)
+/// {
+///     object.position += delta;
+/// }
+/// gizmo.draw(&mut lines, &view_projection, viewport);
+/// ```
+///
+/// The handles keep their size in pixels at any distance. The cursor is
+/// hit tested against their screen space lines, so no id pass is needed,
+/// while a mouse button is held over a handle it is dragged. The gizmo
+/// follows its own translations, `set_position` moves it otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gizmo {
+    mode: GizmoMode,
+    position: Vector3<f32>,
+    size: f32,
+
+    hovered: Option<GizmoAxis>,
+    drag: Option<Drag>,
+    was_pressed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Drag {
+    axis: GizmoAxis,
+    // distance along the axis or angle around it, of the last update
+    last: f32,
+}
+
+impl GizmoAxis {
+    const ALL: [GizmoAxis; 3] = [GizmoAxis::X, GizmoAxis::Y, GizmoAxis::Z];
+
+    pub fn vector(self) -> Vector3<f32> {
+        match self {
+            GizmoAxis::X => Vector3::unit_x(),
+            GizmoAxis::Y => Vector3::unit_y(),
+            GizmoAxis::Z => Vector3::unit_z(),
+        }
+    }
+
+    fn color(self) -> Vector4<f32> {
+        match self {
+            GizmoAxis::X => Vector4::new(0.9, 0.2, 0.2, 1.0),
+            GizmoAxis::Y => Vector4::new(0.2, 0.9, 0.2, 1.0),
+            GizmoAxis::Z => Vector4::new(0.2, 0.4, 0.9, 1.0),
+        }
+    }
+
+    // two axes spanning the plane perpendicular to this one
+    fn tangents(self) -> (Vector3<f32>, Vector3<f32>) {
+        match self {
+            GizmoAxis::X => (Vector3::unit_y(), Vector3::unit_z()),
+            GizmoAxis::Y => (Vector3::unit_z(), Vector3::unit_x()),
+            GizmoAxis::Z => (Vector3::unit_x(), Vector3::unit_y()),
+        }
+    }
+}
+
+impl Gizmo {
+    pub fn new(mode: GizmoMode, position: Vector3<f32>) -> Self {
+        Self {
+            mode,
+            position,
+            size: 100.0,
+
+            hovered: None,
+            drag: None,
+            was_pressed: false,
+        }
+    }
+
+    /// Handle length and rotation circle radius in pixels, 100.0 by default.
+    pub fn with_size(mut self, size: f32) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn mode(&self) -> GizmoMode {
+        self.mode
+    }
+
+    /// Ends any drag.
+    pub fn set_mode(&mut self, mode: GizmoMode) {
+        self.mode = mode;
+        self.drag = None;
+    }
+
+    pub fn position(&self) -> Vector3<f32> {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: Vector3<f32>) {
+        self.position = position;
+    }
+
+    /// The handle under the cursor, or the dragged one.
+    pub fn hovered(&self) -> Option<GizmoAxis> {
+        self.drag.map(|drag| drag.axis).or(self.hovered)
+    }
+
+    /// True while a handle is dragged, the cursor should not reach the scene then.
+    pub fn dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    /// Hit tests and drags the handles.
+    ///
+    /// `cursor` is in pixels from the top left of the `viewport` sized
+    /// viewport, `pressed` is the state of the dragging mouse button.
+    /// `None` without a drag or movement.
+    pub fn update(
+        &mut self,
+        cursor: Vector2<f32>,
+        pressed: bool,
+        view_projection: &Matrix4<f32>,
+        viewport: Vector2<f32>,
+    ) -> Option<GizmoDelta> {
+        let just_pressed = pressed && !self.was_pressed;
+        self.was_pressed = pressed;
+        if !pressed {
+            self.drag = None;
+        }
+
+        let inverse = view_projection.invert()?;
+        let length = self.world_size(view_projection, viewport, &inverse);
+        let ray = cursor_ray(cursor, viewport, &inverse);
+
+        if self.drag.is_none() {
+            self.hovered = self.hit_test(cursor, view_projection, viewport, length);
+            if let (true, Some(axis)) = (just_pressed, self.hovered) {
+                self.drag = self.measure(axis, ray).map(|last| Drag { axis, last });
+            }
+            return None;
+        }
+
+        let axis = self.drag?.axis;
+        let current = self.measure(axis, ray)?;
+        let last = mem::replace(&mut self.drag.as_mut()?.last, current);
+
+        let delta = match self.mode {
+            GizmoMode::Translate => {
+                let offset = axis.vector() * (current - last);
+                self.position += offset;
+                GizmoDelta::Translate(offset)
+            }
+            GizmoMode::Rotate => GizmoDelta::Rotate(Quaternion::from_axis_angle(
+                axis.vector(),
+                Rad(current - last),
+            )),
+            GizmoMode::Scale => {
+                // measured from the pivot, crossing it would flip the scale
+                if last.abs() < f32::EPSILON || current * last <= 0.0 {
+                    return None;
+                }
+                let mut scale = Vector3::new(1.0, 1.0, 1.0);
+                scale[axis as usize] = current / last;
+                GizmoDelta::Scale(scale)
+            }
+        };
+        Some(delta)
+    }
+
+    /// Queues the handles, wider where hovered.
+    ///
+    /// Leaves the color and width of `lines` changed.
+    pub fn draw(&self, lines: &mut Lines, view_projection: &Matrix4<f32>, viewport: Vector2<f32>) {
+        let inverse = match view_projection.invert() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+        let length = self.world_size(view_projection, viewport, &inverse);
+
+        for &axis in GizmoAxis::ALL.iter() {
+            let highlighted = self.hovered() == Some(axis);
+            lines.color(if highlighted {
+                Vector4::new(1.0, 0.9, 0.2, 1.0)
+            } else {
+                axis.color()
+            });
+            lines.width(if highlighted { 4.0 } else { 2.5 });
+
+            let handle = self.handle(axis, length);
+            lines.path(&handle, self.mode == GizmoMode::Rotate);
+
+            // arrow heads and boxes at the ends
+            let end = self.position + axis.vector() * length;
+            let (u, v) = axis.tangents();
+            let tip = length * 0.06;
+            match self.mode {
+                GizmoMode::Translate => {
+                    let base = end - axis.vector() * tip * 2.0;
+                    lines.path(&[base + u * tip, end, base - u * tip], false);
+                    lines.path(&[base + v * tip, end, base - v * tip], false);
+                }
+                GizmoMode::Scale => {
+                    let corners = [
+                        end + (u + v) * tip,
+                        end + (u - v) * tip,
+                        end - (u + v) * tip,
+                        end - (u - v) * tip,
+                    ];
+                    lines.path(&corners, true);
+                }
+                GizmoMode::Rotate => {}
+            }
+        }
+    }
+
+    // world length of `size` pixels at the pivot
+    fn world_size(
+        &self,
+        view_projection: &Matrix4<f32>,
+        viewport: Vector2<f32>,
+        inverse: &Matrix4<f32>,
+    ) -> f32 {
+        let (screen, depth) = project(Point3::from_vec(self.position), viewport, view_projection);
+        let offset = unproject_inverse(
+            screen + Vector2::new(0.0, self.size),
+            depth,
+            viewport,
+            inverse,
+        );
+        (offset.to_vec() - self.position).magnitude()
+    }
+
+    // points of the handle polyline, the rotation circles are closed
+    fn handle(&self, axis: GizmoAxis, length: f32) -> Vec<Vector3<f32>> {
+        match self.mode {
+            GizmoMode::Translate | GizmoMode::Scale => {
+                vec![self.position, self.position + axis.vector() * length]
+            }
+            GizmoMode::Rotate => {
+                let (u, v) = axis.tangents();
+                (0..CIRCLE_SEGMENTS)
+                    .map(|i| {
+                        let angle = i as f32 / CIRCLE_SEGMENTS as f32 * 2.0 * PI;
+                        self.position + (u * angle.cos() + v * angle.sin()) * length
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    fn hit_test(
+        &self,
+        cursor: Vector2<f32>,
+        view_projection: &Matrix4<f32>,
+        viewport: Vector2<f32>,
+        length: f32,
+    ) -> Option<GizmoAxis> {
+        let closed = self.mode == GizmoMode::Rotate;
+        GizmoAxis::ALL
+            .iter()
+            .filter_map(|&axis| {
+                let points = self
+                    .handle(axis, length)
+                    .into_iter()
+                    .map(|point| project(Point3::from_vec(point), viewport, view_projection))
+                    // past the far plane or behind the camera
+                    .filter(|(_, depth)| *depth <= 1.0)
+                    .map(|(screen, _)| screen)
+                    .collect::<Vec<_>>();
+
+                let segments = points.windows(2).map(|pair| (pair[0], pair[1]));
+                let closing = points
+                    .last()
+                    .zip(points.first())
+                    .filter(|_| closed)
+                    .map(|(&last, &first)| (last, first));
+                segments
+                    .chain(closing)
+                    .map(|(a, b)| segment_distance(cursor, a, b))
+                    .fold(None, |min: Option<f32>, d| {
+                        Some(min.map_or(d, |min| min.min(d)))
+                    })
+                    .filter(|&distance| distance <= GRAB_DISTANCE)
+                    .map(|distance| (axis, distance))
+            })
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(axis, _)| axis)
+    }
+
+    // distance along the axis or angle around it the cursor ray points at
+    fn measure(
+        &self,
+        axis: GizmoAxis,
+        (origin, direction): (Vector3<f32>, Vector3<f32>),
+    ) -> Option<f32> {
+        let a = axis.vector();
+        let value = match self.mode {
+            GizmoMode::Translate | GizmoMode::Scale => {
+                // closest point of the axis line to the ray
+                let w = self.position - origin;
+                let b = a.dot(direction);
+                let c = direction.dot(direction);
+                let denom = c - b * b;
+                if denom.abs() < 1e-6 {
+                    return None;
+                }
+                (b * direction.dot(w) - c * a.dot(w)) / denom
+            }
+            GizmoMode::Rotate => {
+                // the ray hitting the rotation plane
+                let facing = a.dot(direction);
+                if facing.abs() < 1e-6 {
+                    return None;
+                }
+                let t = a.dot(self.position - origin) / facing;
+                let hit = origin + direction * t - self.position;
+                let (u, v) = axis.tangents();
+                hit.dot(v).atan2(hit.dot(u))
+            }
+        };
+
+        Some(match self.drag {
+            // angles continue past a full turn while dragging
+            Some(drag) if self.mode == GizmoMode::Rotate => {
+                drag.last + wrap_angle(value - drag.last)
+            }
+            _ => value,
+        })
+    }
+}
+
+// origin and direction through the cursor
+fn cursor_ray(
+    cursor: Vector2<f32>,
+    viewport: Vector2<f32>,
+    inverse: &Matrix4<f32>,
+) -> (Vector3<f32>, Vector3<f32>) {
+    let near = unproject_inverse(cursor, 0.0, viewport, inverse).to_vec();
+    let far = unproject_inverse(cursor, 0.5, viewport, inverse).to_vec();
+    (near, (far - near).normalize())
+}
+
+fn segment_distance(point: Vector2<f32>, a: Vector2<f32>, b: Vector2<f32>) -> f32 {
+    let ab = b - a;
+    let t = if ab.magnitude2() > 0.0 {
+        ((point - a).dot(ab) / ab.magnitude2()).max(0.0).min(1.0)
+    } else {
+        0.0
+    };
+    (point - (a + ab * t)).magnitude()
+}
+
+fn wrap_angle(angle: f32) -> f32 {
+    (angle + PI).rem_euclid(2.0 * PI) - PI
+}
@@ -0,0 +1,190 @@
+use ash::vk;
+use cgmath::{Matrix4, Vector3, Vector4};
+use log::Level;
+use std::{mem, ops::Range};
+
+use crate::{
+    logging,
+    renderer::{
+        buffer::{streaming::StreamingVertexBuffer, BufferError},
+        pipeline::{Pipeline, PipelineBuilder},
+        ImmediateFrameInfo, RenderRecordInfo, Renderer,
+    },
+};
+
+mod shader {
+    gears_pipeline::pipeline! {
+        vert: {
+            path: "res/immediate.vert.glsl"
+        }
+        frag: {
+            path: "res/immediate.frag.glsl"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Topology {
+    Points,
+    Lines,
+    LineStrip,
+    Triangles,
+    TriangleStrip,
+}
+
+// declaration order, `Immediate::pipelines` is indexed with `Topology as usize`
+const TOPOLOGIES: [Topology; 5] = [
+    Topology::Points,
+    Topology::Lines,
+    Topology::LineStrip,
+    Topology::Triangles,
+    Topology::TriangleStrip,
+];
+
+/// GL style immediate mode drawing for prototypes and debug visualization.
+///
+/// ```ignore
+/// im.color(Vector4::new(1.0, 0.0, 0.0, 1.0));
+/// im.begin(Topology::Lines);
+/// im.vertex(Vector3::new(0.0, 0.0, 0.0));
+/// im.vertex(Vector3::new(1.0, 1.0, 0.0));
+/// im.end();
+/// ```
+///
+/// `draw` uploads and draws everything since the last `draw` in a `DrawScope`,
+/// once per frame. The vertices live for one frame only, so the renderer has
+/// to `request_rerecord` every frame. Triangles are not culled and blend with
+/// their alpha.
+pub struct Immediate {
+    vertices: StreamingVertexBuffer<shader::ImmediateVertex>,
+    pipelines: Vec<Pipeline>,
+
+    color: Vector4<f32>,
+    batch: Option<(Topology, usize)>,
+    batches: Vec<(Topology, Range<usize>)>,
+    pending: Vec<shader::ImmediateVertex>,
+}
+
+impl Topology {
+    fn vk(self) -> vk::PrimitiveTopology {
+        match self {
+            Topology::Points => vk::PrimitiveTopology::POINT_LIST,
+            Topology::Lines => vk::PrimitiveTopology::LINE_LIST,
+            Topology::LineStrip => vk::PrimitiveTopology::LINE_STRIP,
+            Topology::Triangles => vk::PrimitiveTopology::TRIANGLE_LIST,
+            Topology::TriangleStrip => vk::PrimitiveTopology::TRIANGLE_STRIP,
+        }
+    }
+}
+
+impl Immediate {
+    /// `capacity` is the vertex count shared by all frames in flight.
+    pub fn new(renderer: &Renderer, capacity: usize) -> Result<Self, BufferError> {
+        let pipelines = TOPOLOGIES
+            .iter()
+            .map(|topology| {
+                PipelineBuilder::new(renderer)
+                    .without_debug_views()
+                    .with_graphics_modules(shader::VERT_SPIRV_WORDS, shader::FRAG_SPIRV_WORDS)
+                    .with_input::<shader::ImmediateVertex>()
+                    .with_push_constants::<Matrix4<f32>>(vk::ShaderStageFlags::VERTEX)
+                    .with_transparency()
+                    .with_topology(topology.vk())
+                    .build(true)
+            })
+            .collect::<Result<Vec<_>, BufferError>>()?;
+
+        Ok(Self {
+            vertices: StreamingVertexBuffer::new(renderer, capacity)?,
+            pipelines,
+
+            color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            batch: None,
+            batches: Vec::new(),
+            pending: Vec::new(),
+        })
+    }
+
+    /// Color of the following vertices, white by default.
+    pub fn color(&mut self, color: Vector4<f32>) {
+        self.color = color;
+    }
+
+    pub fn begin(&mut self, topology: Topology) {
+        if self.batch.is_some() {
+            log_throttled!(
+                target: logging::RECORD,
+                Level::Warn,
+                "Immediate::begin called before end"
+            );
+            self.end();
+        }
+        self.batch = Some((topology, self.pending.len()));
+    }
+
+    pub fn vertex(&mut self, position: Vector3<f32>) {
+        if self.batch.is_none() {
+            log_throttled!(
+                target: logging::RECORD,
+                Level::Warn,
+                "Immediate::vertex called outside of begin and end"
+            );
+            return;
+        }
+        self.pending.push(shader::ImmediateVertex {
+            position,
+            color: self.color,
+        });
+    }
+
+    pub fn end(&mut self) {
+        match self.batch.take() {
+            Some((topology, start)) if start < self.pending.len() => {
+                self.batches.push((topology, start..self.pending.len()))
+            }
+            Some(_) => {}
+            None => log_throttled!(
+                target: logging::RECORD,
+                Level::Warn,
+                "Immediate::end called without begin"
+            ),
+        }
+    }
+
+    /// Draws and clears the vertices since the last `draw`.
+    ///
+    /// `TriedToOverflow` if the frames in flight used up the capacity, the vertices are dropped.
+    pub unsafe fn draw(
+        &mut self,
+        rri: &RenderRecordInfo,
+        view_projection: &Matrix4<f32>,
+    ) -> Result<(), BufferError> {
+        if self.batch.is_some() {
+            log_throttled!(
+                target: logging::RECORD,
+                Level::Warn,
+                "Immediate::draw called before end"
+            );
+            self.end();
+        }
+
+        self.vertices.begin_frame(&ImmediateFrameInfo {
+            image_index: rri.image_index(),
+        });
+
+        let batches = mem::take(&mut self.batches);
+        let result = batches.iter().try_for_each(|(topology, range)| {
+            let slice = self.vertices.alloc_frame(&self.pending[range.clone()])?;
+            let pipeline = &self.pipelines[*topology as usize];
+
+            pipeline.bind(rri);
+            pipeline.push_constants(rri, view_projection);
+            slice.bind(rri, 0);
+            pipeline.draw_vertices(rri, slice.len() as u32);
+            Ok(())
+        });
+        self.pending.clear();
+
+        result
+    }
+}
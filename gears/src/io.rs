@@ -1,6 +1,9 @@
+pub mod clipboard;
 pub mod cursor_controller;
 pub mod input_state;
 
+#[cfg(feature = "short_namespaces")]
+pub use clipboard::*;
 #[cfg(feature = "short_namespaces")]
 pub use cursor_controller::*;
 #[cfg(feature = "short_namespaces")]
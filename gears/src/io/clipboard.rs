@@ -0,0 +1,113 @@
+use log::{error, warn};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum ClipboardError {
+    /// No clipboard on this platform or it could not be accessed.
+    Unavailable,
+    /// The clipboard has no text.
+    Empty,
+    /// Web only: the browser is still reading the clipboard, ask again on a later frame.
+    Pending,
+}
+
+/// The system clipboard, see `Frame::clipboard`.
+///
+/// Native platforms use `arboard`. The web uses the async clipboard API,
+/// which needs `--cfg=web_sys_unstable_apis`, so `get_text` starts a read
+/// and returns `Pending` until the browser resolves it.
+pub struct Clipboard {
+    #[cfg(not(target_arch = "wasm32"))]
+    clipboard: Option<arboard::Clipboard>,
+
+    #[cfg(target_arch = "wasm32")]
+    read: std::sync::Arc<parking_lot::Mutex<Option<Result<String, ClipboardError>>>>,
+    #[cfg(target_arch = "wasm32")]
+    reading: bool,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Clipboard {
+    pub(crate) fn new() -> Self {
+        let clipboard = arboard::Clipboard::new()
+            .map_err(|err| warn!("Clipboard not available: {}", err))
+            .ok();
+        Self { clipboard }
+    }
+
+    pub fn get_text(&mut self) -> Result<String, ClipboardError> {
+        let clipboard = self.clipboard.as_mut().ok_or(ClipboardError::Unavailable)?;
+
+        clipboard.get_text().map_err(|err| match err {
+            arboard::Error::ContentNotAvailable => ClipboardError::Empty,
+            err => {
+                error!("Clipboard read failed: {}", err);
+                ClipboardError::Unavailable
+            }
+        })
+    }
+
+    pub fn set_text(&mut self, text: &str) -> Result<(), ClipboardError> {
+        let clipboard = self.clipboard.as_mut().ok_or(ClipboardError::Unavailable)?;
+
+        clipboard.set_text(text.to_owned()).map_err(|err| {
+            error!("Clipboard write failed: {}", err);
+            ClipboardError::Unavailable
+        })
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Clipboard {
+    pub(crate) fn new() -> Self {
+        Self {
+            read: Default::default(),
+            reading: false,
+        }
+    }
+
+    fn web_clipboard() -> Result<web_sys::Clipboard, ClipboardError> {
+        let window = web_sys::window().ok_or(ClipboardError::Unavailable)?;
+        // undefined outside of secure contexts
+        Ok(window.navigator().clipboard())
+    }
+
+    pub fn get_text(&mut self) -> Result<String, ClipboardError> {
+        if let Some(result) = self.read.lock().take() {
+            self.reading = false;
+            return result;
+        }
+        if self.reading {
+            return Err(ClipboardError::Pending);
+        }
+
+        let promise = Self::web_clipboard()?.read_text();
+        let read = self.read.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let result = match wasm_bindgen_futures::JsFuture::from(promise).await {
+                Ok(text) => text
+                    .as_string()
+                    .filter(|text| !text.is_empty())
+                    .ok_or(ClipboardError::Empty),
+                Err(err) => {
+                    warn!("Clipboard read failed: {:?}", err);
+                    Err(ClipboardError::Unavailable)
+                }
+            };
+            *read.lock() = Some(result);
+        });
+        self.reading = true;
+
+        Err(ClipboardError::Pending)
+    }
+
+    /// The write finishes in the background, failures are only logged.
+    pub fn set_text(&mut self, text: &str) -> Result<(), ClipboardError> {
+        let promise = Self::web_clipboard()?.write_text(text);
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Err(err) = wasm_bindgen_futures::JsFuture::from(promise).await {
+                warn!("Clipboard write failed: {:?}", err);
+            }
+        });
+        Ok(())
+    }
+}
@@ -1,51 +1,279 @@
-use std::{collections::HashMap, sync::Arc};
+use cgmath::Vector2;
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use parking_lot::RwLock;
-use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+use winit::event::{ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
 
-use crate::loops::frame::EventLoopTarget;
+use crate::{loops::frame::EventLoopTarget, UpdateRate};
 
-pub struct InputState {
-    keymap: HashMap<VirtualKeyCode, bool>,
+/// The input at the start of a frame, see `InputState::snapshot`.
+#[derive(Debug, Clone)]
+pub struct InputSnapshot {
+    // held keys and when they were pressed
+    keymap: HashMap<VirtualKeyCode, Instant>,
+    buttons: HashSet<MouseButton>,
+    // physical pixels from the top left, None outside of the window
+    cursor: Option<Vector2<f32>>,
     window_focused: bool,
+    taken: Instant,
+}
+
+pub struct InputState {
+    live: InputSnapshot,
+    frame: Arc<InputSnapshot>,
+
+    repeat_delay: Duration,
+    repeat_interval: Duration,
+    // key repeats emitted per held key, including the press
+    repeated: HashMap<VirtualKeyCode, u32>,
+    repeats: Vec<VirtualKeyCode>,
+
+    hovered_files: Vec<PathBuf>,
+    dropped_files: Vec<PathBuf>,
+
+    release_on_focus_loss: bool,
+    // replaces the system clock and window events while set
+    controlled_time: Option<Instant>,
+}
+
+impl InputSnapshot {
+    fn new() -> Self {
+        Self {
+            keymap: HashMap::new(),
+            buttons: HashSet::new(),
+            cursor: None,
+            window_focused: false,
+            taken: Instant::now(),
+        }
+    }
+
+    pub fn window_focused(&self) -> bool {
+        self.window_focused
+    }
+
+    pub fn key_held(&self, key: VirtualKeyCode) -> bool {
+        self.keymap.contains_key(&key)
+    }
+
+    pub fn mouse_held(&self, button: MouseButton) -> bool {
+        self.buttons.contains(&button)
+    }
+
+    /// In physical pixels from the top left of the window, `None` if the
+    /// cursor is outside of it.
+    pub fn cursor_position(&self) -> Option<Vector2<f32>> {
+        self.cursor
+    }
+
+    /// How long `key` had been held when the snapshot was taken, `None` if it was not held.
+    pub fn key_hold_duration(&self, key: VirtualKeyCode) -> Option<Duration> {
+        self.hold_duration_at(key, self.taken)
+    }
+
+    fn hold_duration_at(&self, key: VirtualKeyCode, now: Instant) -> Option<Duration> {
+        self.keymap
+            .get(&key)
+            .map(|pressed| now.saturating_duration_since(*pressed))
+    }
 }
 
 impl InputState {
     pub fn new() -> Arc<RwLock<Self>> {
         Arc::new(RwLock::new(Self {
-            keymap: HashMap::new(),
-            window_focused: false,
+            live: InputSnapshot::new(),
+            frame: Arc::new(InputSnapshot::new()),
+
+            repeat_delay: Duration::from_millis(500),
+            repeat_interval: UpdateRate::PerSecond(30).to_interval(),
+            repeated: HashMap::new(),
+            repeats: Vec::new(),
+
+            hovered_files: Vec::new(),
+            dropped_files: Vec::new(),
+
+            release_on_focus_loss: false,
+            controlled_time: None,
         }))
     }
 
+    /// Up to date with the last event, can change between two reads in one frame.
     pub fn window_focused(&self) -> bool {
-        self.window_focused
+        self.live.window_focused()
     }
 
+    /// Up to date with the last event, can change between two reads in one frame.
     pub fn key_held(&self, key: VirtualKeyCode) -> bool {
-        if let Some(value) = self.keymap.get(&key) {
-            *value
+        self.live.key_held(key)
+    }
+
+    /// Up to date with the last event, can change between two reads in one frame.
+    pub fn mouse_held(&self, button: MouseButton) -> bool {
+        self.live.mouse_held(button)
+    }
+
+    /// Up to date with the last event, can change between two reads in one frame.
+    pub fn cursor_position(&self) -> Option<Vector2<f32>> {
+        self.live.cursor_position()
+    }
+
+    /// How long `key` has been held, `None` if it is not held.
+    pub fn key_hold_duration(&self, key: VirtualKeyCode) -> Option<Duration> {
+        self.live.hold_duration_at(key, self.now())
+    }
+
+    /// The input as it was when the current frame started.
+    ///
+    /// Every read in one frame sees the same input. Taken by `FrameLoop`
+    /// for inputs added with `FrameLoopBuilder::with_input`, call
+    /// `begin_frame` otherwise.
+    pub fn snapshot(&self) -> Arc<InputSnapshot> {
+        self.frame.clone()
+    }
+
+    /// Replaces the `snapshot` with the current input.
+    pub fn begin_frame(&mut self) {
+        self.live.taken = self.now();
+        self.frame = Arc::new(self.live.clone());
+    }
+
+    /// Held keys repeat after `delay` at `rate`, 500 ms and 30 per second by default.
+    ///
+    /// The operating system's own key repeat is ignored.
+    pub fn set_key_repeat(&mut self, delay: Duration, rate: UpdateRate) {
+        self.repeat_delay = delay;
+        self.repeat_interval = rate.to_interval();
+    }
+
+    /// Takes the key presses and repeats since the last call, in order per key.
+    ///
+    /// For text fields and scrolling while a key is held. Every press is
+    /// included once, even if the key was released before this call.
+    pub fn key_repeats(&mut self) -> Vec<VirtualKeyCode> {
+        let now = self.now();
+        let keys = self.live.keymap.keys().cloned().collect::<Vec<_>>();
+        for key in keys {
+            self.queue_repeats(key, now);
+        }
+        mem::take(&mut self.repeats)
+    }
+
+    // queues the repeats of `key` due at `now`
+    fn queue_repeats(&mut self, key: VirtualKeyCode, now: Instant) {
+        let held = match self.live.hold_duration_at(key, now) {
+            Some(held) => held,
+            None => return,
+        };
+
+        let due = if held < self.repeat_delay {
+            1
         } else {
-            false
+            let interval = self.repeat_interval.as_nanos().max(1);
+            2 + ((held - self.repeat_delay).as_nanos() / interval) as u32
+        };
+        let repeated = self.repeated.entry(key).or_insert(0);
+        for _ in *repeated..due {
+            self.repeats.push(key);
         }
+        *repeated = due.max(*repeated);
+    }
+
+    /// Releases every held key and mouse button when the window loses focus, off by default.
+    ///
+    /// The window gets no release events while unfocused, so keys held
+    /// while switching windows stay held until pressed again without this.
+    pub fn set_release_on_focus_loss(&mut self, release: bool) {
+        self.release_on_focus_loss = release;
+    }
+
+    /// Files dragged over the window and not dropped yet, for drop previews.
+    pub fn hovered_files(&self) -> &[PathBuf] {
+        &self.hovered_files
+    }
+
+    /// Takes the files dropped onto the window since the last call.
+    pub fn take_dropped_files(&mut self) -> Vec<PathBuf> {
+        mem::take(&mut self.dropped_files)
+    }
+
+    /// Uses `now` instead of the system clock and ignores window events
+    /// while `Some`, input then only changes through `update`. Set by
+    /// `FrameLoop` in `Deterministic` runs.
+    pub fn set_controlled_time(&mut self, now: Option<Instant>) {
+        self.controlled_time = now;
+    }
+
+    fn now(&self) -> Instant {
+        self.controlled_time.unwrap_or_else(Instant::now)
     }
 
     pub fn update_key(&mut self, input: &KeyboardInput) {
-        input.virtual_keycode.map(|keycode| {
-            self.keymap.insert(
-                keycode,
-                match input.state {
-                    ElementState::Pressed => true,
-                    _ => false,
-                },
-            );
-        });
+        let keycode = match input.virtual_keycode {
+            Some(keycode) => keycode,
+            None => return,
+        };
+
+        match input.state {
+            ElementState::Pressed => {
+                // repeated press events from the operating system keep the first press
+                if !self.live.keymap.contains_key(&keycode) {
+                    let now = self.now();
+                    self.live.keymap.insert(keycode, now);
+                    self.repeated.insert(keycode, 1);
+                    self.repeats.push(keycode);
+                }
+            }
+            ElementState::Released => {
+                self.queue_repeats(keycode, self.now());
+                self.live.keymap.remove(&keycode);
+                self.repeated.remove(&keycode);
+            }
+        }
+    }
+
+    fn release_all(&mut self) {
+        let now = self.now();
+        let keys = self.live.keymap.keys().cloned().collect::<Vec<_>>();
+        for key in keys {
+            self.queue_repeats(key, now);
+        }
+        self.live.keymap.clear();
+        self.live.buttons.clear();
+        self.repeated.clear();
     }
 
     pub fn update(&mut self, event: &WindowEvent) {
         match event {
             WindowEvent::KeyboardInput { input, .. } => self.update_key(input),
-            WindowEvent::Focused(f) => self.window_focused = *f,
+            WindowEvent::Focused(f) => {
+                self.live.window_focused = *f;
+                if !*f && self.release_on_focus_loss {
+                    self.release_all();
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.live.cursor = Some(Vector2::new(position.x as f32, position.y as f32))
+            }
+            WindowEvent::CursorLeft { .. } => self.live.cursor = None,
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => {
+                    self.live.buttons.insert(*button);
+                }
+                ElementState::Released => {
+                    self.live.buttons.remove(button);
+                }
+            },
+            WindowEvent::HoveredFile(path) => self.hovered_files.push(path.clone()),
+            WindowEvent::HoveredFileCancelled => self.hovered_files.clear(),
+            WindowEvent::DroppedFile(path) => {
+                self.hovered_files.retain(|hovered| hovered != path);
+                self.dropped_files.push(path.clone());
+            }
             _ => (),
         }
     }
@@ -53,6 +281,8 @@ impl InputState {
 
 impl EventLoopTarget for InputState {
     fn event(&mut self, event: &WindowEvent) {
-        self.update(event);
+        if self.controlled_time.is_none() {
+            self.update(event);
+        }
     }
 }
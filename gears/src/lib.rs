@@ -1,25 +1,87 @@
+// first, `log_throttled!` is used by the modules after it
+#[macro_use]
+pub mod logging;
+// `profile_scope!` too
+#[macro_use]
+mod profiling;
+
+pub mod animation;
+pub mod assets;
+pub mod atmosphere;
+pub mod billboard;
+pub mod camera;
 pub mod context;
 mod debug;
+pub mod decal;
+pub mod deferred;
 pub mod frame;
+// not flattened by short_namespaces, `Pass` is too generic on its own
+pub mod fullscreen;
+pub mod gizmo;
+pub mod immediate;
 pub mod io;
+pub mod line;
 pub mod loops;
+#[cfg(feature = "net")]
+pub mod net;
+pub mod raycast;
 pub mod renderer;
+pub mod scene;
+pub mod split;
+pub mod terrain;
+pub mod ui;
+pub mod viewport;
 
 use log::error;
 use std::{fmt, time};
 
+#[cfg(feature = "short_namespaces")]
+pub use animation::*;
+#[cfg(feature = "short_namespaces")]
+pub use assets::*;
+#[cfg(feature = "short_namespaces")]
+pub use atmosphere::*;
+#[cfg(feature = "short_namespaces")]
+pub use billboard::*;
+#[cfg(feature = "short_namespaces")]
+pub use camera::*;
 #[cfg(feature = "short_namespaces")]
 pub use context::*;
 #[cfg(feature = "short_namespaces")]
+pub use decal::*;
+#[cfg(feature = "short_namespaces")]
+pub use deferred::*;
+#[cfg(feature = "short_namespaces")]
 pub use frame::*;
 #[cfg(feature = "short_namespaces")]
+pub use gizmo::*;
+#[cfg(feature = "short_namespaces")]
+pub use immediate::*;
+#[cfg(feature = "short_namespaces")]
 pub use io::*;
 #[cfg(feature = "short_namespaces")]
+pub use line::*;
+#[cfg(feature = "short_namespaces")]
 pub use loops::*;
+#[cfg(all(feature = "net", feature = "short_namespaces"))]
+pub use net::*;
+#[cfg(feature = "short_namespaces")]
+pub use raycast::*;
 #[cfg(feature = "short_namespaces")]
 pub use renderer::*;
+#[cfg(feature = "short_namespaces")]
+pub use scene::*;
+#[cfg(feature = "short_namespaces")]
+pub use split::*;
+#[cfg(feature = "short_namespaces")]
+pub use terrain::*;
+#[cfg(feature = "short_namespaces")]
+pub use ui::*;
+#[cfg(feature = "short_namespaces")]
+pub use viewport::*;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SyncMode {
     /// Immediate: no sync
     ///
@@ -55,6 +117,19 @@ pub enum SyncMode {
     Mailbox,
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum ColorSpace {
+    /// Srgb: the swapchain encodes gamma
+    ///
+    /// Shaders output linear colors, picks an ```*_SRGB``` surface format.
+    Srgb,
+
+    /// Linear: the shaders encode gamma
+    ///
+    /// Shader outputs are written as is, picks an ```*_UNORM``` surface format.
+    Linear,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum UpdateRate {
     /// _n_ updates per second with even intervals
@@ -76,6 +151,12 @@ impl Default for SyncMode {
     }
 }
 
+impl Default for ColorSpace {
+    fn default() -> Self {
+        ColorSpace::Srgb
+    }
+}
+
 impl UpdateRate {
     pub fn to_interval(&self) -> time::Duration {
         match *self {
@@ -0,0 +1,164 @@
+use ash::vk;
+use cgmath::{Matrix4, Vector2, Vector3, Vector4};
+
+use crate::renderer::{
+    buffer::{streaming::StreamingVertexBuffer, BufferError},
+    pipeline::{Pipeline, PipelineBuilder},
+    ImmediateFrameInfo, RenderRecordInfo, Renderer,
+};
+
+mod shader {
+    gears_pipeline::pipeline! {
+        vert: {
+            path: "res/line.vert.glsl"
+        }
+        frag: {
+            path: "res/line.frag.glsl"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LineCap {
+    /// Ends exactly at the points.
+    Butt,
+    /// Half a circle past the points, which also rounds the joins of paths.
+    Round,
+}
+
+// must match the push constant block in line.vert.glsl
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct LinePush {
+    view_projection: Matrix4<f32>,
+    viewport: Vector2<f32>,
+}
+
+/// Anti-aliased lines with a width in pixels, for gizmos and vector overlays.
+///
+/// ```ignore
+/// lines.color(Vector4::new(1.0, 0.0, 0.0, 1.0));
+/// lines.width(3.0);
+/// lines.path(&[a, b, c], true);
+/// ```
+///
+/// Every segment is a quad expanded to its screen space width by the vertex
+/// shader. Like `Immediate`, `draw` draws everything since the last `draw`
+/// once per frame in a `DrawScope` that has to `request_rerecord` every frame.
+/// Lines blend with their alpha, translucent round joins are blended twice.
+pub struct Lines {
+    vertices: StreamingVertexBuffer<shader::LineVertex>,
+    pipeline: Pipeline,
+
+    color: Vector4<f32>,
+    width: f32,
+    cap: LineCap,
+    pending: Vec<shader::LineVertex>,
+}
+
+impl Lines {
+    /// `capacity` is the segment count shared by all frames in flight.
+    pub fn new(renderer: &Renderer, capacity: usize) -> Result<Self, BufferError> {
+        // debug, the quads are not culled
+        let pipeline = PipelineBuilder::new(renderer)
+            .without_debug_views()
+            .with_graphics_modules(shader::VERT_SPIRV_WORDS, shader::FRAG_SPIRV_WORDS)
+            .with_input::<shader::LineVertex>()
+            .with_push_constants::<LinePush>(vk::ShaderStageFlags::VERTEX)
+            .with_transparency()
+            .build(true)?;
+
+        Ok(Self {
+            vertices: StreamingVertexBuffer::new(renderer, capacity * 6)?,
+            pipeline,
+
+            color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            width: 1.0,
+            cap: LineCap::Round,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Color of the following lines, white by default.
+    pub fn color(&mut self, color: Vector4<f32>) {
+        self.color = color;
+    }
+
+    /// Width of the following lines in pixels, 1.0 by default.
+    pub fn width(&mut self, width: f32) {
+        self.width = width.max(0.0);
+    }
+
+    /// Caps of the following lines, `Round` by default.
+    pub fn cap(&mut self, cap: LineCap) {
+        self.cap = cap;
+    }
+
+    pub fn line(&mut self, start: Vector3<f32>, end: Vector3<f32>) {
+        let round_cap = match self.cap {
+            LineCap::Butt => 0.0,
+            LineCap::Round => 1.0,
+        };
+        let corner = |x: f32, y: f32| shader::LineVertex {
+            start,
+            end,
+            color: self.color,
+            corner: Vector2::new(x, y),
+            width: self.width,
+            round_cap,
+        };
+
+        self.pending.extend_from_slice(&[
+            corner(0.0, -1.0),
+            corner(0.0, 1.0),
+            corner(1.0, -1.0),
+            corner(1.0, -1.0),
+            corner(0.0, 1.0),
+            corner(1.0, 1.0),
+        ]);
+    }
+
+    /// Segments between consecutive `points`, `closed` also connects the last to the first.
+    ///
+    /// Only `Round` caps join the segments without gaps.
+    pub fn path(&mut self, points: &[Vector3<f32>], closed: bool) {
+        for pair in points.windows(2) {
+            self.line(pair[0], pair[1]);
+        }
+        if closed && points.len() > 2 {
+            self.line(points[points.len() - 1], points[0]);
+        }
+    }
+
+    /// Draws and clears the lines since the last `draw`.
+    ///
+    /// `TriedToOverflow` if the frames in flight used up the capacity, the lines are dropped.
+    pub unsafe fn draw(
+        &mut self,
+        rri: &RenderRecordInfo,
+        view_projection: &Matrix4<f32>,
+    ) -> Result<(), BufferError> {
+        self.vertices.begin_frame(&ImmediateFrameInfo {
+            image_index: rri.image_index(),
+        });
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let (width, height) = rri.extent();
+        let push = LinePush {
+            view_projection: *view_projection,
+            viewport: Vector2::new(width as f32, height as f32),
+        };
+
+        let result = self.vertices.alloc_frame(&self.pending).map(|slice| {
+            self.pipeline.bind(rri);
+            self.pipeline.push_constants(rri, &push);
+            slice.bind(rri, 0);
+            self.pipeline.draw_vertices(rri, slice.len() as u32);
+        });
+        self.pending.clear();
+
+        result
+    }
+}
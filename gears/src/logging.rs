@@ -0,0 +1,94 @@
+//! Log targets of the subsystems and throttling for messages logged every frame.
+//!
+//! Filter them like any other target, `RUST_LOG=warn,gears::renderer::swapchain=debug`
+//! with `env_logger` for ex.
+
+use std::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Swapchain, surface format, present mode and render scale changes.
+pub const SWAPCHAIN: &str = "gears::renderer::swapchain";
+/// Allocations, uploads and streaming buffers.
+pub const MEMORY: &str = "gears::renderer::memory";
+/// Pipeline creation and shader interface mismatches.
+pub const PIPELINE: &str = "gears::pipeline";
+/// Every recorded command, with `RenderRecordBeginInfo::debug_calls`.
+pub const COMMANDS: &str = "gears::renderer::commands";
+/// Recording calls made out of order, like `FrameCtx::record` twice in one frame.
+pub const RECORD: &str = "gears::renderer::record";
+/// The frame loop's performance report.
+pub const PERF: &str = "gears::perf";
+/// OpenXR runtime, session state and swapchains of `gears-xr`.
+pub const XR: &str = "gears::xr";
+
+// in milliseconds
+static THROTTLE_INTERVAL: AtomicU64 = AtomicU64::new(1000);
+
+/// How often a throttled message is logged at most, one second by default.
+///
+/// Throttled messages are the ones that could repeat every frame,
+/// the repeats in between are counted and logged with the next one.
+/// Zero logs every message.
+pub fn set_throttle_interval(interval: Duration) {
+    THROTTLE_INTERVAL.store(interval.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// State of one `log_throttled!` call site.
+pub(crate) struct Throttle {
+    // milliseconds since the unix epoch, 0 if never logged
+    last: AtomicU64,
+    suppressed: AtomicUsize,
+}
+
+impl Throttle {
+    pub const fn new() -> Self {
+        Self {
+            last: AtomicU64::new(0),
+            suppressed: AtomicUsize::new(0),
+        }
+    }
+
+    /// The number of suppressed repeats if this one should be logged.
+    pub fn pass(&self) -> Option<usize> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        let last = self.last.load(Ordering::Relaxed);
+
+        let due =
+            last == 0 || now.saturating_sub(last) >= THROTTLE_INTERVAL.load(Ordering::Relaxed);
+        if due
+            && self
+                .last
+                .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            Some(self.suppressed.swap(0, Ordering::Relaxed))
+        } else {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+}
+
+/// `log!` for messages that could repeat every frame, see `set_throttle_interval`.
+macro_rules! log_throttled {
+    (target: $target:expr, $level:expr, $($arg:tt)+) => {{
+        static THROTTLE: $crate::logging::Throttle = $crate::logging::Throttle::new();
+        if log::log_enabled!(target: $target, $level) {
+            match THROTTLE.pass() {
+                Some(0) => log::log!(target: $target, $level, $($arg)+),
+                Some(suppressed) => log::log!(
+                    target: $target,
+                    $level,
+                    "{} ({} repeats suppressed)",
+                    format_args!($($arg)+),
+                    suppressed
+                ),
+                None => {}
+            }
+        }
+    }};
+}
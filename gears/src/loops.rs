@@ -1,7 +1,16 @@
+pub mod deterministic;
 pub mod frame;
+pub mod schedule;
+pub mod task;
 pub mod update;
 
+#[cfg(feature = "short_namespaces")]
+pub use deterministic::*;
 #[cfg(feature = "short_namespaces")]
 pub use frame::*;
 #[cfg(feature = "short_namespaces")]
+pub use schedule::*;
+#[cfg(feature = "short_namespaces")]
+pub use task::*;
+#[cfg(feature = "short_namespaces")]
 pub use update::*;
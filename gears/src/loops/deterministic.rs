@@ -0,0 +1,313 @@
+use std::{
+    collections::VecDeque,
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+use winit::event::WindowEvent;
+
+use super::frame::FrameInfo;
+use crate::UpdateRate;
+
+/// Small, fast pseudo random generator (xoshiro256**).
+///
+/// The same seed gives the same numbers on every platform and build, so a
+/// simulation seeded from `FrameInfo::seed` repeats exactly in a
+/// `Deterministic` run. Not for cryptography.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Rng {
+    state: [u64; 4],
+}
+
+/// Input for the frame loop's inputs, from a script, a replay or the
+/// agreed inputs of a lockstep session.
+pub trait InputSource: Send {
+    /// Events of frame `info`, applied to every `FrameLoopBuilder::with_input`
+    /// input before its snapshot.
+    fn events(&mut self, info: &FrameInfo) -> Vec<WindowEvent<'static>>;
+}
+
+/// `InputSource` replaying events at fixed frame indices.
+#[derive(Debug, Default)]
+pub struct InputScript {
+    // sorted by frame index
+    events: VecDeque<(u64, WindowEvent<'static>)>,
+}
+
+/// Frame loop mode where everything a simulation sees is reproducible, see
+/// `FrameLoopBuilder::with_deterministic`.
+///
+/// Every frame advances `FrameInfo` time by the same fixed step regardless
+/// of how long it really took, `FrameInfo::seed` only depends on the seed
+/// and the frame index and inputs get their events from an `InputSource`
+/// instead of the window. Two runs with the same seed, step and input then
+/// see bit identical frames, for integration tests or lockstep networking.
+///
+/// Frames are still paced by the renderer, so the fixed time runs ahead of
+/// or behind the wall clock. Event targets other than `InputState` still
+/// get window events.
+pub struct Deterministic {
+    seed: u64,
+    step: Duration,
+    input: Option<Box<dyn InputSource>>,
+
+    // the controlled clock of the inputs
+    epoch: Instant,
+    clock: Duration,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut seed = seed;
+        let mut state = [0; 4];
+        for word in state.iter_mut() {
+            *word = split_mix(&mut seed);
+        }
+        Self { state }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let result = self.state[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = self.state[1] << 17;
+
+        self.state[2] ^= self.state[0];
+        self.state[3] ^= self.state[1];
+        self.state[1] ^= self.state[2];
+        self.state[0] ^= self.state[3];
+        self.state[2] ^= t;
+        self.state[3] = self.state[3].rotate_left(45);
+
+        result
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Uniform in `[min, max)`.
+    pub fn range_f32(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /// Uniform in `range`, `range.start` if it is empty.
+    pub fn range_u32(&mut self, range: Range<u32>) -> u32 {
+        let len = range.end.saturating_sub(range.start) as u64;
+        range.start + ((self.next_u32() as u64 * len) >> 32) as u32
+    }
+
+    /// True with the `probability` from 0 to 1.
+    pub fn chance(&mut self, probability: f32) -> bool {
+        self.next_f32() < probability
+    }
+
+    /// A generator of its own, for ex. one per subsystem, so adding draws in
+    /// one does not shift the numbers of the others.
+    pub fn fork(&mut self) -> Self {
+        Self::new(self.next_u64())
+    }
+}
+
+impl InputScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `event` in frame `frame_index`, after the events already added to it.
+    pub fn with_event(mut self, frame_index: u64, event: WindowEvent<'static>) -> Self {
+        self.push(frame_index, event);
+        self
+    }
+
+    pub fn push(&mut self, frame_index: u64, event: WindowEvent<'static>) {
+        let at = self
+            .events
+            .iter()
+            .position(|(index, _)| *index > frame_index)
+            .unwrap_or(self.events.len());
+        self.events.insert(at, (frame_index, event));
+    }
+
+    /// Events not replayed yet.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+impl InputSource for InputScript {
+    /// Events of `info.frame_index` and of earlier frames that were skipped.
+    fn events(&mut self, info: &FrameInfo) -> Vec<WindowEvent<'static>> {
+        let mut events = Vec::new();
+        while self
+            .events
+            .front()
+            .map_or(false, |(index, _)| *index <= info.frame_index)
+        {
+            events.extend(self.events.pop_front().map(|(_, event)| event));
+        }
+        events
+    }
+}
+
+impl Deterministic {
+    /// Steps of 1/60 second and no input.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            step: UpdateRate::PerSecond(60).to_interval(),
+            input: None,
+
+            epoch: Instant::now(),
+            clock: Duration::from_secs(0),
+        }
+    }
+
+    /// The `FrameInfo::real_delta` of every frame.
+    pub fn with_step(mut self, step: Duration) -> Self {
+        self.step = step;
+        self
+    }
+
+    /// `with_step` of one `rate` interval.
+    pub fn with_rate(mut self, rate: UpdateRate) -> Self {
+        self.step = rate.to_interval();
+        self
+    }
+
+    /// The only input of the frame loop's inputs, without one they stay idle.
+    pub fn with_input<I: InputSource + 'static>(mut self, input: I) -> Self {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    /// The controlled clock of the inputs.
+    pub(crate) fn clock(&self) -> Instant {
+        self.epoch + self.clock
+    }
+
+    /// Advances the controlled clock by one step and returns it.
+    pub(crate) fn tick(&mut self) -> Instant {
+        self.clock += self.step;
+        self.clock()
+    }
+
+    pub(crate) fn events(&mut self, info: &FrameInfo) -> Vec<WindowEvent<'static>> {
+        self.input
+            .as_mut()
+            .map_or(Vec::new(), |input| input.events(info))
+    }
+}
+
+/// `FrameInfo::seed` of frame `frame_index` in a run seeded with `seed`.
+pub fn frame_seed(seed: u64, frame_index: u64) -> u64 {
+    let mut state = seed ^ frame_index.wrapping_mul(0xd1b5_4a32_d192_ed03);
+    split_mix(&mut state)
+}
+
+// splitmix64, expands one seed into well mixed words
+fn split_mix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // xoshiro256** seeded by SplitMix64, the values of the reference C
+    // implementations
+    #[test]
+    fn seed_zero_reference() {
+        let rng = Rng::new(0);
+        assert_eq!(
+            rng.state,
+            [
+                0xe220_a839_7b1d_cdaf,
+                0x6e78_9e6a_a1b9_65f4,
+                0x06c4_5d18_8009_454f,
+                0xf88b_b8a8_724c_81ec
+            ]
+        );
+
+        let mut rng = rng;
+        let values = (0..4).map(|_| rng.next_u64()).collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            [
+                0x99ec_5f36_cb75_f2b4,
+                0xbf6e_1f78_4956_452a,
+                0x1a5f_849d_4933_e6e0,
+                0x6aa5_94f1_262d_2d2c
+            ]
+        );
+    }
+
+    #[test]
+    fn seed_42_reference() {
+        let mut rng = Rng::new(42);
+        let values = (0..4).map(|_| rng.next_u64()).collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            [
+                0x1578_0b2e_0c2e_c716,
+                0x6104_d986_6d11_3a7e,
+                0xae17_5332_39e4_99a1,
+                0xecb8_ad47_03b3_60a1
+            ]
+        );
+    }
+
+    #[test]
+    fn derived_values() {
+        let mut rng = Rng::new(42);
+        let values = (0..3).map(|_| rng.next_u32()).collect::<Vec<_>>();
+        assert_eq!(values, [360_188_718, 1_627_707_782, 2_920_764_210]);
+
+        // 24 bit fractions are exact in f32
+        let mut rng = Rng::new(42);
+        let values = (0..3).map(|_| rng.next_f32()).collect::<Vec<_>>();
+        assert_eq!(values, [0.083_862_96, 0.378_980_22, 0.680_043_4]);
+
+        let mut rng = Rng::new(42);
+        let values = (0..5).map(|_| rng.range_u32(10..100)).collect::<Vec<_>>();
+        assert_eq!(values, [17, 44, 71, 93, 99]);
+    }
+
+    #[test]
+    #[allow(clippy::reversed_empty_ranges)]
+    fn empty_range() {
+        let mut rng = Rng::new(42);
+        assert_eq!(rng.range_u32(5..5), 5);
+        assert_eq!(rng.range_u32(7..3), 7);
+    }
+
+    #[test]
+    fn fork_is_reproducible() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+        assert_eq!(a.fork(), b.fork());
+        assert_eq!(a.next_u64(), b.next_u64());
+
+        let mut forked = Rng::new(7).fork();
+        assert_ne!(forked.next_u64(), Rng::new(7).next_u64());
+    }
+}
@@ -1,24 +1,96 @@
 use std::{
-    sync::Arc,
-    time::{Duration, Instant},
+    mem,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, TryRecvError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use log::debug;
+use log::{debug, error, warn};
 use parking_lot::RwLock;
 pub use winit::event::*;
-use winit::event_loop::EventLoop;
+use winit::{
+    dpi::PhysicalSize,
+    event_loop::{ControlFlow, EventLoop, EventLoopProxy},
+};
+
+use crate::{
+    io::input_state::InputState, logging, profiling, renderer::FramePerfReport, ExpectLog,
+    UpdateRate,
+};
 
-use crate::renderer::FramePerfReport;
+use super::{
+    deterministic::{frame_seed, Deterministic},
+    schedule::Scheduler,
+    task::TaskPool,
+};
 
 const PERF_LOG_INTERVAL: usize = 5;
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum CloseResponse {
+    Close,
+    /// Keeps the window open, to confirm unsaved changes first for ex.
+    Cancel,
+}
+
+/// The frame's time, the same for every target and subsystem in one frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct FrameInfo {
+    /// Counts from 0, paused frames included.
+    pub frame_index: u64,
+    /// Time since the previous frame, scaled by the `TimeScale`.
+    pub delta: Duration,
+    /// Sum of every `delta`.
+    pub elapsed: Duration,
+    /// Unscaled `delta`, for UI that keeps moving while paused.
+    pub real_delta: Duration,
+    /// Different every frame, for seeding an `Rng`. The same for the same
+    /// frame of a `Deterministic` run.
+    pub seed: u64,
+}
+
+/// Shared speed of `FrameInfo` time, 1.0 by default and 0.0 pauses.
+///
+/// Clones control the same scale, keep one after `FrameLoopBuilder::with_time_scale`.
+#[derive(Debug, Clone)]
+pub struct TimeScale {
+    // f32 bits
+    scale: Arc<AtomicU32>,
+}
+
 pub trait FrameLoopTarget {
-    fn frame(&self) -> FramePerfReport;
+    fn frame(&mut self, info: &FrameInfo) -> FramePerfReport;
+
+    /// Called once after the window was closed, on the thread calling `frame`.
+    ///
+    /// For saving state and destroying GPU resources in order, no frames
+    /// are in progress and no more are coming.
+    fn on_exit(&mut self) {}
 }
 
+/// Gets every window event, `DroppedFile` and `HoveredFile` included.
 pub trait EventLoopTarget {
     #[allow(unused_variables)]
     fn event(&mut self, event: &WindowEvent);
+
+    /// Called after the `CloseRequested` event, the window stays open if any target cancels.
+    fn close_requested(&mut self) -> CloseResponse {
+        CloseResponse::Close
+    }
+}
+
+/// Both targets in one, for `FrameLoopBuilder::with_target`.
+pub trait LoopTarget: FrameLoopTarget + EventLoopTarget {}
+
+impl<T: FrameLoopTarget + EventLoopTarget> LoopTarget for T {}
+
+enum OwnedTarget {
+    Local(Box<dyn LoopTarget>),
+    Send(Box<dyn LoopTarget + Send>),
 }
 
 pub struct FrameLoop {
@@ -29,6 +101,57 @@ pub struct FrameLoopBuilder {
     event_loop: EventLoop<()>,
     frame_targets: Vec<Arc<RwLock<dyn FrameLoopTarget + Send + Sync>>>,
     event_targets: Vec<Arc<RwLock<dyn EventLoopTarget + Send + Sync>>>,
+    targets: Vec<OwnedTarget>,
+    inputs: Vec<Arc<RwLock<InputState>>>,
+
+    render_thread: bool,
+    render_when_unfocused: bool,
+    unfocused_rate: UpdateRate,
+    time_scale: TimeScale,
+    schedulers: Vec<Scheduler>,
+    deterministic: Option<Deterministic>,
+    task_pool: TaskPool,
+}
+
+enum RenderThreadEvent {
+    Window(WindowEvent<'static>),
+    // `WindowEvent::ScaleFactorChanged` borrows the new size, it is rebuilt on the render thread
+    ScaleFactorChanged(f64, PhysicalSize<u32>),
+    // the render thread returns if no target cancels
+    CloseRequested,
+}
+
+// wakes the event loop when the render thread returned, closed or panicked
+struct WakeOnExit(EventLoopProxy<()>);
+
+// everything the thread calling `FrameLoopTarget::frame` owns,
+// `T` is `dyn LoopTarget + Send` on the render thread
+struct Frames<T: ?Sized> {
+    frame_targets: Vec<Arc<RwLock<dyn FrameLoopTarget + Send + Sync>>>,
+    targets: Vec<Box<T>>,
+    inputs: Vec<Arc<RwLock<InputState>>>,
+
+    render_when_unfocused: bool,
+    unfocused_interval: Duration,
+    focused: bool,
+    last_frame: Instant,
+
+    time_scale: TimeScale,
+    frame_index: u64,
+    elapsed: Duration,
+    schedulers: Vec<Scheduler>,
+    seed: u64,
+    deterministic: Option<Deterministic>,
+    // kept alive with the targets using it
+    _task_pool: TaskPool,
+
+    perf: PerfLog,
+}
+
+struct PerfLog {
+    since: Instant,
+    frames: usize,
+    average: FramePerfReport,
 }
 
 impl FrameLoop {
@@ -37,75 +160,151 @@ impl FrameLoop {
             event_loop: EventLoop::new(),
             frame_targets: Vec::new(),
             event_targets: Vec::new(),
+            targets: Vec::new(),
+            inputs: Vec::new(),
+
+            render_thread: false,
+            render_when_unfocused: true,
+            unfocused_rate: UpdateRate::PerSecond(30),
+            time_scale: TimeScale::new(),
+            schedulers: Vec::new(),
+            deterministic: None,
+            task_pool: TaskPool::new(),
         }
     }
 
     pub fn run(self) -> ! {
-        let event_loop = self.base.event_loop;
-        let frame_targets = self.base.frame_targets.clone();
-        let event_targets = self.base.event_targets;
+        let mut base = self.base;
+        let event_targets = mem::take(&mut base.event_targets);
+        let targets = mem::take(&mut base.targets);
+
+        let local = targets
+            .iter()
+            .any(|target| matches!(target, OwnedTarget::Local(_)));
+        if base.render_thread && local {
+            warn!("Render thread requested, but targets added with with_target are not Send, rendering in the event loop");
+        }
 
-        let mut frame_count_check_tp = Instant::now();
-        let mut frames: usize = 0;
-        let mut avg_perf = FramePerfReport::default();
+        if !base.render_thread || local {
+            let targets = targets
+                .into_iter()
+                .map(|target| match target {
+                    OwnedTarget::Local(target) => target,
+                    OwnedTarget::Send(target) => target as Box<dyn LoopTarget>,
+                })
+                .collect();
+            let frames = Frames::new(&mut base, targets);
+            Self::run_event_loop(base.event_loop, event_targets, frames)
+        } else {
+            let targets = targets
+                .into_iter()
+                .filter_map(|target| match target {
+                    OwnedTarget::Send(target) => Some(target),
+                    OwnedTarget::Local(_) => None,
+                })
+                .collect();
+            let frames = Frames::new(&mut base, targets);
+            Self::run_render_thread(base.event_loop, event_targets, frames)
+        }
+    }
 
+    fn run_event_loop(
+        event_loop: EventLoop<()>,
+        event_targets: Vec<Arc<RwLock<dyn EventLoopTarget + Send + Sync>>>,
+        mut frames: Frames<dyn LoopTarget>,
+    ) -> ! {
         event_loop.run(move |event, _, control_flow| {
-            *control_flow = winit::event_loop::ControlFlow::Poll;
+            *control_flow = ControlFlow::Poll;
             // debug!("event: {:?}", event);
 
             match event {
+                // the first delta would include the setup before run
+                Event::NewEvents(StartCause::Init) => frames.last_frame = Instant::now(),
                 Event::WindowEvent { event, .. } => {
                     for target in event_targets.iter() {
                         target.write().event(&event);
                     }
+                    frames.event(&event);
 
-                    match event {
-                        WindowEvent::CloseRequested => {
-                            *control_flow = winit::event_loop::ControlFlow::Exit;
+                    if let WindowEvent::CloseRequested = event {
+                        if close_requested(&event_targets) == CloseResponse::Close
+                            && frames.close_requested() == CloseResponse::Close
+                        {
+                            frames.exit();
+                            *control_flow = ControlFlow::Exit;
                         }
-                        _ => (),
                     }
                 }
                 Event::RedrawEventsCleared => {
-                    for target in frame_targets.iter() {
-                        let ft = target.read().frame();
-                        avg_perf.cpu_frametime += ft.cpu_frametime;
-                        avg_perf.gpu_frametime += ft.gpu_frametime;
-                        avg_perf.rerecord = avg_perf.rerecord || ft.rerecord;
-                        avg_perf.updates = avg_perf.updates || ft.updates;
-                        avg_perf.triangles = ft.triangles;
+                    *control_flow = frames.control_flow();
+                    if let ControlFlow::Poll = *control_flow {
+                        frames.frame();
+                    }
+                }
+                _ => (),
+            }
+        })
+    }
+
+    fn run_render_thread(
+        event_loop: EventLoop<()>,
+        event_targets: Vec<Arc<RwLock<dyn EventLoopTarget + Send + Sync>>>,
+        frames: Frames<dyn LoopTarget + Send>,
+    ) -> ! {
+        let (tx, rx) = mpsc::channel();
+        let wake = WakeOnExit(event_loop.create_proxy());
+        let mut join_handle = Some(
+            thread::Builder::new()
+                .name("gears render".into())
+                .spawn(move || {
+                    let _wake = wake;
+                    // the targets and their renderers drop before the wake up
+                    frames.run(rx)
+                })
+                .expect_log("Render thread creation failed"),
+        );
+
+        event_loop.run(move |event, _, control_flow| {
+            *control_flow = ControlFlow::Wait;
+
+            match event {
+                Event::WindowEvent { event, .. } => {
+                    for target in event_targets.iter() {
+                        target.write().event(&event);
+                    }
+
+                    let close = matches!(event, WindowEvent::CloseRequested);
+                    // only ScaleFactorChanged borrows, changing its size has no effect on the render thread
+                    match &event {
+                        WindowEvent::ScaleFactorChanged {
+                            scale_factor,
+                            new_inner_size,
+                        } => {
+                            let _ = tx.send(RenderThreadEvent::ScaleFactorChanged(
+                                *scale_factor,
+                                **new_inner_size,
+                            ));
+                        }
+                        _ => {
+                            if let Some(event) = event.to_static() {
+                                let _ = tx.send(RenderThreadEvent::Window(event));
+                            }
+                        }
+                    }
+
+                    // the render thread asks its targets and wakes the loop if it returns
+                    if close && close_requested(&event_targets) == CloseResponse::Close {
+                        let _ = tx.send(RenderThreadEvent::CloseRequested);
                     }
-                    frames += 1;
-
-                    if frame_count_check_tp.elapsed()
-                        > Duration::from_secs(PERF_LOG_INTERVAL as u64)
-                    {
-                        frame_count_check_tp = Instant::now();
-
-                        let cpu_ms =
-                            print_nanos(avg_perf.cpu_frametime.as_nanos() / frames as u128);
-                        let gpu_whole_ms = print_nanos(
-                            avg_perf.gpu_frametime.whole_pipeline.as_nanos() / frames as u128,
-                        );
-                        let gpu_vert_ms =
-                            print_nanos(avg_perf.gpu_frametime.vertex.as_nanos() / frames as u128);
-                        let gpu_frag_ms = print_nanos(
-                            avg_perf.gpu_frametime.fragment.as_nanos() / frames as u128,
-                        );
-
-                        debug!("Performance report (last {} seconds):", PERF_LOG_INTERVAL);
-                        debug!(" - real FPS: {}", frames / PERF_LOG_INTERVAL);
-                        debug!(" - latest triangles: {}", avg_perf.triangles);
-                        debug!(" - any updates: {}", avg_perf.updates);
-                        debug!(" - any rerecords: {}", avg_perf.rerecord);
-                        debug!(" - average CPU frametime: {}", cpu_ms);
-                        debug!(" - average GPU frametime: {}", gpu_whole_ms);
-                        debug!("   - vertex: {}", gpu_vert_ms);
-                        debug!("   - fragment: {}", gpu_frag_ms);
-
-                        frames = 0;
-                        avg_perf = FramePerfReport::default();
+                }
+                Event::UserEvent(()) => {
+                    // the thread already returned, joining does not block
+                    if let Some(join_handle) = join_handle.take() {
+                        if join_handle.join().is_err() {
+                            error!("Render thread panicked");
+                        }
                     }
+                    *control_flow = ControlFlow::Exit;
                 }
                 _ => (),
             }
@@ -113,6 +312,313 @@ impl FrameLoop {
     }
 }
 
+impl<T: LoopTarget + ?Sized> Frames<T> {
+    fn new(base: &mut FrameLoopBuilder, targets: Vec<Box<T>>) -> Self {
+        let seed = match base.deterministic.as_ref() {
+            Some(deterministic) => deterministic.seed(),
+            None => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_nanos() as u64),
+        };
+        let frames = Self {
+            frame_targets: mem::take(&mut base.frame_targets),
+            targets,
+            inputs: mem::take(&mut base.inputs),
+
+            render_when_unfocused: base.render_when_unfocused,
+            unfocused_interval: base.unfocused_rate.to_interval(),
+            focused: true,
+            last_frame: Instant::now(),
+
+            time_scale: base.time_scale.clone(),
+            frame_index: 0,
+            elapsed: Duration::from_secs(0),
+            schedulers: mem::take(&mut base.schedulers),
+            seed,
+            deterministic: base.deterministic.take(),
+            _task_pool: base.task_pool.clone(),
+
+            perf: PerfLog::new(),
+        };
+        // window events before the first frame are ignored too
+        if let Some(deterministic) = frames.deterministic.as_ref() {
+            for input in frames.inputs.iter() {
+                input
+                    .write()
+                    .set_controlled_time(Some(deterministic.clock()));
+            }
+        }
+        frames
+    }
+
+    fn event(&mut self, event: &WindowEvent) {
+        if let WindowEvent::Focused(f) = event {
+            self.focused = *f;
+        }
+        for target in self.targets.iter_mut() {
+            target.event(event);
+        }
+    }
+
+    fn close_requested(&mut self) -> CloseResponse {
+        let cancel = self
+            .targets
+            .iter_mut()
+            .any(|target| target.close_requested() == CloseResponse::Cancel);
+        if cancel {
+            CloseResponse::Cancel
+        } else {
+            CloseResponse::Close
+        }
+    }
+
+    fn exit(&mut self) {
+        for target in self.targets.iter_mut() {
+            target.on_exit();
+        }
+        for target in self.frame_targets.iter() {
+            target.write().on_exit();
+        }
+    }
+
+    /// `Poll` if the next frame is due now.
+    fn control_flow(&self) -> ControlFlow {
+        if self.focused {
+            ControlFlow::Poll
+        } else if !self.render_when_unfocused {
+            ControlFlow::Wait
+        } else {
+            let next_frame = self.last_frame + self.unfocused_interval;
+            if Instant::now() < next_frame {
+                ControlFlow::WaitUntil(next_frame)
+            } else {
+                ControlFlow::Poll
+            }
+        }
+    }
+
+    fn frame(&mut self) {
+        // ends the previous frame, before the scope of this one opens
+        profiling::frame_mark();
+        profile_scope!("FrameLoop::frame");
+        let now = Instant::now();
+        let real_delta = match self.deterministic.as_ref() {
+            Some(deterministic) => deterministic.step(),
+            None => now - self.last_frame,
+        };
+        self.last_frame = now;
+
+        let delta = real_delta.mul_f32(self.time_scale.get());
+        self.elapsed += delta;
+        let info = FrameInfo {
+            frame_index: self.frame_index,
+            delta,
+            elapsed: self.elapsed,
+            real_delta,
+            seed: frame_seed(self.seed, self.frame_index),
+        };
+        self.frame_index += 1;
+
+        for scheduler in self.schedulers.iter() {
+            scheduler.tick(&info);
+        }
+        match self.deterministic.as_mut() {
+            Some(deterministic) => {
+                let clock = deterministic.tick();
+                let events = deterministic.events(&info);
+                for input in self.inputs.iter() {
+                    let mut input = input.write();
+                    input.set_controlled_time(Some(clock));
+                    for event in events.iter() {
+                        input.update(event);
+                    }
+                    input.begin_frame();
+                }
+            }
+            None => {
+                for input in self.inputs.iter() {
+                    input.write().begin_frame();
+                }
+            }
+        }
+
+        let mut reports = Vec::new();
+        for target in self.targets.iter_mut() {
+            reports.push(target.frame(&info));
+        }
+        for target in self.frame_targets.iter() {
+            reports.push(target.write().frame(&info));
+        }
+
+        self.perf.add(reports);
+    }
+
+    // the render thread, returns when the event loop stops
+    fn run(mut self, rx: Receiver<RenderThreadEvent>) {
+        // the first delta would include the setup before run
+        self.last_frame = Instant::now();
+        loop {
+            let received = match self.control_flow() {
+                ControlFlow::Poll => match rx.try_recv() {
+                    Ok(event) => Some(event),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return,
+                },
+                ControlFlow::WaitUntil(next_frame) => {
+                    match rx.recv_timeout(next_frame.saturating_duration_since(Instant::now())) {
+                        Ok(event) => Some(event),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+                _ => match rx.recv() {
+                    Ok(event) => Some(event),
+                    Err(_) => return,
+                },
+            };
+
+            match received {
+                Some(RenderThreadEvent::Window(event)) => self.event(&event),
+                Some(RenderThreadEvent::ScaleFactorChanged(scale_factor, mut size)) => {
+                    self.event(&WindowEvent::ScaleFactorChanged {
+                        scale_factor,
+                        new_inner_size: &mut size,
+                    })
+                }
+                Some(RenderThreadEvent::CloseRequested) => {
+                    if self.close_requested() == CloseResponse::Close {
+                        self.exit();
+                        return;
+                    }
+                }
+                None => self.frame(),
+            }
+        }
+    }
+}
+
+impl TimeScale {
+    /// Larger scales turn one frame into hours.
+    pub const MAX: f32 = 1000.0;
+
+    pub fn new() -> Self {
+        Self {
+            scale: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+        }
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.scale.load(Ordering::Relaxed))
+    }
+
+    /// Clamped to `0.0..=MAX`, NaN and infinite scales are ignored.
+    pub fn set(&self, scale: f32) {
+        if !scale.is_finite() {
+            warn!("Ignored time scale {}", scale);
+            return;
+        }
+        self.scale
+            .store(scale.clamp(0.0, Self::MAX).to_bits(), Ordering::Relaxed);
+    }
+}
+
+impl Default for TimeScale {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PerfLog {
+    fn new() -> Self {
+        Self {
+            since: Instant::now(),
+            frames: 0,
+            average: FramePerfReport::default(),
+        }
+    }
+
+    fn add(&mut self, reports: Vec<FramePerfReport>) {
+        for ft in reports {
+            self.average.cpu_frametime += ft.cpu_frametime;
+            self.average.gpu_frametime += ft.gpu_frametime;
+            self.average.pipeline_stats += ft.pipeline_stats;
+            self.average.rerecord = self.average.rerecord || ft.rerecord;
+            self.average.updates = self.average.updates || ft.updates;
+            self.average.triangles = ft.triangles;
+            for scope in ft.scopes {
+                match self
+                    .average
+                    .scopes
+                    .iter_mut()
+                    .find(|s| s.name == scope.name)
+                {
+                    Some(existing) => *existing += scope,
+                    None => self.average.scopes.push(scope),
+                }
+            }
+        }
+        self.frames += 1;
+
+        if self.since.elapsed() > Duration::from_secs(PERF_LOG_INTERVAL as u64) {
+            self.since = Instant::now();
+
+            let cpu_ms = print_nanos(self.average.cpu_frametime.as_nanos() / self.frames as u128);
+            let gpu_whole_ms = print_nanos(
+                self.average.gpu_frametime.whole_pipeline.as_nanos() / self.frames as u128,
+            );
+            let gpu_vert_ms =
+                print_nanos(self.average.gpu_frametime.vertex.as_nanos() / self.frames as u128);
+            let gpu_frag_ms =
+                print_nanos(self.average.gpu_frametime.fragment.as_nanos() / self.frames as u128);
+
+            debug!(
+                target: logging::PERF,
+                "Performance report (last {} seconds):",
+                PERF_LOG_INTERVAL
+            );
+            debug!(
+                target: logging::PERF,
+                " - real FPS: {}",
+                self.frames / PERF_LOG_INTERVAL
+            );
+            debug!(
+                target: logging::PERF,
+                " - latest triangles: {}",
+                self.average.triangles
+            );
+            debug!(target: logging::PERF, " - any updates: {}", self.average.updates);
+            debug!(target: logging::PERF, " - any rerecords: {}", self.average.rerecord);
+            debug!(target: logging::PERF, " - average CPU frametime: {}", cpu_ms);
+            debug!(target: logging::PERF, " - average GPU frametime: {}", gpu_whole_ms);
+            debug!(target: logging::PERF, "   - vertex: {}", gpu_vert_ms);
+            debug!(target: logging::PERF, "   - fragment: {}", gpu_frag_ms);
+            debug!(
+                target: logging::PERF,
+                " - average primitives: {} ({} after clipping)",
+                self.average.pipeline_stats.primitives / self.frames as u64,
+                self.average.pipeline_stats.clipped_primitives / self.frames as u64
+            );
+            debug!(
+                target: logging::PERF,
+                " - average fragment invocations: {}",
+                self.average.pipeline_stats.fragment_invocations / self.frames as u64
+            );
+            for scope in self.average.scopes.iter() {
+                debug!(
+                    target: logging::PERF,
+                    " - scope '{}': CPU {}, GPU {}",
+                    scope.name,
+                    print_nanos(scope.cpu.as_nanos() / self.frames as u128),
+                    print_nanos(scope.gpu.as_nanos() / self.frames as u128)
+                );
+            }
+
+            self.frames = 0;
+            self.average = FramePerfReport::default();
+        }
+    }
+}
+
 impl FrameLoopBuilder {
     pub fn with_frame_target(
         mut self,
@@ -130,6 +636,89 @@ impl FrameLoopBuilder {
         self
     }
 
+    /// Event target whose `InputState::snapshot` is taken before every frame.
+    pub fn with_input(mut self, input: Arc<RwLock<InputState>>) -> Self {
+        self.event_targets.push(input.clone());
+        self.inputs.push(input);
+        self
+    }
+
+    /// Owned target for both frames and events, without locking.
+    ///
+    /// Shared targets get events first, so an `InputState` added with
+    /// `with_input` is up to date in `event` and `frame`.
+    pub fn with_target<T: LoopTarget + 'static>(mut self, target: T) -> Self {
+        self.targets.push(OwnedTarget::Local(Box::new(target)));
+        self
+    }
+
+    /// `with_target` for targets that can move to the render thread.
+    pub fn with_send_target<T: LoopTarget + Send + 'static>(mut self, target: T) -> Self {
+        self.targets.push(OwnedTarget::Send(Box::new(target)));
+        self
+    }
+
+    /// Calls `FrameLoopTarget::frame` on a render thread, off by default.
+    ///
+    /// The event loop stays on the main thread and long event callbacks,
+    /// like moving or resizing the window on Windows, no longer stall
+    /// frames. Shared event targets still get events on the main thread,
+    /// owned ones get them on the render thread before the next frame.
+    /// Owned targets have to be added with `with_send_target`, a target
+    /// added with `with_target` keeps rendering in the event loop.
+    pub fn with_render_thread(mut self, render_thread: bool) -> Self {
+        self.render_thread = render_thread;
+        self
+    }
+
+    /// Keeps rendering while the window is unfocused, at the `with_unfocused_rate`. On by default.
+    pub fn with_render_when_unfocused(mut self, render: bool) -> Self {
+        self.render_when_unfocused = render;
+        self
+    }
+
+    /// Frame rate limit while the window is unfocused, 30 per second by default.
+    pub fn with_unfocused_rate(mut self, rate: UpdateRate) -> Self {
+        self.unfocused_rate = rate;
+        self
+    }
+
+    /// Scales `FrameInfo::delta`, keep a clone to change it later.
+    pub fn with_time_scale(mut self, time_scale: TimeScale) -> Self {
+        self.time_scale = time_scale;
+        self
+    }
+
+    /// Ticks `scheduler` before every frame, keep a clone to schedule on it.
+    pub fn with_scheduler(mut self, scheduler: Scheduler) -> Self {
+        self.schedulers.push(scheduler);
+        self
+    }
+
+    /// Runs with fixed time steps, seeds and input, see `Deterministic`. Off by default.
+    pub fn with_deterministic(mut self, deterministic: Deterministic) -> Self {
+        self.deterministic = Some(deterministic);
+        self
+    }
+
+    /// Replaces the loop's `TaskPool`, to share one with another loop.
+    pub fn with_task_pool(mut self, task_pool: TaskPool) -> Self {
+        self.task_pool = task_pool;
+        self
+    }
+
+    /// Sizes the loop's `TaskPool`, one per core except for the render
+    /// thread by default. Clones handed out before keep the old pool.
+    pub fn with_task_threads(mut self, threads: usize) -> Self {
+        self.task_pool = TaskPool::with_threads(threads);
+        self
+    }
+
+    /// The worker threads of the loop, for the targets and the systems they own.
+    pub fn task_pool(&self) -> TaskPool {
+        self.task_pool.clone()
+    }
+
     pub fn with_event_loop(mut self, event_loop: EventLoop<()>) -> Self {
         self.event_loop = event_loop;
         self
@@ -140,6 +729,24 @@ impl FrameLoopBuilder {
     }
 }
 
+// asks until one cancels
+fn close_requested(targets: &[Arc<RwLock<dyn EventLoopTarget + Send + Sync>>]) -> CloseResponse {
+    let cancel = targets
+        .iter()
+        .any(|target| target.write().close_requested() == CloseResponse::Cancel);
+    if cancel {
+        CloseResponse::Cancel
+    } else {
+        CloseResponse::Close
+    }
+}
+
+impl Drop for WakeOnExit {
+    fn drop(&mut self) {
+        let _ = self.0.send_event(());
+    }
+}
+
 fn print_nanos(nanos: u128) -> String {
     if nanos > 1_000_000_000 {
         format!("{} seconds", nanos / 1_000_000_000)
@@ -151,3 +758,24 @@ fn print_nanos(nanos: u128) -> String {
         format!("{} nanoseconds", nanos)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_scale_is_clamped() {
+        let time_scale = TimeScale::new();
+        assert_eq!(1.0, time_scale.get());
+
+        time_scale.set(-2.0);
+        assert_eq!(0.0, time_scale.get());
+        time_scale.set(1e30);
+        assert_eq!(TimeScale::MAX, time_scale.get());
+
+        time_scale.set(0.5);
+        time_scale.set(f32::NAN);
+        time_scale.set(f32::INFINITY);
+        assert_eq!(0.5, time_scale.get());
+    }
+}
@@ -0,0 +1,302 @@
+use parking_lot::Mutex;
+use std::{mem, sync::Arc, time::Duration};
+
+use super::frame::FrameInfo;
+
+/// When a `Scheduler::spawn` coroutine runs next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resume {
+    NextFrame,
+    /// Seconds of `FrameInfo` time after it was due.
+    After(f32),
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TaskId(u64);
+
+/// Timers and simple coroutines ticked by the frame loop.
+///
+/// ```ignore
+/// let scheduler = Scheduler::new();
+/// scheduler.after(2.0, move |_| app.write().start());
+/// scheduler.every(0.5, move |info| println!("{:?}", info.elapsed));
+///
+/// FrameLoop::new().with_scheduler(scheduler.clone())
+/// ```
+///
+/// Tasks run before the frame targets, in `FrameInfo` time, so a paused
+/// `TimeScale` pauses them too. Clones schedule on the same tasks and
+/// tasks can schedule more while running.
+#[derive(Clone, Default)]
+pub struct Scheduler {
+    inner: Arc<Mutex<Tasks>>,
+}
+
+type TaskFn = Box<dyn FnMut(&FrameInfo) -> Resume + Send>;
+
+struct Task {
+    id: TaskId,
+    due: Duration,
+    run: TaskFn,
+}
+
+#[derive(Default)]
+struct Tasks {
+    next_id: u64,
+    now: Duration,
+    tasks: Vec<Task>,
+    // taken out by the current tick, cleared when it ends
+    running: Vec<TaskId>,
+    cancelled: Vec<TaskId>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `f` once, `seconds` from now.
+    pub fn after<F>(&self, seconds: f32, f: F) -> TaskId
+    where
+        F: FnOnce(&FrameInfo) + Send + 'static,
+    {
+        let mut f = Some(f);
+        self.schedule(seconds, move |info| {
+            if let Some(f) = f.take() {
+                f(info);
+            }
+            Resume::Done
+        })
+    }
+
+    /// Runs `f` every `seconds`, starting `seconds` from now.
+    ///
+    /// At most once per frame, missed runs are caught up on the next frames.
+    pub fn every<F>(&self, seconds: f32, mut f: F) -> TaskId
+    where
+        F: FnMut(&FrameInfo) + Send + 'static,
+    {
+        self.schedule(seconds, move |info| {
+            f(info);
+            Resume::After(seconds)
+        })
+    }
+
+    /// Runs `f` on the next frame and then whenever it asks to `Resume`.
+    ///
+    /// For staged sequences like loading, `f` keeps its own stage.
+    pub fn spawn<F>(&self, f: F) -> TaskId
+    where
+        F: FnMut(&FrameInfo) -> Resume + Send + 'static,
+    {
+        self.schedule(0.0, f)
+    }
+
+    /// Does nothing if the task already finished.
+    ///
+    /// A task due in the current tick that has not run yet does not run.
+    pub fn cancel(&self, id: TaskId) {
+        let mut inner = self.inner.lock();
+        let len = inner.tasks.len();
+        inner.tasks.retain(|task| task.id != id);
+        if inner.tasks.len() == len && inner.running.contains(&id) {
+            inner.cancelled.push(id);
+        }
+    }
+
+    /// Runs the due tasks, called by `FrameLoop` before every frame.
+    pub fn tick(&self, info: &FrameInfo) {
+        let due = {
+            let mut inner = self.inner.lock();
+            inner.now = info.elapsed;
+            let (due, waiting) = mem::take(&mut inner.tasks)
+                .into_iter()
+                .partition::<Vec<_>, _>(|task| task.due <= info.elapsed);
+            inner.tasks = waiting;
+            inner.running = due.iter().map(|task| task.id).collect();
+            due
+        };
+
+        for mut task in due {
+            // cancelled by a task that ran before it in this tick
+            if self.inner.lock().cancelled.contains(&task.id) {
+                continue;
+            }
+
+            let resume = (task.run)(info);
+
+            let mut inner = self.inner.lock();
+            if inner.cancelled.contains(&task.id) {
+                continue;
+            }
+            match resume {
+                // the next tick with any time passed
+                Resume::NextFrame => task.due = info.elapsed,
+                Resume::After(seconds) => task.due += seconds_duration(seconds),
+                Resume::Done => continue,
+            }
+            inner.tasks.push(task);
+        }
+
+        let mut inner = self.inner.lock();
+        inner.running.clear();
+        inner.cancelled.clear();
+    }
+
+    fn schedule<F>(&self, seconds: f32, f: F) -> TaskId
+    where
+        F: FnMut(&FrameInfo) -> Resume + Send + 'static,
+    {
+        let mut inner = self.inner.lock();
+        let id = TaskId(inner.next_id);
+        inner.next_id += 1;
+
+        let due = inner.now + seconds_duration(seconds);
+        inner.tasks.push(Task {
+            id,
+            due,
+            run: Box::new(f),
+        });
+        id
+    }
+}
+
+fn seconds_duration(seconds: f32) -> Duration {
+    Duration::from_secs_f32(seconds.max(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn at(seconds: f32) -> FrameInfo {
+        FrameInfo {
+            elapsed: Duration::from_secs_f32(seconds),
+            ..FrameInfo::default()
+        }
+    }
+
+    fn counter() -> (Arc<AtomicUsize>, Arc<AtomicUsize>) {
+        let count = Arc::new(AtomicUsize::new(0));
+        (count.clone(), count)
+    }
+
+    #[test]
+    fn after_runs_once_when_due() {
+        let scheduler = Scheduler::new();
+        let (count, counted) = counter();
+        scheduler.after(1.0, move |_| {
+            counted.fetch_add(1, Ordering::SeqCst);
+        });
+
+        scheduler.tick(&at(0.5));
+        assert_eq!(0, count.load(Ordering::SeqCst));
+        scheduler.tick(&at(1.0));
+        assert_eq!(1, count.load(Ordering::SeqCst));
+        scheduler.tick(&at(5.0));
+        assert_eq!(1, count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn every_repeats_and_catches_up() {
+        let scheduler = Scheduler::new();
+        let (count, counted) = counter();
+        scheduler.every(1.0, move |_| {
+            counted.fetch_add(1, Ordering::SeqCst);
+        });
+
+        scheduler.tick(&at(1.0));
+        scheduler.tick(&at(2.0));
+        assert_eq!(2, count.load(Ordering::SeqCst));
+
+        // at most once per frame
+        scheduler.tick(&at(4.5));
+        assert_eq!(3, count.load(Ordering::SeqCst));
+        scheduler.tick(&at(4.6));
+        assert_eq!(4, count.load(Ordering::SeqCst));
+        // caught up, the next run is at 5.0
+        scheduler.tick(&at(4.7));
+        assert_eq!(4, count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn spawn_resumes() {
+        let scheduler = Scheduler::new();
+        let (count, counted) = counter();
+        scheduler.spawn(move |_| match counted.fetch_add(1, Ordering::SeqCst) {
+            0 => Resume::NextFrame,
+            1 => Resume::After(1.0),
+            _ => Resume::Done,
+        });
+
+        scheduler.tick(&at(0.0));
+        scheduler.tick(&at(0.1));
+        assert_eq!(2, count.load(Ordering::SeqCst));
+        scheduler.tick(&at(0.5));
+        assert_eq!(2, count.load(Ordering::SeqCst));
+        scheduler.tick(&at(1.1));
+        scheduler.tick(&at(10.0));
+        assert_eq!(3, count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn cancel_waiting_task() {
+        let scheduler = Scheduler::new();
+        let (count, counted) = counter();
+        let id = scheduler.every(1.0, move |_| {
+            counted.fetch_add(1, Ordering::SeqCst);
+        });
+
+        scheduler.tick(&at(1.0));
+        scheduler.cancel(id);
+        scheduler.tick(&at(2.0));
+        assert_eq!(1, count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn cancel_due_in_the_same_tick() {
+        let scheduler = Scheduler::new();
+        let (count, counted) = counter();
+        let victim = Arc::new(Mutex::new(None));
+
+        let cancel = scheduler.clone();
+        let cancelled = victim.clone();
+        scheduler.after(1.0, move |_| {
+            if let Some(id) = cancelled.lock().take() {
+                cancel.cancel(id);
+            }
+        });
+        *victim.lock() = Some(scheduler.after(1.0, move |_| {
+            counted.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        scheduler.tick(&at(1.0));
+        assert_eq!(0, count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn cancel_from_itself_and_finished() {
+        let scheduler = Scheduler::new();
+        let (count, counted) = counter();
+        let own_id = Arc::new(Mutex::new(None::<TaskId>));
+
+        let cancel = scheduler.clone();
+        let id = own_id.clone();
+        *own_id.lock() = Some(scheduler.every(1.0, move |_| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            cancel.cancel(id.lock().unwrap());
+        }));
+
+        scheduler.tick(&at(1.0));
+        scheduler.tick(&at(2.0));
+        assert_eq!(1, count.load(Ordering::SeqCst));
+
+        // finished tasks leave nothing behind
+        scheduler.cancel(own_id.lock().unwrap());
+        let inner = scheduler.inner.lock();
+        assert!(inner.tasks.is_empty());
+        assert!(inner.cancelled.is_empty());
+    }
+}
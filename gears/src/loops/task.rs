@@ -0,0 +1,196 @@
+use log::error;
+use once_cell::sync::OnceCell;
+use rayon::prelude::*;
+use std::{
+    ops::Range,
+    sync::{
+        mpsc::{self, Receiver, TryRecvError},
+        Arc,
+    },
+};
+
+use crate::ExpectLog;
+
+/// Work stealing worker threads shared by the engine and the application.
+///
+/// The `FrameLoop` owns one, `FrameLoopBuilder::task_pool` hands out clones
+/// so the game, `Assets` decoding, the draw sorting of a `Renderer` given
+/// it with `RendererBuilder::with_task_pool` and systems like `Terrain`
+/// meshing spread their work over the same threads instead of each
+/// spawning their own.
+/// Clones use the same threads, which stop once every clone is dropped.
+///
+/// The parallel helpers block until all their work is done, so they can
+/// borrow frame local data:
+///
+/// ```ignore
+/// let frame_loop = FrameLoop::new();
+/// let pool = frame_loop.task_pool();
+/// pool.par_chunks_mut(&mut vertices, 1024, |chunk, vertices| {
+///     for (i, vertex) in vertices.iter_mut().enumerate() {
+///         *vertex = generate(chunk * 1024 + i);
+///     }
+/// });
+/// ```
+///
+/// Calling them from inside the pool runs the work on the same threads
+/// instead of deadlocking.
+///
+/// The threads start with the first task, so a pool that is replaced or
+/// never used does not spawn any.
+#[derive(Clone)]
+pub struct TaskPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    threads: usize,
+    pool: OnceCell<rayon::ThreadPool>,
+}
+
+/// The result of `TaskPool::spawn_with_result`.
+pub struct TaskHandle<T> {
+    rx: Receiver<T>,
+    result: Option<T>,
+}
+
+impl TaskPool {
+    /// One worker per core, except for one left to the render thread.
+    pub fn new() -> Self {
+        Self::with_threads(num_cpus::get().saturating_sub(1))
+    }
+
+    /// `threads` workers, at least one.
+    pub fn with_threads(threads: usize) -> Self {
+        Self {
+            inner: Arc::new(PoolInner {
+                threads: threads.max(1),
+                pool: OnceCell::new(),
+            }),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.inner.threads
+    }
+
+    fn pool(&self) -> &rayon::ThreadPool {
+        let threads = self.inner.threads;
+        self.inner.pool.get_or_init(|| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .thread_name(|index| format!("gears worker {}", index))
+                .panic_handler(|_| error!("Task panicked"))
+                .build()
+                .expect_log("Task pool creation failed")
+        })
+    }
+
+    /// Runs `f` on a worker without waiting for it.
+    pub fn spawn<F: FnOnce() + Send + 'static>(&self, f: F) {
+        self.pool().spawn(f);
+    }
+
+    /// `spawn` with a handle to poll for the result, for ex. once per frame.
+    pub fn spawn_with_result<T, F>(&self, f: F) -> TaskHandle<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        self.pool().spawn(move || {
+            let _ = tx.send(f());
+        });
+
+        TaskHandle { rx, result: None }
+    }
+
+    /// Runs `a` and `b`, possibly in parallel, and returns both results.
+    pub fn join<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA + Send,
+        B: FnOnce() -> RB + Send,
+        RA: Send,
+        RB: Send,
+    {
+        self.pool().install(|| rayon::join(a, b))
+    }
+
+    /// Calls `f` for every index of `range` in parallel.
+    pub fn par_for<F: Fn(usize) + Send + Sync>(&self, range: Range<usize>, f: F) {
+        self.pool().install(|| range.into_par_iter().for_each(f));
+    }
+
+    /// `f(item)` of every item in parallel, in the order of `items`.
+    pub fn par_map<T, U, F>(&self, items: &[T], f: F) -> Vec<U>
+    where
+        T: Sync,
+        U: Send,
+        F: Fn(&T) -> U + Send + Sync,
+    {
+        self.pool().install(|| items.par_iter().map(f).collect())
+    }
+
+    /// Calls `f` with the chunk index and every `chunk_size` long chunk of
+    /// `data` in parallel, the last one can be shorter.
+    pub fn par_chunks_mut<T, F>(&self, data: &mut [T], chunk_size: usize, f: F)
+    where
+        T: Send,
+        F: Fn(usize, &mut [T]) + Send + Sync,
+    {
+        self.pool().install(|| {
+            data.par_chunks_mut(chunk_size.max(1))
+                .enumerate()
+                .for_each(|(index, chunk)| f(index, chunk))
+        });
+    }
+
+    /// Sorts `data` by `f` in parallel, equal keys keep their order.
+    pub fn par_sort_by_key<T, K, F>(&self, data: &mut [T], f: F)
+    where
+        T: Send,
+        K: Ord + Send,
+        F: Fn(&T) -> K + Send + Sync,
+    {
+        self.pool().install(|| data.par_sort_by_key(f));
+    }
+}
+
+impl Default for TaskPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> TaskHandle<T> {
+    /// True once the result is ready or the task panicked.
+    pub fn is_done(&mut self) -> bool {
+        self.poll()
+    }
+
+    /// The result if it is ready, `None` before and after it was taken.
+    pub fn try_take(&mut self) -> Option<T> {
+        self.poll();
+        self.result.take()
+    }
+
+    /// Blocks until the result is ready, `None` if the task panicked.
+    pub fn wait(mut self) -> Option<T> {
+        self.result.take().or_else(|| self.rx.recv().ok())
+    }
+
+    // true if there is nothing left to wait for
+    fn poll(&mut self) -> bool {
+        if self.result.is_some() {
+            return true;
+        }
+        match self.rx.try_recv() {
+            Ok(result) => {
+                self.result = Some(result);
+                true
+            }
+            Err(TryRecvError::Empty) => false,
+            Err(TryRecvError::Disconnected) => true,
+        }
+    }
+}
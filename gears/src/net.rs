@@ -0,0 +1,324 @@
+//! Transform replication for simple multiplayer, with the `net` feature.
+//!
+//! The authority adds a `Replicator` to its `UpdateLoop` and sends the
+//! snapshots it produces over any transport. Receivers push them into an
+//! `InterpolationBuffer` and sample it every frame, which renders slightly
+//! in the past so there is always a newer snapshot to move towards:
+//!
+//! ```ignore
+//! // server
+//! let replicator = Arc::new(RwLock::new(
+//!     Replicator::<()>::new()
+//!         .with_send_interval(2)
+//!         .with_sender(move |snapshot| socket.send(&snapshot.to_bytes().unwrap()))
+//! ));
+//! UpdateLoop::new().with_rate(UpdateRate::PerSecond(60)).with_target(replicator.clone());
+//!
+//! // client
+//! let mut buffer = InterpolationBuffer::<()>::new(UpdateRate::PerSecond(60));
+//! buffer.push(Snapshot::from_bytes(&packet)?);
+//! buffer.advance(info.real_delta);
+//! for entity in buffer.sample() { .. }
+//! ```
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{loops::update::UpdateLoopTarget, scene::Transform, UpdateRate};
+
+// snapshots kept at most by an `InterpolationBuffer`
+const BUFFER_LEN: usize = 64;
+// ticks the buffer clock can drift before it jumps instead of catching up
+const MAX_DRIFT: f64 = 10.0;
+const CATCH_UP: f64 = 0.05;
+
+/// Identifies one replicated entity on every peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct NetId(pub u32);
+
+/// One entity in a `Snapshot`, `state` is any extra data like health or the animation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntityState<S> {
+    pub id: NetId,
+    pub transform: Transform,
+    pub state: S,
+}
+
+/// Every replicated entity at one fixed update tick, sorted by id.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot<S> {
+    pub tick: u64,
+    pub entities: Vec<EntityState<S>>,
+}
+
+#[derive(Debug)]
+pub enum NetError {
+    Encode(String),
+    Decode(String),
+}
+
+/// The authoritative side, produces a `Snapshot` every `with_send_interval`
+/// ticks of the `UpdateLoop` it is added to.
+pub struct Replicator<S> {
+    tick: u64,
+    send_interval: u64,
+    entities: BTreeMap<NetId, (Transform, S)>,
+    sender: Option<Box<dyn FnMut(&Snapshot<S>) + Send + Sync>>,
+}
+
+/// The receiving side, smooths the snapshots of a `Replicator` into a
+/// transform per frame.
+///
+/// Samples lag `with_delay` ticks behind the newest snapshot. Translation
+/// and scale are interpolated linearly and rotation along the shortest
+/// arc, `state` comes from the older of the two snapshots. Out of order and
+/// duplicate snapshots are dropped. If no newer snapshot arrives in time,
+/// entities stay at their last transform instead of extrapolating.
+pub struct InterpolationBuffer<S> {
+    interval: Duration,
+    delay: f64,
+    snapshots: VecDeque<Snapshot<S>>,
+    // the sampled tick, `None` until the first snapshot
+    time: Option<f64>,
+}
+
+impl<S: Serialize + DeserializeOwned> Snapshot<S> {
+    /// Compact binary encoding for sending, `from_bytes` reverses it.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, NetError> {
+        bincode::serialize(self).map_err(|err| NetError::Encode(err.to_string()))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, NetError> {
+        bincode::deserialize(bytes).map_err(|err| NetError::Decode(err.to_string()))
+    }
+}
+
+impl<S> Snapshot<S> {
+    pub fn get(&self, id: NetId) -> Option<&EntityState<S>> {
+        self.entities
+            .binary_search_by_key(&id, |entity| entity.id)
+            .ok()
+            .map(|index| &self.entities[index])
+    }
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetError::Encode(err) => write!(f, "Snapshot encoding failed: {}", err),
+            NetError::Decode(err) => write!(f, "Snapshot decoding failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for NetError {}
+
+impl<S: Clone> Replicator<S> {
+    pub fn new() -> Self {
+        Self {
+            tick: 0,
+            send_interval: 1,
+            entities: BTreeMap::new(),
+            sender: None,
+        }
+    }
+
+    /// Ticks between two snapshots, 1 by default.
+    pub fn with_send_interval(mut self, ticks: u64) -> Self {
+        self.send_interval = ticks.max(1);
+        self
+    }
+
+    /// Called with every snapshot, on the update loop thread.
+    pub fn with_sender<F: FnMut(&Snapshot<S>) + Send + Sync + 'static>(
+        mut self,
+        sender: F,
+    ) -> Self {
+        self.sender = Some(Box::new(sender));
+        self
+    }
+
+    /// Adds or moves the entity `id`.
+    pub fn set(&mut self, id: NetId, transform: Transform, state: S) {
+        self.entities.insert(id, (transform, state));
+    }
+
+    /// Receivers drop the entity once they sample past the next snapshot.
+    pub fn remove(&mut self, id: NetId) {
+        self.entities.remove(&id);
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// The current state, also for sending a full state to late joiners.
+    pub fn snapshot(&self) -> Snapshot<S> {
+        Snapshot {
+            tick: self.tick,
+            entities: self
+                .entities
+                .iter()
+                .map(|(id, (transform, state))| EntityState {
+                    id: *id,
+                    transform: *transform,
+                    state: state.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<S: Clone> Default for Replicator<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Clone> UpdateLoopTarget for Replicator<S> {
+    fn update(&mut self, _: &Duration) {
+        self.tick += 1;
+        if self.tick % self.send_interval != 0 {
+            return;
+        }
+
+        if self.sender.is_some() {
+            let snapshot = self.snapshot();
+            if let Some(sender) = self.sender.as_mut() {
+                sender(&snapshot);
+            }
+        }
+    }
+}
+
+impl<S: Clone> InterpolationBuffer<S> {
+    /// `rate` is the one of the sender's `UpdateLoop`.
+    pub fn new(rate: UpdateRate) -> Self {
+        Self {
+            interval: rate.to_interval(),
+            delay: 3.0,
+            snapshots: VecDeque::new(),
+            time: None,
+        }
+    }
+
+    /// Ticks behind the newest snapshot, 3 by default. Should cover at
+    /// least two send intervals plus the network jitter.
+    pub fn with_delay(mut self, ticks: f32) -> Self {
+        self.delay = ticks.max(0.0) as f64;
+        self
+    }
+
+    /// False if `snapshot` is a duplicate or already older than what is sampled.
+    pub fn push(&mut self, snapshot: Snapshot<S>) -> bool {
+        let too_old = self
+            .snapshots
+            .front()
+            .map_or(false, |oldest| snapshot.tick < oldest.tick);
+        if too_old {
+            return false;
+        }
+
+        let at = self
+            .snapshots
+            .iter()
+            .position(|existing| existing.tick >= snapshot.tick)
+            .unwrap_or(self.snapshots.len());
+        let duplicate = self
+            .snapshots
+            .get(at)
+            .map_or(false, |existing| existing.tick == snapshot.tick);
+        if duplicate {
+            return false;
+        }
+
+        self.snapshots.insert(at, snapshot);
+        if self.snapshots.len() > BUFFER_LEN {
+            self.snapshots.pop_front();
+        }
+        true
+    }
+
+    /// Moves the sampled time forward by `delta`, usually `FrameInfo::real_delta`.
+    ///
+    /// Speeds up or slows down slightly to stay `with_delay` behind the
+    /// newest snapshot, and jumps if it is far off after a stall.
+    pub fn advance(&mut self, delta: Duration) {
+        let newest = match self.snapshots.back() {
+            Some(newest) => newest.tick as f64,
+            None => return,
+        };
+        let target = newest - self.delay;
+
+        let time = match self.time {
+            Some(time) => {
+                let time = time + delta.as_secs_f64() / self.interval.as_secs_f64();
+                if (target - time).abs() > MAX_DRIFT {
+                    target
+                } else {
+                    time + (target - time) * CATCH_UP
+                }
+            }
+            None => target,
+        };
+        self.time = Some(time);
+
+        // keep the snapshot right before the sampled time
+        while self.snapshots.len() > 2 && self.snapshots[1].tick as f64 <= time {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// The sampled tick, `None` until the first `advance` after a `push`.
+    pub fn time(&self) -> Option<f64> {
+        self.time
+    }
+
+    pub fn latest(&self) -> Option<&Snapshot<S>> {
+        self.snapshots.back()
+    }
+
+    /// Every entity at the sampled time.
+    pub fn sample(&self) -> Vec<EntityState<S>> {
+        let time = match self.time {
+            Some(time) => time,
+            None => return Vec::new(),
+        };
+
+        let next = self
+            .snapshots
+            .iter()
+            .position(|snapshot| snapshot.tick as f64 > time);
+        let (from, to) = match next {
+            // before every snapshot
+            Some(0) => (&self.snapshots[0], None),
+            Some(next) => (&self.snapshots[next - 1], Some(&self.snapshots[next])),
+            None => match self.snapshots.back() {
+                Some(last) => (last, None),
+                None => return Vec::new(),
+            },
+        };
+
+        let to = match to {
+            Some(to) => to,
+            None => return from.entities.clone(),
+        };
+        let t = ((time - from.tick as f64) / (to.tick - from.tick) as f64) as f32;
+        from.entities
+            .iter()
+            .map(|entity| EntityState {
+                id: entity.id,
+                transform: match to.get(entity.id) {
+                    Some(next) => entity.transform.interpolate(&next.transform, t),
+                    None => entity.transform,
+                },
+                state: entity.state.clone(),
+            })
+            .collect()
+    }
+}
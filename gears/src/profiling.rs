@@ -0,0 +1,99 @@
+//! Engine internals for external profilers, with the `puffin` or `tracy` feature.
+//!
+//! Frame stages like waiting for the frame fence, acquiring the swapchain
+//! image and recording become puffin scopes and Tracy zones next to the
+//! ones of the game. The frame loop marks every frame.
+//!
+//! GPU timings come from timestamp queries and arrive a few frames late.
+//! puffin shows them on a `GPU` thread with the `RenderRecordInfo::scope`s
+//! back to back, so their durations are exact but their offsets are not.
+//! Tracy gets them as plots.
+
+#[cfg(any(feature = "puffin", feature = "tracy"))]
+use std::time::Duration;
+
+use crate::renderer::query::{PerfQueryResult, ScopeTiming};
+
+#[cfg(feature = "tracy")]
+static GPU_FRAMETIME: tracy_client::Plot = tracy_client::create_plot!("GPU frametime (ms)");
+#[cfg(feature = "tracy")]
+static GPU_VERTEX: tracy_client::Plot = tracy_client::create_plot!("GPU vertex (ms)");
+#[cfg(feature = "tracy")]
+static GPU_FRAGMENT: tracy_client::Plot = tracy_client::create_plot!("GPU fragment (ms)");
+
+/// Profiler scope until the end of the block, nothing without the profiler features.
+macro_rules! profile_scope {
+    ($name:expr) => {
+        #[cfg(feature = "puffin")]
+        puffin::profile_scope!($name);
+        #[cfg(feature = "tracy")]
+        let _tracy_span = tracy_client::span!($name);
+    };
+}
+
+/// Ends the current profiler frame.
+pub(crate) fn frame_mark() {
+    #[cfg(feature = "puffin")]
+    puffin::GlobalProfiler::lock().new_frame();
+    #[cfg(feature = "tracy")]
+    tracy_client::finish_continuous_frame!();
+}
+
+/// Reports the GPU timings of one frame, zero if timestamps are not supported.
+#[allow(unused_variables)]
+pub(crate) fn gpu_timings(gpu: &PerfQueryResult, scopes: &[ScopeTiming]) {
+    #[cfg(any(feature = "puffin", feature = "tracy"))]
+    if gpu.whole_pipeline == Duration::from_secs(0) {
+        return;
+    }
+
+    #[cfg(feature = "puffin")]
+    puffin_gpu_timings(gpu, scopes);
+
+    #[cfg(feature = "tracy")]
+    {
+        GPU_FRAMETIME.point(millis(gpu.whole_pipeline));
+        GPU_VERTEX.point(millis(gpu.vertex));
+        GPU_FRAGMENT.point(millis(gpu.fragment));
+    }
+}
+
+#[cfg(feature = "puffin")]
+fn puffin_gpu_timings(gpu: &PerfQueryResult, scopes: &[ScopeTiming]) {
+    if !puffin::are_scopes_on() {
+        return;
+    }
+
+    // the GPU has its own clock, the frame is placed to end now
+    let end = puffin::now_ns();
+    let start = end - gpu.whole_pipeline.as_nanos() as puffin::NanoSecond;
+
+    let mut stream = puffin::Stream::default();
+    let frame = stream.begin_scope(start, "frame", "", "");
+    let mut at = start;
+    for scope in scopes {
+        let offset = stream.begin_scope(at, scope.name, "", "");
+        at = (at + scope.gpu.as_nanos() as puffin::NanoSecond).min(end);
+        stream.end_scope(offset, at);
+    }
+    stream.end_scope(frame, end);
+
+    let info = puffin::StreamInfo {
+        stream,
+        num_scopes: 1 + scopes.len(),
+        depth: if scopes.is_empty() { 1 } else { 2 },
+        range_ns: (start, end),
+    };
+    puffin::GlobalProfiler::lock().report(
+        puffin::ThreadInfo {
+            start_time_ns: None,
+            name: "GPU".to_owned(),
+        },
+        &info.as_stream_into_ref(),
+    );
+}
+
+#[cfg(feature = "tracy")]
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,666 @@
+use cgmath::{
+    EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Transform, Vector2, Vector3,
+};
+use std::{ops::Range, sync::Arc};
+
+use crate::{camera::unproject_inverse, scene::Scene};
+
+// scene meshes per BVH leaf
+const LEAF_SIZE: usize = 4;
+
+/// A half line, `direction` is normalized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+/// Axis aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+/// How exactly `SceneBvh::raycast` tests meshes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RaycastPrecision {
+    /// Against their world space bounding boxes, enough for coarse picking.
+    Bounds,
+    /// Against their triangles.
+    Triangles,
+}
+
+/// The closest thing a ray hit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    /// Index of the scene node.
+    pub node: usize,
+    /// Along the ray in world units.
+    pub distance: f32,
+    pub position: Point3<f32>,
+    /// Index of the triangle in the node's `CollisionMesh`, `None` for bounds hits.
+    pub triangle: Option<usize>,
+}
+
+/// CPU copy of a mesh's positions for raycasts, usually a low poly version.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollisionMesh {
+    positions: Vec<Point3<f32>>,
+    indices: Vec<u32>,
+    bounds: Aabb,
+}
+
+/// Bounding volume hierarchy over the meshes of a `Scene`, for picking,
+/// line of sight checks and editor selection without a physics engine.
+///
+/// ```ignore
+/// let bvh = SceneBvh::new(&scene, |path| collision_meshes.get(path).cloned());
+///
+/// let ray = Ray::from_cursor(cursor, viewport, &inverse_view_projection);
+/// if let Some(hit) = bvh.raycast(&ray, RaycastPrecision::Triangles) {
+///     selected = Some(hit.node);
+/// }
+/// ```
+///
+/// Nodes are looked up by their `SceneNode::mesh` path, nodes without a
+/// mesh or a `CollisionMesh` for it are left out. The tree is a snapshot:
+/// `refit` it after moving nodes, build it again after adding or removing them.
+pub struct SceneBvh {
+    entries: Vec<Entry>,
+    nodes: Vec<BvhNode>,
+}
+
+struct Entry {
+    node: usize,
+    mesh: Arc<CollisionMesh>,
+    // world to mesh space, None for degenerate transforms
+    inverse: Option<Matrix4<f32>>,
+    bounds: Aabb,
+}
+
+enum BvhNode {
+    Branch {
+        bounds: Aabb,
+        left: usize,
+        right: usize,
+    },
+    Leaf {
+        bounds: Aabb,
+        entries: Range<usize>,
+    },
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Self {
+        Self {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    /// Through the pixel `cursor` of a `viewport` sized viewport, for mouse picking.
+    ///
+    /// `cursor` is in pixels from the top left like `camera::unproject`.
+    pub fn from_cursor(
+        cursor: Vector2<f32>,
+        viewport: Vector2<f32>,
+        inverse_view_projection: &Matrix4<f32>,
+    ) -> Self {
+        let near = unproject_inverse(cursor, 0.0, viewport, inverse_view_projection);
+        // not 1.0, which is at infinity with an infinite far plane
+        let far = unproject_inverse(cursor, 0.5, viewport, inverse_view_projection);
+        Self::new(near, far - near)
+    }
+
+    /// From `from` towards `to`, with the distance between them.
+    pub fn between(from: Point3<f32>, to: Point3<f32>) -> (Self, f32) {
+        let offset = to - from;
+        (Self::new(from, offset), offset.magnitude())
+    }
+
+    pub fn at(&self, distance: f32) -> Point3<f32> {
+        self.origin + self.direction * distance
+    }
+}
+
+impl Aabb {
+    /// Contains nothing, the start for `union`s.
+    pub fn empty() -> Self {
+        Self {
+            min: Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points<I: IntoIterator<Item = Point3<f32>>>(points: I) -> Self {
+        points.into_iter().fold(Self::empty(), |aabb, point| Self {
+            min: Point3::new(
+                aabb.min.x.min(point.x),
+                aabb.min.y.min(point.y),
+                aabb.min.z.min(point.z),
+            ),
+            max: Point3::new(
+                aabb.max.x.max(point.x),
+                aabb.max.y.max(point.y),
+                aabb.max.z.max(point.z),
+            ),
+        })
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self::from_points([self.min, self.max, other.min, other.max].iter().copied())
+    }
+
+    /// The box around this one transformed by `matrix`.
+    pub fn transformed(&self, matrix: &Matrix4<f32>) -> Self {
+        Self::from_points((0..8).map(|corner| {
+            let pick = |bit: usize, min: f32, max: f32| if corner & bit == 0 { min } else { max };
+            matrix.transform_point(Point3::new(
+                pick(1, self.min.x, self.max.x),
+                pick(2, self.min.y, self.max.y),
+                pick(4, self.min.z, self.max.z),
+            ))
+        }))
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    /// Distance along `ray` to where it enters the box, 0.0 if it starts inside.
+    pub fn intersect(&self, ray: &Ray) -> Option<f32> {
+        let mut near = 0.0_f32;
+        let mut far = f32::INFINITY;
+        for axis in 0..3 {
+            // infinite for axis parallel rays, which then only hit from inside the slab
+            let inverse = 1.0 / ray.direction[axis];
+            let a = (self.min[axis] - ray.origin[axis]) * inverse;
+            let b = (self.max[axis] - ray.origin[axis]) * inverse;
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+        }
+        if near <= far {
+            Some(near)
+        } else {
+            None
+        }
+    }
+}
+
+impl CollisionMesh {
+    /// Triangle list, three `indices` per triangle.
+    pub fn new(positions: Vec<Point3<f32>>, indices: Vec<u32>) -> Self {
+        let bounds = Aabb::from_points(positions.iter().copied());
+        Self {
+            positions,
+            indices,
+            bounds,
+        }
+    }
+
+    /// Copies the positions of the vertices `position` reads.
+    pub fn from_vertices<V, F: Fn(&V) -> Point3<f32>>(
+        vertices: &[V],
+        indices: &[u32],
+        position: F,
+    ) -> Self {
+        Self::new(vertices.iter().map(position).collect(), indices.to_vec())
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    /// The closest triangle `ray` hits from either side, with the distance
+    /// in units of `ray.direction`.
+    ///
+    /// Tested triangle by triangle after the bounds.
+    pub fn intersect(&self, ray: &Ray) -> Option<(f32, usize)> {
+        self.bounds.intersect(ray)?;
+
+        let mut closest: Option<(f32, usize)> = None;
+        for (triangle, indices) in self.indices.chunks_exact(3).enumerate() {
+            let a = self.positions[indices[0] as usize];
+            let b = self.positions[indices[1] as usize];
+            let c = self.positions[indices[2] as usize];
+
+            // Möller-Trumbore
+            let (ab, ac) = (b - a, c - a);
+            let p = ray.direction.cross(ac);
+            let det = ab.dot(p);
+            if det.abs() < 1e-8 {
+                continue;
+            }
+            let inverse = 1.0 / det;
+            let to_origin = ray.origin - a;
+            let u = to_origin.dot(p) * inverse;
+            if !(0.0..=1.0).contains(&u) {
+                continue;
+            }
+            let q = to_origin.cross(ab);
+            let v = ray.direction.dot(q) * inverse;
+            if v < 0.0 || u + v > 1.0 {
+                continue;
+            }
+            let distance = ac.dot(q) * inverse;
+            if distance >= 0.0 && closest.map_or(true, |(closest, _)| distance < closest) {
+                closest = Some((distance, triangle));
+            }
+        }
+        closest
+    }
+}
+
+impl SceneBvh {
+    /// `meshes` returns the collision mesh for a `SceneNode::mesh` path.
+    pub fn new<F: FnMut(&str) -> Option<Arc<CollisionMesh>>>(scene: &Scene, mut meshes: F) -> Self {
+        let transforms = scene.world_transforms();
+        let mut entries = scene
+            .nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, node)| {
+                let mesh = meshes(node.mesh.as_ref()?)?;
+                Some(Entry::new(index, mesh, &transforms[index]))
+            })
+            .collect::<Vec<_>>();
+
+        let mut nodes = Vec::new();
+        if !entries.is_empty() {
+            let len = entries.len();
+            build(&mut entries, 0..len, &mut nodes);
+        }
+        Self { entries, nodes }
+    }
+
+    /// Updates the transforms and bounds after nodes moved, keeping the
+    /// tree's structure.
+    ///
+    /// Cheaper than building it again but the tree gets slower the further
+    /// nodes move from where they were built.
+    pub fn refit(&mut self, scene: &Scene) {
+        let transforms = scene.world_transforms();
+        for entry in self.entries.iter_mut() {
+            *entry = Entry::new(entry.node, entry.mesh.clone(), &transforms[entry.node]);
+        }
+
+        // children come after their parents
+        for index in (0..self.nodes.len()).rev() {
+            let new_bounds = match &self.nodes[index] {
+                BvhNode::Branch { left, right, .. } => self.nodes[*left]
+                    .bounds()
+                    .union(self.nodes[*right].bounds()),
+                BvhNode::Leaf { entries, .. } => bounds_of(&self.entries[entries.clone()]),
+            };
+            match &mut self.nodes[index] {
+                BvhNode::Branch { bounds, .. } | BvhNode::Leaf { bounds, .. } => {
+                    *bounds = new_bounds
+                }
+            }
+        }
+    }
+
+    /// Number of scene nodes in the tree.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The closest hit along `ray`.
+    pub fn raycast(&self, ray: &Ray, precision: RaycastPrecision) -> Option<Hit> {
+        self.raycast_filtered(ray, precision, f32::INFINITY, |_| true)
+    }
+
+    /// The closest hit along `ray` closer than `max_distance`, of nodes `filter` returns true for.
+    pub fn raycast_filtered<F: FnMut(usize) -> bool>(
+        &self,
+        ray: &Ray,
+        precision: RaycastPrecision,
+        max_distance: f32,
+        mut filter: F,
+    ) -> Option<Hit> {
+        let mut closest: Option<Hit> = None;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+
+        while let Some(index) = stack.pop() {
+            let limit = closest.map_or(max_distance, |hit| hit.distance);
+            match self.nodes[index].bounds().intersect(ray) {
+                Some(distance) if distance < limit => {}
+                _ => continue,
+            }
+
+            match &self.nodes[index] {
+                BvhNode::Branch { left, right, .. } => {
+                    stack.push(*right);
+                    stack.push(*left);
+                }
+                BvhNode::Leaf { entries, .. } => {
+                    for entry in self.entries[entries.clone()].iter() {
+                        if !filter(entry.node) {
+                            continue;
+                        }
+                        let limit = closest.map_or(max_distance, |hit| hit.distance);
+                        if let Some(hit) = entry.intersect(ray, precision) {
+                            if hit.distance < limit {
+                                closest = Some(hit);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        closest
+    }
+
+    /// True if nothing blocks the segment from `from` to `to`, nodes
+    /// `filter` returns false for are ignored, for ex. the looking one.
+    pub fn line_of_sight<F: FnMut(usize) -> bool>(
+        &self,
+        from: Point3<f32>,
+        to: Point3<f32>,
+        precision: RaycastPrecision,
+        filter: F,
+    ) -> bool {
+        let (ray, distance) = Ray::between(from, to);
+        self.raycast_filtered(&ray, precision, distance, filter)
+            .is_none()
+    }
+}
+
+impl Entry {
+    fn new(node: usize, mesh: Arc<CollisionMesh>, world: &Matrix4<f32>) -> Self {
+        Self {
+            node,
+            bounds: mesh.bounds().transformed(world),
+            inverse: world.invert(),
+            mesh,
+        }
+    }
+
+    fn intersect(&self, ray: &Ray, precision: RaycastPrecision) -> Option<Hit> {
+        let inverse = match (precision, self.inverse) {
+            (RaycastPrecision::Triangles, Some(inverse)) => inverse,
+            _ => {
+                let distance = self.bounds.intersect(ray)?;
+                return Some(Hit {
+                    node: self.node,
+                    distance,
+                    position: ray.at(distance),
+                    triangle: None,
+                });
+            }
+        };
+
+        // not normalized, so distances in mesh space are world distances
+        let local = Ray {
+            origin: inverse.transform_point(ray.origin),
+            direction: inverse.transform_vector(ray.direction),
+        };
+        let (distance, triangle) = self.mesh.intersect(&local)?;
+        Some(Hit {
+            node: self.node,
+            distance,
+            position: ray.at(distance),
+            triangle: Some(triangle),
+        })
+    }
+}
+
+impl BvhNode {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            BvhNode::Branch { bounds, .. } | BvhNode::Leaf { bounds, .. } => bounds,
+        }
+    }
+}
+
+// median split along the longest axis of the centers, returns the node index
+fn build(entries: &mut [Entry], range: Range<usize>, nodes: &mut Vec<BvhNode>) -> usize {
+    let bounds = bounds_of(&entries[range.clone()]);
+    let index = nodes.len();
+    if range.len() <= LEAF_SIZE {
+        nodes.push(BvhNode::Leaf {
+            bounds,
+            entries: range,
+        });
+        return index;
+    }
+
+    let centers = Aabb::from_points(entries[range.clone()].iter().map(|e| e.bounds.center()));
+    let extent = centers.max - centers.min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+    entries[range.clone()].sort_unstable_by(|a, b| {
+        a.bounds.center()[axis]
+            .partial_cmp(&b.bounds.center()[axis])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    // reserved before the children, which come after it
+    nodes.push(BvhNode::Leaf {
+        bounds,
+        entries: 0..0,
+    });
+    let middle = range.start + range.len() / 2;
+    let left = build(entries, range.start..middle, nodes);
+    let right = build(entries, middle..range.end, nodes);
+    nodes[index] = BvhNode::Branch {
+        bounds,
+        left,
+        right,
+    };
+    index
+}
+
+fn bounds_of(entries: &[Entry]) -> Aabb {
+    entries
+        .iter()
+        .fold(Aabb::empty(), |aabb, entry| aabb.union(&entry.bounds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{SceneNode, Transform};
+
+    fn cube() -> Arc<CollisionMesh> {
+        let positions = (0..8)
+            .map(|corner| {
+                let pick = |bit: usize| if corner & bit == 0 { -0.5 } else { 0.5 };
+                Point3::new(pick(1), pick(2), pick(4))
+            })
+            .collect();
+        #[rustfmt::skip]
+        let indices = vec![
+            0, 1, 3, 0, 3, 2, // -z
+            4, 5, 7, 4, 7, 6, // +z
+            0, 1, 5, 0, 5, 4, // -y
+            2, 3, 7, 2, 7, 6, // +y
+            0, 2, 6, 0, 6, 4, // -x
+            1, 3, 7, 1, 7, 5, // +x
+        ];
+        Arc::new(CollisionMesh::new(positions, indices))
+    }
+
+    // `count` unit cubes along x, 2 units apart
+    fn row(count: usize) -> Scene {
+        let mut scene = Scene::default();
+        scene.add(SceneNode::new("no mesh"));
+        for i in 0..count {
+            scene.add(
+                SceneNode::new(format!("cube {}", i))
+                    .with_mesh("cube")
+                    .with_transform(Transform::from_translation(Vector3::new(
+                        i as f32 * 2.0,
+                        0.0,
+                        0.0,
+                    ))),
+            );
+        }
+        scene
+    }
+
+    fn bvh(scene: &Scene) -> SceneBvh {
+        let cube = cube();
+        SceneBvh::new(scene, |path| Some(cube.clone()).filter(|_| path == "cube"))
+    }
+
+    fn contains(outer: &Aabb, inner: &Aabb) -> bool {
+        (0..3).all(|axis| outer.min[axis] <= inner.min[axis] && inner.max[axis] <= outer.max[axis])
+    }
+
+    const PRECISIONS: [RaycastPrecision; 2] =
+        [RaycastPrecision::Bounds, RaycastPrecision::Triangles];
+
+    #[test]
+    fn build_covers_every_entry() {
+        let bvh = bvh(&row(21));
+        assert_eq!(bvh.len(), 21);
+        assert!(bvh.nodes.len() > 1);
+
+        let mut seen = vec![0; bvh.entries.len()];
+        for node in bvh.nodes.iter() {
+            match node {
+                BvhNode::Branch {
+                    bounds,
+                    left,
+                    right,
+                } => {
+                    assert!(*left > 0 && *right > 0);
+                    assert!(contains(bounds, bvh.nodes[*left].bounds()));
+                    assert!(contains(bounds, bvh.nodes[*right].bounds()));
+                }
+                BvhNode::Leaf { bounds, entries } => {
+                    assert!(entries.len() <= LEAF_SIZE);
+                    for entry in entries.clone() {
+                        assert!(contains(bounds, &bvh.entries[entry].bounds));
+                        seen[entry] += 1;
+                    }
+                }
+            }
+        }
+        assert!(seen.iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn empty_scene() {
+        let bvh = bvh(&row(0));
+        assert!(bvh.is_empty());
+        let ray = Ray::new(Point3::new(0.0, 0.0, 10.0), -Vector3::unit_z());
+        assert_eq!(bvh.raycast(&ray, RaycastPrecision::Bounds), None);
+    }
+
+    #[test]
+    fn hits_the_cube_below() {
+        let bvh = bvh(&row(21));
+        for &precision in PRECISIONS.iter() {
+            for i in 0..21 {
+                let ray = Ray::new(Point3::new(i as f32 * 2.0, 0.1, 10.0), -Vector3::unit_z());
+                let hit = bvh.raycast(&ray, precision).unwrap();
+                assert_eq!(hit.node, i + 1);
+                assert!((hit.distance - 9.5).abs() < 1e-4);
+                assert!((hit.position.z - 0.5).abs() < 1e-4);
+                assert_eq!(
+                    hit.triangle.is_some(),
+                    precision == RaycastPrecision::Triangles
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn hits_the_closest() {
+        let bvh = bvh(&row(21));
+        for &precision in PRECISIONS.iter() {
+            let ray = Ray::new(Point3::new(100.0, 0.0, 0.1), -Vector3::unit_x());
+            let hit = bvh.raycast(&ray, precision).unwrap();
+            assert_eq!(hit.node, 21);
+            assert!((hit.distance - 59.5).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn misses() {
+        let bvh = bvh(&row(21));
+        for &precision in PRECISIONS.iter() {
+            // between two cubes, above the row and pointing away
+            let rays = [
+                Ray::new(Point3::new(1.0, 0.0, 10.0), -Vector3::unit_z()),
+                Ray::new(Point3::new(0.0, 5.0, 10.0), -Vector3::unit_z()),
+                Ray::new(Point3::new(0.0, 0.0, 10.0), Vector3::unit_z()),
+            ];
+            for ray in rays.iter() {
+                assert_eq!(bvh.raycast(ray, precision), None);
+            }
+        }
+    }
+
+    #[test]
+    fn triangles_are_finer_than_bounds() {
+        let triangle = Arc::new(CollisionMesh::new(
+            vec![
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+            ],
+            vec![0, 1, 2],
+        ));
+        let mut scene = Scene::default();
+        scene.add(SceneNode::new("triangle").with_mesh("triangle"));
+        let bvh = SceneBvh::new(&scene, |_| Some(triangle.clone()));
+
+        let ray = Ray::new(Point3::new(0.9, 0.9, 1.0), -Vector3::unit_z());
+        assert!(bvh.raycast(&ray, RaycastPrecision::Bounds).is_some());
+        assert_eq!(bvh.raycast(&ray, RaycastPrecision::Triangles), None);
+
+        let ray = Ray::new(Point3::new(0.2, 0.2, 1.0), -Vector3::unit_z());
+        let hit = bvh.raycast(&ray, RaycastPrecision::Triangles).unwrap();
+        assert_eq!(hit.triangle, Some(0));
+        assert!((hit.distance - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn filter_and_line_of_sight() {
+        let bvh = bvh(&row(3));
+        let ray = Ray::new(Point3::new(-10.0, 0.0, 0.0), Vector3::unit_x());
+        let precision = RaycastPrecision::Triangles;
+
+        let hit = bvh
+            .raycast_filtered(&ray, precision, f32::INFINITY, |node| node != 1)
+            .unwrap();
+        assert_eq!(hit.node, 2);
+        assert_eq!(bvh.raycast_filtered(&ray, precision, 9.0, |_| true), None);
+
+        let (from, to) = (Point3::new(-10.0, 0.0, 0.0), Point3::new(10.0, 0.0, 0.0));
+        assert!(!bvh.line_of_sight(from, to, precision, |_| true));
+        assert!(bvh.line_of_sight(from, to, precision, |_| false));
+        assert!(bvh.line_of_sight(from, Point3::new(-5.0, 0.0, 0.0), precision, |_| true));
+    }
+
+    #[test]
+    fn refit_follows_moved_nodes() {
+        let mut scene = row(21);
+        let mut bvh = bvh(&scene);
+        scene.nodes[1].transform = Transform::from_translation(Vector3::new(0.0, 50.0, 0.0));
+        bvh.refit(&scene);
+
+        let ray = Ray::new(Point3::new(0.0, 50.0, 10.0), -Vector3::unit_z());
+        for &precision in PRECISIONS.iter() {
+            assert_eq!(bvh.raycast(&ray, precision).unwrap().node, 1);
+        }
+        let ray = Ray::new(Point3::new(0.0, 0.0, 10.0), -Vector3::unit_z());
+        assert_eq!(bvh.raycast(&ray, RaycastPrecision::Triangles), None);
+    }
+}
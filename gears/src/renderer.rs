@@ -1,51 +1,128 @@
+pub mod bindless;
 pub mod buffer;
-mod device;
+mod capture;
+pub mod cluster;
+pub mod cull;
+pub mod descriptor;
+pub(crate) mod device;
+pub mod diagnostics;
 pub mod object;
+mod pick;
 pub mod pipeline;
+pub mod post;
+pub mod present;
+pub mod probe;
+pub mod procedural;
 pub mod query;
 pub mod queue;
+pub mod raytracing;
+pub mod readback;
+pub mod record;
+mod scale;
+pub mod settings;
+pub mod sort;
+pub mod sync;
+pub mod target;
 
+#[cfg(feature = "short_namespaces")]
+pub use bindless::*;
 #[cfg(feature = "short_namespaces")]
 pub use buffer::*;
 #[cfg(feature = "short_namespaces")]
+pub use cluster::*;
+#[cfg(feature = "short_namespaces")]
+pub use cull::*;
+#[cfg(feature = "short_namespaces")]
+pub use descriptor::*;
+#[cfg(feature = "short_namespaces")]
+pub use diagnostics::*;
+#[cfg(feature = "short_namespaces")]
 pub use object::*;
 #[cfg(feature = "short_namespaces")]
 pub use pipeline::*;
 #[cfg(feature = "short_namespaces")]
+pub use post::*;
+#[cfg(feature = "short_namespaces")]
+pub use present::*;
+#[cfg(feature = "short_namespaces")]
+pub use probe::*;
+#[cfg(feature = "short_namespaces")]
+pub use procedural::*;
+#[cfg(feature = "short_namespaces")]
 pub use query::*;
 #[cfg(feature = "short_namespaces")]
 pub use queue::*;
+#[cfg(feature = "short_namespaces")]
+pub use raytracing::*;
+#[cfg(feature = "short_namespaces")]
+pub use readback::*;
+#[cfg(feature = "short_namespaces")]
+pub use record::*;
+#[cfg(feature = "short_namespaces")]
+pub use settings::*;
+#[cfg(feature = "short_namespaces")]
+pub use sort::*;
+#[cfg(feature = "short_namespaces")]
+pub use sync::*;
+#[cfg(feature = "short_namespaces")]
+pub use target::*;
 
 use crate::{
-    context::{Context, ContextError},
+    camera,
+    context::{Context, ContextError, Limits},
+    debug, logging,
+    loops::{frame::FrameInfo, task::TaskPool},
+    profiling,
     renderer::device::ReducedContext,
-    MapErrorElseLogResult, MapErrorLog, SyncMode,
+    ColorSpace, MapErrorElseLogResult, MapErrorLog, SyncMode,
 };
 
-use ash::{extensions::khr, version::DeviceV1_0, vk};
-use buffer::{image::Image, image::ImageBuilder, image::ImageFormat, image::ImageUsage};
-use cgmath::Vector4;
-use log::{debug, error};
-use parking_lot::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use ash::{
+    extensions::khr,
+    version::{DeviceV1_0, InstanceV1_0},
+    vk,
+};
+use buffer::{
+    image::has_stencil, image::Image, image::ImageBuilder, image::ImageFormat, image::ImageUsage,
+    image::Layout,
+};
+use cgmath::{Matrix4, Point3, Vector2, Vector4};
+use log::{debug, error, warn};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard};
 use std::{
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
         mpsc::{self, Receiver, Sender},
         Arc,
     },
     thread::{self, JoinHandle},
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
 use self::{
-    buffer::image::BaseFormat,
+    buffer::{image::BaseFormat, streamed::TextureBudget, transient::TransientStats},
+    capture::RenderDoc,
+    descriptor::{DescriptorPoolConfig, DescriptorStats, LayoutStats},
     device::RenderDevice,
-    query::{PerfQuery, PerfQueryResult},
+    pick::Picker,
+    pipeline::{DebugView, DEFAULT_MAX_LIGHTS},
+    query::{
+        PerfQuery, PerfQueryResult, PipelineStatsQuery, PipelineStatsResult, ProfileScope,
+        ScopeQuery, ScopeTiming,
+    },
+    scale::ResolutionScaler,
+    settings::{RendererSettings, SettingsChanges},
+    sync::GpuTimeline,
+    target::RenderTarget,
 };
 
 pub struct FramePerfReport {
     pub cpu_frametime: Duration,
     pub gpu_frametime: PerfQueryResult,
+    /// Zero if pipeline statistics queries are not supported.
+    pub pipeline_stats: PipelineStatsResult,
+    /// `RenderRecordInfo::scope` timings by name.
+    pub scopes: Vec<ScopeTiming>,
 
     pub rerecord: bool,
     pub updates: bool,
@@ -55,6 +132,7 @@ pub struct FramePerfReport {
 struct SwapchainObjects {
     extent: vk::Extent2D,
     format: vk::SurfaceFormatKHR,
+    depth_format: vk::Format,
     present: vk::PresentModeKHR,
     viewport: vk::Viewport,
     scissor: vk::Rect2D,
@@ -69,7 +147,6 @@ struct SwapchainObjects {
 }
 
 struct RenderObject {
-    rerecord_requested: bool,
     image_in_use_fence: vk::Fence,
 
     _color_image: Image,
@@ -82,6 +159,8 @@ struct RenderObject {
     update_cb_pending: bool,
     command_pool: vk::CommandPool,
     perf: PerfQuery,
+    stats: Option<PipelineStatsQuery>,
+    scopes: Arc<ScopeQuery>,
     triangles: usize,
 }
 
@@ -100,14 +179,24 @@ enum PresentThreadEvent {
 }
 
 pub struct ImmediateFrameInfo {
+    /// The swapchain image this frame renders to.
+    ///
+    /// Per image resources (UBOs for ex.) with this index are not in use by the GPU.
     pub image_index: usize,
 }
 
 pub struct RenderRecordInfo {
+    device: Arc<RenderDevice>,
     command_buffer: vk::CommandBuffer,
     image_index: usize,
+    extent: vk::Extent2D,
+    render_scale: f32,
+    debug_view: DebugView,
     triangles: AtomicUsize,
     debug_calls: bool,
+    draws: Mutex<sort::DrawQueue<usize>>,
+    scopes: Arc<ScopeQuery>,
+    task_pool: TaskPool,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -121,26 +210,33 @@ pub struct UpdateRecordInfo {
     image_index: usize,
 }
 
-pub trait RendererRecord {
-    #[allow(unused_variables)]
-    fn immediate(&self, imfi: &ImmediateFrameInfo) {}
-
-    #[allow(unused_variables)]
-    fn update(&self, uri: &UpdateRecordInfo) -> bool {
-        // 'any' all object updates and return the result of that
-        false
-    }
-
-    #[allow(unused_variables)]
-    fn begin_info(&self) -> RenderRecordBeginInfo {
-        RenderRecordBeginInfo {
-            clear_color: Vector4::new(0.18, 0.18, 0.2, 1.0),
-            debug_calls: false,
-        }
-    }
+/// When `RendererRecord::frame` records the swapchain command buffers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordMode {
+    /// Every swapchain image keeps its last recording and resubmits it until
+    /// `Renderer::request_rerecord`, for mostly static scenes. Per image UBOs
+    /// and uploads still change what the recording draws.
+    Reuse,
+    /// Every frame is recorded again, for scenes where the draws change all the time.
+    EveryFrame,
+}
 
-    #[allow(unused_variables)]
-    fn record(&self, rri: &RenderRecordInfo) {}
+pub trait RendererRecord {
+    /// Called once per frame, after the previous use of the swapchain image finished.
+    ///
+    /// `frame.time()` is the `FrameInfo` given to `Renderer::frame`.
+    /// Write per image data with `frame.immediate()`, copy it to the device
+    /// in `frame.upload()` and draw in `frame.record(..)`. The calls are not
+    /// ordered for you: uploads are submitted before the frame's draws
+    /// wherever they are made, but an upload only copies the writes made
+    /// before it.
+    ///
+    /// Each swapchain image has its own command buffer, `frame.record(..)` is
+    /// only `Some` the first time an image is rendered to after
+    /// `Renderer::request_rerecord` or in `RecordMode::EveryFrame`. Otherwise
+    /// the image's last recording is submitted again, and everything it uses
+    /// has to stay alive.
+    fn frame(&mut self, frame: &mut record::FrameCtx);
 }
 
 pub struct RendererData {
@@ -161,12 +257,47 @@ pub struct Renderer {
     frame: AtomicUsize,
     frames_in_flight: usize,
 
+    // signaled with the frame count by every render submission
+    timeline: Option<GpuTimeline>,
+    timeline_value: AtomicU64,
+    timeline_waits: Mutex<Vec<(vk::Semaphore, u64)>>,
+
+    scaler: Mutex<ResolutionScaler>,
+    scale_callbacks: Mutex<Vec<Box<dyn FnMut(f32) + Send>>>,
+
+    spike_detector: Mutex<Option<diagnostics::SpikeDetector>>,
+
+    settings: Mutex<RendererSettings>,
+
+    // one per swapchain image, outside of the render objects so frames can request rerecords
+    rerecord_requested: Vec<AtomicBool>,
+    record_mode: Mutex<RecordMode>,
+
+    debug_views: bool,
+    debug_view: Mutex<DebugView>,
+    max_lights: u32,
+    task_pool: TaskPool,
+
+    picker: Option<Picker>,
+
+    render_doc: Option<RenderDoc>,
+    capture_requested: AtomicBool,
+
     rdevice: Arc<RenderDevice>,
 }
 
 pub struct RendererBuilder {
     sync: SyncMode,
+    settings: RendererSettings,
+    color_space: ColorSpace,
+    stencil: bool,
     frames_in_flight: usize,
+    debug_views: bool,
+    picking: bool,
+    record_mode: RecordMode,
+    descriptor_pools: DescriptorPoolConfig,
+    max_lights: u32,
+    task_pool: Option<TaskPool>,
 }
 
 impl Default for FramePerfReport {
@@ -174,6 +305,8 @@ impl Default for FramePerfReport {
         Self {
             cpu_frametime: Duration::from_secs(0),
             gpu_frametime: PerfQueryResult::default(),
+            pipeline_stats: PipelineStatsResult::default(),
+            scopes: Vec::new(),
 
             rerecord: false,
             updates: false,
@@ -189,6 +322,7 @@ impl RenderObject {
         render_pass: vk::RenderPass,
         color_image: vk::Image,
         color_format: vk::Format,
+        depth_format: vk::Format,
         extent: vk::Extent2D,
     ) -> Result<Self, ContextError> {
         let color_image = ImageBuilder::new_with_device(rdevice.clone())
@@ -198,7 +332,7 @@ impl RenderObject {
         let depth_image = ImageBuilder::new_with_device(rdevice.clone())
             .with_width(extent.width)
             .with_height(extent.height)
-            .build(ImageUsage::WRITE, ImageFormat::<f32>::D)
+            .build(ImageUsage::WRITE, depth_format)
             .map_err_log("Depth image creation failed", ContextError::OutOfMemory)?;
 
         let attachments = [color_image.view(), depth_image.view()];
@@ -239,7 +373,6 @@ impl RenderObject {
         let render_cb = command_buffers.remove(0);
 
         Ok(Self {
-            rerecord_requested: true,
             image_in_use_fence: vk::Fence::null(),
 
             _color_image: color_image,
@@ -251,12 +384,192 @@ impl RenderObject {
             update_cb_recording: false,
             update_cb_pending: false,
             command_pool,
-            perf: PerfQuery::new_with_device(rdevice),
+            perf: PerfQuery::new_with_device(rdevice.clone()),
+            stats: PipelineStatsQuery::new_with_device(rdevice.clone()),
+            scopes: Arc::new(ScopeQuery::new_with_device(rdevice)),
             triangles: 0,
         })
     }
 }
 
+impl Default for RecordMode {
+    fn default() -> Self {
+        RecordMode::Reuse
+    }
+}
+
+impl Default for RenderRecordBeginInfo {
+    fn default() -> Self {
+        Self {
+            clear_color: Vector4::new(0.18, 0.18, 0.2, 1.0),
+            debug_calls: false,
+        }
+    }
+}
+
+impl RenderRecordInfo {
+    /// Queues transparent draw `id` to be recorded after all opaque draws.
+    ///
+    /// `view_depth` is the distance from the camera, the order only changes
+    /// when the frame is rerecorded so moving cameras should `request_rerecord`
+    /// every frame.
+    pub fn queue_transparent(&self, view_depth: f32, id: usize) {
+        self.queue_draw(sort::DrawKey::transparent(view_depth), id);
+    }
+
+    /// Queues draw `id` to be recorded in `key` order, see `DrawScope::sorted`.
+    ///
+    /// Like `queue_transparent` the order only changes when the frame is rerecorded.
+    pub fn queue_draw(&self, key: sort::DrawKey, id: usize) {
+        self.draws.lock().push(key, id);
+    }
+
+    /// Worker threads for culling or other CPU work of the recording, the
+    /// `Renderer::task_pool`.
+    pub fn task_pool(&self) -> &TaskPool {
+        &self.task_pool
+    }
+
+    /// Compute work recorded with this is ordered before the swapchain render pass.
+    ///
+    /// Only valid in a `RecordScope`, outside of `RenderTarget` passes.
+    pub fn as_update_info(&self) -> UpdateRecordInfo {
+        UpdateRecordInfo {
+            command_buffer: self.command_buffer,
+            image_index: self.image_index,
+        }
+    }
+
+    /// Records a barrier moving `image` from its last recorded layout to `layout`.
+    ///
+    /// For custom passes in a `RecordScope`, outside of
+    /// `RenderTarget` passes. Layouts are tracked in recording order, so a
+    /// command buffer should leave images in the layout it found them in.
+    pub fn transition<I: AsRef<Image> + ?Sized>(&self, image: &I, layout: Layout) {
+        unsafe { image.as_ref().transition(self.command_buffer, layout) };
+    }
+
+    /// Measures the CPU and GPU time of everything recorded until the guard is dropped.
+    ///
+    /// ```ignore
+    /// let _scope = rri.scope("terrain");
+    /// terrain.draw(rri);
+    /// ```
+    ///
+    /// Reported in `FramePerfReport::scopes` by name, scopes can nest.
+    pub fn scope(&self, name: &'static str) -> ProfileScope<'_> {
+        ProfileScope::new(self, name)
+    }
+
+    /// The swapchain image this is recorded for.
+    pub fn image_index(&self) -> usize {
+        self.image_index
+    }
+
+    /// The swapchain size in pixels this is recorded for.
+    pub fn extent(&self) -> (u32, u32) {
+        (self.extent.width, self.extent.height)
+    }
+
+    /// The dynamic resolution scale this is recorded for, see `Renderer::set_target_frame_time`.
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    /// The debug view this is recorded for, `Pipeline::bind` follows it automatically.
+    pub fn debug_view(&self) -> DebugView {
+        self.debug_view
+    }
+
+    /// Sets the reference value of pipelines built with `with_stencil`, 0 by default.
+    pub unsafe fn set_stencil_reference(&self, value: u32) {
+        if self.debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_set_stencil_reference");
+        }
+
+        self.device.cmd_set_stencil_reference(
+            self.command_buffer,
+            vk::StencilFaceFlags::FRONT_AND_BACK,
+            value,
+        );
+    }
+
+    /// Sets the viewport and the scissor to `rect`, for drawing to part of the
+    /// swapchain or a `RenderTarget`. `RenderTarget::begin` resets both.
+    pub unsafe fn set_viewport(&self, rect: vk::Rect2D) {
+        let viewport = vk::Viewport {
+            x: rect.offset.x as f32,
+            y: rect.offset.y as f32,
+            width: rect.extent.width as f32,
+            height: rect.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+
+        if self.debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_set_viewport");
+        }
+
+        self.device
+            .cmd_set_viewport(self.command_buffer, 0, &[viewport]);
+        self.device.cmd_set_scissor(self.command_buffer, 0, &[rect]);
+    }
+
+    /// Clears `rect` of the first color attachment to `color` and of the depth
+    /// attachment to the far plane, inside of a render pass.
+    pub unsafe fn clear_rect(&self, rect: vk::Rect2D, color: Vector4<f32>) {
+        let attachments = [
+            vk::ClearAttachment {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                color_attachment: 0,
+                clear_value: vk::ClearValue {
+                    color: vk::ClearColorValue {
+                        float32: [color.x, color.y, color.z, color.w],
+                    },
+                },
+            },
+            vk::ClearAttachment {
+                aspect_mask: vk::ImageAspectFlags::DEPTH,
+                color_attachment: 0,
+                clear_value: vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: 1.0,
+                        stencil: 0,
+                    },
+                },
+            },
+        ];
+        let rects = [vk::ClearRect {
+            rect,
+            base_array_layer: 0,
+            layer_count: 1,
+        }];
+
+        if self.debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_clear_attachments");
+        }
+
+        self.device
+            .cmd_clear_attachments(self.command_buffer, &attachments, &rects);
+    }
+
+    // every queued draw or only one pass, in key order
+    fn sorted_draws(&self, transparent: Option<bool>) -> Vec<usize> {
+        let draws = self
+            .draws
+            .lock()
+            .drain_sorted_on(transparent, &self.task_pool);
+        draws.into_iter().map(|(_, id)| id).collect()
+    }
+}
+
+impl UpdateRecordInfo {
+    /// See `RenderRecordInfo::transition`.
+    pub fn transition<I: AsRef<Image> + ?Sized>(&self, image: &I, layout: Layout) {
+        unsafe { image.as_ref().transition(self.command_buffer, layout) };
+    }
+}
+
 impl ConcurrentRenderObject {
     fn new(device: &Arc<RenderDevice>) -> Result<Self, ContextError> {
         let semaphore_info = vk::SemaphoreCreateInfo::builder();
@@ -279,17 +592,28 @@ impl Renderer {
     pub fn new() -> RendererBuilder {
         RendererBuilder {
             sync: SyncMode::default(),
+            settings: RendererSettings::default(),
+            color_space: ColorSpace::default(),
+            stencil: false,
             frames_in_flight: 3,
+            debug_views: false,
+            picking: false,
+            record_mode: RecordMode::default(),
+            descriptor_pools: DescriptorPoolConfig::default(),
+            max_lights: DEFAULT_MAX_LIGHTS,
+            task_pool: None,
         }
     }
 
     fn wait_in_use_render_object(&self, crender_object: &RwLockReadGuard<ConcurrentRenderObject>) {
+        profile_scope!("wait for frame");
         let fence = [crender_object.frame_fence];
         unsafe { self.rdevice.wait_for_fences(&fence, true, !0) }
             .expect("Failed to wait for fence");
     }
 
     fn acquire_image(&self, crender_object: &RwLockReadGuard<ConcurrentRenderObject>) -> usize {
+        profile_scope!("acquire image");
         let data = self.data.read();
         let swapchain_objects = data.swapchain_objects.read();
 
@@ -306,7 +630,8 @@ impl Renderer {
         }
     }
 
-    pub fn frame<T: RendererRecord>(&self, recorder: &T) -> FramePerfReport {
+    pub fn frame<T: RendererRecord>(&self, info: &FrameInfo, recorder: &mut T) -> FramePerfReport {
+        profile_scope!("Renderer::frame");
         let cpu_frametime = Instant::now();
         let data = self.data.read();
 
@@ -338,28 +663,51 @@ impl Renderer {
         let fence = [crender_object.frame_fence];
         unsafe { self.rdevice.reset_fences(&fence) }.expect("Failed to reset fence");
 
-        // update buffers
-        self.update(recorder, &mut render_object, image_index);
-        self.immediate(recorder, image_index);
-        let rerecord = render_object.rerecord_requested;
-        if rerecord {
-            self.record(recorder, &mut render_object, image_index);
-            render_object.rerecord_requested = false;
+        let capture = self
+            .render_doc
+            .as_ref()
+            .filter(|_| self.capture_requested.swap(false, Ordering::SeqCst));
+        if let Some(render_doc) = capture {
+            render_doc.start_capture();
         }
+
+        // before a rerecord replaces the scope names
+        let scopes = render_object.scopes.get().unwrap_or_default();
+
+        // record
+        if *self.record_mode.lock() == RecordMode::EveryFrame {
+            self.rerecord_requested[image_index].store(true, Ordering::SeqCst);
+        }
+        let rerecord = self.rerecord_requested[image_index].load(Ordering::SeqCst);
+        self.begin_update(&mut render_object);
+        let updates = {
+            profile_scope!("record");
+            let mut frame_ctx = record::FrameCtx::new(self, &mut render_object, image_index, *info);
+            recorder.frame(&mut frame_ctx);
+            frame_ctx.finish()
+        };
+        // every UBO written this frame at once
+        if let Err(err) = self.rdevice.uniform_arena.flush(&self.rdevice) {
+            error!(target: logging::MEMORY, "UBO flush failed: {:?}", err);
+        }
+        self.end_update(&mut render_object, updates);
         let gpu_frametime = render_object
             .perf
             .get()
             .unwrap_or(PerfQueryResult::default());
+        let pipeline_stats = render_object
+            .stats
+            .as_ref()
+            .and_then(|stats| stats.get().ok())
+            .unwrap_or(PipelineStatsResult::default());
 
         // submit
 
         let render_cb = [render_object.render_cb];
         let image_wait = [crender_object.image_semaphore];
         let update_wait = [crender_object.update_semaphore];
-        let render_wait = [crender_object.render_semaphore];
-        let render_stage = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
 
-        let submit_render = if render_object.update_cb_pending {
+        let first_wait = if render_object.update_cb_pending {
             let update_cb = [render_object.update_cb];
             let update_stage = [vk::PipelineStageFlags::ALL_COMMANDS];
 
@@ -379,21 +727,41 @@ impl Renderer {
             }
             .expect("Transfer queue submit failed");
 
-            [vk::SubmitInfo::builder()
-                .command_buffers(&render_cb)
-                .wait_semaphores(&update_wait)
-                .signal_semaphores(&render_wait)
-                .wait_dst_stage_mask(&render_stage)
-                .build()]
+            crender_object.update_semaphore
         } else {
-            [vk::SubmitInfo::builder()
-                .command_buffers(&render_cb)
-                .wait_semaphores(&image_wait)
-                .signal_semaphores(&render_wait)
-                .wait_dst_stage_mask(&render_stage)
-                .build()]
+            crender_object.image_semaphore
         };
 
+        // binary semaphores ignore their value
+        let mut render_wait = vec![first_wait];
+        let mut render_wait_values = vec![0];
+        let mut render_stage = vec![vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        for (semaphore, value) in self.timeline_waits.lock().drain(..) {
+            render_wait.push(semaphore);
+            render_wait_values.push(value);
+            render_stage.push(vk::PipelineStageFlags::ALL_COMMANDS);
+        }
+
+        let mut render_signal = vec![crender_object.render_semaphore];
+        let mut render_signal_values = vec![0];
+        if let Some(timeline) = self.timeline.as_ref() {
+            render_signal.push(timeline.get());
+            render_signal_values.push(self.timeline_value.fetch_add(1, Ordering::SeqCst) + 1);
+        }
+
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .wait_semaphore_values(&render_wait_values[..])
+            .signal_semaphore_values(&render_signal_values[..]);
+        let mut submit_render = vk::SubmitInfo::builder()
+            .command_buffers(&render_cb)
+            .wait_semaphores(&render_wait[..])
+            .signal_semaphores(&render_signal[..])
+            .wait_dst_stage_mask(&render_stage[..]);
+        if self.timeline.is_some() {
+            submit_render = submit_render.push_next(&mut timeline_info);
+        }
+        let submit_render = [submit_render.build()];
+
         unsafe {
             self.rdevice.queue_submit(
                 self.rdevice.queues.graphics,
@@ -403,6 +771,16 @@ impl Renderer {
         }
         .expect("Graphics queue submit failed");
 
+        if let Some(render_doc) = capture {
+            let comments = format!(
+                "gears frame in flight {}, swapchain image {}",
+                frame, image_index
+            );
+            if !render_doc.end_capture(&comments) {
+                warn!("RenderDoc frame capture failed");
+            }
+        }
+
         let updates = render_object.update_cb_pending;
         let triangles = render_object.triangles;
         drop(render_object);
@@ -418,9 +796,28 @@ impl Renderer {
             ))
             .unwrap();
 
+        let new_scale = self.scaler.lock().update(gpu_frametime.whole_pipeline);
+        if let Some(scale) = new_scale {
+            self.render_scale_changed(scale);
+        }
+
+        let cpu_frametime = cpu_frametime.elapsed();
+        profiling::gpu_timings(&gpu_frametime, &scopes);
+        self.detect_spike(diagnostics::FrameSample {
+            frame_index: info.frame_index,
+            real_delta: info.real_delta,
+            cpu_frametime,
+            gpu_frametime: gpu_frametime.whole_pipeline,
+            triangles,
+            rerecord,
+            updates,
+        });
+
         FramePerfReport {
-            cpu_frametime: cpu_frametime.elapsed(),
+            cpu_frametime,
             gpu_frametime: gpu_frametime,
+            pipeline_stats,
+            scopes,
 
             rerecord,
             updates,
@@ -428,12 +825,7 @@ impl Renderer {
         }
     }
 
-    fn update<T: RendererRecord>(
-        &self,
-        recorder: &T,
-        render_object: &mut RwLockWriteGuard<RenderObject>,
-        image_index: usize,
-    ) {
+    fn begin_update(&self, render_object: &mut RenderObject) {
         if render_object.update_cb_recording == false {
             unsafe {
                 self.rdevice.reset_command_buffer(
@@ -452,12 +844,10 @@ impl Renderer {
 
             render_object.update_cb_recording = true;
         }
+    }
 
-        let uri = UpdateRecordInfo {
-            command_buffer: render_object.update_cb,
-            image_index,
-        };
-        render_object.update_cb_pending = recorder.update(&uri);
+    fn end_update(&self, render_object: &mut RenderObject, updates: bool) {
+        render_object.update_cb_pending = updates;
 
         if render_object.update_cb_pending {
             render_object.update_cb_recording = false;
@@ -466,121 +856,49 @@ impl Renderer {
         }
     }
 
-    fn immediate<T: RendererRecord>(&self, recorder: &T, image_index: usize) {
-        let imfi = ImmediateFrameInfo { image_index };
-        recorder.immediate(&imfi)
+    /// Every swapchain image records again the next time it is rendered to.
+    ///
+    /// Can be called from anywhere, also from `RendererRecord::frame`, where
+    /// it applies from the next frame on.
+    pub fn request_rerecord(&self) {
+        for requested in self.rerecord_requested.iter() {
+            requested.store(true, Ordering::SeqCst);
+        }
     }
 
-    fn record<T: RendererRecord>(
-        &self,
-        recorder: &T,
-        render_object: &mut RwLockWriteGuard<RenderObject>,
-        image_index: usize,
-    ) {
-        let begin_info = recorder.begin_info();
+    /// `request_rerecord` that also waits for the GPU and resets the old
+    /// recordings, resources only they used can be dropped right after.
+    ///
+    /// Blocks until the current frame is done, do not call it from `RendererRecord::frame`.
+    /// Fails if a command buffer could not be reset, the rerecord is still requested.
+    pub fn invalidate_recording(&self) -> Result<(), vk::Result> {
+        self.request_rerecord();
+        self.wait();
 
         let data = self.data.read();
-        let swapchain_objects = data.swapchain_objects.read();
-        let rri = RenderRecordInfo {
-            command_buffer: render_object.render_cb,
-            image_index,
-            triangles: AtomicUsize::new(0),
-            debug_calls: begin_info.debug_calls,
-        };
-
-        let viewport = [swapchain_objects.viewport];
-        let scissor = [swapchain_objects.scissor];
-        let clear_values = [
-            vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: [
-                        begin_info.clear_color.x,
-                        begin_info.clear_color.y,
-                        begin_info.clear_color.z,
-                        begin_info.clear_color.w,
-                    ],
-                },
-            },
-            vk::ClearValue {
-                depth_stencil: vk::ClearDepthStencilValue {
-                    depth: 1.0,
-                    stencil: 0,
-                },
-            },
-        ];
-        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
-            .clear_values(&clear_values)
-            .framebuffer(render_object.framebuffer)
-            .render_pass(swapchain_objects.render_pass)
-            .render_area(swapchain_objects.scissor);
-
-        if begin_info.debug_calls {
-            debug!("begin_command_buffer with: {:?}", begin_info);
-        }
-
-        unsafe {
-            self.rdevice.reset_command_buffer(
-                render_object.render_cb,
-                vk::CommandBufferResetFlags::empty(),
-            )
-        }
-        .expect("Command buffer reset failed");
-
-        unsafe {
-            self.rdevice.begin_command_buffer(
-                render_object.render_cb,
-                &vk::CommandBufferBeginInfo::builder(),
-            )
-        }
-        .expect("Command buffer begin failed");
-
-        unsafe {
-            self.rdevice
-                .cmd_set_viewport(render_object.render_cb, 0, &viewport);
-        }
-
-        unsafe {
-            self.rdevice
-                .cmd_set_scissor(render_object.render_cb, 0, &scissor);
-        }
-
-        drop(swapchain_objects);
-
-        unsafe {
-            render_object.perf.reset(&rri);
-        }
-
-        unsafe {
-            self.rdevice.cmd_begin_render_pass(
-                render_object.render_cb,
-                &render_pass_begin_info,
-                vk::SubpassContents::INLINE,
-            );
-        }
-
-        recorder.record(&rri);
-        unsafe {
-            render_object.perf.bind(&rri);
-        }
-        render_object.triangles = rri.triangles.load(Ordering::SeqCst);
-
-        unsafe {
-            self.rdevice.cmd_end_render_pass(render_object.render_cb);
+        for render_object in data.render_objects.iter() {
+            let render_object = render_object.write();
+            unsafe {
+                self.rdevice.reset_command_buffer(
+                    render_object.render_cb,
+                    vk::CommandBufferResetFlags::empty(),
+                )
+            }?;
         }
-
-        unsafe { self.rdevice.end_command_buffer(render_object.render_cb) }
-            .expect("Command buffer end failed");
+        Ok(())
     }
 
-    pub fn request_rerecord(&self) {
-        let data = self.data.read();
+    /// `RecordMode::Reuse` by default.
+    pub fn set_record_mode(&self, mode: RecordMode) {
+        *self.record_mode.lock() = mode;
+    }
 
-        for target in data.render_objects.iter() {
-            target.write().rerecord_requested = true;
-        }
+    pub fn record_mode(&self) -> RecordMode {
+        *self.record_mode.lock()
     }
 
     fn recreate_swapchain_silent(&self) {
+        profile_scope!("recreate swapchain");
         let data = self.data.read();
 
         let mut render_objects = data
@@ -613,6 +931,14 @@ impl Renderer {
 
         swapchain_objects.extent = extent;
         swapchain_objects.swapchain = swapchain;
+        if let Some(picker) = self.picker.as_ref() {
+            if let Err(err) = picker.resize(extent) {
+                error!(
+                    target: logging::SWAPCHAIN,
+                    "Picking target resize failed, picking keeps the old size: {:?}", err
+                );
+            }
+        }
         swapchain_objects.viewport = viewport;
         swapchain_objects.scissor = scissor;
 
@@ -637,7 +963,7 @@ impl Renderer {
             let depth_image = ImageBuilder::new_with_device(self.rdevice.clone())
                 .with_width(swapchain_objects.extent.width)
                 .with_height(swapchain_objects.extent.height)
-                .build(ImageUsage::WRITE, ImageFormat::<f32>::D)
+                .build(ImageUsage::WRITE, swapchain_objects.depth_format)
                 .expect("Depth image creation failed");
 
             let attachments = [color_image.view(), depth_image.view()];
@@ -663,10 +989,377 @@ impl Renderer {
         self.request_rerecord();
     }
 
+    /// For libraries sharing the device, like an OpenXR runtime.
+    pub fn instance(&self) -> &ash::Instance {
+        &self.rdevice.instance
+    }
+
+    /// For libraries sharing the device, it stays alive as long as anything
+    /// created from this renderer, for ex. an `Image`.
+    pub fn device(&self) -> &ash::Device {
+        &self.rdevice
+    }
+
+    pub fn physical_device(&self) -> vk::PhysicalDevice {
+        self.rdevice.pdevice
+    }
+
+    /// The queue `frame` submits to and its family, the queue is the first
+    /// of the family. Other submissions have to come from the same thread.
+    pub fn graphics_queue(&self) -> (vk::Queue, u32) {
+        (
+            self.rdevice.queues.graphics,
+            self.rdevice.queues.graphics_family as u32,
+        )
+    }
+
+    /// Frames the CPU can record ahead of the GPU, see `RendererBuilder::with_frames_in_flight`.
     pub fn frames_in_flight(&self) -> usize {
         self.frames_in_flight
     }
 
+    /// Number of swapchain images, per image resources like UBO copies need this many.
+    ///
+    /// Chosen by the surface, usually one more than its minimum.
+    pub fn image_count(&self) -> usize {
+        self.data.read().render_objects.len()
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.rdevice.limits
+    }
+
+    /// The chosen swapchain color format, see `RendererBuilder::with_color_space`.
+    pub fn surface_format(&self) -> vk::Format {
+        self.data.read().swapchain_objects.read().format.format
+    }
+
+    /// The current swapchain size in pixels.
+    pub fn extent(&self) -> (u32, u32) {
+        let extent = self.data.read().swapchain_objects.read().extent;
+        (extent.width, extent.height)
+    }
+
+    /// Device memory limit for `StreamedTexture2D` levels.
+    pub fn texture_budget(&self) -> &TextureBudget {
+        &self.rdevice.texture_budget
+    }
+
+    /// Device memory of every `TransientImages`.
+    pub fn transient_stats(&self) -> &TransientStats {
+        &self.rdevice.transient_stats
+    }
+
+    /// Usage of the descriptor pools shared by every pipeline.
+    pub fn descriptor_stats(&self) -> DescriptorStats {
+        self.rdevice.descriptor_pools.stats()
+    }
+
+    /// Descriptor set and pipeline layouts shared between pipelines with the same bindings.
+    pub fn layout_stats(&self) -> LayoutStats {
+        self.rdevice.layouts.stats()
+    }
+
+    /// True if `AccelerationStructure`s and ray tracing pipelines can be built.
+    pub fn ray_tracing(&self) -> bool {
+        self.rdevice.ray_tracing.is_some()
+    }
+
+    /// True if `GpuTimeline`s can be created and frames signal `frame_timeline`.
+    pub fn timeline_semaphores(&self) -> bool {
+        self.rdevice.timeline_semaphore
+    }
+
+    /// The timeline every frame signals once its rendering is done, and the
+    /// value of the last submitted frame.
+    ///
+    /// Own submissions waiting for this value start after that frame.
+    pub fn frame_timeline(&self) -> Option<(&GpuTimeline, u64)> {
+        self.timeline
+            .as_ref()
+            .map(|timeline| (timeline, self.timeline_value.load(Ordering::SeqCst)))
+    }
+
+    /// The next submitted frame waits until `timeline` reaches `value`.
+    ///
+    /// Orders own compute or transfer submissions before rendering that uses
+    /// their results. `timeline` must outlive the frame.
+    pub fn wait_timeline(&self, timeline: &GpuTimeline, value: u64) {
+        self.timeline_waits.lock().push((timeline.get(), value));
+    }
+
+    /// Adjusts `render_scale` to keep the GPU frametime around `ms`, `None` disables it.
+    ///
+    /// Needs timestamp queries, without them the scale stays where it is.
+    /// Disabling resets the scale to the top of `set_render_scale_range`.
+    pub fn set_target_frame_time(&self, ms: Option<f32>) {
+        let target = ms.map(|ms| Duration::from_secs_f32(ms.max(0.0) / 1000.0));
+        let new_scale = self.scaler.lock().set_target(target);
+        if let Some(scale) = new_scale {
+            self.render_scale_changed(scale);
+        }
+    }
+
+    /// Limits of the dynamic resolution scale, 0.5 to 1.0 by default.
+    pub fn set_render_scale_range(&self, min: f32, max: f32) {
+        let new_scale = self.scaler.lock().set_range(min, max);
+        if let Some(scale) = new_scale {
+            self.render_scale_changed(scale);
+        }
+    }
+
+    /// Fraction of the full resolution offscreen passes should render at.
+    ///
+    /// `ScaledPresent` follows it automatically, other passes can read it from
+    /// `RenderRecordInfo::render_scale`.
+    pub fn render_scale(&self) -> f32 {
+        self.scaler.lock().scale()
+    }
+
+    /// The settings last applied, clamped to what the device supports.
+    pub fn settings(&self) -> RendererSettings {
+        *self.settings.lock()
+    }
+
+    /// Applies `settings` with as few recreations as possible, see `RendererSettings`.
+    ///
+    /// Only a changed `sync` recreates the swapchain. Returns what changed,
+    /// resources the application owns have to be rebuilt for `MSAA`,
+    /// `ANISOTROPY` and `SHADOWS`.
+    pub fn apply_settings(&self, settings: &RendererSettings) -> SettingsChanges {
+        let settings = settings.clamped(&self.rdevice);
+        let changes = {
+            let mut current = self.settings.lock();
+            let changes = current.changes(&settings);
+            *current = settings;
+            changes
+        };
+        debug!(target: logging::SWAPCHAIN, "Settings applied: {:?}", changes);
+
+        if changes.contains(SettingsChanges::SWAPCHAIN) {
+            {
+                let data = self.data.read();
+                let mut swapchain_objects = data.swapchain_objects.write();
+                match RendererBuilder::pick_surface_present_mode(
+                    self.rdevice.pdevice,
+                    swapchain_objects.surface,
+                    &swapchain_objects.surface_loader,
+                    settings.sync,
+                ) {
+                    Ok(present) => swapchain_objects.present = present,
+                    Err(err) => {
+                        warn!(target: logging::SWAPCHAIN, "Present mode not changed: {:?}", err)
+                    }
+                }
+            }
+            self.recreate_swapchain();
+        }
+
+        if changes.contains(SettingsChanges::RENDER_SCALE) {
+            let (min, _) = self.scaler.lock().range();
+            let scale = settings.resolution_scale;
+            self.set_render_scale_range(min.min(scale), scale);
+        }
+
+        changes
+    }
+
+    /// Called with the new scale every time `render_scale` changes.
+    ///
+    /// The callback must not register other callbacks.
+    pub fn on_render_scale_change<F: FnMut(f32) + Send + 'static>(&self, callback: F) {
+        self.scale_callbacks.lock().push(Box::new(callback));
+    }
+
+    fn render_scale_changed(&self, scale: f32) {
+        debug!(target: logging::SWAPCHAIN, "Render scale changed to {}", scale);
+
+        // the scale is recorded into the viewports
+        self.request_rerecord();
+        for callback in self.scale_callbacks.lock().iter_mut() {
+            callback(scale);
+        }
+    }
+
+    /// Binds the `view` variant of pipelines from the next recorded frame on.
+    ///
+    /// Only pipelines built with `RendererBuilder::with_debug_views` have
+    /// variants, the rest keep drawing normally.
+    pub fn set_debug_view(&self, view: DebugView) {
+        if view != DebugView::None && !self.debug_views {
+            warn!(
+                "Debug view {:?} set without RendererBuilder::with_debug_views",
+                view
+            );
+        }
+        if view == DebugView::Wireframe && !self.rdevice.fill_mode_non_solid {
+            warn!("Wireframe debug view is not supported by the device");
+        }
+
+        *self.debug_view.lock() = view;
+        self.request_rerecord();
+    }
+
+    /// `DebugView::None` by default.
+    pub fn debug_view(&self) -> DebugView {
+        *self.debug_view.lock()
+    }
+
+    /// Pipelines built with this renderer include `DebugView` variants.
+    pub fn debug_views(&self) -> bool {
+        self.debug_views
+    }
+
+    /// `ShaderEnvironment::max_lights` of pipelines built with this renderer.
+    pub fn max_lights(&self) -> u32 {
+        self.max_lights
+    }
+
+    /// The worker threads recording uses, see `RendererBuilder::with_task_pool`.
+    pub fn task_pool(&self) -> TaskPool {
+        self.task_pool.clone()
+    }
+
+    /// Object id at the pixel `x`, `y` of `picking_target`, see `RendererBuilder::with_picking`.
+    ///
+    /// The readback is asynchronous, this requests the pixel for the next frame
+    /// and returns the last finished request. `None` before that, over pixels
+    /// cleared to 0 and without picking.
+    pub fn pick(&self, x: u32, y: u32) -> Option<u32> {
+        self.picker.as_ref().and_then(|picker| picker.pick(x, y))
+    }
+
+    /// Swapchain sized `R32_UINT` target to record the id pass into.
+    ///
+    /// Replaced when the swapchain is recreated, fetch it again when rerecording.
+    pub fn picking_target(&self) -> Option<Arc<RenderTarget>> {
+        self.picker.as_ref().map(|picker| picker.target())
+    }
+
+    /// World position at the pixel `x`, `y` of `picking_target`, read back like `pick`.
+    ///
+    /// `view_projection` has to be the one the id pass was drawn with.
+    /// `None` over pixels with nothing drawn.
+    pub fn pick_position(
+        &self,
+        x: u32,
+        y: u32,
+        view_projection: &Matrix4<f32>,
+    ) -> Option<Point3<f32>> {
+        let pixel = self.picker.as_ref()?.depth(x, y)?;
+        let depth = f32::from_bits(pixel.value);
+        if depth >= 1.0 {
+            return None;
+        }
+
+        let (width, height) = self.extent();
+        camera::unproject(
+            Vector2::new(pixel.x as f32 + 0.5, pixel.y as f32 + 0.5),
+            depth,
+            Vector2::new(width as f32, height as f32),
+            view_projection,
+        )
+    }
+
+    /// Captures the next frame with RenderDoc, for ex. from a debug hotkey.
+    ///
+    /// Only works when the application was launched from RenderDoc, returns
+    /// false otherwise. The capture is commented with the frame in flight and swapchain image.
+    pub fn trigger_capture(&self) -> bool {
+        if self.render_doc.is_none() {
+            warn!("RenderDoc capture requested, but RenderDoc is not attached");
+            return false;
+        }
+
+        self.capture_requested.store(true, Ordering::SeqCst);
+        true
+    }
+
+    /// Checks every frame for spikes and dumps `Diagnostics` for them, `None` disables it.
+    ///
+    /// Disabled by default.
+    pub fn set_spike_detector(&self, detector: Option<diagnostics::SpikeDetector>) {
+        *self.spike_detector.lock() = detector;
+    }
+
+    /// The current state, with the spike detector's frame history if it is set.
+    pub fn diagnostics(&self) -> diagnostics::Diagnostics {
+        let history = self
+            .spike_detector
+            .lock()
+            .as_ref()
+            .map_or(Vec::new(), |detector| detector.history());
+        self.snapshot(None, history)
+    }
+
+    fn detect_spike(&self, sample: diagnostics::FrameSample) {
+        let mut detector = self.spike_detector.lock();
+        if let Some(detector) = detector.as_mut() {
+            if detector.record(sample) {
+                let diagnostics = self.snapshot(Some(sample), detector.history());
+                detector.dump(&diagnostics);
+            }
+        }
+    }
+
+    fn snapshot(
+        &self,
+        spike: Option<diagnostics::FrameSample>,
+        history: Vec<diagnostics::FrameSample>,
+    ) -> diagnostics::Diagnostics {
+        let memory_properties = unsafe {
+            self.rdevice
+                .instance
+                .get_physical_device_memory_properties(self.rdevice.pdevice)
+        };
+        let heaps = memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+            .iter()
+            .map(|heap| diagnostics::MemoryHeap {
+                size: heap.size,
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+            })
+            .collect();
+
+        let swapchain = {
+            let data = self.data.read();
+            let swapchain_objects = data.swapchain_objects.read();
+            diagnostics::SwapchainState {
+                extent: (
+                    swapchain_objects.extent.width,
+                    swapchain_objects.extent.height,
+                ),
+                format: swapchain_objects.format.format,
+                present_mode: swapchain_objects.present,
+                image_count: data.render_objects.len(),
+                frames_in_flight: self.frames_in_flight,
+                render_scale: self.render_scale(),
+                record_mode: self.record_mode(),
+            }
+        };
+
+        diagnostics::Diagnostics {
+            time: SystemTime::now(),
+            spike,
+            history,
+            memory: diagnostics::MemoryStats {
+                heaps,
+                streamed_used: self.rdevice.texture_budget.used(),
+                streamed_limit: self.rdevice.texture_budget.limit(),
+                transient_allocated: self.rdevice.transient_stats.allocated(),
+                transient_aliased: self.rdevice.transient_stats.aliased(),
+            },
+            swapchain,
+            descriptors: self.descriptor_stats(),
+            layouts: self.layout_stats(),
+            validation: debug::recent_messages(),
+        }
+    }
+
+    /// True if the depth attachment has a stencil aspect, see `RendererBuilder::with_stencil`.
+    pub fn stencil(&self) -> bool {
+        has_stencil(self.data.read().swapchain_objects.read().depth_format)
+    }
+
     pub fn wait(&self) {
         let queue_wait_result = |res: Result<(), vk::Result>| {
             res.map_err_else_log("Could not wait for queue to become idle", |err| match err {
@@ -692,6 +1385,22 @@ impl RendererBuilder {
         self
     }
 
+    /// Srgb by default.
+    ///
+    /// Falls back to any available surface format if the requested one is not supported.
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    /// Combined depth-stencil attachment, cleared to 0 every frame.
+    ///
+    /// Disabled by default. Falls back to depth only if no depth-stencil format is supported.
+    pub fn with_stencil(mut self, stencil: bool) -> Self {
+        self.stencil = stencil;
+        self
+    }
+
     /// Increasing frames in flight <u>MIGHT</u> decrease the cpu frametime if the scene is simple.
     ///
     /// Slightly increases input delay.
@@ -699,39 +1408,143 @@ impl RendererBuilder {
     /// Recommended values: 2 or 3.
     ///
     /// 1 Never recommended and going above rarely improves anything.
+    /// 0 is raised to 1.
     pub fn with_frames_in_flight(mut self, frames_in_flight: usize) -> Self {
         self.frames_in_flight = frames_in_flight;
         self
     }
 
+    /// Graphics pipelines also build their `DebugView` variants, see `Renderer::set_debug_view`.
+    ///
+    /// Disabled by default, the variants multiply pipeline creation time.
+    pub fn with_debug_views(mut self, debug_views: bool) -> Self {
+        self.debug_views = debug_views;
+        self
+    }
+
+    /// Initial `RendererSettings`, replaces `with_sync`.
+    pub fn with_settings(mut self, settings: RendererSettings) -> Self {
+        self.sync = settings.sync;
+        self.settings = settings;
+        self
+    }
+
+    /// Initial `RecordMode`, `Reuse` by default.
+    pub fn with_record_mode(mut self, record_mode: RecordMode) -> Self {
+        self.record_mode = record_mode;
+        self
+    }
+
+    /// Creates the `R32_UINT` object id target of `Renderer::pick`.
+    ///
+    /// Disabled by default.
+    pub fn with_picking(mut self, picking: bool) -> Self {
+        self.picking = picking;
+        self
+    }
+
+    /// Sizing of the descriptor pools pipelines allocate their sets from.
+    ///
+    /// More pools are created when they run out, a larger first pool only
+    /// saves the growing for scenes with many materials.
+    pub fn with_descriptor_pools(mut self, config: DescriptorPoolConfig) -> Self {
+        self.descriptor_pools = config;
+        self
+    }
+
+    /// `GEARS_MAX_LIGHTS` of the shaders including `gears://environment.glsl`.
+    ///
+    /// `DEFAULT_MAX_LIGHTS` by default. Usually the capacity of the light
+    /// buffers, like the one of `LightClusters`.
+    pub fn with_max_lights(mut self, max_lights: u32) -> Self {
+        self.max_lights = max_lights;
+        self
+    }
+
+    /// Worker threads for the CPU side of recording, like sorting the
+    /// queued draws, usually `FrameLoopBuilder::task_pool` to share its
+    /// threads. A pool of its own by default.
+    pub fn with_task_pool(mut self, task_pool: TaskPool) -> Self {
+        self.task_pool = Some(task_pool);
+        self
+    }
+
     fn pick_surface_format(
         pdevice: vk::PhysicalDevice,
         surface: vk::SurfaceKHR,
         surface_loader: &khr::Surface,
+        color_space: ColorSpace,
     ) -> Result<vk::SurfaceFormatKHR, ContextError> {
         let available =
             unsafe { surface_loader.get_physical_device_surface_formats(pdevice, surface) }
                 .map_err_log("Surface format query failed", ContextError::OutOfMemory)?;
 
         if available.len() == 0 {
-            error!("No surface formats available");
+            error!(target: logging::SWAPCHAIN, "No surface formats available");
             return Err(ContextError::MissingSurfaceConfigs);
         }
 
-        let format = available
+        let preferred: &[vk::Format] = match color_space {
+            ColorSpace::Srgb => &[vk::Format::R8G8B8A8_SRGB, vk::Format::B8G8R8A8_SRGB],
+            ColorSpace::Linear => &[vk::Format::R8G8B8A8_UNORM, vk::Format::B8G8R8A8_UNORM],
+        };
+
+        let format = preferred
             .iter()
-            .find(|format| {
-                format.format == vk::Format::R8G8B8A8_SRGB
-                    && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+            .find_map(|&preferred| {
+                available.iter().find(|format| {
+                    format.format == preferred
+                        && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+                })
             })
-            .unwrap_or(&available[0]);
+            .unwrap_or_else(|| {
+                warn!(
+                    target: logging::SWAPCHAIN,
+                    "No surface format for {:?} color space, using {:?}",
+                    color_space, available[0]
+                );
+                &available[0]
+            });
         let format = format.clone();
 
-        debug!("Surface format chosen: {:?} from {:?}", format, available);
+        debug!(
+            target: logging::SWAPCHAIN,
+            "Surface format chosen: {:?} from {:?}",
+            format,
+            available
+        );
 
         Ok(format)
     }
 
+    fn pick_depth_format(rdevice: &RenderDevice, stencil: bool) -> vk::Format {
+        if !stencil {
+            return ImageFormat::<f32>::D.format();
+        }
+
+        let supported = |format: vk::Format| {
+            unsafe {
+                rdevice
+                    .instance
+                    .get_physical_device_format_properties(rdevice.pdevice, format)
+            }
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+        };
+
+        [
+            vk::Format::D24_UNORM_S8_UINT,
+            vk::Format::D32_SFLOAT_S8_UINT,
+        ]
+        .iter()
+        .cloned()
+        .find(|&format| supported(format))
+        .unwrap_or_else(|| {
+            warn!("No depth-stencil format supported, stencil disabled");
+            ImageFormat::<f32>::D.format()
+        })
+    }
+
     fn pick_surface_present_mode(
         pdevice: vk::PhysicalDevice,
         surface: vk::SurfaceKHR,
@@ -746,7 +1559,7 @@ impl RendererBuilder {
             )?;
 
         if available.len() == 0 {
-            error!("No surface present modes available");
+            error!(target: logging::SWAPCHAIN, "No surface present modes available");
             return Err(ContextError::MissingSurfaceConfigs);
         }
 
@@ -765,6 +1578,7 @@ impl RendererBuilder {
         };
 
         debug!(
+            target: logging::SWAPCHAIN,
             "Surface present mode chosen: {:?} from {:?}",
             mode, available
         );
@@ -846,6 +1660,7 @@ impl RendererBuilder {
             .image_array_layers(1);
 
         debug!(
+            target: logging::SWAPCHAIN,
             "Swapchain images: {} - Swapchain format: {:?}",
             min_swapchain_len, format
         );
@@ -887,7 +1702,14 @@ impl RendererBuilder {
     fn render_pass(
         device: Arc<RenderDevice>,
         format: vk::Format,
+        depth_format: vk::Format,
     ) -> Result<vk::RenderPass, ContextError> {
+        let stencil_load_op = if has_stencil(depth_format) {
+            vk::AttachmentLoadOp::CLEAR
+        } else {
+            vk::AttachmentLoadOp::DONT_CARE
+        };
+
         let color_attachment = vk::AttachmentDescription::builder()
             .format(format)
             .samples(vk::SampleCountFlags::TYPE_1)
@@ -905,11 +1727,11 @@ impl RendererBuilder {
             .build()];
 
         let depth_attachment = vk::AttachmentDescription::builder()
-            .format(ImageFormat::<f32>::D.format())
+            .format(depth_format)
             .samples(vk::SampleCountFlags::TYPE_1)
             .load_op(vk::AttachmentLoadOp::CLEAR)
             .store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_load_op(stencil_load_op)
             .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
             .initial_layout(vk::ImageLayout::UNDEFINED)
             .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
@@ -958,9 +1780,11 @@ impl RendererBuilder {
         // rdevice
         let (r_context, surface, surface_loader, mut extent) = ReducedContext::new(context);
         let rdevice = RenderDevice::from_context(r_context)?;
+        rdevice.descriptor_pools.configure(self.descriptor_pools);
 
         // swapchain
-        let format = Self::pick_surface_format(rdevice.pdevice, surface, &surface_loader)?;
+        let format =
+            Self::pick_surface_format(rdevice.pdevice, surface, &surface_loader, self.color_space)?;
         let present =
             Self::pick_surface_present_mode(rdevice.pdevice, surface, &surface_loader, self.sync)?;
 
@@ -980,8 +1804,19 @@ impl RendererBuilder {
         let color_images = Self::swapchain_images(&swapchain_loader, swapchain)?;
 
         // main render pass
-        let render_pass = Self::render_pass(rdevice.clone(), format.format)?;
+        let depth_format = Self::pick_depth_format(&rdevice, self.stencil);
+        let render_pass = Self::render_pass(rdevice.clone(), format.format, depth_format)?;
 
+        let picker = if self.picking {
+            Some(
+                Picker::new(rdevice.clone(), extent, color_images.len())
+                    .map_err_log("Picking target creation failed", ContextError::OutOfMemory)?,
+            )
+        } else {
+            None
+        };
+
+        let rerecord_requested = color_images.iter().map(|_| AtomicBool::new(true)).collect();
         let render_objects = color_images
             .into_iter()
             .map(|image| {
@@ -990,12 +1825,30 @@ impl RendererBuilder {
                     render_pass,
                     image,
                     format.format,
+                    depth_format,
                     extent,
                 )?))
             })
             .collect::<Result<_, _>>()?;
 
-        let frames_in_flight = self.frames_in_flight;
+        let timeline = if rdevice.timeline_semaphore {
+            Some(GpuTimeline::new_with_device(rdevice.clone(), 0)?)
+        } else {
+            None
+        };
+
+        let settings = RendererSettings {
+            sync: self.sync,
+            ..self.settings
+        }
+        .clamped(&rdevice);
+        let mut scaler = ResolutionScaler::new();
+        scaler.set_range(
+            settings.resolution_scale.min(0.5),
+            settings.resolution_scale,
+        );
+
+        let frames_in_flight = self.frames_in_flight.max(1);
         let crender_objects = (0..frames_in_flight)
             .map(|_| Ok(RwLock::new(ConcurrentRenderObject::new(&rdevice)?)))
             .collect::<Result<_, _>>()?;
@@ -1003,6 +1856,7 @@ impl RendererBuilder {
         let swapchain_objects = RwLock::new(SwapchainObjects {
             extent,
             format,
+            depth_format,
             present,
             viewport,
             scissor,
@@ -1078,6 +1932,30 @@ impl RendererBuilder {
             frame: AtomicUsize::new(0),
             frames_in_flight,
 
+            timeline,
+            timeline_value: AtomicU64::new(0),
+            timeline_waits: Mutex::new(Vec::new()),
+
+            scaler: Mutex::new(scaler),
+            scale_callbacks: Mutex::new(Vec::new()),
+
+            spike_detector: Mutex::new(None),
+
+            settings: Mutex::new(settings),
+
+            rerecord_requested,
+            record_mode: Mutex::new(self.record_mode),
+
+            debug_views: self.debug_views,
+            debug_view: Mutex::new(DebugView::None),
+            max_lights: self.max_lights,
+            task_pool: self.task_pool.unwrap_or_default(),
+
+            picker,
+
+            render_doc: RenderDoc::load(),
+            capture_requested: AtomicBool::new(false),
+
             rdevice,
         })
     }
@@ -0,0 +1,205 @@
+use ash::{version::DeviceV1_0, vk};
+use log::debug;
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+use super::{
+    buffer::{texture::Texture2D, BufferError},
+    device::RenderDevice,
+    Renderer,
+};
+
+struct Slots {
+    free: Vec<u32>,
+    next: u32,
+}
+
+/// Unbounded sampled texture array in its own descriptor set.
+///
+/// Pipelines built with `with_texture_registry` bind it as set 1, shaders
+/// index it with `textures[nonuniformEXT(index)]` and the index is usually
+/// passed in push constants:
+///
+/// ```glsl
+/// #extension GL_EXT_nonuniform_qualifier : require
+/// layout(set = 1, binding = 0) uniform sampler2D textures[];
+/// ```
+///
+/// Textures are not owned by the registry and must outlive their indices.
+pub struct TextureRegistry {
+    device: Arc<RenderDevice>,
+
+    desc_pool: vk::DescriptorPool,
+    desc_set_layout: vk::DescriptorSetLayout,
+    desc_set: vk::DescriptorSet,
+
+    capacity: u32,
+    slots: Mutex<Slots>,
+}
+
+impl TextureRegistry {
+    pub fn new(renderer: &Renderer, capacity: u32) -> Result<Self, BufferError> {
+        Self::new_with_device(renderer.rdevice.clone(), capacity)
+    }
+
+    pub fn new_with_device(device: Arc<RenderDevice>, capacity: u32) -> Result<Self, BufferError> {
+        if !device.descriptor_indexing {
+            return Err(BufferError::UnsupportedFeature("descriptor indexing"));
+        }
+        if capacity == 0 {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let binding_flags = [vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+            | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING
+            | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT];
+        let mut binding_flags_info =
+            vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder().binding_flags(&binding_flags);
+
+        let bindings = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(capacity)
+            .stage_flags(vk::ShaderStageFlags::ALL_GRAPHICS)
+            .build()];
+
+        let desc_set_layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+            .bindings(&bindings)
+            .push_next(&mut binding_flags_info);
+
+        let desc_set_layout =
+            unsafe { device.create_descriptor_set_layout(&desc_set_layout_info, None) }
+                .or(Err(BufferError::OutOfMemory))?;
+
+        let pool_sizes = [vk::DescriptorPoolSize::builder()
+            .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(capacity)
+            .build()];
+
+        let desc_pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+            .max_sets(1)
+            .pool_sizes(&pool_sizes);
+
+        let desc_pool = unsafe { device.create_descriptor_pool(&desc_pool_info, None) }
+            .or(Err(BufferError::OutOfMemory))?;
+
+        let counts = [capacity];
+        let mut count_info = vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder()
+            .descriptor_counts(&counts);
+
+        let set_layouts = [desc_set_layout];
+        let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(desc_pool)
+            .set_layouts(&set_layouts)
+            .push_next(&mut count_info);
+
+        let desc_set = unsafe { device.allocate_descriptor_sets(&allocate_info) }
+            .or(Err(BufferError::OutOfMemory))?[0];
+
+        debug!("TextureRegistry created with capacity {}", capacity);
+
+        Ok(Self {
+            device,
+
+            desc_pool,
+            desc_set_layout,
+            desc_set,
+
+            capacity,
+            slots: Mutex::new(Slots {
+                free: Vec::new(),
+                next: 0,
+            }),
+        })
+    }
+
+    /// Returns the index of `texture` in the array or `None` if the registry is full.
+    pub fn insert(&self, texture: &Texture2D) -> Option<u32> {
+        self.insert_view(texture.view(), texture.sampler())
+    }
+
+    /// `insert` for images not owned by a `Texture2D`, in `SHADER_READ_ONLY_OPTIMAL`.
+    pub fn insert_view(&self, view: vk::ImageView, sampler: vk::Sampler) -> Option<u32> {
+        let index = {
+            let mut slots = self.slots.lock();
+            match slots.free.pop() {
+                Some(index) => index,
+                None if slots.next < self.capacity => {
+                    slots.next += 1;
+                    slots.next - 1
+                }
+                None => return None,
+            }
+        };
+
+        self.write(index, view, sampler);
+        Some(index)
+    }
+
+    /// Points `index` to a new view, for ex. after a `StreamedTexture2D` generation change.
+    ///
+    /// Frames in flight must not be using `index`, otherwise `insert_view` the
+    /// new view and `remove` the old index once those frames are done.
+    pub fn replace(&self, index: u32, view: vk::ImageView, sampler: vk::Sampler) {
+        if index < self.slots.lock().next {
+            self.write(index, view, sampler);
+        }
+    }
+
+    fn write(&self, index: u32, view: vk::ImageView, sampler: vk::Sampler) {
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(view)
+            .sampler(sampler)
+            .build()];
+
+        let write_set = [vk::WriteDescriptorSet::builder()
+            .dst_set(self.desc_set)
+            .dst_binding(0)
+            .dst_array_element(index)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_info)
+            .build()];
+
+        // Unsafe: the binding is UPDATE_AFTER_BIND and this element is not used by any frame
+        unsafe { self.device.update_descriptor_sets(&write_set, &[]) };
+    }
+
+    /// Frees `index` for reuse.
+    ///
+    /// Frames still using the index must have finished before it is reinserted.
+    pub fn remove(&self, index: u32) {
+        let mut slots = self.slots.lock();
+        if index < slots.next && !slots.free.contains(&index) {
+            slots.free.push(index);
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.desc_set_layout
+    }
+
+    pub fn set(&self) -> vk::DescriptorSet {
+        self.desc_set
+    }
+}
+
+impl Drop for TextureRegistry {
+    fn drop(&mut self) {
+        // pipeline layouts with it must not be shared with a new registry
+        // getting the same handle
+        self.device.layouts.forget_set_layout(self.desc_set_layout);
+        unsafe {
+            self.device
+                .destroy_descriptor_set_layout(self.desc_set_layout, None);
+            self.device.destroy_descriptor_pool(self.desc_pool, None);
+        }
+    }
+}
@@ -1,9 +1,20 @@
+pub(crate) mod arena;
+pub mod atlas;
 pub mod image;
 pub mod index;
 pub mod stage;
+pub mod storage;
+pub mod streamed;
+pub mod streaming;
+pub mod texel;
+pub mod texture;
+pub mod transient;
 pub mod uniform;
 pub mod vertex;
+pub mod video;
 
+#[cfg(feature = "short_namespaces")]
+pub use atlas::*;
 #[cfg(feature = "short_namespaces")]
 pub use image::*;
 #[cfg(feature = "short_namespaces")]
@@ -11,15 +22,30 @@ pub use index::*;
 #[cfg(feature = "short_namespaces")]
 pub use stage::*;
 #[cfg(feature = "short_namespaces")]
+pub use storage::*;
+#[cfg(feature = "short_namespaces")]
+pub use streamed::*;
+#[cfg(feature = "short_namespaces")]
+pub use streaming::*;
+#[cfg(feature = "short_namespaces")]
+pub use texel::*;
+#[cfg(feature = "short_namespaces")]
+pub use texture::*;
+#[cfg(feature = "short_namespaces")]
+pub use transient::*;
+#[cfg(feature = "short_namespaces")]
 pub use uniform::*;
 #[cfg(feature = "short_namespaces")]
 pub use vertex::*;
+#[cfg(feature = "short_namespaces")]
+pub use video::*;
 
 use ash::{version::DeviceV1_0, vk};
 use log::warn;
 use std::sync::Arc;
 
 use super::{device::RenderDevice, UpdateRecordInfo};
+use crate::logging;
 
 #[derive(Debug)]
 pub enum WriteType {
@@ -34,6 +60,9 @@ pub enum BufferError {
     TriedToOverflow,
     OutOfMemory,
     NoMemoryType(vk::MemoryPropertyFlags),
+    UnsupportedFeature(&'static str),
+    /// Not SPIR-V words, see `Pipeline::from_spirv`.
+    InvalidSpirv,
 }
 
 pub trait Buffer {
@@ -41,7 +70,7 @@ pub trait Buffer {
     fn get(&self) -> vk::Buffer;
 }
 
-fn create_buffer(
+pub(crate) fn create_buffer(
     device: &Arc<RenderDevice>,
     byte_size: usize,
     usage: vk::BufferUsageFlags,
@@ -104,9 +133,14 @@ fn create_buffer_with_mem_type<F: FnMut(&vk::MemoryRequirements) -> Result<u32,
 
         let mem_type = mem_type(&req)?;
 
-        let alloc_info = vk::MemoryAllocateInfo::builder()
+        let mut flags_info =
+            vk::MemoryAllocateFlagsInfo::builder().flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+        let mut alloc_info = vk::MemoryAllocateInfo::builder()
             .allocation_size(req.size)
             .memory_type_index(mem_type);
+        if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) {
+            alloc_info = alloc_info.push_next(&mut flags_info);
+        }
 
         // Unsafe: and here
         let memory = unsafe { device.allocate_memory(&alloc_info, None) }
@@ -145,7 +179,10 @@ fn upload_type(
     if let Some(primary) = primary {
         (primary, false)
     } else {
-        warn!("Primary memory properties not available, using fallback memory properties");
+        warn!(
+            target: logging::MEMORY,
+            "Primary memory properties not available, using fallback memory properties"
+        );
         let fallback = find_mem_type(available_memory_types, requirements, fallback_properties)
             .expect("Fallback memory properties not available");
 
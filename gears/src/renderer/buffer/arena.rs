@@ -0,0 +1,183 @@
+use ash::{version::DeviceV1_0, vk};
+use parking_lot::Mutex;
+use std::{ops::Range, sync::Arc};
+
+use super::{create_buffer_with_fallback, BufferError};
+use crate::renderer::device::RenderDevice;
+
+// bigger allocations get a block of their own
+const BLOCK_SIZE: u64 = 64 * 1024;
+
+/// Persistently mapped host visible memory shared by every `UniformBuffer` of a device.
+///
+/// Writes are plain copies into the mapping. Non coherent blocks written to
+/// are flushed together in one `flush`, which `Renderer::frame` calls
+/// after `RendererRecord::frame`, so a frame writing many small UBOs maps
+/// nothing and flushes once.
+///
+/// Regions are written while earlier frames may be in flight, so a region
+/// must not be shared between frames, see `UniformBuffer`.
+#[derive(Default)]
+pub struct UniformArena {
+    blocks: Mutex<Vec<ArenaBlock>>,
+}
+
+/// A region of a `UniformArena` block.
+#[derive(Debug, Clone)]
+pub struct ArenaAlloc {
+    pub block: usize,
+    pub buffer: vk::Buffer,
+    // bytes
+    pub range: Range<u64>,
+}
+
+struct ArenaBlock {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapping: *mut u8,
+    non_coherent: bool,
+    dirty: bool,
+
+    // sorted and merged
+    free: Vec<Range<u64>>,
+}
+
+// the mapping is only accessed with the arena locked
+unsafe impl Send for ArenaBlock {}
+
+impl UniformArena {
+    pub fn alloc(&self, device: &Arc<RenderDevice>, size: u64) -> Result<ArenaAlloc, BufferError> {
+        let size = size.max(1);
+        let alignment = device.limits.min_uniform_buffer_offset_alignment.max(1);
+        let mut blocks = self.blocks.lock();
+
+        for (index, block) in blocks.iter_mut().enumerate() {
+            if let Some(range) = block.take(size, alignment) {
+                return Ok(ArenaAlloc {
+                    block: index,
+                    buffer: block.buffer,
+                    range,
+                });
+            }
+        }
+
+        let mut block = ArenaBlock::new(device, size.max(BLOCK_SIZE))?;
+        let range = block.take(size, alignment).unwrap();
+        let alloc = ArenaAlloc {
+            block: blocks.len(),
+            buffer: block.buffer,
+            range,
+        };
+        blocks.push(block);
+        Ok(alloc)
+    }
+
+    /// The region can be reused once the GPU is done with it.
+    pub fn free(&self, alloc: &ArenaAlloc) {
+        self.blocks.lock()[alloc.block].give_back(alloc.range.clone());
+    }
+
+    /// Copies `bytes` to the start of `alloc`, they have to fit.
+    pub unsafe fn write(&self, alloc: &ArenaAlloc, bytes: &[u8]) {
+        debug_assert!(bytes.len() as u64 <= alloc.range.end - alloc.range.start);
+
+        let mut blocks = self.blocks.lock();
+        let block = &mut blocks[alloc.block];
+        bytes
+            .as_ptr()
+            .copy_to_nonoverlapping(block.mapping.add(alloc.range.start as usize), bytes.len());
+        block.dirty |= block.non_coherent;
+    }
+
+    /// Flushes every non coherent block written to since the last flush, in one call.
+    pub fn flush(&self, device: &RenderDevice) -> Result<(), BufferError> {
+        let mut blocks = self.blocks.lock();
+        let ranges = blocks
+            .iter_mut()
+            .filter(|block| block.dirty)
+            .map(|block| {
+                block.dirty = false;
+                vk::MappedMemoryRange::builder()
+                    .memory(block.memory)
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        if !ranges.is_empty() {
+            unsafe { device.flush_mapped_memory_ranges(&ranges) }
+                .or(Err(BufferError::OutOfMemory))?;
+        }
+        Ok(())
+    }
+}
+
+impl ArenaBlock {
+    fn new(device: &Arc<RenderDevice>, size: u64) -> Result<Self, BufferError> {
+        let (buffer, memory, non_coherent) = create_buffer_with_fallback(
+            device,
+            size as usize,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::SharingMode::EXCLUSIVE,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        let mapping =
+            unsafe { device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()) }
+                .or(Err(BufferError::OutOfMemory))? as *mut u8;
+
+        Ok(Self {
+            buffer,
+            memory,
+            mapping,
+            non_coherent,
+            dirty: false,
+
+            free: vec![0..size],
+        })
+    }
+
+    // first fit
+    fn take(&mut self, size: u64, alignment: u64) -> Option<Range<u64>> {
+        let (index, start) = self.free.iter().enumerate().find_map(|(index, free)| {
+            let start = (free.start + alignment - 1) / alignment * alignment;
+            if start + size <= free.end {
+                Some((index, start))
+            } else {
+                None
+            }
+        })?;
+
+        let free = self.free.remove(index);
+        let end = start + size;
+        if end < free.end {
+            self.free.insert(index, end..free.end);
+        }
+        // the alignment padding stays free
+        if free.start < start {
+            self.free.insert(index, free.start..start);
+        }
+        Some(start..end)
+    }
+
+    fn give_back(&mut self, range: Range<u64>) {
+        let index = self
+            .free
+            .iter()
+            .position(|free| free.start > range.start)
+            .unwrap_or(self.free.len());
+        self.free.insert(index, range);
+
+        // merge with the next and the previous range
+        if index + 1 < self.free.len() && self.free[index].end == self.free[index + 1].start {
+            let next = self.free.remove(index + 1);
+            self.free[index].end = next.end;
+        }
+        if index > 0 && self.free[index - 1].end == self.free[index].start {
+            let this = self.free.remove(index);
+            self.free[index - 1].end = this.end;
+        }
+    }
+}
@@ -0,0 +1,451 @@
+use cgmath::Vector2;
+use log::error;
+use std::{mem, sync::Arc};
+
+use super::{
+    texture::{Texture2D, TextureConfig},
+    BufferError,
+};
+use crate::{
+    logging,
+    renderer::{device::RenderDevice, Renderer, UpdateRecordInfo},
+};
+
+/// Skyline rectangle packer, places rectangles as low and then as far left as they fit.
+///
+/// Plain CPU bookkeeping in pixels, `TextureAtlas` packs with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RectPacker {
+    width: u32,
+    height: u32,
+    // top edge of the packed area, left to right, covering the whole width
+    skyline: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Segment {
+    x: u32,
+    y: u32,
+    width: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AtlasId(usize);
+
+/// Pixel rectangle of an image in a `TextureAtlas`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AtlasRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Normalized texture coordinates, `min` at the top left.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UvRect {
+    pub min: Vector2<f32>,
+    pub max: Vector2<f32>,
+}
+
+/// Many small RGBA8 images, like glyphs, particles or sprites, packed into
+/// one `Texture2D`.
+///
+/// ```ignore
+/// let mut atlas = TextureAtlas::new(&renderer, 512, 512)?;
+/// let coin = atlas.insert(16, 16, &coin_pixels)?;
+/// let uv = atlas.uv(coin);
+///
+/// // in RendererRecord::frame, before drawing
+/// atlas.update(&uri);
+/// ```
+///
+/// Images can be added at any time and are uploaded together in the next
+/// `update`. A full atlas doubles its size up to `with_max_size`, which
+/// creates a new texture and changes every `uv`: `generation` changes with
+/// it, bind the new `texture` again and recompute the coordinates. Pixel
+/// rects never move.
+pub struct TextureAtlas {
+    device: Arc<RenderDevice>,
+    config: TextureConfig,
+    frames_in_flight: usize,
+
+    texture: Texture2D,
+    packer: RectPacker,
+    // host copy of the whole texture, written on update
+    pixels: Vec<u8>,
+    dirty: bool,
+
+    rects: Vec<AtlasRect>,
+    padding: u32,
+    max_size: u32,
+    generation: u64,
+
+    // textures replaced by growing stay alive until frames using them are done
+    retired: Vec<(usize, Texture2D)>,
+}
+
+impl RectPacker {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            skyline: vec![Segment { x: 0, y: 0, width }],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Top left corner of a free `width` by `height` area, `None` if it does not fit.
+    pub fn pack(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        if width == 0 || height == 0 {
+            return Some((0, 0));
+        }
+
+        // lowest top edge, then leftmost
+        let (index, y) = (0..self.skyline.len())
+            .filter_map(|index| Some((index, self.fits(index, width, height)?)))
+            .min_by_key(|&(index, y)| (y + height, self.skyline[index].x))?;
+
+        let x = self.skyline[index].x;
+        self.raise(index, x, y + height, width);
+        Some((x, y))
+    }
+
+    /// Enlarges the packing area, packed rectangles stay where they are.
+    pub fn grow(&mut self, width: u32, height: u32) {
+        if width > self.width {
+            self.skyline.push(Segment {
+                x: self.width,
+                y: 0,
+                width: width - self.width,
+            });
+            self.width = width;
+        }
+        self.height = self.height.max(height);
+    }
+
+    // the y a rectangle starting at segment `index` would be placed at
+    fn fits(&self, index: usize, width: u32, height: u32) -> Option<u32> {
+        let x = self.skyline[index].x;
+        if x + width > self.width {
+            return None;
+        }
+
+        let mut y = 0;
+        let mut covered = 0;
+        for segment in self.skyline[index..].iter() {
+            if covered >= width {
+                break;
+            }
+            y = y.max(segment.y);
+            covered += segment.width;
+        }
+
+        if y + height > self.height {
+            None
+        } else {
+            Some(y)
+        }
+    }
+
+    // replaces the skyline under x..x + width with a segment at `top`
+    fn raise(&mut self, index: usize, x: u32, top: u32, width: u32) {
+        self.skyline.insert(index, Segment { x, y: top, width });
+
+        let end = x + width;
+        let next = index + 1;
+        while next < self.skyline.len() {
+            let segment = &mut self.skyline[next];
+            if segment.x >= end {
+                break;
+            }
+            let segment_end = segment.x + segment.width;
+            if segment_end <= end {
+                self.skyline.remove(next);
+            } else {
+                segment.width = segment_end - end;
+                segment.x = end;
+                break;
+            }
+        }
+
+        // neighbours at the same height become one
+        let mut i = 0;
+        while i + 1 < self.skyline.len() {
+            if self.skyline[i].y == self.skyline[i + 1].y {
+                self.skyline[i].width += self.skyline[i + 1].width;
+                self.skyline.remove(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+impl TextureAtlas {
+    pub fn new(renderer: &Renderer, width: u32, height: u32) -> Result<Self, BufferError> {
+        Self::new_with_config(renderer, width, height, &TextureConfig::default())
+    }
+
+    pub fn new_with_config(
+        renderer: &Renderer,
+        width: u32,
+        height: u32,
+        config: &TextureConfig,
+    ) -> Result<Self, BufferError> {
+        let device = renderer.rdevice.clone();
+        let texture = Texture2D::new_with_device(device.clone(), width, height, config)?;
+        let max_size = device.limits.max_texture_size;
+
+        Ok(Self {
+            device,
+            config: *config,
+            frames_in_flight: renderer.frames_in_flight(),
+
+            texture,
+            packer: RectPacker::new(width, height),
+            pixels: vec![0; width as usize * height as usize * 4],
+            dirty: true,
+
+            rects: Vec::new(),
+            padding: 1,
+            max_size,
+            generation: 0,
+
+            retired: Vec::new(),
+        })
+    }
+
+    /// Transparent pixels kept around every image against filtering bleeding
+    /// in its neighbours, 1 by default.
+    pub fn with_padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Largest width and height growing can reach, the device's max texture
+    /// size by default. Growing is disabled with the initial size.
+    pub fn with_max_size(mut self, max_size: u32) -> Self {
+        self.max_size = max_size.min(self.device.limits.max_texture_size);
+        self
+    }
+
+    /// Packs a `width` by `height` image of tightly packed RGBA8 rows.
+    ///
+    /// `InvalidSize` if `rgba` is not `width * height * 4` bytes,
+    /// `TriedToOverflow` if it does not fit even at the max size.
+    pub fn insert(&mut self, width: u32, height: u32, rgba: &[u8]) -> Result<AtlasId, BufferError> {
+        if rgba.len() != width as usize * height as usize * 4 {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let (padded_width, padded_height) = (width + 2 * self.padding, height + 2 * self.padding);
+        let (x, y) = loop {
+            if let Some(position) = self.packer.pack(padded_width, padded_height) {
+                break position;
+            }
+            self.grow()?;
+        };
+
+        let rect = AtlasRect {
+            x: x + self.padding,
+            y: y + self.padding,
+            width,
+            height,
+        };
+        let stride = self.packer.width() as usize * 4;
+        // empty images, like the glyph of a space, copy nothing
+        for (row, source) in rgba.chunks_exact(width.max(1) as usize * 4).enumerate() {
+            let start = (rect.y as usize + row) * stride + rect.x as usize * 4;
+            self.pixels[start..start + source.len()].copy_from_slice(source);
+        }
+        self.dirty = true;
+
+        self.rects.push(rect);
+        Ok(AtlasId(self.rects.len() - 1))
+    }
+
+    pub fn rect(&self, id: AtlasId) -> AtlasRect {
+        self.rects[id.0]
+    }
+
+    /// Texture coordinates of `id` in the current `generation`.
+    pub fn uv(&self, id: AtlasId) -> UvRect {
+        let rect = self.rects[id.0];
+        let size = Vector2::new(self.packer.width() as f32, self.packer.height() as f32);
+        UvRect {
+            min: Vector2::new(rect.x as f32 / size.x, rect.y as f32 / size.y),
+            max: Vector2::new(
+                (rect.x + rect.width) as f32 / size.x,
+                (rect.y + rect.height) as f32 / size.y,
+            ),
+        }
+    }
+
+    pub fn texture(&self) -> &Texture2D {
+        &self.texture
+    }
+
+    /// Changes when growing replaced the texture.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn width(&self) -> u32 {
+        self.packer.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.packer.height()
+    }
+
+    /// Number of packed images.
+    pub fn len(&self) -> usize {
+        self.rects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    /// Uploads images inserted since the last update, returns true if
+    /// anything was recorded. Has to run every frame for replaced textures
+    /// to be freed.
+    pub unsafe fn update(&mut self, uri: &UpdateRecordInfo) -> bool {
+        for (frames, _) in self.retired.iter_mut() {
+            *frames -= 1;
+        }
+        self.retired.retain(|(frames, _)| *frames > 0);
+
+        if self.dirty {
+            self.dirty = false;
+            if let Err(err) = self.texture.write(&self.pixels) {
+                error!(target: logging::MEMORY, "TextureAtlas upload failed: {:?}", err);
+            }
+        }
+        self.texture.update(uri)
+    }
+
+    // doubles the shorter side, keeping every pixel where it is
+    fn grow(&mut self) -> Result<(), BufferError> {
+        let (width, height) = (self.packer.width(), self.packer.height());
+        let (new_width, new_height) = if width <= height {
+            (width * 2, height)
+        } else {
+            (width, height * 2)
+        };
+        if new_width > self.max_size || new_height > self.max_size {
+            return Err(BufferError::TriedToOverflow);
+        }
+
+        let texture =
+            Texture2D::new_with_device(self.device.clone(), new_width, new_height, &self.config)?;
+        let mut pixels = vec![0; new_width as usize * new_height as usize * 4];
+        for (row, source) in self.pixels.chunks_exact(width as usize * 4).enumerate() {
+            let start = row * new_width as usize * 4;
+            pixels[start..start + source.len()].copy_from_slice(source);
+        }
+
+        let old = mem::replace(&mut self.texture, texture);
+        self.retired.push((self.frames_in_flight, old));
+        self.pixels = pixels;
+        self.packer.grow(new_width, new_height);
+        self.dirty = true;
+        self.generation += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlaps(a: AtlasRect, b: AtlasRect) -> bool {
+        a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height
+    }
+
+    fn pack(packer: &mut RectPacker, width: u32, height: u32) -> Option<AtlasRect> {
+        let (x, y) = packer.pack(width, height)?;
+        Some(AtlasRect {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    #[test]
+    fn lowest_then_leftmost() {
+        let mut packer = RectPacker::new(64, 64);
+        assert_eq!(packer.pack(40, 10), Some((0, 0)));
+        assert_eq!(packer.pack(20, 30), Some((40, 0)));
+        assert_eq!(packer.pack(30, 5), Some((0, 10)));
+        assert_eq!(packer.pack(4, 4), Some((60, 0)));
+    }
+
+    #[test]
+    fn fills_exactly() {
+        let mut packer = RectPacker::new(64, 64);
+        for _ in 0..4 {
+            assert!(packer.pack(32, 32).is_some());
+        }
+        assert_eq!(packer.pack(32, 32), None);
+        assert_eq!(packer.pack(1, 1), None);
+        assert_eq!(packer.pack(0, 5), Some((0, 0)));
+    }
+
+    #[test]
+    fn too_large() {
+        let mut packer = RectPacker::new(64, 64);
+        assert_eq!(packer.pack(65, 1), None);
+        assert_eq!(packer.pack(1, 65), None);
+        assert_eq!(packer.pack(64, 64), Some((0, 0)));
+    }
+
+    #[test]
+    fn no_overlaps() {
+        let mut packer = RectPacker::new(256, 256);
+        let mut rects = Vec::new();
+        // deterministic mix of sizes until the packer is full
+        let mut seed = 7_u32;
+        for _ in 0..500 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let width = 1 + (seed >> 8) % 40;
+            let height = 1 + (seed >> 20) % 40;
+            if let Some(rect) = pack(&mut packer, width, height) {
+                assert!(rect.x + rect.width <= 256 && rect.y + rect.height <= 256);
+                assert!(rects.iter().all(|other| !overlaps(rect, *other)));
+                rects.push(rect);
+            }
+        }
+        assert!(rects.len() > 20);
+    }
+
+    #[test]
+    fn grow_keeps_rects() {
+        let mut packer = RectPacker::new(64, 64);
+        let rects = (0..4)
+            .map(|_| pack(&mut packer, 32, 32).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(packer.pack(32, 32), None);
+
+        packer.grow(128, 64);
+        assert_eq!((packer.width(), packer.height()), (128, 64));
+        for _ in 0..4 {
+            let rect = pack(&mut packer, 32, 32).unwrap();
+            assert!(rect.x >= 64);
+            assert!(rects.iter().all(|other| !overlaps(rect, *other)));
+        }
+        assert_eq!(packer.pack(32, 32), None);
+
+        packer.grow(128, 128);
+        assert_eq!(packer.pack(128, 64), Some((0, 64)));
+    }
+}
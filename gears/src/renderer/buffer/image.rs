@@ -1,9 +1,13 @@
 use ash::{version::DeviceV1_0, vk};
 use bitflags::bitflags;
 use log::*;
+use parking_lot::Mutex;
 use std::{marker::PhantomData, sync::Arc};
 
-use crate::renderer::{device::RenderDevice, Renderer};
+use crate::{
+    logging,
+    renderer::{device::RenderDevice, Renderer},
+};
 
 use super::{find_mem_type, BufferError};
 
@@ -16,9 +20,28 @@ bitflags! {
         const READ = 1;
         const WRITE = 2;
         const BOTH = 3;
+        const UPLOAD = 4;
+        const STORAGE = 8;
+        /// Source of copies and resolves.
+        const COPY = 16;
     }
 }
 
+/// Image layouts with the accesses they are used for, see `RenderRecordInfo::transition`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Contents are discarded by the next transition.
+    Undefined,
+    /// Storage image reads and writes.
+    General,
+    /// Sampled in vertex, fragment or compute shaders.
+    ShaderRead,
+    ColorAttachment,
+    DepthAttachment,
+    TransferSrc,
+    TransferDst,
+}
+
 pub enum ImageFormat<T> {
     R,
     RG,
@@ -42,6 +65,9 @@ pub struct ImageBuilder2D {
     base: ImageBuilder,
     width: u32,
     height: u32,
+    mip_levels: u32,
+    samples: vk::SampleCountFlags,
+    cube: bool,
 }
 
 pub struct ImageBuilder3D {
@@ -57,10 +83,64 @@ pub struct Image {
     image: vk::Image,
     image_view: vk::ImageView,
     memory: Option<vk::DeviceMemory>,
+    aspects: vk::ImageAspectFlags,
+    mip_levels: u32,
+    layers: u32,
+
+    // layout at the end of the last recorded use
+    layout: Mutex<Layout>,
 
     owns_image: bool,
 }
 
+impl Layout {
+    fn vk(self) -> vk::ImageLayout {
+        match self {
+            Layout::Undefined => vk::ImageLayout::UNDEFINED,
+            Layout::General => vk::ImageLayout::GENERAL,
+            Layout::ShaderRead => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            Layout::ColorAttachment => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            Layout::DepthAttachment => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            Layout::TransferSrc => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            Layout::TransferDst => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        }
+    }
+
+    fn access(self) -> vk::AccessFlags {
+        match self {
+            Layout::Undefined => vk::AccessFlags::empty(),
+            Layout::General => vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            Layout::ShaderRead => vk::AccessFlags::SHADER_READ,
+            Layout::ColorAttachment => {
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            }
+            Layout::DepthAttachment => {
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+            }
+            Layout::TransferSrc => vk::AccessFlags::TRANSFER_READ,
+            Layout::TransferDst => vk::AccessFlags::TRANSFER_WRITE,
+        }
+    }
+
+    fn stages(self) -> vk::PipelineStageFlags {
+        match self {
+            Layout::Undefined => vk::PipelineStageFlags::TOP_OF_PIPE,
+            Layout::General | Layout::ShaderRead => {
+                vk::PipelineStageFlags::VERTEX_SHADER
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::COMPUTE_SHADER
+            }
+            Layout::ColorAttachment => vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            Layout::DepthAttachment => {
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+            }
+            Layout::TransferSrc | Layout::TransferDst => vk::PipelineStageFlags::TRANSFER,
+        }
+    }
+}
+
 impl<T> Into<vk::Format> for ImageFormat<T>
 where
     ImageFormat<T>: BaseFormat,
@@ -183,7 +263,7 @@ impl ImageBuilder {
         ImageBuilder1D { base: self, width }
     }
 
-    fn get(
+    pub(super) fn get(
         image_usage: ImageUsage,
         image_format: vk::Format,
     ) -> (vk::ImageAspectFlags, vk::ImageUsageFlags) {
@@ -201,14 +281,27 @@ impl ImageBuilder {
             usage |= vk::ImageUsageFlags::SAMPLED;
         }
         if image_usage.contains(ImageUsage::WRITE) {
-            usage |= if depth {
+            usage |= if depth || has_stencil(image_format) {
                 vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
             } else {
                 vk::ImageUsageFlags::COLOR_ATTACHMENT
             };
         }
+        if image_usage.contains(ImageUsage::UPLOAD) {
+            usage |= vk::ImageUsageFlags::TRANSFER_DST;
+        }
+        if image_usage.contains(ImageUsage::STORAGE) {
+            usage |= vk::ImageUsageFlags::STORAGE;
+        }
+        if image_usage.contains(ImageUsage::COPY) {
+            usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        }
 
-        let aspects = if depth {
+        let aspects = if has_stencil(image_format) && depth {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        } else if has_stencil(image_format) {
+            vk::ImageAspectFlags::STENCIL
+        } else if depth {
             vk::ImageAspectFlags::DEPTH
         } else {
             vk::ImageAspectFlags::COLOR
@@ -217,6 +310,28 @@ impl ImageBuilder {
         (aspects, usage)
     }
 
+    pub(super) fn info(
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        extent: vk::Extent3D,
+        image_type: vk::ImageType,
+        mip_levels: u32,
+        samples: vk::SampleCountFlags,
+    ) -> vk::ImageCreateInfo {
+        vk::ImageCreateInfo::builder()
+            .format(format)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .extent(extent)
+            .image_type(image_type)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .samples(samples)
+            .mip_levels(mip_levels)
+            .array_layers(1)
+            .build()
+    }
+
     pub fn build_with_image<T>(
         self,
         image: vk::Image,
@@ -234,7 +349,10 @@ impl ImageBuilder {
             image,
             format,
             aspects,
-            vk::ImageType::TYPE_2D,
+            vk::ImageViewType::TYPE_2D,
+            1,
+            1,
+            None,
             false,
         )
     }
@@ -246,6 +364,9 @@ impl ImageBuilder1D {
             base: self.base,
             width: self.width,
             height,
+            mip_levels: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            cube: false,
         }
     }
 
@@ -264,14 +385,15 @@ impl ImageBuilder1D {
                 depth: 1,
             };
 
-            Image::new(
-                self.base.device,
+            let image_info = ImageBuilder::info(
                 format,
                 usage,
-                aspects,
                 extent,
                 vk::ImageType::TYPE_1D,
-            )
+                1,
+                vk::SampleCountFlags::TYPE_1,
+            );
+            Image::new(self.base.device, &image_info, aspects)
         }
     }
 }
@@ -286,11 +408,35 @@ impl ImageBuilder2D {
         }
     }
 
+    /// Mip levels are not generated, they are usually uploaded one by one.
+    pub fn with_mip_levels(mut self, mip_levels: u32) -> Self {
+        self.mip_levels = mip_levels;
+        self
+    }
+
+    /// Multisampled attachment, the image can only have one mip level.
+    pub fn with_samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// Cube map of six square layers, viewed as ```samplerCube```.
+    pub fn with_cube(mut self) -> Self {
+        self.cube = true;
+        self
+    }
+
     pub fn build<T>(self, image_usage: ImageUsage, image_format: T) -> Result<Image, BufferError>
     where
         T: Into<vk::Format>,
     {
-        if self.width == 0 || self.height == 0 {
+        if self.width == 0
+            || self.height == 0
+            || self.mip_levels == 0
+            || (self.mip_levels > 1 && self.samples != vk::SampleCountFlags::TYPE_1)
+            || (self.cube
+                && (self.width != self.height || self.samples != vk::SampleCountFlags::TYPE_1))
+        {
             Err(BufferError::InvalidSize)
         } else {
             let format = image_format.into();
@@ -301,14 +447,19 @@ impl ImageBuilder2D {
                 depth: 1,
             };
 
-            Image::new(
-                self.base.device,
+            let mut image_info = ImageBuilder::info(
                 format,
                 usage,
-                aspects,
                 extent,
                 vk::ImageType::TYPE_2D,
-            )
+                self.mip_levels,
+                self.samples,
+            );
+            if self.cube {
+                image_info.flags = vk::ImageCreateFlags::CUBE_COMPATIBLE;
+                image_info.array_layers = 6;
+            }
+            Image::new(self.base.device, &image_info, aspects)
         }
     }
 }
@@ -329,14 +480,15 @@ impl ImageBuilder3D {
                 depth: self.depth,
             };
 
-            Image::new(
-                self.base.device,
+            let image_info = ImageBuilder::info(
                 format,
                 usage,
-                aspects,
                 extent,
                 vk::ImageType::TYPE_3D,
-            )
+                1,
+                vk::SampleCountFlags::TYPE_1,
+            );
+            Image::new(self.base.device, &image_info, aspects)
         }
     }
 }
@@ -344,68 +496,118 @@ impl ImageBuilder3D {
 impl Image {
     fn new(
         device: Arc<RenderDevice>,
-        format: vk::Format,
-        usage: vk::ImageUsageFlags,
+        image_info: &vk::ImageCreateInfo,
         aspects: vk::ImageAspectFlags,
-        extent: vk::Extent3D,
-        image_type: vk::ImageType,
     ) -> Result<Self, BufferError> {
-        let image_info = vk::ImageCreateInfo::builder()
-            .format(format)
-            .tiling(vk::ImageTiling::OPTIMAL)
-            .usage(usage)
-            .extent(extent)
-            .image_type(image_type)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .mip_levels(1)
-            .array_layers(1)
-            .build();
-
-        let image = unsafe { device.create_image(&image_info, None) }.or_else(|err| {
-            error!("Image ({:?}) creation failed: {:?}", image_info, err);
+        let image = unsafe { device.create_image(image_info, None) }.or_else(|err| {
+            error!(
+                target: logging::MEMORY,
+                "Image ({:?}) creation failed: {:?}",
+                image_info,
+                err
+            );
             Err(BufferError::OutOfMemory)
         })?;
 
-        Self::new_with_image(device, image, format, aspects, image_type, true)
+        // the view can only be created once the memory is bound
+        let memory = match Self::allocate_bound(&device, image) {
+            Ok(memory) => memory,
+            Err(err) => {
+                unsafe { device.destroy_image(image, None) };
+                return Err(err);
+            }
+        };
+
+        Self::new_with_image(
+            device,
+            image,
+            image_info.format,
+            aspects,
+            Self::view_type(image_info),
+            image_info.mip_levels,
+            image_info.array_layers,
+            Some(memory),
+            true,
+        )
     }
 
-    fn new_with_image(
+    // already bound to memory someone else frees, for `TransientImages`
+    pub(super) fn new_with_bound_image(
         device: Arc<RenderDevice>,
         image: vk::Image,
-        format: vk::Format,
+        image_info: &vk::ImageCreateInfo,
         aspects: vk::ImageAspectFlags,
-        image_type: vk::ImageType,
-        owns_image: bool,
     ) -> Result<Self, BufferError> {
-        let memory = if owns_image {
-            let req = unsafe { device.get_image_memory_requirements(image) };
+        Self::new_with_image(
+            device,
+            image,
+            image_info.format,
+            aspects,
+            Self::view_type(image_info),
+            image_info.mip_levels,
+            image_info.array_layers,
+            None,
+            true,
+        )
+    }
 
-            let mem_type = find_mem_type(
-                &device.memory_types,
-                &req,
-                vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            )
-            .ok_or(BufferError::NoMemoryType(
-                vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            ))?;
+    fn allocate_bound(
+        device: &Arc<RenderDevice>,
+        image: vk::Image,
+    ) -> Result<vk::DeviceMemory, BufferError> {
+        let req = unsafe { device.get_image_memory_requirements(image) };
 
-            let memory_info = vk::MemoryAllocateInfo::builder()
-                .allocation_size(req.size)
-                .memory_type_index(mem_type);
+        let mem_type = find_mem_type(
+            &device.memory_types,
+            &req,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+        .ok_or(BufferError::NoMemoryType(
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        ))?;
 
-            let memory = unsafe { device.allocate_memory(&memory_info, None) }
-                .or(Err(BufferError::OutOfMemory))?;
+        let memory_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(req.size)
+            .memory_type_index(mem_type);
 
-            unsafe { device.bind_image_memory(image, memory, 0) }
-                .or(Err(BufferError::OutOfMemory))?;
+        let memory = unsafe { device.allocate_memory(&memory_info, None) }
+            .or(Err(BufferError::OutOfMemory))?;
 
-            Some(memory)
-        } else {
-            None
-        };
+        match unsafe { device.bind_image_memory(image, memory, 0) } {
+            Ok(()) => Ok(memory),
+            Err(_) => {
+                unsafe { device.free_memory(memory, None) };
+                Err(BufferError::OutOfMemory)
+            }
+        }
+    }
 
+    fn view_type(image_info: &vk::ImageCreateInfo) -> vk::ImageViewType {
+        match image_info.image_type {
+            vk::ImageType::TYPE_1D => vk::ImageViewType::TYPE_1D,
+            vk::ImageType::TYPE_2D
+                if image_info
+                    .flags
+                    .contains(vk::ImageCreateFlags::CUBE_COMPATIBLE) =>
+            {
+                vk::ImageViewType::CUBE
+            }
+            vk::ImageType::TYPE_2D => vk::ImageViewType::TYPE_2D,
+            _ /* vk::ImageType::TYPE_3D */ => vk::ImageViewType::TYPE_3D,
+        }
+    }
+
+    fn new_with_image(
+        device: Arc<RenderDevice>,
+        image: vk::Image,
+        format: vk::Format,
+        aspects: vk::ImageAspectFlags,
+        view_type: vk::ImageViewType,
+        mip_levels: u32,
+        layers: u32,
+        memory: Option<vk::DeviceMemory>,
+        owns_image: bool,
+    ) -> Result<Self, BufferError> {
         let image_view_info = vk::ImageViewCreateInfo::builder()
             .image(image)
             .format(format)
@@ -417,23 +619,32 @@ impl Image {
                     .a(vk::ComponentSwizzle::A)
                     .build(),
             )
-            .view_type(match image_type {
-                vk::ImageType::TYPE_1D => vk::ImageViewType::TYPE_1D,
-                vk::ImageType::TYPE_2D => vk::ImageViewType::TYPE_2D,
-                _ /* vk::ImageType::TYPE_3D */ => vk::ImageViewType::TYPE_3D,
-            })
+            .view_type(view_type)
             .subresource_range(
                 vk::ImageSubresourceRange::builder()
                     .aspect_mask(aspects)
                     .base_mip_level(0)
-                    .level_count(1)
+                    .level_count(mip_levels)
                     .base_array_layer(0)
-                    .layer_count(1)
+                    .layer_count(layers)
                     .build(),
             );
 
-        let image_view = unsafe { device.create_image_view(&image_view_info, None) }
-            .or(Err(BufferError::OutOfMemory))?;
+        // takes the image and memory, they are freed like on drop
+        let image_view = match unsafe { device.create_image_view(&image_view_info, None) } {
+            Ok(image_view) => image_view,
+            Err(_) => {
+                unsafe {
+                    if let Some(memory) = memory {
+                        device.free_memory(memory, None);
+                    }
+                    if owns_image {
+                        device.destroy_image(image, None);
+                    }
+                }
+                return Err(BufferError::OutOfMemory);
+            }
+        };
 
         Ok(Self {
             device,
@@ -441,14 +652,118 @@ impl Image {
             image,
             image_view,
             memory,
+            aspects,
+            mip_levels,
+            layers,
+
+            layout: Mutex::new(Layout::Undefined),
 
             owns_image,
         })
     }
 
+    /// The layout the last recorded transition or pass left this image in.
+    pub fn layout(&self) -> Layout {
+        *self.layout.lock()
+    }
+
+    // for passes that change the layout themselves, render pass final layouts for ex.
+    pub(crate) fn set_layout(&self, layout: Layout) {
+        *self.layout.lock() = layout;
+    }
+
+    // waits for the accesses of the current layout before the accesses of the new one
+    pub(crate) unsafe fn transition(&self, command_buffer: vk::CommandBuffer, layout: Layout) {
+        let mut current = self.layout.lock();
+        let old = *current;
+        *current = layout;
+
+        // nothing to wait for or no layout to change to
+        if (old == layout && old == Layout::ShaderRead) || layout == Layout::Undefined {
+            return;
+        }
+
+        self.barrier(command_buffer, old.vk(), old.stages(), old.access(), layout);
+    }
+
+    // like `transition` from `Layout::Undefined`, but also waits for writes of
+    // images sharing the memory, the contents are discarded
+    pub(crate) unsafe fn alias(&self, command_buffer: vk::CommandBuffer, layout: Layout) {
+        *self.layout.lock() = layout;
+
+        self.barrier(
+            command_buffer,
+            vk::ImageLayout::UNDEFINED,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::MEMORY_WRITE,
+            layout,
+        );
+    }
+
+    unsafe fn barrier(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        old_layout: vk::ImageLayout,
+        src_stages: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+        layout: Layout,
+    ) {
+        let barriers = [vk::ImageMemoryBarrier::builder()
+            .old_layout(old_layout)
+            .new_layout(layout.vk())
+            .src_access_mask(src_access)
+            .dst_access_mask(layout.access())
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(self.aspects)
+                    .base_mip_level(0)
+                    .level_count(self.mip_levels)
+                    .base_array_layer(0)
+                    .layer_count(self.layers)
+                    .build(),
+            )
+            .build()];
+
+        self.device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stages,
+            layout.stages(),
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &barriers,
+        );
+    }
+
     pub fn view(&self) -> vk::ImageView {
         self.image_view
     }
+
+    pub fn image(&self) -> vk::Image {
+        self.image
+    }
+
+    pub fn aspects(&self) -> vk::ImageAspectFlags {
+        self.aspects
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.mip_levels
+    }
+
+    /// 6 for cube maps, 1 otherwise.
+    pub fn layers(&self) -> u32 {
+        self.layers
+    }
+}
+
+impl AsRef<Image> for Image {
+    fn as_ref(&self) -> &Image {
+        self
+    }
 }
 
 impl Drop for Image {
@@ -465,6 +780,17 @@ impl Drop for Image {
     }
 }
 
+/// True if `format` has a stencil aspect.
+pub(crate) fn has_stencil(format: vk::Format) -> bool {
+    match format {
+        vk::Format::S8_UINT
+        | vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => true,
+        _ => false,
+    }
+}
+
 /* fn find_format<B: Backend>(
     physical_device: &B::PhysicalDevice,
     accepted_formats: &[Format],
@@ -12,7 +12,10 @@ use log::debug;
 use super::{
     create_buffer, stage::StageBuffer, vertex::VertexBuffer, Buffer, BufferError, WriteType,
 };
-use crate::renderer::{device::RenderDevice, RenderRecordInfo, Renderer, UpdateRecordInfo};
+use crate::{
+    logging,
+    renderer::{device::RenderDevice, RenderRecordInfo, Renderer, UpdateRecordInfo},
+};
 
 pub trait UInt {
     fn get() -> vk::IndexType;
@@ -91,7 +94,7 @@ impl<I: UInt> IndexBuffer<I> {
 
     pub unsafe fn bind(&self, rri: &RenderRecordInfo) {
         if rri.debug_calls {
-            debug!("cmd_bind_index_buffer");
+            debug!(target: logging::COMMANDS, "cmd_bind_index_buffer");
         }
 
         self.device
@@ -105,7 +108,7 @@ impl<I: UInt> IndexBuffer<I> {
         rri.triangles.fetch_add(self.len() / 3, Ordering::SeqCst);
 
         if rri.debug_calls {
-            debug!("cmd_draw");
+            debug!(target: logging::COMMANDS, "cmd_draw");
         }
 
         self.device
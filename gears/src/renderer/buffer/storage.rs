@@ -0,0 +1,113 @@
+use ash::{version::DeviceV1_0, vk};
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use super::{create_buffer, stage::StageBuffer, Buffer, BufferError, WriteType};
+use crate::renderer::{device::RenderDevice, Renderer, UpdateRecordInfo};
+
+/// Device local shader storage buffer.
+///
+/// `usage` is added to ```STORAGE_BUFFER | TRANSFER_DST```,
+/// for ex. ```INDIRECT_BUFFER``` for compute generated draws.
+pub struct StorageBuffer<T> {
+    device: Arc<RenderDevice>,
+
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+
+    requested_copy: AtomicBool,
+    stage: StageBuffer<T>,
+}
+
+impl<T> StorageBuffer<T> {
+    pub fn new(
+        renderer: &Renderer,
+        size: usize,
+        usage: vk::BufferUsageFlags,
+    ) -> Result<Self, BufferError> {
+        Self::new_with_device(renderer.rdevice.clone(), size, usage)
+    }
+
+    pub fn new_with_data(
+        renderer: &Renderer,
+        data: &[T],
+        usage: vk::BufferUsageFlags,
+    ) -> Result<Self, BufferError> {
+        let mut buffer = Self::new(renderer, data.len(), usage)?;
+        buffer.write(0, data)?;
+        Ok(buffer)
+    }
+
+    pub fn new_with_device(
+        device: Arc<RenderDevice>,
+        size: usize,
+        usage: vk::BufferUsageFlags,
+    ) -> Result<Self, BufferError> {
+        let byte_len = size * mem::size_of::<T>();
+        let (buffer, memory) = create_buffer(
+            &device,
+            byte_len,
+            vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::STORAGE_BUFFER | usage,
+            vk::SharingMode::EXCLUSIVE,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        let stage = StageBuffer::new_with_device(device.clone(), size, true)?;
+
+        Ok(Self {
+            device,
+
+            buffer,
+            memory,
+
+            requested_copy: AtomicBool::new(false),
+            stage,
+        })
+    }
+
+    pub fn write(&mut self, offset: usize, data: &[T]) -> Result<WriteType, BufferError> {
+        let result = self.stage.write_slice(offset, data);
+        if let Ok(WriteType::Write) = result {
+            self.requested_copy.store(true, Ordering::SeqCst);
+        }
+        result
+    }
+
+    pub fn len(&self) -> usize {
+        self.stage.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.stage.capacity()
+    }
+}
+
+impl<T> Buffer for StorageBuffer<T> {
+    unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        let requested_copy = self.requested_copy.swap(false, Ordering::SeqCst);
+
+        if requested_copy {
+            self.stage.copy_to(uri, self);
+        }
+
+        requested_copy
+    }
+
+    fn get(&self) -> vk::Buffer {
+        self.buffer
+    }
+}
+
+impl<T> Drop for StorageBuffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.free_memory(self.memory, None);
+            self.device.destroy_buffer(self.buffer, None);
+        }
+    }
+}
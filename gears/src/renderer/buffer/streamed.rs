@@ -0,0 +1,416 @@
+use ash::{version::DeviceV1_0, vk};
+use log::Level;
+use parking_lot::Mutex;
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use super::{
+    image::{Image, ImageBuilder, ImageFormat, ImageUsage, Layout},
+    stage::StageBuffer,
+    Buffer, BufferError,
+};
+use crate::{
+    logging,
+    renderer::{device::RenderDevice, Renderer, UpdateRecordInfo},
+};
+
+/// Device memory limit shared by all streamed textures, see `Renderer::texture_budget`.
+pub struct TextureBudget {
+    limit: AtomicU64,
+    used: AtomicU64,
+}
+
+/// RGBA8 (sRGB) mipmapped 2D texture with only some of its mip levels resident.
+///
+/// Starts with the smallest `initial_levels` levels in device memory and
+/// streams in one larger level per `update` until `target_levels` are
+/// resident, as long as the `TextureBudget` allows it. Lowering the target
+/// streams levels out again. Every residency change creates a new image, so
+/// `view()` changes with `generation()`: rebuild pipelines using it or
+/// `TextureRegistry::replace` its index.
+///
+/// All levels are kept in host memory. `update` must run every frame for
+/// replaced images to be freed.
+pub struct StreamedTexture2D {
+    device: Arc<RenderDevice>,
+
+    sampler: vk::Sampler,
+    // full resolution first
+    levels: Vec<(u32, u32, Vec<u8>)>,
+    frames_in_flight: usize,
+
+    state: Mutex<StreamState>,
+}
+
+struct StreamState {
+    image: Image,
+    stage: Option<StageBuffer<u8>>,
+    requested_copy: bool,
+
+    resident: u32,
+    target: u32,
+    bytes: u64,
+    generation: u64,
+
+    // old images and their stage buffers stay alive until frames using them are done
+    retired: Vec<(usize, Image, Option<StageBuffer<u8>>)>,
+}
+
+impl Default for TextureBudget {
+    fn default() -> Self {
+        Self {
+            limit: AtomicU64::new(!0),
+            used: AtomicU64::new(0),
+        }
+    }
+}
+
+impl TextureBudget {
+    /// Unlimited by default.
+    pub fn limit(&self) -> u64 {
+        self.limit.load(Ordering::SeqCst)
+    }
+
+    /// Lowering the limit does not evict anything, textures only stop streaming in.
+    pub fn set_limit(&self, bytes: u64) {
+        self.limit.store(bytes, Ordering::SeqCst);
+    }
+
+    /// Bytes of resident streamed texture levels.
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    fn reserve(&self, bytes: u64) -> bool {
+        let limit = self.limit();
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(bytes).filter(|&used| used <= limit)
+            })
+            .is_ok()
+    }
+
+    fn release(&self, bytes: u64) {
+        self.used.fetch_sub(bytes, Ordering::SeqCst);
+    }
+}
+
+impl StreamedTexture2D {
+    /// `data` is the full resolution level as tightly packed RGBA8 rows, smaller levels are box filtered from it.
+    pub fn new(
+        renderer: &Renderer,
+        width: u32,
+        height: u32,
+        data: &[u8],
+        initial_levels: u32,
+    ) -> Result<Self, BufferError> {
+        Self::new_with_device(
+            renderer.rdevice.clone(),
+            renderer.frames_in_flight(),
+            width,
+            height,
+            data,
+            initial_levels,
+        )
+    }
+
+    pub fn new_with_device(
+        device: Arc<RenderDevice>,
+        frames_in_flight: usize,
+        width: u32,
+        height: u32,
+        data: &[u8],
+        initial_levels: u32,
+    ) -> Result<Self, BufferError> {
+        if width == 0 || height == 0 || data.len() != width as usize * height as usize * 4 {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let levels = mip_chain(width, height, data);
+        let level_count = levels.len() as u32;
+        let initial_levels = initial_levels.max(1).min(level_count);
+
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::REPEAT)
+            .address_mode_v(vk::SamplerAddressMode::REPEAT)
+            .address_mode_w(vk::SamplerAddressMode::REPEAT)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .min_lod(0.0)
+            .max_lod(vk::LOD_CLAMP_NONE);
+
+        let sampler = unsafe { device.create_sampler(&sampler_info, None) }
+            .or(Err(BufferError::OutOfMemory))?;
+
+        // the initial levels ignore the budget
+        let (image, stage) = create(&device, resident_levels(&levels, initial_levels))?;
+        let bytes = resident_bytes(&levels, initial_levels);
+        device
+            .texture_budget
+            .used
+            .fetch_add(bytes, Ordering::SeqCst);
+
+        Ok(Self {
+            device,
+
+            sampler,
+            levels,
+            frames_in_flight,
+
+            state: Mutex::new(StreamState {
+                image,
+                stage: Some(stage),
+                requested_copy: true,
+
+                resident: initial_levels,
+                target: level_count,
+                bytes,
+                generation: 0,
+
+                retired: Vec::new(),
+            }),
+        })
+    }
+
+    /// Levels to stream towards, all of them by default.
+    pub fn set_target_levels(&self, levels: u32) {
+        self.state.lock().target = levels.max(1).min(self.level_count());
+    }
+
+    pub fn resident_levels(&self) -> u32 {
+        self.state.lock().resident
+    }
+
+    pub fn level_count(&self) -> u32 {
+        self.levels.len() as u32
+    }
+
+    /// Incremented every time `view()` changes.
+    pub fn generation(&self) -> u64 {
+        self.state.lock().generation
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.state.lock().image.view()
+    }
+
+    pub fn sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+
+    /// Frees replaced images, moves residency one level towards the target and uploads it.
+    pub unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        let mut state = self.state.lock();
+
+        for (frames, _, _) in state.retired.iter_mut() {
+            *frames -= 1;
+        }
+        state.retired.retain(|(frames, _, _)| *frames > 0);
+
+        let next = if state.resident < state.target {
+            state.resident + 1
+        } else if state.resident > state.target {
+            state.resident - 1
+        } else {
+            state.resident
+        };
+
+        if next != state.resident && !state.requested_copy {
+            self.stream(&mut state, next);
+        }
+
+        let requested_copy = mem::replace(&mut state.requested_copy, false);
+        if requested_copy {
+            self.copy(uri, &state);
+        }
+
+        requested_copy
+    }
+
+    fn stream(&self, state: &mut StreamState, resident: u32) {
+        let budget = &self.device.texture_budget;
+        let bytes = resident_bytes(&self.levels, resident);
+        let grow = bytes.saturating_sub(state.bytes);
+        if !budget.reserve(grow) {
+            return;
+        }
+
+        let (image, stage) = match create(&self.device, resident_levels(&self.levels, resident)) {
+            Ok(created) => created,
+            Err(err) => {
+                log_throttled!(
+                    target: logging::MEMORY,
+                    Level::Warn,
+                    "Texture level streaming failed: {:?}",
+                    err
+                );
+                budget.release(grow);
+                return;
+            }
+        };
+        budget.release(state.bytes.saturating_sub(bytes));
+
+        log_throttled!(
+            target: logging::MEMORY,
+            Level::Debug,
+            "StreamedTexture2D residency: {} -> {} levels",
+            state.resident, resident
+        );
+
+        let old_image = mem::replace(&mut state.image, image);
+        let old_stage = mem::replace(&mut state.stage, Some(stage));
+        state
+            .retired
+            .push((self.frames_in_flight, old_image, old_stage));
+
+        state.requested_copy = true;
+        state.resident = resident;
+        state.bytes = bytes;
+        state.generation += 1;
+    }
+
+    unsafe fn copy(&self, uri: &UpdateRecordInfo, state: &StreamState) {
+        let stage = match state.stage.as_ref() {
+            Some(stage) => stage,
+            None => return,
+        };
+
+        let levels = resident_levels(&self.levels, state.resident);
+        let mut offset = 0;
+        let regions = levels
+            .iter()
+            .enumerate()
+            .map(|(mip_level, (width, height, data))| {
+                let region = vk::BufferImageCopy::builder()
+                    .buffer_offset(offset)
+                    .buffer_row_length(0)
+                    .buffer_image_height(0)
+                    .image_subresource(
+                        vk::ImageSubresourceLayers::builder()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .mip_level(mip_level as u32)
+                            .base_array_layer(0)
+                            .layer_count(1)
+                            .build(),
+                    )
+                    .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                    .image_extent(vk::Extent3D {
+                        width: *width,
+                        height: *height,
+                        depth: 1,
+                    })
+                    .build();
+                offset += data.len() as u64;
+                region
+            })
+            .collect::<Vec<_>>();
+
+        state
+            .image
+            .transition(uri.command_buffer, Layout::TransferDst);
+        self.device.cmd_copy_buffer_to_image(
+            uri.command_buffer,
+            stage.get(),
+            state.image.image(),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &regions[..],
+        );
+        state
+            .image
+            .transition(uri.command_buffer, Layout::ShaderRead);
+    }
+}
+
+impl Drop for StreamedTexture2D {
+    fn drop(&mut self) {
+        self.device
+            .texture_budget
+            .release(self.state.get_mut().bytes);
+        unsafe {
+            self.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+// the smallest `resident` levels
+fn resident_levels(levels: &[(u32, u32, Vec<u8>)], resident: u32) -> &[(u32, u32, Vec<u8>)] {
+    &levels[levels.len() - resident as usize..]
+}
+
+fn resident_bytes(levels: &[(u32, u32, Vec<u8>)], resident: u32) -> u64 {
+    resident_levels(levels, resident)
+        .iter()
+        .map(|(_, _, data)| data.len() as u64)
+        .sum()
+}
+
+// image with `levels` and a filled stage buffer for them
+fn create(
+    device: &Arc<RenderDevice>,
+    levels: &[(u32, u32, Vec<u8>)],
+) -> Result<(Image, StageBuffer<u8>), BufferError> {
+    let (width, height, _) = levels[0];
+
+    let image = ImageBuilder::new_with_device(device.clone())
+        .with_width(width)
+        .with_height(height)
+        .with_mip_levels(levels.len() as u32)
+        .build(
+            ImageUsage::READ | ImageUsage::UPLOAD,
+            ImageFormat::<f32>::RGBA,
+        )?;
+
+    let data = levels
+        .iter()
+        .flat_map(|(_, _, data)| data.iter().cloned())
+        .collect::<Vec<_>>();
+    let mut stage = StageBuffer::new_with_device(device.clone(), data.len(), false)?;
+    stage.write_slice(0, &data)?;
+
+    Ok((image, stage))
+}
+
+// box filtered levels down to 1x1
+fn mip_chain(width: u32, height: u32, data: &[u8]) -> Vec<(u32, u32, Vec<u8>)> {
+    let mut levels = vec![(width, height, data.to_vec())];
+
+    while let Some(&(width, height, ref data)) = levels.last() {
+        if width == 1 && height == 1 {
+            break;
+        }
+
+        let next_width = (width / 2).max(1);
+        let next_height = (height / 2).max(1);
+        let texel = |x: u32, y: u32, c: u32| {
+            let x = x.min(width - 1);
+            let y = y.min(height - 1);
+            data[((y * width + x) * 4 + c) as usize] as u32
+        };
+
+        let mut next = Vec::with_capacity((next_width * next_height * 4) as usize);
+        for y in 0..next_height {
+            for x in 0..next_width {
+                for c in 0..4 {
+                    let sum = texel(x * 2, y * 2, c)
+                        + texel(x * 2 + 1, y * 2, c)
+                        + texel(x * 2, y * 2 + 1, c)
+                        + texel(x * 2 + 1, y * 2 + 1, c);
+                    next.push((sum / 4) as u8);
+                }
+            }
+        }
+
+        levels.push((next_width, next_height, next));
+    }
+
+    levels
+}
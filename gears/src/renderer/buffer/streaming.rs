@@ -0,0 +1,265 @@
+use ash::{version::DeviceV1_0, vk};
+use log::{debug, Level};
+use parking_lot::Mutex;
+use std::{collections::VecDeque, marker::PhantomData, mem, sync::atomic::Ordering, sync::Arc};
+
+use super::{create_buffer_with_fallback, Buffer, BufferError};
+use crate::{
+    logging,
+    renderer::{
+        device::RenderDevice, ImmediateFrameInfo, RenderRecordInfo, Renderer, UpdateRecordInfo,
+    },
+    ExpectLog,
+};
+
+/// Ring of host visible vertex memory for geometry that changes every frame.
+///
+/// Call `begin_frame` with `FrameCtx::immediate` every frame, then
+/// `alloc_frame` while recording. Allocations stay valid until the same
+/// swapchain image comes around again, so anything drawn from them has to
+/// `request_rerecord` every frame, like immediate mode UI or debug lines.
+pub struct StreamingVertexBuffer<T> {
+    device: Arc<RenderDevice>,
+
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapping: *mut u8,
+    non_coherent: bool,
+
+    // bytes
+    capacity: usize,
+    state: Mutex<RingState>,
+
+    _p: PhantomData<T>,
+}
+
+/// Part of a `StreamingVertexBuffer` allocated by `alloc_frame`.
+#[derive(Debug, Clone, Copy)]
+pub struct StreamSlice {
+    buffer: vk::Buffer,
+    // bytes
+    offset: u64,
+    // not bytes
+    len: usize,
+}
+
+struct RingState {
+    head: usize,
+    used: usize,
+
+    // oldest first, the last one is the open frame
+    frames: VecDeque<RingFrame>,
+}
+
+struct RingFrame {
+    image_index: usize,
+    // including bytes skipped at the end when wrapping around
+    bytes: usize,
+    done: bool,
+}
+
+// the mapping is persistent and only written with the state locked
+unsafe impl<T: Send> Send for StreamingVertexBuffer<T> {}
+unsafe impl<T: Sync> Sync for StreamingVertexBuffer<T> {}
+
+impl<T: Copy> StreamingVertexBuffer<T> {
+    /// `size` is the capacity in elements shared by all frames in flight.
+    pub fn new(renderer: &Renderer, size: usize) -> Result<Self, BufferError> {
+        Self::new_with_device(renderer.rdevice.clone(), size)
+    }
+
+    pub fn new_with_device(device: Arc<RenderDevice>, size: usize) -> Result<Self, BufferError> {
+        let capacity = size * mem::size_of::<T>();
+        let (buffer, memory, non_coherent) = create_buffer_with_fallback(
+            &device,
+            capacity,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::SharingMode::EXCLUSIVE,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        // mapped once for the whole lifetime, mapping every alloc_frame would stall
+        let mapping = match unsafe {
+            device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+        } {
+            Ok(mapping) => mapping as *mut u8,
+            Err(_) => {
+                unsafe {
+                    device.free_memory(memory, None);
+                    device.destroy_buffer(buffer, None);
+                }
+                return Err(BufferError::OutOfMemory);
+            }
+        };
+
+        Ok(Self {
+            device,
+
+            buffer,
+            memory,
+            mapping,
+            non_coherent,
+
+            capacity,
+            state: Mutex::new(RingState {
+                head: 0,
+                used: 0,
+
+                frames: VecDeque::new(),
+            }),
+
+            _p: PhantomData::default(),
+        })
+    }
+
+    /// Frees the allocations of the last frame that rendered to the same image.
+    pub fn begin_frame(&self, imfi: &ImmediateFrameInfo) {
+        let mut state = self.state.lock();
+
+        for frame in state.frames.iter_mut() {
+            if frame.image_index == imfi.image_index {
+                frame.done = true;
+            }
+        }
+
+        // only the oldest frames can be freed, the ring stays contiguous
+        while state.frames.front().map_or(false, |frame| frame.done) {
+            let frame = state.frames.pop_front().unwrap();
+            state.used -= frame.bytes;
+        }
+
+        state.frames.push_back(RingFrame {
+            image_index: imfi.image_index,
+            bytes: 0,
+            done: false,
+        });
+    }
+
+    /// Copies `data` into the ring, `TriedToOverflow` if the frames in flight filled it.
+    pub fn alloc_frame(&self, data: &[T]) -> Result<StreamSlice, BufferError> {
+        let size = data.len() * mem::size_of::<T>();
+        if size == 0 {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let mut state = self.state.lock();
+        let state = &mut *state;
+        let frame = state
+            .frames
+            .back_mut()
+            .expect_log("StreamingVertexBuffer::begin_frame was not called");
+
+        let mut offset = state.head;
+        let mut skipped = 0;
+        if offset + size > self.capacity {
+            skipped = self.capacity - offset;
+            offset = 0;
+        }
+
+        if state.used + skipped + size > self.capacity {
+            log_throttled!(
+                target: logging::MEMORY,
+                Level::Debug,
+                "StreamingVertexBuffer full: {} of {} bytes in use",
+                state.used, self.capacity
+            );
+            return Err(BufferError::TriedToOverflow);
+        }
+
+        unsafe { self.write(offset, data) }?;
+
+        state.head = offset + size;
+        state.used += skipped + size;
+        frame.bytes += skipped + size;
+
+        Ok(StreamSlice {
+            buffer: self.buffer,
+            offset: offset as u64,
+            len: data.len(),
+        })
+    }
+
+    /// In elements.
+    pub fn capacity(&self) -> usize {
+        self.capacity / mem::size_of::<T>()
+    }
+
+    unsafe fn write(&self, offset: usize, data: &[T]) -> Result<(), BufferError> {
+        (data.as_ptr() as *const u8)
+            .copy_to_nonoverlapping(self.mapping.add(offset), data.len() * mem::size_of::<T>());
+
+        if self.non_coherent {
+            let ranges = [vk::MappedMemoryRange::builder()
+                .memory(self.memory)
+                .offset(0)
+                .size(vk::WHOLE_SIZE)
+                .build()];
+            self.device
+                .flush_mapped_memory_ranges(&ranges)
+                .or(Err(BufferError::OutOfMemory))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StreamSlice {
+    pub unsafe fn bind(&self, rri: &RenderRecordInfo, binding: u32) {
+        let buffer = [self.buffer];
+        let offsets = [self.offset];
+
+        if rri.debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_bind_vertex_buffers");
+        }
+
+        rri.device
+            .cmd_bind_vertex_buffers(rri.command_buffer, binding, &buffer, &offsets);
+    }
+
+    pub unsafe fn draw(&self, rri: &RenderRecordInfo) {
+        self.bind(rri, 0);
+
+        rri.triangles.fetch_add(self.len / 3, Ordering::SeqCst);
+
+        if rri.debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_draw");
+        }
+
+        rri.device
+            .cmd_draw(rri.command_buffer, self.len as u32, 1, 0, 0);
+    }
+
+    /// Byte offset in `get()`.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get(&self) -> vk::Buffer {
+        self.buffer
+    }
+}
+
+impl<T> Buffer for StreamingVertexBuffer<T> {
+    unsafe fn update(&self, _: &UpdateRecordInfo) -> bool {
+        false
+    }
+
+    fn get(&self) -> vk::Buffer {
+        self.buffer
+    }
+}
+
+impl<T> Drop for StreamingVertexBuffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.unmap_memory(self.memory);
+            self.device.free_memory(self.memory, None);
+            self.device.destroy_buffer(self.buffer, None);
+        }
+    }
+}
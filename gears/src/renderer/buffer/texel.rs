@@ -0,0 +1,186 @@
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk,
+};
+use bitflags::bitflags;
+use log::warn;
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use super::{create_buffer, stage::StageBuffer, Buffer, BufferError, WriteType};
+use crate::renderer::{device::RenderDevice, Renderer, UpdateRecordInfo};
+
+bitflags! {
+    pub struct TexelUsage: u8 {
+        const UNIFORM_TEXEL = 1;
+        const STORAGE_TEXEL = 2;
+    }
+}
+
+/// Device local buffer read through a formatted buffer view.
+///
+/// Every `T` is one texel of `format`, for ex. `[f32; 4]` with
+/// ```R32G32B32A32_SFLOAT```. Bound with `with_uniform_texel_buffer(buffer.view())`
+/// as ```samplerBuffer``` or `with_storage_texel_buffer(buffer.view())` as
+/// ```imageBuffer```, the element count can far exceed the UBO size limit.
+pub struct TexelBuffer<T> {
+    device: Arc<RenderDevice>,
+
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    view: vk::BufferView,
+    format: vk::Format,
+
+    requested_copy: AtomicBool,
+    stage: StageBuffer<T>,
+}
+
+impl<T> TexelBuffer<T> {
+    pub fn new(
+        renderer: &Renderer,
+        size: usize,
+        format: vk::Format,
+        usage: TexelUsage,
+    ) -> Result<Self, BufferError> {
+        Self::new_with_device(renderer.rdevice.clone(), size, format, usage)
+    }
+
+    pub fn new_with_data(
+        renderer: &Renderer,
+        data: &[T],
+        format: vk::Format,
+        usage: TexelUsage,
+    ) -> Result<Self, BufferError> {
+        let mut buffer = Self::new(renderer, data.len(), format, usage)?;
+        buffer.write(0, data)?;
+        Ok(buffer)
+    }
+
+    pub fn new_with_device(
+        device: Arc<RenderDevice>,
+        size: usize,
+        format: vk::Format,
+        usage: TexelUsage,
+    ) -> Result<Self, BufferError> {
+        let (usage, features) = Self::usage_features(usage);
+        if usage.is_empty() {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let supported = unsafe {
+            device
+                .instance
+                .get_physical_device_format_properties(device.pdevice, format)
+        }
+        .buffer_features;
+        if !supported.contains(features) {
+            warn!(
+                "Texel buffer format {:?} does not support {:?}",
+                format, features
+            );
+            return Err(BufferError::UnsupportedFeature("texel buffer format"));
+        }
+
+        let byte_len = size * mem::size_of::<T>();
+        let (buffer, memory) = create_buffer(
+            &device,
+            byte_len,
+            vk::BufferUsageFlags::TRANSFER_DST | usage,
+            vk::SharingMode::EXCLUSIVE,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        let view_info = vk::BufferViewCreateInfo::builder()
+            .buffer(buffer)
+            .format(format)
+            .offset(0)
+            .range(vk::WHOLE_SIZE);
+
+        let view = unsafe { device.create_buffer_view(&view_info, None) }
+            .or(Err(BufferError::OutOfMemory))?;
+
+        let stage = StageBuffer::new_with_device(device.clone(), size, true)?;
+
+        Ok(Self {
+            device,
+
+            buffer,
+            memory,
+            view,
+            format,
+
+            requested_copy: AtomicBool::new(false),
+            stage,
+        })
+    }
+
+    fn usage_features(usage: TexelUsage) -> (vk::BufferUsageFlags, vk::FormatFeatureFlags) {
+        let mut buffer_usage = vk::BufferUsageFlags::empty();
+        let mut features = vk::FormatFeatureFlags::empty();
+        if usage.contains(TexelUsage::UNIFORM_TEXEL) {
+            buffer_usage |= vk::BufferUsageFlags::UNIFORM_TEXEL_BUFFER;
+            features |= vk::FormatFeatureFlags::UNIFORM_TEXEL_BUFFER;
+        }
+        if usage.contains(TexelUsage::STORAGE_TEXEL) {
+            buffer_usage |= vk::BufferUsageFlags::STORAGE_TEXEL_BUFFER;
+            features |= vk::FormatFeatureFlags::STORAGE_TEXEL_BUFFER;
+        }
+
+        (buffer_usage, features)
+    }
+
+    pub fn write(&mut self, offset: usize, data: &[T]) -> Result<WriteType, BufferError> {
+        let result = self.stage.write_slice(offset, data);
+        if let Ok(WriteType::Write) = result {
+            self.requested_copy.store(true, Ordering::SeqCst);
+        }
+        result
+    }
+
+    pub fn view(&self) -> vk::BufferView {
+        self.view
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn len(&self) -> usize {
+        self.stage.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.stage.capacity()
+    }
+}
+
+impl<T> Buffer for TexelBuffer<T> {
+    unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        let requested_copy = self.requested_copy.swap(false, Ordering::SeqCst);
+
+        if requested_copy {
+            self.stage.copy_to(uri, self);
+        }
+
+        requested_copy
+    }
+
+    fn get(&self) -> vk::Buffer {
+        self.buffer
+    }
+}
+
+impl<T> Drop for TexelBuffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_buffer_view(self.view, None);
+            self.device.free_memory(self.memory, None);
+            self.device.destroy_buffer(self.buffer, None);
+        }
+    }
+}
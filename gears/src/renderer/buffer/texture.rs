@@ -0,0 +1,450 @@
+use ash::{version::DeviceV1_0, vk};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use log::{debug, warn};
+
+use super::{
+    image::{Image, ImageBuilder, ImageFormat, ImageUsage, Layout},
+    stage::StageBuffer,
+    Buffer, BufferError, WriteType,
+};
+use crate::{
+    logging,
+    renderer::{device::RenderDevice, Renderer, UpdateRecordInfo},
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TextureConfig {
+    /// Anisotropic filtering level, clamped to `Limits::max_anisotropy`.
+    pub anisotropy: Option<f32>,
+}
+
+/// RGBA8 (sRGB) sampled 2D texture.
+///
+/// Pixels are written to a staging buffer and copied to the image in `update`.
+pub struct Texture2D {
+    device: Arc<RenderDevice>,
+
+    image: Image,
+    sampler: vk::Sampler,
+    width: u32,
+    height: u32,
+
+    requested_copy: AtomicBool,
+    stage: StageBuffer<u8>,
+}
+
+/// RGBA8 (linear) sampled 3D texture, for ex. a color grading LUT.
+///
+/// Sampled with linear filtering and clamped to the edges.
+pub struct Texture3D {
+    device: Arc<RenderDevice>,
+
+    image: Image,
+    sampler: vk::Sampler,
+    extent: vk::Extent3D,
+
+    requested_copy: AtomicBool,
+    stage: StageBuffer<u8>,
+}
+
+impl Texture2D {
+    pub fn new(renderer: &Renderer, width: u32, height: u32) -> Result<Self, BufferError> {
+        Self::new_with_config(renderer, width, height, &TextureConfig::default())
+    }
+
+    pub fn new_with_config(
+        renderer: &Renderer,
+        width: u32,
+        height: u32,
+        config: &TextureConfig,
+    ) -> Result<Self, BufferError> {
+        Self::new_with_device(renderer.rdevice.clone(), width, height, config)
+    }
+
+    pub fn new_with_data(
+        renderer: &Renderer,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Result<Self, BufferError> {
+        let mut texture = Self::new(renderer, width, height)?;
+        texture.write(data)?;
+        Ok(texture)
+    }
+
+    pub fn new_with_device(
+        device: Arc<RenderDevice>,
+        width: u32,
+        height: u32,
+        config: &TextureConfig,
+    ) -> Result<Self, BufferError> {
+        let max_size = device.limits.max_texture_size;
+        if width > max_size || height > max_size {
+            warn!(
+                "Texture size {}x{} exceeds the max texture size {}",
+                width, height, max_size
+            );
+            return Err(BufferError::InvalidSize);
+        }
+
+        let max_anisotropy = device.limits.max_anisotropy;
+        let anisotropy = match config.anisotropy {
+            Some(anisotropy) if anisotropy > max_anisotropy => {
+                warn!(
+                    "Anisotropy {} is not supported, clamping to {}",
+                    anisotropy, max_anisotropy
+                );
+                max_anisotropy
+            }
+            Some(anisotropy) => anisotropy.max(1.0),
+            None => 1.0,
+        };
+
+        let image = ImageBuilder::new_with_device(device.clone())
+            .with_width(width)
+            .with_height(height)
+            .build(
+                ImageUsage::READ | ImageUsage::UPLOAD,
+                ImageFormat::<f32>::RGBA,
+            )?;
+
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::REPEAT)
+            .address_mode_v(vk::SamplerAddressMode::REPEAT)
+            .address_mode_w(vk::SamplerAddressMode::REPEAT)
+            .anisotropy_enable(anisotropy > 1.0)
+            .max_anisotropy(anisotropy)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .min_lod(0.0)
+            .max_lod(0.0);
+
+        let sampler = unsafe { device.create_sampler(&sampler_info, None) }
+            .or(Err(BufferError::OutOfMemory))?;
+
+        let stage = StageBuffer::new_with_device(
+            device.clone(),
+            width as usize * height as usize * 4,
+            true,
+        )?;
+
+        Ok(Self {
+            device,
+
+            image,
+            sampler,
+            width,
+            height,
+
+            requested_copy: AtomicBool::new(false),
+            stage,
+        })
+    }
+
+    /// `data` is tightly packed RGBA8 rows, `width * height * 4` bytes.
+    pub fn write(&mut self, data: &[u8]) -> Result<WriteType, BufferError> {
+        if data.len() != self.stage.capacity() {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let result = self.stage.write_slice(0, data);
+        if let Ok(WriteType::Write) = result {
+            self.requested_copy.store(true, Ordering::SeqCst);
+        }
+        result
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.image.view()
+    }
+
+    pub fn sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+
+    pub unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        let requested_copy = self.requested_copy.swap(false, Ordering::SeqCst);
+
+        if requested_copy {
+            self.copy(uri);
+        }
+
+        requested_copy
+    }
+
+    unsafe fn copy(&self, uri: &UpdateRecordInfo) {
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
+        let to_transfer = [vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::SHADER_READ)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image.image())
+            .subresource_range(subresource_range)
+            .build()];
+
+        let regions = [vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(0)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(vk::Extent3D {
+                width: self.width,
+                height: self.height,
+                depth: 1,
+            })
+            .build()];
+
+        let to_shader_read = [vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image.image())
+            .subresource_range(subresource_range)
+            .build()];
+
+        debug!(target: logging::MEMORY, "Texture2D upload: {}x{}", self.width, self.height);
+
+        self.device.cmd_pipeline_barrier(
+            uri.command_buffer,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &to_transfer,
+        );
+        self.device.cmd_copy_buffer_to_image(
+            uri.command_buffer,
+            self.stage.get(),
+            self.image.image(),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &regions,
+        );
+        self.device.cmd_pipeline_barrier(
+            uri.command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &to_shader_read,
+        );
+        self.image.set_layout(Layout::ShaderRead);
+    }
+}
+
+impl AsRef<Image> for Texture2D {
+    fn as_ref(&self) -> &Image {
+        &self.image
+    }
+}
+
+impl Texture3D {
+    pub fn new(
+        renderer: &Renderer,
+        width: u32,
+        height: u32,
+        depth: u32,
+    ) -> Result<Self, BufferError> {
+        Self::new_with_device(renderer.rdevice.clone(), width, height, depth)
+    }
+
+    pub fn new_with_data(
+        renderer: &Renderer,
+        width: u32,
+        height: u32,
+        depth: u32,
+        data: &[u8],
+    ) -> Result<Self, BufferError> {
+        let mut texture = Self::new(renderer, width, height, depth)?;
+        texture.write(data)?;
+        Ok(texture)
+    }
+
+    pub fn new_with_device(
+        device: Arc<RenderDevice>,
+        width: u32,
+        height: u32,
+        depth: u32,
+    ) -> Result<Self, BufferError> {
+        let image = ImageBuilder::new_with_device(device.clone())
+            .with_width(width)
+            .with_height(height)
+            .with_depth(depth)
+            .build(
+                ImageUsage::READ | ImageUsage::UPLOAD,
+                vk::Format::R8G8B8A8_UNORM,
+            )?;
+
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .min_lod(0.0)
+            .max_lod(0.0);
+
+        let sampler = unsafe { device.create_sampler(&sampler_info, None) }
+            .or(Err(BufferError::OutOfMemory))?;
+
+        let stage = StageBuffer::new_with_device(
+            device.clone(),
+            width as usize * height as usize * depth as usize * 4,
+            true,
+        )?;
+
+        Ok(Self {
+            device,
+
+            image,
+            sampler,
+            extent: vk::Extent3D {
+                width,
+                height,
+                depth,
+            },
+
+            requested_copy: AtomicBool::new(false),
+            stage,
+        })
+    }
+
+    /// `data` is tightly packed RGBA8 rows and slices, `width * height * depth * 4` bytes.
+    pub fn write(&mut self, data: &[u8]) -> Result<WriteType, BufferError> {
+        if data.len() != self.stage.capacity() {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let result = self.stage.write_slice(0, data);
+        if let Ok(WriteType::Write) = result {
+            self.requested_copy.store(true, Ordering::SeqCst);
+        }
+        result
+    }
+
+    pub fn width(&self) -> u32 {
+        self.extent.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.extent.height
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.extent.depth
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.image.view()
+    }
+
+    pub fn sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+
+    pub unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        let requested_copy = self.requested_copy.swap(false, Ordering::SeqCst);
+
+        if requested_copy {
+            let regions = [vk::BufferImageCopy::builder()
+                .buffer_offset(0)
+                .buffer_row_length(0)
+                .buffer_image_height(0)
+                .image_subresource(
+                    vk::ImageSubresourceLayers::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .mip_level(0)
+                        .base_array_layer(0)
+                        .layer_count(1)
+                        .build(),
+                )
+                .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                .image_extent(self.extent)
+                .build()];
+
+            debug!(
+                target: logging::MEMORY,
+                "Texture3D upload: {}x{}x{}",
+                self.extent.width, self.extent.height, self.extent.depth
+            );
+
+            self.image
+                .transition(uri.command_buffer, Layout::TransferDst);
+            self.device.cmd_copy_buffer_to_image(
+                uri.command_buffer,
+                self.stage.get(),
+                self.image.image(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+            );
+            self.image
+                .transition(uri.command_buffer, Layout::ShaderRead);
+        }
+
+        requested_copy
+    }
+}
+
+impl Drop for Texture2D {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+impl AsRef<Image> for Texture3D {
+    fn as_ref(&self) -> &Image {
+        &self.image
+    }
+}
+
+impl Drop for Texture3D {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use ash::{version::DeviceV1_0, vk};
+use log::{debug, error};
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use super::{
+    find_mem_type,
+    image::{Image, ImageBuilder, ImageUsage, Layout},
+    BufferError,
+};
+use crate::{
+    logging,
+    renderer::{device::RenderDevice, Renderer, UpdateRecordInfo},
+};
+
+/// Device memory of every `TransientImages` of a device, see `Renderer::transient_stats`.
+#[derive(Default)]
+pub struct TransientStats {
+    allocated: AtomicU64,
+    images: AtomicU64,
+}
+
+/// Attachments and storage images only used by some passes of a frame.
+///
+/// Each image is declared with the range of passes using it, in recording
+/// order. Images whose ranges do not overlap share device memory, for ex.
+/// a shadow atlas used by the first passes and the levels of a bloom chain
+/// after them, so the memory is that of the largest set of images used at
+/// the same time instead of the sum of all of them.
+///
+/// Images are returned in declaration order. Contents do not survive the
+/// passes of an image: the first pass using it each frame starts with
+/// `TransientImages::acquire` instead of `transition`.
+pub struct TransientBuilder {
+    device: Arc<RenderDevice>,
+    images: Vec<TransientImage>,
+}
+
+/// Images of a `TransientBuilder` and the memory they share.
+pub struct TransientImages {
+    device: Arc<RenderDevice>,
+
+    images: Vec<Image>,
+    memory: Vec<vk::DeviceMemory>,
+
+    allocated: u64,
+    requested: u64,
+}
+
+struct TransientImage {
+    info: vk::ImageCreateInfo,
+    aspects: vk::ImageAspectFlags,
+    passes: Range<usize>,
+}
+
+impl TransientStats {
+    /// Bytes of device memory allocated for transient images.
+    pub fn allocated(&self) -> u64 {
+        self.allocated.load(Ordering::SeqCst)
+    }
+
+    /// Bytes the images would take without sharing memory, minus `allocated`.
+    pub fn aliased(&self) -> u64 {
+        self.images
+            .load(Ordering::SeqCst)
+            .saturating_sub(self.allocated())
+    }
+
+    fn add(&self, allocated: u64, images: u64) {
+        self.allocated.fetch_add(allocated, Ordering::SeqCst);
+        self.images.fetch_add(images, Ordering::SeqCst);
+    }
+
+    fn sub(&self, allocated: u64, images: u64) {
+        self.allocated.fetch_sub(allocated, Ordering::SeqCst);
+        self.images.fetch_sub(images, Ordering::SeqCst);
+    }
+}
+
+impl TransientBuilder {
+    pub fn new(renderer: &Renderer) -> Self {
+        Self::new_with_device(renderer.rdevice.clone())
+    }
+
+    pub fn new_with_device(device: Arc<RenderDevice>) -> Self {
+        Self {
+            device,
+            images: Vec::new(),
+        }
+    }
+
+    /// 2D image used from the first pass of `passes` to the last one before its end.
+    pub fn with_image<T>(
+        self,
+        width: u32,
+        height: u32,
+        image_usage: ImageUsage,
+        image_format: T,
+        passes: Range<usize>,
+    ) -> Self
+    where
+        T: Into<vk::Format>,
+    {
+        self.with_image_samples(
+            width,
+            height,
+            vk::SampleCountFlags::TYPE_1,
+            image_usage,
+            image_format,
+            passes,
+        )
+    }
+
+    /// Multisampled attachment, see `with_image`.
+    pub fn with_image_samples<T>(
+        mut self,
+        width: u32,
+        height: u32,
+        samples: vk::SampleCountFlags,
+        image_usage: ImageUsage,
+        image_format: T,
+        passes: Range<usize>,
+    ) -> Self
+    where
+        T: Into<vk::Format>,
+    {
+        let format = image_format.into();
+        let (aspects, usage) = ImageBuilder::get(image_usage, format);
+        let extent = vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        };
+
+        self.images.push(TransientImage {
+            info: ImageBuilder::info(format, usage, extent, vk::ImageType::TYPE_2D, 1, samples),
+            aspects,
+            passes,
+        });
+        self
+    }
+
+    pub fn build(self) -> Result<TransientImages, BufferError> {
+        if self.images.iter().any(|image| {
+            image.info.extent.width == 0
+                || image.info.extent.height == 0
+                || image.passes.start >= image.passes.end
+        }) {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let device = self.device.clone();
+        let mut transient = TransientImages {
+            device: device.clone(),
+
+            images: Vec::new(),
+            memory: Vec::new(),
+
+            allocated: 0,
+            requested: 0,
+        };
+
+        // memory allocated so far is freed by `transient` on errors
+        let mut vk_images = Vec::new();
+        let bound = self.create_bound(&device, &mut transient, &mut vk_images);
+        if let Err(err) = bound {
+            for vk_image in vk_images {
+                unsafe { device.destroy_image(vk_image, None) };
+            }
+            return Err(err);
+        }
+
+        // views are created once the memory is bound
+        let mut vk_images = vk_images.into_iter();
+        for (image, vk_image) in self.images.iter().zip(vk_images.by_ref()) {
+            let image =
+                Image::new_with_bound_image(device.clone(), vk_image, &image.info, image.aspects);
+            match image {
+                Ok(image) => transient.images.push(image),
+                Err(err) => {
+                    for vk_image in vk_images {
+                        unsafe { device.destroy_image(vk_image, None) };
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        debug!(
+            target: logging::MEMORY,
+            "TransientImages created: {} images, {} bytes for {} bytes of images",
+            transient.images.len(),
+            transient.allocated,
+            transient.requested
+        );
+
+        Ok(transient)
+    }
+
+    // creates the images into `vk_images` and binds them to shared memory
+    fn create_bound(
+        &self,
+        device: &Arc<RenderDevice>,
+        transient: &mut TransientImages,
+        vk_images: &mut Vec<vk::Image>,
+    ) -> Result<(), BufferError> {
+        for image in self.images.iter() {
+            let vk_image = unsafe { device.create_image(&image.info, None) }.or_else(|err| {
+                error!(
+                    target: logging::MEMORY,
+                    "Image ({:?}) creation failed: {:?}",
+                    image.info,
+                    err
+                );
+                Err(BufferError::OutOfMemory)
+            })?;
+            vk_images.push(vk_image);
+        }
+
+        // images can only share memory of a type all of them support
+        let mut groups = HashMap::<u32, Vec<(usize, vk::MemoryRequirements)>>::new();
+        for (index, vk_image) in vk_images.iter().enumerate() {
+            let req = unsafe { device.get_image_memory_requirements(*vk_image) };
+            let mem_type = find_mem_type(
+                &device.memory_types,
+                &req,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .ok_or(BufferError::NoMemoryType(
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ))?;
+            groups.entry(mem_type).or_default().push((index, req));
+        }
+
+        for (mem_type, group) in groups {
+            let requests = group
+                .iter()
+                .map(|(index, req)| (*req, self.images[*index].passes.clone()))
+                .collect::<Vec<_>>();
+            let (offsets, size) = place(&requests);
+
+            let memory_info = vk::MemoryAllocateInfo::builder()
+                .allocation_size(size)
+                .memory_type_index(mem_type);
+            let memory = unsafe { device.allocate_memory(&memory_info, None) }
+                .or(Err(BufferError::OutOfMemory))?;
+            transient.memory.push(memory);
+            transient.allocated += size;
+            device.transient_stats.add(size, 0);
+
+            for ((index, req), offset) in group.iter().zip(offsets) {
+                unsafe { device.bind_image_memory(vk_images[*index], memory, offset) }
+                    .or(Err(BufferError::OutOfMemory))?;
+                transient.requested += req.size;
+                device.transient_stats.add(0, req.size);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TransientImages {
+    /// Starts the first pass using the image this frame, in `layout`.
+    ///
+    /// Waits for every write to the shared memory and discards the contents,
+    /// later passes use `UpdateRecordInfo::transition` as usual.
+    pub fn acquire(&self, uri: &UpdateRecordInfo, index: usize, layout: Layout) {
+        unsafe { self.images[index].alias(uri.command_buffer, layout) };
+    }
+
+    pub fn image(&self, index: usize) -> &Image {
+        &self.images[index]
+    }
+
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+
+    /// Bytes of device memory allocated for the images.
+    pub fn allocated(&self) -> u64 {
+        self.allocated
+    }
+
+    /// Bytes saved by sharing memory.
+    pub fn aliased(&self) -> u64 {
+        self.requested.saturating_sub(self.allocated)
+    }
+}
+
+impl Drop for TransientImages {
+    fn drop(&mut self) {
+        self.device
+            .transient_stats
+            .sub(self.allocated, self.requested);
+
+        self.images.clear();
+        for memory in self.memory.drain(..) {
+            unsafe { self.device.free_memory(memory, None) };
+        }
+    }
+}
+
+// offsets into one allocation and its size, images used by overlapping
+// passes get disjoint byte ranges
+fn place(requests: &[(vk::MemoryRequirements, Range<usize>)]) -> (Vec<u64>, u64) {
+    // largest first, small images fill the gaps
+    let mut order = (0..requests.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| std::cmp::Reverse(requests[i].0.size));
+
+    let mut offsets = vec![0; requests.len()];
+    let mut placed = Vec::<(Range<u64>, &Range<usize>)>::new();
+    for i in order {
+        let (req, passes) = &requests[i];
+        let align = |offset: u64| (offset + req.alignment - 1) / req.alignment * req.alignment;
+
+        let mut taken = placed
+            .iter()
+            .filter(|(_, other)| other.start < passes.end && passes.start < other.end)
+            .map(|(bytes, _)| bytes.clone())
+            .collect::<Vec<_>>();
+        taken.sort_by_key(|bytes| bytes.start);
+
+        let mut offset = 0;
+        for bytes in taken {
+            if align(offset) + req.size <= bytes.start {
+                break;
+            }
+            offset = offset.max(bytes.end);
+        }
+        let offset = align(offset);
+
+        offsets[i] = offset;
+        placed.push((offset..offset + req.size, passes));
+    }
+
+    let size = placed.iter().map(|(bytes, _)| bytes.end).max().unwrap_or(0);
+    (offsets, size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn req(size: u64, alignment: u64) -> vk::MemoryRequirements {
+        vk::MemoryRequirements {
+            size,
+            alignment,
+            memory_type_bits: !0,
+        }
+    }
+
+    fn overlaps(a: Range<u64>, b: Range<u64>) -> bool {
+        a.start < b.end && b.start < a.end
+    }
+
+    #[test]
+    fn disjoint_passes_alias() {
+        let (offsets, size) = place(&[(req(256, 16), 0..2), (req(512, 16), 2..4)]);
+        assert_eq!(offsets, vec![0, 0]);
+        assert_eq!(size, 512);
+    }
+
+    #[test]
+    fn overlapping_passes_do_not_alias() {
+        let requests = [(req(256, 16), 0..3), (req(512, 16), 2..4)];
+        let (offsets, size) = place(&requests);
+        assert!(!overlaps(
+            offsets[0]..offsets[0] + 256,
+            offsets[1]..offsets[1] + 512
+        ));
+        assert_eq!(size, 768);
+    }
+
+    #[test]
+    fn offsets_are_aligned() {
+        let requests = [
+            (req(100, 1), 0..2),
+            (req(60, 64), 1..3),
+            (req(30, 256), 0..3),
+        ];
+        let (offsets, size) = place(&requests);
+        for ((req, _), offset) in requests.iter().zip(offsets.iter()) {
+            assert_eq!(offset % req.alignment, 0);
+            assert!(offset + req.size <= size);
+        }
+        for a in 0..requests.len() {
+            for b in a + 1..requests.len() {
+                assert!(!overlaps(
+                    offsets[a]..offsets[a] + requests[a].0.size,
+                    offsets[b]..offsets[b] + requests[b].0.size
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(place(&[]), (vec![], 0));
+    }
+}
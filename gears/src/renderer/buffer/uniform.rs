@@ -1,46 +1,145 @@
 use ash::vk;
-use std::sync::Arc;
+use std::{
+    collections::hash_map::DefaultHasher, hash::Hasher, marker::PhantomData, mem, slice, sync::Arc,
+};
 
-use super::{stage::StageBuffer, Buffer, BufferError, WriteType};
+use super::{arena::ArenaAlloc, Buffer, BufferError, WriteType};
 use crate::renderer::{device::RenderDevice, Renderer, UpdateRecordInfo};
 
+/// Regions of the device's shared uniform arena, one per frame.
+///
+/// Writes are copies into persistently mapped memory, flushed once per
+/// frame with every other UBO. The GPU may still read the region of a frame
+/// in flight, so each swapchain image writes and binds its own region.
+/// Bind it with `descriptor`, which has the buffer, offset and range of a frame.
 pub struct UniformBuffer<T> {
-    stage: StageBuffer<T>, // the uniform buffer itself
+    device: Arc<RenderDevice>,
+    // one per frame
+    allocs: Vec<ArenaAlloc>,
+    last_hashes: Vec<u64>,
+
+    // not bytes
+    capacity: usize,
+
+    _p: PhantomData<T>,
 }
 
 impl<T> UniformBuffer<T> {
+    /// One region per swapchain image.
     pub fn new(renderer: &Renderer) -> Result<Self, BufferError> {
-        Self::new_with_device(renderer.rdevice.clone())
+        Self::new_with_device(renderer.rdevice.clone(), renderer.image_count())
     }
 
+    /// Every frame starts with `data`.
     pub fn new_with_data(renderer: &Renderer, data: &T) -> Result<Self, BufferError> {
         let mut buffer = Self::new(renderer)?;
-        buffer.write(data)?;
+        for frame in 0..buffer.frames() {
+            buffer.write(frame, data)?;
+        }
         Ok(buffer)
     }
 
-    pub fn new_with_device(device: Arc<RenderDevice>) -> Result<Self, BufferError> {
+    /// `frames` regions, a single region is only safe to write if the
+    /// caller keeps one buffer per frame, like `Pipeline` does.
+    pub fn new_with_device(device: Arc<RenderDevice>, frames: usize) -> Result<Self, BufferError> {
+        Self::new_array_with_device(device, 1, frames)
+    }
+
+    /// Room for `len` elements per frame, written with `write_slice`.
+    pub fn new_array_with_device(
+        device: Arc<RenderDevice>,
+        len: usize,
+        frames: usize,
+    ) -> Result<Self, BufferError> {
+        if len == 0 || frames == 0 {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let mut allocs = Vec::with_capacity(frames);
+        for _ in 0..frames {
+            let alloc = device
+                .uniform_arena
+                .alloc(&device, (len * mem::size_of::<T>()) as u64);
+            match alloc {
+                Ok(alloc) => allocs.push(alloc),
+                Err(err) => {
+                    for alloc in allocs.iter() {
+                        device.uniform_arena.free(alloc);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
         Ok(Self {
-            stage: StageBuffer::new_with_usage(
-                device.clone(),
-                vk::BufferUsageFlags::UNIFORM_BUFFER,
-                1,
-                true,
-            )?,
+            device,
+            allocs,
+            last_hashes: vec![0; frames],
+
+            capacity: len,
+
+            _p: PhantomData::default(),
         })
     }
 
-    pub fn write(&mut self, data: &T) -> Result<WriteType, BufferError> {
-        self.stage.write_single(0, data)
+    pub fn frames(&self) -> usize {
+        self.allocs.len()
+    }
+
+    /// Writes the region of `frame`, the swapchain image index.
+    ///
+    /// A buffer with a single frame always writes the same region.
+    pub fn write(&mut self, frame: usize, data: &T) -> Result<WriteType, BufferError> {
+        self.write_slice(frame, slice::from_ref(data))
+    }
+
+    pub fn write_slice(&mut self, frame: usize, data: &[T]) -> Result<WriteType, BufferError> {
+        if data.len() > self.capacity {
+            return Err(BufferError::TriedToOverflow);
+        }
+        let frame = frame % self.allocs.len();
+
+        let bytes =
+            unsafe { slice::from_raw_parts(data.as_ptr() as *const u8, mem::size_of_val(data)) };
+
+        let mut hasher = DefaultHasher::new();
+        hasher.write(bytes);
+        let new_hash = hasher.finish();
+        if self.last_hashes[frame] == new_hash {
+            return Ok(WriteType::NoWrite);
+        }
+        self.last_hashes[frame] = new_hash;
+
+        unsafe { self.device.uniform_arena.write(&self.allocs[frame], bytes) };
+        Ok(WriteType::Write)
+    }
+
+    /// The region of `get` used by `frame`.
+    pub fn descriptor(&self, frame: usize) -> vk::DescriptorBufferInfo {
+        let alloc = &self.allocs[frame % self.allocs.len()];
+        vk::DescriptorBufferInfo {
+            buffer: alloc.buffer,
+            offset: alloc.range.start,
+            range: alloc.range.end - alloc.range.start,
+        }
     }
 }
 
 impl<T> Buffer for UniformBuffer<T> {
-    unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
-        self.stage.update(uri)
+    unsafe fn update(&self, _: &UpdateRecordInfo) -> bool {
+        false
     }
 
+    // frames can be in different arena blocks, `descriptor` has the buffer of each
     fn get(&self) -> vk::Buffer {
-        self.stage.get()
+        self.allocs[0].buffer
+    }
+}
+
+impl<T> Drop for UniformBuffer<T> {
+    fn drop(&mut self) {
+        for alloc in self.allocs.iter() {
+            self.device.uniform_arena.free(alloc);
+        }
     }
 }
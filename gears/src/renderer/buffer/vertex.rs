@@ -9,7 +9,10 @@ use std::{
 
 use log::debug;
 
-use crate::renderer::{device::RenderDevice, RenderRecordInfo, Renderer, UpdateRecordInfo};
+use crate::{
+    logging,
+    renderer::{device::RenderDevice, RenderRecordInfo, Renderer, UpdateRecordInfo},
+};
 
 use super::{create_buffer, stage::StageBuffer, Buffer, BufferError, WriteType};
 
@@ -78,7 +81,7 @@ impl<T> VertexBuffer<T> {
         let offsets = [0];
 
         if rri.debug_calls {
-            debug!("cmd_bind_vertex_buffers");
+            debug!(target: logging::COMMANDS, "cmd_bind_vertex_buffers");
         }
 
         self.device
@@ -91,7 +94,7 @@ impl<T> VertexBuffer<T> {
         rri.triangles.fetch_add(self.len() / 3, Ordering::SeqCst);
 
         if rri.debug_calls {
-            debug!("cmd_draw");
+            debug!(target: logging::COMMANDS, "cmd_draw");
         }
 
         self.device
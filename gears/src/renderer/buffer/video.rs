@@ -0,0 +1,420 @@
+use ash::{version::DeviceV1_0, vk};
+use log::{debug, error};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use super::{
+    image::{Image, ImageBuilder, ImageFormat, ImageUsage, Layout},
+    stage::StageBuffer,
+    Buffer, BufferError,
+};
+use crate::{
+    logging,
+    renderer::{device::RenderDevice, Renderer, UpdateRecordInfo},
+};
+
+/// Decodes a video for `VideoTexture`, on its own thread.
+///
+/// Wrap any decoding library, for ex. dav1d or ffmpeg bindings, in it.
+pub trait VideoDecoder: Send + 'static {
+    /// Width and height of every frame in pixels.
+    fn size(&self) -> (u32, u32);
+
+    /// Decodes the next frame into `rgba` as tightly packed RGBA8 rows and
+    /// returns when it is shown from the start of the video, `None` at the end.
+    fn decode(&mut self, rgba: &mut [u8]) -> Option<Duration>;
+
+    /// Seeks back to the first frame for looping, false if it can not.
+    fn rewind(&mut self) -> bool {
+        false
+    }
+}
+
+/// A decoded video streamed into a sampled RGBA8 (sRGB) image, for
+/// cutscenes and animated billboards.
+///
+/// ```ignore
+/// let mut video = VideoTexture::new(&renderer, decoder)?.with_looping(true);
+///
+/// // in RendererRecord::frame
+/// video.update(&uri, frame.time().delta);
+/// ```
+///
+/// Frames are double buffered: the decoder thread fills one frame while
+/// the other waits for its presentation time. Each swapchain image uploads
+/// from its own staging buffer, so a frame is never overwritten while an
+/// earlier upload of it may still be read, and the copy waits for the
+/// frames sampling the previous video frame with a barrier. `view` stays
+/// the same for the whole video, recordings need no rerecord to show new
+/// frames.
+pub struct VideoTexture {
+    device: Arc<RenderDevice>,
+
+    image: Image,
+    sampler: vk::Sampler,
+    width: u32,
+    height: u32,
+
+    // per swapchain image, created on first use
+    stages: Vec<Option<StageBuffer<u8>>>,
+
+    decoded: Receiver<Decoded>,
+    // None while dropping, which stops the decoder thread
+    free: Option<Sender<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+
+    // the next frame to show and when
+    next: Option<(Duration, Vec<u8>)>,
+    time: Duration,
+    playing: bool,
+    looping: Arc<AtomicBool>,
+    ended: bool,
+}
+
+enum Decoded {
+    Frame(Duration, Vec<u8>),
+    // the next frames start from the beginning
+    Rewound,
+    End,
+}
+
+impl VideoTexture {
+    /// Starts decoding right away, the texture is black until the first frame.
+    pub fn new<D: VideoDecoder>(renderer: &Renderer, decoder: D) -> Result<Self, BufferError> {
+        Self::new_with_device(renderer.rdevice.clone(), decoder)
+    }
+
+    pub fn new_with_device<D: VideoDecoder>(
+        device: Arc<RenderDevice>,
+        decoder: D,
+    ) -> Result<Self, BufferError> {
+        let (width, height) = decoder.size();
+        if width == 0 || height == 0 {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let image = ImageBuilder::new_with_device(device.clone())
+            .with_width(width)
+            .with_height(height)
+            .build(
+                ImageUsage::READ | ImageUsage::UPLOAD,
+                ImageFormat::<f32>::RGBA,
+            )?;
+
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .min_lod(0.0)
+            .max_lod(0.0);
+
+        let sampler = unsafe { device.create_sampler(&sampler_info, None) }
+            .or(Err(BufferError::OutOfMemory))?;
+
+        let frame_size = width as usize * height as usize * 4;
+        let looping = Arc::new(AtomicBool::new(false));
+        let thread_looping = looping.clone();
+        let (decoded_tx, decoded) = mpsc::channel();
+        let (free, free_rx) = mpsc::channel();
+        // the second of the two frame buffers is the black first frame
+        free.send(vec![0; frame_size]).unwrap();
+
+        let thread = thread::Builder::new()
+            .name("video decoder".into())
+            .spawn(move || decode_thread(decoder, thread_looping, free_rx, decoded_tx))
+            .or(Err(BufferError::OutOfMemory))?;
+
+        Ok(Self {
+            device,
+
+            image,
+            sampler,
+            width,
+            height,
+
+            stages: Vec::new(),
+
+            decoded,
+            free: Some(free),
+            thread: Some(thread),
+
+            // black until the first frame, the image starts undefined
+            next: Some((Duration::from_secs(0), vec![0; frame_size])),
+            time: Duration::from_secs(0),
+            playing: true,
+            looping,
+            ended: false,
+        })
+    }
+
+    /// Starts over at the end, if the decoder can `rewind`.
+    pub fn with_looping(self, looping: bool) -> Self {
+        self.set_looping(looping);
+        self
+    }
+
+    /// Has no effect once the decoder reached the end.
+    pub fn set_looping(&self, looping: bool) {
+        self.looping.store(looping, Ordering::SeqCst);
+    }
+
+    pub fn looping(&self) -> bool {
+        self.looping.load(Ordering::SeqCst)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.image.view()
+    }
+
+    pub fn sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+
+    /// Playback position from the start of the video.
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+
+    pub fn set_playing(&mut self, playing: bool) {
+        self.playing = playing;
+    }
+
+    pub fn playing(&self) -> bool {
+        self.playing
+    }
+
+    /// True after the last frame was shown, never while looping.
+    pub fn ended(&self) -> bool {
+        self.ended && self.next.is_none()
+    }
+
+    /// Advances playback by `delta` and uploads the newest frame that is
+    /// due, returns true if an upload was recorded.
+    ///
+    /// Has to run every frame, before the draws sampling the texture.
+    pub unsafe fn update(&mut self, uri: &UpdateRecordInfo, delta: Duration) -> bool {
+        if self.playing {
+            self.time += delta;
+        }
+
+        // skip the frames that are already late
+        let mut due = None;
+        loop {
+            if self.next.is_none() {
+                self.next = match self.decoded.try_recv() {
+                    Ok(Decoded::Frame(time, rgba)) => Some((time, rgba)),
+                    Ok(Decoded::Rewound) => {
+                        self.time = Duration::from_secs(0);
+                        continue;
+                    }
+                    Ok(Decoded::End) => {
+                        self.ended = true;
+                        None
+                    }
+                    Err(_) => None,
+                };
+            }
+
+            match self.next.take() {
+                Some((time, rgba)) if time <= self.time => {
+                    if let Some((_, late)) = due.replace((time, rgba)) {
+                        self.recycle(late);
+                    }
+                }
+                next => {
+                    self.next = next;
+                    break;
+                }
+            }
+        }
+
+        let rgba = match due {
+            Some((_, rgba)) => rgba,
+            None => return false,
+        };
+        let result = self.upload(uri, &rgba);
+        self.recycle(rgba);
+
+        match result {
+            Ok(()) => true,
+            Err(err) => {
+                error!("VideoTexture upload failed: {:?}", err);
+                false
+            }
+        }
+    }
+
+    fn recycle(&self, rgba: Vec<u8>) {
+        if let Some(free) = self.free.as_ref() {
+            // fails only after the decoder thread stopped
+            let _ = free.send(rgba);
+        }
+    }
+
+    unsafe fn upload(&mut self, uri: &UpdateRecordInfo, rgba: &[u8]) -> Result<(), BufferError> {
+        let index = uri.image_index;
+        if self.stages.len() <= index {
+            self.stages.resize_with(index + 1, || None);
+        }
+        if self.stages[index].is_none() {
+            self.stages[index] = Some(StageBuffer::new_with_device(
+                self.device.clone(),
+                rgba.len(),
+                false,
+            )?);
+        }
+        let stage = self.stages[index].as_mut().unwrap();
+        stage.write_slice(0, rgba)?;
+
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
+        // waits for the frames still sampling the previous video frame
+        let to_transfer = [vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::SHADER_READ)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image.image())
+            .subresource_range(subresource_range)
+            .build()];
+
+        let regions = [vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(0)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(vk::Extent3D {
+                width: self.width,
+                height: self.height,
+                depth: 1,
+            })
+            .build()];
+
+        let to_shader_read = [vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image.image())
+            .subresource_range(subresource_range)
+            .build()];
+
+        debug!(target: logging::MEMORY, "VideoTexture upload: {}x{}", self.width, self.height);
+
+        self.device.cmd_pipeline_barrier(
+            uri.command_buffer,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &to_transfer,
+        );
+        self.device.cmd_copy_buffer_to_image(
+            uri.command_buffer,
+            stage.get(),
+            self.image.image(),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &regions,
+        );
+        self.device.cmd_pipeline_barrier(
+            uri.command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &to_shader_read,
+        );
+        self.image.set_layout(Layout::ShaderRead);
+        Ok(())
+    }
+}
+
+impl AsRef<Image> for VideoTexture {
+    fn as_ref(&self) -> &Image {
+        &self.image
+    }
+}
+
+impl Drop for VideoTexture {
+    fn drop(&mut self) {
+        // the decoder thread stops waiting for a free frame
+        self.free = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+
+        unsafe {
+            self.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+fn decode_thread<D: VideoDecoder>(
+    mut decoder: D,
+    looping: Arc<AtomicBool>,
+    free: Receiver<Vec<u8>>,
+    decoded: Sender<Decoded>,
+) {
+    while let Ok(mut rgba) = free.recv() {
+        let frame = match decoder.decode(&mut rgba) {
+            Some(time) => Decoded::Frame(time, rgba),
+            None if looping.load(Ordering::SeqCst) && decoder.rewind() => {
+                if decoded.send(Decoded::Rewound).is_err() {
+                    return;
+                }
+                // decode into the same buffer again
+                match decoder.decode(&mut rgba) {
+                    Some(time) => Decoded::Frame(time, rgba),
+                    None => Decoded::End,
+                }
+            }
+            None => Decoded::End,
+        };
+
+        let end = matches!(frame, Decoded::End);
+        if decoded.send(frame).is_err() || end {
+            return;
+        }
+    }
+}
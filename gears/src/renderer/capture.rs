@@ -0,0 +1,114 @@
+use log::debug;
+use std::{
+    ffi::CString,
+    os::raw::{c_char, c_void},
+    ptr,
+};
+
+// eRENDERDOC_API_Version_1_2_0, the first with SetCaptureFileComments
+const API_VERSION: u32 = 10200;
+
+#[cfg(unix)]
+type Library = libloading::os::unix::Library;
+#[cfg(windows)]
+type Library = libloading::os::windows::Library;
+#[cfg(not(any(unix, windows)))]
+type Library = ();
+
+type GetApi = unsafe extern "C" fn(version: u32, api: *mut *mut c_void) -> i32;
+
+// RENDERDOC_API_1_2_0, up to the last entry used
+#[repr(C)]
+struct Api {
+    // GetAPIVersion to GetCapture
+    _unused_0: [usize; 15],
+    _trigger_capture: usize,
+    _is_target_control_connected: usize,
+    _launch_replay_ui: usize,
+    _set_active_window: usize,
+    start_frame_capture: unsafe extern "C" fn(device: *mut c_void, window: *mut c_void),
+    _is_frame_capturing: usize,
+    end_frame_capture: unsafe extern "C" fn(device: *mut c_void, window: *mut c_void) -> u32,
+    _trigger_multi_frame_capture: usize,
+    set_capture_file_comments:
+        unsafe extern "C" fn(file_path: *const c_char, comments: *const c_char),
+}
+
+/// The RenderDoc in-application API, if the application was launched from RenderDoc.
+pub(crate) struct RenderDoc {
+    api: *const Api,
+    _library: Library,
+}
+
+// the API is thread safe
+unsafe impl Send for RenderDoc {}
+unsafe impl Sync for RenderDoc {}
+
+impl RenderDoc {
+    /// Only finds an already injected RenderDoc, loading it this late would miss the instance.
+    #[cfg(any(unix, windows))]
+    pub fn load() -> Option<Self> {
+        let library = Self::library()?;
+        let get_api = unsafe { library.get::<GetApi>(b"RENDERDOC_GetAPI\0") }.ok()?;
+
+        let mut api = ptr::null_mut();
+        if unsafe { get_api(API_VERSION, &mut api) } != 1 || api.is_null() {
+            debug!("RenderDoc does not support API version {}", API_VERSION);
+            return None;
+        }
+        debug!("RenderDoc in-application API loaded");
+
+        Some(Self {
+            api: api as *const Api,
+            _library: library,
+        })
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub fn load() -> Option<Self> {
+        None
+    }
+
+    #[cfg(target_os = "linux")]
+    fn library() -> Option<Library> {
+        // RTLD_NOLOAD, libloading does not export it
+        const RTLD_NOLOAD: i32 = 0x4;
+        unsafe {
+            libloading::os::unix::Library::open(
+                Some("librenderdoc.so"),
+                libloading::os::unix::RTLD_NOW | RTLD_NOLOAD,
+            )
+        }
+        .ok()
+    }
+
+    #[cfg(windows)]
+    fn library() -> Option<Library> {
+        libloading::os::windows::Library::open_already_loaded("renderdoc.dll").ok()
+    }
+
+    #[cfg(all(unix, not(target_os = "linux")))]
+    fn library() -> Option<Library> {
+        None
+    }
+
+    /// Captures every submission until `end_capture`.
+    pub fn start_capture(&self) {
+        // null device and window match the only ones
+        unsafe { ((*self.api).start_frame_capture)(ptr::null_mut(), ptr::null_mut()) }
+    }
+
+    /// Ends the capture and stores `comments` with it, false if it failed.
+    pub fn end_capture(&self, comments: &str) -> bool {
+        let api = unsafe { &*self.api };
+        if unsafe { (api.end_frame_capture)(ptr::null_mut(), ptr::null_mut()) } == 0 {
+            return false;
+        }
+
+        // null path is the last capture
+        if let Ok(comments) = CString::new(comments) {
+            unsafe { (api.set_capture_file_comments)(ptr::null(), comments.as_ptr()) }
+        }
+        true
+    }
+}
@@ -0,0 +1,261 @@
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{Matrix4, Vector2, Vector4};
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+use crate::deferred::PointLight;
+
+use super::{
+    buffer::{storage::StorageBuffer, Buffer, BufferError, WriteType},
+    device::RenderDevice,
+    pipeline::{GraphicsPipelineBuilder, Pipeline, PipelineBuilder},
+    Renderer, UpdateRecordInfo,
+};
+
+mod shader {
+    gears_pipeline::pipeline! {
+        comp: {
+            path: "res/cluster.comp.glsl"
+        }
+    }
+}
+
+/// Source of the lighting include reading `LightClusters`, built into
+/// `pipeline!` as ```#include "gears://clusters.glsl"```.
+pub const CLUSTERS_GLSL: &str = include_str!("../../../gears-pipeline/res/clusters.glsl");
+
+const LOCAL_SIZE: u32 = 64;
+// cluster_params and cluster_grid in front of the counts
+const HEADER_LEN: usize = 8;
+
+// must match the push constant block in cluster.comp.glsl
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct ClusterPush {
+    view: Matrix4<f32>,
+    projection: Vector4<f32>,
+    grid: [u32; 4],
+    screen: Vector2<f32>,
+    light_count: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClusterConfig {
+    /// Clusters along the screen width, height and view depth.
+    pub grid: [u32; 3],
+    /// Lights past this in one cluster are ignored.
+    pub max_lights_per_cluster: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ClusterView {
+    view: Matrix4<f32>,
+    projection: Vector4<f32>,
+    screen: Vector2<f32>,
+}
+
+/// Clustered forward lighting, for hundreds of point lights.
+///
+/// Every `update` a compute pass splits the view frustum into froxels,
+/// screen tiles sliced exponentially along the view depth, and lists the
+/// lights whose radius reaches each of them. Forward shaders then only
+/// loop over the lights of their own cluster with `clustered_point_lights`
+/// from `CLUSTERS_GLSL`:
+///
+/// ```glsl
+/// #include "gears://clusters.glsl"
+///
+/// color += clustered_point_lights(gl_FragCoord.xy, -view_position.z, position, normal, view, albedo);
+/// ```
+///
+/// Pipelines get the three storage buffers from `bind_to`, which binds them
+/// from the next binding in call order, `CLUSTER_BINDING` in the shader.
+/// The lights use the same `PointLight` as `DeferredRenderer`.
+pub struct LightClusters {
+    device: Arc<RenderDevice>,
+
+    pipeline: Pipeline,
+    lights: StorageBuffer<PointLight>,
+    // header followed by the light count of every cluster
+    clusters: StorageBuffer<u32>,
+    indices: StorageBuffer<u32>,
+
+    config: ClusterConfig,
+    view: Mutex<ClusterView>,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            grid: [16, 9, 24],
+            max_lights_per_cluster: 64,
+        }
+    }
+}
+
+impl ClusterConfig {
+    pub fn cluster_count(&self) -> usize {
+        self.grid.iter().map(|&count| count as usize).product()
+    }
+}
+
+impl LightClusters {
+    pub fn new(
+        renderer: &Renderer,
+        max_lights: usize,
+        config: &ClusterConfig,
+    ) -> Result<Self, BufferError> {
+        Self::new_with_device(renderer.rdevice.clone(), max_lights, config)
+    }
+
+    pub fn new_with_device(
+        device: Arc<RenderDevice>,
+        max_lights: usize,
+        config: &ClusterConfig,
+    ) -> Result<Self, BufferError> {
+        let cluster_count = config.cluster_count();
+        if cluster_count == 0 || config.max_lights_per_cluster == 0 || max_lights == 0 {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let lights = StorageBuffer::new_with_device(
+            device.clone(),
+            max_lights,
+            vk::BufferUsageFlags::empty(),
+        )?;
+        let clusters = StorageBuffer::new_with_device(
+            device.clone(),
+            HEADER_LEN + cluster_count,
+            vk::BufferUsageFlags::empty(),
+        )?;
+        let indices = StorageBuffer::new_with_device(
+            device.clone(),
+            cluster_count * config.max_lights_per_cluster as usize,
+            vk::BufferUsageFlags::empty(),
+        )?;
+
+        let pipeline = PipelineBuilder::new_with_device(device.clone(), vk::RenderPass::null(), 1)
+            .with_compute_module(shader::COMP_SPIRV_WORDS)
+            .with_push_constants::<ClusterPush>()
+            .with_storage_buffer(&lights)
+            .with_storage_buffer(&clusters)
+            .with_storage_buffer(&indices)
+            .build()?;
+
+        Ok(Self {
+            device,
+
+            pipeline,
+            lights,
+            clusters,
+            indices,
+
+            config: *config,
+            view: Mutex::new(ClusterView {
+                view: Matrix4::from_scale(1.0),
+                projection: Vector4::new(1.0, 1.0, 0.1, 100.0),
+                screen: Vector2::new(1.0, 1.0),
+            }),
+        })
+    }
+
+    /// The light count is the highest written index + 1.
+    pub fn write_lights(
+        &mut self,
+        offset: usize,
+        lights: &[PointLight],
+    ) -> Result<WriteType, BufferError> {
+        self.lights.write(offset, lights)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.lights.capacity()
+    }
+
+    pub fn config(&self) -> &ClusterConfig {
+        &self.config
+    }
+
+    /// The camera of the next `update`: its view matrix, its perspective
+    /// projection, the `near` and `far` planes of that projection and the
+    /// size of the target in pixels.
+    pub fn set_view(
+        &self,
+        view: &Matrix4<f32>,
+        projection: &Matrix4<f32>,
+        near: f32,
+        far: f32,
+        extent: (u32, u32),
+    ) {
+        *self.view.lock() = ClusterView {
+            view: *view,
+            projection: Vector4::new(projection.x.x, projection.y.y, near, far),
+            screen: Vector2::new(extent.0 as f32, extent.1 as f32),
+        };
+    }
+
+    /// Binds the lights, clusters and light indices as the next three
+    /// storage buffers of `builder`.
+    pub fn bind_to<'a>(&self, builder: GraphicsPipelineBuilder<'a>) -> GraphicsPipelineBuilder<'a> {
+        builder
+            .with_storage_buffer(&self.lights)
+            .with_storage_buffer(&self.clusters)
+            .with_storage_buffer(&self.indices)
+    }
+
+    /// Uploads lights and records the clustering dispatch, always returns true.
+    pub unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        self.lights.update(uri);
+
+        let view = *self.view.lock();
+        let [x, y, z] = self.config.grid;
+        let push = ClusterPush {
+            view: view.view,
+            projection: view.projection,
+            grid: [x, y, z, self.config.max_lights_per_cluster],
+            screen: view.screen,
+            light_count: self.len() as u32,
+        };
+
+        // light upload and the previous frames shading reads before the dispatch
+        let before = [vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+            .build()];
+        self.device.cmd_pipeline_barrier(
+            uri.command_buffer,
+            vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &before,
+            &[],
+            &[],
+        );
+
+        let cluster_count = self.config.cluster_count() as u32;
+        self.pipeline.bind_compute(uri);
+        self.pipeline.push_constants_compute(uri, &push);
+        self.pipeline
+            .dispatch(uri, (cluster_count + LOCAL_SIZE - 1) / LOCAL_SIZE, 1, 1);
+
+        let after = [vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .build()];
+        self.device.cmd_pipeline_barrier(
+            uri.command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &after,
+            &[],
+            &[],
+        );
+
+        true
+    }
+}
@@ -0,0 +1,228 @@
+use ash::{version::DeviceV1_0, vk};
+use cgmath::{InnerSpace, Matrix, Matrix4, Vector4};
+use log::debug;
+use parking_lot::Mutex;
+use std::{mem, sync::Arc};
+
+use crate::logging;
+
+use super::{
+    buffer::{
+        index::{IndexBuffer, UInt},
+        storage::StorageBuffer,
+        vertex::VertexBuffer,
+        Buffer, BufferError, WriteType,
+    },
+    device::RenderDevice,
+    pipeline::{Pipeline, PipelineBuilder},
+    RenderRecordInfo, Renderer, UpdateRecordInfo,
+};
+
+mod shader {
+    gears_pipeline::pipeline! {
+        comp: {
+            path: "res/cull.comp.glsl"
+        }
+    }
+}
+
+const LOCAL_SIZE: u32 = 64;
+
+/// Bounding sphere and indexed draw parameters of one object.
+///
+/// `first_instance` is passed through and can index per object data
+/// with `gl_InstanceIndex`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CullObject {
+    /// World space center in xyz and radius in w.
+    pub sphere: Vector4<f32>,
+    pub index_count: u32,
+    pub first_index: u32,
+    pub vertex_offset: i32,
+    pub first_instance: u32,
+}
+
+// must match the push constant block in cull.comp.glsl
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Frustum {
+    planes: [Vector4<f32>; 6],
+    object_count: u32,
+}
+
+/// Frustum culling of many objects on the GPU.
+///
+/// `update` tests every `CullObject` against the frustum given to
+/// `set_view_projection` in a compute shader and writes one indirect draw
+/// per object, culled objects get an instance count of 0. `draw` then
+/// issues all of them with one indirect draw call.
+pub struct CullPass {
+    device: Arc<RenderDevice>,
+
+    pipeline: Pipeline,
+    objects: StorageBuffer<CullObject>,
+    draws: StorageBuffer<vk::DrawIndexedIndirectCommand>,
+
+    planes: Mutex<[Vector4<f32>; 6]>,
+}
+
+impl CullPass {
+    pub fn new(renderer: &Renderer, capacity: usize) -> Result<Self, BufferError> {
+        Self::new_with_device(renderer.rdevice.clone(), capacity)
+    }
+
+    pub fn new_with_device(
+        device: Arc<RenderDevice>,
+        capacity: usize,
+    ) -> Result<Self, BufferError> {
+        let objects = StorageBuffer::new_with_device(
+            device.clone(),
+            capacity,
+            vk::BufferUsageFlags::empty(),
+        )?;
+        let draws = StorageBuffer::new_with_device(
+            device.clone(),
+            capacity,
+            vk::BufferUsageFlags::INDIRECT_BUFFER,
+        )?;
+
+        let pipeline = PipelineBuilder::new_with_device(device.clone(), vk::RenderPass::null(), 1)
+            .with_compute_module(shader::COMP_SPIRV_WORDS)
+            .with_push_constants::<Frustum>()
+            .with_storage_buffer(&objects)
+            .with_storage_buffer(&draws)
+            .build()?;
+
+        Ok(Self {
+            device,
+
+            pipeline,
+            objects,
+            draws,
+
+            planes: Mutex::new([Vector4::new(0.0, 0.0, 0.0, 1.0); 6]),
+        })
+    }
+
+    pub fn write(
+        &mut self,
+        offset: usize,
+        objects: &[CullObject],
+    ) -> Result<WriteType, BufferError> {
+        self.objects.write(offset, objects)
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.objects.capacity()
+    }
+
+    /// Extracts the frustum planes from a Vulkan (0..1 depth) projection * view matrix.
+    pub fn set_view_projection(&self, view_projection: &Matrix4<f32>) {
+        let m = view_projection;
+        let mut planes = [
+            m.row(3) + m.row(0),
+            m.row(3) - m.row(0),
+            m.row(3) + m.row(1),
+            m.row(3) - m.row(1),
+            m.row(2),
+            m.row(3) - m.row(2),
+        ];
+
+        for plane in planes.iter_mut() {
+            *plane /= plane.truncate().magnitude();
+        }
+
+        *self.planes.lock() = planes;
+    }
+
+    /// Uploads objects and records the culling dispatch, always returns true.
+    pub unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        self.objects.update(uri);
+
+        let frustum = Frustum {
+            planes: *self.planes.lock(),
+            object_count: self.len() as u32,
+        };
+
+        // object upload and the previous frames indirect reads before the dispatch
+        let before = [vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .build()];
+        self.device.cmd_pipeline_barrier(
+            uri.command_buffer,
+            vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::DRAW_INDIRECT,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &before,
+            &[],
+            &[],
+        );
+
+        self.pipeline.bind_compute(uri);
+        self.pipeline.push_constants_compute(uri, &frustum);
+        self.pipeline.dispatch(
+            uri,
+            (frustum.object_count + LOCAL_SIZE - 1) / LOCAL_SIZE,
+            1,
+            1,
+        );
+
+        let after = [vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::INDIRECT_COMMAND_READ)
+            .build()];
+        self.device.cmd_pipeline_barrier(
+            uri.command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::DRAW_INDIRECT,
+            vk::DependencyFlags::empty(),
+            &after,
+            &[],
+            &[],
+        );
+
+        true
+    }
+
+    /// Draws the surviving objects, the graphics pipeline must be bound first.
+    pub unsafe fn draw<T, I: UInt>(
+        &self,
+        rri: &RenderRecordInfo,
+        vertices: &VertexBuffer<T>,
+        indices: &IndexBuffer<I>,
+    ) {
+        indices.bind(rri);
+        vertices.bind(rri);
+
+        if rri.debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_draw_indexed_indirect");
+        }
+
+        let stride = mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+        if self.device.multi_draw_indirect {
+            self.device.cmd_draw_indexed_indirect(
+                rri.command_buffer,
+                self.draws.get(),
+                0,
+                self.len() as u32,
+                stride,
+            );
+        } else {
+            for i in 0..self.len() as u64 {
+                self.device.cmd_draw_indexed_indirect(
+                    rri.command_buffer,
+                    self.draws.get(),
+                    i * stride as u64,
+                    1,
+                    stride,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,739 @@
+use ash::{version::DeviceV1_0, vk};
+use log::{debug, error};
+use parking_lot::Mutex;
+use std::{collections::HashMap, fmt, hash::Hash};
+
+use super::{buffer::BufferError, device::RenderDevice};
+use crate::logging;
+
+/// Sizing of the pools `DescriptorPools` creates, see `RendererBuilder::with_descriptor_pools`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DescriptorPoolConfig {
+    /// Sets in the first pool.
+    pub sets: u32,
+    /// Every new pool holds this many times the sets of the previous one.
+    pub growth: f32,
+    /// Upper limit of sets in one pool.
+    pub max_sets: u32,
+    /// Average descriptors of each type per set, pools hold this many times
+    /// their sets. Types missing here only get room for the set that
+    /// created the pool.
+    pub sizes: Vec<(vk::DescriptorType, f32)>,
+}
+
+/// One descriptor set allocated from `DescriptorPools`.
+#[derive(Debug)]
+pub struct DescriptorAlloc {
+    pub set: vk::DescriptorSet,
+    pool: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DescriptorTypeStats {
+    pub ty: vk::DescriptorType,
+    pub allocated: u32,
+    pub capacity: u32,
+}
+
+/// Usage of `DescriptorPools`, from `Renderer::descriptor_stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DescriptorStats {
+    pub pools: usize,
+    /// Sets not freed yet.
+    pub live_sets: u32,
+    /// Sets taken from the pools, freed sets included until their pool is empty.
+    pub allocated_sets: u32,
+    pub set_capacity: u32,
+    /// Summed over every pool.
+    pub descriptors: Vec<DescriptorTypeStats>,
+    /// Allocations that found every pool full and created a new one.
+    pub exhaustions: u64,
+}
+
+/// Growing descriptor pools shared by every pipeline of a device.
+///
+/// Sets come from the first pool with room for them. Once every pool is
+/// full, a new one `growth` times as large as the last is created instead
+/// of failing. Pools are linear, sets freed with `free` are reclaimed
+/// together when their pool has no live sets left, which keeps them free of
+/// fragmentation. The texture array of a `TextureRegistry` has a pool of
+/// its own.
+pub struct DescriptorPools {
+    inner: Mutex<Pools>,
+}
+
+/// Descriptor set and pipeline layouts shared by every pipeline of a device.
+///
+/// Pipelines from different `pipeline!` invocations with identical bindings
+/// get the same layout objects, so their descriptor sets are compatible and
+/// switching between them keeps the bound sets. Layouts are reference
+/// counted, every `set_layout` and `pipeline_layout` needs a matching
+/// release, the last one destroys the layout.
+pub struct LayoutCache {
+    inner: Mutex<Layouts>,
+}
+
+/// Usage of the `LayoutCache`, from `Renderer::layout_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LayoutStats {
+    pub set_layouts: usize,
+    pub pipeline_layouts: usize,
+    /// Requests that got an existing layout.
+    pub hits: u64,
+    pub misses: u64,
+}
+
+struct Pools {
+    config: DescriptorPoolConfig,
+    pools: Vec<Pool>,
+    exhaustions: u64,
+}
+
+struct Pool {
+    pool: vk::DescriptorPool,
+    max_sets: u32,
+    live_sets: u32,
+    allocated_sets: u32,
+    // capacity and allocated of each type
+    sizes: Vec<(vk::DescriptorType, u32, u32)>,
+}
+
+struct Layouts {
+    set_layouts: Cache<SetLayoutKey, vk::DescriptorSetLayout>,
+    pipeline_layouts: Cache<PipelineLayoutKey, vk::PipelineLayout>,
+    hits: u64,
+    misses: u64,
+}
+
+struct Cache<K, T> {
+    // lookup of the layouts new requests can share
+    layouts: HashMap<K, T>,
+    // key and reference count of every live layout, also the ones whose
+    // key went stale
+    refs: HashMap<T, (K, u32)>,
+}
+
+// binding, type, count, stages and binding flags of each binding sorted by
+// binding, immutable samplers are not part of it as no pipeline uses them
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct SetLayoutKey(
+    Vec<(
+        u32,
+        vk::DescriptorType,
+        u32,
+        vk::ShaderStageFlags,
+        vk::DescriptorBindingFlags,
+    )>,
+);
+
+// the set layouts are handles from the cache or owned by something that
+// outlives the pipeline, like a `TextureRegistry`, which calls
+// `forget_set_layout` before its handle can be reused
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct PipelineLayoutKey {
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    push_constants: Vec<(vk::ShaderStageFlags, u32, u32)>,
+}
+
+impl Default for DescriptorPoolConfig {
+    fn default() -> Self {
+        Self {
+            sets: 64,
+            growth: 2.0,
+            max_sets: 1024,
+            sizes: vec![
+                (vk::DescriptorType::UNIFORM_BUFFER, 2.0),
+                (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 1.0),
+                (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 2.0),
+                (vk::DescriptorType::STORAGE_BUFFER, 1.0),
+                (vk::DescriptorType::STORAGE_IMAGE, 0.5),
+                (vk::DescriptorType::UNIFORM_TEXEL_BUFFER, 0.25),
+                (vk::DescriptorType::STORAGE_TEXEL_BUFFER, 0.25),
+            ],
+        }
+    }
+}
+
+impl DescriptorPools {
+    pub fn new(config: DescriptorPoolConfig) -> Self {
+        Self {
+            inner: Mutex::new(Pools {
+                config,
+                pools: Vec::new(),
+                exhaustions: 0,
+            }),
+        }
+    }
+
+    pub fn config(&self) -> DescriptorPoolConfig {
+        self.inner.lock().config.clone()
+    }
+
+    /// Applies to the pools created after the call.
+    pub fn configure(&self, config: DescriptorPoolConfig) {
+        self.inner.lock().config = config;
+    }
+
+    /// Allocates a set of `layout`, `sizes` are the descriptors of its bindings.
+    ///
+    /// `variable_count` is the descriptor count of the layout's
+    /// `VARIABLE_DESCRIPTOR_COUNT` binding, `sizes` has to match it.
+    pub fn alloc(
+        &self,
+        device: &RenderDevice,
+        layout: vk::DescriptorSetLayout,
+        sizes: &[vk::DescriptorPoolSize],
+        variable_count: Option<u32>,
+    ) -> Result<DescriptorAlloc, BufferError> {
+        let sizes = merge_sizes(sizes);
+        let layouts = [layout];
+        let counts = variable_count.into_iter().collect::<Vec<_>>();
+        let mut count_info = vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder()
+            .descriptor_counts(&counts);
+        let mut allocate_info = vk::DescriptorSetAllocateInfo::builder().set_layouts(&layouts);
+        if variable_count.is_some() {
+            allocate_info = allocate_info.push_next(&mut count_info);
+        }
+        // the pool is set for each try
+        let mut allocate_info = allocate_info.build();
+        let mut inner = self.inner.lock();
+
+        for (index, pool) in inner.pools.iter_mut().enumerate() {
+            if !pool.fits(&sizes) {
+                continue;
+            }
+
+            allocate_info.descriptor_pool = pool.pool;
+            match unsafe { device.allocate_descriptor_sets(&allocate_info) } {
+                Ok(sets) => {
+                    pool.take(&sizes);
+                    return Ok(DescriptorAlloc {
+                        set: sets[0],
+                        pool: index,
+                    });
+                }
+                // the driver counts differently, try the next one
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY)
+                | Err(vk::Result::ERROR_FRAGMENTED_POOL) => continue,
+                Err(_) => return Err(BufferError::OutOfMemory),
+            }
+        }
+
+        if !inner.pools.is_empty() {
+            inner.exhaustions += 1;
+        }
+
+        let last_sets = inner.pools.last().map(|pool| pool.max_sets);
+        let mut pool = Pool::new(device, &inner.config, last_sets, &sizes)?;
+        debug!(
+            target: logging::PIPELINE,
+            "Descriptor pool {} created with {} sets",
+            inner.pools.len(),
+            pool.max_sets
+        );
+
+        allocate_info.descriptor_pool = pool.pool;
+        let set = unsafe { device.allocate_descriptor_sets(&allocate_info) }
+            .or(Err(BufferError::OutOfMemory))?[0];
+        pool.take(&sizes);
+
+        inner.pools.push(pool);
+        Ok(DescriptorAlloc {
+            set,
+            pool: inner.pools.len() - 1,
+        })
+    }
+
+    /// The set must not be in use by the GPU anymore.
+    pub fn free(&self, device: &RenderDevice, alloc: DescriptorAlloc) {
+        let mut inner = self.inner.lock();
+        let pool = &mut inner.pools[alloc.pool];
+        pool.live_sets -= 1;
+        if pool.live_sets == 0 {
+            if let Err(err) = pool.reset(device) {
+                error!(
+                    target: logging::PIPELINE,
+                    "Descriptor pool {} reset failed: {:?}",
+                    alloc.pool,
+                    err
+                );
+            }
+        }
+    }
+
+    pub fn stats(&self) -> DescriptorStats {
+        let inner = self.inner.lock();
+        let mut stats = DescriptorStats {
+            pools: inner.pools.len(),
+            exhaustions: inner.exhaustions,
+            ..DescriptorStats::default()
+        };
+
+        for pool in inner.pools.iter() {
+            stats.live_sets += pool.live_sets;
+            stats.allocated_sets += pool.allocated_sets;
+            stats.set_capacity += pool.max_sets;
+
+            for &(ty, capacity, allocated) in pool.sizes.iter() {
+                match stats.descriptors.iter_mut().find(|stats| stats.ty == ty) {
+                    Some(stats) => {
+                        stats.allocated += allocated;
+                        stats.capacity += capacity;
+                    }
+                    None => stats.descriptors.push(DescriptorTypeStats {
+                        ty,
+                        allocated,
+                        capacity,
+                    }),
+                }
+            }
+        }
+        stats
+    }
+}
+
+impl Default for DescriptorPools {
+    fn default() -> Self {
+        Self::new(DescriptorPoolConfig::default())
+    }
+}
+
+impl LayoutCache {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(Layouts {
+                set_layouts: Cache::new(),
+                pipeline_layouts: Cache::new(),
+                hits: 0,
+                misses: 0,
+            }),
+        }
+    }
+
+    /// A layout with `bindings`, `release_set_layout` it once it is not used anymore.
+    ///
+    /// `binding_flags` has one entry per binding or is empty for none, any
+    /// flag requires `RenderDevice::descriptor_indexing`.
+    pub fn set_layout(
+        &self,
+        device: &RenderDevice,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+        binding_flags: &[vk::DescriptorBindingFlags],
+    ) -> Result<vk::DescriptorSetLayout, BufferError> {
+        let flagged = binding_flags.iter().any(|flags| !flags.is_empty());
+        if flagged && !device.descriptor_indexing {
+            return Err(BufferError::UnsupportedFeature("descriptor indexing"));
+        }
+        if flagged && binding_flags.len() != bindings.len() {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let mut key = bindings
+            .iter()
+            .enumerate()
+            .map(|(i, binding)| {
+                (
+                    binding.binding,
+                    binding.descriptor_type,
+                    binding.descriptor_count,
+                    binding.stage_flags,
+                    binding_flags.get(i).copied().unwrap_or_default(),
+                )
+            })
+            .collect::<Vec<_>>();
+        key.sort();
+        let key = SetLayoutKey(key);
+
+        let mut inner = self.inner.lock();
+        if let Some(layout) = inner.set_layouts.share(&key) {
+            inner.hits += 1;
+            return Ok(layout);
+        }
+
+        let mut binding_flags_info =
+            vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder().binding_flags(binding_flags);
+        let mut desc_set_layout_info =
+            vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+        if flagged {
+            desc_set_layout_info = desc_set_layout_info.push_next(&mut binding_flags_info);
+        }
+        let layout = unsafe { device.create_descriptor_set_layout(&desc_set_layout_info, None) }
+            .or(Err(BufferError::OutOfMemory))?;
+        inner.misses += 1;
+        inner.set_layouts.insert(key, layout);
+        Ok(layout)
+    }
+
+    /// A layout with `set_layouts` and `push_constants`, `release_pipeline_layout`
+    /// it once it is not used anymore.
+    pub fn pipeline_layout(
+        &self,
+        device: &RenderDevice,
+        set_layouts: &[vk::DescriptorSetLayout],
+        push_constants: &[vk::PushConstantRange],
+    ) -> Result<vk::PipelineLayout, BufferError> {
+        let key = PipelineLayoutKey {
+            set_layouts: set_layouts.to_vec(),
+            push_constants: push_constants
+                .iter()
+                .map(|range| (range.stage_flags, range.offset, range.size))
+                .collect(),
+        };
+
+        let mut inner = self.inner.lock();
+        if let Some(layout) = inner.pipeline_layouts.share(&key) {
+            inner.hits += 1;
+            return Ok(layout);
+        }
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(set_layouts)
+            .push_constant_ranges(push_constants);
+        let layout = unsafe { device.create_pipeline_layout(&pipeline_layout_info, None) }
+            .or(Err(BufferError::OutOfMemory))?;
+        inner.misses += 1;
+        inner.pipeline_layouts.insert(key, layout);
+        Ok(layout)
+    }
+
+    /// Pipelines release their pipeline layout before its set layouts.
+    pub fn release_set_layout(&self, device: &RenderDevice, layout: vk::DescriptorSetLayout) {
+        let mut inner = self.inner.lock();
+        if inner.set_layouts.release(layout) {
+            unsafe { device.destroy_descriptor_set_layout(layout, None) };
+        }
+    }
+
+    pub fn release_pipeline_layout(&self, device: &RenderDevice, layout: vk::PipelineLayout) {
+        let mut inner = self.inner.lock();
+        if inner.pipeline_layouts.release(layout) {
+            unsafe { device.destroy_pipeline_layout(layout, None) };
+        }
+    }
+
+    /// Stops sharing the pipeline layouts using `layout`, a set layout not
+    /// from this cache that is about to be destroyed, so a new layout with
+    /// the same handle does not get them.
+    ///
+    /// Pipelines still using them release them as usual.
+    pub fn forget_set_layout(&self, layout: vk::DescriptorSetLayout) {
+        self.inner
+            .lock()
+            .pipeline_layouts
+            .forget(|key| key.set_layouts.contains(&layout));
+    }
+
+    pub fn stats(&self) -> LayoutStats {
+        let inner = self.inner.lock();
+        LayoutStats {
+            set_layouts: inner.set_layouts.refs.len(),
+            pipeline_layouts: inner.pipeline_layouts.refs.len(),
+            hits: inner.hits,
+            misses: inner.misses,
+        }
+    }
+}
+
+impl Default for LayoutCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Clone + Eq + Hash, T: Copy + Eq + Hash> Cache<K, T> {
+    fn new() -> Self {
+        Self {
+            layouts: HashMap::new(),
+            refs: HashMap::new(),
+        }
+    }
+
+    // another reference to the layout of `key`
+    fn share(&mut self, key: &K) -> Option<T> {
+        let layout = *self.layouts.get(key)?;
+        self.refs.get_mut(&layout)?.1 += 1;
+        Some(layout)
+    }
+
+    fn insert(&mut self, key: K, layout: T) {
+        self.refs.insert(layout, (key.clone(), 1));
+        self.layouts.insert(key, layout);
+    }
+
+    // true if that was the last reference and `layout` has to be destroyed
+    fn release(&mut self, layout: T) -> bool {
+        let last = match self.refs.get_mut(&layout) {
+            Some((_, refs)) => {
+                *refs -= 1;
+                *refs == 0
+            }
+            None => return false,
+        };
+
+        if last {
+            if let Some((key, _)) = self.refs.remove(&layout) {
+                // a forgotten key can be taken by a newer layout
+                if self.layouts.get(&key) == Some(&layout) {
+                    self.layouts.remove(&key);
+                }
+            }
+        }
+        last
+    }
+
+    fn forget<F: Fn(&K) -> bool>(&mut self, stale: F) {
+        self.layouts.retain(|key, _| !stale(key));
+    }
+}
+
+impl Pool {
+    fn new(
+        device: &RenderDevice,
+        config: &DescriptorPoolConfig,
+        last_sets: Option<u32>,
+        needed: &[vk::DescriptorPoolSize],
+    ) -> Result<Self, BufferError> {
+        let max_sets = match last_sets {
+            Some(last_sets) => (last_sets as f32 * config.growth.max(1.0)) as u32,
+            None => config.sets,
+        }
+        .min(config.max_sets)
+        .max(1);
+
+        let mut sizes = config
+            .sizes
+            .iter()
+            .map(|&(ty, per_set)| (ty, (per_set * max_sets as f32).ceil() as u32))
+            .filter(|&(_, count)| count > 0)
+            .collect::<Vec<_>>();
+        for need in needed {
+            match sizes.iter_mut().find(|(ty, _)| *ty == need.ty) {
+                Some((_, count)) => *count = (*count).max(need.descriptor_count),
+                None => sizes.push((need.ty, need.descriptor_count)),
+            }
+        }
+
+        let pool_sizes = sizes
+            .iter()
+            .map(|&(ty, count)| {
+                vk::DescriptorPoolSize::builder()
+                    .ty(ty)
+                    .descriptor_count(count)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let desc_pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(max_sets)
+            .pool_sizes(&pool_sizes);
+        let pool = unsafe { device.create_descriptor_pool(&desc_pool_info, None) }
+            .or(Err(BufferError::OutOfMemory))?;
+
+        Ok(Self {
+            pool,
+            max_sets,
+            live_sets: 0,
+            allocated_sets: 0,
+            sizes: sizes
+                .into_iter()
+                .map(|(ty, capacity)| (ty, capacity, 0))
+                .collect(),
+        })
+    }
+
+    fn fits(&self, needed: &[vk::DescriptorPoolSize]) -> bool {
+        self.allocated_sets < self.max_sets
+            && needed.iter().all(|need| {
+                self.sizes.iter().any(|&(ty, capacity, allocated)| {
+                    ty == need.ty && allocated + need.descriptor_count <= capacity
+                })
+            })
+    }
+
+    fn take(&mut self, needed: &[vk::DescriptorPoolSize]) {
+        self.live_sets += 1;
+        self.allocated_sets += 1;
+        for need in needed {
+            if let Some((_, _, allocated)) = self.sizes.iter_mut().find(|(ty, _, _)| *ty == need.ty)
+            {
+                *allocated += need.descriptor_count;
+            }
+        }
+    }
+
+    // the sets stay allocated if the reset fails
+    fn reset(&mut self, device: &RenderDevice) -> Result<(), BufferError> {
+        unsafe { device.reset_descriptor_pool(self.pool, vk::DescriptorPoolResetFlags::empty()) }
+            .or(Err(BufferError::OutOfMemory))?;
+        self.allocated_sets = 0;
+        for (_, _, allocated) in self.sizes.iter_mut() {
+            *allocated = 0;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DescriptorStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} pools, {} live sets, {} of {} sets allocated, {} exhaustions",
+            self.pools, self.live_sets, self.allocated_sets, self.set_capacity, self.exhaustions
+        )
+    }
+}
+
+impl fmt::Display for LayoutStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} set layouts, {} pipeline layouts, {} of {} requests shared",
+            self.set_layouts,
+            self.pipeline_layouts,
+            self.hits,
+            self.hits + self.misses
+        )
+    }
+}
+
+// one entry per descriptor type
+fn merge_sizes(sizes: &[vk::DescriptorPoolSize]) -> Vec<vk::DescriptorPoolSize> {
+    let mut merged: Vec<vk::DescriptorPoolSize> = Vec::new();
+    for size in sizes {
+        match merged.iter_mut().find(|merged| merged.ty == size.ty) {
+            Some(merged) => merged.descriptor_count += size.descriptor_count,
+            None => merged.push(*size),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(ty: vk::DescriptorType, descriptor_count: u32) -> vk::DescriptorPoolSize {
+        vk::DescriptorPoolSize::builder()
+            .ty(ty)
+            .descriptor_count(descriptor_count)
+            .build()
+    }
+
+    fn pool(max_sets: u32, sizes: &[(vk::DescriptorType, u32)]) -> Pool {
+        Pool {
+            pool: vk::DescriptorPool::null(),
+            max_sets,
+            live_sets: 0,
+            allocated_sets: 0,
+            sizes: sizes
+                .iter()
+                .map(|&(ty, capacity)| (ty, capacity, 0))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn merge_sizes_sums_types() {
+        let merged = merge_sizes(&[
+            size(vk::DescriptorType::UNIFORM_BUFFER, 1),
+            size(vk::DescriptorType::STORAGE_BUFFER, 2),
+            size(vk::DescriptorType::UNIFORM_BUFFER, 3),
+        ]);
+
+        let merged = merged
+            .iter()
+            .map(|size| (size.ty, size.descriptor_count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            merged,
+            [
+                (vk::DescriptorType::UNIFORM_BUFFER, 4),
+                (vk::DescriptorType::STORAGE_BUFFER, 2)
+            ]
+        );
+        assert!(merge_sizes(&[]).is_empty());
+    }
+
+    #[test]
+    fn take_counts_sets_and_descriptors() {
+        let mut pool = pool(
+            4,
+            &[
+                (vk::DescriptorType::UNIFORM_BUFFER, 8),
+                (vk::DescriptorType::STORAGE_BUFFER, 8),
+            ],
+        );
+        pool.take(&[size(vk::DescriptorType::UNIFORM_BUFFER, 3)]);
+        pool.take(&[
+            size(vk::DescriptorType::UNIFORM_BUFFER, 2),
+            size(vk::DescriptorType::STORAGE_BUFFER, 1),
+        ]);
+
+        assert_eq!(pool.live_sets, 2);
+        assert_eq!(pool.allocated_sets, 2);
+        assert_eq!(
+            pool.sizes,
+            [
+                (vk::DescriptorType::UNIFORM_BUFFER, 8, 5),
+                (vk::DescriptorType::STORAGE_BUFFER, 8, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn fits_descriptor_capacity() {
+        let mut pool = pool(4, &[(vk::DescriptorType::UNIFORM_BUFFER, 4)]);
+        let three = [size(vk::DescriptorType::UNIFORM_BUFFER, 3)];
+        let two = [size(vk::DescriptorType::UNIFORM_BUFFER, 2)];
+
+        assert!(pool.fits(&three));
+        pool.take(&three);
+        assert!(!pool.fits(&two));
+        assert!(pool.fits(&[size(vk::DescriptorType::UNIFORM_BUFFER, 1)]));
+
+        // types the pool has no room for at all
+        assert!(!pool.fits(&[size(vk::DescriptorType::STORAGE_BUFFER, 1)]));
+    }
+
+    #[test]
+    fn fits_set_count() {
+        let mut pool = pool(2, &[(vk::DescriptorType::UNIFORM_BUFFER, 100)]);
+        let one = [size(vk::DescriptorType::UNIFORM_BUFFER, 1)];
+
+        pool.take(&one);
+        assert!(pool.fits(&one));
+        pool.take(&one);
+        assert!(!pool.fits(&one));
+
+        // sets without descriptors still need a set
+        assert!(!pool.fits(&[]));
+    }
+
+    #[test]
+    fn cache_counts_references() {
+        let mut cache = Cache::new();
+        cache.insert("a", 1);
+        assert_eq!(cache.share(&"a"), Some(1));
+        assert_eq!(cache.share(&"b"), None);
+
+        assert!(!cache.release(1));
+        assert!(cache.release(1));
+        assert_eq!(cache.share(&"a"), None);
+        assert!(cache.refs.is_empty());
+
+        // unknown layouts are not destroyed
+        assert!(!cache.release(2));
+    }
+
+    #[test]
+    fn forgotten_keys_are_not_shared() {
+        let mut cache = Cache::new();
+        cache.insert("a", 1);
+        cache.forget(|key| *key == "a");
+        assert_eq!(cache.share(&"a"), None);
+
+        // a new layout takes the key, the old one is still released
+        cache.insert("a", 2);
+        assert!(cache.release(1));
+        assert_eq!(cache.share(&"a"), Some(2));
+    }
+}
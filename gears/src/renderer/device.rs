@@ -1,14 +1,27 @@
-use ash::{extensions::khr, version::InstanceV1_0, vk};
+use ash::{
+    extensions::{khr, nv},
+    version::{InstanceV1_0, InstanceV1_1},
+    vk,
+};
 use log::{debug, error};
-use std::{ffi::CStr, ops, sync::Arc};
+use std::{
+    ffi::{CStr, CString},
+    ops,
+    sync::Arc,
+};
 
 use crate::{
-    context::{Context, ContextError},
+    context::{Context, ContextError, Limits},
     debug::Debugger,
-    MapErrorLog,
+    logging, MapErrorLog,
 };
 
-use super::queue::{QueueFamilies, Queues};
+use super::{
+    buffer::{arena::UniformArena, streamed::TextureBudget, transient::TransientStats},
+    descriptor::{DescriptorPools, LayoutCache},
+    queue::{QueueFamilies, Queues},
+    raytracing::RayTracing,
+};
 
 pub struct ReducedContext {
     pub debugger: Debugger,
@@ -18,6 +31,10 @@ pub struct ReducedContext {
 
     pub instance: ash::Instance,
     pub instance_layers: Vec<&'static CStr>,
+    pub api_version: u32,
+    pub device_extensions: Vec<CString>,
+
+    pub limits: Limits,
 
     pub entry: ash::Entry,
 }
@@ -33,6 +50,10 @@ impl ReducedContext {
 
                 instance: context.instance,
                 instance_layers: context.instance_layers,
+                api_version: context.api_version,
+                device_extensions: context.device_extensions,
+
+                limits: context.limits,
 
                 entry: context.entry,
             },
@@ -50,6 +71,28 @@ pub struct RenderDevice {
     pub memory_types: Vec<vk::MemoryType>,
     pub pdevice: vk::PhysicalDevice,
 
+    /// Sampled image arrays can be partially bound, indexed non uniformly and updated after bind.
+    pub descriptor_indexing: bool,
+    /// Pipeline statistics queries can be used.
+    pub pipeline_statistics: bool,
+    /// Indirect draws can draw more than one command per call.
+    pub multi_draw_indirect: bool,
+    /// Semaphores can be created with the timeline type.
+    pub timeline_semaphore: bool,
+    /// Pipelines can rasterize triangle edges only, needed by `DebugView::Wireframe`.
+    pub fill_mode_non_solid: bool,
+    /// Loaded if task and mesh shaders are supported.
+    pub mesh_shader: Option<nv::MeshShader>,
+    /// Loaded if ray tracing pipelines and acceleration structures are supported.
+    pub ray_tracing: Option<RayTracing>,
+
+    pub limits: Limits,
+    pub texture_budget: TextureBudget,
+    pub transient_stats: TransientStats,
+    pub uniform_arena: UniformArena,
+    pub descriptor_pools: DescriptorPools,
+    pub layouts: LayoutCache,
+
     device: ash::Device,
     pub instance: ash::Instance,
     _entry: ash::Entry,
@@ -64,10 +107,11 @@ impl RenderDevice {
             .collect()
     }
 
-    // safe if instance and pdevice are valid
+    // safe if instance and pdevice are valid and ptrs are not used after extra is modified or dropped
     unsafe fn device_extensions(
         instance: &ash::Instance,
         pdevice: vk::PhysicalDevice,
+        extra: &[CString],
     ) -> Result<Vec<*const i8>, ContextError> {
         let available = instance
             .enumerate_device_extension_properties(pdevice)
@@ -76,7 +120,12 @@ impl RenderDevice {
                 ContextError::OutOfMemory,
             )?;
 
-        let requested = vec![khr::Swapchain::name()];
+        let mut requested = vec![khr::Swapchain::name()];
+        for ext in extra.iter() {
+            if !requested.contains(&ext.as_c_str()) {
+                requested.push(ext.as_c_str());
+            }
+        }
         let requested_raw: Vec<*const i8> =
             requested.iter().map(|raw_name| raw_name.as_ptr()).collect();
 
@@ -112,19 +161,174 @@ impl RenderDevice {
         pdevice: vk::PhysicalDevice,
     ) -> vk::PhysicalDeviceMemoryProperties {
         let memory_properties = unsafe { instance.get_physical_device_memory_properties(pdevice) };
-        debug!("Memory properties: {:?}", memory_properties);
+        debug!(
+            target: logging::MEMORY,
+            "Memory properties: {:?}",
+            memory_properties
+        );
         memory_properties
     }
 
+    // safe if instance and pdevice are valid
+    unsafe fn descriptor_indexing(
+        instance: &ash::Instance,
+        pdevice: vk::PhysicalDevice,
+        api_version: u32,
+    ) -> bool {
+        let device_api_version = instance.get_physical_device_properties(pdevice).api_version;
+        if api_version.min(device_api_version) < vk::make_version(1, 2, 0) {
+            return false;
+        }
+
+        let mut indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+        let mut features = vk::PhysicalDeviceFeatures2::default();
+        features.p_next = &mut indexing as *mut _ as *mut _;
+        instance.get_physical_device_features2(pdevice, &mut features);
+
+        let supported = indexing.shader_sampled_image_array_non_uniform_indexing == vk::TRUE
+            && indexing.descriptor_binding_sampled_image_update_after_bind == vk::TRUE
+            && indexing.descriptor_binding_update_unused_while_pending == vk::TRUE
+            && indexing.descriptor_binding_partially_bound == vk::TRUE
+            && indexing.descriptor_binding_variable_descriptor_count == vk::TRUE
+            && indexing.runtime_descriptor_array == vk::TRUE;
+
+        debug!("Descriptor indexing supported: {}", supported);
+        supported
+    }
+
+    // safe if instance and pdevice are valid
+    unsafe fn timeline_semaphore(
+        instance: &ash::Instance,
+        pdevice: vk::PhysicalDevice,
+        api_version: u32,
+    ) -> bool {
+        let device_api_version = instance.get_physical_device_properties(pdevice).api_version;
+        if api_version.min(device_api_version) < vk::make_version(1, 2, 0) {
+            return false;
+        }
+
+        let mut timeline = vk::PhysicalDeviceTimelineSemaphoreFeatures::default();
+        let mut features = vk::PhysicalDeviceFeatures2::default();
+        features.p_next = &mut timeline as *mut _ as *mut _;
+        instance.get_physical_device_features2(pdevice, &mut features);
+
+        let supported = timeline.timeline_semaphore == vk::TRUE;
+
+        debug!("Timeline semaphores supported: {}", supported);
+        supported
+    }
+
+    // safe if instance and pdevice are valid
+    pub(crate) unsafe fn mesh_shader(
+        instance: &ash::Instance,
+        pdevice: vk::PhysicalDevice,
+        api_version: u32,
+    ) -> bool {
+        if api_version < vk::make_version(1, 1, 0) {
+            return false;
+        }
+
+        let has_extension = instance
+            .enumerate_device_extension_properties(pdevice)
+            .unwrap_or_default()
+            .iter()
+            .any(|ext| CStr::from_ptr(ext.extension_name.as_ptr()) == nv::MeshShader::name());
+        if !has_extension {
+            return false;
+        }
+
+        let mut mesh = vk::PhysicalDeviceMeshShaderFeaturesNV::default();
+        let mut features = vk::PhysicalDeviceFeatures2::default();
+        features.p_next = &mut mesh as *mut _ as *mut _;
+        instance.get_physical_device_features2(pdevice, &mut features);
+
+        let supported = mesh.task_shader == vk::TRUE && mesh.mesh_shader == vk::TRUE;
+
+        debug!("Mesh shaders supported: {}", supported);
+        supported
+    }
+
+    // safe if instance and pdevice are valid
+    pub(crate) unsafe fn ray_tracing(
+        instance: &ash::Instance,
+        pdevice: vk::PhysicalDevice,
+        api_version: u32,
+    ) -> bool {
+        let device_api_version = instance.get_physical_device_properties(pdevice).api_version;
+        if api_version.min(device_api_version) < vk::make_version(1, 2, 0) {
+            return false;
+        }
+
+        let available = instance
+            .enumerate_device_extension_properties(pdevice)
+            .unwrap_or_default();
+        let has_extensions = Self::ray_tracing_extensions().iter().all(|name| {
+            available
+                .iter()
+                .any(|ext| CStr::from_ptr(ext.extension_name.as_ptr()) == *name)
+        });
+        if !has_extensions {
+            return false;
+        }
+
+        let mut address = vk::PhysicalDeviceBufferDeviceAddressFeatures::default();
+        let mut acceleration_structure =
+            vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default();
+        let mut pipeline = vk::PhysicalDeviceRayTracingPipelineFeaturesKHR::default();
+        pipeline.p_next = &mut acceleration_structure as *mut _ as *mut _;
+        address.p_next = &mut pipeline as *mut _ as *mut _;
+        let mut features = vk::PhysicalDeviceFeatures2::default();
+        features.p_next = &mut address as *mut _ as *mut _;
+        instance.get_physical_device_features2(pdevice, &mut features);
+
+        let supported = address.buffer_device_address == vk::TRUE
+            && acceleration_structure.acceleration_structure == vk::TRUE
+            && pipeline.ray_tracing_pipeline == vk::TRUE;
+
+        debug!("Ray tracing supported: {}", supported);
+        supported
+    }
+
+    fn ray_tracing_extensions() -> [&'static CStr; 3] {
+        [
+            khr::AccelerationStructure::name(),
+            khr::RayTracingPipeline::name(),
+            khr::DeferredHostOperations::name(),
+        ]
+    }
+
     pub fn from_context(context: ReducedContext) -> Result<Arc<Self>, ContextError> {
         // legacy device layers
         // unsafe: instance_layers is dropped in this function
         let instance_layers = unsafe { Self::device_layers(&context.instance_layers) };
 
         // device extensions
+        // unsafe: instance, pdevice and device_extensions are owned by this function
+        let mut device_extensions = unsafe {
+            Self::device_extensions(
+                &context.instance,
+                context.pdevice,
+                &context.device_extensions,
+            )?
+        };
+
         // unsafe: instance and pdevice are owned by this function
-        let device_extensions =
-            unsafe { Self::device_extensions(&context.instance, context.pdevice)? };
+        let mesh_shader =
+            unsafe { Self::mesh_shader(&context.instance, context.pdevice, context.api_version) };
+        if mesh_shader {
+            device_extensions.push(nv::MeshShader::name().as_ptr());
+        }
+
+        // unsafe: instance and pdevice are owned by this function
+        let ray_tracing =
+            unsafe { Self::ray_tracing(&context.instance, context.pdevice, context.api_version) };
+        if ray_tracing {
+            device_extensions.extend(
+                Self::ray_tracing_extensions()
+                    .iter()
+                    .map(|name| name.as_ptr()),
+            );
+        }
 
         // memory
         let memory_types = Self::memory_properties(&context.instance, context.pdevice)
@@ -137,17 +341,72 @@ impl RenderDevice {
         let queue_create_infos = context.queue_families.get_vec().unwrap();
 
         // features
+        let available_features = unsafe {
+            context
+                .instance
+                .get_physical_device_features(context.pdevice)
+        };
+        let pipeline_statistics = available_features.pipeline_statistics_query == vk::TRUE;
+        let multi_draw_indirect = available_features.multi_draw_indirect == vk::TRUE;
+        let fill_mode_non_solid = available_features.fill_mode_non_solid == vk::TRUE;
         let features = vk::PhysicalDeviceFeatures {
             geometry_shader: vk::TRUE,
+            pipeline_statistics_query: available_features.pipeline_statistics_query,
+            sampler_anisotropy: available_features.sampler_anisotropy,
+            multi_draw_indirect: available_features.multi_draw_indirect,
+            fill_mode_non_solid: available_features.fill_mode_non_solid,
             ..Default::default()
         };
 
+        // unsafe: instance and pdevice are owned by this function
+        let descriptor_indexing = unsafe {
+            Self::descriptor_indexing(&context.instance, context.pdevice, context.api_version)
+        };
+        let mut indexing_features = vk::PhysicalDeviceDescriptorIndexingFeatures::builder()
+            .shader_sampled_image_array_non_uniform_indexing(true)
+            .descriptor_binding_sampled_image_update_after_bind(true)
+            .descriptor_binding_update_unused_while_pending(true)
+            .descriptor_binding_partially_bound(true)
+            .descriptor_binding_variable_descriptor_count(true)
+            .runtime_descriptor_array(true);
+        // unsafe: instance and pdevice are owned by this function
+        let timeline_semaphore = unsafe {
+            Self::timeline_semaphore(&context.instance, context.pdevice, context.api_version)
+        };
+        let mut timeline_features =
+            vk::PhysicalDeviceTimelineSemaphoreFeatures::builder().timeline_semaphore(true);
+        let mut mesh_shader_features = vk::PhysicalDeviceMeshShaderFeaturesNV::builder()
+            .task_shader(true)
+            .mesh_shader(true);
+        let mut address_features =
+            vk::PhysicalDeviceBufferDeviceAddressFeatures::builder().buffer_device_address(true);
+        let mut acceleration_structure_features =
+            vk::PhysicalDeviceAccelerationStructureFeaturesKHR::builder()
+                .acceleration_structure(true);
+        let mut ray_tracing_features =
+            vk::PhysicalDeviceRayTracingPipelineFeaturesKHR::builder().ray_tracing_pipeline(true);
+
         // device
-        let device_info = vk::DeviceCreateInfo::builder()
+        let mut device_info = vk::DeviceCreateInfo::builder()
             .queue_create_infos(&queue_create_infos)
             .enabled_layer_names(&instance_layers[..])
             .enabled_extension_names(&device_extensions[..])
             .enabled_features(&features);
+        if descriptor_indexing {
+            device_info = device_info.push_next(&mut indexing_features);
+        }
+        if timeline_semaphore {
+            device_info = device_info.push_next(&mut timeline_features);
+        }
+        if mesh_shader {
+            device_info = device_info.push_next(&mut mesh_shader_features);
+        }
+        if ray_tracing {
+            device_info = device_info
+                .push_next(&mut address_features)
+                .push_next(&mut acceleration_structure_features)
+                .push_next(&mut ray_tracing_features);
+        }
 
         // unsafe: instance is again owned by this function and moving instance or entry will not invalidate device
         let device = unsafe {
@@ -160,6 +419,17 @@ impl RenderDevice {
         // unsafe: queues does not live beyond device, instance or entry. Moving is allowed but destruction is not.
         let queues = unsafe { context.queue_families.get_queues(&device).unwrap() };
 
+        let mesh_shader = if mesh_shader {
+            Some(nv::MeshShader::new(&context.instance, &device))
+        } else {
+            None
+        };
+        let ray_tracing = if ray_tracing {
+            Some(RayTracing::new(&context.instance, &device, context.pdevice))
+        } else {
+            None
+        };
+
         let rdevice = Arc::new(Self {
             _debugger: context.debugger,
             queues,
@@ -167,6 +437,21 @@ impl RenderDevice {
             memory_types,
             pdevice: context.pdevice,
 
+            descriptor_indexing,
+            pipeline_statistics,
+            multi_draw_indirect,
+            timeline_semaphore,
+            fill_mode_non_solid,
+            mesh_shader,
+            ray_tracing,
+
+            limits: context.limits,
+            texture_budget: TextureBudget::default(),
+            transient_stats: TransientStats::default(),
+            uniform_arena: UniformArena::default(),
+            descriptor_pools: DescriptorPools::default(),
+            layouts: LayoutCache::default(),
+
             device,
             instance: context.instance,
             _entry: context.entry,
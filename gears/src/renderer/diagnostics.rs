@@ -0,0 +1,304 @@
+use ash::vk;
+use log::{error, warn};
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::logging;
+
+use super::{
+    descriptor::{DescriptorStats, LayoutStats},
+    RecordMode,
+};
+
+const MIB: u64 = 1024 * 1024;
+
+/// Timings of one `Renderer::frame`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameSample {
+    pub frame_index: u64,
+    /// `FrameInfo::real_delta`, the time since the previous frame.
+    pub real_delta: Duration,
+    pub cpu_frametime: Duration,
+    /// Zero if timestamp queries are not supported.
+    pub gpu_frametime: Duration,
+    pub triangles: usize,
+    pub rerecord: bool,
+    pub updates: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryHeap {
+    pub size: u64,
+    pub device_local: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub heaps: Vec<MemoryHeap>,
+    /// `TextureBudget::used`.
+    pub streamed_used: u64,
+    /// `TextureBudget::limit`.
+    pub streamed_limit: u64,
+    /// `TransientStats::allocated`.
+    pub transient_allocated: u64,
+    /// `TransientStats::aliased`.
+    pub transient_aliased: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SwapchainState {
+    pub extent: (u32, u32),
+    pub format: vk::Format,
+    pub present_mode: vk::PresentModeKHR,
+    pub image_count: usize,
+    pub frames_in_flight: usize,
+    pub render_scale: f32,
+    pub record_mode: RecordMode,
+}
+
+/// Snapshot of the renderer state, from `Renderer::diagnostics` or a `SpikeDetector`.
+///
+/// `Display` formats it as the plain text report the detector writes.
+#[derive(Debug, Clone)]
+pub struct Diagnostics {
+    pub time: SystemTime,
+    /// The frame that exceeded the threshold, `None` for manual snapshots.
+    pub spike: Option<FrameSample>,
+    /// The most recent frames, oldest first.
+    pub history: Vec<FrameSample>,
+    pub memory: MemoryStats,
+    pub swapchain: SwapchainState,
+    pub descriptors: DescriptorStats,
+    pub layouts: LayoutStats,
+    /// The latest validation warnings and errors, oldest first. Always empty
+    /// without validation layers.
+    pub validation: Vec<String>,
+}
+
+enum SpikeOutput {
+    Log,
+    File(PathBuf),
+    Callback(Box<dyn FnMut(&Diagnostics) + Send>),
+}
+
+/// Dumps `Diagnostics` when a frame takes longer than a threshold, see
+/// `Renderer::set_spike_detector`.
+///
+/// A frame counts as a spike if its real delta, CPU or GPU frametime
+/// exceeds the threshold. The report goes to the `gears::perf` log target
+/// as a warning unless `with_file` or `with_callback` is set. Hitches often
+/// come in bursts, so after a dump the next `with_cooldown` frames are only
+/// recorded. The first recorded frame is never a spike.
+pub struct SpikeDetector {
+    threshold: Duration,
+    history: VecDeque<FrameSample>,
+    history_len: usize,
+    cooldown: usize,
+    cooling: usize,
+    output: SpikeOutput,
+}
+
+impl SpikeDetector {
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            history: VecDeque::new(),
+            history_len: 120,
+            cooldown: 60,
+            // the first frame includes setup and swapchain creation
+            cooling: 1,
+            output: SpikeOutput::Log,
+        }
+    }
+
+    /// Frames kept for the report, the spike included. 120 by default.
+    pub fn with_history(mut self, frames: usize) -> Self {
+        self.history_len = frames.max(1);
+        self
+    }
+
+    /// Frames after a dump that are not checked, 60 by default.
+    pub fn with_cooldown(mut self, frames: usize) -> Self {
+        self.cooldown = frames;
+        self
+    }
+
+    /// Appends every report to the file at `path`, created if missing.
+    pub fn with_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.output = SpikeOutput::File(path.into());
+        self
+    }
+
+    /// Calls `callback` with every report, on the thread calling `Renderer::frame`.
+    ///
+    /// The callback must not set the spike detector of the same renderer.
+    pub fn with_callback<F: FnMut(&Diagnostics) + Send + 'static>(mut self, callback: F) -> Self {
+        self.output = SpikeOutput::Callback(Box::new(callback));
+        self
+    }
+
+    pub fn threshold(&self) -> Duration {
+        self.threshold
+    }
+
+    /// Adds `sample` to the history, true if it is a spike to dump.
+    pub(crate) fn record(&mut self, sample: FrameSample) -> bool {
+        if self.history.len() >= self.history_len {
+            self.history.pop_front();
+        }
+        self.history.push_back(sample);
+
+        if self.cooling > 0 {
+            self.cooling -= 1;
+            return false;
+        }
+
+        let longest = sample
+            .real_delta
+            .max(sample.cpu_frametime)
+            .max(sample.gpu_frametime);
+        if longest > self.threshold {
+            self.cooling = self.cooldown;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn history(&self) -> Vec<FrameSample> {
+        self.history.iter().copied().collect()
+    }
+
+    pub(crate) fn dump(&mut self, diagnostics: &Diagnostics) {
+        match &mut self.output {
+            SpikeOutput::Log => warn!(target: logging::PERF, "{}", diagnostics),
+            SpikeOutput::File(path) => {
+                let written = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .and_then(|mut file| writeln!(file, "{}", diagnostics));
+                if let Err(err) = written {
+                    error!(
+                        target: logging::PERF,
+                        "Could not write diagnostics to {:?}: {}",
+                        path,
+                        err
+                    );
+                }
+            }
+            SpikeOutput::Callback(callback) => callback(diagnostics),
+        }
+    }
+}
+
+impl fmt::Display for FrameSample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{}: real {:.2} ms, CPU {:.2} ms, GPU {:.2} ms, {} triangles",
+            self.frame_index,
+            millis(self.real_delta),
+            millis(self.cpu_frametime),
+            millis(self.gpu_frametime),
+            self.triangles
+        )?;
+        if self.rerecord {
+            write!(f, ", rerecord")?;
+        }
+        if self.updates {
+            write!(f, ", updates")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since_epoch = self
+            .time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        match self.spike {
+            Some(spike) => {
+                writeln!(f, "Frame spike at {} (unix time)", since_epoch)?;
+                writeln!(f, " - spike {}", spike)?;
+            }
+            None => writeln!(f, "Diagnostics at {} (unix time)", since_epoch)?,
+        }
+
+        let swapchain = &self.swapchain;
+        writeln!(
+            f,
+            " - swapchain: {}x{}, {:?}, {:?}, {} images, {} frames in flight, render scale {:.2}, {:?} recording",
+            swapchain.extent.0,
+            swapchain.extent.1,
+            swapchain.format,
+            swapchain.present_mode,
+            swapchain.image_count,
+            swapchain.frames_in_flight,
+            swapchain.render_scale,
+            swapchain.record_mode
+        )?;
+
+        writeln!(f, " - memory heaps:")?;
+        for (i, heap) in self.memory.heaps.iter().enumerate() {
+            writeln!(
+                f,
+                "   - {}: {} MiB{}",
+                i,
+                heap.size / MIB,
+                if heap.device_local {
+                    ", device local"
+                } else {
+                    ""
+                }
+            )?;
+        }
+        if self.memory.streamed_limit == !0 {
+            writeln!(
+                f,
+                " - streamed textures: {} MiB, unlimited",
+                self.memory.streamed_used / MIB
+            )?;
+        } else {
+            writeln!(
+                f,
+                " - streamed textures: {} MiB of {} MiB",
+                self.memory.streamed_used / MIB,
+                self.memory.streamed_limit / MIB
+            )?;
+        }
+        writeln!(
+            f,
+            " - transient images: {} MiB, {} MiB saved by aliasing",
+            self.memory.transient_allocated / MIB,
+            self.memory.transient_aliased / MIB
+        )?;
+
+        writeln!(f, " - descriptors: {}", self.descriptors)?;
+        writeln!(f, " - layouts: {}", self.layouts)?;
+
+        writeln!(f, " - last {} frames:", self.history.len())?;
+        for sample in self.history.iter() {
+            writeln!(f, "   - {}", sample)?;
+        }
+
+        writeln!(f, " - last {} validation messages:", self.validation.len())?;
+        for message in self.validation.iter() {
+            writeln!(f, "   - {}", message)?;
+        }
+        Ok(())
+    }
+}
+
+fn millis(duration: Duration) -> f32 {
+    duration.as_secs_f32() * 1000.0
+}
@@ -0,0 +1,97 @@
+use ash::vk;
+use parking_lot::RwLock;
+use std::sync::Arc;
+
+use super::{
+    buffer::BufferError,
+    device::RenderDevice,
+    readback::{PixelReadback, ReadPixel},
+    target::RenderTarget,
+    UpdateRecordInfo,
+};
+
+/// Object id target and its readbacks, see `Renderer::pick`.
+pub(crate) struct Picker {
+    device: Arc<RenderDevice>,
+    target: RwLock<Arc<RenderTarget>>,
+
+    ids: PixelReadback,
+    depths: PixelReadback,
+}
+
+impl Picker {
+    pub fn new(
+        device: Arc<RenderDevice>,
+        extent: vk::Extent2D,
+        image_count: usize,
+    ) -> Result<Self, BufferError> {
+        let target = Self::create_target(device.clone(), extent)?;
+        let ids = PixelReadback::new_with_device(device.clone(), image_count)?;
+        let depths = PixelReadback::new_with_device(device.clone(), image_count)?;
+
+        Ok(Self {
+            device,
+            target: RwLock::new(Arc::new(target)),
+
+            ids,
+            depths,
+        })
+    }
+
+    fn create_target(
+        device: Arc<RenderDevice>,
+        extent: vk::Extent2D,
+    ) -> Result<RenderTarget, BufferError> {
+        RenderTarget::new_with_device(device, extent.width, extent.height, &[vk::Format::R32_UINT])
+    }
+
+    pub fn target(&self) -> Arc<RenderTarget> {
+        self.target.read().clone()
+    }
+
+    /// The device has to be idle, the old target is kept if the new one can't be created.
+    pub fn resize(&self, extent: vk::Extent2D) -> Result<(), BufferError> {
+        let target = Self::create_target(self.device.clone(), extent)?;
+        *self.target.write() = Arc::new(target);
+        Ok(())
+    }
+
+    // requests both, so ids and depths stay in sync
+    fn request(&self, x: u32, y: u32) -> bool {
+        let target = self.target.read();
+        if x >= target.width() || y >= target.height() {
+            return false;
+        }
+
+        self.ids.request(x, y);
+        self.depths.request(x, y);
+        true
+    }
+
+    pub fn pick(&self, x: u32, y: u32) -> Option<u32> {
+        if !self.request(x, y) {
+            return None;
+        }
+
+        // the target is cleared to 0
+        self.ids
+            .get()
+            .map(|pixel| pixel.value)
+            .filter(|&id| id != 0)
+    }
+
+    pub fn depth(&self, x: u32, y: u32) -> Option<ReadPixel> {
+        if !self.request(x, y) {
+            return None;
+        }
+
+        self.depths.get()
+    }
+
+    /// Copies the requested pixel of the last frame, returns true if anything was recorded.
+    pub unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        let target = self.target();
+        // no short circuit, both read their finished copies
+        self.ids.update(uri, target.color_image(0)) | self.depths.update(uri, target.depth_image())
+    }
+}
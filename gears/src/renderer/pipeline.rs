@@ -1,25 +1,56 @@
-use ash::{util::read_spv, version::DeviceV1_0, vk};
-use gears_traits::{Vertex, UBO};
-use log::debug;
+use ash::{version::DeviceV1_0, vk};
+use gears_traits::{environment, AlignedArray, Vertex, UBO};
+use log::{debug, error, warn};
 use parking_lot::Mutex;
 use std::{
     any::{type_name, Any, TypeId},
     collections::HashMap,
     ffi::CStr,
-    io::Cursor,
-    sync::Arc,
+    mem, slice,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc,
+    },
 };
 
 use crate::{
-    renderer::buffer::Buffer, renderer::ImmediateFrameInfo, renderer::RenderRecordInfo,
+    logging, renderer::buffer::Buffer, renderer::ImmediateFrameInfo, renderer::RenderRecordInfo,
     renderer::Renderer, renderer::UpdateRecordInfo, ExpectLog,
 };
 
 use super::{
+    bindless::TextureRegistry,
     buffer::{uniform::UniformBuffer, BufferError, WriteType},
+    descriptor::DescriptorAlloc,
     device::RenderDevice,
+    raytracing::{address_buffer, AccelerationStructure},
+    target::RenderTarget,
 };
 
+mod overdraw_shader {
+    gears_pipeline::pipeline! {
+        vert: {
+            path: "res/fullscreen.vert.glsl"
+        }
+        frag: {
+            path: "res/debug.frag.glsl"
+            define: ["NORMALS" = "0"]
+        }
+    }
+}
+
+mod normals_shader {
+    gears_pipeline::pipeline! {
+        vert: {
+            path: "res/fullscreen.vert.glsl"
+        }
+        frag: {
+            path: "res/debug.frag.glsl"
+            define: ["NORMALS" = "1"]
+        }
+    }
+}
+
 trait UniformBufferT {
     unsafe fn update_t(&self, uri: &UpdateRecordInfo) -> bool;
     fn as_any(&mut self) -> &mut dyn Any;
@@ -36,19 +67,122 @@ impl<U: 'static> UniformBufferT for UniformBuffer<U> {
 }
 
 type UBStorage = Arc<Mutex<dyn UniformBufferT + Send>>;
+// the buffers of one set, one per block of a `uniform(count = ..)` array
+type UboBlocks = Vec<(vk::DescriptorBufferInfo, UBStorage)>;
+type DescriptorSets = Vec<(vk::DescriptorSet, HashMap<TypeId, Vec<UBStorage>>)>;
+// source of `Pipeline::sort_id`
+static NEXT_SORT_ID: AtomicU16 = AtomicU16::new(0);
+// the first word of every SPIR-V module
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+/// The default `ShaderEnvironment::max_lights`.
+pub const DEFAULT_MAX_LIGHTS: u32 = environment::DEFAULT_MAX_LIGHTS;
+
+type Descriptors = (
+    vk::DescriptorSetLayout,
+    Vec<DescriptorAlloc>,
+    DescriptorSets,
+);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineConfig {
+    /// Number of descriptor sets and uniform buffer copies, one per swapchain image.
+    ///
+    /// Defaults to the renderer's swapchain image count. Smaller values are raised to it.
+    pub frames_in_flight: Option<usize>,
+
+    /// Unused, descriptor sets come from the renderer's growing pools, see
+    /// `RendererBuilder::with_descriptor_pools`.
+    #[deprecated(note = "descriptor pools grow on demand, see `DescriptorPoolConfig`")]
+    pub max_sets: Option<usize>,
+
+    /// Disables backface culling.
+    pub debug: bool,
+}
+
+/// Pipeline variants to draw with instead, see `Renderer::set_debug_view`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DebugView {
+    None,
+    /// Triangle edges with the pipeline's own fragment shader, requires `RenderDevice::fill_mode_non_solid`.
+    Wireframe,
+    /// Flat per triangle normals in screen space, reconstructed from depth.
+    Normals,
+    /// Every fragment adds a bit of color, without depth testing.
+    Overdraw,
+}
+
+/// Values of the specialization constants declared by
+/// `#include "gears://environment.glsl"`, see the `pipeline!` docs.
+///
+/// Every `PipelineBuilder` specializes its shaders with these, so a shader
+/// can size its light array or take the mesh shader path without a define
+/// in each `pipeline!` call. Shaders without the include ignore them.
+///
+/// The constants use the `constant_id`s from `environment::FIRST_CONSTANT_ID`
+/// (1000) to `environment::END_CONSTANT_ID`, own constants need other ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShaderEnvironment {
+    /// `GEARS_MSAA_SAMPLES`, the sample count of the render target.
+    pub msaa_samples: u32,
+    /// `GEARS_MAX_LIGHTS`, see `RendererBuilder::with_max_lights`.
+    pub max_lights: u32,
+    /// `GEARS_FRAMES_IN_FLIGHT`, the pipeline's descriptor set count.
+    pub frames_in_flight: u32,
+    /// `GEARS_DESCRIPTOR_INDEXING`
+    pub descriptor_indexing: bool,
+    /// `GEARS_MESH_SHADER`
+    pub mesh_shader: bool,
+    /// `GEARS_RAY_TRACING`
+    pub ray_tracing: bool,
+}
+
+impl Default for DebugView {
+    fn default() -> Self {
+        DebugView::None
+    }
+}
+
+/// Overrides where `Vertex` attributes are read from, see `GraphicsPipelineBuilder::with_input_remap`.
+///
+/// Attributes are addressed by shader location. Attributes moved to the same
+/// binding are tightly packed in location order, the rest stay in the
+/// interleaved buffer at binding 0.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VertexRemap {
+    attributes: Vec<(u32, VertexSource)>,
+}
+
+/// The layout `Pipeline::from_spirv` builds a pipeline with, what
+/// `pipeline!` would read from the GLSL.
+///
+/// The uniform block is untyped, it is written with `Pipeline::write_ubo_bytes`.
+#[derive(Debug, Default, Clone)]
+pub struct PipelineReflection {
+    vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    uniform: Option<(usize, vk::ShaderStageFlags)>,
+    push_constants: Option<(usize, vk::ShaderStageFlags)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VertexSource {
+    PerVertex(u32),
+    Constant(u32),
+}
 
 pub struct PipelineBuilder {
     device: Arc<RenderDevice>,
     render_pass: vk::RenderPass,
+    color_attachments: usize,
+    samples: vk::SampleCountFlags,
     set_count: usize,
+    push_constants: Option<vk::PushConstantRange>,
+    debug_views: bool,
+    environment: ShaderEnvironment,
 
-    ubos: HashMap<
-        TypeId,
-        (
-            vk::ShaderStageFlags,
-            Result<Vec<(vk::Buffer, UBStorage)>, BufferError>,
-        ),
-    >,
+    ubos: HashMap<TypeId, UboBinding>,
+    ubo_array: Option<UboArray>,
 }
 
 pub struct GraphicsPipelineBuilder<'a> {
@@ -57,37 +191,343 @@ pub struct GraphicsPipelineBuilder<'a> {
     vert_input_binding: Vec<vk::VertexInputBindingDescription>,
     vert_input_attribute: Vec<vk::VertexInputAttributeDescription>,
 
-    vert_spirv: &'a [u8],
-    geom_spirv: Option<&'a [u8]>,
-    frag_spirv: &'a [u8],
+    vert_spirv: Option<&'a [u32]>,
+    task_spirv: Option<&'a [u32]>,
+    mesh_spirv: Option<&'a [u32]>,
+    geom_spirv: Option<&'a [u32]>,
+    frag_spirv: &'a [u32],
+
+    texture_registry: Option<Arc<TextureRegistry>>,
+    resources: Vec<Resource>,
+    transparent: bool,
+    stencil: Option<vk::StencilOpState>,
+    topology: vk::PrimitiveTopology,
+}
+
+pub struct ComputePipelineBuilder<'a> {
+    base: PipelineBuilder,
+
+    comp_spirv: &'a [u32],
+
+    resources: Vec<Resource>,
+}
+
+pub struct RayTracingPipelineBuilder<'a> {
+    base: PipelineBuilder,
+
+    rgen_spirv: &'a [u32],
+    rmiss_spirv: &'a [u32],
+    rchit_spirv: &'a [u32],
+
+    resources: Vec<Resource>,
+}
+
+enum Resource {
+    StorageBuffer(vk::Buffer),
+    UniformBuffer(vk::Buffer),
+    SampledImage(vk::ImageView, vk::Sampler),
+    // the written elements and the descriptor count
+    SampledImageArray(Vec<(vk::ImageView, vk::Sampler)>, u32),
+    StorageImage(vk::ImageView),
+    UniformTexelBuffer(vk::BufferView),
+    StorageTexelBuffer(vk::BufferView),
+    AccelerationStructure(vk::AccelerationStructureKHR),
+}
+
+// key of the `with_ubo_bytes` UBO, it has no Rust type
+struct RawUbo;
+
+// a UBO or an array of UBO blocks given to the builder
+struct UboBinding {
+    stage: vk::ShaderStageFlags,
+    // the declared count, `blocks` for `count = unbounded`
+    descriptor_count: u32,
+    binding_flags: vk::DescriptorBindingFlags,
+    // one buffer per block for each set
+    buffers: Result<Vec<UboBlocks>, BufferError>,
+}
+
+// the `with_ubo_array` UBO, bound with a dynamic offset
+#[derive(Debug, Clone, Copy)]
+struct UboArray {
+    type_id: TypeId,
+    stride: u32,
+    size: u32,
+}
+
+// storage buffers, images and texel buffers given to the builder
+struct ResourceSet {
+    desc_alloc: DescriptorAlloc,
+    desc_set_layout: vk::DescriptorSetLayout,
+    desc_set: vk::DescriptorSet,
 }
 
-/* TODO: pub struct ComputePipelineBuilder<'a, B: Backend> {
-    base: PipelineBuilder<'a, B>,
+// binding description of a remapped binding, its packed size and if it is constant
+type RemappedBinding = (vk::VertexInputBindingDescription, u32, bool);
 
-    comp_spirv: &'a [u8],
-} */
+// ray generation, miss and hit group regions of one buffer
+struct ShaderBindingTable {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    regions: [vk::StridedDeviceAddressRegionKHR; 3],
+}
 
 pub struct Pipeline {
     device: Arc<RenderDevice>,
+    sort_id: u16,
 
-    desc_pool: Option<vk::DescriptorPool>,
+    desc_allocs: Vec<DescriptorAlloc>,
 
     desc_set_layout: vk::DescriptorSetLayout,
-    desc_sets: Vec<(vk::DescriptorSet, HashMap<TypeId, UBStorage>)>,
-
+    desc_sets: DescriptorSets,
+    texture_registry: Option<Arc<TextureRegistry>>,
+    resources: Option<ResourceSet>,
+    push_constants: Option<vk::PushConstantRange>,
+    ubo_array: Option<UboArray>,
+    sbt: Option<ShaderBindingTable>,
+
+    bind_point: vk::PipelineBindPoint,
     pipeline_layout: vk::PipelineLayout,
     pipeline: vk::Pipeline,
+    debug_pipelines: Vec<(DebugView, vk::Pipeline)>,
+}
+
+impl UboBinding {
+    fn single(stage: vk::ShaderStageFlags, buffers: Result<Vec<UboBlocks>, BufferError>) -> Self {
+        Self {
+            stage,
+            descriptor_count: 1,
+            binding_flags: vk::DescriptorBindingFlags::empty(),
+            buffers,
+        }
+    }
+}
+
+impl ShaderEnvironment {
+    fn new(device: &RenderDevice, max_lights: u32, frames_in_flight: usize) -> Self {
+        Self {
+            msaa_samples: 1,
+            max_lights,
+            frames_in_flight: frames_in_flight as u32,
+            descriptor_indexing: device.descriptor_indexing,
+            mesh_shader: device.mesh_shader.is_some(),
+            ray_tracing: device.ray_tracing.is_some(),
+        }
+    }
+
+    // map entries and data, booleans are 32 bit
+    fn specialization(&self) -> (Vec<vk::SpecializationMapEntry>, Vec<u8>) {
+        // in the order of `environment::CONSTANTS`
+        let values: [u32; environment::CONSTANTS.len()] = [
+            self.msaa_samples,
+            self.max_lights,
+            self.frames_in_flight,
+            self.descriptor_indexing as u32,
+            self.mesh_shader as u32,
+            self.ray_tracing as u32,
+        ];
+
+        let entries = (0..values.len() as u32)
+            .map(|i| vk::SpecializationMapEntry {
+                constant_id: environment::FIRST_CONSTANT_ID + i,
+                offset: i * 4,
+                size: 4,
+            })
+            .collect();
+        let data = values
+            .iter()
+            .flat_map(|value| value.to_ne_bytes().to_vec())
+            .collect();
+        (entries, data)
+    }
+}
+
+impl VertexRemap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the attribute at `location` from its own vertex buffer at `binding`.
+    pub fn with_binding(mut self, location: u32, binding: u32) -> Self {
+        self.set(location, VertexSource::PerVertex(binding));
+        self
+    }
+
+    /// Reads the same value for every vertex from the buffer at `binding`.
+    ///
+    /// For attributes present in the shader but not worth storing per vertex,
+    /// the buffer only has to hold one element.
+    pub fn with_constant(mut self, location: u32, binding: u32) -> Self {
+        self.set(location, VertexSource::Constant(binding));
+        self
+    }
+
+    fn set(&mut self, location: u32, source: VertexSource) {
+        self.attributes.retain(|(l, _)| *l != location);
+        self.attributes.push((location, source));
+    }
+
+    fn apply(
+        &self,
+        bindings: Vec<vk::VertexInputBindingDescription>,
+        mut attributes: Vec<vk::VertexInputAttributeDescription>,
+    ) -> (
+        Vec<vk::VertexInputBindingDescription>,
+        Vec<vk::VertexInputAttributeDescription>,
+    ) {
+        let mut remapped: Vec<RemappedBinding> = Vec::new();
+
+        attributes.sort_by_key(|attribute| (attribute.location, attribute.offset));
+        for attribute in attributes.iter_mut() {
+            let (binding, constant) = match self
+                .attributes
+                .iter()
+                .find(|(location, _)| *location == attribute.location)
+            {
+                Some((_, VertexSource::PerVertex(binding))) => (*binding, false),
+                Some((_, VertexSource::Constant(binding))) => (*binding, true),
+                None => continue,
+            };
+
+            if bindings.iter().any(|desc| desc.binding == binding) {
+                warn!(
+                    target: logging::PIPELINE,
+                    "Vertex attribute {} remapped to binding {} which is already used by the vertex input",
+                    attribute.location, binding
+                );
+                continue;
+            }
+
+            let size = match format_size(attribute.format) {
+                Some(size) => size,
+                None => {
+                    warn!(
+                        target: logging::PIPELINE,
+                        "Vertex attribute {} has a format that cannot be remapped: {:?}",
+                        attribute.location, attribute.format
+                    );
+                    continue;
+                }
+            };
+
+            let index = match remapped
+                .iter()
+                .position(|(desc, _, _)| desc.binding == binding)
+            {
+                Some(index) => index,
+                None => {
+                    let desc = vk::VertexInputBindingDescription {
+                        binding,
+                        stride: 0,
+                        input_rate: vk::VertexInputRate::VERTEX,
+                    };
+                    remapped.push((desc, 0, constant));
+                    remapped.len() - 1
+                }
+            };
+
+            let (_, offset, binding_constant) = &mut remapped[index];
+            if *binding_constant != constant {
+                warn!(
+                    target: logging::PIPELINE,
+                    "Vertex binding {} mixes constant and per vertex attributes",
+                    binding
+                );
+                continue;
+            }
+
+            attribute.binding = binding;
+            attribute.offset = *offset;
+            *offset += size;
+        }
+
+        // the interleaved buffer is still bound by the application, keep its stride
+        let bindings = bindings
+            .into_iter()
+            .filter(|desc| {
+                attributes
+                    .iter()
+                    .any(|attribute| attribute.binding == desc.binding)
+            })
+            .chain(remapped.into_iter().map(|(mut desc, size, constant)| {
+                desc.stride = if constant { 0 } else { size };
+                desc
+            }))
+            .collect();
+
+        (bindings, attributes)
+    }
+}
+
+impl PipelineReflection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The vertex input of `V`, for shaders sharing a vertex type with the application.
+    pub fn with_input<V: Vertex>(self) -> Self {
+        self.with_vertex_input(V::binding_desc(), V::attribute_desc())
+    }
+
+    pub fn with_vertex_input(
+        mut self,
+        bindings: Vec<vk::VertexInputBindingDescription>,
+        attributes: Vec<vk::VertexInputAttributeDescription>,
+    ) -> Self {
+        self.vertex_bindings = bindings;
+        self.vertex_attributes = attributes;
+        self
+    }
+
+    /// A `size` byte ```layout(set = 0, binding = 0) uniform``` block.
+    pub fn with_uniform(mut self, size: usize, stage: vk::ShaderStageFlags) -> Self {
+        self.uniform = Some((size, stage));
+        self
+    }
+
+    /// A `size` byte push constant block at offset 0.
+    pub fn with_push_constants(mut self, size: usize, stage: vk::ShaderStageFlags) -> Self {
+        self.push_constants = Some((size, stage));
+        self
+    }
 }
 
 impl PipelineBuilder {
     pub fn new(renderer: &Renderer) -> Self {
+        Self::new_with_config(renderer, &PipelineConfig::default())
+    }
+
+    pub fn new_with_config(renderer: &Renderer, config: &PipelineConfig) -> Self {
+        let image_count = renderer.image_count();
+
+        let set_count = config.frames_in_flight.unwrap_or(image_count);
+        let set_count = if set_count < image_count {
+            warn!(
+                target: logging::PIPELINE,
+                "PipelineConfig::frames_in_flight ({}) is less than the swapchain image count ({})",
+                set_count, image_count
+            );
+            image_count
+        } else {
+            set_count
+        };
+
         Self {
             device: renderer.rdevice.clone(),
             render_pass: renderer.data.read().swapchain_objects.read().render_pass,
-            set_count: renderer.data.read().render_objects.len(),
+            color_attachments: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            set_count,
+            push_constants: None,
+            debug_views: renderer.debug_views(),
+            environment: ShaderEnvironment::new(
+                &renderer.rdevice,
+                renderer.max_lights(),
+                set_count,
+            ),
 
             ubos: HashMap::new(),
+            ubo_array: None,
         }
     }
 
@@ -97,18 +537,80 @@ impl PipelineBuilder {
         set_count: usize,
     ) -> Self {
         Self {
+            environment: ShaderEnvironment::new(&device, DEFAULT_MAX_LIGHTS, set_count),
             device,
             render_pass,
+            color_attachments: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
             set_count,
+            push_constants: None,
+            debug_views: false,
 
             ubos: HashMap::new(),
+            ubo_array: None,
         }
     }
 
+    /// Draw to `target` instead of the swapchain, one color blend state per attachment.
+    pub fn with_render_target(mut self, target: &RenderTarget) -> Self {
+        self.render_pass = target.render_pass();
+        self.color_attachments = target.color_count();
+        self.samples = target.samples();
+        self
+    }
+
+    /// One color blend state per attachment of a `render_pass` given to
+    /// `new_with_device` with more than one color attachment.
+    pub fn with_color_attachments(mut self, color_attachments: usize) -> Self {
+        self.color_attachments = color_attachments;
+        self
+    }
+
+    /// Overrides the `ShaderEnvironment` taken from the renderer, the sample
+    /// count still comes from the render target.
+    pub fn with_environment(mut self, environment: ShaderEnvironment) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// No `DebugView` variants even with `RendererBuilder::with_debug_views`, for fullscreen passes.
+    pub fn without_debug_views(mut self) -> Self {
+        self.debug_views = false;
+        self
+    }
+
     pub fn with_graphics_modules<'a>(
         self,
-        vert_spirv: &'a [u8],
-        frag_spirv: &'a [u8],
+        vert_spirv: &'a [u32],
+        frag_spirv: &'a [u32],
+    ) -> GraphicsPipelineBuilder<'a> {
+        GraphicsPipelineBuilder::<'a> {
+            base: self,
+
+            vert_input_binding: Vec::new(),
+            vert_input_attribute: Vec::new(),
+
+            vert_spirv: Some(vert_spirv),
+            task_spirv: None,
+            mesh_spirv: None,
+            geom_spirv: None,
+            frag_spirv,
+
+            texture_registry: None,
+            resources: Vec::new(),
+            transparent: false,
+            stencil: None,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+        }
+    }
+
+    /// Mesh shading pipeline, requires `RenderDevice::mesh_shader`.
+    ///
+    /// Drawn with `Pipeline::draw_mesh_tasks`, vertex inputs are ignored.
+    pub fn with_mesh_modules<'a>(
+        self,
+        mesh_spirv: &'a [u32],
+        frag_spirv: &'a [u32],
     ) -> GraphicsPipelineBuilder<'a> {
         GraphicsPipelineBuilder::<'a> {
             base: self,
@@ -116,246 +618,573 @@ impl PipelineBuilder {
             vert_input_binding: Vec::new(),
             vert_input_attribute: Vec::new(),
 
-            vert_spirv,
+            vert_spirv: None,
+            task_spirv: None,
+            mesh_spirv: Some(mesh_spirv),
             geom_spirv: None,
             frag_spirv,
+
+            texture_registry: None,
+            resources: Vec::new(),
+            transparent: false,
+            stencil: None,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
         }
     }
 
-    /* TODO: pub fn with_compute_module(self, comp_spirv: &'a [u8]) -> ComputePipelineBuilder<'a, B> {
-        ComputePipelineBuilder::<'a, B> {
+    pub fn with_compute_module<'a>(self, comp_spirv: &'a [u32]) -> ComputePipelineBuilder<'a> {
+        ComputePipelineBuilder::<'a> {
             base: self,
+
             comp_spirv,
+
+            resources: Vec::new(),
         }
-    } */
+    }
 
-    pub fn with_ubo<U: 'static + UBO + Default + Send>(mut self) -> Self {
+    /// Ray generation, miss and closest hit modules, the hit group is triangles only.
+    ///
+    /// Requires `Renderer::ray_tracing`, the pipeline is traced with `Pipeline::trace_rays`.
+    pub fn with_ray_tracing_modules<'a>(
+        self,
+        rgen_spirv: &'a [u32],
+        rmiss_spirv: &'a [u32],
+        rchit_spirv: &'a [u32],
+    ) -> RayTracingPipelineBuilder<'a> {
+        RayTracingPipelineBuilder::<'a> {
+            base: self,
+
+            rgen_spirv,
+            rmiss_spirv,
+            rchit_spirv,
+
+            resources: Vec::new(),
+        }
+    }
+
+    /// One UBO, or every block of a ```uniform(count = N)``` array.
+    ///
+    /// A ```uniform(count = unbounded)``` array gets a single block, see `with_ubo_blocks`.
+    pub fn with_ubo<U: 'static + UBO + Default + Send>(self) -> Self {
+        self.with_ubo_blocks::<U>(U::COUNT.unwrap_or(1))
+    }
+
+    /// `blocks` blocks of a ```uniform(count = ..)``` array, written with
+    /// `Pipeline::write_ubo_block`.
+    ///
+    /// The descriptor set of a ```count = unbounded``` array is allocated
+    /// with `blocks` descriptors. Fixed arrays hold at most their count,
+    /// the blocks after `blocks` are left unbound and must not be read.
+    pub fn with_ubo_blocks<U: 'static + UBO + Default + Send>(mut self, blocks: u32) -> Self {
+        let blocks = U::COUNT.map_or(blocks, |count| blocks.min(count)).max(1);
         let buffers = (0..self.set_count)
-            .map(|_| -> Result<(vk::Buffer, UBStorage), BufferError> {
-                let mut ubo = UniformBuffer::<U>::new_with_device(self.device.clone())?;
-                ubo.write(&U::default())?;
-                Ok((ubo.get(), Arc::new(Mutex::new(ubo))))
+            .map(|_| {
+                (0..blocks)
+                    .map(
+                        |_| -> Result<(vk::DescriptorBufferInfo, UBStorage), BufferError> {
+                            let mut ubo =
+                                UniformBuffer::<U>::new_with_device(self.device.clone(), 1)?;
+                            ubo.write(0, &U::default())?;
+                            Ok((ubo.descriptor(0), Arc::new(Mutex::new(ubo))))
+                        },
+                    )
+                    .collect::<Result<Vec<_>, BufferError>>()
             })
             .collect::<Result<Vec<_>, BufferError>>();
 
-        self.ubos.insert(TypeId::of::<U>(), (U::STAGE, buffers));
+        self.ubos.insert(
+            TypeId::of::<U>(),
+            UboBinding {
+                stage: U::STAGE,
+                descriptor_count: U::COUNT.unwrap_or(blocks),
+                binding_flags: U::BINDING_FLAGS,
+                buffers,
+            },
+        );
 
         self
     }
-}
 
-impl<'a> GraphicsPipelineBuilder<'a> {
-    pub fn with_input<V: Vertex>(mut self) -> Self {
-        self.vert_input_binding = V::binding_desc();
-        self.vert_input_attribute = V::attribute_desc();
+    /// A zeroed `size` byte UBO without a Rust type, written with `Pipeline::write_ubo_bytes`.
+    pub fn with_ubo_bytes(mut self, size: usize, stage: vk::ShaderStageFlags) -> Self {
+        let buffers = (0..self.set_count)
+            .map(|_| -> Result<UboBlocks, BufferError> {
+                let mut ubo =
+                    UniformBuffer::<u8>::new_array_with_device(self.device.clone(), size, 1)?;
+                ubo.write_slice(0, &vec![0; size])?;
+                Ok(vec![(
+                    ubo.descriptor(0),
+                    Arc::new(Mutex::new(ubo)) as UBStorage,
+                )])
+            })
+            .collect::<Result<Vec<_>, BufferError>>();
+
+        self.ubos
+            .insert(TypeId::of::<RawUbo>(), UboBinding::single(stage, buffers));
+
         self
     }
 
-    pub fn with_geometry_module(mut self, geom_spirv: &'a [u8]) -> Self {
-        self.geom_spirv = Some(geom_spirv);
+    /// `capacity` instances of `U` bound with a dynamic offset, one per object.
+    ///
+    /// Written with `Pipeline::write_ubo_slice` and selected with
+    /// `Pipeline::bind_ubo_element`. Replaces an earlier `with_ubo_array`.
+    pub fn with_ubo_array<U: 'static + UBO + Copy + Send>(mut self, capacity: usize) -> Self {
+        if let Some(ubo_array) = self.ubo_array.take() {
+            self.ubos.remove(&ubo_array.type_id);
+        }
+
+        let alignment = self.device.limits.min_uniform_buffer_offset_alignment as usize;
+        let stride = AlignedArray::<U>::new(alignment).stride();
+        let buffers = (0..self.set_count)
+            .map(|_| -> Result<UboBlocks, BufferError> {
+                let ubo = UniformBuffer::<u8>::new_array_with_device(
+                    self.device.clone(),
+                    capacity * stride,
+                    1,
+                )?;
+                Ok(vec![(
+                    ubo.descriptor(0),
+                    Arc::new(Mutex::new(ubo)) as UBStorage,
+                )])
+            })
+            .collect::<Result<Vec<_>, BufferError>>();
+
+        self.ubos
+            .insert(TypeId::of::<U>(), UboBinding::single(U::STAGE, buffers));
+        self.ubo_array = Some(UboArray {
+            type_id: TypeId::of::<U>(),
+            stride: stride as u32,
+            size: mem::size_of::<U>() as u32,
+        });
+
         self
     }
 
-    pub fn with_ubo<U: 'static + UBO + Default + Send>(mut self) -> Self {
-        self.base = self.base.with_ubo::<U>();
-        self
+    /// Push constant block of type `P` at offset 0, written with `Pipeline::push_constants`.
+    pub fn with_push_constants<P: 'static + Copy>(self, stage: vk::ShaderStageFlags) -> Self {
+        self.with_push_constant_bytes(mem::size_of::<P>(), stage)
     }
 
-    pub fn build(self, debug: bool) -> Result<Pipeline, BufferError> {
-        // modules
-        let vert = shader_module(
-            &self.base.device,
-            self.vert_spirv,
-            vk::ShaderStageFlags::VERTEX,
-        );
-        let frag = shader_module(
-            &self.base.device,
-            self.frag_spirv,
-            vk::ShaderStageFlags::FRAGMENT,
+    /// `with_push_constants` without a Rust type, written with `Pipeline::push_constant_bytes`.
+    pub fn with_push_constant_bytes(mut self, size: usize, stage: vk::ShaderStageFlags) -> Self {
+        self.push_constants = Some(
+            vk::PushConstantRange::builder()
+                .stage_flags(stage)
+                .offset(0)
+                .size(size as u32)
+                .build(),
         );
-        // optional module(s)
-        let geom = self.geom_spirv.map(|geom_spirv| {
-            shader_module(
-                &self.base.device,
-                geom_spirv,
-                vk::ShaderStageFlags::GEOMETRY,
-            )
-        });
+        self
+    }
+
+    fn build_pipeline_layout(&self, set_layouts: &[vk::DescriptorSetLayout]) -> vk::PipelineLayout {
+        let push_constant_ranges = self
+            .push_constants
+            .iter()
+            .cloned()
+            .collect::<Vec<vk::PushConstantRange>>();
+
+        self.device
+            .layouts
+            .pipeline_layout(&self.device, set_layouts, &push_constant_ranges[..])
+            .expect("Pipeline layout creation failed")
+    }
+
+    // the environment with the sample count of the render target
+    fn specialization(&self) -> (Vec<vk::SpecializationMapEntry>, Vec<u8>) {
+        ShaderEnvironment {
+            msaa_samples: self.samples.as_raw(),
+            ..self.environment
+        }
+        .specialization()
+    }
 
-        let mut stages = vec![vert.1, frag.1];
-        geom.map(|geom| stages.push(geom.1));
+    // layout and one set per image from the device's descriptor pools
+    fn build_descriptors(&mut self) -> Result<Descriptors, BufferError> {
+        let ubo_array = self.ubo_array;
+        let descriptor_type = |id: &TypeId| match ubo_array {
+            Some(ubo_array) if ubo_array.type_id == *id => {
+                vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC
+            }
+            _ => vk::DescriptorType::UNIFORM_BUFFER,
+        };
 
         let bindings = self
-            .base
             .ubos
             .iter()
-            .map(|(_, (stage, _))| {
+            .map(|(id, ubo)| {
                 vk::DescriptorSetLayoutBinding::builder()
                     .binding(0)
-                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                    .descriptor_count(1)
-                    .stage_flags(stage.clone())
+                    .descriptor_type(descriptor_type(id))
+                    .descriptor_count(ubo.descriptor_count)
+                    .stage_flags(ubo.stage)
                     .build()
             })
             .collect::<Vec<_>>();
+        let binding_flags = self
+            .ubos
+            .values()
+            .map(|ubo| ubo.binding_flags)
+            .collect::<Vec<_>>();
+        let variable_count = self
+            .ubos
+            .values()
+            .find(|ubo| {
+                ubo.binding_flags
+                    .contains(vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT)
+            })
+            .map(|ubo| ubo.descriptor_count);
 
-        let desc_set_layout_info =
-            vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings[..]);
-
-        let desc_set_layout = [unsafe {
-            self.base
+        let desc_set_layout =
+            [self
                 .device
-                .create_descriptor_set_layout(&desc_set_layout_info, None)
-        }
-        .expect("Descriptor set layout creation failed")];
+                .layouts
+                .set_layout(&self.device, &bindings[..], &binding_flags[..])?];
 
         let descriptor_sizes: Vec<vk::DescriptorPoolSize> = self
-            .base
             .ubos
             .iter()
-            .map(|_| {
+            .map(|(id, ubo)| {
                 vk::DescriptorPoolSize::builder()
-                    .descriptor_count(1)
-                    .ty(vk::DescriptorType::UNIFORM_BUFFER)
+                    .descriptor_count(ubo.descriptor_count)
+                    .ty(descriptor_type(id))
                     .build()
             })
             .collect();
 
-        let (desc_pool, desc_sets) = if descriptor_sizes.len() > 0 {
-            let desc_pool_info = vk::DescriptorPoolCreateInfo::builder()
-                .max_sets(self.base.set_count as u32)
-                .pool_sizes(&descriptor_sizes);
-
-            let desc_pool = unsafe {
-                self.base
-                    .device
-                    .create_descriptor_pool(&desc_pool_info, None)
-            }
-            .expect("Descriptor pool creation failed");
-
-            let mut ubos = self
-                .base
-                .ubos
+        let (desc_allocs, desc_sets) = if descriptor_sizes.len() > 0 {
+            let mut ubos = mem::take(&mut self.ubos)
                 .into_iter()
-                .map(|(key, (stage, ubos))| match ubos {
-                    Ok(ubos) => Ok((key, (stage, ubos))),
+                .map(|(key, ubo)| match ubo.buffers {
+                    Ok(buffers) => Ok((key, buffers)),
                     Err(e) => Err(e),
                 })
                 .collect::<Result<HashMap<_, _>, BufferError>>()?;
-            let device = &self.base.device;
-            let desc_sets = (0..self.base.set_count)
-                .into_iter()
+            let device = &self.device;
+            let desc_allocs = (0..self.set_count)
                 .map(|_| {
-                    let allocate_info = vk::DescriptorSetAllocateInfo::builder()
-                        .descriptor_pool(desc_pool)
-                        .set_layouts(&desc_set_layout);
-                    let desc_set =
-                        unsafe { device.allocate_descriptor_sets(&allocate_info) }.unwrap()[0];
+                    device.descriptor_pools.alloc(
+                        device,
+                        desc_set_layout[0],
+                        &descriptor_sizes,
+                        variable_count,
+                    )
+                })
+                .collect::<Result<Vec<_>, BufferError>>()?;
+            let desc_sets = desc_allocs
+                .iter()
+                .map(|desc_alloc| {
+                    let desc_set = desc_alloc.set;
                     let ubos = ubos
                         .iter_mut()
-                        .map(|(id, (_, ubos))| (id.clone(), ubos.remove(0)))
-                        .collect::<HashMap<TypeId, (vk::Buffer, UBStorage)>>();
-
-                    let first_ubo = ubos.iter().next().unwrap().1 .0;
-
-                    let buffer_info = [vk::DescriptorBufferInfo::builder()
-                        .offset(0)
-                        .range(vk::WHOLE_SIZE)
-                        .buffer(first_ubo)
-                        .build()];
+                        .map(|(id, buffers)| (id.clone(), buffers.remove(0)))
+                        .collect::<HashMap<TypeId, UboBlocks>>();
+
+                    let (first_id, first_blocks) = ubos.iter().next().unwrap();
+
+                    // dynamic offsets select one element, arrays get one descriptor per block
+                    let buffer_info = first_blocks
+                        .iter()
+                        .map(|(block, _)| {
+                            let range = match ubo_array {
+                                Some(ubo_array) if ubo_array.type_id == *first_id => {
+                                    ubo_array.size as u64
+                                }
+                                _ => block.range,
+                            };
+                            vk::DescriptorBufferInfo { range, ..*block }
+                        })
+                        .collect::<Vec<_>>();
 
                     let write_set = [vk::WriteDescriptorSet::builder()
                         .dst_array_element(0)
                         .dst_binding(0)
                         .dst_set(desc_set)
-                        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                        .descriptor_type(descriptor_type(first_id))
                         .buffer_info(&buffer_info)
                         .build()];
                     unsafe { device.update_descriptor_sets(&write_set, &[]) };
 
                     let ubos = ubos
                         .into_iter()
-                        .map(|(id, (_, ubos))| (id, ubos))
-                        .collect::<HashMap<TypeId, UBStorage>>();
+                        .map(|(id, blocks)| (id, blocks.into_iter().map(|(_, ubo)| ubo).collect()))
+                        .collect::<HashMap<TypeId, Vec<UBStorage>>>();
 
                     (desc_set, ubos)
                 })
                 .collect();
 
-            (Some(desc_pool), desc_sets)
+            (desc_allocs, desc_sets)
         } else {
-            (None, Vec::new())
+            (Vec::new(), Vec::new())
         };
 
-        let pipeline_layout_info =
-            vk::PipelineLayoutCreateInfo::builder().set_layouts(&desc_set_layout);
+        Ok((desc_set_layout[0], desc_allocs, desc_sets))
+    }
+}
 
-        let pipeline_layout = unsafe {
-            self.base
-                .device
-                .create_pipeline_layout(&pipeline_layout_info, None)
-        }
-        .expect("Pipeline layout creation failed");
+impl<'a> GraphicsPipelineBuilder<'a> {
+    pub fn with_input<V: Vertex>(mut self) -> Self {
+        self.vert_input_binding = V::binding_desc();
+        self.vert_input_attribute = V::attribute_desc();
+        self
+    }
 
-        let vertex_state = vk::PipelineVertexInputStateCreateInfo::builder()
-            .vertex_binding_descriptions(&self.vert_input_binding[..])
-            .vertex_attribute_descriptions(&self.vert_input_attribute[..]);
+    /// `with_input` without a `Vertex` type.
+    pub fn with_input_desc(
+        mut self,
+        bindings: Vec<vk::VertexInputBindingDescription>,
+        attributes: Vec<vk::VertexInputAttributeDescription>,
+    ) -> Self {
+        self.vert_input_binding = bindings;
+        self.vert_input_attribute = attributes;
+        self
+    }
 
-        let vertex_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
-            .primitive_restart_enable(false);
+    /// `with_input` with some attributes read from other vertex buffers.
+    ///
+    /// Bind the buffers with `vkCmdBindVertexBuffers` at the bindings `remap` uses.
+    pub fn with_input_remap<V: Vertex>(mut self, remap: &VertexRemap) -> Self {
+        let (bindings, attributes) = remap.apply(V::binding_desc(), V::attribute_desc());
+        self.vert_input_binding = bindings;
+        self.vert_input_attribute = attributes;
+        self
+    }
 
-        let rasterizer_state = vk::PipelineRasterizationStateCreateInfo::builder()
-            .polygon_mode(
-                vk::PolygonMode::FILL, /* if debug {
-                                           vk::PolygonMode::LINE
-                                       } else {
-                                           vk::PolygonMode::FILL
-                                       } */
-            )
-            .cull_mode(if debug {
-                vk::CullModeFlags::NONE
-            } else {
-                vk::CullModeFlags::BACK
-            })
-            .front_face(vk::FrontFace::CLOCKWISE)
-            .depth_clamp_enable(false)
-            .depth_bias_enable(false)
-            .depth_bias_constant_factor(0.0)
-            .depth_bias_clamp(0.0)
-            .depth_bias_slope_factor(0.0)
-            .rasterizer_discard_enable(false)
-            .line_width(1.0);
+    pub fn with_geometry_module(mut self, geom_spirv: &'a [u32]) -> Self {
+        self.geom_spirv = Some(geom_spirv);
+        self
+    }
 
-        let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
-            .sample_shading_enable(false)
-            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
-            .min_sample_shading(1.0)
-            .alpha_to_coverage_enable(false)
+    pub fn with_task_module(mut self, task_spirv: &'a [u32]) -> Self {
+        self.task_spirv = Some(task_spirv);
+        self
+    }
+
+    pub fn with_ubo<U: 'static + UBO + Default + Send>(mut self) -> Self {
+        self.base = self.base.with_ubo::<U>();
+        self
+    }
+
+    /// See `PipelineBuilder::with_ubo_array`.
+    pub fn with_ubo_array<U: 'static + UBO + Copy + Send>(mut self, capacity: usize) -> Self {
+        self.base = self.base.with_ubo_array::<U>(capacity);
+        self
+    }
+
+    /// See `PipelineBuilder::with_ubo_bytes`.
+    pub fn with_ubo_bytes(mut self, size: usize, stage: vk::ShaderStageFlags) -> Self {
+        self.base = self.base.with_ubo_bytes(size, stage);
+        self
+    }
+
+    /// Binds the registry's texture array as descriptor set 1.
+    pub fn with_texture_registry(mut self, texture_registry: Arc<TextureRegistry>) -> Self {
+        self.texture_registry = Some(texture_registry);
+        self
+    }
+
+    /// Binds `buffer` as ```layout(set = S, binding = N) buffer```.
+    ///
+    /// S is 2 with a texture registry and 1 without, N is the call order
+    /// shared with `with_sampled_image`. The buffer must outlive the pipeline.
+    pub fn with_storage_buffer(mut self, buffer: &dyn Buffer) -> Self {
+        self.resources.push(Resource::StorageBuffer(buffer.get()));
+        self
+    }
+
+    /// Binds `buffer` as ```layout(set = S, binding = N) uniform```, a UBO
+    /// shared with other pipelines, see `with_storage_buffer`.
+    pub fn with_uniform_buffer(mut self, buffer: &dyn Buffer) -> Self {
+        self.resources.push(Resource::UniformBuffer(buffer.get()));
+        self
+    }
+
+    /// Binds `view` as ```layout(set = S, binding = N) uniform sampler2D```,
+    /// see `with_storage_buffer`.
+    pub fn with_sampled_image(mut self, view: vk::ImageView, sampler: vk::Sampler) -> Self {
+        self.resources.push(Resource::SampledImage(view, sampler));
+        self
+    }
+
+    /// Binds `images` as ```layout(set = S, binding = N) uniform sampler2D textures[]```
+    /// with room for `capacity` textures, see `with_storage_buffer`.
+    ///
+    /// Requires `RenderDevice::descriptor_indexing`. The array is partially
+    /// bound, elements after `images` must not be read. As the last resource
+    /// its set is allocated with a variable count of `capacity`.
+    pub fn with_sampled_images(
+        mut self,
+        images: &[(vk::ImageView, vk::Sampler)],
+        capacity: u32,
+    ) -> Self {
+        self.resources.push(Resource::SampledImageArray(
+            images.to_vec(),
+            capacity.max(images.len() as u32).max(1),
+        ));
+        self
+    }
+
+    /// Binds `view` as ```layout(set = S, binding = N) uniform samplerBuffer```,
+    /// see `with_storage_buffer`.
+    pub fn with_uniform_texel_buffer(mut self, view: vk::BufferView) -> Self {
+        self.resources.push(Resource::UniformTexelBuffer(view));
+        self
+    }
+
+    /// Binds `view` as ```layout(set = S, binding = N) uniform imageBuffer```,
+    /// see `with_storage_buffer`.
+    pub fn with_storage_texel_buffer(mut self, view: vk::BufferView) -> Self {
+        self.resources.push(Resource::StorageTexelBuffer(view));
+        self
+    }
+
+    pub fn with_push_constants<P: 'static + Copy>(mut self, stage: vk::ShaderStageFlags) -> Self {
+        self.base = self.base.with_push_constants::<P>(stage);
+        self
+    }
+
+    /// See `PipelineBuilder::with_push_constant_bytes`.
+    pub fn with_push_constant_bytes(mut self, size: usize, stage: vk::ShaderStageFlags) -> Self {
+        self.base = self.base.with_push_constant_bytes(size, stage);
+        self
+    }
+
+    /// Alpha blending without depth writes.
+    ///
+    /// Draws with this pipeline belong after `DrawScope::transparent`.
+    pub fn with_transparency(mut self) -> Self {
+        self.transparent = true;
+        self
+    }
+
+    /// Stencil test and ops for both faces, requires `RendererBuilder::with_stencil`.
+    ///
+    /// The reference value is dynamic and set with `rri.set_stencil_reference`.
+    pub fn with_stencil(mut self, stencil: vk::StencilOpState) -> Self {
+        self.stencil = Some(stencil);
+        self
+    }
+
+    /// Triangle list by default. Point topologies need `gl_PointSize` in the vertex shader.
+    pub fn with_topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn build(mut self, debug: bool) -> Result<Pipeline, BufferError> {
+        if self.mesh_spirv.is_some() && self.base.device.mesh_shader.is_none() {
+            return Err(BufferError::UnsupportedFeature("mesh shaders"));
+        }
+
+        // modules
+        let (map_entries, data) = self.base.specialization();
+        let specialization = vk::SpecializationInfo::builder()
+            .map_entries(&map_entries)
+            .data(&data);
+        let modules = [
+            (self.vert_spirv, vk::ShaderStageFlags::VERTEX),
+            (self.task_spirv, vk::ShaderStageFlags::TASK_NV),
+            (self.mesh_spirv, vk::ShaderStageFlags::MESH_NV),
+            (self.geom_spirv, vk::ShaderStageFlags::GEOMETRY),
+            (Some(self.frag_spirv), vk::ShaderStageFlags::FRAGMENT),
+        ]
+        .iter()
+        .filter_map(|(spirv, stage)| {
+            spirv.map(|spirv| shader_module(&self.base.device, spirv, *stage, &specialization))
+        })
+        .collect::<Vec<_>>();
+
+        let stages = modules.iter().map(|(_, stage)| *stage).collect::<Vec<_>>();
+
+        let (desc_set_layout, desc_allocs, desc_sets) = self.base.build_descriptors()?;
+        let desc_set_layout = [desc_set_layout];
+
+        let resources = if self.resources.is_empty() {
+            None
+        } else {
+            Some(resource_set(
+                &self.base.device,
+                &self.resources,
+                vk::ShaderStageFlags::ALL_GRAPHICS,
+            )?)
+        };
+
+        let mut set_layouts = desc_set_layout.to_vec();
+        if let Some(texture_registry) = self.texture_registry.as_ref() {
+            set_layouts.push(texture_registry.layout());
+        }
+        if let Some(resources) = resources.as_ref() {
+            set_layouts.push(resources.desc_set_layout);
+        }
+        let pipeline_layout = self.base.build_pipeline_layout(&set_layouts[..]);
+
+        let vertex_state = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(&self.vert_input_binding[..])
+            .vertex_attribute_descriptions(&self.vert_input_attribute[..]);
+
+        let vertex_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(self.topology)
+            .primitive_restart_enable(false);
+
+        let rasterizer_state = vk::PipelineRasterizationStateCreateInfo::builder()
+            .polygon_mode(
+                vk::PolygonMode::FILL, /* if debug {
+                                           vk::PolygonMode::LINE
+                                       } else {
+                                           vk::PolygonMode::FILL
+                                       } */
+            )
+            .cull_mode(if debug {
+                vk::CullModeFlags::NONE
+            } else {
+                vk::CullModeFlags::BACK
+            })
+            .front_face(vk::FrontFace::CLOCKWISE)
+            .depth_clamp_enable(false)
+            .depth_bias_enable(false)
+            .depth_bias_constant_factor(0.0)
+            .depth_bias_clamp(0.0)
+            .depth_bias_slope_factor(0.0)
+            .rasterizer_discard_enable(false)
+            .line_width(1.0)
+            .build();
+
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+            .sample_shading_enable(false)
+            .rasterization_samples(self.base.samples)
+            .min_sample_shading(1.0)
+            .alpha_to_coverage_enable(false)
             .alpha_to_one_enable(false);
 
         let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
-            .stencil_test_enable(false)
+            .stencil_test_enable(self.stencil.is_some())
+            .front(self.stencil.unwrap_or_default())
+            .back(self.stencil.unwrap_or_default())
             .depth_test_enable(true)
-            .depth_write_enable(true)
+            .depth_write_enable(!self.transparent)
             .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
             .depth_bounds_test_enable(false)
             .min_depth_bounds(0.0)
-            .max_depth_bounds(1.0);
+            .max_depth_bounds(1.0)
+            .build();
 
-        let color_blend_attachment = [vk::PipelineColorBlendAttachmentState::builder()
+        let color_blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
             .color_write_mask(vk::ColorComponentFlags::all())
-            .blend_enable(false)
-            .src_color_blend_factor(vk::BlendFactor::ONE)
-            .dst_color_blend_factor(vk::BlendFactor::ZERO)
+            .blend_enable(self.transparent)
+            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
             .color_blend_op(vk::BlendOp::ADD)
             .src_alpha_blend_factor(vk::BlendFactor::ONE)
             .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
             .alpha_blend_op(vk::BlendOp::ADD)
-            .build()];
+            .build();
+        let color_blend_attachments = vec![color_blend_attachment; self.base.color_attachments];
 
-        let color_blend_state =
-            vk::PipelineColorBlendStateCreateInfo::builder().attachments(&color_blend_attachment);
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(&color_blend_attachments[..])
+            .build();
 
         let tmp_viewport = [vk::Viewport::builder()
             .width(32.0)
@@ -376,59 +1205,538 @@ impl<'a> GraphicsPipelineBuilder<'a> {
             .viewports(&tmp_viewport)
             .scissors(&tmp_scissors);
 
-        let viewport_dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_states = [
+            vk::DynamicState::VIEWPORT,
+            vk::DynamicState::SCISSOR,
+            vk::DynamicState::STENCIL_REFERENCE,
+        ];
+        let dynamic_states = if self.stencil.is_some() {
+            &dynamic_states[..]
+        } else {
+            &dynamic_states[..2]
+        };
         let dynamic_state =
-            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&viewport_dynamic_state);
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(dynamic_states);
+
+        let pipeline_info =
+            |rasterizer_state: &vk::PipelineRasterizationStateCreateInfo,
+             depth_stencil_state: &vk::PipelineDepthStencilStateCreateInfo,
+             color_blend_state: &vk::PipelineColorBlendStateCreateInfo,
+             stages: &[vk::PipelineShaderStageCreateInfo]| {
+                vk::GraphicsPipelineCreateInfo::builder()
+                    .subpass(0)
+                    .render_pass(self.base.render_pass)
+                    .layout(pipeline_layout)
+                    .vertex_input_state(&vertex_state)
+                    .input_assembly_state(&vertex_assembly_state)
+                    .rasterization_state(rasterizer_state)
+                    .multisample_state(&multisample_state)
+                    .depth_stencil_state(depth_stencil_state)
+                    .color_blend_state(color_blend_state)
+                    .stages(stages)
+                    .viewport_state(&viewport_state)
+                    .dynamic_state(&dynamic_state)
+                    .build()
+            };
+        let mut pipeline_infos = vec![pipeline_info(
+            &rasterizer_state,
+            &depth_stencil_state,
+            &color_blend_state,
+            &stages[..],
+        )];
+
+        // debug views replace the fragment shader or some fixed function state
+        let debug_modules = if self.base.debug_views {
+            vec![
+                shader_module(
+                    &self.base.device,
+                    overdraw_shader::FRAG_SPIRV_WORDS,
+                    vk::ShaderStageFlags::FRAGMENT,
+                    &specialization,
+                ),
+                shader_module(
+                    &self.base.device,
+                    normals_shader::FRAG_SPIRV_WORDS,
+                    vk::ShaderStageFlags::FRAGMENT,
+                    &specialization,
+                ),
+            ]
+        } else {
+            Vec::new()
+        };
+        let debug_stages = debug_modules
+            .iter()
+            .map(|(_, debug_stage)| {
+                stages
+                    .iter()
+                    .map(|stage| {
+                        if stage.stage == vk::ShaderStageFlags::FRAGMENT {
+                            *debug_stage
+                        } else {
+                            *stage
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let wireframe_rasterizer_state = vk::PipelineRasterizationStateCreateInfo {
+            polygon_mode: vk::PolygonMode::LINE,
+            ..rasterizer_state
+        };
+        let overdraw_depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo {
+            depth_test_enable: vk::FALSE,
+            depth_write_enable: vk::FALSE,
+            ..depth_stencil_state
+        };
+        let overdraw_blend_attachments = vec![
+            vk::PipelineColorBlendAttachmentState {
+                blend_enable: vk::TRUE,
+                src_color_blend_factor: vk::BlendFactor::ONE,
+                dst_color_blend_factor: vk::BlendFactor::ONE,
+                src_alpha_blend_factor: vk::BlendFactor::ONE,
+                dst_alpha_blend_factor: vk::BlendFactor::ONE,
+                ..color_blend_attachment
+            };
+            self.base.color_attachments
+        ];
+        let overdraw_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(&overdraw_blend_attachments[..])
+            .build();
+
+        let mut debug_views = Vec::new();
+        if self.base.debug_views {
+            if self.base.device.fill_mode_non_solid {
+                debug_views.push(DebugView::Wireframe);
+                pipeline_infos.push(pipeline_info(
+                    &wireframe_rasterizer_state,
+                    &depth_stencil_state,
+                    &color_blend_state,
+                    &stages[..],
+                ));
+            }
+
+            debug_views.push(DebugView::Overdraw);
+            pipeline_infos.push(pipeline_info(
+                &rasterizer_state,
+                &overdraw_depth_stencil_state,
+                &overdraw_blend_state,
+                &debug_stages[0][..],
+            ));
+
+            debug_views.push(DebugView::Normals);
+            pipeline_infos.push(pipeline_info(
+                &rasterizer_state,
+                &depth_stencil_state,
+                &color_blend_state,
+                &debug_stages[1][..],
+            ));
+        }
+
+        let pipelines = unsafe {
+            self.base.device.create_graphics_pipelines(
+                vk::PipelineCache::null(),
+                &pipeline_infos[..],
+                None,
+            )
+        };
+
+        unsafe {
+            for (module, _) in modules.into_iter().chain(debug_modules) {
+                self.base.device.destroy_shader_module(module, None);
+            }
+        }
+
+        let pipelines = pipelines.expect("Graphics pipeline creation failed");
+        let pipeline = pipelines[0];
+        let debug_pipelines = debug_views
+            .into_iter()
+            .zip(pipelines[1..].iter().cloned())
+            .collect();
+
+        Ok(Pipeline {
+            device: self.base.device,
+            sort_id: next_sort_id(),
+            desc_allocs,
+            desc_sets,
+            desc_set_layout: desc_set_layout[0],
+            texture_registry: self.texture_registry,
+            resources,
+            push_constants: self.base.push_constants,
+            ubo_array: self.base.ubo_array,
+            sbt: None,
+            bind_point: vk::PipelineBindPoint::GRAPHICS,
+            pipeline_layout,
+            pipeline,
+            debug_pipelines,
+        })
+    }
+}
+
+impl<'a> ComputePipelineBuilder<'a> {
+    pub fn with_ubo<U: 'static + UBO + Default + Send>(mut self) -> Self {
+        self.base = self.base.with_ubo::<U>();
+        self
+    }
+
+    pub fn with_push_constants<P: 'static + Copy>(mut self) -> Self {
+        self.base = self
+            .base
+            .with_push_constants::<P>(vk::ShaderStageFlags::COMPUTE);
+        self
+    }
+
+    /// Binds `buffer` as ```layout(set = 1, binding = N) buffer```, N is the call order.
+    ///
+    /// The buffer must outlive the pipeline.
+    pub fn with_storage_buffer(mut self, buffer: &dyn Buffer) -> Self {
+        self.resources.push(Resource::StorageBuffer(buffer.get()));
+        self
+    }
+
+    /// Binds `buffer` as ```layout(set = 1, binding = N) uniform```, N is the call order.
+    ///
+    /// The buffer must outlive the pipeline.
+    pub fn with_uniform_buffer(mut self, buffer: &dyn Buffer) -> Self {
+        self.resources.push(Resource::UniformBuffer(buffer.get()));
+        self
+    }
+
+    /// Binds `view` as ```uniform sampler2D```, expected in `SHADER_READ_ONLY_OPTIMAL`.
+    pub fn with_sampled_image(mut self, view: vk::ImageView, sampler: vk::Sampler) -> Self {
+        self.resources.push(Resource::SampledImage(view, sampler));
+        self
+    }
 
-        let pipeline_info = [vk::GraphicsPipelineCreateInfo::builder()
-            .subpass(0)
-            .render_pass(self.base.render_pass)
+    /// Binds `images` as ```uniform sampler2D textures[]```, see
+    /// `GraphicsPipelineBuilder::with_sampled_images`.
+    pub fn with_sampled_images(
+        mut self,
+        images: &[(vk::ImageView, vk::Sampler)],
+        capacity: u32,
+    ) -> Self {
+        self.resources.push(Resource::SampledImageArray(
+            images.to_vec(),
+            capacity.max(images.len() as u32).max(1),
+        ));
+        self
+    }
+
+    /// Binds `view` as ```uniform image2D```, expected in `GENERAL`.
+    pub fn with_storage_image(mut self, view: vk::ImageView) -> Self {
+        self.resources.push(Resource::StorageImage(view));
+        self
+    }
+
+    /// Binds `view` as ```uniform samplerBuffer```.
+    pub fn with_uniform_texel_buffer(mut self, view: vk::BufferView) -> Self {
+        self.resources.push(Resource::UniformTexelBuffer(view));
+        self
+    }
+
+    /// Binds `view` as ```uniform imageBuffer```.
+    pub fn with_storage_texel_buffer(mut self, view: vk::BufferView) -> Self {
+        self.resources.push(Resource::StorageTexelBuffer(view));
+        self
+    }
+
+    pub fn build(mut self) -> Result<Pipeline, BufferError> {
+        let (desc_set_layout, desc_allocs, desc_sets) = self.base.build_descriptors()?;
+
+        let resources = if self.resources.is_empty() {
+            None
+        } else {
+            Some(resource_set(
+                &self.base.device,
+                &self.resources,
+                vk::ShaderStageFlags::COMPUTE,
+            )?)
+        };
+
+        let mut set_layouts = vec![desc_set_layout];
+        if let Some(resources) = resources.as_ref() {
+            set_layouts.push(resources.desc_set_layout);
+        }
+        let pipeline_layout = self.base.build_pipeline_layout(&set_layouts[..]);
+
+        let (map_entries, data) = self.base.specialization();
+        let specialization = vk::SpecializationInfo::builder()
+            .map_entries(&map_entries)
+            .data(&data);
+        let comp = shader_module(
+            &self.base.device,
+            self.comp_spirv,
+            vk::ShaderStageFlags::COMPUTE,
+            &specialization,
+        );
+
+        let pipeline_info = [vk::ComputePipelineCreateInfo::builder()
+            .stage(comp.1)
             .layout(pipeline_layout)
-            .vertex_input_state(&vertex_state)
-            .input_assembly_state(&vertex_assembly_state)
-            .rasterization_state(&rasterizer_state)
-            .multisample_state(&multisample_state)
-            .depth_stencil_state(&depth_stencil_state)
-            .color_blend_state(&color_blend_state)
+            .build()];
+
+        let pipeline = unsafe {
+            self.base.device.create_compute_pipelines(
+                vk::PipelineCache::null(),
+                &pipeline_info,
+                None,
+            )
+        };
+
+        unsafe { self.base.device.destroy_shader_module(comp.0, None) };
+
+        let pipeline = pipeline.expect("Compute pipeline creation failed")[0];
+
+        Ok(Pipeline {
+            device: self.base.device,
+            sort_id: next_sort_id(),
+            desc_allocs,
+            desc_sets,
+            desc_set_layout,
+            texture_registry: None,
+            resources,
+            push_constants: self.base.push_constants,
+            ubo_array: self.base.ubo_array,
+            sbt: None,
+            bind_point: vk::PipelineBindPoint::COMPUTE,
+            pipeline_layout,
+            pipeline,
+            debug_pipelines: Vec::new(),
+        })
+    }
+}
+
+impl<'a> RayTracingPipelineBuilder<'a> {
+    const STAGES: vk::ShaderStageFlags = vk::ShaderStageFlags::from_raw(
+        vk::ShaderStageFlags::RAYGEN_KHR.as_raw()
+            | vk::ShaderStageFlags::MISS_KHR.as_raw()
+            | vk::ShaderStageFlags::CLOSEST_HIT_KHR.as_raw(),
+    );
+
+    pub fn with_ubo<U: 'static + UBO + Default + Send>(mut self) -> Self {
+        self.base = self.base.with_ubo::<U>();
+        self
+    }
+
+    pub fn with_push_constants<P: 'static + Copy>(mut self) -> Self {
+        self.base = self.base.with_push_constants::<P>(Self::STAGES);
+        self
+    }
+
+    /// Binds `buffer` as ```layout(set = 1, binding = N) buffer```, N is the call order.
+    ///
+    /// The buffer must outlive the pipeline.
+    pub fn with_storage_buffer(mut self, buffer: &dyn Buffer) -> Self {
+        self.resources.push(Resource::StorageBuffer(buffer.get()));
+        self
+    }
+
+    /// Binds `buffer` as ```layout(set = 1, binding = N) uniform```, N is the call order.
+    ///
+    /// The buffer must outlive the pipeline.
+    pub fn with_uniform_buffer(mut self, buffer: &dyn Buffer) -> Self {
+        self.resources.push(Resource::UniformBuffer(buffer.get()));
+        self
+    }
+
+    /// Binds `view` as ```uniform sampler2D```, expected in `SHADER_READ_ONLY_OPTIMAL`.
+    pub fn with_sampled_image(mut self, view: vk::ImageView, sampler: vk::Sampler) -> Self {
+        self.resources.push(Resource::SampledImage(view, sampler));
+        self
+    }
+
+    /// Binds `view` as ```uniform image2D```, expected in `GENERAL`.
+    pub fn with_storage_image(mut self, view: vk::ImageView) -> Self {
+        self.resources.push(Resource::StorageImage(view));
+        self
+    }
+
+    /// Binds a top level `structure` as ```uniform accelerationStructureEXT```.
+    ///
+    /// The structure must outlive the pipeline.
+    pub fn with_acceleration_structure(mut self, structure: &AccelerationStructure) -> Self {
+        self.resources
+            .push(Resource::AccelerationStructure(structure.get()));
+        self
+    }
+
+    pub fn build(mut self) -> Result<Pipeline, BufferError> {
+        let device = self.base.device.clone();
+        let ray_tracing = device
+            .ray_tracing
+            .as_ref()
+            .ok_or(BufferError::UnsupportedFeature("ray tracing"))?;
+
+        let (desc_set_layout, desc_allocs, desc_sets) = self.base.build_descriptors()?;
+
+        let resources = if self.resources.is_empty() {
+            None
+        } else {
+            Some(resource_set(&device, &self.resources, Self::STAGES)?)
+        };
+
+        let mut set_layouts = vec![desc_set_layout];
+        if let Some(resources) = resources.as_ref() {
+            set_layouts.push(resources.desc_set_layout);
+        }
+        let pipeline_layout = self.base.build_pipeline_layout(&set_layouts[..]);
+
+        let (map_entries, data) = self.base.specialization();
+        let specialization = vk::SpecializationInfo::builder()
+            .map_entries(&map_entries)
+            .data(&data);
+        let modules = [
+            shader_module(
+                &device,
+                self.rgen_spirv,
+                vk::ShaderStageFlags::RAYGEN_KHR,
+                &specialization,
+            ),
+            shader_module(
+                &device,
+                self.rmiss_spirv,
+                vk::ShaderStageFlags::MISS_KHR,
+                &specialization,
+            ),
+            shader_module(
+                &device,
+                self.rchit_spirv,
+                vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+                &specialization,
+            ),
+        ];
+        let stages = modules.iter().map(|(_, stage)| *stage).collect::<Vec<_>>();
+
+        let group = |ty: vk::RayTracingShaderGroupTypeKHR, general: u32, closest_hit: u32| {
+            vk::RayTracingShaderGroupCreateInfoKHR::builder()
+                .ty(ty)
+                .general_shader(general)
+                .closest_hit_shader(closest_hit)
+                .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                .intersection_shader(vk::SHADER_UNUSED_KHR)
+                .build()
+        };
+        let groups = [
+            group(
+                vk::RayTracingShaderGroupTypeKHR::GENERAL,
+                0,
+                vk::SHADER_UNUSED_KHR,
+            ),
+            group(
+                vk::RayTracingShaderGroupTypeKHR::GENERAL,
+                1,
+                vk::SHADER_UNUSED_KHR,
+            ),
+            group(
+                vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP,
+                vk::SHADER_UNUSED_KHR,
+                2,
+            ),
+        ];
+
+        let pipeline_info = [vk::RayTracingPipelineCreateInfoKHR::builder()
             .stages(&stages[..])
-            .viewport_state(&viewport_state)
-            .dynamic_state(&dynamic_state)
+            .groups(&groups)
+            .max_pipeline_ray_recursion_depth(1)
+            .layout(pipeline_layout)
             .build()];
 
         let pipeline = unsafe {
-            self.base.device.create_graphics_pipelines(
+            ray_tracing.pipeline.create_ray_tracing_pipelines(
+                vk::DeferredOperationKHR::null(),
                 vk::PipelineCache::null(),
                 &pipeline_info,
                 None,
             )
         };
 
-        unsafe {
-            let device = &self.base.device;
-            device.destroy_shader_module(frag.0, None);
-            geom.map(|geom| device.destroy_shader_module(geom.0, None));
-            device.destroy_shader_module(vert.0, None);
+        for (module, _) in modules.iter() {
+            unsafe { device.destroy_shader_module(*module, None) };
         }
 
-        let pipeline = pipeline.expect("Graphics pipeline creation failed")[0];
+        let pipeline = match pipeline {
+            Ok(pipelines) => pipelines[0],
+            Err(err) => {
+                error!(
+                    target: logging::PIPELINE,
+                    "Ray tracing pipeline creation failed: {:?}", err
+                );
+                unsafe { device.destroy_pipeline_layout(pipeline_layout, None) };
+                return Err(BufferError::OutOfMemory);
+            }
+        };
+
+        let sbt = shader_binding_table(&device, pipeline, groups.len() as u32)?;
 
         Ok(Pipeline {
             device: self.base.device,
-            desc_pool,
+            sort_id: next_sort_id(),
+            desc_allocs,
             desc_sets,
-            desc_set_layout: desc_set_layout[0],
+            desc_set_layout,
+            texture_registry: None,
+            resources,
+            push_constants: self.base.push_constants,
+            ubo_array: self.base.ubo_array,
+            sbt: Some(sbt),
+            bind_point: vk::PipelineBindPoint::RAY_TRACING_KHR,
             pipeline_layout,
             pipeline,
+            debug_pipelines: Vec::new(),
         })
     }
 }
 
 impl Pipeline {
+    /// A graphics pipeline from SPIR-V loaded at runtime, for ex. from mods
+    /// or downloaded shaders, without `pipeline!`.
+    ///
+    /// Built with a default `PipelineBuilder` like the `build` function
+    /// `pipeline!` generates. Use `PipelineBuilder` with the same words for
+    /// storage buffers, images or the texture registry. The modules are only
+    /// checked for the SPIR-V magic number, an invalid module or one that does
+    /// not match `layout` is a Vulkan validation error.
+    pub fn from_spirv(
+        renderer: &Renderer,
+        vert: &[u32],
+        frag: &[u32],
+        layout: &PipelineReflection,
+    ) -> Result<Self, BufferError> {
+        if [vert, frag]
+            .iter()
+            .any(|words| words.first() != Some(&SPIRV_MAGIC))
+        {
+            return Err(BufferError::InvalidSpirv);
+        }
+
+        let mut builder = PipelineBuilder::new(renderer);
+        if let Some((size, stage)) = layout.uniform {
+            builder = builder.with_ubo_bytes(size, stage);
+        }
+        if let Some((size, stage)) = layout.push_constants {
+            builder = builder.with_push_constant_bytes(size, stage);
+        }
+
+        builder
+            .with_graphics_modules(vert, frag)
+            .with_input_desc(
+                layout.vertex_bindings.clone(),
+                layout.vertex_attributes.clone(),
+            )
+            .build(false)
+    }
+
+    /// Identifies the pipeline in a `DrawKey`. Ids run out after 65535
+    /// pipelines, every later pipeline shares the last id.
+    pub fn sort_id(&self) -> u16 {
+        self.sort_id
+    }
+
     pub unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
         let mut updates = false;
 
         if let Some((_, ubos)) = self.desc_sets.get(uri.image_index) {
-            for (_, ubo) in ubos {
+            for ubo in ubos.values().flatten() {
                 let ubo_lock = ubo.lock();
                 updates = updates || ubo_lock.update_t(uri);
             }
@@ -437,43 +1745,252 @@ impl Pipeline {
         updates
     }
 
+    /// Binds the `rri.debug_view()` variant if this pipeline has one.
     pub unsafe fn bind(&self, rri: &RenderRecordInfo) {
-        if rri.debug_calls {
-            debug!("cmd_bind_pipeline");
-        }
+        let pipeline = self
+            .debug_pipelines
+            .iter()
+            .find(|(view, _)| *view == rri.debug_view)
+            .map_or(self.pipeline, |(_, pipeline)| *pipeline);
 
-        self.device.cmd_bind_pipeline(
+        self.bind_raw(
             rri.command_buffer,
-            vk::PipelineBindPoint::GRAPHICS,
-            self.pipeline,
+            rri.image_index,
+            rri.debug_calls,
+            pipeline,
         );
+    }
 
-        if let Some((desc_set, _)) = self.desc_sets.get(rri.image_index) {
-            if rri.debug_calls {
-                debug!("cmd_bind_descriptor_sets");
+    /// Binds a compute or ray tracing pipeline in the update command buffer,
+    /// or a graphics pipeline for a pass recorded there.
+    pub unsafe fn bind_compute(&self, uri: &UpdateRecordInfo) {
+        self.bind_raw(uri.command_buffer, uri.image_index, false, self.pipeline);
+    }
+
+    unsafe fn bind_raw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        debug_calls: bool,
+        pipeline: vk::Pipeline,
+    ) {
+        if debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_bind_pipeline");
+        }
+
+        self.device
+            .cmd_bind_pipeline(command_buffer, self.bind_point, pipeline);
+
+        if let Some((desc_set, _)) = self.desc_sets.get(image_index) {
+            if debug_calls {
+                debug!(target: logging::COMMANDS, "cmd_bind_descriptor_sets");
             }
 
+            // the first element until `bind_ubo_element`
+            let dynamic_offsets = [0];
+            let dynamic_offsets = if self.ubo_array.is_some() {
+                &dynamic_offsets[..]
+            } else {
+                &[]
+            };
+
             let desc_set = [*desc_set];
             self.device.cmd_bind_descriptor_sets(
-                rri.command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
+                command_buffer,
+                self.bind_point,
                 self.pipeline_layout,
                 0,
                 &desc_set,
+                dynamic_offsets,
+            );
+        }
+
+        // texture registry and resources from set 1 onwards
+        let desc_sets = self
+            .texture_registry
+            .as_ref()
+            .map(|texture_registry| texture_registry.set())
+            .into_iter()
+            .chain(self.resources.as_ref().map(|resources| resources.desc_set))
+            .collect::<Vec<_>>();
+        if !desc_sets.is_empty() {
+            if debug_calls {
+                debug!(target: logging::COMMANDS, "cmd_bind_descriptor_sets");
+            }
+
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                self.bind_point,
+                self.pipeline_layout,
+                1,
+                &desc_sets[..],
                 &[],
             );
         }
     }
 
+    /// Selects element `index` of the `with_ubo_array` UBO for the next draws.
+    pub unsafe fn bind_ubo_element(&self, rri: &RenderRecordInfo, index: usize) {
+        let ubo_array = self
+            .ubo_array
+            .expect_log("Pipeline was not built with_ubo_array");
+
+        if let Some((desc_set, _)) = self.desc_sets.get(rri.image_index) {
+            if rri.debug_calls {
+                debug!(target: logging::COMMANDS, "cmd_bind_descriptor_sets");
+            }
+
+            self.device.cmd_bind_descriptor_sets(
+                rri.command_buffer,
+                self.bind_point,
+                self.pipeline_layout,
+                0,
+                &[*desc_set],
+                &[index as u32 * ubo_array.stride],
+            );
+        }
+    }
+
+    /// Records a compute dispatch, `bind_compute` must be called first.
+    pub unsafe fn dispatch(&self, uri: &UpdateRecordInfo, x: u32, y: u32, z: u32) {
+        self.device.cmd_dispatch(uri.command_buffer, x, y, z);
+    }
+
+    /// Records a `width` x `height` ray trace, `bind_compute` must be called first.
+    pub unsafe fn trace_rays(&self, uri: &UpdateRecordInfo, width: u32, height: u32) {
+        let ray_tracing = self
+            .device
+            .ray_tracing
+            .as_ref()
+            .expect_log("Ray tracing is not supported by this device");
+        let sbt = self
+            .sbt
+            .as_ref()
+            .expect_log("Cannot trace rays with a pipeline that was not built for ray tracing");
+
+        ray_tracing.pipeline.cmd_trace_rays(
+            uri.command_buffer,
+            &sbt.regions[0],
+            &sbt.regions[1],
+            &sbt.regions[2],
+            &vk::StridedDeviceAddressRegionKHR::default(),
+            width,
+            height,
+            1,
+        );
+    }
+
+    pub unsafe fn draw_mesh_tasks(&self, rri: &RenderRecordInfo, task_count: u32, first_task: u32) {
+        let mesh_shader = self
+            .device
+            .mesh_shader
+            .as_ref()
+            .expect_log("Mesh shaders are not supported by this device");
+
+        if rri.debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_draw_mesh_tasks");
+        }
+
+        mesh_shader.cmd_draw_mesh_tasks(rri.command_buffer, task_count, first_task);
+    }
+
+    /// Draws `count` vertices without vertex buffers, for ex. a full screen
+    /// triangle generated from `gl_VertexIndex`.
+    pub unsafe fn draw_vertices(&self, rri: &RenderRecordInfo, count: u32) {
+        if rri.debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_draw");
+        }
+
+        self.device.cmd_draw(rri.command_buffer, count, 1, 0, 0);
+    }
+
+    /// Records a push constant update, for ex. a `TextureRegistry` index.
+    pub unsafe fn push_constants<P: 'static + Copy>(&self, rri: &RenderRecordInfo, data: &P) {
+        self.push_constants_raw(rri.command_buffer, as_bytes(data), rri.debug_calls);
+    }
+
+    /// `push_constants` for `with_push_constant_bytes`, `bytes` must fill the block.
+    pub unsafe fn push_constant_bytes(&self, rri: &RenderRecordInfo, bytes: &[u8]) {
+        self.push_constants_raw(rri.command_buffer, bytes, rri.debug_calls);
+    }
+
+    pub unsafe fn push_constants_compute<P: 'static + Copy>(
+        &self,
+        uri: &UpdateRecordInfo,
+        data: &P,
+    ) {
+        self.push_constants_raw(uri.command_buffer, as_bytes(data), false);
+    }
+
+    unsafe fn push_constants_raw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        bytes: &[u8],
+        debug_calls: bool,
+    ) {
+        let range = self
+            .push_constants
+            .expect_log("Cannot push constants when no push constants were given");
+        debug_assert_eq!(range.size as usize, bytes.len());
+
+        if debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_push_constants");
+        }
+
+        self.device.cmd_push_constants(
+            command_buffer,
+            self.pipeline_layout,
+            range.stage_flags,
+            0,
+            bytes,
+        );
+    }
+
     pub fn write_ubo<'a, U: 'static + UBO>(
         &self,
         imfi: &ImmediateFrameInfo,
         new_data: &U,
     ) -> Result<WriteType, BufferError> {
-        let (_, ubos) = self
-            .desc_sets
-            .get(imfi.image_index)
-            .expect_log(&*format!("Cannot write to UBO when no UBOs were given"));
+        self.write_ubo_for(imfi.image_index, new_data)
+    }
+
+    /// Writes the UBO used when rendering to the swapchain image `image_index`.
+    ///
+    /// Can be used to pre-populate every image's UBO for static scenes.
+    /// The image must not be in flight: call this before the first frame,
+    /// after `Renderer::wait` or for `ImmediateFrameInfo::image_index`.
+    pub fn write_ubo_for<U: 'static + UBO>(
+        &self,
+        image_index: usize,
+        new_data: &U,
+    ) -> Result<WriteType, BufferError> {
+        self.write_ubo_block_for(image_index, 0, new_data)
+    }
+
+    /// Writes block `block` of a ```uniform(count = ..)``` array used when
+    /// rendering this frame, see `PipelineBuilder::with_ubo_blocks`.
+    ///
+    /// Fails with `TriedToOverflow` past the last block and with `NoUBOs`
+    /// if `U` was given as an array or as bytes.
+    pub fn write_ubo_block<U: 'static + UBO>(
+        &self,
+        imfi: &ImmediateFrameInfo,
+        block: usize,
+        new_data: &U,
+    ) -> Result<WriteType, BufferError> {
+        self.write_ubo_block_for(imfi.image_index, block, new_data)
+    }
+
+    fn write_ubo_block_for<U: 'static + UBO>(
+        &self,
+        image_index: usize,
+        block: usize,
+        new_data: &U,
+    ) -> Result<WriteType, BufferError> {
+        let (_, ubos) = self.desc_sets.get(image_index).expect_log(&*format!(
+            "Cannot write to UBO when no UBOs were given or image index {} is out of range",
+            image_index
+        ));
 
         let mut ubo_lock = ubos
             .get(&TypeId::of::<U>())
@@ -481,13 +1998,79 @@ impl Pipeline {
                 "Type {:?} is not an UBO for this pipeline",
                 type_name::<U>()
             ))
+            .get(block)
+            .ok_or(BufferError::TriedToOverflow)?
             .lock();
         let ubo = ubo_lock
             .as_any()
             .downcast_mut::<UniformBuffer<U>>()
-            .unwrap();
+            .ok_or(BufferError::NoUBOs)?;
+
+        ubo.write(0, new_data)
+    }
+
+    /// Writes the `with_ubo_bytes` UBO used when rendering this frame.
+    ///
+    /// Fails with `TriedToOverflow` if `bytes` is larger than the block.
+    pub fn write_ubo_bytes(
+        &self,
+        imfi: &ImmediateFrameInfo,
+        bytes: &[u8],
+    ) -> Result<WriteType, BufferError> {
+        let (_, ubos) = self.desc_sets.get(imfi.image_index).expect_log(&*format!(
+            "Cannot write to UBO when no UBOs were given or image index {} is out of range",
+            imfi.image_index
+        ));
+
+        let mut ubo_lock = ubos
+            .get(&TypeId::of::<RawUbo>())
+            .and_then(|blocks| blocks.first())
+            .expect_log("Pipeline was not built with_ubo_bytes")
+            .lock();
+        let ubo = ubo_lock
+            .as_any()
+            .downcast_mut::<UniformBuffer<u8>>()
+            .ok_or(BufferError::NoUBOs)?;
+
+        ubo.write_slice(0, bytes)
+    }
+
+    /// Empty `AlignedArray` with this device's alignment, for `write_ubo_slice`.
+    pub fn ubo_array<U: 'static + UBO + Copy>(&self) -> AlignedArray<U> {
+        AlignedArray::new(self.device.limits.min_uniform_buffer_offset_alignment as usize)
+    }
+
+    /// Writes the `with_ubo_array` UBO used when rendering this frame.
+    ///
+    /// Fails with `TriedToOverflow` if `data` has more elements than the capacity.
+    pub fn write_ubo_slice<U: 'static + UBO + Copy>(
+        &self,
+        imfi: &ImmediateFrameInfo,
+        data: &AlignedArray<U>,
+    ) -> Result<WriteType, BufferError> {
+        let (_, ubos) = self.desc_sets.get(imfi.image_index).expect_log(&*format!(
+            "Cannot write to UBO when no UBOs were given or image index {} is out of range",
+            imfi.image_index
+        ));
+
+        let mut ubo_lock = ubos
+            .get(&TypeId::of::<U>())
+            .and_then(|blocks| blocks.first())
+            .filter(|_| {
+                self.ubo_array
+                    .map_or(false, |ubo_array| ubo_array.type_id == TypeId::of::<U>())
+            })
+            .expect_log(&*format!(
+                "Type {:?} is not an UBO array for this pipeline",
+                type_name::<U>()
+            ))
+            .lock();
+        let ubo = ubo_lock
+            .as_any()
+            .downcast_mut::<UniformBuffer<u8>>()
+            .ok_or(BufferError::NoUBOs)?;
 
-        ubo.write(new_data)
+        ubo.write_slice(0, data.as_bytes())
     }
 }
 
@@ -496,29 +2079,321 @@ impl Drop for Pipeline {
         self.desc_sets.clear();
 
         unsafe {
-            self.device
-                .destroy_pipeline_layout(self.pipeline_layout, None);
-
             self.device.destroy_pipeline(self.pipeline, None);
+            for (_, pipeline) in self.debug_pipelines.drain(..) {
+                self.device.destroy_pipeline(pipeline, None);
+            }
 
+            // shared with other pipelines, the last one destroys them
             self.device
-                .destroy_descriptor_set_layout(self.desc_set_layout, None);
+                .layouts
+                .release_pipeline_layout(&self.device, self.pipeline_layout);
+            self.device
+                .layouts
+                .release_set_layout(&self.device, self.desc_set_layout);
+
+            for desc_alloc in self.desc_allocs.drain(..) {
+                self.device.descriptor_pools.free(&self.device, desc_alloc);
+            }
 
-            if let Some(desc_pool) = self.desc_pool.take() {
-                self.device.destroy_descriptor_pool(desc_pool, None);
+            if let Some(resources) = self.resources.take() {
+                self.device
+                    .layouts
+                    .release_set_layout(&self.device, resources.desc_set_layout);
+                self.device
+                    .descriptor_pools
+                    .free(&self.device, resources.desc_alloc);
             }
+
+            if let Some(sbt) = self.sbt.take() {
+                self.device.destroy_buffer(sbt.buffer, None);
+                self.device.free_memory(sbt.memory, None);
+            }
+        }
+    }
+}
+
+unsafe fn as_bytes<P: Copy>(data: &P) -> &[u8] {
+    slice::from_raw_parts(data as *const P as *const u8, mem::size_of::<P>())
+}
+
+// saturates instead of wrapping back onto the ids of older pipelines
+fn next_sort_id() -> u16 {
+    match NEXT_SORT_ID.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |id| id.checked_add(1)) {
+        Ok(id) => {
+            if id == u16::MAX - 1 {
+                warn!(
+                    target: logging::PIPELINE,
+                    "Pipeline sort ids exhausted, later pipelines share id {} in draw keys",
+                    u16::MAX
+                );
+            }
+            id
+        }
+        Err(id) => id,
+    }
+}
+
+// size of one element of the formats `Vertex` impls use
+fn format_size(format: vk::Format) -> Option<u32> {
+    match format {
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SNORM | vk::Format::R8G8B8A8_UINT => {
+            Some(4)
+        }
+        vk::Format::A2B10G10R10_SNORM_PACK32 | vk::Format::R16G16_SFLOAT => Some(4),
+        vk::Format::R16G16B16A16_SFLOAT => Some(8),
+        vk::Format::R32_SFLOAT | vk::Format::R32_SINT | vk::Format::R32_UINT => Some(4),
+        vk::Format::R32G32_SFLOAT | vk::Format::R32G32_SINT | vk::Format::R32G32_UINT => Some(8),
+        vk::Format::R32G32B32_SFLOAT | vk::Format::R32G32B32_SINT | vk::Format::R32G32B32_UINT => {
+            Some(12)
         }
+        vk::Format::R32G32B32A32_SFLOAT
+        | vk::Format::R32G32B32A32_SINT
+        | vk::Format::R32G32B32A32_UINT => Some(16),
+        _ => None,
     }
 }
 
+fn resource_set(
+    device: &Arc<RenderDevice>,
+    resources: &[Resource],
+    stage: vk::ShaderStageFlags,
+) -> Result<ResourceSet, BufferError> {
+    let ty = |resource: &Resource| match resource {
+        Resource::StorageBuffer(_) => vk::DescriptorType::STORAGE_BUFFER,
+        Resource::UniformBuffer(_) => vk::DescriptorType::UNIFORM_BUFFER,
+        Resource::SampledImage(_, _) | Resource::SampledImageArray(_, _) => {
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+        }
+        Resource::StorageImage(_) => vk::DescriptorType::STORAGE_IMAGE,
+        Resource::UniformTexelBuffer(_) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+        Resource::StorageTexelBuffer(_) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+        Resource::AccelerationStructure(_) => vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+    };
+    let count = |resource: &Resource| match resource {
+        Resource::SampledImageArray(_, count) => *count,
+        _ => 1,
+    };
+
+    let bindings = resources
+        .iter()
+        .enumerate()
+        .map(|(binding, resource)| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding as u32)
+                .descriptor_type(ty(resource))
+                .descriptor_count(count(resource))
+                .stage_flags(stage)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    // only the last binding can have a variable count
+    let binding_flags = resources
+        .iter()
+        .enumerate()
+        .map(|(binding, resource)| match resource {
+            Resource::SampledImageArray(_, _) if binding + 1 == resources.len() => {
+                vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                    | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT
+            }
+            Resource::SampledImageArray(_, _) => vk::DescriptorBindingFlags::PARTIALLY_BOUND,
+            _ => vk::DescriptorBindingFlags::empty(),
+        })
+        .collect::<Vec<_>>();
+    let variable_count = match resources.last() {
+        Some(Resource::SampledImageArray(_, count)) => Some(*count),
+        _ => None,
+    };
+
+    let desc_set_layout = device
+        .layouts
+        .set_layout(device, &bindings[..], &binding_flags[..])?;
+
+    let descriptor_sizes = resources
+        .iter()
+        .map(|resource| {
+            vk::DescriptorPoolSize::builder()
+                .descriptor_count(count(resource))
+                .ty(ty(resource))
+                .build()
+        })
+        .collect::<Vec<_>>();
+    let desc_alloc = device.descriptor_pools.alloc(
+        device,
+        desc_set_layout,
+        &descriptor_sizes,
+        variable_count,
+    )?;
+    let desc_set = desc_alloc.set;
+
+    let sampled = |view: vk::ImageView, sampler: vk::Sampler| {
+        vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(view)
+            .sampler(sampler)
+            .build()
+    };
+
+    // infos must outlive the writes
+    let mut infos = resources
+        .iter()
+        .map(|resource| match resource {
+            Resource::StorageBuffer(buffer) | Resource::UniformBuffer(buffer) => (
+                vk::DescriptorBufferInfo::builder()
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)
+                    .buffer(*buffer)
+                    .build(),
+                Vec::new(),
+                vk::WriteDescriptorSetAccelerationStructureKHR::default(),
+            ),
+            Resource::SampledImage(view, sampler) => (
+                vk::DescriptorBufferInfo::default(),
+                vec![sampled(*view, *sampler)],
+                vk::WriteDescriptorSetAccelerationStructureKHR::default(),
+            ),
+            Resource::SampledImageArray(images, _) => (
+                vk::DescriptorBufferInfo::default(),
+                images
+                    .iter()
+                    .map(|&(view, sampler)| sampled(view, sampler))
+                    .collect(),
+                vk::WriteDescriptorSetAccelerationStructureKHR::default(),
+            ),
+            Resource::StorageImage(view) => (
+                vk::DescriptorBufferInfo::default(),
+                vec![vk::DescriptorImageInfo::builder()
+                    .image_layout(vk::ImageLayout::GENERAL)
+                    .image_view(*view)
+                    .build()],
+                vk::WriteDescriptorSetAccelerationStructureKHR::default(),
+            ),
+            Resource::UniformTexelBuffer(_) | Resource::StorageTexelBuffer(_) => (
+                vk::DescriptorBufferInfo::default(),
+                Vec::new(),
+                vk::WriteDescriptorSetAccelerationStructureKHR::default(),
+            ),
+            Resource::AccelerationStructure(structure) => (
+                vk::DescriptorBufferInfo::default(),
+                Vec::new(),
+                vk::WriteDescriptorSetAccelerationStructureKHR::builder()
+                    .acceleration_structures(slice::from_ref(structure))
+                    .build(),
+            ),
+        })
+        .collect::<Vec<_>>();
+    let write_sets = resources
+        .iter()
+        .zip(infos.iter_mut())
+        .enumerate()
+        // empty texture arrays have nothing to write
+        .filter(|(_, (resource, _))| match resource {
+            Resource::SampledImageArray(images, _) => !images.is_empty(),
+            _ => true,
+        })
+        .map(
+            |(binding, (resource, (buffer_info, image_infos, structure_info)))| {
+                let write_set = vk::WriteDescriptorSet::builder()
+                    .dst_array_element(0)
+                    .dst_binding(binding as u32)
+                    .dst_set(desc_set)
+                    .descriptor_type(ty(resource));
+
+                match resource {
+                    Resource::StorageBuffer(_) | Resource::UniformBuffer(_) => {
+                        write_set.buffer_info(slice::from_ref(buffer_info)).build()
+                    }
+                    Resource::SampledImage(_, _)
+                    | Resource::SampledImageArray(_, _)
+                    | Resource::StorageImage(_) => write_set.image_info(image_infos).build(),
+                    Resource::UniformTexelBuffer(view) | Resource::StorageTexelBuffer(view) => {
+                        write_set.texel_buffer_view(slice::from_ref(view)).build()
+                    }
+                    Resource::AccelerationStructure(_) => {
+                        // the count is not set by push_next
+                        let mut write_set = write_set.push_next(structure_info).build();
+                        write_set.descriptor_count = 1;
+                        write_set
+                    }
+                }
+            },
+        )
+        .collect::<Vec<_>>();
+    unsafe { device.update_descriptor_sets(&write_sets, &[]) };
+
+    Ok(ResourceSet {
+        desc_alloc,
+        desc_set_layout,
+        desc_set,
+    })
+}
+
+fn shader_binding_table(
+    device: &Arc<RenderDevice>,
+    pipeline: vk::Pipeline,
+    group_count: u32,
+) -> Result<ShaderBindingTable, BufferError> {
+    let ray_tracing = device.ray_tracing.as_ref().unwrap();
+    let align = |size: u32, alignment: u32| (size + alignment - 1) & !(alignment - 1);
+
+    // one group per region, each region starts at the base alignment
+    let handle_size = ray_tracing.handle_size;
+    let stride = align(handle_size, ray_tracing.handle_alignment);
+    let region_size = align(stride, ray_tracing.base_alignment);
+
+    let handles = unsafe {
+        ray_tracing.pipeline.get_ray_tracing_shader_group_handles(
+            pipeline,
+            0,
+            group_count,
+            (group_count * handle_size) as usize,
+        )
+    }
+    .or(Err(BufferError::OutOfMemory))?;
+
+    let mut data = vec![0u8; (group_count * region_size) as usize];
+    for (group, handle) in handles.chunks(handle_size as usize).enumerate() {
+        let offset = group * region_size as usize;
+        data[offset..offset + handle.len()].copy_from_slice(handle);
+    }
+
+    let (buffer, memory, address) = address_buffer(
+        device,
+        data.len() as u64,
+        vk::BufferUsageFlags::SHADER_BINDING_TABLE_KHR,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+    unsafe {
+        let ptr = device
+            .map_memory(memory, 0, data.len() as u64, vk::MemoryMapFlags::empty())
+            .or(Err(BufferError::OutOfMemory))?;
+        (ptr as *mut u8).copy_from_nonoverlapping(data.as_ptr(), data.len());
+        device.unmap_memory(memory);
+    }
+
+    let region = |group: u32| {
+        vk::StridedDeviceAddressRegionKHR::builder()
+            .device_address(address + (group * region_size) as u64)
+            .stride(region_size as u64)
+            .size(region_size as u64)
+            .build()
+    };
+
+    Ok(ShaderBindingTable {
+        buffer,
+        memory,
+        regions: [region(0), region(1), region(2)],
+    })
+}
+
 fn shader_module(
     device: &Arc<RenderDevice>,
-    spirv: &[u8],
+    spirv: &[u32],
     stage: vk::ShaderStageFlags,
+    specialization: &vk::SpecializationInfo,
 ) -> (vk::ShaderModule, vk::PipelineShaderStageCreateInfo) {
-    let spirv = read_spv(&mut Cursor::new(&spirv[..])).expect("SPIR-V read failed");
-
-    let module_info = vk::ShaderModuleCreateInfo::builder().code(&spirv[..]);
+    let module_info = vk::ShaderModuleCreateInfo::builder().code(spirv);
 
     let module = unsafe { device.create_shader_module(&module_info, None) }
         .expect("Vertex shader module creation failed");
@@ -527,6 +2402,7 @@ fn shader_module(
         .module(module)
         .stage(stage)
         .name(CStr::from_bytes_with_nul(b"main\0").unwrap())
+        .specialization_info(specialization)
         .build();
 
     (module, stage)
@@ -0,0 +1,355 @@
+use ash::{version::DeviceV1_0, vk};
+use log::debug;
+use std::sync::Arc;
+
+use super::{
+    buffer::{
+        image::{Image, ImageBuilder, ImageUsage, Layout},
+        BufferError,
+    },
+    device::RenderDevice,
+    pipeline::{Pipeline, PipelineBuilder},
+    Renderer, UpdateRecordInfo,
+};
+
+mod blur {
+    gears_pipeline::pipeline! {
+        comp: {
+            path: "res/blur.comp.glsl"
+        }
+    }
+}
+
+mod downsample {
+    gears_pipeline::pipeline! {
+        comp: {
+            path: "res/downsample.comp.glsl"
+        }
+    }
+}
+
+mod upsample {
+    gears_pipeline::pipeline! {
+        comp: {
+            path: "res/upsample.comp.glsl"
+        }
+    }
+}
+
+const LOCAL_SIZE: u32 = 8;
+const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+// must match the push constant blocks in the post processing shaders
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct BlurPush {
+    direction: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct ScalarPush {
+    value: f32,
+}
+
+/// Separable 9 tap gaussian blur.
+///
+/// `source` is sampled in `SHADER_READ_ONLY_OPTIMAL`, for ex. a `Texture2D`
+/// or a `RenderTarget` color attachment, and the blurred RGBA16F result is
+/// left in `SHADER_READ_ONLY_OPTIMAL` for `output_view()` and `sampler()`.
+/// `record` takes an `UpdateRecordInfo`, from `UploadScope::record` or
+/// `RenderRecordInfo::as_update_info` before `RecordScope::begin_pass`.
+pub struct BlurPass {
+    device: Arc<RenderDevice>,
+
+    sampler: vk::Sampler,
+    tmp: Image,
+    output: Image,
+    horizontal: Pipeline,
+    vertical: Pipeline,
+    extent: vk::Extent2D,
+}
+
+/// Bloom from a threshold downsample chain and additive upsampling.
+///
+/// Each level halves the size of the previous one, starting at half of
+/// `width` x `height`. The result is at half resolution and meant to be added
+/// on top of the source image, with the same layout rules as `BlurPass`.
+pub struct BloomPass {
+    device: Arc<RenderDevice>,
+
+    sampler: vk::Sampler,
+    levels: Vec<(Image, vk::Extent2D)>,
+    downsample: Vec<Pipeline>,
+    upsample: Vec<Pipeline>,
+
+    threshold: f32,
+    intensity: f32,
+}
+
+impl BlurPass {
+    pub fn new(
+        renderer: &Renderer,
+        source: vk::ImageView,
+        width: u32,
+        height: u32,
+    ) -> Result<Self, BufferError> {
+        Self::new_with_device(renderer.rdevice.clone(), source, width, height)
+    }
+
+    pub fn new_with_device(
+        device: Arc<RenderDevice>,
+        source: vk::ImageView,
+        width: u32,
+        height: u32,
+    ) -> Result<Self, BufferError> {
+        let sampler = linear_sampler(&device)?;
+        let tmp = storage_image(&device, width, height)?;
+        let output = storage_image(&device, width, height)?;
+
+        let pipeline = |src: vk::ImageView, dst: vk::ImageView| {
+            PipelineBuilder::new_with_device(device.clone(), vk::RenderPass::null(), 1)
+                .with_compute_module(blur::COMP_SPIRV_WORDS)
+                .with_push_constants::<BlurPush>()
+                .with_sampled_image(src, sampler)
+                .with_storage_image(dst)
+                .build()
+        };
+        let horizontal = pipeline(source, tmp.view())?;
+        let vertical = pipeline(tmp.view(), output.view())?;
+
+        debug!("BlurPass created: {}x{}", width, height);
+
+        Ok(Self {
+            device,
+
+            sampler,
+            tmp,
+            output,
+            horizontal,
+            vertical,
+            extent: vk::Extent2D { width, height },
+        })
+    }
+
+    pub unsafe fn record(&self, uri: &UpdateRecordInfo) {
+        let passes = [
+            (
+                &self.horizontal,
+                &self.tmp,
+                [1.0 / self.extent.width as f32, 0.0],
+            ),
+            (
+                &self.vertical,
+                &self.output,
+                [0.0, 1.0 / self.extent.height as f32],
+            ),
+        ];
+
+        for (pipeline, dst, direction) in passes.iter() {
+            let push = BlurPush {
+                direction: *direction,
+            };
+
+            uri.transition(dst, Layout::General);
+            pipeline.bind_compute(uri);
+            pipeline.push_constants_compute(uri, &push);
+            dispatch(pipeline, uri, self.extent);
+            uri.transition(dst, Layout::ShaderRead);
+        }
+    }
+
+    pub fn output_view(&self) -> vk::ImageView {
+        self.output.view()
+    }
+
+    /// Linear, clamp to edge sampler for reading the output.
+    pub fn sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+}
+
+impl BloomPass {
+    pub fn new(
+        renderer: &Renderer,
+        source: vk::ImageView,
+        width: u32,
+        height: u32,
+        level_count: usize,
+    ) -> Result<Self, BufferError> {
+        Self::new_with_device(renderer.rdevice.clone(), source, width, height, level_count)
+    }
+
+    pub fn new_with_device(
+        device: Arc<RenderDevice>,
+        source: vk::ImageView,
+        width: u32,
+        height: u32,
+        level_count: usize,
+    ) -> Result<Self, BufferError> {
+        if level_count == 0 {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let sampler = linear_sampler(&device)?;
+        let levels = (1..=level_count as u32)
+            .map(|i| {
+                let extent = vk::Extent2D {
+                    width: (width >> i).max(1),
+                    height: (height >> i).max(1),
+                };
+                Ok((storage_image(&device, extent.width, extent.height)?, extent))
+            })
+            .collect::<Result<Vec<_>, BufferError>>()?;
+
+        let pipeline = |spirv: &[u32], src: vk::ImageView, dst: vk::ImageView| {
+            PipelineBuilder::new_with_device(device.clone(), vk::RenderPass::null(), 1)
+                .with_compute_module(spirv)
+                .with_push_constants::<ScalarPush>()
+                .with_sampled_image(src, sampler)
+                .with_storage_image(dst)
+                .build()
+        };
+
+        let sources = Some(source)
+            .into_iter()
+            .chain(levels.iter().map(|(image, _)| image.view()));
+        let downsample = sources
+            .zip(levels.iter())
+            .map(|(src, (dst, _))| pipeline(downsample::COMP_SPIRV_WORDS, src, dst.view()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let upsample = levels
+            .windows(2)
+            .map(|pair| {
+                pipeline(
+                    upsample::COMP_SPIRV_WORDS,
+                    pair[1].0.view(),
+                    pair[0].0.view(),
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        debug!(
+            "BloomPass created: {}x{} with {} levels",
+            width, height, level_count
+        );
+
+        Ok(Self {
+            device,
+
+            sampler,
+            levels,
+            downsample,
+            upsample,
+
+            threshold: 1.0,
+            intensity: 1.0,
+        })
+    }
+
+    /// Luminance cut off of the first downsample, 0 blooms everything.
+    ///
+    /// Like push constants in general, this is recorded and needs a rerecord to change.
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+    }
+
+    pub unsafe fn record(&self, uri: &UpdateRecordInfo) {
+        for (i, (pipeline, (dst, extent))) in
+            self.downsample.iter().zip(self.levels.iter()).enumerate()
+        {
+            let push = ScalarPush {
+                value: if i == 0 { self.threshold } else { 0.0 },
+            };
+
+            uri.transition(dst, Layout::General);
+            pipeline.bind_compute(uri);
+            pipeline.push_constants_compute(uri, &push);
+            dispatch(pipeline, uri, *extent);
+            uri.transition(dst, Layout::ShaderRead);
+        }
+
+        // smallest level first, each one is added to the next larger one
+        let push = ScalarPush {
+            value: self.intensity,
+        };
+        for (pipeline, (dst, extent)) in self.upsample.iter().zip(self.levels.iter()).rev() {
+            uri.transition(dst, Layout::General);
+            pipeline.bind_compute(uri);
+            pipeline.push_constants_compute(uri, &push);
+            dispatch(pipeline, uri, *extent);
+            uri.transition(dst, Layout::ShaderRead);
+        }
+    }
+
+    pub fn output_view(&self) -> vk::ImageView {
+        self.levels[0].0.view()
+    }
+
+    pub fn output_extent(&self) -> (u32, u32) {
+        let extent = self.levels[0].1;
+        (extent.width, extent.height)
+    }
+
+    /// Linear, clamp to edge sampler for reading the output.
+    pub fn sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+}
+
+impl Drop for BlurPass {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+impl Drop for BloomPass {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+fn storage_image(
+    device: &Arc<RenderDevice>,
+    width: u32,
+    height: u32,
+) -> Result<Image, BufferError> {
+    ImageBuilder::new_with_device(device.clone())
+        .with_width(width)
+        .with_height(height)
+        .build(ImageUsage::READ | ImageUsage::STORAGE, FORMAT)
+}
+
+fn linear_sampler(device: &Arc<RenderDevice>) -> Result<vk::Sampler, BufferError> {
+    let sampler_info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .min_lod(0.0)
+        .max_lod(0.0);
+
+    unsafe { device.create_sampler(&sampler_info, None) }.or(Err(BufferError::OutOfMemory))
+}
+
+unsafe fn dispatch(pipeline: &Pipeline, uri: &UpdateRecordInfo, extent: vk::Extent2D) {
+    pipeline.dispatch(
+        uri,
+        (extent.width + LOCAL_SIZE - 1) / LOCAL_SIZE,
+        (extent.height + LOCAL_SIZE - 1) / LOCAL_SIZE,
+        1,
+    );
+}
@@ -0,0 +1,432 @@
+use ash::{version::DeviceV1_0, vk};
+use cgmath::Vector4;
+use log::debug;
+use std::sync::Arc;
+
+use crate::logging;
+
+use super::{
+    buffer::{texture::Texture3D, BufferError},
+    device::RenderDevice,
+    pipeline::{Pipeline, PipelineBuilder},
+    target::RenderTarget,
+    RenderRecordInfo, Renderer,
+};
+
+mod shader {
+    gears_pipeline::pipeline! {
+        vert: {
+            path: "res/fullscreen.vert.glsl"
+        }
+        frag: {
+            path: "res/present.frag.glsl"
+            define: ["LUT" = "0"]
+        }
+    }
+}
+
+mod lut_shader {
+    gears_pipeline::pipeline! {
+        vert: {
+            path: "res/fullscreen.vert.glsl"
+        }
+        frag: {
+            path: "res/present.frag.glsl"
+            define: ["LUT" = "1"]
+        }
+    }
+}
+
+// must match the push constant block in present.frag.glsl
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct PresentPush {
+    scale: [f32; 2],
+    lut_strength: f32,
+    srgb: u32,
+}
+
+/// Format of the `ScaledPresent::new_with_velocity` attachment.
+pub const VELOCITY_FORMAT: vk::Format = vk::Format::R16G16_SFLOAT;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScaleFilter {
+    /// Sharp pixels, for pixel art.
+    Nearest,
+    Linear,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScaleMode {
+    /// Fills the whole swapchain, ignoring the aspect ratio.
+    Stretch,
+    /// Largest size with the internal aspect ratio, bars are the clear color.
+    Letterbox,
+    /// Like `Letterbox` but only whole multiples of the internal resolution.
+    ///
+    /// Falls back to `Letterbox` if the swapchain is smaller than the internal resolution.
+    Integer,
+}
+
+/// Renders the scene at a fixed internal resolution and scales it to the swapchain.
+///
+/// Scene pipelines are built with `PipelineBuilder::new(renderer).with_render_target(present.target())`.
+/// A frame then looks like:
+/// - `RecordScope`: `present.begin(rri, clear_color)`, scene draws, `present.end(rri)`
+/// - `DrawScope`: `present.present(rri)`, followed by any draws at swapchain resolution, like UI
+///
+/// The target uses the swapchain format, so the scene pipelines output the same values.
+/// With `Renderer::set_target_frame_time` the scene only renders to the top left
+/// `RenderRecordInfo::render_scale` part of the target, which is stretched to the
+/// same rect as the full resolution would be.
+///
+/// `set_lut` applies a color grading LUT while scaling.
+///
+/// For TAA or other temporal upscalers, `new_with_velocity` adds a velocity
+/// attachment to the target and `set_resolve` presents the upscaler's output
+/// instead of the target. Jitter the scene with `camera::jitter`.
+pub struct ScaledPresent {
+    device: Arc<RenderDevice>,
+
+    target: RenderTarget,
+    sampler: vk::Sampler,
+    pipeline: Pipeline,
+    lut: Option<(vk::ImageView, vk::Sampler)>,
+    resolve: Option<vk::ImageView>,
+
+    filter: ScaleFilter,
+    mode: ScaleMode,
+    lut_strength: f32,
+    srgb: bool,
+}
+
+impl ScaledPresent {
+    pub fn new(
+        renderer: &Renderer,
+        width: u32,
+        height: u32,
+        filter: ScaleFilter,
+    ) -> Result<Self, BufferError> {
+        Self::new_with_formats(
+            renderer,
+            width,
+            height,
+            filter,
+            &[renderer.surface_format()],
+        )
+    }
+
+    /// Adds a `VELOCITY_FORMAT` color attachment after the scene color.
+    ///
+    /// Scene pipelines write the screen space motion of each pixel since the
+    /// previous frame to `layout(location = 1) out vec2`, in pixels of the
+    /// internal resolution. Read it with `velocity_view` after `end`.
+    pub fn new_with_velocity(
+        renderer: &Renderer,
+        width: u32,
+        height: u32,
+        filter: ScaleFilter,
+    ) -> Result<Self, BufferError> {
+        let formats = [renderer.surface_format(), VELOCITY_FORMAT];
+        Self::new_with_formats(renderer, width, height, filter, &formats)
+    }
+
+    fn new_with_formats(
+        renderer: &Renderer,
+        width: u32,
+        height: u32,
+        filter: ScaleFilter,
+        formats: &[vk::Format],
+    ) -> Result<Self, BufferError> {
+        let device = renderer.rdevice.clone();
+        let target = RenderTarget::new(renderer, width, height, formats)?;
+        let sampler = sampler(&device, filter)?;
+        let pipeline = pipeline(renderer, target.color_view(0), sampler, None)?;
+
+        debug!(
+            target: logging::SWAPCHAIN,
+            "ScaledPresent created: {}x{} with {:?}",
+            width, height, filter
+        );
+
+        Ok(Self {
+            device,
+
+            target,
+            sampler,
+            pipeline,
+            lut: None,
+            resolve: None,
+
+            filter,
+            mode: ScaleMode::Letterbox,
+            lut_strength: 1.0,
+            srgb: is_srgb(renderer.surface_format()),
+        })
+    }
+
+    /// Color grades the scene with `lut`, `None` removes it.
+    ///
+    /// `lut` is indexed with sRGB encoded colors, red along the width, green along
+    /// the height and blue along the depth, see `identity_lut`. It has to outlive
+    /// this and be uploaded with `Texture3D::update` before the first frame.
+    /// Rebuilds the pipeline, so a rerecord is needed.
+    pub fn set_lut(
+        &mut self,
+        renderer: &Renderer,
+        lut: Option<&Texture3D>,
+    ) -> Result<(), BufferError> {
+        let lut = lut.map(|lut| (lut.view(), lut.sampler()));
+        self.pipeline = pipeline(renderer, self.source(), self.sampler, lut)?;
+        self.lut = lut;
+        Ok(())
+    }
+
+    /// Presents `resolve` instead of the target, `None` goes back to the target.
+    ///
+    /// `resolve` is the output of a temporal upscaler, sampled in
+    /// `SHADER_READ_ONLY_OPTIMAL` over its whole area, so it already undoes
+    /// the render scale. It has the aspect ratio of the target and has to
+    /// outlive this or the next `set_resolve`. Rebuilds the pipeline, so a
+    /// rerecord is needed.
+    pub fn set_resolve(
+        &mut self,
+        renderer: &Renderer,
+        resolve: Option<vk::ImageView>,
+    ) -> Result<(), BufferError> {
+        let source = resolve.unwrap_or_else(|| self.target.color_view(0));
+        self.pipeline = pipeline(renderer, source, self.sampler, self.lut)?;
+        self.resolve = resolve;
+        Ok(())
+    }
+
+    /// The velocity attachment of `new_with_velocity`.
+    pub fn velocity_view(&self) -> Option<vk::ImageView> {
+        if self.target.color_count() > 1 {
+            Some(self.target.color_view(1))
+        } else {
+            None
+        }
+    }
+
+    fn source(&self) -> vk::ImageView {
+        self.resolve.unwrap_or_else(|| self.target.color_view(0))
+    }
+
+    /// Blend between the original (0) and color graded (1) colors, 1 by default.
+    ///
+    /// Like push constants in general, this is recorded and needs a rerecord to change.
+    pub fn set_lut_strength(&mut self, strength: f32) {
+        self.lut_strength = strength.max(0.0).min(1.0);
+    }
+
+    pub fn target(&self) -> &RenderTarget {
+        &self.target
+    }
+
+    pub fn filter(&self) -> ScaleFilter {
+        self.filter
+    }
+
+    pub fn mode(&self) -> ScaleMode {
+        self.mode
+    }
+
+    /// `Letterbox` by default.
+    ///
+    /// Like viewports in general, this is recorded and needs a rerecord to change.
+    pub fn set_mode(&mut self, mode: ScaleMode) {
+        self.mode = mode;
+    }
+
+    /// The part of a `width` x `height` swapchain the scene is scaled to.
+    pub fn rect(&self, width: u32, height: u32) -> vk::Rect2D {
+        scaled_rect(
+            self.mode,
+            (self.target.width(), self.target.height()),
+            (width, height),
+        )
+    }
+
+    /// Maps a swapchain pixel, for ex. the cursor, to an internal resolution pixel.
+    ///
+    /// `None` if it is on the bars.
+    pub fn to_internal(&self, extent: (u32, u32), position: (f32, f32)) -> Option<(f32, f32)> {
+        let rect = self.rect(extent.0, extent.1);
+        let x = (position.0 - rect.offset.x as f32) / rect.extent.width as f32;
+        let y = (position.1 - rect.offset.y as f32) / rect.extent.height as f32;
+
+        if (0.0..1.0).contains(&x) && (0.0..1.0).contains(&y) {
+            Some((
+                x * self.target.width() as f32,
+                y * self.target.height() as f32,
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// The part of the target rendered to at `render_scale`.
+    pub fn scaled_extent(&self, render_scale: f32) -> (u32, u32) {
+        let scaled = |size: u32| {
+            ((size as f32 * render_scale).round() as u32)
+                .max(1)
+                .min(size)
+        };
+        (scaled(self.target.width()), scaled(self.target.height()))
+    }
+
+    /// Begins the target render pass with the viewport at the current render scale.
+    ///
+    /// The velocity attachment is cleared to no motion.
+    pub unsafe fn begin(&self, rri: &RenderRecordInfo, clear_color: Vector4<f32>) {
+        let no_motion = Vector4::new(0.0, 0.0, 0.0, 0.0);
+        self.target
+            .begin_with_clear_colors(rri, &[clear_color, no_motion]);
+
+        let (width, height) = self.scaled_extent(rri.render_scale());
+        rri.set_viewport(vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: vk::Extent2D { width, height },
+        });
+    }
+
+    pub unsafe fn end(&self, rri: &RenderRecordInfo) {
+        self.target.end(rri);
+    }
+
+    /// Draws the target to the swapchain and restores the full viewport and scissor.
+    pub unsafe fn present(&self, rri: &RenderRecordInfo) {
+        let (width, height) = rri.extent();
+        let rect = self.rect(width, height);
+        let scale = if self.resolve.is_some() {
+            [1.0, 1.0]
+        } else {
+            let (scaled_width, scaled_height) = self.scaled_extent(rri.render_scale());
+            [
+                scaled_width as f32 / self.target.width() as f32,
+                scaled_height as f32 / self.target.height() as f32,
+            ]
+        };
+        let push = PresentPush {
+            scale,
+            lut_strength: self.lut_strength,
+            srgb: self.srgb as u32,
+        };
+
+        rri.set_viewport(rect);
+        self.pipeline.bind(rri);
+        self.pipeline.push_constants(rri, &push);
+        self.pipeline.draw_vertices(rri, 3);
+
+        rri.set_viewport(vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: vk::Extent2D { width, height },
+        });
+    }
+}
+
+impl Drop for ScaledPresent {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+/// `size`³ RGBA8 LUT that maps every color to itself, a starting point for color grading.
+pub fn identity_lut(size: u32) -> Vec<u8> {
+    let max = (size.max(2) - 1) as f32;
+    let value = |i: u32| (i as f32 / max * 255.0).round() as u8;
+
+    let mut data = Vec::with_capacity((size * size * size * 4) as usize);
+    for b in 0..size {
+        for g in 0..size {
+            for r in 0..size {
+                data.extend_from_slice(&[value(r), value(g), value(b), 255]);
+            }
+        }
+    }
+    data
+}
+
+fn pipeline(
+    renderer: &Renderer,
+    source: vk::ImageView,
+    sampler: vk::Sampler,
+    lut: Option<(vk::ImageView, vk::Sampler)>,
+) -> Result<Pipeline, BufferError> {
+    let builder = PipelineBuilder::new(renderer).without_debug_views();
+    let builder = match lut {
+        Some(_) => builder
+            .with_graphics_modules(lut_shader::VERT_SPIRV_WORDS, lut_shader::FRAG_SPIRV_WORDS),
+        None => builder.with_graphics_modules(shader::VERT_SPIRV_WORDS, shader::FRAG_SPIRV_WORDS),
+    };
+
+    let builder = builder
+        .with_push_constants::<PresentPush>(vk::ShaderStageFlags::FRAGMENT)
+        .with_sampled_image(source, sampler);
+    match lut {
+        Some((view, sampler)) => builder.with_sampled_image(view, sampler),
+        None => builder,
+    }
+    .build(false)
+}
+
+fn is_srgb(format: vk::Format) -> bool {
+    match format {
+        vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::A8B8G8R8_SRGB_PACK32 => true,
+        _ => false,
+    }
+}
+
+fn scaled_rect(mode: ScaleMode, src: (u32, u32), dst: (u32, u32)) -> vk::Rect2D {
+    let scale_x = dst.0 as f32 / src.0 as f32;
+    let scale_y = dst.1 as f32 / src.1 as f32;
+
+    let (width, height) = match mode {
+        ScaleMode::Stretch => dst,
+        ScaleMode::Letterbox | ScaleMode::Integer => {
+            let mut scale = scale_x.min(scale_y);
+            if mode == ScaleMode::Integer && scale >= 1.0 {
+                scale = scale.floor();
+            }
+            (
+                ((src.0 as f32 * scale).round() as u32).min(dst.0),
+                ((src.1 as f32 * scale).round() as u32).min(dst.1),
+            )
+        }
+    };
+
+    vk::Rect2D {
+        offset: vk::Offset2D {
+            x: ((dst.0 - width) / 2) as i32,
+            y: ((dst.1 - height) / 2) as i32,
+        },
+        extent: vk::Extent2D { width, height },
+    }
+}
+
+fn sampler(device: &RenderDevice, filter: ScaleFilter) -> Result<vk::Sampler, BufferError> {
+    let filter = match filter {
+        ScaleFilter::Nearest => vk::Filter::NEAREST,
+        ScaleFilter::Linear => vk::Filter::LINEAR,
+    };
+
+    let sampler_info = vk::SamplerCreateInfo::builder()
+        .mag_filter(filter)
+        .min_filter(filter)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .min_lod(0.0)
+        .max_lod(0.0);
+
+    unsafe { device.create_sampler(&sampler_info, None) }.or(Err(BufferError::OutOfMemory))
+}
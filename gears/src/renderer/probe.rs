@@ -0,0 +1,465 @@
+use ash::{version::DeviceV1_0, vk};
+use log::debug;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use super::{
+    buffer::{
+        image::{Image, ImageBuilder, ImageUsage, Layout},
+        BufferError,
+    },
+    device::RenderDevice,
+    pipeline::{Pipeline, PipelineBuilder},
+    Renderer, UpdateRecordInfo,
+};
+
+mod irradiance {
+    gears_pipeline::pipeline! {
+        comp: {
+            path: "res/irradiance.comp.glsl"
+        }
+    }
+}
+
+mod prefilter {
+    gears_pipeline::pipeline! {
+        comp: {
+            path: "res/prefilter.comp.glsl"
+        }
+    }
+}
+
+mod brdf {
+    gears_pipeline::pipeline! {
+        comp: {
+            path: "res/brdf.comp.glsl"
+        }
+    }
+}
+
+/// Source of the `ibl_ambient` lighting helper and the cube and sampling
+/// functions the probe shaders use, for ```#include "ibl.glsl"``` in own shaders.
+pub const IBL_GLSL: &str = include_str!("../../res/ibl.glsl");
+
+const LOCAL_SIZE: u32 = 8;
+const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+// must match the push constant blocks in the baking shaders
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct IrradiancePush {
+    sample_delta: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct PrefilterPush {
+    roughness: f32,
+    sample_count: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct BrdfPush {
+    sample_count: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProbeConfig {
+    /// Face size of the irradiance cube map, diffuse light has no detail to keep.
+    pub irradiance_size: u32,
+    /// Face size of the first specular mip.
+    pub specular_size: u32,
+    /// Specular mips from roughness 0 to 1.
+    pub specular_mips: u32,
+    /// GGX samples per specular texel.
+    pub sample_count: u32,
+    /// Angle between irradiance samples in radians.
+    pub sample_delta: f32,
+}
+
+/// Image based lighting baked from an environment: an irradiance cube map
+/// for diffuse light and a prefiltered cube map with one roughness per mip
+/// for specular light.
+///
+/// The source is an equirectangular panorama with +Y at the top, sampled in
+/// `SHADER_READ_ONLY_OPTIMAL`, for ex. an HDR `Texture2D` or the output of a
+/// pass rendering the sky. Baking runs on the GPU in `update`, once after
+/// creation and again after `request_bake`, for ex. when the sky changed:
+///
+/// ```ignore
+/// let lut = BrdfLut::new(&renderer, 256)?;
+/// let probe = EnvironmentProbe::new(&renderer, sky.view(), &ProbeConfig::default())?;
+///
+/// // UploadScope
+/// upload.record(|uri| unsafe { lut.update(uri) | probe.update(uri) });
+/// ```
+///
+/// Both cube maps, with `sampler()`, and a `BrdfLut` are the inputs of
+/// `ibl_ambient` in `IBL_GLSL`:
+///
+/// ```glsl
+/// #include "ibl.glsl"
+///
+/// layout(set = 1, binding = 0) uniform samplerCube irradiance;
+/// layout(set = 1, binding = 1) uniform samplerCube specular;
+/// layout(set = 1, binding = 2) uniform sampler2D brdf_lut;
+///
+/// color += ibl_ambient(n, v, albedo, metallic, roughness, irradiance, specular, brdf_lut, max_lod);
+/// ```
+pub struct EnvironmentProbe {
+    device: Arc<RenderDevice>,
+
+    // equirect, repeat along u
+    source_sampler: vk::Sampler,
+    sampler: vk::Sampler,
+
+    irradiance: Image,
+    specular: Image,
+    // per mip layered views for the storage writes
+    storage_views: Vec<vk::ImageView>,
+
+    irradiance_pipeline: Pipeline,
+    specular_pipelines: Vec<Pipeline>,
+
+    config: ProbeConfig,
+    requested_bake: AtomicBool,
+}
+
+/// Split sum lookup table of the GGX specular BRDF, shared by every
+/// `EnvironmentProbe`.
+///
+/// Indexed by n dot v along x and roughness along y, red and green are the
+/// scale and bias of f0. Baked once in the first `update`.
+pub struct BrdfLut {
+    device: Arc<RenderDevice>,
+
+    sampler: vk::Sampler,
+    image: Image,
+    pipeline: Pipeline,
+    size: u32,
+
+    sample_count: u32,
+    requested_bake: AtomicBool,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            irradiance_size: 32,
+            specular_size: 128,
+            specular_mips: 5,
+            sample_count: 512,
+            sample_delta: 0.025,
+        }
+    }
+}
+
+impl EnvironmentProbe {
+    pub fn new(
+        renderer: &Renderer,
+        source: vk::ImageView,
+        config: &ProbeConfig,
+    ) -> Result<Self, BufferError> {
+        Self::new_with_device(renderer.rdevice.clone(), source, config)
+    }
+
+    pub fn new_with_device(
+        device: Arc<RenderDevice>,
+        source: vk::ImageView,
+        config: &ProbeConfig,
+    ) -> Result<Self, BufferError> {
+        let max_mips = 32 - config.specular_size.leading_zeros();
+        if config.specular_mips == 0
+            || config.specular_mips > max_mips
+            || config.sample_count == 0
+            || config.sample_delta <= 0.0
+        {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let source_sampler = sampler(
+            &device,
+            vk::SamplerAddressMode::REPEAT,
+            vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            0.0,
+        )?;
+        let cube_sampler = sampler(
+            &device,
+            vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            config.specular_mips as f32,
+        )?;
+
+        let irradiance = cube_image(&device, config.irradiance_size, 1)?;
+        let specular = cube_image(&device, config.specular_size, config.specular_mips)?;
+
+        let mut storage_views = vec![layered_view(&device, &irradiance, 0)?];
+        for mip in 0..config.specular_mips {
+            storage_views.push(layered_view(&device, &specular, mip)?);
+        }
+
+        let builder =
+            || PipelineBuilder::new_with_device(device.clone(), vk::RenderPass::null(), 1);
+        let irradiance_pipeline = builder()
+            .with_compute_module(irradiance::COMP_SPIRV_WORDS)
+            .with_push_constants::<IrradiancePush>()
+            .with_sampled_image(source, source_sampler)
+            .with_storage_image(storage_views[0])
+            .build()?;
+        let specular_pipelines = storage_views[1..]
+            .iter()
+            .map(|&view| {
+                builder()
+                    .with_compute_module(prefilter::COMP_SPIRV_WORDS)
+                    .with_push_constants::<PrefilterPush>()
+                    .with_sampled_image(source, source_sampler)
+                    .with_storage_image(view)
+                    .build()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        debug!(
+            "EnvironmentProbe created: irradiance {}, specular {} with {} mips",
+            config.irradiance_size, config.specular_size, config.specular_mips
+        );
+
+        Ok(Self {
+            device,
+
+            source_sampler,
+            sampler: cube_sampler,
+
+            irradiance,
+            specular,
+            storage_views,
+
+            irradiance_pipeline,
+            specular_pipelines,
+
+            config: *config,
+            requested_bake: AtomicBool::new(true),
+        })
+    }
+
+    /// Bakes again in the next `update`.
+    pub fn request_bake(&self) {
+        self.requested_bake.store(true, Ordering::SeqCst);
+    }
+
+    /// Bakes if requested, returns true if anything was recorded.
+    pub unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        let requested_bake = self.requested_bake.swap(false, Ordering::SeqCst);
+
+        if requested_bake {
+            self.bake(uri);
+        }
+
+        requested_bake
+    }
+
+    pub fn irradiance_view(&self) -> vk::ImageView {
+        self.irradiance.view()
+    }
+
+    /// Roughness `r` is at mip `r * (specular_mips - 1)`.
+    pub fn specular_view(&self) -> vk::ImageView {
+        self.specular.view()
+    }
+
+    pub fn specular_mips(&self) -> u32 {
+        self.config.specular_mips
+    }
+
+    pub fn config(&self) -> &ProbeConfig {
+        &self.config
+    }
+
+    /// Trilinear, clamp to edge sampler for both cube maps.
+    pub fn sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+
+    unsafe fn bake(&self, uri: &UpdateRecordInfo) {
+        uri.transition(&self.irradiance, Layout::General);
+        uri.transition(&self.specular, Layout::General);
+
+        let push = IrradiancePush {
+            sample_delta: self.config.sample_delta,
+        };
+        self.irradiance_pipeline.bind_compute(uri);
+        self.irradiance_pipeline.push_constants_compute(uri, &push);
+        dispatch_cube(&self.irradiance_pipeline, uri, self.config.irradiance_size);
+
+        let max_mip = (self.config.specular_mips - 1).max(1) as f32;
+        for (mip, pipeline) in self.specular_pipelines.iter().enumerate() {
+            let push = PrefilterPush {
+                roughness: mip as f32 / max_mip,
+                sample_count: self.config.sample_count,
+            };
+            pipeline.bind_compute(uri);
+            pipeline.push_constants_compute(uri, &push);
+            dispatch_cube(pipeline, uri, (self.config.specular_size >> mip).max(1));
+        }
+
+        uri.transition(&self.irradiance, Layout::ShaderRead);
+        uri.transition(&self.specular, Layout::ShaderRead);
+    }
+}
+
+impl Drop for EnvironmentProbe {
+    fn drop(&mut self) {
+        unsafe {
+            for &view in self.storage_views.iter() {
+                self.device.destroy_image_view(view, None);
+            }
+            self.device.destroy_sampler(self.source_sampler, None);
+            self.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+impl BrdfLut {
+    /// `size` x `size` RGBA16F, 256 is plenty.
+    pub fn new(renderer: &Renderer, size: u32) -> Result<Self, BufferError> {
+        Self::new_with_device(renderer.rdevice.clone(), size)
+    }
+
+    pub fn new_with_device(device: Arc<RenderDevice>, size: u32) -> Result<Self, BufferError> {
+        let image = ImageBuilder::new_with_device(device.clone())
+            .with_width(size)
+            .with_height(size)
+            .build(ImageUsage::READ | ImageUsage::STORAGE, FORMAT)?;
+        let lut_sampler = sampler(
+            &device,
+            vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            0.0,
+        )?;
+
+        let pipeline = PipelineBuilder::new_with_device(device.clone(), vk::RenderPass::null(), 1)
+            .with_compute_module(brdf::COMP_SPIRV_WORDS)
+            .with_push_constants::<BrdfPush>()
+            .with_storage_image(image.view())
+            .build()?;
+
+        Ok(Self {
+            device,
+
+            sampler: lut_sampler,
+            image,
+            pipeline,
+            size,
+
+            sample_count: 1024,
+            requested_bake: AtomicBool::new(true),
+        })
+    }
+
+    /// Bakes once, returns true if anything was recorded.
+    pub unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        let requested_bake = self.requested_bake.swap(false, Ordering::SeqCst);
+
+        if requested_bake {
+            uri.transition(&self.image, Layout::General);
+            self.pipeline.bind_compute(uri);
+            self.pipeline.push_constants_compute(
+                uri,
+                &BrdfPush {
+                    sample_count: self.sample_count,
+                },
+            );
+            self.pipeline.dispatch(
+                uri,
+                (self.size + LOCAL_SIZE - 1) / LOCAL_SIZE,
+                (self.size + LOCAL_SIZE - 1) / LOCAL_SIZE,
+                1,
+            );
+            uri.transition(&self.image, Layout::ShaderRead);
+        }
+
+        requested_bake
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.image.view()
+    }
+
+    /// Linear, clamp to edge sampler.
+    pub fn sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+}
+
+impl Drop for BrdfLut {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+fn cube_image(device: &Arc<RenderDevice>, size: u32, mips: u32) -> Result<Image, BufferError> {
+    ImageBuilder::new_with_device(device.clone())
+        .with_width(size)
+        .with_height(size)
+        .with_mip_levels(mips)
+        .with_cube()
+        .build(ImageUsage::READ | ImageUsage::STORAGE, FORMAT)
+}
+
+// the six faces of one mip as an `image2DArray`
+fn layered_view(
+    device: &Arc<RenderDevice>,
+    image: &Image,
+    mip: u32,
+) -> Result<vk::ImageView, BufferError> {
+    let view_info = vk::ImageViewCreateInfo::builder()
+        .image(image.image())
+        .format(FORMAT)
+        .view_type(vk::ImageViewType::TYPE_2D_ARRAY)
+        .subresource_range(
+            vk::ImageSubresourceRange::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .base_mip_level(mip)
+                .level_count(1)
+                .base_array_layer(0)
+                .layer_count(6)
+                .build(),
+        );
+
+    unsafe { device.create_image_view(&view_info, None) }.or(Err(BufferError::OutOfMemory))
+}
+
+fn sampler(
+    device: &Arc<RenderDevice>,
+    address_u: vk::SamplerAddressMode,
+    address_v: vk::SamplerAddressMode,
+    max_lod: f32,
+) -> Result<vk::Sampler, BufferError> {
+    let sampler_info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+        .address_mode_u(address_u)
+        .address_mode_v(address_v)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .min_lod(0.0)
+        .max_lod(max_lod);
+
+    unsafe { device.create_sampler(&sampler_info, None) }.or(Err(BufferError::OutOfMemory))
+}
+
+unsafe fn dispatch_cube(pipeline: &Pipeline, uri: &UpdateRecordInfo, size: u32) {
+    pipeline.dispatch(
+        uri,
+        (size + LOCAL_SIZE - 1) / LOCAL_SIZE,
+        (size + LOCAL_SIZE - 1) / LOCAL_SIZE,
+        6,
+    );
+}
@@ -0,0 +1,559 @@
+use ash::{version::DeviceV1_0, vk};
+use gears_traits::UBO;
+use log::debug;
+use std::sync::Arc;
+
+use super::{
+    buffer::{
+        image::{Image, ImageBuilder, ImageUsage, Layout},
+        BufferError,
+    },
+    device::RenderDevice,
+    pipeline::{ComputePipelineBuilder, GraphicsPipelineBuilder, Pipeline, PipelineBuilder},
+    Renderer, UpdateRecordInfo,
+};
+use crate::fullscreen;
+
+const LOCAL_SIZE: u32 = 8;
+const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+type Configure<'a> = Box<dyn Fn(ComputePipelineBuilder<'a>) -> ComputePipelineBuilder<'a> + 'a>;
+type ConfigureFragment<'a> =
+    Box<dyn Fn(GraphicsPipelineBuilder<'a>) -> GraphicsPipelineBuilder<'a> + 'a>;
+
+/// A texture generated on the GPU by a compute or fragment shader, for ex. noise,
+/// gradients or simulations like water ripples.
+///
+/// Without feedback the shader only writes the RGBA16F output:
+///
+/// ```glsl
+/// layout(local_size_x = 8, local_size_y = 8) in;
+/// layout(rgba16f, set = 1, binding = 0) writeonly uniform image2D dst;
+/// ```
+///
+/// `with_feedback` adds a state of the same size, kept in two images that
+/// swap every `step`. The shader reads the previous state and writes the
+/// next one and the output, which can show something else than the state,
+/// for ex. normals of a height simulation:
+///
+/// ```glsl
+/// layout(set = 1, binding = 0) uniform sampler2D previous;
+/// layout(rgba16f, set = 1, binding = 1) writeonly uniform image2D next;
+/// layout(rgba16f, set = 1, binding = 2) writeonly uniform image2D dst;
+/// ```
+///
+/// Inputs added on the builder follow in call order. Everything starts
+/// cleared to zero, the output stays the same image and is left in
+/// `SHADER_READ_ONLY_OPTIMAL` for `output_view()` and `sampler()`. `step`
+/// takes an `UpdateRecordInfo`, usually from `UploadScope::record` to run
+/// once per frame.
+///
+/// Generators that are easier as fragment shaders are made with
+/// `new_fragment`. They get the uv of `fullscreen::VERT_SPIRV` and write the
+/// output, and with feedback first the next state, as color attachments:
+///
+/// ```glsl
+/// layout(location = 0) in vec2 uv;
+/// layout(set = 1, binding = 0) uniform sampler2D previous;
+/// layout(location = 0) out vec4 next;
+/// layout(location = 1) out vec4 dst;
+/// ```
+///
+/// Without feedback `dst` is `location = 0` and the inputs start at binding 0.
+pub struct ProceduralTexture {
+    device: Arc<RenderDevice>,
+
+    sampler: vk::Sampler,
+    output: Image,
+    // the previous state is `state[current]`
+    state: Option<[Image; 2]>,
+    // one per state direction, or one without feedback
+    pipelines: Vec<Pipeline>,
+    current: usize,
+    extent: vk::Extent2D,
+    // `None` for compute generators
+    fragment: Option<FragmentPass>,
+
+    cleared: bool,
+    steps: u64,
+}
+
+struct FragmentPass {
+    render_pass: vk::RenderPass,
+    // one per state direction, like the pipelines
+    framebuffers: Vec<vk::Framebuffer>,
+}
+
+pub struct ProceduralTextureBuilder<'a> {
+    device: Arc<RenderDevice>,
+    spirv: &'a [u32],
+    fragment: bool,
+    extent: vk::Extent2D,
+    feedback: bool,
+    configure: Vec<(Configure<'a>, ConfigureFragment<'a>)>,
+}
+
+impl ProceduralTexture {
+    /// `comp_spirv` is usually `COMP_SPIRV_WORDS` of a `pipeline!` with only a `comp` module.
+    pub fn new<'a>(
+        renderer: &Renderer,
+        comp_spirv: &'a [u32],
+        width: u32,
+        height: u32,
+    ) -> ProceduralTextureBuilder<'a> {
+        Self::new_with_device(renderer.rdevice.clone(), comp_spirv, width, height)
+    }
+
+    pub fn new_with_device<'a>(
+        device: Arc<RenderDevice>,
+        comp_spirv: &'a [u32],
+        width: u32,
+        height: u32,
+    ) -> ProceduralTextureBuilder<'a> {
+        ProceduralTextureBuilder {
+            device,
+            spirv: comp_spirv,
+            fragment: false,
+            extent: vk::Extent2D { width, height },
+            feedback: false,
+            configure: Vec::new(),
+        }
+    }
+
+    /// Generated by a fullscreen fragment shader, `frag_spirv` is usually
+    /// `FRAG_SPIRV_WORDS` of a `pipeline!` with only an `fs` module.
+    pub fn new_fragment<'a>(
+        renderer: &Renderer,
+        frag_spirv: &'a [u32],
+        width: u32,
+        height: u32,
+    ) -> ProceduralTextureBuilder<'a> {
+        Self::new_fragment_with_device(renderer.rdevice.clone(), frag_spirv, width, height)
+    }
+
+    pub fn new_fragment_with_device<'a>(
+        device: Arc<RenderDevice>,
+        frag_spirv: &'a [u32],
+        width: u32,
+        height: u32,
+    ) -> ProceduralTextureBuilder<'a> {
+        ProceduralTextureBuilder {
+            fragment: true,
+            ..Self::new_with_device(device, frag_spirv, width, height)
+        }
+    }
+
+    /// The pipeline for the next `step`, for ex. for `write_ubo`.
+    pub fn pipeline(&self) -> &Pipeline {
+        &self.pipelines[self.current % self.pipelines.len()]
+    }
+
+    /// Runs the shader once over the whole texture, a dispatch or a render pass.
+    pub unsafe fn step(&mut self, uri: &UpdateRecordInfo) {
+        self.step_raw(uri, |_| {});
+    }
+
+    /// `step` with the push constant block of `ProceduralTextureBuilder::with_push_constants`.
+    pub unsafe fn step_with<P: 'static + Copy>(&mut self, uri: &UpdateRecordInfo, push: &P) {
+        self.step_raw(uri, |pipeline| pipeline.push_constants_compute(uri, push));
+    }
+
+    /// Clears the output and the state to zero before the next `step`.
+    pub fn reset(&mut self) {
+        self.cleared = false;
+        self.steps = 0;
+    }
+
+    /// Number of steps since the creation or the last `reset`.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn output_view(&self) -> vk::ImageView {
+        self.output.view()
+    }
+
+    pub fn output(&self) -> &Image {
+        &self.output
+    }
+
+    /// The state written by the last `step`, `None` without feedback.
+    pub fn state_view(&self) -> Option<vk::ImageView> {
+        self.state.as_ref().map(|state| state[self.current].view())
+    }
+
+    /// Linear, clamp to edge sampler for reading the output.
+    pub fn sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+
+    pub fn width(&self) -> u32 {
+        self.extent.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.extent.height
+    }
+
+    unsafe fn step_raw<F: FnOnce(&Pipeline)>(&mut self, uri: &UpdateRecordInfo, push: F) {
+        if !self.cleared {
+            self.clear(uri);
+        }
+
+        let pipeline = &self.pipelines[self.current % self.pipelines.len()];
+        // written as storage images or color attachments
+        let write_layout = match self.fragment {
+            Some(_) => Layout::ColorAttachment,
+            None => Layout::General,
+        };
+        if let Some(state) = self.state.as_ref() {
+            uri.transition(&state[self.current], Layout::ShaderRead);
+            uri.transition(&state[1 - self.current], write_layout);
+        }
+        uri.transition(&self.output, write_layout);
+
+        match self.fragment.as_ref() {
+            Some(fragment) => {
+                let framebuffer = fragment.framebuffers[self.current % fragment.framebuffers.len()];
+                self.draw(uri, fragment.render_pass, framebuffer, pipeline, push);
+            }
+            None => {
+                pipeline.bind_compute(uri);
+                push(pipeline);
+                pipeline.dispatch(
+                    uri,
+                    (self.extent.width + LOCAL_SIZE - 1) / LOCAL_SIZE,
+                    (self.extent.height + LOCAL_SIZE - 1) / LOCAL_SIZE,
+                    1,
+                );
+            }
+        }
+
+        uri.transition(&self.output, Layout::ShaderRead);
+        if let Some(state) = self.state.as_ref() {
+            uri.transition(&state[1 - self.current], Layout::ShaderRead);
+            self.current = 1 - self.current;
+        }
+        self.steps += 1;
+    }
+
+    unsafe fn draw<F: FnOnce(&Pipeline)>(
+        &self,
+        uri: &UpdateRecordInfo,
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+        pipeline: &Pipeline,
+        push: F,
+    ) {
+        let scissor = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        };
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .framebuffer(framebuffer)
+            .render_pass(render_pass)
+            .render_area(scissor);
+
+        self.device
+            .cmd_set_viewport(uri.command_buffer, 0, &[viewport]);
+        self.device
+            .cmd_set_scissor(uri.command_buffer, 0, &[scissor]);
+        self.device.cmd_begin_render_pass(
+            uri.command_buffer,
+            &render_pass_begin_info,
+            vk::SubpassContents::INLINE,
+        );
+        pipeline.bind_compute(uri);
+        push(pipeline);
+        // the fullscreen triangle
+        self.device.cmd_draw(uri.command_buffer, 3, 1, 0, 0);
+        self.device.cmd_end_render_pass(uri.command_buffer);
+    }
+
+    unsafe fn clear(&mut self, uri: &UpdateRecordInfo) {
+        self.cleared = true;
+
+        let range = [vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build()];
+        let zero = vk::ClearColorValue { float32: [0.0; 4] };
+
+        let images = Some(&self.output)
+            .into_iter()
+            .chain(self.state.iter().flat_map(|state| state.iter()));
+        for image in images {
+            uri.transition(image, Layout::TransferDst);
+            self.device.cmd_clear_color_image(
+                uri.command_buffer,
+                image.image(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &zero,
+                &range,
+            );
+            uri.transition(image, Layout::ShaderRead);
+        }
+    }
+}
+
+impl Drop for ProceduralTexture {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_sampler(self.sampler, None);
+            if let Some(fragment) = self.fragment.take() {
+                fragment.destroy(&self.device);
+            }
+        }
+    }
+}
+
+impl<'a> ProceduralTextureBuilder<'a> {
+    /// Keeps a state between steps, see `ProceduralTexture`.
+    pub fn with_feedback(mut self) -> Self {
+        self.feedback = true;
+        self
+    }
+
+    /// Compute or fragment stage push constant block of type `P`, see `ProceduralTexture::step_with`.
+    pub fn with_push_constants<P: 'static + Copy>(mut self) -> Self {
+        self.configure.push((
+            Box::new(|builder| builder.with_push_constants::<P>()),
+            Box::new(|builder| builder.with_push_constants::<P>(vk::ShaderStageFlags::FRAGMENT)),
+        ));
+        self
+    }
+
+    /// Uniform block `U`, written through `ProceduralTexture::pipeline`.
+    pub fn with_ubo<U: 'static + UBO + Default + Send>(mut self) -> Self {
+        self.configure.push((
+            Box::new(|builder| builder.with_ubo::<U>()),
+            Box::new(|builder| builder.with_ubo::<U>()),
+        ));
+        self
+    }
+
+    /// Samples `view` with `sampler`, after the built-in bindings.
+    ///
+    /// `view` has to outlive the texture and be in `SHADER_READ_ONLY_OPTIMAL` on `step`.
+    pub fn with_sampled_image(mut self, view: vk::ImageView, sampler: vk::Sampler) -> Self {
+        self.configure.push((
+            Box::new(move |builder| builder.with_sampled_image(view, sampler)),
+            Box::new(move |builder| builder.with_sampled_image(view, sampler)),
+        ));
+        self
+    }
+
+    pub fn build(self) -> Result<ProceduralTexture, BufferError> {
+        let vk::Extent2D { width, height } = self.extent;
+        if width == 0 || height == 0 {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let device = self.device;
+        let usage = if self.fragment {
+            ImageUsage::BOTH
+        } else {
+            ImageUsage::READ | ImageUsage::STORAGE
+        };
+        let sampler = linear_sampler(&device)?;
+        let output = target_image(&device, width, height, usage)?;
+        let state = if self.feedback {
+            Some([
+                target_image(&device, width, height, usage)?,
+                target_image(&device, width, height, usage)?,
+            ])
+        } else {
+            None
+        };
+
+        // the state written and the output, per state direction
+        let targets = match state.as_ref() {
+            Some(state) => (0..2)
+                .map(|current| vec![state[1 - current].view(), output.view()])
+                .collect::<Vec<_>>(),
+            None => vec![vec![output.view()]],
+        };
+        let fragment = if self.fragment {
+            Some(FragmentPass::new(&device, &targets, self.extent)?)
+        } else {
+            None
+        };
+
+        // the sampled previous state, per state direction
+        let previous =
+            |current: usize| state.as_ref().map(|state| (state[current].view(), sampler));
+        let configure = &self.configure;
+        let spirv = self.spirv;
+        let pipeline = |current: usize| match fragment.as_ref() {
+            Some(fragment) => {
+                let mut builder =
+                    PipelineBuilder::new_with_device(device.clone(), fragment.render_pass, 1)
+                        .with_color_attachments(targets[current].len())
+                        .with_graphics_modules(fullscreen::VERT_SPIRV, spirv);
+                if let Some((view, sampler)) = previous(current) {
+                    builder = builder.with_sampled_image(view, sampler);
+                }
+                for (_, configure) in configure.iter() {
+                    builder = configure(builder);
+                }
+                builder.build(false)
+            }
+            None => {
+                let mut builder =
+                    PipelineBuilder::new_with_device(device.clone(), vk::RenderPass::null(), 1)
+                        .with_compute_module(spirv);
+                if let Some((view, sampler)) = previous(current) {
+                    builder = builder.with_sampled_image(view, sampler);
+                }
+                for &view in targets[current].iter() {
+                    builder = builder.with_storage_image(view);
+                }
+                for (configure, _) in configure.iter() {
+                    builder = configure(builder);
+                }
+                builder.build()
+            }
+        };
+
+        let pipelines = (0..targets.len())
+            .map(pipeline)
+            .collect::<Result<Vec<_>, _>>();
+        let pipelines = match pipelines {
+            Ok(pipelines) => pipelines,
+            Err(err) => {
+                if let Some(fragment) = fragment {
+                    unsafe { fragment.destroy(&device) };
+                }
+                unsafe { device.destroy_sampler(sampler, None) };
+                return Err(err);
+            }
+        };
+
+        debug!(
+            "ProceduralTexture created: {}x{}, feedback: {}, fragment: {}",
+            width, height, self.feedback, self.fragment
+        );
+
+        Ok(ProceduralTexture {
+            device,
+
+            sampler,
+            output,
+            state,
+            pipelines,
+            current: 0,
+            extent: self.extent,
+            fragment,
+
+            cleared: false,
+            steps: 0,
+        })
+    }
+}
+
+impl FragmentPass {
+    // a framebuffer per set of `targets`, which all have the same count
+    fn new(
+        device: &Arc<RenderDevice>,
+        targets: &[Vec<vk::ImageView>],
+        extent: vk::Extent2D,
+    ) -> Result<Self, BufferError> {
+        // layouts are transitioned around the pass, every pixel is written
+        let attachment = vk::AttachmentDescription::builder()
+            .format(FORMAT)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build();
+        let attachments = vec![attachment; targets[0].len()];
+        let color_attachment_refs = (0..attachments.len())
+            .map(|i| {
+                vk::AttachmentReference::builder()
+                    .attachment(i as u32)
+                    .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let subpasses = [vk::SubpassDescription::builder()
+            .color_attachments(&color_attachment_refs[..])
+            .build()];
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments[..])
+            .subpasses(&subpasses);
+        let render_pass = unsafe { device.create_render_pass(&render_pass_info, None) }
+            .or(Err(BufferError::OutOfMemory))?;
+
+        let mut pass = Self {
+            render_pass,
+            framebuffers: Vec::new(),
+        };
+        for views in targets {
+            let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                .attachments(&views[..])
+                .render_pass(render_pass)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1);
+            match unsafe { device.create_framebuffer(&framebuffer_info, None) } {
+                Ok(framebuffer) => pass.framebuffers.push(framebuffer),
+                Err(_) => {
+                    unsafe { pass.destroy(device) };
+                    return Err(BufferError::OutOfMemory);
+                }
+            }
+        }
+        Ok(pass)
+    }
+
+    unsafe fn destroy(self, device: &RenderDevice) {
+        for framebuffer in self.framebuffers {
+            device.destroy_framebuffer(framebuffer, None);
+        }
+        device.destroy_render_pass(self.render_pass, None);
+    }
+}
+
+// also cleared with a transfer
+fn target_image(
+    device: &Arc<RenderDevice>,
+    width: u32,
+    height: u32,
+    usage: ImageUsage,
+) -> Result<Image, BufferError> {
+    ImageBuilder::new_with_device(device.clone())
+        .with_width(width)
+        .with_height(height)
+        .build(usage | ImageUsage::UPLOAD, FORMAT)
+}
+
+fn linear_sampler(device: &Arc<RenderDevice>) -> Result<vk::Sampler, BufferError> {
+    let sampler_info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .min_lod(0.0)
+        .max_lod(0.0);
+
+    unsafe { device.create_sampler(&sampler_info, None) }.or(Err(BufferError::OutOfMemory))
+}
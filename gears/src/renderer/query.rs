@@ -1,12 +1,14 @@
 use ash::{version::DeviceV1_0, vk};
-use log::debug;
+use log::{debug, warn};
+use parking_lot::Mutex;
 use std::{
+    mem,
     ops::{Add, AddAssign},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use crate::renderer::RenderRecordInfo;
+use crate::{logging, renderer::RenderRecordInfo};
 
 use super::device::RenderDevice;
 
@@ -20,6 +22,19 @@ const TIMESTAMP_STAGES: [vk::PipelineStageFlags; 6] = [
 ];
 const TIMESTAMP_COUNT: u32 = TIMESTAMP_STAGES.len() as u32;
 
+// named scopes per recording, each takes a begin and an end timestamp
+const SCOPE_CAPACITY: usize = 64;
+
+// results are written in the bit order of these flags
+const STATISTICS_COUNT: usize = 5;
+const STATISTICS_FLAGS: vk::QueryPipelineStatisticFlags = vk::QueryPipelineStatisticFlags::from_raw(
+    vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES.as_raw()
+        | vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES.as_raw()
+        | vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw()
+        | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw(),
+);
+
 pub struct PerfQueryResult {
     pub whole_pipeline: Duration,
 
@@ -27,6 +42,28 @@ pub struct PerfQueryResult {
     pub fragment: Duration,
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineStatsResult {
+    pub vertices: u64,
+    pub primitives: u64,
+    pub vertex_invocations: u64,
+    /// Primitives that passed clipping, compare with `primitives` for culling efficiency.
+    pub clipped_primitives: u64,
+    /// Compare with the framebuffer size for overdraw.
+    pub fragment_invocations: u64,
+}
+
+/// Every `RenderRecordInfo::scope` with the same name in one frame, summed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeTiming {
+    pub name: &'static str,
+    pub calls: usize,
+    /// Recording time, from the last rerecord.
+    pub cpu: Duration,
+    /// Scaled by the device's `timestamp_period`.
+    pub gpu: Duration,
+}
+
 #[derive(Debug)]
 pub enum PerfQueryError {
     NotDone,
@@ -38,6 +75,26 @@ pub struct PerfQuery {
     query_pool: vk::QueryPool,
 }
 
+pub struct PipelineStatsQuery {
+    device: Arc<RenderDevice>,
+    query_pool: vk::QueryPool,
+}
+
+pub struct ScopeQuery {
+    device: Arc<RenderDevice>,
+    query_pool: vk::QueryPool,
+    // in recording order, with the CPU time once ended
+    scopes: Mutex<Vec<(&'static str, Duration)>>,
+}
+
+/// Timestamps the commands recorded while it is alive, see `RenderRecordInfo::scope`.
+#[must_use = "the scope ends when dropped"]
+pub struct ProfileScope<'a> {
+    rri: &'a RenderRecordInfo,
+    index: Option<usize>,
+    begin: Instant,
+}
+
 impl Default for PerfQueryResult {
     fn default() -> Self {
         Self {
@@ -65,6 +122,33 @@ impl Add for PerfQueryResult {
     }
 }
 
+impl AddAssign for PipelineStatsResult {
+    fn add_assign(&mut self, rhs: Self) {
+        self.vertices += rhs.vertices;
+        self.primitives += rhs.primitives;
+        self.vertex_invocations += rhs.vertex_invocations;
+        self.clipped_primitives += rhs.clipped_primitives;
+        self.fragment_invocations += rhs.fragment_invocations;
+    }
+}
+
+impl AddAssign for ScopeTiming {
+    fn add_assign(&mut self, rhs: Self) {
+        self.calls += rhs.calls;
+        self.cpu += rhs.cpu;
+        self.gpu += rhs.gpu;
+    }
+}
+
+impl Add for PipelineStatsResult {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self += rhs;
+        self
+    }
+}
+
 impl PerfQuery {
     pub fn new_with_device(device: Arc<RenderDevice>) -> Self {
         let query_pool_info = vk::QueryPoolCreateInfo::builder()
@@ -80,7 +164,7 @@ impl PerfQuery {
 
     pub unsafe fn reset(&self, rri: &RenderRecordInfo) {
         if rri.debug_calls {
-            debug!("cmd_reset_query_pool");
+            debug!(target: logging::COMMANDS, "cmd_reset_query_pool");
         }
 
         self.device
@@ -89,7 +173,7 @@ impl PerfQuery {
 
     unsafe fn query(&self, rri: &RenderRecordInfo, id: u32) {
         if rri.debug_calls {
-            debug!("cmd_write_timestamp");
+            debug!(target: logging::COMMANDS, "cmd_write_timestamp");
         }
 
         self.device.cmd_write_timestamp(
@@ -147,3 +231,239 @@ impl PerfQuery {
         self.get_with_flags(vk::QueryResultFlags::WAIT | vk::QueryResultFlags::TYPE_64)
     } */
 }
+
+impl PipelineStatsQuery {
+    /// `None` if the device does not support pipeline statistics queries.
+    pub fn new_with_device(device: Arc<RenderDevice>) -> Option<Self> {
+        if !device.pipeline_statistics {
+            return None;
+        }
+
+        let query_pool_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::PIPELINE_STATISTICS)
+            .pipeline_statistics(STATISTICS_FLAGS)
+            .query_count(1);
+
+        // Unsafe: device must be valid
+        let query_pool = unsafe { device.create_query_pool(&query_pool_info, None) }
+            .expect("Could not create a query pool");
+
+        Some(Self { device, query_pool })
+    }
+
+    // outside of a render pass
+    pub unsafe fn reset(&self, rri: &RenderRecordInfo) {
+        if rri.debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_reset_query_pool");
+        }
+
+        self.device
+            .cmd_reset_query_pool(rri.command_buffer, self.query_pool, 0, 1);
+    }
+
+    pub unsafe fn begin(&self, rri: &RenderRecordInfo) {
+        if rri.debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_begin_query");
+        }
+
+        self.device.cmd_begin_query(
+            rri.command_buffer,
+            self.query_pool,
+            0,
+            vk::QueryControlFlags::empty(),
+        );
+    }
+
+    pub unsafe fn end(&self, rri: &RenderRecordInfo) {
+        if rri.debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_end_query");
+        }
+
+        self.device
+            .cmd_end_query(rri.command_buffer, self.query_pool, 0);
+    }
+
+    pub fn get(&self) -> Result<PipelineStatsResult, PerfQueryError> {
+        let mut data = [0u64; STATISTICS_COUNT];
+        let data_size = mem::size_of_val(&data);
+
+        // ash's get_query_pool_results assumes one value per query
+        let result = unsafe {
+            self.device.fp_v1_0().get_query_pool_results(
+                self.device.handle(),
+                self.query_pool,
+                0,
+                1,
+                data_size,
+                data.as_mut_ptr() as *mut _,
+                data_size as u64,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        };
+        if result != vk::Result::SUCCESS {
+            return Err(PerfQueryError::NotDone);
+        }
+
+        Ok(PipelineStatsResult {
+            vertices: data[0],
+            primitives: data[1],
+            vertex_invocations: data[2],
+            clipped_primitives: data[3],
+            fragment_invocations: data[4],
+        })
+    }
+}
+
+impl Drop for PipelineStatsQuery {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_query_pool(self.query_pool, None) };
+    }
+}
+
+impl ScopeQuery {
+    pub fn new_with_device(device: Arc<RenderDevice>) -> Self {
+        let query_pool_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(SCOPE_CAPACITY as u32 * 2);
+
+        // Unsafe: device must be valid
+        let query_pool = unsafe { device.create_query_pool(&query_pool_info, None) }
+            .expect("Could not create a query pool");
+
+        Self {
+            device,
+            query_pool,
+            scopes: Mutex::new(Vec::new()),
+        }
+    }
+
+    // outside of a render pass, before any scope
+    pub unsafe fn reset(&self, rri: &RenderRecordInfo) {
+        if rri.debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_reset_query_pool");
+        }
+
+        self.device.cmd_reset_query_pool(
+            rri.command_buffer,
+            self.query_pool,
+            0,
+            SCOPE_CAPACITY as u32 * 2,
+        );
+        self.scopes.lock().clear();
+    }
+
+    unsafe fn query(&self, rri: &RenderRecordInfo, stage: vk::PipelineStageFlags, id: usize) {
+        if rri.debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_write_timestamp");
+        }
+
+        self.device
+            .cmd_write_timestamp(rri.command_buffer, stage, self.query_pool, id as u32);
+    }
+
+    unsafe fn begin(&self, rri: &RenderRecordInfo, name: &'static str) -> Option<usize> {
+        let mut scopes = self.scopes.lock();
+        let index = scopes.len();
+        if index >= SCOPE_CAPACITY {
+            if index == SCOPE_CAPACITY {
+                warn!(
+                    "More than {} profiling scopes in one recording, '{}' and later ones are ignored",
+                    SCOPE_CAPACITY, name
+                );
+                // only warn once
+                scopes.push((name, Duration::from_secs(0)));
+            }
+            return None;
+        }
+        scopes.push((name, Duration::from_secs(0)));
+        drop(scopes);
+
+        self.query(rri, vk::PipelineStageFlags::TOP_OF_PIPE, index * 2);
+        Some(index)
+    }
+
+    unsafe fn end(&self, rri: &RenderRecordInfo, index: usize, cpu: Duration) {
+        self.query(rri, vk::PipelineStageFlags::BOTTOM_OF_PIPE, index * 2 + 1);
+        self.scopes.lock()[index].1 = cpu;
+    }
+
+    /// Timings of the last submitted recording, aggregated by name in first use order.
+    pub fn get(&self) -> Result<Vec<ScopeTiming>, PerfQueryError> {
+        let scopes = self.scopes.lock();
+        let count = scopes.len().min(SCOPE_CAPACITY);
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut data = vec![0u64; count * 2];
+        unsafe {
+            self.device.get_query_pool_results(
+                self.query_pool,
+                0,
+                count as u32 * 2,
+                &mut data,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        }
+        .or(Err(PerfQueryError::NotDone))?;
+
+        let mut timings: Vec<ScopeTiming> = Vec::new();
+        for (&(name, cpu), gpu) in scopes.iter().zip(data.chunks_exact(2)) {
+            let timing = ScopeTiming {
+                name,
+                calls: 1,
+                cpu,
+                gpu: ticks_to_duration(
+                    gpu[1].saturating_sub(gpu[0]),
+                    self.device.limits.timestamp_period,
+                ),
+            };
+            match timings.iter_mut().find(|timing| timing.name == name) {
+                Some(existing) => *existing += timing,
+                None => timings.push(timing),
+            }
+        }
+        Ok(timings)
+    }
+}
+
+impl Drop for ScopeQuery {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_query_pool(self.query_pool, None) };
+    }
+}
+
+impl<'a> ProfileScope<'a> {
+    pub(super) fn new(rri: &'a RenderRecordInfo, name: &'static str) -> Self {
+        Self {
+            rri,
+            index: unsafe { rri.scopes.begin(rri, name) },
+            begin: Instant::now(),
+        }
+    }
+}
+
+impl<'a> Drop for ProfileScope<'a> {
+    fn drop(&mut self) {
+        if let Some(index) = self.index {
+            unsafe { self.rri.scopes.end(self.rri, index, self.begin.elapsed()) };
+        }
+    }
+}
+
+// timestamps count ticks of `timestamp_period` nanoseconds, not nanoseconds
+fn ticks_to_duration(ticks: u64, timestamp_period: f32) -> Duration {
+    Duration::from_nanos((ticks as f64 * timestamp_period as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_period() {
+        assert_eq!(Duration::from_nanos(1000), ticks_to_duration(1000, 1.0));
+        assert_eq!(Duration::from_nanos(2500), ticks_to_duration(1000, 2.5));
+        assert_eq!(Duration::from_nanos(0), ticks_to_duration(0, 83.333));
+    }
+}
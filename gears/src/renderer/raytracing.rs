@@ -0,0 +1,477 @@
+use ash::{
+    extensions::khr,
+    version::{DeviceV1_0, DeviceV1_2},
+    vk,
+};
+use log::debug;
+use std::{
+    mem, slice,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use super::{
+    buffer::{create_buffer, BufferError},
+    device::RenderDevice,
+    Renderer, UpdateRecordInfo,
+};
+
+/// Ray tracing extension functions, see `Renderer::ray_tracing`.
+pub struct RayTracing {
+    pub acceleration_structure: khr::AccelerationStructure,
+    pub pipeline: khr::RayTracingPipeline,
+
+    pub handle_size: u32,
+    pub handle_alignment: u32,
+    pub base_alignment: u32,
+    pub scratch_alignment: u32,
+}
+
+/// Bottom level (triangles) or top level (instances) acceleration structure.
+///
+/// The build is recorded by the first `update` and the structure can be
+/// traced against after it. Bottom levels must be updated before the top
+/// levels referring to them, in the same or an earlier update command buffer.
+/// Build inputs and scratch memory are kept until drop.
+pub struct AccelerationStructure {
+    device: Arc<RenderDevice>,
+
+    structure: vk::AccelerationStructureKHR,
+    address: vk::DeviceAddress,
+    geometry: Geometry,
+    primitive_count: u32,
+
+    requested_build: AtomicBool,
+    // structure storage, scratch and inputs
+    buffers: Vec<(vk::Buffer, vk::DeviceMemory)>,
+    scratch: vk::DeviceAddress,
+}
+
+/// Top level acceleration structure entry, `custom_index` is
+/// ```gl_InstanceCustomIndexEXT``` in hit shaders.
+pub struct AccelerationStructureInstance<'a> {
+    pub blas: &'a AccelerationStructure,
+    /// Row major 3x4 object to world transform.
+    pub transform: [f32; 12],
+    pub custom_index: u32,
+}
+
+enum Geometry {
+    Triangles {
+        vertices: vk::DeviceAddress,
+        indices: vk::DeviceAddress,
+        max_vertex: u32,
+    },
+    Instances {
+        instances: vk::DeviceAddress,
+    },
+}
+
+impl RayTracing {
+    pub(crate) fn new(
+        instance: &ash::Instance,
+        device: &ash::Device,
+        pdevice: vk::PhysicalDevice,
+    ) -> Self {
+        let properties = unsafe { khr::RayTracingPipeline::get_properties(instance, pdevice) };
+        debug!("Ray tracing properties: {:?}", properties);
+        let acceleration_structure_properties =
+            unsafe { khr::AccelerationStructure::get_properties(instance, pdevice) };
+        debug!(
+            "Acceleration structure properties: {:?}",
+            acceleration_structure_properties
+        );
+
+        Self {
+            acceleration_structure: khr::AccelerationStructure::new(instance, device),
+            pipeline: khr::RayTracingPipeline::new(instance, device),
+
+            handle_size: properties.shader_group_handle_size,
+            handle_alignment: properties.shader_group_handle_alignment,
+            base_alignment: properties.shader_group_base_alignment,
+            scratch_alignment: acceleration_structure_properties
+                .min_acceleration_structure_scratch_offset_alignment
+                .max(1),
+        }
+    }
+}
+
+impl Geometry {
+    fn ty(&self) -> vk::AccelerationStructureTypeKHR {
+        match self {
+            Geometry::Triangles { .. } => vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL,
+            Geometry::Instances { .. } => vk::AccelerationStructureTypeKHR::TOP_LEVEL,
+        }
+    }
+
+    fn vk(&self) -> vk::AccelerationStructureGeometryKHR {
+        let (geometry_type, geometry) = match *self {
+            Geometry::Triangles {
+                vertices,
+                indices,
+                max_vertex,
+            } => (
+                vk::GeometryTypeKHR::TRIANGLES,
+                vk::AccelerationStructureGeometryDataKHR {
+                    triangles: vk::AccelerationStructureGeometryTrianglesDataKHR::builder()
+                        .vertex_format(vk::Format::R32G32B32_SFLOAT)
+                        .vertex_data(vk::DeviceOrHostAddressConstKHR {
+                            device_address: vertices,
+                        })
+                        .vertex_stride(mem::size_of::<[f32; 3]>() as u64)
+                        .max_vertex(max_vertex)
+                        .index_type(vk::IndexType::UINT32)
+                        .index_data(vk::DeviceOrHostAddressConstKHR {
+                            device_address: indices,
+                        })
+                        .build(),
+                },
+            ),
+            Geometry::Instances { instances } => (
+                vk::GeometryTypeKHR::INSTANCES,
+                vk::AccelerationStructureGeometryDataKHR {
+                    instances: vk::AccelerationStructureGeometryInstancesDataKHR::builder()
+                        .array_of_pointers(false)
+                        .data(vk::DeviceOrHostAddressConstKHR {
+                            device_address: instances,
+                        })
+                        .build(),
+                },
+            ),
+        };
+
+        vk::AccelerationStructureGeometryKHR::builder()
+            .geometry_type(geometry_type)
+            .geometry(geometry)
+            .flags(vk::GeometryFlagsKHR::OPAQUE)
+            .build()
+    }
+}
+
+impl AccelerationStructure {
+    /// Indexed triangle list, every 3 `indices` are one triangle.
+    pub fn bottom_level(
+        renderer: &Renderer,
+        vertices: &[[f32; 3]],
+        indices: &[u32],
+    ) -> Result<Self, BufferError> {
+        Self::bottom_level_with_device(renderer.rdevice.clone(), vertices, indices)
+    }
+
+    pub fn bottom_level_with_device(
+        device: Arc<RenderDevice>,
+        vertices: &[[f32; 3]],
+        indices: &[u32],
+    ) -> Result<Self, BufferError> {
+        if vertices.is_empty() || indices.len() < 3 || indices.len() % 3 != 0 {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let usage = vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR;
+        let (vertex_buffer, vertex_memory, vertex_address) =
+            host_address_buffer(&device, usage, as_bytes(vertices))?;
+        let (index_buffer, index_memory, index_address) =
+            host_address_buffer(&device, usage, as_bytes(indices))?;
+
+        Self::new_with_device(
+            device,
+            Geometry::Triangles {
+                vertices: vertex_address,
+                indices: index_address,
+                max_vertex: vertices.len() as u32 - 1,
+            },
+            indices.len() as u32 / 3,
+            vec![(vertex_buffer, vertex_memory), (index_buffer, index_memory)],
+        )
+    }
+
+    /// The bottom levels must outlive the top level.
+    pub fn top_level(
+        renderer: &Renderer,
+        instances: &[AccelerationStructureInstance],
+    ) -> Result<Self, BufferError> {
+        Self::top_level_with_device(renderer.rdevice.clone(), instances)
+    }
+
+    pub fn top_level_with_device(
+        device: Arc<RenderDevice>,
+        instances: &[AccelerationStructureInstance],
+    ) -> Result<Self, BufferError> {
+        if instances.is_empty() {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let instances = instances
+            .iter()
+            .map(|instance| vk::AccelerationStructureInstanceKHR {
+                transform: vk::TransformMatrixKHR {
+                    matrix: instance.transform,
+                },
+                // 24 bit custom index and 8 bit visibility mask
+                instance_custom_index_and_mask: (instance.custom_index & 0xFFFFFF) | (0xFF << 24),
+                // 24 bit hit group offset and 8 bit flags
+                instance_shader_binding_table_record_offset_and_flags:
+                    vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw() << 24,
+                acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
+                    device_handle: instance.blas.address,
+                },
+            })
+            .collect::<Vec<_>>();
+
+        let (instance_buffer, instance_memory, instance_address) = host_address_buffer(
+            &device,
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+            as_bytes(&instances[..]),
+        )?;
+
+        Self::new_with_device(
+            device,
+            Geometry::Instances {
+                instances: instance_address,
+            },
+            instances.len() as u32,
+            vec![(instance_buffer, instance_memory)],
+        )
+    }
+
+    fn new_with_device(
+        device: Arc<RenderDevice>,
+        geometry: Geometry,
+        primitive_count: u32,
+        mut buffers: Vec<(vk::Buffer, vk::DeviceMemory)>,
+    ) -> Result<Self, BufferError> {
+        let ray_tracing = match device.ray_tracing.as_ref() {
+            Some(ray_tracing) => ray_tracing,
+            None => {
+                free(&device, &buffers);
+                return Err(BufferError::UnsupportedFeature("ray tracing"));
+            }
+        };
+
+        let geometries = [geometry.vk()];
+        let build_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+            .ty(geometry.ty())
+            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+            .geometries(&geometries);
+        let sizes = unsafe {
+            ray_tracing
+                .acceleration_structure
+                .get_acceleration_structure_build_sizes(
+                    vk::AccelerationStructureBuildTypeKHR::DEVICE,
+                    &build_info,
+                    &[primitive_count],
+                )
+        };
+
+        let storage = address_buffer(
+            &device,
+            sizes.acceleration_structure_size,
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+        // the scratch address has to be aligned, over-allocate to align it up
+        let scratch_alignment = ray_tracing.scratch_alignment as vk::DeviceSize;
+        let scratch = address_buffer(
+            &device,
+            sizes.build_scratch_size + scratch_alignment - 1,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+        let ((storage, storage_memory, _), (scratch, scratch_memory, scratch_address)) =
+            match (storage, scratch) {
+                (Ok(storage), Ok(scratch)) => (storage, scratch),
+                (storage, scratch) => {
+                    buffers.extend(storage.iter().chain(scratch.iter()).map(|b| (b.0, b.1)));
+                    free(&device, &buffers);
+                    return Err(BufferError::OutOfMemory);
+                }
+            };
+        buffers.push((storage, storage_memory));
+        buffers.push((scratch, scratch_memory));
+        let scratch_address = scratch_address.div_ceil(scratch_alignment) * scratch_alignment;
+
+        let structure_info = vk::AccelerationStructureCreateInfoKHR::builder()
+            .buffer(storage)
+            .size(sizes.acceleration_structure_size)
+            .ty(geometry.ty());
+        let structure = match unsafe {
+            ray_tracing
+                .acceleration_structure
+                .create_acceleration_structure(&structure_info, None)
+        } {
+            Ok(structure) => structure,
+            Err(_) => {
+                free(&device, &buffers);
+                return Err(BufferError::OutOfMemory);
+            }
+        };
+
+        let address_info = vk::AccelerationStructureDeviceAddressInfoKHR::builder()
+            .acceleration_structure(structure);
+        let address = unsafe {
+            ray_tracing
+                .acceleration_structure
+                .get_acceleration_structure_device_address(&address_info)
+        };
+
+        debug!(
+            "AccelerationStructure created: {:?} with {} primitives, {} bytes",
+            geometry.ty(),
+            primitive_count,
+            sizes.acceleration_structure_size
+        );
+
+        Ok(Self {
+            device,
+
+            structure,
+            address,
+            geometry,
+            primitive_count,
+
+            requested_build: AtomicBool::new(true),
+            buffers,
+            scratch: scratch_address,
+        })
+    }
+
+    /// Records the build, only the first call records anything.
+    pub unsafe fn update(&self, uri: &UpdateRecordInfo) -> bool {
+        let requested_build = self.requested_build.swap(false, Ordering::SeqCst);
+        if !requested_build {
+            return false;
+        }
+
+        let ray_tracing = self.device.ray_tracing.as_ref().unwrap();
+
+        let geometries = [self.geometry.vk()];
+        let build_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+            .ty(self.geometry.ty())
+            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+            .dst_acceleration_structure(self.structure)
+            .geometries(&geometries)
+            .scratch_data(vk::DeviceOrHostAddressKHR {
+                device_address: self.scratch,
+            })
+            .build();
+        let range = vk::AccelerationStructureBuildRangeInfoKHR::builder()
+            .primitive_count(self.primitive_count)
+            .build();
+
+        ray_tracing
+            .acceleration_structure
+            .cmd_build_acceleration_structures(
+                uri.command_buffer,
+                slice::from_ref(&build_info),
+                &[slice::from_ref(&range)],
+            );
+
+        // later builds and traces read this one
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR)
+            .dst_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR);
+        self.device.cmd_pipeline_barrier(
+            uri.command_buffer,
+            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR
+                | vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+            vk::DependencyFlags::empty(),
+            slice::from_ref(&barrier),
+            &[],
+            &[],
+        );
+
+        true
+    }
+
+    pub fn address(&self) -> vk::DeviceAddress {
+        self.address
+    }
+
+    pub fn get(&self) -> vk::AccelerationStructureKHR {
+        self.structure
+    }
+}
+
+impl Drop for AccelerationStructure {
+    fn drop(&mut self) {
+        unsafe {
+            self.device
+                .ray_tracing
+                .as_ref()
+                .unwrap()
+                .acceleration_structure
+                .destroy_acceleration_structure(self.structure, None);
+        }
+        free(&self.device, &self.buffers);
+    }
+}
+
+/// Buffer with ```SHADER_DEVICE_ADDRESS``` usage and its address.
+pub(crate) fn address_buffer(
+    device: &Arc<RenderDevice>,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<(vk::Buffer, vk::DeviceMemory, vk::DeviceAddress), BufferError> {
+    let (buffer, memory) = create_buffer(
+        device,
+        size as usize,
+        usage | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+        vk::SharingMode::EXCLUSIVE,
+        properties,
+    )?;
+
+    let address_info = vk::BufferDeviceAddressInfo::builder().buffer(buffer);
+    let address = unsafe { device.get_buffer_device_address(&address_info) };
+
+    Ok((buffer, memory, address))
+}
+
+/// Host visible `address_buffer` filled with `data`.
+pub(crate) fn host_address_buffer(
+    device: &Arc<RenderDevice>,
+    usage: vk::BufferUsageFlags,
+    data: &[u8],
+) -> Result<(vk::Buffer, vk::DeviceMemory, vk::DeviceAddress), BufferError> {
+    let (buffer, memory, address) = address_buffer(
+        device,
+        data.len() as u64,
+        usage,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+
+    let result = unsafe {
+        device
+            .map_memory(memory, 0, data.len() as u64, vk::MemoryMapFlags::empty())
+            .map(|ptr| {
+                (ptr as *mut u8).copy_from_nonoverlapping(data.as_ptr(), data.len());
+                device.unmap_memory(memory);
+            })
+    };
+
+    match result {
+        Ok(()) => Ok((buffer, memory, address)),
+        Err(_) => {
+            free(device, &[(buffer, memory)]);
+            Err(BufferError::OutOfMemory)
+        }
+    }
+}
+
+fn free(device: &RenderDevice, buffers: &[(vk::Buffer, vk::DeviceMemory)]) {
+    for &(buffer, memory) in buffers {
+        unsafe {
+            device.destroy_buffer(buffer, None);
+            device.free_memory(memory, None);
+        }
+    }
+}
+
+fn as_bytes<T>(data: &[T]) -> &[u8] {
+    unsafe { slice::from_raw_parts(data.as_ptr() as *const u8, data.len() * mem::size_of::<T>()) }
+}
@@ -0,0 +1,169 @@
+use ash::{version::DeviceV1_0, vk};
+use parking_lot::Mutex;
+use std::{mem, sync::Arc};
+
+use super::{
+    buffer::{
+        create_buffer,
+        image::{Image, Layout},
+        BufferError,
+    },
+    device::RenderDevice,
+    Renderer, UpdateRecordInfo,
+};
+
+/// A pixel copied by `PixelReadback`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadPixel {
+    pub x: u32,
+    pub y: u32,
+    /// Raw 32 bit texel, `f32::from_bits` for depth.
+    pub value: u32,
+}
+
+/// Asynchronous readback of single 32 bit pixels, like object ids or depth.
+///
+/// Each swapchain image has its own slot, so a copy is read when its image
+/// comes around again and its frame is known to be finished.
+pub struct PixelReadback {
+    device: Arc<RenderDevice>,
+
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    copied: Mutex<Vec<Option<(u32, u32)>>>,
+
+    request: Mutex<Option<(u32, u32)>>,
+    result: Mutex<Option<ReadPixel>>,
+}
+
+impl PixelReadback {
+    pub fn new(renderer: &Renderer) -> Result<Self, BufferError> {
+        Self::new_with_device(renderer.rdevice.clone(), renderer.image_count())
+    }
+
+    pub fn new_with_device(
+        device: Arc<RenderDevice>,
+        image_count: usize,
+    ) -> Result<Self, BufferError> {
+        let (buffer, memory) = create_buffer(
+            &device,
+            image_count * mem::size_of::<u32>(),
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::SharingMode::EXCLUSIVE,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        Ok(Self {
+            device,
+
+            buffer,
+            memory,
+            copied: Mutex::new(vec![None; image_count]),
+
+            request: Mutex::new(None),
+            result: Mutex::new(None),
+        })
+    }
+
+    /// Copies pixel `x`, `y` on the next `update`, it has to be inside the image.
+    pub fn request(&self, x: u32, y: u32) {
+        *self.request.lock() = Some((x, y));
+    }
+
+    /// The last finished copy.
+    pub fn get(&self) -> Option<ReadPixel> {
+        *self.result.lock()
+    }
+
+    /// Reads the finished copy of this swapchain image, then copies the requested pixel.
+    ///
+    /// `image` has to be a single sampled 32 bit color or `D32_SFLOAT` image
+    /// written by an earlier submission, like a `RenderTarget` of the last frame.
+    /// Returns true if anything was recorded.
+    pub unsafe fn update(&self, uri: &UpdateRecordInfo, image: &Image) -> bool {
+        let offset = (uri.image_index * mem::size_of::<u32>()) as u64;
+
+        if let Some((x, y)) = self.copied.lock()[uri.image_index].take() {
+            let mapping = self
+                .device
+                .map_memory(
+                    self.memory,
+                    offset,
+                    mem::size_of::<u32>() as u64,
+                    vk::MemoryMapFlags::empty(),
+                )
+                .expect("Readback map failed") as *const u32;
+            let value = mapping.read();
+            self.device.unmap_memory(self.memory);
+
+            *self.result.lock() = Some(ReadPixel { x, y, value });
+        }
+
+        let (x, y) = match self.request.lock().take() {
+            Some(pixel) => pixel,
+            None => return false,
+        };
+
+        let aspect_mask = if image.aspects().contains(vk::ImageAspectFlags::DEPTH) {
+            vk::ImageAspectFlags::DEPTH
+        } else {
+            vk::ImageAspectFlags::COLOR
+        };
+        let regions = [vk::BufferImageCopy::builder()
+            .buffer_offset(offset)
+            .image_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(aspect_mask)
+                    .mip_level(0)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .image_offset(vk::Offset3D {
+                x: x as i32,
+                y: y as i32,
+                z: 0,
+            })
+            .image_extent(vk::Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            })
+            .build()];
+        let host_barrier = [vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .build()];
+
+        image.transition(uri.command_buffer, Layout::TransferSrc);
+        self.device.cmd_copy_image_to_buffer(
+            uri.command_buffer,
+            image.image(),
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            self.buffer,
+            &regions,
+        );
+        image.transition(uri.command_buffer, Layout::ShaderRead);
+        self.device.cmd_pipeline_barrier(
+            uri.command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &host_barrier,
+            &[],
+            &[],
+        );
+
+        self.copied.lock()[uri.image_index] = Some((x, y));
+        true
+    }
+}
+
+impl Drop for PixelReadback {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_buffer(self.buffer, None);
+            self.device.free_memory(self.memory, None);
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use ash::{version::DeviceV1_0, vk};
+use cgmath::Vector4;
+use log::{debug, Level};
+use parking_lot::Mutex;
+use std::sync::{atomic::AtomicUsize, atomic::Ordering};
+
+use crate::logging;
+
+use crate::loops::frame::FrameInfo;
+
+use super::{
+    buffer::{
+        index::{IndexBuffer, UInt},
+        vertex::VertexBuffer,
+        Buffer,
+    },
+    pipeline::Pipeline,
+    sort::{DrawKey, DrawQueue},
+    ImmediateFrameInfo, RenderObject, RenderRecordBeginInfo, RenderRecordInfo, Renderer,
+    UpdateRecordInfo,
+};
+
+/// One frame of `RendererRecord::frame`.
+///
+/// The scopes it hands out borrow it mutably, so only one is open at a
+/// time. Anything bound or drawn in a `RecordScope` is used by every
+/// later frame until the next rerecord and must stay alive until then.
+pub struct FrameCtx<'a> {
+    renderer: &'a Renderer,
+    render_object: &'a mut RenderObject,
+    time: FrameInfo,
+
+    imfi: ImmediateFrameInfo,
+    uri: UpdateRecordInfo,
+    updates: bool,
+    recorded: bool,
+}
+
+/// Copies and compute work, submitted before the frame's draws even when
+/// opened after `FrameCtx::record`.
+pub struct UploadScope<'a> {
+    uri: &'a UpdateRecordInfo,
+    updates: &'a mut bool,
+}
+
+/// Rerecording of the frame's command buffer, before the swapchain render pass.
+///
+/// `RenderTarget` and other offscreen passes are recorded with `info()`,
+/// `begin_pass` moves on to the swapchain. Dropping it without a pass
+/// still clears the swapchain image.
+pub struct RecordScope<'a> {
+    recording: Option<Recording<'a>>,
+}
+
+/// Inside the swapchain render pass, ended when dropped.
+pub struct DrawScope<'a> {
+    recording: Recording<'a>,
+}
+
+/// Draws with a bound pipeline.
+pub struct BindScope<'a> {
+    rri: &'a RenderRecordInfo,
+    pipeline: &'a Pipeline,
+}
+
+struct Recording<'a> {
+    renderer: &'a Renderer,
+    render_object: &'a mut RenderObject,
+    rri: RenderRecordInfo,
+
+    clear_color: Vector4<f32>,
+    render_pass: vk::RenderPass,
+    viewport: vk::Viewport,
+    scissor: vk::Rect2D,
+}
+
+impl<'a> FrameCtx<'a> {
+    pub(super) fn new(
+        renderer: &'a Renderer,
+        render_object: &'a mut RenderObject,
+        image_index: usize,
+        time: FrameInfo,
+    ) -> Self {
+        let uri = UpdateRecordInfo {
+            command_buffer: render_object.update_cb,
+            image_index,
+        };
+
+        Self {
+            renderer,
+            render_object,
+            time,
+
+            imfi: ImmediateFrameInfo { image_index },
+            uri,
+            updates: false,
+            recorded: false,
+        }
+    }
+
+    /// The swapchain image this frame renders to.
+    pub fn image_index(&self) -> usize {
+        self.imfi.image_index
+    }
+
+    /// Delta time and frame index, see `FrameInfo`.
+    pub fn time(&self) -> &FrameInfo {
+        &self.time
+    }
+
+    /// For per image writes like `Pipeline::write_ubo`.
+    pub fn immediate(&self) -> &ImmediateFrameInfo {
+        &self.imfi
+    }
+
+    pub fn upload(&mut self) -> UploadScope<'_> {
+        UploadScope {
+            uri: &self.uri,
+            updates: &mut self.updates,
+        }
+    }
+
+    /// `None` if the last recording is still valid, see `Renderer::request_rerecord`.
+    pub fn record(&mut self, begin_info: RenderRecordBeginInfo) -> Option<RecordScope<'_>> {
+        let requested = &self.renderer.rerecord_requested[self.imfi.image_index];
+        if !requested.load(Ordering::SeqCst) {
+            return None;
+        }
+        if self.recorded {
+            log_throttled!(
+                target: logging::RECORD,
+                Level::Warn,
+                "FrameCtx::record called twice in one frame"
+            );
+            return None;
+        }
+        self.recorded = true;
+        // requests made while recording apply to the next use of this image
+        requested.store(false, Ordering::SeqCst);
+
+        Some(RecordScope {
+            recording: Some(Recording::begin(
+                self.renderer,
+                &mut *self.render_object,
+                self.imfi.image_index,
+                begin_info,
+            )),
+        })
+    }
+
+    /// Records the frame if `RendererRecord::frame` did not, returns true if uploads are pending.
+    pub(super) fn finish(mut self) -> bool {
+        self.record(RenderRecordBeginInfo::default());
+        if let Some(picker) = self.renderer.picker.as_ref() {
+            // copies the ids of the last submitted frame
+            self.updates |= unsafe { picker.update(&self.uri) };
+        }
+        self.updates
+    }
+}
+
+impl<'a> UploadScope<'a> {
+    pub fn info(&self) -> &UpdateRecordInfo {
+        self.uri
+    }
+
+    /// Copies `buffer`'s pending writes to the device.
+    pub fn buffer<B: Buffer + ?Sized>(&mut self, buffer: &B) {
+        *self.updates |= unsafe { buffer.update(self.uri) };
+    }
+
+    /// Copies `pipeline`'s pending UBO writes to the device.
+    pub fn pipeline(&mut self, pipeline: &Pipeline) {
+        *self.updates |= unsafe { pipeline.update(self.uri) };
+    }
+
+    /// Records other work like compute dispatches, `f` returns true if it recorded anything.
+    pub fn record<F: FnOnce(&UpdateRecordInfo) -> bool>(&mut self, f: F) {
+        *self.updates |= f(self.uri);
+    }
+}
+
+impl<'a> RecordScope<'a> {
+    pub fn info(&self) -> &RenderRecordInfo {
+        &self.recording.as_ref().unwrap().rri
+    }
+
+    pub fn begin_pass(mut self) -> DrawScope<'a> {
+        let mut recording = self.recording.take().unwrap();
+        recording.begin_pass();
+        DrawScope { recording }
+    }
+}
+
+impl<'a> Drop for RecordScope<'a> {
+    fn drop(&mut self) {
+        if let Some(mut recording) = self.recording.take() {
+            recording.begin_pass();
+            recording.end_pass();
+        }
+    }
+}
+
+impl<'a> DrawScope<'a> {
+    pub fn info(&self) -> &RenderRecordInfo {
+        &self.recording.rri
+    }
+
+    pub fn bind<'s>(&'s mut self, pipeline: &'s Pipeline) -> BindScope<'s> {
+        let rri = &self.recording.rri;
+        unsafe { pipeline.bind(rri) };
+        BindScope { rri, pipeline }
+    }
+
+    /// Queues transparent draw `id`, see `RenderRecordInfo::queue_transparent`.
+    pub fn queue_transparent(&mut self, view_depth: f32, id: usize) {
+        self.recording.rri.queue_transparent(view_depth, id);
+    }
+
+    /// Queues draw `id`, see `RenderRecordInfo::queue_draw`.
+    pub fn queue(&mut self, key: DrawKey, id: usize) {
+        self.recording.rri.queue_draw(key, id);
+    }
+
+    /// The queued opaque draws, grouped by pipeline and material and nearest first.
+    pub fn opaque(&mut self) -> Vec<usize> {
+        self.recording.rri.sorted_draws(Some(false))
+    }
+
+    /// The queued transparent draws, farthest first. Draw them after all opaque draws.
+    pub fn transparent(&mut self) -> Vec<usize> {
+        self.recording.rri.sorted_draws(Some(true))
+    }
+
+    /// Every queued draw in `DrawKey` order, for draws queued with user bits.
+    pub fn sorted(&mut self) -> Vec<usize> {
+        self.recording.rri.sorted_draws(None)
+    }
+}
+
+impl<'a> Drop for DrawScope<'a> {
+    fn drop(&mut self) {
+        self.recording.end_pass();
+    }
+}
+
+impl<'a> BindScope<'a> {
+    pub fn info(&self) -> &RenderRecordInfo {
+        self.rri
+    }
+
+    pub fn draw<T>(&mut self, vertices: &VertexBuffer<T>) {
+        unsafe { vertices.draw(self.rri) };
+    }
+
+    pub fn draw_indexed<T, I: UInt>(
+        &mut self,
+        indices: &IndexBuffer<I>,
+        vertices: &VertexBuffer<T>,
+    ) {
+        unsafe { indices.draw(self.rri, vertices) };
+    }
+
+    /// See `Pipeline::draw_vertices`.
+    pub fn draw_vertices(&mut self, count: u32) {
+        unsafe { self.pipeline.draw_vertices(self.rri, count) };
+    }
+
+    /// See `Pipeline::draw_mesh_tasks`.
+    pub fn draw_mesh_tasks(&mut self, task_count: u32, first_task: u32) {
+        unsafe {
+            self.pipeline
+                .draw_mesh_tasks(self.rri, task_count, first_task)
+        };
+    }
+
+    pub fn push_constants<P: 'static + Copy>(&mut self, data: &P) {
+        unsafe { self.pipeline.push_constants(self.rri, data) };
+    }
+
+    /// See `Pipeline::bind_ubo_element`.
+    pub fn ubo_element(&mut self, index: usize) {
+        unsafe { self.pipeline.bind_ubo_element(self.rri, index) };
+    }
+}
+
+impl<'a> Recording<'a> {
+    fn begin(
+        renderer: &'a Renderer,
+        render_object: &'a mut RenderObject,
+        image_index: usize,
+        begin_info: RenderRecordBeginInfo,
+    ) -> Self {
+        let data = renderer.data.read();
+        let swapchain_objects = data.swapchain_objects.read();
+        let rri = RenderRecordInfo {
+            device: renderer.rdevice.clone(),
+            command_buffer: render_object.render_cb,
+            image_index,
+            extent: swapchain_objects.extent,
+            render_scale: renderer.scaler.lock().scale(),
+            debug_view: *renderer.debug_view.lock(),
+            triangles: AtomicUsize::new(0),
+            debug_calls: begin_info.debug_calls,
+            draws: Mutex::new(DrawQueue::new()),
+            scopes: render_object.scopes.clone(),
+            task_pool: renderer.task_pool.clone(),
+        };
+        let render_pass = swapchain_objects.render_pass;
+        let viewport = swapchain_objects.viewport;
+        let scissor = swapchain_objects.scissor;
+        drop(swapchain_objects);
+        drop(data);
+
+        if begin_info.debug_calls {
+            debug!(target: logging::COMMANDS, "begin_command_buffer with: {:?}", begin_info);
+        }
+
+        unsafe {
+            renderer.rdevice.reset_command_buffer(
+                render_object.render_cb,
+                vk::CommandBufferResetFlags::empty(),
+            )
+        }
+        .expect("Command buffer reset failed");
+
+        unsafe {
+            renderer.rdevice.begin_command_buffer(
+                render_object.render_cb,
+                &vk::CommandBufferBeginInfo::builder(),
+            )
+        }
+        .expect("Command buffer begin failed");
+        unsafe { render_object.scopes.reset(&rri) };
+
+        Self {
+            renderer,
+            render_object,
+            rri,
+
+            clear_color: begin_info.clear_color,
+            render_pass,
+            viewport,
+            scissor,
+        }
+    }
+
+    fn begin_pass(&mut self) {
+        let device = &self.renderer.rdevice;
+        let render_object = &mut *self.render_object;
+        let rri = &self.rri;
+
+        let viewport = [self.viewport];
+        let scissor = [self.scissor];
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [
+                        self.clear_color.x,
+                        self.clear_color.y,
+                        self.clear_color.z,
+                        self.clear_color.w,
+                    ],
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .clear_values(&clear_values)
+            .framebuffer(render_object.framebuffer)
+            .render_pass(self.render_pass)
+            .render_area(self.scissor);
+
+        unsafe {
+            device.cmd_set_viewport(render_object.render_cb, 0, &viewport);
+            device.cmd_set_scissor(render_object.render_cb, 0, &scissor);
+            rri.set_stencil_reference(0);
+
+            render_object.perf.reset(rri);
+            render_object.stats.as_ref().map(|stats| stats.reset(rri));
+
+            device.cmd_begin_render_pass(
+                render_object.render_cb,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+            render_object.stats.as_ref().map(|stats| stats.begin(rri));
+        }
+    }
+
+    fn end_pass(&mut self) {
+        let device = &self.renderer.rdevice;
+        let render_object = &mut *self.render_object;
+        let rri = &self.rri;
+
+        unsafe {
+            render_object.perf.bind(rri);
+        }
+        render_object.triangles = rri.triangles.load(Ordering::SeqCst);
+
+        unsafe {
+            render_object.stats.as_ref().map(|stats| stats.end(rri));
+            device.cmd_end_render_pass(render_object.render_cb);
+        }
+
+        unsafe { device.end_command_buffer(render_object.render_cb) }
+            .expect("Command buffer end failed");
+    }
+}
@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+// frames between changes, lets the GPU timings settle at the new resolution
+const COOLDOWN: usize = 30;
+const STEP: f32 = 0.05;
+// scales up only with this much headroom, so it does not oscillate around the target
+const HEADROOM: f32 = 0.85;
+
+/// Dynamic resolution state, see `Renderer::set_target_frame_time`.
+pub(crate) struct ResolutionScaler {
+    target: Option<Duration>,
+    min: f32,
+    max: f32,
+
+    scale: f32,
+    // smoothed GPU frametime in ms since the last change
+    average: Option<f32>,
+    cooldown: usize,
+}
+
+impl ResolutionScaler {
+    pub fn new() -> Self {
+        Self {
+            target: None,
+            min: 0.5,
+            max: 1.0,
+
+            scale: 1.0,
+            average: None,
+            cooldown: 0,
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn range(&self) -> (f32, f32) {
+        (self.min, self.max)
+    }
+
+    /// Returns the new scale if disabling reset it.
+    pub fn set_target(&mut self, target: Option<Duration>) -> Option<f32> {
+        self.target = target;
+        self.average = None;
+        if target.is_none() {
+            self.set_scale(self.max)
+        } else {
+            None
+        }
+    }
+
+    pub fn set_range(&mut self, min: f32, max: f32) -> Option<f32> {
+        self.min = min.max(STEP).min(1.0);
+        self.max = max.max(self.min).min(1.0);
+        self.set_scale(self.scale)
+    }
+
+    /// Returns the new scale if it changed.
+    pub fn update(&mut self, gpu_frametime: Duration) -> Option<f32> {
+        let target = self.target?.as_secs_f32() * 1000.0;
+        // zero if timestamp queries are not supported
+        if gpu_frametime == Duration::from_secs(0) {
+            return None;
+        }
+
+        let ms = gpu_frametime.as_secs_f32() * 1000.0;
+        let average = self.average.map_or(ms, |average| average * 0.9 + ms * 0.1);
+        self.average = Some(average);
+
+        if self.cooldown > 0 {
+            self.cooldown -= 1;
+            return None;
+        }
+
+        // the frametime grows with the pixel count, the square of the scale
+        let ideal = self.scale * (target / average).sqrt();
+        let scale = if average > target {
+            (ideal / STEP).floor() * STEP
+        } else if average < target * HEADROOM {
+            (self.scale + STEP).min(ideal)
+        } else {
+            return None;
+        };
+
+        if (scale - self.scale).abs() < STEP * 0.5 {
+            return None;
+        }
+        self.set_scale(scale)
+    }
+
+    fn set_scale(&mut self, scale: f32) -> Option<f32> {
+        let scale = scale.max(self.min).min(self.max);
+        if scale == self.scale {
+            return None;
+        }
+
+        self.scale = scale;
+        self.average = None;
+        self.cooldown = COOLDOWN;
+        Some(scale)
+    }
+}
@@ -0,0 +1,152 @@
+use ash::{version::InstanceV1_0, vk};
+use bitflags::bitflags;
+
+use super::{buffer::texture::TextureConfig, device::RenderDevice};
+use crate::SyncMode;
+
+/// Shadow map resolution picked in an options menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ShadowQuality {
+    Off,
+    Low,
+    Medium,
+    High,
+}
+
+/// Graphics options, for ex. loaded from a config file with the `serde` feature.
+///
+/// Set at startup with `RendererBuilder::with_settings` and changed with
+/// `Renderer::apply_settings`. The renderer applies `sync` and
+/// `resolution_scale` itself, the rest describe resources the application
+/// owns and rebuilds when `apply_settings` reports them changed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct RendererSettings {
+    pub sync: SyncMode,
+    /// Sample count of multisampled `RenderTarget`s, 1 disables MSAA.
+    ///
+    /// Lowered to the closest supported count.
+    pub msaa: u32,
+    /// Top of `Renderer::set_render_scale_range`, the fixed scale without a
+    /// target frame time.
+    pub resolution_scale: f32,
+    /// Anisotropic filtering level of textures, 1.0 disables it.
+    pub anisotropy: f32,
+    pub shadow_quality: ShadowQuality,
+}
+
+bitflags! {
+    /// What `Renderer::apply_settings` changed.
+    pub struct SettingsChanges: u8 {
+        /// The swapchain was recreated, frames are rerecorded.
+        const SWAPCHAIN = 1;
+        /// The render scale range changed, `on_render_scale_change` callbacks ran.
+        const RENDER_SCALE = 2;
+        /// Multisampled targets need rebuilding with `samples`.
+        const MSAA = 4;
+        /// Textures need rebuilding with `texture_config`.
+        const ANISOTROPY = 8;
+        /// Shadow maps need rebuilding with `shadow_map_size`.
+        const SHADOWS = 16;
+    }
+}
+
+impl ShadowQuality {
+    /// Shadow map width and height in pixels, 0 for `Off`.
+    pub fn shadow_map_size(&self) -> u32 {
+        match self {
+            ShadowQuality::Off => 0,
+            ShadowQuality::Low => 1024,
+            ShadowQuality::Medium => 2048,
+            ShadowQuality::High => 4096,
+        }
+    }
+}
+
+impl Default for ShadowQuality {
+    fn default() -> Self {
+        ShadowQuality::Medium
+    }
+}
+
+impl RendererSettings {
+    /// `msaa` as the sample count of `RenderTarget::new_multisampled`.
+    pub fn samples(&self) -> vk::SampleCountFlags {
+        vk::SampleCountFlags::from_raw(power_of_two_below(self.msaa))
+    }
+
+    /// `anisotropy` for `Texture2D::new_with_config`.
+    pub fn texture_config(&self) -> TextureConfig {
+        TextureConfig {
+            anisotropy: Some(self.anisotropy),
+        }
+    }
+
+    pub fn shadow_map_size(&self) -> u32 {
+        self.shadow_quality.shadow_map_size()
+    }
+
+    /// What changes going from `self` to `other`.
+    pub fn changes(&self, other: &RendererSettings) -> SettingsChanges {
+        let mut changes = SettingsChanges::empty();
+        changes.set(SettingsChanges::SWAPCHAIN, self.sync != other.sync);
+        changes.set(
+            SettingsChanges::RENDER_SCALE,
+            self.resolution_scale != other.resolution_scale,
+        );
+        changes.set(SettingsChanges::MSAA, self.msaa != other.msaa);
+        changes.set(
+            SettingsChanges::ANISOTROPY,
+            self.anisotropy != other.anisotropy,
+        );
+        changes.set(
+            SettingsChanges::SHADOWS,
+            self.shadow_quality != other.shadow_quality,
+        );
+        changes
+    }
+
+    /// Clamped to what `device` supports.
+    pub(crate) fn clamped(mut self, device: &RenderDevice) -> Self {
+        let limits = unsafe {
+            device
+                .instance
+                .get_physical_device_properties(device.pdevice)
+        }
+        .limits;
+        let supported =
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+
+        let mut msaa = power_of_two_below(self.msaa);
+        while msaa > 1 && !supported.contains(vk::SampleCountFlags::from_raw(msaa)) {
+            msaa /= 2;
+        }
+
+        self.msaa = msaa;
+        self.resolution_scale = self.resolution_scale.max(0.05).min(1.0);
+        self.anisotropy = self.anisotropy.max(1.0).min(device.limits.max_anisotropy);
+        self
+    }
+}
+
+impl Default for RendererSettings {
+    fn default() -> Self {
+        Self {
+            sync: SyncMode::default(),
+            msaa: 1,
+            resolution_scale: 1.0,
+            anisotropy: 1.0,
+            shadow_quality: ShadowQuality::default(),
+        }
+    }
+}
+
+// sample counts are powers of two, 0 counts as 1
+fn power_of_two_below(n: u32) -> u32 {
+    1 << (31 - n.max(1).leading_zeros())
+}
@@ -0,0 +1,289 @@
+use std::{cmp::Ordering, mem};
+
+use super::pipeline::Pipeline;
+use crate::loops::task::TaskPool;
+
+// bits of the packed key, the user bits on top, then the pass
+const USER_SHIFT: u32 = 56;
+const TRANSPARENT_BIT: u64 = 1 << 55;
+const DEPTH_BITS: u32 = 23;
+const DEPTH_MAX: u64 = (1 << DEPTH_BITS) - 1;
+
+/// Where a queued draw goes in the frame, see `DrawQueue`.
+///
+/// Keys sort by the user bits first, then opaque before transparent draws.
+/// Opaque draws are grouped by pipeline and material and go front to back
+/// within a group, so state changes are rare and early depth testing
+/// rejects hidden fragments. Transparent draws go back to front, ties are
+/// grouped by pipeline and material.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DrawKey {
+    user: u8,
+    transparent: bool,
+    pipeline: u16,
+    material: u16,
+    depth: f32,
+}
+
+/// Draws sorted by their `DrawKey`, draws with equal keys stay in queue order.
+///
+/// `RenderRecordInfo` has one for `DrawScope::queue`. Own queues can use
+/// `drain_sorted_by` for orderings keys cannot express.
+#[derive(Debug, Clone)]
+pub struct DrawQueue<T> {
+    draws: Vec<(DrawKey, T)>,
+}
+
+impl DrawKey {
+    /// `depth` is the distance from the camera.
+    pub fn opaque(depth: f32) -> Self {
+        Self {
+            depth,
+            ..Self::default()
+        }
+    }
+
+    pub fn transparent(depth: f32) -> Self {
+        Self {
+            depth,
+            transparent: true,
+            ..Self::default()
+        }
+    }
+
+    /// Groups draws with `Pipeline::sort_id`.
+    pub fn with_pipeline(mut self, pipeline: &Pipeline) -> Self {
+        self.pipeline = pipeline.sort_id();
+        self
+    }
+
+    /// Groups draws with the same textures or UBO element, any id the application picks.
+    pub fn with_material(mut self, material: u16) -> Self {
+        self.material = material;
+        self
+    }
+
+    /// Sorted before anything else, for ex. layers drawn over lower ones.
+    pub fn with_user(mut self, user: u8) -> Self {
+        self.user = user;
+        self
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.transparent
+    }
+
+    /// The key packed into an integer, smaller draws first.
+    pub fn sort_key(&self) -> u64 {
+        // positive floats order like their bits, the low mantissa bits are dropped
+        let depth = (self.depth.max(0.0).to_bits() >> 8) as u64 & DEPTH_MAX;
+        let state = (self.pipeline as u64) << 16 | self.material as u64;
+
+        let pass = if self.transparent {
+            TRANSPARENT_BIT | (DEPTH_MAX - depth) << 32 | state
+        } else {
+            state << DEPTH_BITS | depth
+        };
+        (self.user as u64) << USER_SHIFT | pass
+    }
+}
+
+impl<T> DrawQueue<T> {
+    pub fn new() -> Self {
+        Self { draws: Vec::new() }
+    }
+
+    pub fn push(&mut self, key: DrawKey, draw: T) {
+        self.draws.push((key, draw));
+    }
+
+    pub fn len(&self) -> usize {
+        self.draws.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.draws.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.draws.clear();
+    }
+
+    /// Takes every draw in key order.
+    pub fn drain_sorted(&mut self) -> Vec<(DrawKey, T)> {
+        let mut draws = mem::take(&mut self.draws);
+        draws.sort_by_key(|(key, _)| key.sort_key());
+        draws
+    }
+
+    /// Takes the opaque or the transparent draws in key order, the others stay queued.
+    pub fn drain_pass(&mut self, transparent: bool) -> Vec<(DrawKey, T)> {
+        let (mut draws, rest) = mem::take(&mut self.draws)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(key, _)| key.transparent == transparent);
+        self.draws = rest;
+        draws.sort_by_key(|(key, _)| key.sort_key());
+        draws
+    }
+
+    /// `drain_pass` of `transparent` or with `None` `drain_sorted`, sorted
+    /// on the threads of `pool`.
+    pub fn drain_sorted_on(
+        &mut self,
+        transparent: Option<bool>,
+        pool: &TaskPool,
+    ) -> Vec<(DrawKey, T)>
+    where
+        T: Send,
+    {
+        let mut draws = match transparent {
+            Some(transparent) => {
+                let (draws, rest) = mem::take(&mut self.draws)
+                    .into_iter()
+                    .partition::<Vec<_>, _>(|(key, _)| key.transparent == transparent);
+                self.draws = rest;
+                draws
+            }
+            None => mem::take(&mut self.draws),
+        };
+        pool.par_sort_by_key(&mut draws, |(key, _)| key.sort_key());
+        draws
+    }
+
+    /// Takes every draw in the order of `compare`, ties stay in queue order.
+    pub fn drain_sorted_by<F: FnMut(&DrawKey, &DrawKey) -> Ordering>(
+        &mut self,
+        mut compare: F,
+    ) -> Vec<(DrawKey, T)> {
+        let mut draws = mem::take(&mut self.draws);
+        draws.sort_by(|(a, _), (b, _)| compare(a, b));
+        draws
+    }
+}
+
+impl<T> Default for DrawQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn depth_bits(depth: f32) -> u64 {
+        (depth.to_bits() >> 8) as u64
+    }
+
+    #[test]
+    fn opaque_layout() {
+        let key = DrawKey {
+            pipeline: 0xABCD,
+            material: 0x1234,
+            ..DrawKey::opaque(2.0)
+        }
+        .with_user(0x7F);
+
+        assert_eq!(
+            key.sort_key(),
+            0x7F << USER_SHIFT | 0xABCD_1234 << DEPTH_BITS | depth_bits(2.0)
+        );
+        assert_eq!(key.sort_key() & TRANSPARENT_BIT, 0);
+    }
+
+    #[test]
+    fn transparent_layout() {
+        let key = DrawKey {
+            pipeline: 0xABCD,
+            material: 0x1234,
+            ..DrawKey::transparent(2.0)
+        }
+        .with_user(0x7F);
+
+        assert_eq!(
+            key.sort_key(),
+            0x7F << USER_SHIFT
+                | TRANSPARENT_BIT
+                | (DEPTH_MAX - depth_bits(2.0)) << 32
+                | 0xABCD_1234
+        );
+    }
+
+    #[test]
+    fn fields_do_not_overlap() {
+        let full = DrawKey {
+            pipeline: u16::MAX,
+            material: u16::MAX,
+            ..DrawKey::opaque(f32::MAX)
+        };
+        let pass = full.sort_key();
+        assert_eq!(pass >> USER_SHIFT, 0);
+        assert_eq!(pass & TRANSPARENT_BIT, 0);
+        assert_eq!(pass | DEPTH_MAX, TRANSPARENT_BIT - 1);
+
+        let transparent = DrawKey {
+            transparent: true,
+            ..full
+        };
+        assert_eq!(transparent.sort_key() >> USER_SHIFT, 0);
+        assert_eq!(
+            full.with_user(u8::MAX).sort_key() >> USER_SHIFT,
+            u8::MAX as u64
+        );
+    }
+
+    #[test]
+    fn negative_and_nan_depth_clamp_to_zero() {
+        assert_eq!(DrawKey::opaque(-5.0).sort_key(), 0);
+        assert_eq!(DrawKey::opaque(f32::NAN).sort_key(), 0);
+        assert_eq!(
+            DrawKey::transparent(-5.0).sort_key(),
+            TRANSPARENT_BIT | DEPTH_MAX << 32
+        );
+    }
+
+    #[test]
+    fn order() {
+        let near = DrawKey::opaque(1.0);
+        let far = DrawKey::opaque(10.0);
+        assert!(near.sort_key() < far.sort_key());
+
+        // pipeline groups beat depth for opaque draws
+        let grouped = DrawKey {
+            pipeline: 1,
+            ..near
+        };
+        assert!(far.sort_key() < grouped.sort_key());
+
+        let near = DrawKey::transparent(1.0);
+        let far = DrawKey::transparent(10.0);
+        assert!(far.sort_key() < near.sort_key());
+        assert!(DrawKey::opaque(1e30).sort_key() < far.sort_key());
+
+        // depth beats pipeline groups for transparent draws
+        let grouped = DrawKey { pipeline: 1, ..far };
+        assert!(grouped.sort_key() < near.sort_key());
+
+        // user bits beat everything
+        assert!(far.sort_key() < DrawKey::opaque(0.0).with_user(1).sort_key());
+    }
+
+    #[test]
+    fn queue_keeps_ties_in_order() {
+        let mut queue = DrawQueue::new();
+        queue.push(DrawKey::transparent(1.0), 0);
+        queue.push(DrawKey::opaque(2.0), 1);
+        queue.push(DrawKey::opaque(2.0), 2);
+        queue.push(DrawKey::opaque(1.0), 3);
+
+        let opaque: Vec<_> = queue
+            .drain_pass(false)
+            .into_iter()
+            .map(|(_, d)| d)
+            .collect();
+        assert_eq!(opaque, [3, 1, 2]);
+        assert_eq!(queue.len(), 1);
+        assert!(queue.drain_sorted()[0].0.is_transparent());
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+use ash::{
+    version::{DeviceV1_0, DeviceV1_2},
+    vk,
+};
+use log::warn;
+use std::{sync::Arc, time::Duration};
+
+use super::{device::RenderDevice, Renderer};
+use crate::{context::ContextError, MapErrorLog};
+
+/// Host waitable fence for work submitted outside of gears.
+///
+/// Pass `get()` to `vkQueueSubmit` and wait for it instead of idling the whole queue.
+pub struct GpuFence {
+    device: Arc<RenderDevice>,
+    fence: vk::Fence,
+}
+
+/// Timeline semaphore, a GPU and host visible counter that only increases.
+///
+/// Submissions signal it to a value and other submissions or the host wait
+/// until it reaches a value, see `Renderer::frame_timeline` and
+/// `Renderer::wait_timeline` for ordering against frames. Requires Vulkan 1.2,
+/// see `Renderer::timeline_semaphores`.
+pub struct GpuTimeline {
+    device: Arc<RenderDevice>,
+    semaphore: vk::Semaphore,
+}
+
+fn device_lost(err: vk::Result) -> ContextError {
+    match err {
+        vk::Result::ERROR_DEVICE_LOST => ContextError::DriverCrash,
+        _ => ContextError::OutOfMemory,
+    }
+}
+
+fn timeout_nanos(timeout: Option<Duration>) -> u64 {
+    timeout.map_or(!0, |timeout| timeout.as_nanos() as u64)
+}
+
+impl GpuFence {
+    pub fn new(renderer: &Renderer, signaled: bool) -> Result<Self, ContextError> {
+        Self::new_with_device(renderer.rdevice.clone(), signaled)
+    }
+
+    pub fn new_with_device(
+        device: Arc<RenderDevice>,
+        signaled: bool,
+    ) -> Result<Self, ContextError> {
+        let flags = if signaled {
+            vk::FenceCreateFlags::SIGNALED
+        } else {
+            vk::FenceCreateFlags::empty()
+        };
+        let fence_info = vk::FenceCreateInfo::builder().flags(flags);
+
+        let fence = unsafe { device.create_fence(&fence_info, None) }
+            .map_err_log("Fence creation failed", ContextError::OutOfMemory)?;
+
+        Ok(Self { device, fence })
+    }
+
+    /// Returns false if `timeout` ran out first, `None` waits for ever.
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<bool, ContextError> {
+        let fences = [self.fence];
+        match unsafe {
+            self.device
+                .wait_for_fences(&fences, true, timeout_nanos(timeout))
+        } {
+            Ok(()) => Ok(true),
+            Err(vk::Result::TIMEOUT) => Ok(false),
+            Err(err) => Err(device_lost(err)),
+        }
+    }
+
+    pub fn signaled(&self) -> Result<bool, ContextError> {
+        unsafe { self.device.get_fence_status(self.fence) }.map_err(device_lost)
+    }
+
+    /// The fence must not be in use by a pending submission.
+    pub fn reset(&self) -> Result<(), ContextError> {
+        let fences = [self.fence];
+        unsafe { self.device.reset_fences(&fences) }.map_err(device_lost)
+    }
+
+    pub fn get(&self) -> vk::Fence {
+        self.fence
+    }
+}
+
+impl GpuTimeline {
+    pub fn new(renderer: &Renderer, initial_value: u64) -> Result<Self, ContextError> {
+        Self::new_with_device(renderer.rdevice.clone(), initial_value)
+    }
+
+    pub fn new_with_device(
+        device: Arc<RenderDevice>,
+        initial_value: u64,
+    ) -> Result<Self, ContextError> {
+        if !device.timeline_semaphore {
+            warn!("Timeline semaphores are not supported");
+            return Err(ContextError::MissingDeviceExtensions);
+        }
+
+        let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(initial_value);
+        let semaphore_info = vk::SemaphoreCreateInfo::builder().push_next(&mut type_info);
+
+        let semaphore = unsafe { device.create_semaphore(&semaphore_info, None) }
+            .map_err_log("Semaphore creation failed", ContextError::OutOfMemory)?;
+
+        Ok(Self { device, semaphore })
+    }
+
+    /// The value last reached on the GPU or host.
+    pub fn value(&self) -> Result<u64, ContextError> {
+        unsafe { self.device.get_semaphore_counter_value(self.semaphore) }.map_err(device_lost)
+    }
+
+    /// Returns false if `timeout` ran out before `value` was reached, `None` waits for ever.
+    pub fn wait(&self, value: u64, timeout: Option<Duration>) -> Result<bool, ContextError> {
+        let semaphores = [self.semaphore];
+        let values = [value];
+        let wait_info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(&semaphores)
+            .values(&values);
+
+        match unsafe {
+            self.device
+                .wait_semaphores(&wait_info, timeout_nanos(timeout))
+        } {
+            Ok(()) => Ok(true),
+            Err(vk::Result::TIMEOUT) => Ok(false),
+            Err(err) => Err(device_lost(err)),
+        }
+    }
+
+    /// Sets the counter from the host, `value` must be larger than the current one.
+    pub fn signal(&self, value: u64) -> Result<(), ContextError> {
+        let signal_info = vk::SemaphoreSignalInfo::builder()
+            .semaphore(self.semaphore)
+            .value(value);
+
+        unsafe { self.device.signal_semaphore(&signal_info) }.map_err(device_lost)
+    }
+
+    pub fn get(&self) -> vk::Semaphore {
+        self.semaphore
+    }
+}
+
+impl Drop for GpuFence {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_fence(self.fence, None);
+        }
+    }
+}
+
+impl Drop for GpuTimeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_semaphore(self.semaphore, None);
+        }
+    }
+}
@@ -0,0 +1,562 @@
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk,
+};
+use cgmath::Vector4;
+use log::debug;
+use std::sync::Arc;
+
+use crate::logging;
+
+use super::{
+    buffer::{
+        image::{BaseFormat, Image, ImageBuilder, ImageFormat, ImageUsage, Layout},
+        BufferError,
+    },
+    device::RenderDevice,
+    RenderRecordInfo, Renderer,
+};
+
+/// Offscreen framebuffer with one or more color attachments and a depth attachment.
+///
+/// Fragment output `location = N` writes to `color_formats[N]`. Recorded in
+/// a `RecordScope` before `begin_pass`, pipelines drawing to it are built with
+/// `PipelineBuilder::with_render_target`. All attachments are left in
+/// `SHADER_READ_ONLY_OPTIMAL` so later passes can sample them with `sampler()`.
+///
+/// `new_multisampled` targets draw to multisampled color attachments and
+/// resolve them into the single sampled `color_view`s, see `Resolve`.
+pub struct RenderTarget {
+    device: Arc<RenderDevice>,
+
+    // single sampled, resolved into if multisampled
+    color_images: Vec<Image>,
+    // empty if not multisampled
+    multisampled_images: Vec<Image>,
+    depth_image: Image,
+    sampler: vk::Sampler,
+    samples: vk::SampleCountFlags,
+    resolve: Resolve,
+
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    extent: vk::Extent2D,
+}
+
+/// When the multisampled color attachments of a `RenderTarget` are resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resolve {
+    /// By the render pass as it ends, the multisampled contents are discarded.
+    EndOfPass,
+    /// Only by `RenderTarget::resolve`. The multisampled contents are kept
+    /// and can be read with `sampler2DMS` through `multisampled_view`.
+    Explicit,
+}
+
+impl RenderTarget {
+    pub fn new(
+        renderer: &Renderer,
+        width: u32,
+        height: u32,
+        color_formats: &[vk::Format],
+    ) -> Result<Self, BufferError> {
+        Self::new_with_device(renderer.rdevice.clone(), width, height, color_formats)
+    }
+
+    pub fn new_with_device(
+        device: Arc<RenderDevice>,
+        width: u32,
+        height: u32,
+        color_formats: &[vk::Format],
+    ) -> Result<Self, BufferError> {
+        Self::new_multisampled_with_device(
+            device,
+            width,
+            height,
+            color_formats,
+            vk::SampleCountFlags::TYPE_1,
+            Resolve::EndOfPass,
+        )
+    }
+
+    /// Antialiased target, `samples` has to be supported for color and depth attachments.
+    ///
+    /// The depth attachment is multisampled too and never resolved.
+    pub fn new_multisampled(
+        renderer: &Renderer,
+        width: u32,
+        height: u32,
+        color_formats: &[vk::Format],
+        samples: vk::SampleCountFlags,
+        resolve: Resolve,
+    ) -> Result<Self, BufferError> {
+        Self::new_multisampled_with_device(
+            renderer.rdevice.clone(),
+            width,
+            height,
+            color_formats,
+            samples,
+            resolve,
+        )
+    }
+
+    pub fn new_multisampled_with_device(
+        device: Arc<RenderDevice>,
+        width: u32,
+        height: u32,
+        color_formats: &[vk::Format],
+        samples: vk::SampleCountFlags,
+        resolve: Resolve,
+    ) -> Result<Self, BufferError> {
+        if color_formats.is_empty() {
+            return Err(BufferError::InvalidSize);
+        }
+
+        let multisampled = samples != vk::SampleCountFlags::TYPE_1;
+        if multisampled {
+            let limits = unsafe {
+                device
+                    .instance
+                    .get_physical_device_properties(device.pdevice)
+            }
+            .limits;
+            let supported =
+                limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+            if !supported.contains(samples) {
+                return Err(BufferError::UnsupportedFeature(
+                    "render target sample count",
+                ));
+            }
+        }
+
+        let image = |format: vk::Format, usage: ImageUsage, samples: vk::SampleCountFlags| {
+            ImageBuilder::new_with_device(device.clone())
+                .with_width(width)
+                .with_height(height)
+                .with_samples(samples)
+                .build(usage, format)
+        };
+
+        let (color_usage, multisampled_usage) = match resolve {
+            Resolve::EndOfPass => (ImageUsage::BOTH, ImageUsage::WRITE),
+            // resolved with a transfer
+            Resolve::Explicit if multisampled => (
+                ImageUsage::BOTH | ImageUsage::UPLOAD,
+                ImageUsage::BOTH | ImageUsage::COPY,
+            ),
+            Resolve::Explicit => (ImageUsage::BOTH, ImageUsage::BOTH),
+        };
+        // copyable for readbacks like `Renderer::pick`
+        let color_usage = color_usage | ImageUsage::COPY;
+        let color_images = color_formats
+            .iter()
+            .map(|&format| image(format, color_usage, vk::SampleCountFlags::TYPE_1))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let multisampled_images = if multisampled {
+            color_formats
+                .iter()
+                .map(|&format| image(format, multisampled_usage, samples))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            Vec::new()
+        };
+
+        let depth_format = ImageFormat::<f32>::D.format();
+        let depth_image = image(depth_format, ImageUsage::BOTH | ImageUsage::COPY, samples)?;
+
+        let render_pass =
+            Self::create_render_pass(&device, color_formats, depth_format, samples, resolve)?;
+
+        // same order as the render pass attachments
+        let attachments = if multisampled {
+            let resolve_images = match resolve {
+                Resolve::EndOfPass => &color_images[..],
+                Resolve::Explicit => &[],
+            };
+            multisampled_images
+                .iter()
+                .chain(Some(&depth_image))
+                .chain(resolve_images)
+                .map(|image| image.view())
+                .collect::<Vec<_>>()
+        } else {
+            color_images
+                .iter()
+                .chain(Some(&depth_image))
+                .map(|image| image.view())
+                .collect::<Vec<_>>()
+        };
+
+        let framebuffer_info = vk::FramebufferCreateInfo::builder()
+            .attachments(&attachments[..])
+            .render_pass(render_pass)
+            .width(width)
+            .height(height)
+            .layers(1);
+
+        let framebuffer = unsafe { device.create_framebuffer(&framebuffer_info, None) }
+            .or(Err(BufferError::OutOfMemory))?;
+
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .min_lod(0.0)
+            .max_lod(0.0);
+
+        let sampler = unsafe { device.create_sampler(&sampler_info, None) }
+            .or(Err(BufferError::OutOfMemory))?;
+
+        debug!(
+            "RenderTarget created: {}x{} with {:?} ({:?})",
+            width, height, color_formats, samples
+        );
+
+        Ok(Self {
+            device,
+
+            color_images,
+            multisampled_images,
+            depth_image,
+            sampler,
+            samples,
+            resolve,
+
+            render_pass,
+            framebuffer,
+            extent: vk::Extent2D { width, height },
+        })
+    }
+
+    fn create_render_pass(
+        device: &Arc<RenderDevice>,
+        color_formats: &[vk::Format],
+        depth_format: vk::Format,
+        samples: vk::SampleCountFlags,
+        resolve: Resolve,
+    ) -> Result<vk::RenderPass, BufferError> {
+        let attachment = |format: vk::Format| {
+            vk::AttachmentDescription::builder()
+                .format(format)
+                .samples(samples)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .build()
+        };
+
+        let mut attachments = color_formats
+            .iter()
+            .chain(Some(&depth_format))
+            .map(|&format| attachment(format))
+            .collect::<Vec<_>>();
+
+        // only the resolved images are stored
+        let end_of_pass_resolve =
+            samples != vk::SampleCountFlags::TYPE_1 && resolve == Resolve::EndOfPass;
+        if end_of_pass_resolve {
+            for attachment in attachments[..color_formats.len()].iter_mut() {
+                attachment.store_op = vk::AttachmentStoreOp::DONT_CARE;
+                attachment.final_layout = vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL;
+            }
+            attachments.extend(
+                color_formats
+                    .iter()
+                    .map(|&format| vk::AttachmentDescription {
+                        samples: vk::SampleCountFlags::TYPE_1,
+                        load_op: vk::AttachmentLoadOp::DONT_CARE,
+                        ..attachment(format)
+                    }),
+            );
+        }
+
+        let color_attachment_refs = (0..color_formats.len())
+            .map(|i| {
+                vk::AttachmentReference::builder()
+                    .attachment(i as u32)
+                    .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(color_formats.len() as u32)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+
+        // resolve attachments follow the depth attachment
+        let resolve_attachment_refs = (0..color_formats.len())
+            .map(|i| {
+                vk::AttachmentReference::builder()
+                    .attachment((color_formats.len() + 1 + i) as u32)
+                    .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        // previous frames sampling before writing and writes before sampling in later passes
+        let dependencies = [
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                )
+                .src_access_mask(vk::AccessFlags::SHADER_READ)
+                .dst_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                )
+                .build(),
+            vk::SubpassDependency::builder()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                )
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .src_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                )
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build(),
+        ];
+
+        let mut subpass = vk::SubpassDescription::builder()
+            .color_attachments(&color_attachment_refs[..])
+            .depth_stencil_attachment(&depth_attachment_ref);
+        if end_of_pass_resolve {
+            subpass = subpass.resolve_attachments(&resolve_attachment_refs[..]);
+        }
+        let subpasses = [subpass.build()];
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments[..])
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+
+        unsafe { device.create_render_pass(&render_pass_info, None) }
+            .or(Err(BufferError::OutOfMemory))
+    }
+
+    /// Begins the render pass and sets the viewport and scissor to the target size.
+    pub unsafe fn begin(&self, rri: &RenderRecordInfo, clear_color: Vector4<f32>) {
+        self.begin_with_clear_colors(rri, &vec![clear_color; self.color_images.len()]);
+    }
+
+    /// `begin` with a clear color per color attachment, the last one repeats if there are fewer.
+    pub unsafe fn begin_with_clear_colors(
+        &self,
+        rri: &RenderRecordInfo,
+        clear_colors: &[Vector4<f32>],
+    ) {
+        let clear_color = |i: usize| {
+            let color = clear_colors
+                .get(i)
+                .or_else(|| clear_colors.last())
+                .copied()
+                .unwrap_or_else(|| Vector4::new(0.0, 0.0, 0.0, 0.0));
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [color.x, color.y, color.z, color.w],
+                },
+            }
+        };
+        let clear_depth = vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        };
+        let clear_values = (0..self.color_images.len())
+            .map(clear_color)
+            .chain(Some(clear_depth))
+            .collect::<Vec<_>>();
+
+        let scissor = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        };
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .clear_values(&clear_values[..])
+            .framebuffer(self.framebuffer)
+            .render_pass(self.render_pass)
+            .render_area(scissor);
+
+        if rri.debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_begin_render_pass (RenderTarget)");
+        }
+
+        self.device
+            .cmd_set_viewport(rri.command_buffer, 0, &[viewport]);
+        self.device
+            .cmd_set_scissor(rri.command_buffer, 0, &[scissor]);
+        self.device.cmd_begin_render_pass(
+            rri.command_buffer,
+            &render_pass_begin_info,
+            vk::SubpassContents::INLINE,
+        );
+    }
+
+    pub unsafe fn end(&self, rri: &RenderRecordInfo) {
+        if rri.debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_end_render_pass (RenderTarget)");
+        }
+
+        self.device.cmd_end_render_pass(rri.command_buffer);
+
+        self.depth_image.set_layout(Layout::ShaderRead);
+        if self.multisampled_images.is_empty() {
+            for image in self.color_images.iter() {
+                image.set_layout(Layout::ShaderRead);
+            }
+            return;
+        }
+
+        match self.resolve {
+            Resolve::EndOfPass => {
+                for (multisampled, image) in self.multisampled_images.iter().zip(&self.color_images)
+                {
+                    multisampled.set_layout(Layout::ColorAttachment);
+                    image.set_layout(Layout::ShaderRead);
+                }
+            }
+            Resolve::Explicit => {
+                for multisampled in self.multisampled_images.iter() {
+                    multisampled.set_layout(Layout::ShaderRead);
+                }
+            }
+        }
+    }
+
+    /// Resolves the multisampled color attachments into `color_view`, outside of the pass.
+    ///
+    /// Only needed with `Resolve::Explicit`, does nothing otherwise.
+    pub unsafe fn resolve(&self, rri: &RenderRecordInfo) {
+        if self.resolve != Resolve::Explicit || self.multisampled_images.is_empty() {
+            return;
+        }
+
+        if rri.debug_calls {
+            debug!(target: logging::COMMANDS, "cmd_resolve_image (RenderTarget)");
+        }
+
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+        let regions = [vk::ImageResolve::builder()
+            .src_subresource(subresource)
+            .src_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .dst_subresource(subresource)
+            .dst_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .extent(vk::Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            })
+            .build()];
+
+        for (multisampled, image) in self.multisampled_images.iter().zip(&self.color_images) {
+            multisampled.transition(rri.command_buffer, Layout::TransferSrc);
+            image.transition(rri.command_buffer, Layout::TransferDst);
+            self.device.cmd_resolve_image(
+                rri.command_buffer,
+                multisampled.image(),
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image.image(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+            );
+            multisampled.transition(rri.command_buffer, Layout::ShaderRead);
+            image.transition(rri.command_buffer, Layout::ShaderRead);
+        }
+    }
+
+    pub fn color_count(&self) -> usize {
+        self.color_images.len()
+    }
+
+    pub fn color_view(&self, index: usize) -> vk::ImageView {
+        self.color_images[index].view()
+    }
+
+    pub fn depth_view(&self) -> vk::ImageView {
+        self.depth_image.view()
+    }
+
+    pub fn color_image(&self, index: usize) -> &Image {
+        &self.color_images[index]
+    }
+
+    /// The multisampled color attachment, only sampleable with `Resolve::Explicit`.
+    pub fn multisampled_view(&self, index: usize) -> Option<vk::ImageView> {
+        self.multisampled_images
+            .get(index)
+            .map(|image| image.view())
+    }
+
+    pub fn multisampled_image(&self, index: usize) -> Option<&Image> {
+        self.multisampled_images.get(index)
+    }
+
+    pub fn samples(&self) -> vk::SampleCountFlags {
+        self.samples
+    }
+
+    pub fn depth_image(&self) -> &Image {
+        &self.depth_image
+    }
+
+    /// Nearest, clamp to edge sampler for reading the attachments.
+    pub fn sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    pub fn width(&self) -> u32 {
+        self.extent.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.extent.height
+    }
+}
+
+impl Drop for RenderTarget {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_framebuffer(self.framebuffer, None);
+            self.device.destroy_render_pass(self.render_pass, None);
+            self.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
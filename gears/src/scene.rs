@@ -0,0 +1,686 @@
+use cgmath::{
+    ortho, perspective, Deg, Matrix4, Quaternion, SquareMatrix, Vector3, Vector4, VectorSpace,
+};
+use std::{collections::HashMap, fmt};
+
+use crate::deferred::PointLight;
+
+/// Position, rotation and scale of a `SceneNode` relative to its parent.
+///
+/// Plain arrays so the files do not depend on the math library's format,
+/// `rotation` is a quaternion in x, y, z, w order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Transform {
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+/// A light attached to a node, placed and aimed by the node's transform.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SceneLight {
+    /// Linear `color` with a `radius` of influence.
+    Point {
+        color: [f32; 3],
+        intensity: f32,
+        radius: f32,
+    },
+    /// Shines along the node's -z axis.
+    Directional { color: [f32; 3], intensity: f32 },
+}
+
+/// A camera attached to a node, the node's world transform is its inverse view.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SceneCamera {
+    /// `fov_y` in degrees.
+    Perspective { fov_y: f32, near: f32, far: f32 },
+    /// `height` of the view volume in world units.
+    Orthographic { height: f32, near: f32, far: f32 },
+}
+
+/// One entry of a `Scene`.
+///
+/// Meshes and materials are referenced by asset path, the name they are
+/// loaded with into `Assets`, so a scene file does not contain GPU data.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct SceneNode {
+    pub name: String,
+    /// Index of the parent node, which comes before this one in `Scene::nodes`.
+    pub parent: Option<usize>,
+    pub transform: Transform,
+    pub mesh: Option<String>,
+    pub material: Option<String>,
+    pub light: Option<SceneLight>,
+    pub camera: Option<SceneCamera>,
+}
+
+/// A node hierarchy that can be saved and loaded, for tools and editors
+/// round tripping content created at runtime.
+///
+/// ```ignore
+/// let mut scene = Scene::default();
+/// let root = scene.add(SceneNode::new("level"));
+/// scene.add(SceneNode::new("lamp").with_parent(root).with_light(light));
+/// scene.save("level.ron")?;
+///
+/// let scene = Scene::load("level.ron")?;
+/// deferred.write_lights(imfi, 0, &scene.point_lights())?;
+/// ```
+///
+/// Saving and loading RON or JSON needs the `scene` feature, the types
+/// derive `serde` traits with the `serde` feature for other formats.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Scene {
+    pub nodes: Vec<SceneNode>,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    /// The file is not a valid scene, with the parser's message.
+    Parse(String),
+    /// The scene could not be written, with the serializer's message.
+    Serialize(String),
+    /// The path has no `.ron` or `.json` extension.
+    UnknownFormat,
+    /// A node's parent does not come before it.
+    InvalidParent(usize),
+    /// A prefab is empty or has more than one root.
+    InvalidPrefab,
+}
+
+/// A reusable subtree, spawned into scenes any number of times with `Scene::spawn`.
+///
+/// ```ignore
+/// let tree = Prefab::load("tree.ron")?;
+/// for (i, position) in positions.iter().enumerate() {
+///     let overrides = PrefabOverrides::default()
+///         .with_name(format!("tree {}", i))
+///         .with_transform(Transform::from_translation(*position));
+///     level.spawn(&tree, Some(forest), &overrides);
+/// }
+/// ```
+///
+/// Its first node is its only root. Made from part of a scene with
+/// `Scene::extract`, or from a saved scene with one root.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prefab {
+    scene: Scene,
+}
+
+/// Changes to one instance of a `Prefab`.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct PrefabOverrides {
+    /// Replaces the root's name.
+    pub name: Option<String>,
+    /// Replaces the root's transform, placing the instance under its parent.
+    pub transform: Option<Transform>,
+    /// Changes to nodes, by their name in the prefab.
+    pub nodes: HashMap<String, NodeOverride>,
+}
+
+/// Changes to one node of a `Prefab` instance, `None` keeps the prefab's value.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct NodeOverride {
+    pub transform: Option<Transform>,
+    pub mesh: Option<String>,
+    pub material: Option<String>,
+    pub light: Option<SceneLight>,
+    pub camera: Option<SceneCamera>,
+}
+
+impl Transform {
+    pub fn from_translation(translation: Vector3<f32>) -> Self {
+        Self {
+            translation: translation.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn new(translation: Vector3<f32>, rotation: Quaternion<f32>, scale: Vector3<f32>) -> Self {
+        Self {
+            translation: translation.into(),
+            rotation: rotation.into(),
+            scale: scale.into(),
+        }
+    }
+
+    pub fn translation(&self) -> Vector3<f32> {
+        self.translation.into()
+    }
+
+    pub fn rotation(&self) -> Quaternion<f32> {
+        self.rotation.into()
+    }
+
+    pub fn scale(&self) -> Vector3<f32> {
+        self.scale.into()
+    }
+
+    /// Scale, then rotation, then translation.
+    pub fn matrix(&self) -> Matrix4<f32> {
+        let scale = self.scale();
+        Matrix4::from_translation(self.translation())
+            * Matrix4::from(self.rotation())
+            * Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z)
+    }
+
+    /// From `self` at `t` 0 to `other` at `t` 1, the rotation along the shortest arc.
+    pub fn interpolate(&self, other: &Transform, t: f32) -> Transform {
+        Self::new(
+            self.translation().lerp(other.translation(), t),
+            self.rotation().nlerp(other.rotation(), t),
+            self.scale().lerp(other.scale(), t),
+        )
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: [0.0; 3],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+            scale: [1.0; 3],
+        }
+    }
+}
+
+impl SceneCamera {
+    /// Projection for a viewport with `aspect` width over height.
+    pub fn projection(&self, aspect: f32) -> Matrix4<f32> {
+        match *self {
+            SceneCamera::Perspective { fov_y, near, far } => {
+                perspective(Deg(fov_y), aspect, near, far)
+            }
+            SceneCamera::Orthographic { height, near, far } => {
+                let (x, y) = (height * aspect * 0.5, height * 0.5);
+                ortho(-x, x, -y, y, near, far)
+            }
+        }
+    }
+}
+
+impl SceneNode {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_parent(mut self, parent: usize) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn with_mesh<S: Into<String>>(mut self, path: S) -> Self {
+        self.mesh = Some(path.into());
+        self
+    }
+
+    pub fn with_material<S: Into<String>>(mut self, path: S) -> Self {
+        self.material = Some(path.into());
+        self
+    }
+
+    pub fn with_light(mut self, light: SceneLight) -> Self {
+        self.light = Some(light);
+        self
+    }
+
+    pub fn with_camera(mut self, camera: SceneCamera) -> Self {
+        self.camera = Some(camera);
+        self
+    }
+}
+
+impl Scene {
+    /// Appends `node` and returns its index.
+    ///
+    /// Panics if its parent is not already in the scene.
+    pub fn add(&mut self, node: SceneNode) -> usize {
+        let index = self.nodes.len();
+        assert!(
+            node.parent.map_or(true, |parent| parent < index),
+            "parent of scene node {} is not in the scene",
+            index
+        );
+        self.nodes.push(node);
+        index
+    }
+
+    /// The first node named `name`.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.name == name)
+    }
+
+    /// Nodes without a parent.
+    pub fn roots(&self) -> impl Iterator<Item = usize> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.parent.is_none())
+            .map(|(index, _)| index)
+    }
+
+    pub fn children(&self, node: usize) -> impl Iterator<Item = usize> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(move |(_, child)| child.parent == Some(node))
+            .map(|(index, _)| index)
+    }
+
+    /// `node`'s transform combined with all of its parents'.
+    pub fn world_transform(&self, node: usize) -> Matrix4<f32> {
+        let mut matrix = self.nodes[node].transform.matrix();
+        let mut parent = self.nodes[node].parent;
+        while let Some(index) = parent {
+            matrix = self.nodes[index].transform.matrix() * matrix;
+            parent = self.nodes[index].parent;
+        }
+        matrix
+    }
+
+    /// World transforms of every node, in one pass instead of walking each chain.
+    pub fn world_transforms(&self) -> Vec<Matrix4<f32>> {
+        let mut transforms: Vec<Matrix4<f32>> = Vec::with_capacity(self.nodes.len());
+        for node in self.nodes.iter() {
+            let local = node.transform.matrix();
+            let world = match node.parent {
+                Some(parent) => transforms[parent] * local,
+                None => local,
+            };
+            transforms.push(world);
+        }
+        transforms
+    }
+
+    /// View matrix of the camera at `node`, `None` if its transform can not be inverted.
+    pub fn view(&self, node: usize) -> Option<Matrix4<f32>> {
+        self.world_transform(node).invert()
+    }
+
+    /// The point lights in world space, for `DeferredRenderer::write_lights`.
+    pub fn point_lights(&self) -> Vec<PointLight> {
+        let transforms = self.world_transforms();
+        self.nodes
+            .iter()
+            .zip(transforms.iter())
+            .filter_map(|(node, transform)| match node.light {
+                Some(SceneLight::Point {
+                    color,
+                    intensity,
+                    radius,
+                }) => Some(PointLight {
+                    position: transform.w.truncate().extend(radius),
+                    color: Vector4::new(color[0], color[1], color[2], intensity),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Copies `node` and everything under it into a `Prefab`, `node` becomes its root.
+    pub fn extract(&self, node: usize) -> Prefab {
+        // old to new indices, descendants always come after `node`
+        let mut indices = HashMap::new();
+        let mut scene = Scene::default();
+        for (index, source) in self.nodes.iter().enumerate().skip(node) {
+            let parent = if index == node {
+                None
+            } else {
+                match source.parent.and_then(|parent| indices.get(&parent)) {
+                    Some(&parent) => Some(parent),
+                    None => continue,
+                }
+            };
+            indices.insert(index, scene.nodes.len());
+            scene.nodes.push(SceneNode {
+                parent,
+                ..source.clone()
+            });
+        }
+        Prefab { scene }
+    }
+
+    /// Appends a copy of `prefab` with `overrides` applied under `parent`,
+    /// returns the index of the instance's root.
+    ///
+    /// Panics if `parent` is not in the scene.
+    pub fn spawn(
+        &mut self,
+        prefab: &Prefab,
+        parent: Option<usize>,
+        overrides: &PrefabOverrides,
+    ) -> usize {
+        let root = self.nodes.len();
+        assert!(
+            parent.map_or(true, |parent| parent < root),
+            "parent of the prefab instance is not in the scene"
+        );
+
+        for source in prefab.scene.nodes.iter() {
+            let mut node = source.clone();
+            if let Some(node_override) = overrides.nodes.get(&node.name) {
+                node_override.apply(&mut node);
+            }
+            node.parent = match node.parent {
+                Some(index) => Some(root + index),
+                None => parent,
+            };
+            self.nodes.push(node);
+        }
+
+        let node = &mut self.nodes[root];
+        if let Some(name) = overrides.name.as_ref() {
+            node.name = name.clone();
+        }
+        if let Some(transform) = overrides.transform {
+            node.transform = transform;
+        }
+        root
+    }
+
+    /// Checks that every parent comes before its children, the order
+    /// `world_transforms` and the other lookups rely on.
+    pub fn validate(&self) -> Result<(), SceneError> {
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.parent.map_or(false, |parent| parent >= index) {
+                return Err(SceneError::InvalidParent(index));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "scene")]
+impl Scene {
+    pub fn to_ron(&self) -> Result<String, SceneError> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| SceneError::Serialize(err.to_string()))
+    }
+
+    pub fn from_ron(source: &str) -> Result<Self, SceneError> {
+        let scene: Self =
+            ron::from_str(source).map_err(|err| SceneError::Parse(err.to_string()))?;
+        scene.validate()?;
+        Ok(scene)
+    }
+
+    pub fn to_json(&self) -> Result<String, SceneError> {
+        serde_json::to_string_pretty(self).map_err(|err| SceneError::Serialize(err.to_string()))
+    }
+
+    pub fn from_json(source: &str) -> Result<Self, SceneError> {
+        let scene: Self =
+            serde_json::from_str(source).map_err(|err| SceneError::Parse(err.to_string()))?;
+        scene.validate()?;
+        Ok(scene)
+    }
+
+    /// Writes RON or JSON depending on the extension of `path`.
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), SceneError> {
+        let source = match SceneFormat::of(path.as_ref())? {
+            SceneFormat::Ron => self.to_ron()?,
+            SceneFormat::Json => self.to_json()?,
+        };
+        std::fs::write(path, source).map_err(SceneError::Io)
+    }
+
+    /// Reads RON or JSON depending on the extension of `path`.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, SceneError> {
+        let format = SceneFormat::of(path.as_ref())?;
+        let source = std::fs::read_to_string(path).map_err(SceneError::Io)?;
+        match format {
+            SceneFormat::Ron => Self::from_ron(&source),
+            SceneFormat::Json => Self::from_json(&source),
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(err) => write!(f, "Scene file access failed: {}", err),
+            SceneError::Parse(err) => write!(f, "Scene parsing failed: {}", err),
+            SceneError::Serialize(err) => write!(f, "Scene serialization failed: {}", err),
+            SceneError::UnknownFormat => write!(f, "Scene path is not .ron or .json"),
+            SceneError::InvalidParent(node) => {
+                write!(f, "Parent of scene node {} does not come before it", node)
+            }
+            SceneError::InvalidPrefab => write!(f, "Prefab does not have exactly one root"),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl Prefab {
+    /// `InvalidPrefab` unless `scene` has exactly one root.
+    pub fn from_scene(scene: Scene) -> Result<Self, SceneError> {
+        scene.validate()?;
+        if scene.roots().count() != 1 {
+            return Err(SceneError::InvalidPrefab);
+        }
+        Ok(Self { scene })
+    }
+
+    /// The prefab's nodes, the root first.
+    pub fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    pub fn into_scene(self) -> Scene {
+        self.scene
+    }
+}
+
+#[cfg(feature = "scene")]
+impl Prefab {
+    /// Saved like a `Scene`.
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), SceneError> {
+        self.scene.save(path)
+    }
+
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, SceneError> {
+        Self::from_scene(Scene::load(path)?)
+    }
+}
+
+impl PrefabOverrides {
+    pub fn with_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.transform = Some(transform);
+        self
+    }
+
+    /// Changes the prefab's nodes named `name`.
+    pub fn with_node<S: Into<String>>(mut self, name: S, node_override: NodeOverride) -> Self {
+        self.nodes.insert(name.into(), node_override);
+        self
+    }
+}
+
+impl NodeOverride {
+    fn apply(&self, node: &mut SceneNode) {
+        if let Some(transform) = self.transform {
+            node.transform = transform;
+        }
+        if let Some(mesh) = self.mesh.as_ref() {
+            node.mesh = Some(mesh.clone());
+        }
+        if let Some(material) = self.material.as_ref() {
+            node.material = Some(material.clone());
+        }
+        if let Some(light) = self.light {
+            node.light = Some(light);
+        }
+        if let Some(camera) = self.camera {
+            node.camera = Some(camera);
+        }
+    }
+}
+
+#[cfg(feature = "scene")]
+enum SceneFormat {
+    Ron,
+    Json,
+}
+
+#[cfg(feature = "scene")]
+impl SceneFormat {
+    fn of(path: &std::path::Path) -> Result<Self, SceneError> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("ron") => Ok(SceneFormat::Ron),
+            Some("json") => Ok(SceneFormat::Json),
+            _ => Err(SceneError::UnknownFormat),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene() -> Scene {
+        let mut scene = Scene::default();
+        let root = scene.add(SceneNode::new("level").with_transform(Transform {
+            translation: [1.0, 2.0, 3.0],
+            rotation: [0.0, 0.6, 0.0, 0.8],
+            scale: [2.0, 2.0, 2.0],
+        }));
+        scene.add(
+            SceneNode::new("lamp")
+                .with_parent(root)
+                .with_light(SceneLight::Point {
+                    color: [1.0, 0.5, 0.25],
+                    intensity: 4.0,
+                    radius: 10.0,
+                }),
+        );
+        scene.add(
+            SceneNode::new("rock")
+                .with_parent(root)
+                .with_mesh("rock.gltf")
+                .with_material("rock.mat"),
+        );
+        scene.add(
+            SceneNode::new("camera").with_camera(SceneCamera::Perspective {
+                fov_y: 60.0,
+                near: 0.1,
+                far: 100.0,
+            }),
+        );
+        scene
+    }
+
+    #[test]
+    fn invalid_parent() {
+        let mut scene = scene();
+        scene.nodes[1].parent = Some(2);
+        assert!(matches!(
+            scene.validate(),
+            Err(SceneError::InvalidParent(1))
+        ));
+    }
+
+    #[test]
+    fn prefab_needs_one_root() {
+        assert!(matches!(
+            Prefab::from_scene(scene()),
+            Err(SceneError::InvalidPrefab)
+        ));
+        assert_eq!(scene().extract(0).scene().nodes.len(), 3);
+    }
+
+    #[cfg(feature = "scene")]
+    #[test]
+    fn ron_round_trip() {
+        let scene = scene();
+        let source = scene.to_ron().unwrap();
+        assert_eq!(Scene::from_ron(&source).unwrap(), scene);
+    }
+
+    #[cfg(feature = "scene")]
+    #[test]
+    fn json_round_trip() {
+        let scene = scene();
+        let source = scene.to_json().unwrap();
+        assert_eq!(Scene::from_json(&source).unwrap(), scene);
+    }
+
+    #[cfg(feature = "scene")]
+    #[test]
+    fn file_round_trip() {
+        let scene = scene();
+        for ext in ["ron", "json"].iter() {
+            let path =
+                std::env::temp_dir().join(format!("gears_scene_{}.{}", std::process::id(), ext));
+            scene.save(&path).unwrap();
+            let loaded = Scene::load(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.unwrap(), scene);
+        }
+        assert!(matches!(
+            scene.save("scene.txt"),
+            Err(SceneError::UnknownFormat)
+        ));
+    }
+
+    #[cfg(feature = "scene")]
+    #[test]
+    fn invalid_files() {
+        assert!(matches!(
+            Scene::from_json("{\"nodes\": 1}"),
+            Err(SceneError::Parse(_))
+        ));
+        assert!(matches!(
+            Scene::from_json("{\"nodes\": [{\"parent\": 0}]}"),
+            Err(SceneError::InvalidParent(0))
+        ));
+    }
+}
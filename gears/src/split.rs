@@ -0,0 +1,235 @@
+use cgmath::Vector4;
+use gears_traits::UBO;
+use std::collections::HashMap;
+use winit::{
+    dpi::PhysicalPosition,
+    event::{DeviceId, WindowEvent},
+};
+
+use crate::{
+    renderer::{
+        buffer::{BufferError, WriteType},
+        pipeline::Pipeline,
+        ImmediateFrameInfo, RenderRecordInfo,
+    },
+    viewport::{Viewport, ViewportRect},
+};
+
+/// How `SplitScreen` divides the swapchain between players.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SplitLayout {
+    /// Side by side columns.
+    Columns,
+    /// Stacked rows.
+    Rows,
+    /// Two players side by side, more in the squarest grid that fits them.
+    Grid,
+}
+
+/// Local multiplayer, one swapchain `Viewport` per player.
+///
+/// ```ignore
+/// let mut cameras = pipeline.ubo_array::<Camera>();
+/// for player in &players {
+///     cameras.push(player.camera(split.aspect(rri, player.index)));
+/// }
+/// pipeline.write_ubo_slice(imfi, &cameras)?;
+///
+/// split.draw(rri, |index| {
+///     pipeline.bind(rri);
+///     pipeline.bind_ubo_element(rri, index);
+///     mesh.draw(rri);
+/// });
+/// ```
+///
+/// Every player's camera is one element of a pipeline's `with_ubo_array`,
+/// selected by its viewport index with `bind_ubo_element`, so all players
+/// share one recording. Input events are routed to players by the device
+/// they came from with `assign_device`, or by the viewport under the cursor.
+pub struct SplitScreen {
+    viewports: Vec<Viewport>,
+    layout: SplitLayout,
+    devices: HashMap<DeviceId, usize>,
+}
+
+impl SplitLayout {
+    /// Rectangles of `players` viewports, from the top left to the bottom right.
+    pub fn rects(&self, players: usize) -> Vec<ViewportRect> {
+        let players = players.max(1);
+        let (columns, rows) = match self {
+            SplitLayout::Columns => (players, 1),
+            SplitLayout::Rows => (1, players),
+            SplitLayout::Grid => {
+                let columns = (players as f32).sqrt().ceil() as usize;
+                (columns, (players + columns - 1) / columns)
+            }
+        };
+
+        let (width, height) = (1.0 / columns as f32, 1.0 / rows as f32);
+        (0..players)
+            .map(|index| {
+                let (column, row) = (index % columns, index / columns);
+                // the last row's viewports share its whole width
+                let in_row = (players - row * columns).min(columns);
+                let width = if row == rows - 1 {
+                    1.0 / in_row as f32
+                } else {
+                    width
+                };
+                ViewportRect::new(column as f32 * width, row as f32 * height, width, height)
+            })
+            .collect()
+    }
+}
+
+impl SplitScreen {
+    pub fn new(players: usize, layout: SplitLayout) -> Self {
+        let viewports = layout
+            .rects(players)
+            .into_iter()
+            .map(Viewport::new)
+            .collect();
+
+        Self {
+            viewports,
+            layout,
+            devices: HashMap::new(),
+        }
+    }
+
+    /// Clears every viewport before its player draws.
+    pub fn with_clear_color(mut self, color: Vector4<f32>) -> Self {
+        self.viewports = self
+            .viewports
+            .into_iter()
+            .map(|viewport| viewport.with_clear_color(color))
+            .collect();
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.viewports.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.viewports.is_empty()
+    }
+
+    pub fn layout(&self) -> SplitLayout {
+        self.layout
+    }
+
+    /// Lays the viewports out again, players past the new count lose their devices.
+    ///
+    /// Needs a rerecord like `Viewport::set_rect`.
+    pub fn set_players(&mut self, players: usize, layout: SplitLayout) {
+        let clear_color = self.viewports.first().and_then(Viewport::clear_color);
+        self.viewports = layout
+            .rects(players)
+            .into_iter()
+            .map(|rect| match clear_color {
+                Some(color) => Viewport::new(rect).with_clear_color(color),
+                None => Viewport::new(rect),
+            })
+            .collect();
+        self.layout = layout;
+
+        let players = self.viewports.len();
+        self.devices.retain(|_, player| *player < players);
+    }
+
+    pub fn viewport(&self, player: usize) -> &Viewport {
+        &self.viewports[player]
+    }
+
+    pub fn viewports(&self) -> &[Viewport] {
+        &self.viewports
+    }
+
+    /// Width over height of `player`'s viewport, for its camera's projection.
+    pub fn aspect(&self, rri: &RenderRecordInfo, player: usize) -> f32 {
+        self.viewports[player].aspect(rri)
+    }
+
+    /// Writes `cameras` in player order to `pipeline`'s `with_ubo_array`
+    /// for this frame.
+    ///
+    /// `TriedToOverflow` if the UBO array is smaller than `cameras`.
+    pub fn write_cameras<U: 'static + UBO + Copy>(
+        &self,
+        pipeline: &Pipeline,
+        imfi: &ImmediateFrameInfo,
+        cameras: &[U],
+    ) -> Result<WriteType, BufferError> {
+        let mut array = pipeline.ubo_array::<U>();
+        for camera in cameras {
+            array.push(*camera);
+        }
+        pipeline.write_ubo_slice(imfi, &array)
+    }
+
+    /// Calls `draw` with every player's index inside their viewport, in a `DrawScope`.
+    pub unsafe fn draw<F: FnMut(usize)>(&self, rri: &RenderRecordInfo, mut draw: F) {
+        for (player, viewport) in self.viewports.iter().enumerate() {
+            viewport.begin(rri);
+            draw(player);
+            viewport.end(rri);
+        }
+    }
+
+    /// Routes the events of `device` to `player` instead of by the cursor.
+    ///
+    /// A device only belongs to one player, assigning it again moves it.
+    pub fn assign_device(&mut self, device: DeviceId, player: usize) {
+        self.devices.insert(device, player);
+    }
+
+    pub fn unassign_device(&mut self, device: DeviceId) {
+        self.devices.remove(&device);
+    }
+
+    pub fn player_for_device(&self, device: DeviceId) -> Option<usize> {
+        self.devices.get(&device).copied()
+    }
+
+    /// The player whose viewport is under `position` on an `extent` sized swapchain.
+    pub fn player_at(
+        &self,
+        position: PhysicalPosition<f64>,
+        (width, height): (u32, u32),
+    ) -> Option<usize> {
+        let (x, y) = (
+            position.x as f32 / width as f32,
+            position.y as f32 / height as f32,
+        );
+        self.viewports.iter().position(|viewport| {
+            let rect = viewport.rect();
+            x >= rect.x && x < rect.x + rect.width && y >= rect.y && y < rect.y + rect.height
+        })
+    }
+
+    /// Which player `event` is for, `None` if it has no device or is not
+    /// clearly anyone's.
+    ///
+    /// Assigned devices win, unassigned cursors go to the viewport under them.
+    /// Unassigned keyboards and buttons carry no position and go to nobody.
+    pub fn route(&self, event: &WindowEvent, extent: (u32, u32)) -> Option<usize> {
+        let device_id = match event {
+            WindowEvent::KeyboardInput { device_id, .. }
+            | WindowEvent::MouseInput { device_id, .. }
+            | WindowEvent::MouseWheel { device_id, .. }
+            | WindowEvent::AxisMotion { device_id, .. } => *device_id,
+            WindowEvent::CursorMoved {
+                device_id,
+                position,
+                ..
+            } => {
+                return self
+                    .player_for_device(*device_id)
+                    .or_else(|| self.player_at(*position, extent))
+            }
+            _ => return None,
+        };
+        self.player_for_device(device_id)
+    }
+}
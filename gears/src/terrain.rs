@@ -0,0 +1,560 @@
+use ash::vk;
+use cgmath::{InnerSpace, Matrix4, Vector2, Vector3, Vector4};
+use log::Level;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+
+use crate::{
+    logging,
+    loops::task::TaskPool,
+    renderer::{
+        buffer::{index::IndexBuffer, vertex::VertexBuffer, Buffer, BufferError},
+        pipeline::{Pipeline, PipelineBuilder},
+        RenderRecordInfo, Renderer, UpdateRecordInfo,
+    },
+};
+
+mod shader {
+    gears_pipeline::pipeline! {
+        vert: {
+            path: "res/terrain.vert.glsl"
+        }
+        frag: {
+            path: "res/terrain.frag.glsl"
+        }
+    }
+}
+
+/// Heights of a heightmap texture, row major with x along the rows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heightmap {
+    width: usize,
+    depth: usize,
+    heights: Vec<f32>,
+}
+
+// must match the push constant blocks in terrain.vert.glsl and terrain.frag.glsl
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct TerrainPush {
+    view_projection: Matrix4<f32>,
+    light: Vector4<f32>,
+    layer_scale: f32,
+}
+
+/// Chunked heightmap terrain, streamed around the camera.
+///
+/// Chunks within the view distance are meshed from the heightmap with a
+/// level of detail that halves the resolution every time the distance
+/// doubles past `TerrainBuilder::with_lod_distance`. Skirts hide the cracks
+/// between chunks of different detail. A frame looks like:
+/// - `terrain.set_camera(position)`
+/// - `UploadScope`: `terrain.update(renderer, uri)`, which meshes and uploads a few
+///   chunks and frees the ones out of range
+/// - `RecordScope` or `DrawScope`: `terrain.draw(rri, &view_projection)`
+///
+/// The recorded draws go stale when `generation()` changes, request a
+/// rerecord then. The default pipeline blends four layer textures with the
+/// rgba weights of a splat map stretched over the whole terrain.
+pub struct Terrain {
+    frames_in_flight: usize,
+
+    heightmap: Heightmap,
+    pipeline: Pipeline,
+    config: TerrainConfig,
+    chunks: (u32, u32),
+    tasks: Option<TaskPool>,
+
+    state: Mutex<StreamState>,
+}
+
+pub struct TerrainBuilder<'a> {
+    renderer: &'a Renderer,
+    heightmap: Heightmap,
+    config: TerrainConfig,
+
+    splat: Option<(vk::ImageView, vk::Sampler)>,
+    layers: Vec<(vk::ImageView, vk::Sampler)>,
+    tasks: Option<TaskPool>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TerrainConfig {
+    chunk_size: u32,
+    lod_count: u32,
+    spacing: f32,
+    height_scale: f32,
+    view_distance: f32,
+    lod_distance: f32,
+    build_budget: usize,
+    layer_scale: f32,
+}
+
+struct Chunk {
+    lod: u32,
+    vertices: VertexBuffer<shader::TerrainVertex>,
+    indices: IndexBuffer<u32>,
+}
+
+struct StreamState {
+    camera: Vector3<f32>,
+    light: Vector4<f32>,
+
+    loaded: HashMap<(u32, u32), Chunk>,
+    generation: u64,
+
+    // replaced and unloaded chunks stay alive until frames using them are done
+    retired: Vec<(usize, Chunk)>,
+}
+
+impl Heightmap {
+    /// Panics if `heights` is not `width * depth` long.
+    pub fn new(width: usize, depth: usize, heights: Vec<f32>) -> Self {
+        assert_eq!(
+            heights.len(),
+            width * depth,
+            "Heightmap size does not match its dimensions"
+        );
+        Self {
+            width,
+            depth,
+            heights,
+        }
+    }
+
+    /// 8 bit single channel texels, 0..255 map to heights 0..1.
+    pub fn from_r8(width: usize, depth: usize, texels: &[u8]) -> Self {
+        let heights = texels.iter().map(|&h| h as f32 / u8::MAX as f32).collect();
+        Self::new(width, depth, heights)
+    }
+
+    /// 16 bit single channel texels, 0..65535 map to heights 0..1.
+    pub fn from_r16(width: usize, depth: usize, texels: &[u16]) -> Self {
+        let heights = texels.iter().map(|&h| h as f32 / u16::MAX as f32).collect();
+        Self::new(width, depth, heights)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Height of the texel at `x`, `z`, clamped to the edges.
+    pub fn get(&self, x: i64, z: i64) -> f32 {
+        let x = x.max(0).min(self.width as i64 - 1) as usize;
+        let z = z.max(0).min(self.depth as i64 - 1) as usize;
+        self.heights[z * self.width + x]
+    }
+
+    /// Bilinearly filtered height between texels, clamped to the edges.
+    pub fn sample(&self, x: f32, z: f32) -> f32 {
+        let (x0, z0) = (x.floor(), z.floor());
+        let (fx, fz) = (x - x0, z - z0);
+        let (x0, z0) = (x0 as i64, z0 as i64);
+
+        let top = self.get(x0, z0) * (1.0 - fx) + self.get(x0 + 1, z0) * fx;
+        let bottom = self.get(x0, z0 + 1) * (1.0 - fx) + self.get(x0 + 1, z0 + 1) * fx;
+        top * (1.0 - fz) + bottom * fz
+    }
+}
+
+impl Terrain {
+    pub fn new(renderer: &Renderer, heightmap: Heightmap) -> TerrainBuilder<'_> {
+        TerrainBuilder {
+            renderer,
+            heightmap,
+            config: TerrainConfig {
+                chunk_size: 64,
+                lod_count: 4,
+                spacing: 1.0,
+                height_scale: 64.0,
+                view_distance: 512.0,
+                lod_distance: 64.0,
+                build_budget: 4,
+                layer_scale: 64.0,
+            },
+
+            splat: None,
+            layers: Vec::new(),
+            tasks: None,
+        }
+    }
+
+    pub fn heightmap(&self) -> &Heightmap {
+        &self.heightmap
+    }
+
+    /// World space height of the terrain surface at `x`, `z`, for placing things on it.
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        let spacing = self.config.spacing;
+        self.heightmap.sample(x / spacing, z / spacing) * self.config.height_scale
+    }
+
+    /// World space position the chunks are streamed and their detail picked around.
+    pub fn set_camera(&self, camera: Vector3<f32>) {
+        self.state.lock().camera = camera;
+    }
+
+    /// `direction` points towards the light, `ambient` is added to the diffuse term.
+    ///
+    /// Like push constants in general, this is recorded and needs a rerecord to change.
+    pub fn set_light(&self, direction: Vector3<f32>, ambient: f32) {
+        self.state.lock().light = direction.normalize().extend(ambient);
+    }
+
+    /// Incremented every time the set of drawn chunks changes.
+    pub fn generation(&self) -> u64 {
+        self.state.lock().generation
+    }
+
+    /// Number of chunks with a mesh.
+    pub fn loaded_chunks(&self) -> usize {
+        self.state.lock().loaded.len()
+    }
+
+    /// Frees retired chunks, streams chunks in and out and uploads the new ones.
+    pub unsafe fn update(&self, renderer: &Renderer, uri: &UpdateRecordInfo) -> bool {
+        let mut state = self.state.lock();
+        let state = &mut *state;
+
+        for (frames, _) in state.retired.iter_mut() {
+            *frames -= 1;
+        }
+        state.retired.retain(|(frames, _)| *frames > 0);
+
+        let wanted = self.wanted_chunks(state.camera);
+        let mut changed = false;
+
+        let unloaded = state
+            .loaded
+            .keys()
+            .filter(|key| !wanted.iter().any(|(wanted, _)| wanted == *key))
+            .copied()
+            .collect::<Vec<_>>();
+        for key in unloaded {
+            if let Some(chunk) = state.loaded.remove(&key) {
+                state.retired.push((self.frames_in_flight, chunk));
+                changed = true;
+            }
+        }
+
+        let outdated = wanted
+            .into_iter()
+            .filter(|(key, lod)| {
+                state
+                    .loaded
+                    .get(key)
+                    .map_or(true, |chunk| chunk.lod != *lod)
+            })
+            .take(self.config.build_budget)
+            .collect::<Vec<_>>();
+        let meshes = match self.tasks.as_ref() {
+            Some(tasks) => tasks.par_map(&outdated, |&(key, lod)| self.mesh_chunk(key, lod)),
+            None => outdated
+                .iter()
+                .map(|&(key, lod)| self.mesh_chunk(key, lod))
+                .collect(),
+        };
+        for ((key, lod), mesh) in outdated.into_iter().zip(meshes) {
+            let chunk = match self.build_chunk(renderer, lod, mesh) {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    log_throttled!(
+                        target: logging::MEMORY,
+                        Level::Warn,
+                        "Terrain chunk upload failed: {:?}",
+                        err
+                    );
+                    break;
+                }
+            };
+            if let Some(old) = state.loaded.insert(key, chunk) {
+                state.retired.push((self.frames_in_flight, old));
+            }
+            changed = true;
+        }
+
+        if changed {
+            state.generation += 1;
+        }
+
+        let mut copied = false;
+        for chunk in state.loaded.values() {
+            copied |= chunk.vertices.update(uri);
+            copied |= chunk.indices.update(uri);
+        }
+        copied
+    }
+
+    pub unsafe fn draw(&self, rri: &RenderRecordInfo, view_projection: &Matrix4<f32>) {
+        let state = self.state.lock();
+        let push = TerrainPush {
+            view_projection: *view_projection,
+            light: state.light,
+            layer_scale: self.config.layer_scale,
+        };
+
+        self.pipeline.bind(rri);
+        self.pipeline.push_constants(rri, &push);
+        for chunk in state.loaded.values() {
+            chunk.indices.draw(rri, &chunk.vertices);
+        }
+    }
+
+    // chunks in range with their level of detail, nearest first
+    fn wanted_chunks(&self, camera: Vector3<f32>) -> Vec<((u32, u32), u32)> {
+        let config = &self.config;
+        let chunk_extent = config.chunk_size as f32 * config.spacing;
+
+        let mut wanted = Vec::new();
+        for cz in 0..self.chunks.1 {
+            for cx in 0..self.chunks.0 {
+                // to the closest point of the chunk, 0 inside it
+                let min = Vector2::new(cx as f32, cz as f32) * chunk_extent;
+                let closest = Vector2::new(
+                    camera.x.max(min.x).min(min.x + chunk_extent),
+                    camera.z.max(min.y).min(min.y + chunk_extent),
+                );
+                let distance = (Vector2::new(camera.x, camera.z) - closest).magnitude();
+                if distance > config.view_distance {
+                    continue;
+                }
+
+                let lod = if distance < config.lod_distance {
+                    0
+                } else {
+                    (distance / config.lod_distance).log2() as u32 + 1
+                };
+                wanted.push(((cx, cz), lod.min(config.lod_count - 1), distance));
+            }
+        }
+
+        wanted.sort_by(|a, b| a.2.total_cmp(&b.2));
+        wanted.into_iter().map(|(key, lod, _)| (key, lod)).collect()
+    }
+
+    fn build_chunk(
+        &self,
+        renderer: &Renderer,
+        lod: u32,
+        (vertices, indices): (Vec<shader::TerrainVertex>, Vec<u32>),
+    ) -> Result<Chunk, BufferError> {
+        Ok(Chunk {
+            lod,
+            vertices: VertexBuffer::new_with_data(renderer, &vertices)?,
+            indices: IndexBuffer::new_with_data(renderer, &indices)?,
+        })
+    }
+
+    fn mesh_chunk(&self, (cx, cz): (u32, u32), lod: u32) -> (Vec<shader::TerrainVertex>, Vec<u32>) {
+        let config = &self.config;
+        let map = &self.heightmap;
+        let step = 1 << lod;
+        let quads = config.chunk_size / step;
+        let row = quads + 1;
+
+        let texel = |i: u32, j: u32| {
+            let x = ((cx * config.chunk_size + i * step) as usize).min(map.width - 1);
+            let z = ((cz * config.chunk_size + j * step) as usize).min(map.depth - 1);
+            (x as i64, z as i64)
+        };
+
+        let mut vertices = Vec::with_capacity((row * row + 4 * row * 2) as usize);
+        for j in 0..row {
+            for i in 0..row {
+                let (x, z) = texel(i, j);
+                // central differences at full resolution, for smooth lighting at every detail
+                let slope = config.height_scale / (2.0 * config.spacing);
+                let normal = Vector3::new(
+                    (map.get(x - 1, z) - map.get(x + 1, z)) * slope,
+                    1.0,
+                    (map.get(x, z - 1) - map.get(x, z + 1)) * slope,
+                );
+
+                vertices.push(shader::TerrainVertex {
+                    position: Vector3::new(
+                        x as f32 * config.spacing,
+                        map.get(x, z) * config.height_scale,
+                        z as f32 * config.spacing,
+                    ),
+                    normal: normal.normalize(),
+                    uv: Vector2::new(
+                        x as f32 / (map.width - 1) as f32,
+                        z as f32 / (map.depth - 1) as f32,
+                    ),
+                });
+            }
+        }
+
+        let mut indices = Vec::with_capacity((quads * quads * 6 + 4 * quads * 12) as usize);
+        for j in 0..quads {
+            for i in 0..quads {
+                let index = j * row + i;
+                // counter clockwise seen from above
+                indices.extend_from_slice(&[index, index + row, index + 1]);
+                indices.extend_from_slice(&[index + 1, index + row, index + row + 1]);
+            }
+        }
+
+        // skirts as deep as the chunk is tall always cover the cracks to coarser neighbours
+        let (low, high) = vertices
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(low, high), v| {
+                (low.min(v.position.y), high.max(v.position.y))
+            });
+        let skirt = high - low + config.spacing;
+
+        let edges = [
+            (0..row).collect::<Vec<_>>(),
+            (0..row).map(|i| quads * row + i).collect(),
+            (0..row).map(|j| j * row).collect(),
+            (0..row).map(|j| j * row + quads).collect(),
+        ];
+        for edge in edges.iter() {
+            let first = vertices.len() as u32;
+            for &top in edge {
+                let mut vertex = vertices[top as usize];
+                vertex.position.y -= skirt;
+                vertices.push(vertex);
+            }
+
+            for (k, pair) in edge.windows(2).enumerate() {
+                let (a, b) = (pair[0], pair[1]);
+                let (a_low, b_low) = (first + k as u32, first + k as u32 + 1);
+                // both windings, the skirts are seen from either side
+                indices.extend_from_slice(&[a, a_low, b, b, a_low, b_low]);
+                indices.extend_from_slice(&[a, b, a_low, b, b_low, a_low]);
+            }
+        }
+
+        (vertices, indices)
+    }
+}
+
+impl<'a> TerrainBuilder<'a> {
+    /// Heightmap texels per chunk side at full detail, 64 by default.
+    ///
+    /// Has to be a power of two and at least `1 << (lod_count - 1)`.
+    pub fn with_chunk_size(mut self, chunk_size: u32) -> Self {
+        self.config.chunk_size = chunk_size;
+        self
+    }
+
+    /// Levels of detail, 4 by default. Each halves the resolution of the previous one.
+    pub fn with_lod_count(mut self, lod_count: u32) -> Self {
+        self.config.lod_count = lod_count;
+        self
+    }
+
+    /// World space distance between texels and the height of heightmap value 1.0,
+    /// 1.0 and 64.0 by default.
+    pub fn with_scale(mut self, spacing: f32, height_scale: f32) -> Self {
+        self.config.spacing = spacing;
+        self.config.height_scale = height_scale;
+        self
+    }
+
+    /// Chunks farther than this are not meshed, 512.0 by default.
+    pub fn with_view_distance(mut self, view_distance: f32) -> Self {
+        self.config.view_distance = view_distance;
+        self
+    }
+
+    /// Distance drawn at full detail, 64.0 by default.
+    pub fn with_lod_distance(mut self, lod_distance: f32) -> Self {
+        self.config.lod_distance = lod_distance;
+        self
+    }
+
+    /// Chunks meshed per `Terrain::update` at most, 4 by default.
+    pub fn with_build_budget(mut self, build_budget: usize) -> Self {
+        self.config.build_budget = build_budget.max(1);
+        self
+    }
+
+    /// Meshes the chunks of one `Terrain::update` in parallel on `tasks`,
+    /// usually the `FrameLoopBuilder::task_pool`.
+    pub fn with_task_pool(mut self, tasks: TaskPool) -> Self {
+        self.tasks = Some(tasks);
+        self
+    }
+
+    /// The splat map, its rgba channels weigh the four layers.
+    ///
+    /// Required, like at least one `with_layer`.
+    pub fn with_splat_map(mut self, view: vk::ImageView, sampler: vk::Sampler) -> Self {
+        self.splat = Some((view, sampler));
+        self
+    }
+
+    /// Up to four layer textures in splat channel order, the last one is
+    /// repeated for the missing ones. Repeating samplers are expected.
+    pub fn with_layer(mut self, view: vk::ImageView, sampler: vk::Sampler) -> Self {
+        self.layers.push((view, sampler));
+        self
+    }
+
+    /// Layer texture repeats across the whole terrain, 64.0 by default.
+    pub fn with_layer_scale(mut self, layer_scale: f32) -> Self {
+        self.config.layer_scale = layer_scale;
+        self
+    }
+
+    /// `InvalidSize` for heightmaps smaller than 2x2, an invalid chunk
+    /// size or a missing splat map or layers.
+    pub fn build(self) -> Result<Terrain, BufferError> {
+        let config = self.config;
+        let map = &self.heightmap;
+        let valid = map.width >= 2
+            && map.depth >= 2
+            && config.lod_count >= 1
+            && config.chunk_size.is_power_of_two()
+            && config.chunk_size >= 1 << (config.lod_count - 1)
+            && (1..=4).contains(&self.layers.len());
+        let splat = match self.splat {
+            Some(splat) if valid => splat,
+            _ => return Err(BufferError::InvalidSize),
+        };
+
+        let mut pipeline = PipelineBuilder::new(self.renderer)
+            .with_graphics_modules(shader::VERT_SPIRV_WORDS, shader::FRAG_SPIRV_WORDS)
+            .with_input::<shader::TerrainVertex>()
+            .with_push_constants::<TerrainPush>(
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            )
+            .with_sampled_image(splat.0, splat.1);
+        let last = self.layers[self.layers.len() - 1];
+        for (view, sampler) in self.layers.iter().chain([last; 4].iter()).take(4) {
+            pipeline = pipeline.with_sampled_image(*view, *sampler);
+        }
+        let pipeline = pipeline.build(false)?;
+
+        // quads between the texels, the last chunks can be partial
+        let chunks =
+            |texels: usize| ((texels as u32 - 1) + config.chunk_size - 1) / config.chunk_size;
+        let chunks = (chunks(map.width), chunks(map.depth));
+
+        Ok(Terrain {
+            frames_in_flight: self.renderer.frames_in_flight(),
+
+            heightmap: self.heightmap,
+            pipeline,
+            config,
+            chunks,
+            tasks: self.tasks,
+
+            state: Mutex::new(StreamState {
+                camera: Vector3::new(0.0, 0.0, 0.0),
+                light: Vector3::new(0.3, 0.9, 0.3).normalize().extend(0.2),
+
+                loaded: HashMap::new(),
+                generation: 0,
+
+                retired: Vec::new(),
+            }),
+        })
+    }
+}
@@ -0,0 +1,351 @@
+pub mod projection;
+pub mod widget;
+
+#[cfg(feature = "short_namespaces")]
+pub use projection::*;
+#[cfg(feature = "short_namespaces")]
+pub use widget::*;
+
+use ash::vk;
+use cgmath::{Vector2, Vector4};
+
+use crate::renderer::{
+    buffer::{atlas::UvRect, streaming::StreamingVertexBuffer, BufferError},
+    pipeline::{Pipeline, PipelineBuilder},
+    ImmediateFrameInfo, RenderRecordInfo, Renderer,
+};
+
+mod shader {
+    gears_pipeline::pipeline! {
+        vert: {
+            path: "res/ui.vert.glsl"
+        }
+        frag: {
+            path: "res/ui.frag.glsl"
+        }
+    }
+}
+
+/// Screen rectangle from the top left, in the units of a `Projection2D`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UiRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Fill, corner radius and border of a `UiQuads` shape.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuadStyle {
+    /// Linear color, multiplied with the atlas for images.
+    pub color: Vector4<f32>,
+    /// Corner radius, at most half the shorter side of the shape. Shapes
+    /// not in `Units2D::Pixels` are clamped once converted to pixels.
+    pub radius: f32,
+    /// Border width, drawn inside the shape.
+    pub border: f32,
+    pub border_color: Vector4<f32>,
+}
+
+/// An atlas image scaled with fixed size corners, edges stretched along
+/// one axis and a center stretched along both.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NineSlice {
+    /// The whole image, for ex. `TextureAtlas::uv`.
+    pub uv: UvRect,
+    /// Size of the image in pixels.
+    pub size: Vector2<f32>,
+    /// Corner sizes in image pixels: left, top, right and bottom.
+    pub insets: Vector4<f32>,
+    /// Screen units per image pixel of the corners.
+    pub scale: f32,
+}
+
+/// Rectangles, rounded rectangles with borders and 9-slice images, for
+/// simple HUDs and menus.
+///
+/// Coordinates and lengths are in the units of `set_projection`, pixels by
+/// default. They are converted with the swapchain size the shapes are drawn
+/// at, so normalized shapes follow resizes without being added again.
+///
+/// ```ignore
+/// ui.rect(panel, &QuadStyle::fill(dark).with_radius(8.0).with_border(2.0, light));
+/// ui.nine_slice(button, &button_slice, white);
+/// ui.image(icon, atlas.uv(coin), &QuadStyle::fill(white));
+/// ui.draw(rri)?;
+/// ```
+///
+/// Shapes are drawn in the order they were added, over everything drawn
+/// before and blended with their alpha. Edges of rounded corners are
+/// anti-aliased. Like `Lines`, `draw` draws everything since the last `draw`
+/// once per frame in a `DrawScope` that has to `request_rerecord` every frame.
+pub struct UiQuads {
+    vertices: StreamingVertexBuffer<shader::UiVertex>,
+    pipeline: Pipeline,
+    projection: projection::Projection2D,
+
+    // in units, converted to pixels in `draw`
+    pending: Vec<shader::UiVertex>,
+}
+
+impl UiRect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Smaller by `amount` pixels on every side.
+    pub fn inset(&self, amount: f32) -> Self {
+        Self::new(
+            self.x + amount,
+            self.y + amount,
+            (self.width - 2.0 * amount).max(0.0),
+            (self.height - 2.0 * amount).max(0.0),
+        )
+    }
+
+    pub fn contains(&self, point: Vector2<f32>) -> bool {
+        point.x >= self.x
+            && point.x < self.x + self.width
+            && point.y >= self.y
+            && point.y < self.y + self.height
+    }
+}
+
+impl QuadStyle {
+    /// Square corners without a border.
+    pub fn fill(color: Vector4<f32>) -> Self {
+        Self {
+            color,
+            radius: 0.0,
+            border: 0.0,
+            border_color: color,
+        }
+    }
+
+    pub fn with_radius(mut self, radius: f32) -> Self {
+        self.radius = radius.max(0.0);
+        self
+    }
+
+    pub fn with_border(mut self, width: f32, color: Vector4<f32>) -> Self {
+        self.border = width.max(0.0);
+        self.border_color = color;
+        self
+    }
+}
+
+impl NineSlice {
+    pub fn new(uv: UvRect, size: Vector2<f32>, insets: Vector4<f32>) -> Self {
+        Self {
+            uv,
+            size,
+            insets,
+            scale: 1.0,
+        }
+    }
+
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+}
+
+impl UiQuads {
+    /// `capacity` is the quad count shared by all frames in flight, a
+    /// `nine_slice` takes 9.
+    ///
+    /// Images and 9-slices sample `atlas_view`, which has to outlive the
+    /// quads and be in `SHADER_READ_ONLY_OPTIMAL` when drawn.
+    pub fn new(
+        renderer: &Renderer,
+        capacity: usize,
+        atlas_view: vk::ImageView,
+        atlas_sampler: vk::Sampler,
+    ) -> Result<Self, BufferError> {
+        // debug, quads are not culled
+        let pipeline = PipelineBuilder::new(renderer)
+            .with_graphics_modules(shader::VERT_SPIRV_WORDS, shader::FRAG_SPIRV_WORDS)
+            .with_input::<shader::UiVertex>()
+            .with_push_constants::<Vector2<f32>>(vk::ShaderStageFlags::VERTEX)
+            .with_sampled_image(atlas_view, atlas_sampler)
+            .with_transparency()
+            .build(true)?;
+
+        Ok(Self {
+            vertices: StreamingVertexBuffer::new(renderer, capacity * 6)?,
+            pipeline,
+            projection: projection::Projection2D::default(),
+
+            pending: Vec::new(),
+        })
+    }
+
+    /// The units of the shapes added after the last `draw`. Only its units
+    /// and scale factor are used, the extent comes from the swapchain.
+    pub fn set_projection(&mut self, projection: projection::Projection2D) {
+        self.projection = projection;
+    }
+
+    pub fn projection(&self) -> &projection::Projection2D {
+        &self.projection
+    }
+
+    /// A solid shape.
+    pub fn rect(&mut self, rect: UiRect, style: &QuadStyle) {
+        self.quad(rect, None, style);
+    }
+
+    /// `uv` of the atlas stretched over `rect`, clipped to the style's rounded corners.
+    pub fn image(&mut self, rect: UiRect, uv: UvRect, style: &QuadStyle) {
+        self.quad(rect, Some(uv), style);
+    }
+
+    /// `slice` scaled to `rect`, the corners shrink if `rect` is smaller than them.
+    pub fn nine_slice(&mut self, rect: UiRect, slice: &NineSlice, color: Vector4<f32>) {
+        let insets = slice.insets * slice.scale;
+        // corners of a too small rect share its size by their proportions
+        let fit = |size: f32, a: f32, b: f32| {
+            if a + b > size && a + b > 0.0 {
+                size / (a + b)
+            } else {
+                1.0
+            }
+        };
+        let fit_x = fit(rect.width, insets.x, insets.z);
+        let fit_y = fit(rect.height, insets.y, insets.w);
+
+        let xs = [
+            rect.x,
+            rect.x + insets.x * fit_x,
+            rect.x + rect.width - insets.z * fit_x,
+            rect.x + rect.width,
+        ];
+        let ys = [
+            rect.y,
+            rect.y + insets.y * fit_y,
+            rect.y + rect.height - insets.w * fit_y,
+            rect.y + rect.height,
+        ];
+
+        let uv_size = slice.uv.max - slice.uv.min;
+        let us = [
+            slice.uv.min.x,
+            slice.uv.min.x + slice.insets.x / slice.size.x * uv_size.x,
+            slice.uv.max.x - slice.insets.z / slice.size.x * uv_size.x,
+            slice.uv.max.x,
+        ];
+        let vs = [
+            slice.uv.min.y,
+            slice.uv.min.y + slice.insets.y / slice.size.y * uv_size.y,
+            slice.uv.max.y - slice.insets.w / slice.size.y * uv_size.y,
+            slice.uv.max.y,
+        ];
+
+        let style = QuadStyle::fill(color);
+        for (y, v) in ys.windows(2).zip(vs.windows(2)) {
+            for (x, u) in xs.windows(2).zip(us.windows(2)) {
+                let piece = UiRect::new(x[0], y[0], x[1] - x[0], y[1] - y[0]);
+                if piece.width <= 0.0 || piece.height <= 0.0 {
+                    continue;
+                }
+                let uv = UvRect {
+                    min: Vector2::new(u[0], v[0]),
+                    max: Vector2::new(u[1], v[1]),
+                };
+                self.quad(piece, Some(uv), &style);
+            }
+        }
+    }
+
+    /// Draws and clears the shapes since the last `draw`.
+    ///
+    /// `TriedToOverflow` if the frames in flight used up the capacity, the shapes are dropped.
+    pub unsafe fn draw(&mut self, rri: &RenderRecordInfo) -> Result<(), BufferError> {
+        self.vertices.begin_frame(&ImmediateFrameInfo {
+            image_index: rri.image_index(),
+        });
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let (width, height) = rri.extent();
+        let viewport = Vector2::new(width as f32, height as f32);
+
+        // pixel shapes are drawn as they were added
+        let projection = self.projection.with_extent(rri.extent());
+        if projection.units() != projection::Units2D::Pixels {
+            let scale = projection.scale();
+            let length = projection.length_scale();
+            let per_axis = |v: Vector2<f32>| Vector2::new(v.x * scale.x, v.y * scale.y);
+            for vertex in self.pending.iter_mut() {
+                vertex.position = per_axis(vertex.position);
+                vertex.local = per_axis(vertex.local);
+                vertex.half_size = per_axis(vertex.half_size);
+                // normalized units scale the axes differently
+                vertex.radius = (vertex.radius * length)
+                    .min(vertex.half_size.x)
+                    .min(vertex.half_size.y);
+                vertex.border *= length;
+            }
+        }
+
+        let result = self.vertices.alloc_frame(&self.pending).map(|slice| {
+            self.pipeline.bind(rri);
+            self.pipeline.push_constants(rri, &viewport);
+            slice.bind(rri, 0);
+            self.pipeline.draw_vertices(rri, slice.len() as u32);
+        });
+        self.pending.clear();
+
+        result
+    }
+
+    fn quad(&mut self, rect: UiRect, uv: Option<UvRect>, style: &QuadStyle) {
+        let half_size = Vector2::new(rect.width, rect.height) * 0.5;
+        // other units are clamped in `draw`
+        let radius = match self.projection.units() {
+            projection::Units2D::Pixels => style.radius.min(half_size.x).min(half_size.y),
+            _ => style.radius,
+        };
+        let (uv, textured) = match uv {
+            Some(uv) => (uv, 1.0),
+            None => (
+                UvRect {
+                    min: Vector2::new(0.0, 0.0),
+                    max: Vector2::new(0.0, 0.0),
+                },
+                0.0,
+            ),
+        };
+
+        let corner = |x: f32, y: f32| shader::UiVertex {
+            position: Vector2::new(rect.x + x * rect.width, rect.y + y * rect.height),
+            uv: Vector2::new(
+                uv.min.x + x * (uv.max.x - uv.min.x),
+                uv.min.y + y * (uv.max.y - uv.min.y),
+            ),
+            color: style.color,
+            border_color: style.border_color,
+            local: Vector2::new((x * 2.0 - 1.0) * half_size.x, (y * 2.0 - 1.0) * half_size.y),
+            half_size,
+            radius,
+            border: style.border,
+            textured,
+        };
+
+        self.pending.extend_from_slice(&[
+            corner(0.0, 0.0),
+            corner(0.0, 1.0),
+            corner(1.0, 0.0),
+            corner(1.0, 0.0),
+            corner(0.0, 1.0),
+            corner(1.0, 1.0),
+        ]);
+    }
+}
@@ -0,0 +1,230 @@
+use cgmath::{ortho, Matrix4, Vector2};
+use winit::{event::WindowEvent, window::Window};
+
+use super::UiRect;
+
+/// What 2D positions and sizes are measured in, always from the top left
+/// of the viewport with y down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Units2D {
+    /// Physical pixels of the swapchain.
+    Pixels,
+    /// Pixels divided by the scale factor of the window, so the UI has the
+    /// same size on a high DPI display.
+    Points,
+    /// 0.0 to 1.0 across the viewport on both axes.
+    Normalized,
+}
+
+/// Maps 2D coordinates in `Units2D` to pixels and clip space.
+///
+/// `UiQuads`, `Gui` and their `GuiText` use it to agree on what a
+/// coordinate means. Pass it every window event and it follows resizes and
+/// scale factor changes:
+///
+/// ```ignore
+/// let mut projection = Projection2D::for_window(Units2D::Points, frame.window());
+/// // in EventLoopTarget::event
+/// projection.event(event);
+/// // every frame
+/// gui.update_projected(&input, &projection);
+/// quads.set_projection(projection);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Projection2D {
+    units: Units2D,
+    extent: (u32, u32),
+    scale_factor: f32,
+}
+
+impl Projection2D {
+    /// A 1 x 1 viewport with a scale factor of 1.0, until `with_extent` or the first `event`.
+    pub fn new(units: Units2D) -> Self {
+        Self {
+            units,
+            extent: (1, 1),
+            scale_factor: 1.0,
+        }
+    }
+
+    /// The current size and scale factor of `window`.
+    pub fn for_window(units: Units2D, window: &Window) -> Self {
+        let size = window.inner_size();
+        Self::new(units)
+            .with_extent((size.width, size.height))
+            .with_scale_factor(window.scale_factor() as f32)
+    }
+
+    /// The viewport size in pixels.
+    pub fn with_extent(mut self, (width, height): (u32, u32)) -> Self {
+        self.extent = (width.max(1), height.max(1));
+        self
+    }
+
+    pub fn with_scale_factor(mut self, scale_factor: f32) -> Self {
+        if scale_factor > 0.0 {
+            self.scale_factor = scale_factor;
+        }
+        self
+    }
+
+    pub fn units(&self) -> Units2D {
+        self.units
+    }
+
+    pub fn extent(&self) -> (u32, u32) {
+        self.extent
+    }
+
+    pub fn scale_factor(&self) -> f32 {
+        self.scale_factor
+    }
+
+    /// Follows `Resized` and `ScaleFactorChanged`, true if either changed the projection.
+    pub fn event(&mut self, event: &WindowEvent) -> bool {
+        let before = *self;
+        *self = match event {
+            WindowEvent::Resized(size) => self.with_extent((size.width, size.height)),
+            WindowEvent::ScaleFactorChanged {
+                scale_factor,
+                new_inner_size,
+            } => self
+                .with_extent((new_inner_size.width, new_inner_size.height))
+                .with_scale_factor(*scale_factor as f32),
+            _ => return false,
+        };
+        *self != before
+    }
+
+    /// The viewport size in units.
+    pub fn size(&self) -> Vector2<f32> {
+        let scale = self.scale();
+        Vector2::new(
+            self.extent.0 as f32 / scale.x,
+            self.extent.1 as f32 / scale.y,
+        )
+    }
+
+    /// Pixels per unit along each axis.
+    pub fn scale(&self) -> Vector2<f32> {
+        match self.units {
+            Units2D::Pixels => Vector2::new(1.0, 1.0),
+            Units2D::Points => Vector2::new(self.scale_factor, self.scale_factor),
+            Units2D::Normalized => Vector2::new(self.extent.0 as f32, self.extent.1 as f32),
+        }
+    }
+
+    /// Pixels per unit of lengths without a direction, like corner radii,
+    /// borders and text sizes. `Normalized` lengths are fractions of the
+    /// viewport height.
+    pub fn length_scale(&self) -> f32 {
+        self.scale().y
+    }
+
+    pub fn to_pixels(&self, point: Vector2<f32>) -> Vector2<f32> {
+        let scale = self.scale();
+        Vector2::new(point.x * scale.x, point.y * scale.y)
+    }
+
+    /// For ex. `InputSnapshot::cursor_position`, which is in pixels.
+    pub fn from_pixels(&self, pixels: Vector2<f32>) -> Vector2<f32> {
+        let scale = self.scale();
+        Vector2::new(pixels.x / scale.x, pixels.y / scale.y)
+    }
+
+    pub fn rect_to_pixels(&self, rect: UiRect) -> UiRect {
+        let scale = self.scale();
+        UiRect::new(
+            rect.x * scale.x,
+            rect.y * scale.y,
+            rect.width * scale.x,
+            rect.height * scale.y,
+        )
+    }
+
+    /// Units to clip space, for 2D pipelines with a projection matrix.
+    pub fn matrix(&self) -> Matrix4<f32> {
+        // clip space y points down like the units
+        let size = self.size();
+        ortho(0.0, size.x, 0.0, size.y, -1.0, 1.0)
+    }
+}
+
+impl Default for Projection2D {
+    fn default() -> Self {
+        Self::new(Units2D::Pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Vector4;
+
+    fn projection(units: Units2D) -> Projection2D {
+        Projection2D::new(units)
+            .with_extent((800, 400))
+            .with_scale_factor(2.0)
+    }
+
+    #[test]
+    fn pixels() {
+        let projection = projection(Units2D::Pixels);
+        assert_eq!(projection.size(), Vector2::new(800.0, 400.0));
+        assert_eq!(
+            projection.to_pixels(Vector2::new(10.0, 20.0)),
+            Vector2::new(10.0, 20.0)
+        );
+        assert_eq!(projection.length_scale(), 1.0);
+    }
+
+    #[test]
+    fn points() {
+        let projection = projection(Units2D::Points);
+        assert_eq!(projection.size(), Vector2::new(400.0, 200.0));
+        assert_eq!(
+            projection.to_pixels(Vector2::new(10.0, 20.0)),
+            Vector2::new(20.0, 40.0)
+        );
+        assert_eq!(
+            projection.from_pixels(Vector2::new(20.0, 40.0)),
+            Vector2::new(10.0, 20.0)
+        );
+        assert_eq!(projection.length_scale(), 2.0);
+    }
+
+    #[test]
+    fn normalized() {
+        let projection = projection(Units2D::Normalized);
+        assert_eq!(projection.size(), Vector2::new(1.0, 1.0));
+        assert_eq!(
+            projection.to_pixels(Vector2::new(0.5, 0.25)),
+            Vector2::new(400.0, 100.0)
+        );
+        assert_eq!(
+            projection.rect_to_pixels(UiRect::new(0.25, 0.5, 0.5, 0.5)),
+            UiRect::new(200.0, 200.0, 400.0, 200.0)
+        );
+        // lengths are fractions of the height
+        assert_eq!(projection.length_scale(), 400.0);
+    }
+
+    #[test]
+    fn invalid_sizes_are_ignored() {
+        let projection = Projection2D::new(Units2D::Points)
+            .with_extent((0, 0))
+            .with_scale_factor(0.0);
+        assert_eq!(projection.extent(), (1, 1));
+        assert_eq!(projection.scale_factor(), 1.0);
+    }
+
+    #[test]
+    fn matrix_maps_corners() {
+        let projection = projection(Units2D::Points);
+        let matrix = projection.matrix();
+        let top_left = matrix * Vector4::new(0.0, 0.0, 0.0, 1.0);
+        let bottom_right = matrix * Vector4::new(400.0, 200.0, 0.0, 1.0);
+        assert_eq!((top_left.x, top_left.y), (-1.0, -1.0));
+        assert_eq!((bottom_right.x, bottom_right.y), (1.0, 1.0));
+    }
+}
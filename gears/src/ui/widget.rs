@@ -0,0 +1,502 @@
+use cgmath::{Vector2, Vector4};
+use winit::event::MouseButton;
+
+use super::{projection::Projection2D, QuadStyle, UiQuads, UiRect};
+use crate::io::input_state::InputSnapshot;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WidgetId(usize);
+
+/// The point of the parent a widget's same point is placed at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+/// How a panel places its children.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    /// By their own anchors.
+    Anchored,
+    /// Top to bottom, `spacing` units apart, ignoring their anchors.
+    Column { spacing: f32 },
+    /// Left to right, `spacing` units apart, ignoring their anchors.
+    Row { spacing: f32 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WidgetKind {
+    Panel { layout: Layout },
+    Button { text: String },
+    Label { text: String },
+    Slider { min: f32, max: f32, value: f32 },
+}
+
+/// A node of a `Gui`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Widget {
+    pub kind: WidgetKind,
+    pub anchor: Anchor,
+    /// Units from the anchor, positive to the right and down.
+    pub offset: Vector2<f32>,
+    pub size: Vector2<f32>,
+    /// Hidden widgets hide their children and take no input.
+    pub visible: bool,
+}
+
+/// What the user did in `Gui::update`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GuiEvent {
+    /// Pressed and released over the button.
+    Clicked(WidgetId),
+    /// A slider was dragged to a new value.
+    Changed(WidgetId, f32),
+}
+
+/// Draws the labels and button texts of a `Gui`, for ex. a glyph atlas batcher.
+///
+/// Sizes and positions are in the units the `Gui` was updated in, the
+/// same `Projection2D` converts them to pixels.
+pub trait GuiText {
+    /// Width and height of `text`.
+    fn measure(&self, text: &str, size: f32) -> Vector2<f32>;
+
+    /// `position` is the top left of the text.
+    fn draw(&mut self, text: &str, position: Vector2<f32>, size: f32, color: Vector4<f32>);
+}
+
+/// Colors and sizes of every `Gui` widget.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    pub panel: QuadStyle,
+    pub button: QuadStyle,
+    pub button_hovered: QuadStyle,
+    pub button_pressed: QuadStyle,
+    pub slider_track: QuadStyle,
+    pub slider_knob: QuadStyle,
+    pub text_color: Vector4<f32>,
+    /// Text height.
+    pub text_size: f32,
+    /// Space between a panel's edge and its children.
+    pub padding: f32,
+}
+
+/// Retained mode panels, buttons, labels and sliders for game menus.
+///
+/// ```ignore
+/// let menu = gui.add(None, Widget::panel(Layout::Column { spacing: 8.0 }));
+/// let play = gui.add(Some(menu), Widget::button("Play"));
+/// let volume = gui.add(Some(menu), Widget::slider(0.0, 1.0, 0.8));
+///
+/// for event in gui.update(&input.read().snapshot(), screen) {
+///     match event {
+///         GuiEvent::Clicked(id) if id == play => start(),
+///         GuiEvent::Changed(id, value) if id == volume => set_volume(value),
+///         _ => {}
+///     }
+/// }
+///
+/// // in the DrawScope
+/// gui.draw(&mut quads, &mut text);
+/// quads.draw(rri)?;
+/// ```
+///
+/// Widgets are built once and changed through `widget_mut`. Shapes go
+/// through `UiQuads`, texts through a `GuiText` the application provides.
+/// Input comes from the left mouse button and cursor of an `InputSnapshot`,
+/// `hovered` tells if the cursor is over the GUI instead of the game.
+pub struct Gui {
+    theme: Theme,
+
+    widgets: Vec<Widget>,
+    parents: Vec<Option<WidgetId>>,
+    // screen rects of the last layout
+    rects: Vec<UiRect>,
+
+    hovered: Option<WidgetId>,
+    // the widget the button went down on
+    pressed: Option<WidgetId>,
+    was_down: bool,
+}
+
+impl Widget {
+    fn new(kind: WidgetKind, size: Vector2<f32>) -> Self {
+        Self {
+            kind,
+            anchor: Anchor::Center,
+            offset: Vector2::new(0.0, 0.0),
+            size,
+            visible: true,
+        }
+    }
+
+    pub fn panel(layout: Layout) -> Self {
+        Self::new(WidgetKind::Panel { layout }, Vector2::new(240.0, 320.0))
+    }
+
+    pub fn button<S: Into<String>>(text: S) -> Self {
+        Self::new(
+            WidgetKind::Button { text: text.into() },
+            Vector2::new(200.0, 40.0),
+        )
+    }
+
+    pub fn label<S: Into<String>>(text: S) -> Self {
+        Self::new(
+            WidgetKind::Label { text: text.into() },
+            Vector2::new(200.0, 24.0),
+        )
+    }
+
+    /// `value` is clamped to `min..=max`.
+    pub fn slider(min: f32, max: f32, value: f32) -> Self {
+        Self::new(
+            WidgetKind::Slider {
+                min,
+                max,
+                value: value.max(min).min(max),
+            },
+            Vector2::new(200.0, 24.0),
+        )
+    }
+
+    pub fn with_anchor(mut self, anchor: Anchor, offset: Vector2<f32>) -> Self {
+        self.anchor = anchor;
+        self.offset = offset;
+        self
+    }
+
+    pub fn with_size(mut self, size: Vector2<f32>) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// The text of buttons and labels.
+    pub fn text(&self) -> Option<&str> {
+        match &self.kind {
+            WidgetKind::Button { text } | WidgetKind::Label { text } => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Does nothing for panels and sliders.
+    pub fn set_text<S: Into<String>>(&mut self, new_text: S) {
+        if let WidgetKind::Button { text } | WidgetKind::Label { text } = &mut self.kind {
+            *text = new_text.into();
+        }
+    }
+
+    /// The value of sliders.
+    pub fn value(&self) -> Option<f32> {
+        match self.kind {
+            WidgetKind::Slider { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl Anchor {
+    // 0, 0.5 or 1 along both axes
+    fn factors(self) -> Vector2<f32> {
+        let (x, y) = match self {
+            Anchor::TopLeft => (0.0, 0.0),
+            Anchor::Top => (0.5, 0.0),
+            Anchor::TopRight => (1.0, 0.0),
+            Anchor::Left => (0.0, 0.5),
+            Anchor::Center => (0.5, 0.5),
+            Anchor::Right => (1.0, 0.5),
+            Anchor::BottomLeft => (0.0, 1.0),
+            Anchor::Bottom => (0.5, 1.0),
+            Anchor::BottomRight => (1.0, 1.0),
+        };
+        Vector2::new(x, y)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        let gray = |value: f32, alpha: f32| Vector4::new(value, value, value, alpha);
+        Self {
+            panel: QuadStyle::fill(gray(0.05, 0.85))
+                .with_radius(8.0)
+                .with_border(1.0, gray(0.3, 1.0)),
+            button: QuadStyle::fill(gray(0.15, 1.0)).with_radius(6.0),
+            button_hovered: QuadStyle::fill(gray(0.25, 1.0)).with_radius(6.0),
+            button_pressed: QuadStyle::fill(gray(0.1, 1.0))
+                .with_radius(6.0)
+                .with_border(2.0, gray(0.5, 1.0)),
+            slider_track: QuadStyle::fill(gray(0.15, 1.0)).with_radius(3.0),
+            slider_knob: QuadStyle::fill(gray(0.8, 1.0)).with_radius(8.0),
+            text_color: gray(0.95, 1.0),
+            text_size: 18.0,
+            padding: 12.0,
+        }
+    }
+}
+
+impl Gui {
+    pub fn new(theme: Theme) -> Self {
+        Self {
+            theme,
+
+            widgets: Vec::new(),
+            parents: Vec::new(),
+            rects: Vec::new(),
+
+            hovered: None,
+            pressed: None,
+            was_down: false,
+        }
+    }
+
+    /// Adds `widget` to the root or a panel.
+    ///
+    /// Panics if `parent` is not a panel.
+    pub fn add(&mut self, parent: Option<WidgetId>, widget: Widget) -> WidgetId {
+        if let Some(parent) = parent {
+            assert!(
+                matches!(self.widgets[parent.0].kind, WidgetKind::Panel { .. }),
+                "widgets can only be added to panels"
+            );
+        }
+
+        self.widgets.push(widget);
+        self.parents.push(parent);
+        self.rects.push(UiRect::new(0.0, 0.0, 0.0, 0.0));
+        WidgetId(self.widgets.len() - 1)
+    }
+
+    pub fn widget(&self, id: WidgetId) -> &Widget {
+        &self.widgets[id.0]
+    }
+
+    pub fn widget_mut(&mut self, id: WidgetId) -> &mut Widget {
+        &mut self.widgets[id.0]
+    }
+
+    /// Screen rect of `id` at the last `update`.
+    pub fn rect(&self, id: WidgetId) -> UiRect {
+        self.rects[id.0]
+    }
+
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    /// True if the cursor was over a visible widget at the last `update`,
+    /// so clicks are not meant for the game.
+    pub fn hovered(&self) -> bool {
+        self.hovered.is_some()
+    }
+
+    /// Lays the widgets out on a `screen` sized window in pixels and handles `input`.
+    pub fn update(&mut self, input: &InputSnapshot, screen: Vector2<f32>) -> Vec<GuiEvent> {
+        self.handle(input, screen, input.cursor_position())
+    }
+
+    /// `update` in the units of `projection`, draw into `UiQuads` with the same projection.
+    pub fn update_projected(
+        &mut self,
+        input: &InputSnapshot,
+        projection: &Projection2D,
+    ) -> Vec<GuiEvent> {
+        let cursor = input
+            .cursor_position()
+            .map(|cursor| projection.from_pixels(cursor));
+        self.handle(input, projection.size(), cursor)
+    }
+
+    fn handle(
+        &mut self,
+        input: &InputSnapshot,
+        screen: Vector2<f32>,
+        cursor: Option<Vector2<f32>>,
+    ) -> Vec<GuiEvent> {
+        self.layout(screen);
+
+        let down = input.mouse_held(MouseButton::Left);
+        let just_pressed = down && !self.was_down;
+        let just_released = !down && self.was_down;
+        self.was_down = down;
+
+        // the last drawn, topmost, widget under the cursor
+        self.hovered = cursor.and_then(|cursor| {
+            (0..self.widgets.len())
+                .rev()
+                .find(|&index| self.visible(index) && self.rects[index].contains(cursor))
+                .map(WidgetId)
+        });
+
+        let mut events = Vec::new();
+        if just_pressed {
+            // panels only block the game's input
+            self.pressed = self
+                .hovered
+                .filter(|id| !matches!(self.widgets[id.0].kind, WidgetKind::Panel { .. }));
+        }
+
+        if let (Some(id), Some(cursor)) = (self.pressed, cursor) {
+            let rect = self.rects[id.0];
+            if let WidgetKind::Slider { min, max, value } = &mut self.widgets[id.0].kind {
+                let t = ((cursor.x - rect.x) / rect.width.max(1.0))
+                    .max(0.0)
+                    .min(1.0);
+                let new_value = *min + (*max - *min) * t;
+                if new_value != *value {
+                    *value = new_value;
+                    events.push(GuiEvent::Changed(id, new_value));
+                }
+            }
+        }
+
+        if just_released {
+            if let Some(id) = self.pressed.take() {
+                if self.hovered == Some(id)
+                    && matches!(self.widgets[id.0].kind, WidgetKind::Button { .. })
+                {
+                    events.push(GuiEvent::Clicked(id));
+                }
+            }
+        }
+        events
+    }
+
+    /// Queues the visible widgets, parents before their children.
+    pub fn draw(&self, quads: &mut UiQuads, text: &mut dyn GuiText) {
+        let theme = &self.theme;
+        for (index, widget) in self.widgets.iter().enumerate() {
+            if !self.visible(index) {
+                continue;
+            }
+            let rect = self.rects[index];
+            let id = Some(WidgetId(index));
+
+            match &widget.kind {
+                WidgetKind::Panel { .. } => quads.rect(rect, &theme.panel),
+                WidgetKind::Button { text: label } => {
+                    let style = if self.pressed == id && self.hovered == id {
+                        &theme.button_pressed
+                    } else if self.hovered == id {
+                        &theme.button_hovered
+                    } else {
+                        &theme.button
+                    };
+                    quads.rect(rect, style);
+
+                    let size = text.measure(label, theme.text_size);
+                    let position = Vector2::new(
+                        rect.x + (rect.width - size.x) * 0.5,
+                        rect.y + (rect.height - size.y) * 0.5,
+                    );
+                    text.draw(label, position, theme.text_size, theme.text_color);
+                }
+                WidgetKind::Label { text: label } => {
+                    let size = text.measure(label, theme.text_size);
+                    let position = Vector2::new(rect.x, rect.y + (rect.height - size.y) * 0.5);
+                    text.draw(label, position, theme.text_size, theme.text_color);
+                }
+                WidgetKind::Slider { min, max, value } => {
+                    let track_height = (rect.height * 0.25).max(2.0);
+                    let track = UiRect::new(
+                        rect.x,
+                        rect.y + (rect.height - track_height) * 0.5,
+                        rect.width,
+                        track_height,
+                    );
+                    quads.rect(track, &theme.slider_track);
+
+                    let t = if max > min {
+                        (value - min) / (max - min)
+                    } else {
+                        0.0
+                    };
+                    let knob = UiRect::new(
+                        rect.x + t * rect.width - rect.height * 0.5,
+                        rect.y,
+                        rect.height,
+                        rect.height,
+                    );
+                    quads.rect(knob, &theme.slider_knob);
+                }
+            }
+        }
+    }
+
+    fn visible(&self, index: usize) -> bool {
+        let mut index = Some(WidgetId(index));
+        while let Some(id) = index {
+            if !self.widgets[id.0].visible {
+                return false;
+            }
+            index = self.parents[id.0];
+        }
+        true
+    }
+
+    // parents always come before their children
+    fn layout(&mut self, screen: Vector2<f32>) {
+        // where the next child of a column or row panel goes
+        let mut cursors = vec![0.0_f32; self.widgets.len()];
+
+        for index in 0..self.widgets.len() {
+            let widget = &self.widgets[index];
+            let parent = self.parents[index];
+
+            let area = match parent {
+                Some(parent) => self.rects[parent.0].inset(self.theme.padding),
+                None => UiRect::new(0.0, 0.0, screen.x, screen.y),
+            };
+            let layout = parent.and_then(|parent| match self.widgets[parent.0].kind {
+                WidgetKind::Panel { layout } => Some(layout),
+                _ => None,
+            });
+
+            let size = widget.size;
+            let position = match layout {
+                Some(Layout::Column { spacing }) => {
+                    let parent = parent.unwrap().0;
+                    let y = area.y + cursors[parent];
+                    if widget.visible {
+                        cursors[parent] += size.y + spacing;
+                    }
+                    Vector2::new(area.x + (area.width - size.x) * 0.5, y)
+                }
+                Some(Layout::Row { spacing }) => {
+                    let parent = parent.unwrap().0;
+                    let x = area.x + cursors[parent];
+                    if widget.visible {
+                        cursors[parent] += size.x + spacing;
+                    }
+                    Vector2::new(x, area.y + (area.height - size.y) * 0.5)
+                }
+                _ => {
+                    let anchor = widget.anchor.factors();
+                    Vector2::new(
+                        area.x + (area.width - size.x) * anchor.x + widget.offset.x,
+                        area.y + (area.height - size.y) * anchor.y + widget.offset.y,
+                    )
+                }
+            };
+
+            self.rects[index] = UiRect::new(position.x, position.y, size.x, size.y);
+        }
+    }
+}
+
+impl Default for Gui {
+    fn default() -> Self {
+        Self::new(Theme::default())
+    }
+}
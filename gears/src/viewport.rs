@@ -0,0 +1,194 @@
+use ash::vk;
+use cgmath::Vector4;
+
+use crate::{
+    fullscreen::Pass,
+    renderer::{buffer::BufferError, target::RenderTarget, RenderRecordInfo, Renderer},
+};
+
+mod shader {
+    gears_pipeline::pipeline! {
+        frag: {
+            path: "res/blit.frag.glsl"
+        }
+    }
+}
+
+/// Color format of offscreen viewports, linear like the G-buffer.
+pub const VIEWPORT_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Part of the swapchain in fractions of its size, from the top left.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ViewportRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// A second camera's view, for minimaps, mirrors and split screens.
+///
+/// Swapchain viewports draw the second camera to a scissored part of the
+/// swapchain, in the `DrawScope` between `begin` and `end`. Offscreen
+/// viewports draw it to their own `RenderTarget` in a `RecordScope` between
+/// `begin` and `end`, and `draw` puts it on the swapchain as a quad later.
+/// Pipelines drawing into an offscreen viewport have to be built
+/// `with_render_target(viewport.target().unwrap())`.
+///
+/// Either way the rectangle follows swapchain resizes, project the second
+/// camera with `aspect`.
+pub struct Viewport {
+    rect: ViewportRect,
+    clear_color: Option<Vector4<f32>>,
+    offscreen: Option<Offscreen>,
+}
+
+struct Offscreen {
+    target: RenderTarget,
+    blit: Pass,
+}
+
+impl ViewportRect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// The whole swapchain.
+    pub fn full() -> Self {
+        Self::new(0.0, 0.0, 1.0, 1.0)
+    }
+
+    /// The rectangle in pixels of an `extent` sized swapchain.
+    pub fn pixels(&self, (width, height): (u32, u32)) -> vk::Rect2D {
+        let (width, height) = (width as f32, height as f32);
+        vk::Rect2D {
+            offset: vk::Offset2D {
+                x: (self.x * width).round() as i32,
+                y: (self.y * height).round() as i32,
+            },
+            extent: vk::Extent2D {
+                width: (self.width * width).round().max(1.0) as u32,
+                height: (self.height * height).round().max(1.0) as u32,
+            },
+        }
+    }
+}
+
+impl Default for ViewportRect {
+    fn default() -> Self {
+        Self::full()
+    }
+}
+
+impl Viewport {
+    /// Part of the swapchain, drawn over whatever is below unless `with_clear_color`.
+    pub fn new(rect: ViewportRect) -> Self {
+        Self {
+            rect,
+            clear_color: None,
+            offscreen: None,
+        }
+    }
+
+    /// A `width` x `height` `VIEWPORT_FORMAT` target, drawn to `rect` of the swapchain.
+    ///
+    /// Cleared to transparent black unless `with_clear_color`.
+    pub fn new_offscreen(
+        renderer: &Renderer,
+        width: u32,
+        height: u32,
+        rect: ViewportRect,
+    ) -> Result<Self, BufferError> {
+        let target = RenderTarget::new(renderer, width, height, &[VIEWPORT_FORMAT])?;
+        let blit = Pass::new(renderer, shader::FRAG_SPIRV_WORDS)
+            .with_input(target.color_view(0), target.sampler())
+            .build()?;
+
+        Ok(Self {
+            rect,
+            clear_color: None,
+            offscreen: Some(Offscreen { target, blit }),
+        })
+    }
+
+    /// Clears the color and the depth before the second camera draws.
+    ///
+    /// Swapchain viewports keep the depth of the main camera without it.
+    pub fn with_clear_color(mut self, color: Vector4<f32>) -> Self {
+        self.clear_color = Some(color);
+        self
+    }
+
+    pub fn clear_color(&self) -> Option<Vector4<f32>> {
+        self.clear_color
+    }
+
+    pub fn rect(&self) -> ViewportRect {
+        self.rect
+    }
+
+    /// Swapchain viewports need a rerecord for this, like offscreen viewports' `draw`.
+    pub fn set_rect(&mut self, rect: ViewportRect) {
+        self.rect = rect;
+    }
+
+    /// The offscreen target, `None` for swapchain viewports.
+    pub fn target(&self) -> Option<&RenderTarget> {
+        self.offscreen.as_ref().map(|offscreen| &offscreen.target)
+    }
+
+    /// Width over height of what the second camera draws to.
+    pub fn aspect(&self, rri: &RenderRecordInfo) -> f32 {
+        let extent = match &self.offscreen {
+            Some(offscreen) => vk::Extent2D {
+                width: offscreen.target.width(),
+                height: offscreen.target.height(),
+            },
+            None => self.rect.pixels(rri.extent()).extent,
+        };
+        extent.width as f32 / extent.height as f32
+    }
+
+    pub unsafe fn begin(&self, rri: &RenderRecordInfo) {
+        match &self.offscreen {
+            Some(offscreen) => offscreen.target.begin(
+                rri,
+                self.clear_color.unwrap_or(Vector4::new(0.0, 0.0, 0.0, 0.0)),
+            ),
+            None => {
+                let rect = self.rect.pixels(rri.extent());
+                rri.set_viewport(rect);
+                if let Some(color) = self.clear_color {
+                    rri.clear_rect(rect, color);
+                }
+            }
+        }
+    }
+
+    /// Restores the full swapchain viewport or ends the offscreen render pass.
+    pub unsafe fn end(&self, rri: &RenderRecordInfo) {
+        match &self.offscreen {
+            Some(offscreen) => offscreen.target.end(rri),
+            None => rri.set_viewport(ViewportRect::full().pixels(rri.extent())),
+        }
+    }
+
+    /// Draws the offscreen target to its rectangle of the swapchain, in a `DrawScope`.
+    ///
+    /// Blends nothing, the quad replaces what is below. Does nothing for swapchain viewports.
+    pub unsafe fn draw(&self, rri: &RenderRecordInfo) {
+        if let Some(offscreen) = &self.offscreen {
+            let rect = self.rect.pixels(rri.extent());
+            rri.set_viewport(rect);
+            // the full screen triangle is at the far plane, the scene's depth would hide it
+            rri.clear_rect(rect, Vector4::new(0.0, 0.0, 0.0, 0.0));
+            offscreen.blit.draw(rri);
+            rri.set_viewport(ViewportRect::full().pixels(rri.extent()));
+        }
+    }
+}